@@ -15,5 +15,6 @@ extern {
 
 #[wasm_bindgen]
 pub fn greet() {
+    utils::set_panic_hook();
     alert("Hello, FIXExchange!");
 }
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::types::*;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountAuthorizationsConfig {
+    #[serde(default)]
+    comp_ids: HashMap<String, Vec<AccountID>>,
+}
+
+// The accounts each CompID's sessions may trade, when the exchange is started with a list of
+// them. Without one, an account is opened by the first order naming it and belongs to whoever
+// sent that.
+#[derive(Debug, Clone, Default)]
+pub struct AccountAuthorizations {
+    enforced: bool,
+    by_comp_id: HashMap<String, HashSet<AccountID>>,
+}
+
+impl AccountAuthorizations {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: AccountAuthorizationsConfig = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut authorizations = Self { enforced: true, ..Self::default() };
+        for (comp_id, accounts) in config.comp_ids {
+            for account_id in accounts {
+                authorizations.authorize(&comp_id, account_id);
+            }
+        }
+        Ok(authorizations)
+    }
+
+    pub fn enforced(&self) -> bool {
+        self.enforced
+    }
+
+    pub fn authorize(&mut self, comp_id: &str, account_id: AccountID) {
+        self.by_comp_id.entry(comp_id.to_string()).or_default().insert(account_id);
+    }
+
+    pub fn revoke(&mut self, comp_id: &str, account_id: &AccountID) {
+        if let Some(accounts) = self.by_comp_id.get_mut(comp_id) {
+            accounts.remove(account_id);
+        }
+    }
+
+    // Why `comp_id` may not trade `account_id`, if it may not. An account on nobody's list is
+    // told apart from one on someone else's, so a mistyped account name reads as such.
+    pub fn refusal(&self, comp_id: &str, account_id: &AccountID) -> Option<&'static str> {
+        if self.by_comp_id.get(comp_id).is_some_and(|accounts| accounts.contains(account_id)) {
+            return None;
+        }
+        if self.by_comp_id.values().any(|accounts| accounts.contains(account_id)) {
+            Some("Account not authorized for session")
+        } else {
+            Some("Unknown account")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fefix::definitions::fix50::{OrdType, Side};
+    use fefix::fix_values::Timestamp;
+
+    use super::*;
+    use crate::engine::EngineMessage;
+    use crate::exchange::Exchange;
+    use crate::fix::testkit::{accepted_order_id, client};
+    use crate::instrument::SpecOverrides;
+
+    fn limit_order(exchange: &mut Exchange, comp_id: &str, account: &str, price: f64) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(comp_id),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(price)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        })
+    }
+
+    fn cancel(exchange: &mut Exchange, comp_id: &str, account: &str, order_id: OrderID) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(comp_id),
+            account_id: account.to_string(),
+            order_id,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        })
+    }
+
+    fn rejection(events: &[EngineMessage]) -> &str {
+        match events {
+            [EngineMessage::OrderRejected { reason, .. }] => reason,
+            _ => panic!("expected a rejection: {:?}", events),
+        }
+    }
+
+    fn authorized_exchange() -> Exchange {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("accounts.toml");
+        let mut exchange = Exchange::new().with_account_authorizations(AccountAuthorizations::load(&path).unwrap());
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+        exchange
+    }
+
+    #[test]
+    fn a_session_trades_every_account_listed_for_its_comp_id_and_no_other() {
+        let mut exchange = authorized_exchange();
+        // One session, two accounts
+        let main = accepted_order_id(&limit_order(&mut exchange, "FIRM1", "FIRM1-MAIN", 10.0));
+        let hedge = accepted_order_id(&limit_order(&mut exchange, "FIRM1", "FIRM1-HEDGE", 9.0));
+
+        assert_eq!(rejection(&limit_order(&mut exchange, "FIRM1", "FIRM2-MAIN", 10.0)), "Account not authorized for session");
+        assert_eq!(rejection(&cancel(&mut exchange, "FIRM2", "FIRM2-MAIN", main)), "Account not authorized for session");
+        // Unlisted CompIDs trade nothing
+        assert_eq!(rejection(&limit_order(&mut exchange, "FIRM3", "FIRM1-MAIN", 10.0)), "Account not authorized for session");
+
+        assert!(matches!(cancel(&mut exchange, "FIRM1", "FIRM1-HEDGE", hedge).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+    }
+
+    #[test]
+    fn a_mistyped_account_is_unknown_rather_than_opened() {
+        let mut exchange = authorized_exchange();
+        let main = accepted_order_id(&limit_order(&mut exchange, "FIRM1", "FIRM1-MAIN", 10.0));
+        for typo in ["FIRM1 MAIN", "firm1-main", "FIRM1-MAIN "] {
+            assert_eq!(rejection(&limit_order(&mut exchange, "FIRM1", typo, 10.0)), "Unknown account");
+            assert_eq!(rejection(&cancel(&mut exchange, "FIRM1", typo, main)), "Unknown account");
+        }
+        let query = exchange.handle_message(EngineMessage::PositionQuery {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("FIRM1"),
+            account_id: "FIRM1 MAIN".to_string(),
+        });
+        assert_eq!(rejection(&query), "Unknown account");
+    }
+
+    #[test]
+    fn authorizations_change_while_running() {
+        let mut exchange = authorized_exchange();
+        let authorize = |exchange: &mut Exchange, account: &str, authorized| {
+            exchange.handle_message(EngineMessage::SetAccountAuthorization { client_id: client("FIRM2"), account_id: account.to_string(), authorized })
+        };
+        assert_eq!(rejection(&limit_order(&mut exchange, "FIRM2", "FIRM2-NEW", 10.0)), "Unknown account");
+        let granted = authorize(&mut exchange, "FIRM2-NEW", true);
+        assert!(matches!(granted.as_slice(), [EngineMessage::LogEvent { message, .. }] if message == "FIRM2 may trade account FIRM2-NEW"), "{:?}", granted);
+        let order_id = accepted_order_id(&limit_order(&mut exchange, "FIRM2", "FIRM2-NEW", 10.0));
+
+        // Revoking stops new business on the account; the order already resting stays out of reach too
+        authorize(&mut exchange, "FIRM2-NEW", false);
+        assert_eq!(rejection(&limit_order(&mut exchange, "FIRM2", "FIRM2-NEW", 10.0)), "Unknown account");
+        assert_eq!(rejection(&cancel(&mut exchange, "FIRM2", "FIRM2-NEW", order_id)), "Unknown account");
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::config::LiveConfig;
+use crate::engine::EngineMessage;
+use crate::inbound::InboundSender;
+
+// How long each activity interval runs, and so how often subscribers are sent their books'
+// activity, unless the server config says otherwise
+pub const ACTIVITY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Asks the engine to close each activity interval as the configured one runs out, queued behind
+// whatever it was sent before. Returns once `shutdown` reads true or its sender is dropped, or
+// the engine is gone.
+pub async fn summarize_periodically(config: Arc<LiveConfig>, tx: InboundSender, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = config.snapshot().activity_interval();
+    let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                if tx.send(EngineMessage::PublishActivity).is_err() {
+                    return;
+                }
+                // A reloaded interval takes over from the next one
+                let reloaded = config.snapshot().activity_interval();
+                if reloaded != interval {
+                    interval = reloaded;
+                    ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+                    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::inbound::inbound_channel;
+
+    #[tokio::test]
+    async fn each_interval_is_closed_once_it_runs_out_until_shutdown() {
+        let config = LiveConfig::fixed(ServerConfig { activity_interval_ms: 20, ..ServerConfig::default() });
+        let (tx, mut rx) = inbound_channel(false);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let started = Instant::now();
+        let summarizer = tokio::spawn(summarize_periodically(config, tx, shutdown_rx));
+
+        for interval in 1..=3 {
+            let (message, _) = rx.recv().await.unwrap();
+            assert_eq!(message, EngineMessage::PublishActivity);
+            // Never before the interval has run its length
+            assert!(started.elapsed() >= Duration::from_millis(20 * interval));
+        }
+        shutdown_tx.send(true).unwrap();
+        summarizer.await.unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::{Date, Time, Timestamp};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::clock::timestamp_now;
+use crate::engine::{EngineMessage, MalformedEntry};
+use crate::framing::RawMessage;
+use crate::types::*;
+
+// Orders kept in memory for audit queries
+pub const RECENT_ORDER_CAPACITY: usize = 1_000_000;
+// Trades kept for replay and history queries unless the exchange is told otherwise, oldest dropped first
+pub const TRADE_LOG_RETENTION: usize = 100_000;
+// Unreadable inbound messages kept for the rejection log, oldest dropped first
+pub const MALFORMED_LOG_RETENTION: usize = 10_000;
+// Entries one history query returns when it names no limit, and the most it may ask for
+pub const DEFAULT_HISTORY_PAGE: usize = 100;
+pub const MAX_HISTORY_PAGE: usize = 1_000;
+
+pub type RecentOrders = CircularOrderBuffer<OrderAuditEntry, RECENT_ORDER_CAPACITY>;
+
+// Inbound messages that could not be read, as the sessions that got them logged them. Kept
+// outside the engine, which never sees such messages, and read by it for the rejection log.
+#[derive(Debug, Default)]
+pub struct MalformedLog {
+    entries: Mutex<VecDeque<MalformedEntry>>, // oldest first
+}
+
+impl MalformedLog {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record(&self, reason: String, raw_message: RawMessage) {
+        let mut entries = self.entries.lock();
+        if entries.len() == MALFORMED_LOG_RETENTION {
+            entries.pop_front();
+        }
+        entries.push_back(MalformedEntry { timestamp: timestamp_now(), reason, raw_message });
+    }
+
+    pub fn since(&self, since: &Timestamp) -> Vec<MalformedEntry> {
+        let since = timestamp_key(since);
+        self.entries.lock().iter().filter(|entry| timestamp_key(&entry.timestamp) >= since).cloned().collect()
+    }
+}
+
+// What the recent-orders buffer remembers about an accepted order. It must be Copy to live
+// in the ring, so who sent it and on which book is left to the order's own records.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OrderAuditEntry {
+    pub order_id: OrderID,
+    #[serde(with = "fix_value_serde")]
+    pub side: Side,
+    #[serde(with = "fix_value_serde")]
+    pub order_type: OrdType,
+    #[serde(with = "fix_value_serde")]
+    pub time_in_force: TimeInForce,
+    pub price: Option<Price>,
+    pub quantity: Quantity,
+    #[serde(serialize_with = "serialize_received")]
+    pub received: (Date, Time),
+}
+
+// One side of a trade: the order and who it was entered for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeParty {
+    pub order_id: OrderID,
+    pub account_id: AccountID,
+    pub client_id: ClientID,
+}
+
+// A trade as the trade log keeps it, for replay, history queries and surveillance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub trade_match_id: u64, // as both sides' fills carried it
+    pub instrument_id: InstrumentID,
+    pub price: Price,
+    pub quantity: Quantity,
+    #[serde(with = "fix_value_serde")]
+    pub timestamp: Timestamp,
+    pub buyer: TradeParty,
+    pub seller: TradeParty,
+}
+
+// Which trades a history query wants: those in [from, to] on the instrument, the account or
+// both, a page at a time. `after` is the last TradeMatchID of the page before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeQuery {
+    #[serde(default)]
+    pub instrument_id: Option<InstrumentID>,
+    #[serde(default)]
+    pub account_id: Option<AccountID>, // the buyer's or the seller's
+    #[serde(with = "fix_value_serde")]
+    pub from: Timestamp,
+    #[serde(with = "fix_value_serde")]
+    pub to: Timestamp,
+    #[serde(default)]
+    pub after: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>, // DEFAULT_HISTORY_PAGE if None, and never more than MAX_HISTORY_PAGE
+}
+
+// One step in an order's life: the report that went to its sender, the status it left the
+// order in, and the request that set it off, which may be another client's, such as the
+// order that traded with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderEvent {
+    #[serde(with = "fix_value_serde")]
+    pub timestamp: Timestamp,
+    #[serde(with = "fix_value_serde")]
+    pub status: OrdStatus,
+    pub report: EngineMessage,
+    pub cause: String, // the request's type, e.g. "cancel_order", or "advance_time" for an expiry
+    pub cause_client_id: Option<ClientID>,
+}
+
+// A page of history and where the next one starts, None once there is no more
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryPage<T, C> {
+    pub entries: Vec<T>,
+    pub next: Option<C>,
+}
+
+// The first `limit` of `entries` and, if any are left, the cursor `cursor` gives the last one
+pub(crate) fn paginate<T, C>(entries: impl Iterator<Item = T>, limit: Option<usize>, cursor: impl Fn(&T) -> C) -> HistoryPage<T, C> {
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_PAGE).clamp(1, MAX_HISTORY_PAGE);
+    let mut entries = entries.peekable();
+    let page: Vec<T> = entries.by_ref().take(limit).collect();
+    let next = if entries.peek().is_some() { page.last().map(cursor) } else { None };
+    HistoryPage { entries: page, next }
+}
+
+fn serialize_received<S: Serializer>(received: &(Date, Time), serializer: S) -> Result<S::Ok, S::Error> {
+    fix_value_serde::serialize(&Timestamp::new(received.0, received.1), serializer)
+}
+
+// The last N values pushed, overwriting the oldest once full. One thread pushes while any
+// number read without locks: a reader copies entries out, then drops any the writer may
+// have overwritten during the copy, the way a seqlock retries. That is why T must be Copy.
+pub struct CircularOrderBuffer<T, const N: usize> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>; N]>,
+    head: AtomicUsize, // count of the oldest entry still readable
+    tail: AtomicUsize, // count of entries ever pushed
+    writing: AtomicBool, // catches a second writer instead of letting it race
+}
+
+// Slots below `tail` and at or above `head` are initialized, and readers only ever copy them
+unsafe impl<T: Copy + Send, const N: usize> Sync for CircularOrderBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> CircularOrderBuffer<T, N> {
+    pub fn new() -> Self {
+        const { assert!(N > 0, "a ring buffer needs at least one slot") };
+        // Built as a Vec so a million slots neither sit on the stack nor get touched up front
+        let mut slots = Vec::with_capacity(N);
+        // SAFETY: UnsafeCell<MaybeUninit<T>> is valid uninitialized
+        unsafe { slots.set_len(N) };
+        let slots: Box<[UnsafeCell<MaybeUninit<T>>]> = slots.into_boxed_slice();
+        Self {
+            slots: slots.try_into().unwrap_or_else(|_| unreachable!()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            writing: AtomicBool::new(false),
+        }
+    }
+
+    // Appends a value, dropping the oldest if the buffer is full. Panics if another
+    // thread is pushing at the same time.
+    pub fn push(&self, value: T) {
+        assert!(!self.writing.swap(true, Ordering::Acquire), "CircularOrderBuffer has a single writer");
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail >= N {
+            // Retire the entry about to be overwritten before touching its slot
+            self.head.store(tail + 1 - N, Ordering::Relaxed);
+            fence(Ordering::Release);
+        }
+        // SAFETY: only this writer touches slot contents, and readers discard what it overwrites
+        unsafe { (*self.slots[tail % N].get()).write(value) };
+        self.tail.store(tail + 1, Ordering::Release);
+        self.writing.store(false, Ordering::Release);
+    }
+
+    // The last `count` entries, oldest first. Entries overwritten while they were being
+    // read are left out, so under heavy pushing this can return fewer than asked for.
+    pub fn iter_recent(&self, count: usize) -> std::vec::IntoIter<T> {
+        let tail = self.tail.load(Ordering::Acquire);
+        // The writer may have lapped `tail` by the time `head` is read, leaving nothing to copy
+        let start = tail.saturating_sub(count.min(N)).max(self.head.load(Ordering::Acquire));
+        let mut copied = Vec::with_capacity(tail.saturating_sub(start));
+        for position in start..tail {
+            // SAFETY: the slot was initialized before `tail` was published; a concurrent
+            // overwrite is detected below and the copy discarded
+            copied.push(unsafe { std::ptr::read_volatile((*self.slots[position % N].get()).as_ptr()) });
+        }
+        fence(Ordering::Acquire);
+        let overwritten = self.head.load(Ordering::Relaxed).saturating_sub(start).min(copied.len());
+        copied.drain(..overwritten);
+        copied.into_iter()
+    }
+}
+
+impl<T: Copy, const N: usize> Default for CircularOrderBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> std::fmt::Debug for CircularOrderBuffer<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircularOrderBuffer")
+            .field("capacity", &N)
+            .field("pushed", &self.tail.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_n_values_oldest_first() {
+        let buffer = CircularOrderBuffer::<u64, 4>::new();
+        assert_eq!(buffer.iter_recent(10).count(), 0);
+        for value in 1..=3 {
+            buffer.push(value);
+        }
+        assert_eq!(buffer.iter_recent(10).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        for value in 4..=6 {
+            buffer.push(value);
+        }
+        assert_eq!(buffer.iter_recent(usize::MAX).count(), 4);
+        assert_eq!(buffer.iter_recent(10).collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        assert_eq!(buffer.iter_recent(2).collect::<Vec<_>>(), vec![5, 6]);
+    }
+
+    #[test]
+    fn readers_never_see_torn_or_stale_entries_while_the_writer_laps_them() {
+        const PUSHES: u64 = 2_000_000;
+        let buffer = Arc::new(CircularOrderBuffer::<(u64, u64), 1024>::new());
+        let writer = {
+            let buffer = Arc::clone(&buffer);
+            std::thread::spawn(move || {
+                for value in 0..PUSHES {
+                    buffer.push((value, !value));
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let buffer = Arc::clone(&buffer);
+                std::thread::spawn(move || {
+                    let mut reads = 0;
+                    while reads < 2_000 {
+                        let recent: Vec<(u64, u64)> = buffer.iter_recent(1024).collect();
+                        for pair in recent.windows(2) {
+                            assert_eq!(pair[1].0, pair[0].0 + 1, "entries out of order");
+                        }
+                        for (value, check) in &recent {
+                            assert_eq!(*check, !value, "torn entry");
+                        }
+                        reads += 1;
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(buffer.iter_recent(1).next(), Some((PUSHES - 1, !(PUSHES - 1))));
+    }
+
+    #[test]
+    fn holds_a_million_orders_without_touching_them_up_front() {
+        let buffer = RecentOrders::new();
+        assert_eq!(buffer.iter_recent(usize::MAX).count(), 0);
+        let entry = OrderAuditEntry {
+            order_id: 1,
+            side: Side::Buy,
+            order_type: OrdType::Limit,
+            time_in_force: TimeInForce::Day,
+            price: Some(Price::from(10.0)),
+            quantity: 5,
+            received: (Date::new(2024, 1, 2).unwrap(), Time::from_hmsm(14, 30, 0, 0).unwrap()),
+        };
+        buffer.push(entry);
+        assert_eq!(buffer.iter_recent(5).collect::<Vec<_>>(), vec![entry]);
+    }
+}
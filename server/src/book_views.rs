@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use fefix::fix_values::Timestamp;
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::clock::timestamp_now;
+use crate::config::LiveConfig;
+use crate::engine::{BookGranularity, EngineMessage};
+use crate::inbound::InboundSender;
+use crate::types::*;
+
+// How often the matching thread publishes its books, and so how stale a read can be, unless
+// the server config says otherwise
+pub const BOOK_VIEW_INTERVAL: Duration = Duration::from_millis(100);
+
+// One book's resting liquidity as the matching thread last published it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PublishedBook {
+    pub bids: Vec<(Price, Quantity)>, // best first
+    pub asks: Vec<(Price, Quantity)>,
+    pub bid_orders: Vec<(OrderID, Price, Quantity)>, // best level first, in queue order
+    pub ask_orders: Vec<(OrderID, Price, Quantity)>,
+}
+
+// Every book at one moment. Never changed once published; the next publication replaces it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishedBooks {
+    pub sequence: u64, // counts publications, 0 before the first
+    #[serde(with = "fix_value_serde")]
+    pub published_at: Timestamp, // the exchange's time when these were taken
+    pub books: HashMap<InstrumentID, PublishedBook>,
+}
+
+// The latest published books, shared between the matching thread, which swaps in each new
+// publication, and the sessions and admin API, which read them. The lock only ever guards
+// swapping or cloning the Arc, so a reader holding a publication, however long, never
+// keeps the matching thread waiting.
+#[derive(Debug)]
+pub struct BookViews {
+    latest: RwLock<Arc<PublishedBooks>>,
+}
+
+impl BookViews {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            latest: RwLock::new(Arc::new(PublishedBooks {
+                sequence: 0,
+                published_at: timestamp_now(),
+                books: HashMap::new(),
+            })),
+        })
+    }
+
+    pub fn latest(&self) -> Arc<PublishedBooks> {
+        Arc::clone(&self.latest.read())
+    }
+
+    pub(crate) fn publish(&self, published_at: Timestamp, books: HashMap<InstrumentID, PublishedBook>) {
+        let sequence = self.latest.read().sequence + 1;
+        *self.latest.write() = Arc::new(PublishedBooks { sequence, published_at, books });
+    }
+
+    // Answers a snapshot request from the latest publication, stamped with when it was taken
+    pub fn snapshot(&self, client_id: ClientID, instrument_id: InstrumentID, depth: Option<u32>, granularity: BookGranularity) -> EngineMessage {
+        let latest = self.latest();
+        let Some(book) = latest.books.get(&instrument_id) else {
+            return EngineMessage::OrderRejected {
+                reason: "Unknown instrument".to_string(),
+                client_id,
+                code: None,
+            };
+        };
+        let levels = match depth.unwrap_or(0) {
+            0 => usize::MAX,
+            depth => depth as usize,
+        };
+        let (bids, asks, bid_orders, ask_orders) = match granularity {
+            BookGranularity::ByPrice => (
+                book.bids.iter().take(levels).copied().collect(),
+                book.asks.iter().take(levels).copied().collect(),
+                Vec::new(),
+                Vec::new(),
+            ),
+            BookGranularity::ByOrder => (
+                Vec::new(),
+                Vec::new(),
+                top_level_orders(&book.bid_orders, levels),
+                top_level_orders(&book.ask_orders, levels),
+            ),
+        };
+        EngineMessage::Snapshot {
+            client_id,
+            timestamp: latest.published_at.clone(),
+            instrument_id,
+            bids,
+            asks,
+            depth,
+            granularity,
+            bid_orders,
+            ask_orders,
+        }
+    }
+}
+
+// The orders on the first `levels` distinct prices of a side, best first
+fn top_level_orders(orders: &[(OrderID, Price, Quantity)], levels: usize) -> Vec<(OrderID, Price, Quantity)> {
+    let mut prices = 0;
+    let mut last_price = None;
+    orders
+        .iter()
+        .take_while(|(_, price, _)| {
+            if last_price != Some(*price) {
+                last_price = Some(*price);
+                prices += 1;
+            }
+            prices <= levels
+        })
+        .copied()
+        .collect()
+}
+
+// Asks the engine to publish its books once per the configured interval, queued behind whatever it was
+// sent before. Returns once `shutdown` reads true or its sender is dropped, or the engine is gone.
+pub async fn publish_periodically(config: Arc<LiveConfig>, tx: InboundSender, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = config.snapshot().book_view_interval();
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                if tx.send(EngineMessage::PublishBookViews).is_err() {
+                    return;
+                }
+                // A reloaded interval takes over from the next publication
+                let reloaded = config.snapshot().book_view_interval();
+                if reloaded != interval {
+                    interval = reloaded;
+                    ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+                    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use fefix::definitions::fix50::{OrdType, Side};
+
+    use super::*;
+    use crate::exchange::Exchange;
+    use crate::fix::testkit::client;
+    use crate::instrument::SpecOverrides;
+
+    fn create_instrument(exchange: &mut Exchange) {
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+    }
+
+    fn limit_order(exchange: &mut Exchange, account: &str, side: Side, quantity: Quantity, price: f64) {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side,
+            quantity,
+            price: Some(Price::from(price)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        });
+    }
+
+    // Levels best first and agreeing with the orders on them, and the book not crossed
+    fn assert_consistent(book: &PublishedBook) {
+        assert!(book.bids.windows(2).all(|pair| pair[0].0 > pair[1].0), "{:?}", book);
+        assert!(book.asks.windows(2).all(|pair| pair[0].0 < pair[1].0), "{:?}", book);
+        for (levels, orders) in [(&book.bids, &book.bid_orders), (&book.asks, &book.ask_orders)] {
+            for &(price, quantity) in levels {
+                let on_level: Quantity = orders.iter().filter(|order| order.1 == price).map(|order| order.2).sum();
+                assert_eq!(on_level, quantity, "{:?}", book);
+            }
+            assert_eq!(orders.iter().map(|order| order.2).sum::<Quantity>(), levels.iter().map(|level| level.1).sum::<Quantity>());
+        }
+        if let (Some(bid), Some(ask)) = (book.bids.first(), book.asks.first()) {
+            assert!(bid.0 < ask.0, "{:?}", book);
+        }
+    }
+
+    #[test]
+    fn snapshots_come_from_the_last_publication_and_say_when_it_was() {
+        let mut exchange = Exchange::new();
+        let views = exchange.book_views();
+        create_instrument(&mut exchange);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 2, 9.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 11.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 3, 12.0);
+
+        // Nothing published yet
+        let unpublished = views.snapshot(client("WATCHER"), "AAPL".to_string(), None, BookGranularity::ByPrice);
+        assert!(matches!(unpublished, EngineMessage::OrderRejected { .. }), "{:?}", unpublished);
+
+        exchange.handle_message(EngineMessage::PublishBookViews);
+        let published_at = views.latest().published_at.clone();
+        // Published books stay as they were until the next publication
+        limit_order(&mut exchange, "BUYER", Side::Buy, 5, 10.0);
+        let snapshot = views.snapshot(client("WATCHER"), "AAPL".to_string(), Some(1), BookGranularity::ByPrice);
+        assert!(matches!(&snapshot, EngineMessage::Snapshot { bids, asks, timestamp, .. }
+            if *bids == vec![(Price::from(9.0), 2)] && *asks == vec![(Price::from(11.0), 1)] && *timestamp == published_at
+        ), "{:?}", snapshot);
+
+        exchange.handle_message(EngineMessage::PublishBookViews);
+        let snapshot = views.snapshot(client("WATCHER"), "AAPL".to_string(), Some(1), BookGranularity::ByOrder);
+        assert!(matches!(&snapshot, EngineMessage::Snapshot { bid_orders, ask_orders, .. }
+            if *bid_orders == vec![(4, Price::from(10.0), 5)] && *ask_orders == vec![(2, Price::from(11.0), 1)]
+        ), "{:?}", snapshot);
+        assert_eq!(views.latest().sequence, 2);
+    }
+
+    #[test]
+    fn readers_holding_a_publication_never_hold_up_matching_and_always_see_whole_books() {
+        let mut exchange = Exchange::new();
+        let views = exchange.book_views();
+        create_instrument(&mut exchange);
+        exchange.handle_message(EngineMessage::PublishBookViews);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Each reader keeps every publication it reads for the whole run
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let views = Arc::clone(&views);
+                let stop = Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    let mut held = vec![views.latest()];
+                    while !stop.load(Ordering::Relaxed) {
+                        let latest = views.latest();
+                        assert!(latest.sequence >= held.last().unwrap().sequence);
+                        latest.books.values().for_each(assert_consistent);
+                        held.push(latest);
+                    }
+                    held.len()
+                })
+            })
+            .collect();
+
+        // Orders that partly trade, publishing after each round. Fresh accounts keep every
+        // round funded.
+        for round in 0..500 {
+            let price = 10.0 + (round % 5) as f64;
+            let (seller, buyer) = (format!("SELLER{}", round), format!("BUYER{}", round));
+            limit_order(&mut exchange, &seller, Side::Sell, 3, price);
+            limit_order(&mut exchange, &buyer, Side::Buy, 2, price);
+            limit_order(&mut exchange, &buyer, Side::Buy, 1, price - 1.0);
+            exchange.handle_message(EngineMessage::PublishBookViews);
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(views.latest().sequence, 501);
+        assert_consistent(&views.latest().books["AAPL"]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use fefix::fix_values::Timestamp;
+use parking_lot::Mutex;
+
+use crate::types::to_timestamp;
+
+// The one clock every time the exchange stamps is read from, to the microsecond: on orders and
+// fills, candles, reports and the outbound header. fefix's Timestamp stops at milliseconds, so
+// times bound for the wire go through WireValue instead.
+pub(crate) type ExchangeTime = chrono::DateTime<chrono::Utc>;
+
+static PROCESS_CLOCK: OnceLock<Clock> = OnceLock::new();
+
+thread_local! {
+    // A clock a test holds for just the thread it runs on
+    static THREAD_CLOCK: RefCell<Option<Clock>> = const { RefCell::new(None) };
+}
+
+// The wall clock by default, as when live. A manual clock only moves when it is set or advanced, which a
+// simulation does as it steps and a test does by hand, so the same input is stamped with the
+// same times, and given the same ExecIDs, on every run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Clock {
+    manual: Option<Arc<ManualTime>>,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    now: Mutex<ExchangeTime>,
+    next_exec_id: AtomicU64,
+}
+
+impl Clock {
+    pub(crate) fn manual(start: ExchangeTime) -> Self {
+        let next_exec_id = AtomicU64::new(start.timestamp_micros() as u64);
+        Self { manual: Some(Arc::new(ManualTime { now: Mutex::new(start), next_exec_id })) }
+    }
+
+    pub(crate) fn now(&self) -> ExchangeTime {
+        match &self.manual {
+            Some(manual) => *manual.now.lock(),
+            None => chrono::Utc::now(),
+        }
+    }
+
+    // Moves a manual clock to `time`; the wall clock keeps its own
+    pub(crate) fn set(&self, time: ExchangeTime) {
+        if let Some(manual) = &self.manual {
+            *manual.now.lock() = time;
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn advance(&self, by: chrono::Duration) {
+        if let Some(manual) = &self.manual {
+            *manual.now.lock() += by;
+        }
+    }
+
+    // A fresh ExecID(17). Live ones count on from when the server started, so they don't repeat
+    // across restarts; a manual clock's count on from its start.
+    pub(crate) fn next_exec_id(&self) -> u64 {
+        static LIVE: OnceLock<AtomicU64> = OnceLock::new();
+        let next = match &self.manual {
+            Some(manual) => &manual.next_exec_id,
+            None => LIVE.get_or_init(|| AtomicU64::new(chrono::Utc::now().timestamp_micros() as u64)),
+        };
+        next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Makes this the clock read on the current thread until the guard is dropped
+    #[cfg(test)]
+    pub(crate) fn enter(&self) -> EnteredClock {
+        let previous = THREAD_CLOCK.with(|clock| clock.replace(Some(self.clone())));
+        EnteredClock { previous }
+    }
+}
+
+#[cfg(test)]
+pub(crate) struct EnteredClock {
+    previous: Option<Clock>,
+}
+
+#[cfg(test)]
+impl Drop for EnteredClock {
+    fn drop(&mut self) {
+        THREAD_CLOCK.with(|clock| clock.replace(self.previous.take()));
+    }
+}
+
+// Makes `clock` the one every thread reads, once, before the server starts
+pub(crate) fn install(clock: Clock) -> Result<(), String> {
+    PROCESS_CLOCK.set(clock).map_err(|_| "a clock is already installed".to_string())
+}
+
+// The clock the current thread reads: its own if a test entered one, else the server's
+pub(crate) fn current() -> Clock {
+    THREAD_CLOCK.with(|clock| clock.borrow().clone()).unwrap_or_else(|| PROCESS_CLOCK.get().cloned().unwrap_or_default())
+}
+
+pub(crate) fn exchange_now() -> ExchangeTime {
+    current().now()
+}
+
+// The time now, to the millisecond fefix keeps
+pub(crate) fn timestamp_now() -> Timestamp {
+    to_timestamp(&exchange_now())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn start() -> ExchangeTime {
+        chrono::Utc.with_ymd_and_hms(2024, 1, 2, 14, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn a_manual_clock_only_moves_when_told_to() {
+        let clock = Clock::manual(start());
+        assert_eq!(clock.now(), start());
+        clock.advance(chrono::Duration::milliseconds(1_500));
+        assert_eq!(clock.now(), start() + chrono::Duration::milliseconds(1_500));
+        clock.set(start());
+        assert_eq!(clock.now(), start());
+
+        let exec_id = clock.next_exec_id();
+        assert_eq!((exec_id, clock.next_exec_id()), (start().timestamp_micros() as u64, exec_id + 1));
+        assert_eq!(Clock::manual(start()).next_exec_id(), exec_id);
+    }
+
+    #[test]
+    fn an_entered_clock_is_read_on_its_thread_until_it_is_dropped() {
+        let clock = Clock::manual(start());
+        {
+            let _entered = clock.enter();
+            assert_eq!(exchange_now(), start());
+            assert_eq!(format!("{:?}", timestamp_now()), format!("{:?}", to_timestamp(&start())));
+            assert!(std::thread::spawn(|| exchange_now() != start()).join().unwrap());
+        }
+        assert_ne!(exchange_now(), start());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::engine::EngineMessage;
+use crate::inbound::InboundSender;
+use crate::types::ClientID;
+
+// How often the engine is asked to compact, when nothing is waiting for it
+pub const COMPACTION_INTERVAL: Duration = Duration::from_secs(5);
+// Roughly how many price levels, collections and finished orders one pass may touch, so a
+// pass never holds matching up for long. Whatever it leaves, the next pass carries on with.
+pub const COMPACTION_BUDGET: usize = 1_000;
+// Finished orders whose status is kept, newest first, so a late cancel or amend learns what became of them
+pub const FINISHED_ORDER_RETENTION: usize = 100_000;
+// Collections this small are left alone; their spare room costs next to nothing
+const MIN_SHRINK_CAPACITY: usize = 16;
+
+// What compaction gave back. Bytes are estimated from the capacity dropped, not read from the
+// allocator, which may keep freed memory for reuse rather than return it to the system.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CompactionMetrics {
+    pub passes: u64,
+    pub empty_levels_dropped: u64,
+    pub collections_shrunk: u64,
+    pub finished_orders_forgotten: u64,
+    pub bytes_reclaimed: u64,
+}
+
+impl CompactionMetrics {
+    pub(crate) fn shrunk(&mut self, bytes: usize) {
+        if bytes > 0 {
+            self.collections_shrunk += 1;
+            self.bytes_reclaimed += bytes as u64;
+        }
+    }
+
+    fn add(&mut self, other: &CompactionMetrics) {
+        self.passes += other.passes;
+        self.empty_levels_dropped += other.empty_levels_dropped;
+        self.collections_shrunk += other.collections_shrunk;
+        self.finished_orders_forgotten += other.finished_orders_forgotten;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+    }
+}
+
+// One pass's work, and whether it got through everything or stopped at its budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPass {
+    pub reclaimed: CompactionMetrics,
+    pub complete: bool,
+}
+
+impl CompactionPass {
+    pub fn summary(&self) -> String {
+        format!(
+            "Compaction reclaimed about {} bytes: {} empty levels dropped, {} collections shrunk, {} finished orders forgotten{}",
+            self.reclaimed.bytes_reclaimed,
+            self.reclaimed.empty_levels_dropped,
+            self.reclaimed.collections_shrunk,
+            self.reclaimed.finished_orders_forgotten,
+            if self.complete { "" } else { "; more left for the next pass" }
+        )
+    }
+}
+
+// Everything compaction has given back since the exchange started, for the admin API
+#[derive(Debug, Default)]
+pub struct CompactionStats {
+    totals: Mutex<CompactionMetrics>,
+}
+
+impl CompactionStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn totals(&self) -> CompactionMetrics {
+        *self.totals.lock()
+    }
+
+    pub(crate) fn record(&self, reclaimed: &CompactionMetrics) {
+        self.totals.lock().add(reclaimed);
+    }
+}
+
+// Shrinks a map down to what it holds once it is under a quarter full, returning the bytes
+// that frees: each bucket is an entry and a control byte
+pub(crate) fn shrink_map<K: Eq + Hash, V>(map: &mut HashMap<K, V>) -> usize {
+    let capacity = map.capacity();
+    if capacity < MIN_SHRINK_CAPACITY || map.len() * 4 > capacity {
+        return 0;
+    }
+    map.shrink_to_fit();
+    (capacity - map.capacity()) * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+pub(crate) fn shrink_queue<T>(queue: &mut VecDeque<T>) -> usize {
+    let capacity = queue.capacity();
+    if capacity < MIN_SHRINK_CAPACITY || queue.len() * 4 > capacity {
+        return 0;
+    }
+    queue.shrink_to_fit();
+    (capacity - queue.capacity()) * std::mem::size_of::<T>()
+}
+
+fn shrink_shared_map<K: Eq + Hash + Clone, V>(map: &DashMap<K, V>) -> usize {
+    let capacity = map.capacity();
+    if capacity < MIN_SHRINK_CAPACITY || map.len() * 4 > capacity {
+        return 0;
+    }
+    map.shrink_to_fit();
+    capacity.saturating_sub(map.capacity()) * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+// Asks the engine for a compaction pass once per `interval`, but only on ticks when nothing
+// is queued for it, so a pass never delays a client's message. The session maps, which the
+// engine doesn't own, are shrunk here. Returns once `shutdown` reads true or its sender is
+// dropped, or the engine is gone.
+pub async fn compact_periodically(
+    interval: Duration,
+    tx: InboundSender,
+    sessions: &'static DashMap<ClientID, UnboundedSender<Bytes>>,
+    stats: Arc<CompactionStats>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let queue_depth = tx.queue_depth();
+    loop {
+        tokio::select! {
+            biased;
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                if queue_depth.get() > 0 {
+                    continue;
+                }
+                let mut reclaimed = CompactionMetrics::default();
+                reclaimed.shrunk(shrink_shared_map(sessions));
+                reclaimed.shrunk(shrink_shared_map(crate::rest::pending_responses()));
+                stats.record(&reclaimed);
+                if tx.send(EngineMessage::Compact { client_id: None }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fefix::definitions::fix50::{OrdType, Side};
+    use fefix::fix_values::Timestamp;
+
+    use super::*;
+    use crate::exchange::Exchange;
+    use crate::instrument::SpecOverrides;
+    use crate::tests::allocations::live_bytes;
+    use crate::types::*;
+
+    fn client() -> ClientID {
+        ClientID::new("BURST".to_string(), None)
+    }
+
+    // Bids small enough that the account's starting cash covers the whole burst
+    fn bid(exchange: &mut Exchange, price: f64) -> OrderID {
+        let events = exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(),
+            account_id: "BURST".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(price)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        });
+        match events.first() {
+            Some(EngineMessage::OrderAccepted { order_id, .. }) => *order_id,
+            _ => panic!("order not accepted: {:?}", events),
+        }
+    }
+
+    fn cancel(exchange: &mut Exchange, order_id: OrderID) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(),
+            account_id: "BURST".to_string(),
+            order_id,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        })
+    }
+
+    #[test]
+    fn a_drained_burst_is_given_back_a_budget_at_a_time() {
+        let mut exchange = Exchange::new().with_finished_order_retention(100);
+        let stats = exchange.compaction_stats();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+        // Warm up with what stays: the account and a single order on the book
+        let resting = bid(&mut exchange, 0.001);
+        let quiet = live_bytes();
+
+        // A burst deep on one level and wide across many, then cancelled away
+        let mut burst: Vec<OrderID> = (0..2_000).map(|_| bid(&mut exchange, 0.01)).collect();
+        burst.extend((0..3_000).map(|level| bid(&mut exchange, 0.1 + level as f64 * 0.0001)));
+        let peak = live_bytes();
+        for &order_id in &burst {
+            cancel(&mut exchange, order_id);
+        }
+        // A cancel frees each order but not the room it took
+        let drained = live_bytes();
+        assert!(drained - quiet > (peak - quiet) / 10, "cancelling alone gave back {} of {} bytes", peak - drained, peak - quiet);
+
+        let mut passes = 0;
+        loop {
+            passes += 1;
+            let pass = exchange.handle_message(EngineMessage::Compact { client_id: Some(client()) });
+            let [EngineMessage::LogEvent { message, .. }] = pass.as_slice() else {
+                panic!("expected the pass's summary: {:?}", pass);
+            };
+            if !message.ends_with("more left for the next pass") {
+                break;
+            }
+        }
+        let compacted = live_bytes();
+
+        // No pass forgot more than its budget, and every finished order past retention is gone
+        let totals = stats.totals();
+        assert!(passes > 1 && passes as u64 == totals.passes, "{} passes, {:?}", passes, totals);
+        assert_eq!(totals.finished_orders_forgotten, 4_900);
+        assert!(totals.collections_shrunk > 0 && totals.bytes_reclaimed > 0, "{:?}", totals);
+        // All but a sliver of the burst's footprint is back, and the estimate is in the right range
+        let given_back = drained - compacted;
+        assert!(compacted - quiet < (peak - quiet) / 10, "{} bytes left of a {} byte burst", compacted - quiet, peak - quiet);
+        assert!(totals.bytes_reclaimed as isize > given_back / 4 && (totals.bytes_reclaimed as isize) < given_back * 4, "estimated {:?}, measured {}", totals, given_back);
+
+        // What was resting all along still is, and still answers to its OrderID
+        let cancelled = cancel(&mut exchange, resting);
+        assert!(matches!(cancelled.as_slice(), [EngineMessage::OrderCancelled { order_id, .. }] if *order_id == resting), "{:?}", cancelled);
+    }
+}
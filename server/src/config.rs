@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::activity::ACTIVITY_INTERVAL;
+use crate::book_views::BOOK_VIEW_INTERVAL;
+use crate::exchange::MAX_EXECUTIONS_PER_ORDER;
+use crate::gateway::ConnectionLimits;
+use crate::overload::{OVERLOAD_QUEUE_DEPTH, OVERLOAD_RESUME_DEPTH, OVERLOAD_SAMPLES};
+use crate::types::InstrumentID;
+
+pub const DEFAULT_MAX_INVALID_MESSAGES: usize = 10;
+
+// The level messages are printed at, shared by every thread so a reload takes effect at once
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+// How much the server prints; each level also prints everything above it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+// Whether a message at `level` is printed under the level in force
+pub fn logs(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+// The server's settings, e.g.
+//   log_level = "warn"
+//   max_connections = 512
+//   price_band_percent = 10.0
+//   [price_bands]
+//   AAPL = 5.0
+// Anything left out keeps its default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub log_level: LogLevel,
+    pub fix_address: String, // bound once at startup
+    pub rest_address: String, // bound once at startup; loopback by default, as REST clients are not authenticated
+    pub max_connections: usize, // open at once across all sources, 0 = unlimited
+    pub max_connects_per_window: usize, // per source address, 0 = unlimited
+    pub rate_window_ms: u64,
+    pub violations_before_ban: usize,
+    pub ban_seconds: u64,
+    pub max_invalid_messages: usize, // unreadable messages in a row that get a session logged out, 0 = no limit
+    pub book_view_interval_ms: u64,
+    pub activity_interval_ms: u64, // how often subscribers are sent each book's activity
+    pub price_band_percent: f64, // how far from a book's last trade a priced order may be, 0 = any distance
+    pub price_bands: HashMap<InstrumentID, f64>, // per instrument, in place of price_band_percent
+    pub max_executions_per_order: usize, // trades one order may make before its leaves are cancelled, 0 = unlimited
+    pub statement_dir: PathBuf, // where account statements are written as CSV
+    pub webhook_url: Option<Url>, // POSTed every trade as JSON, if set
+    pub overload_queue_depth: usize, // queued messages that put the exchange in cancel-only mode, 0 = never
+    pub overload_samples: usize, // samples in a row over overload_queue_depth before it does
+    pub overload_resume_depth: usize, // queued messages new orders are taken again under
+    pub admin_token: Option<String>, // what REST /admin requests carry as "Authorization: Bearer ..."; unset refuses them all
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let config = Self {
+            log_level: LogLevel::default(),
+            fix_address: "0.0.0.0:9000".to_string(),
+            rest_address: "127.0.0.1:8080".to_string(),
+            max_connections: 0,
+            max_connects_per_window: 0,
+            rate_window_ms: 0,
+            violations_before_ban: 0,
+            ban_seconds: 0,
+            max_invalid_messages: DEFAULT_MAX_INVALID_MESSAGES,
+            book_view_interval_ms: BOOK_VIEW_INTERVAL.as_millis() as u64,
+            activity_interval_ms: ACTIVITY_INTERVAL.as_millis() as u64,
+            price_band_percent: 0.0,
+            price_bands: HashMap::new(),
+            max_executions_per_order: MAX_EXECUTIONS_PER_ORDER,
+            statement_dir: PathBuf::from("statements"),
+            webhook_url: None,
+            overload_queue_depth: OVERLOAD_QUEUE_DEPTH,
+            overload_samples: OVERLOAD_SAMPLES,
+            overload_resume_depth: OVERLOAD_RESUME_DEPTH,
+            admin_token: None,
+        };
+        config.with_connection_limits(&ConnectionLimits::default())
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if config.book_view_interval_ms == 0 {
+            return Err(format!("{}: book_view_interval_ms must be at least 1", path.display()));
+        }
+        if config.activity_interval_ms == 0 {
+            return Err(format!("{}: activity_interval_ms must be at least 1", path.display()));
+        }
+        if let Some((instrument_id, _)) = config.price_bands.iter().find(|(_, band)| **band < 0.0) {
+            return Err(format!("{}: price band for {} is negative", path.display(), instrument_id));
+        }
+        if config.price_band_percent < 0.0 {
+            return Err(format!("{}: price_band_percent is negative", path.display()));
+        }
+        if config.overload_samples == 0 {
+            return Err(format!("{}: overload_samples must be at least 1", path.display()));
+        }
+        if config.overload_queue_depth != 0 && config.overload_resume_depth >= config.overload_queue_depth {
+            return Err(format!("{}: overload_resume_depth must be under overload_queue_depth", path.display()));
+        }
+        Ok(config)
+    }
+
+    pub fn with_connection_limits(mut self, limits: &ConnectionLimits) -> Self {
+        self.max_connections = limits.max_connections;
+        self.max_connects_per_window = limits.max_connects_per_window;
+        self.rate_window_ms = limits.rate_window.as_millis() as u64;
+        self.violations_before_ban = limits.violations_before_ban;
+        self.ban_seconds = limits.ban_duration.as_secs();
+        self
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: self.max_connections,
+            max_connects_per_window: self.max_connects_per_window,
+            rate_window: Duration::from_millis(self.rate_window_ms),
+            violations_before_ban: self.violations_before_ban,
+            ban_duration: Duration::from_secs(self.ban_seconds),
+        }
+    }
+
+    pub fn book_view_interval(&self) -> Duration {
+        Duration::from_millis(self.book_view_interval_ms)
+    }
+
+    pub fn activity_interval(&self) -> Duration {
+        Duration::from_millis(self.activity_interval_ms)
+    }
+
+    // The trades one order may make, usize::MAX for any number
+    pub fn max_executions(&self) -> usize {
+        if self.max_executions_per_order == 0 { usize::MAX } else { self.max_executions_per_order }
+    }
+
+    // The band, as a percentage, a priced order for `instrument_id` must lie within; 0 = none
+    pub fn price_band(&self, instrument_id: &str) -> f64 {
+        self.price_bands.get(instrument_id).copied().unwrap_or(self.price_band_percent)
+    }
+}
+
+// What a reload changed, by setting name. Refused settings keep the value they had.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    pub applied: Vec<&'static str>,
+    pub refused: Vec<String>,
+}
+
+impl std::fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let applied = if self.applied.is_empty() { "nothing".to_string() } else { self.applied.join(", ") };
+        write!(f, "Config reloaded, applied {}", applied)?;
+        if !self.refused.is_empty() {
+            write!(f, "; not applied: {}", self.refused.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// The settings in force, reloadable from their file while running. Readers take a snapshot,
+// so a reload never changes settings under one partway through its work.
+#[derive(Debug)]
+pub struct LiveConfig {
+    path: Option<PathBuf>,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl LiveConfig {
+    // No config file: the settings given are the settings for good. The log level stays the
+    // process's own, so a component given fixed settings, as in tests, leaves it alone.
+    pub fn fixed(config: ServerConfig) -> Arc<Self> {
+        Arc::new(Self { path: None, current: RwLock::new(Arc::new(config)) })
+    }
+
+    pub fn load(path: &Path) -> Result<Arc<Self>, String> {
+        let config = ServerConfig::load(path)?;
+        LOG_LEVEL.store(config.log_level as u8, Ordering::Relaxed);
+        Ok(Arc::new(Self { path: Some(path.to_path_buf()), current: RwLock::new(Arc::new(config)) }))
+    }
+
+    pub fn snapshot(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.current.read())
+    }
+
+    // Rereads the file, keeping the current settings if it cannot be loaded
+    pub fn reload(&self) -> Result<ReloadReport, String> {
+        let path = self.path.as_ref().ok_or("no config file configured")?;
+        Ok(self.apply(ServerConfig::load(path)?))
+    }
+
+    // Takes every changed setting that can change while running; the listening addresses
+    // stay as they were bound
+    pub fn apply(&self, mut next: ServerConfig) -> ReloadReport {
+        let mut current = self.current.write();
+        let mut report = ReloadReport::default();
+        if next.fix_address != current.fix_address {
+            report.refused.push("fix_address needs a restart".to_string());
+            next.fix_address = current.fix_address.clone();
+        }
+        if next.rest_address != current.rest_address {
+            report.refused.push("rest_address needs a restart".to_string());
+            next.rest_address = current.rest_address.clone();
+        }
+        let changes = [
+            ("log_level", current.log_level != next.log_level),
+            ("max_connections", current.max_connections != next.max_connections),
+            ("max_connects_per_window", current.max_connects_per_window != next.max_connects_per_window),
+            ("rate_window_ms", current.rate_window_ms != next.rate_window_ms),
+            ("violations_before_ban", current.violations_before_ban != next.violations_before_ban),
+            ("ban_seconds", current.ban_seconds != next.ban_seconds),
+            ("max_invalid_messages", current.max_invalid_messages != next.max_invalid_messages),
+            ("book_view_interval_ms", current.book_view_interval_ms != next.book_view_interval_ms),
+            ("activity_interval_ms", current.activity_interval_ms != next.activity_interval_ms),
+            ("price_band_percent", current.price_band_percent != next.price_band_percent),
+            ("price_bands", current.price_bands != next.price_bands),
+            ("max_executions_per_order", current.max_executions_per_order != next.max_executions_per_order),
+            ("statement_dir", current.statement_dir != next.statement_dir),
+            ("webhook_url", current.webhook_url != next.webhook_url),
+            ("overload_queue_depth", current.overload_queue_depth != next.overload_queue_depth),
+            ("overload_samples", current.overload_samples != next.overload_samples),
+            ("overload_resume_depth", current.overload_resume_depth != next.overload_resume_depth),
+            ("admin_token", current.admin_token != next.admin_token),
+        ];
+        report.applied = changes.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect();
+        LOG_LEVEL.store(next.log_level as u8, Ordering::Relaxed);
+        *current = Arc::new(next);
+        report
+    }
+}
+
+// Reloads the config file each time the process is sent SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_hangup(config: Arc<LiveConfig>) {
+    let Ok(mut hangups) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) else {
+        eprintln!("Could not listen for SIGHUP; the config reloads only over the admin API");
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        match config.reload() {
+            Ok(report) => println!("{}", report),
+            Err(e) => eprintln!("Config reload failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_reload_applies_what_can_change_while_running_and_names_what_needs_a_restart() {
+        let path = std::env::temp_dir().join(format!("server-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "max_connections = 10\nprice_band_percent = 10.0\n").unwrap();
+        let config = LiveConfig::load(&path).unwrap();
+        assert_eq!(config.snapshot().connection_limits().max_connections, 10);
+        assert_eq!(config.snapshot().rest_address, "127.0.0.1:8080");
+
+        std::fs::write(&path, "max_connections = 10\nprice_band_percent = 5.0\nrest_address = \"127.0.0.1:8081\"\n[price_bands]\nAAPL = 2.0\n").unwrap();
+        let report = config.reload().unwrap();
+        assert_eq!(report, ReloadReport { applied: vec!["price_band_percent", "price_bands"], refused: vec!["rest_address needs a restart".to_string()] });
+        assert_eq!(report.to_string(), "Config reloaded, applied price_band_percent, price_bands; not applied: rest_address needs a restart");
+        let snapshot = config.snapshot();
+        assert_eq!((snapshot.price_band("AAPL"), snapshot.price_band("MSFT")), (2.0, 5.0));
+        assert_eq!(snapshot.rest_address, "127.0.0.1:8080");
+
+        // A file that no longer loads leaves the settings in force untouched
+        std::fs::write(&path, "price_band_percent = -1.0\n").unwrap();
+        assert!(config.reload().is_err());
+        std::fs::write(&path, "unknown_setting = 1\n").unwrap();
+        assert!(config.reload().is_err());
+        std::fs::write(&path, "overload_queue_depth = 100\noverload_resume_depth = 100\n").unwrap();
+        assert!(config.reload().is_err());
+        assert_eq!(config.snapshot(), snapshot);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::clock::exchange_now;
+
+// How far a signed Logon's SendingTime may be from the server clock, so a captured
+// signature cannot be replayed later
+const MAX_SIGNATURE_SKEW_SECONDS: i64 = 120;
+
+// Which CompIDs may log on and how each proves who it is, e.g.
+//   require_credentials = true
+//   [comp_ids.FIRM1]
+//   password = "..."
+//   [comp_ids.FIRM2]
+//   hmac_key = "<hex>"
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialsConfig {
+    #[serde(default)]
+    pub require_credentials: bool, // refuse CompIDs with no entry rather than let them in unchecked
+    #[serde(default)]
+    pub comp_ids: HashMap<String, Credential>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Credential {
+    Password(String),
+    HmacKey(String), // hex; the Logon signs "SendingTime|MsgSeqNum" with HMAC-SHA256
+}
+
+// Secrets stay out of logs
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credential::Password(_) => f.write_str("Password(..)"),
+            Credential::HmacKey(_) => f.write_str("HmacKey(..)"),
+        }
+    }
+}
+
+impl CredentialsConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Self = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (comp_id, credential) in &config.comp_ids {
+            if let Credential::HmacKey(key) = credential {
+                hex::decode(key).map_err(|e| format!("{}: hmac_key for {}: {}", path.display(), comp_id, e))?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+// What a Logon carried to prove who sent it, kept apart from the engine message so secrets
+// never travel past the session
+#[derive(Clone, Default)]
+pub struct LogonCredentials {
+    pub password: Option<String>, // Password(554)
+    pub signature: Option<String>, // RawData(96), the hex HMAC
+    pub sending_time: String, // SendingTime(52) exactly as sent
+    pub msg_seq_num: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogonFailure {
+    NotLoggedOn, // the session opened with something other than a Logon
+    MissingCredentials,
+    InvalidCredentials,
+    StaleSignature,
+    UnknownNamespace, // the TargetCompID names no namespace this server runs
+}
+
+impl LogonFailure {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LogonFailure::NotLoggedOn => "Logon required",
+            LogonFailure::MissingCredentials => "Credentials required",
+            // Unknown CompIDs and wrong secrets read the same, so neither can be probed for
+            LogonFailure::InvalidCredentials => "Invalid credentials",
+            LogonFailure::StaleSignature => "Logon signature expired",
+            LogonFailure::UnknownNamespace => "Unknown TargetCompID",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authenticated {
+    Verified, // proved its CompID, so the session is held to it
+    Unchecked, // no credentials configured for the CompID and none required
+}
+
+// The credentials sessions are checked against, reloadable from their file while running.
+// A reload only applies to logons after it; sessions already open stay open.
+pub struct Credentials {
+    path: Option<PathBuf>,
+    config: RwLock<CredentialsConfig>,
+}
+
+impl Credentials {
+    // No credentials file: every CompID is let in unchecked
+    pub fn open() -> Arc<Self> {
+        Self::from_config(CredentialsConfig::default())
+    }
+
+    pub fn from_config(config: CredentialsConfig) -> Arc<Self> {
+        Arc::new(Self { path: None, config: RwLock::new(config) })
+    }
+
+    pub fn load(path: &Path) -> Result<Arc<Self>, String> {
+        let config = CredentialsConfig::load(path)?;
+        Ok(Arc::new(Self { path: Some(path.to_path_buf()), config: RwLock::new(config) }))
+    }
+
+    // Rereads the file, keeping the current credentials if it cannot be loaded. Returns how
+    // many CompIDs it configures.
+    pub fn reload(&self) -> Result<usize, String> {
+        let path = self.path.as_ref().ok_or("no credentials file configured")?;
+        let config = CredentialsConfig::load(path)?;
+        let comp_ids = config.comp_ids.len();
+        *self.config.write() = config;
+        Ok(comp_ids)
+    }
+
+    pub fn required(&self) -> bool {
+        self.config.read().require_credentials
+    }
+
+    // Whether a session may send a message from `comp_id`: one that logged on with credentials
+    // only as itself, any other only as a CompID that needs none
+    pub fn may_send_as(&self, verified: Option<&str>, comp_id: &str) -> bool {
+        match verified {
+            Some(verified) => verified == comp_id,
+            None => self.verify(comp_id, None) == Ok(Authenticated::Unchecked),
+        }
+    }
+
+    // Checks the first message of a session: `logon` is None when it was not a Logon
+    pub fn verify(&self, comp_id: &str, logon: Option<&LogonCredentials>) -> Result<Authenticated, LogonFailure> {
+        let config = self.config.read();
+        let credential = match config.comp_ids.get(comp_id) {
+            Some(credential) => credential,
+            None if !config.require_credentials => return Ok(Authenticated::Unchecked),
+            None => {
+                return Err(match logon {
+                    None => LogonFailure::NotLoggedOn,
+                    Some(logon) if logon.password.is_none() && logon.signature.is_none() => LogonFailure::MissingCredentials,
+                    Some(_) => LogonFailure::InvalidCredentials,
+                });
+            }
+        };
+        let logon = logon.ok_or(LogonFailure::NotLoggedOn)?;
+        match credential {
+            Credential::Password(expected) => {
+                let password = logon.password.as_deref().ok_or(LogonFailure::MissingCredentials)?;
+                if !constant_time_eq(password.as_bytes(), expected.as_bytes()) {
+                    return Err(LogonFailure::InvalidCredentials);
+                }
+            }
+            Credential::HmacKey(key) => {
+                let (Some(signature), Some(msg_seq_num)) = (&logon.signature, logon.msg_seq_num) else {
+                    return Err(LogonFailure::MissingCredentials);
+                };
+                let signature = hex::decode(signature).map_err(|_| LogonFailure::InvalidCredentials)?;
+                // Checked at load, so this only fails if the key was never valid hex
+                let key = hex::decode(key).map_err(|_| LogonFailure::InvalidCredentials)?;
+                let mut mac = Hmac::<Sha256>::new_from_slice(&key).map_err(|_| LogonFailure::InvalidCredentials)?;
+                mac.update(signed_text(&logon.sending_time, msg_seq_num).as_bytes());
+                mac.verify_slice(&signature).map_err(|_| LogonFailure::InvalidCredentials)?;
+                if !is_recent(&logon.sending_time) {
+                    return Err(LogonFailure::StaleSignature);
+                }
+            }
+        }
+        Ok(Authenticated::Verified)
+    }
+}
+
+// What a signed Logon's HMAC covers
+pub fn signed_text(sending_time: &str, msg_seq_num: u64) -> String {
+    format!("{}|{}", sending_time, msg_seq_num)
+}
+
+fn is_recent(sending_time: &str) -> bool {
+    NaiveDateTime::parse_from_str(sending_time, "%Y%m%d-%H:%M:%S%.f")
+        .is_ok_and(|sent| (exchange_now().naive_utc() - sent).num_seconds().abs() <= MAX_SIGNATURE_SKEW_SECONDS)
+}
+
+// Compares without returning early, so timing does not reveal how much of a password matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff";
+
+    fn signed_logon(sending_time: &str, msg_seq_num: u64) -> LogonCredentials {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&hex::decode(KEY).unwrap()).unwrap();
+        mac.update(signed_text(sending_time, msg_seq_num).as_bytes());
+        LogonCredentials {
+            signature: Some(hex::encode(mac.finalize().into_bytes())),
+            sending_time: sending_time.to_string(),
+            msg_seq_num: Some(msg_seq_num),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signed_logons_must_be_recent_and_signed_over_what_they_claim() {
+        let credentials = Credentials::from_config(CredentialsConfig {
+            require_credentials: true,
+            comp_ids: HashMap::from([("FIRM1".to_string(), Credential::HmacKey(KEY.to_string()))]),
+        });
+        let now = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+        assert_eq!(credentials.verify("FIRM1", Some(&signed_logon(&now, 1))), Ok(Authenticated::Verified));
+
+        let mut replayed = signed_logon(&now, 1);
+        replayed.msg_seq_num = Some(2);
+        assert_eq!(credentials.verify("FIRM1", Some(&replayed)), Err(LogonFailure::InvalidCredentials));
+        let stale = signed_logon("20240102-14:30:00.000", 1);
+        assert_eq!(credentials.verify("FIRM1", Some(&stale)), Err(LogonFailure::StaleSignature));
+        assert_eq!(credentials.verify("FIRM2", Some(&signed_logon(&now, 1))), Err(LogonFailure::InvalidCredentials));
+        assert_eq!(credentials.verify("FIRM1", None), Err(LogonFailure::NotLoggedOn));
+    }
+
+    #[test]
+    fn reload_picks_up_file_changes_and_keeps_the_old_credentials_on_a_bad_file() {
+        let path = std::env::temp_dir().join(format!("credentials-{}.toml", std::process::id()));
+        let password = |password: &str| LogonCredentials { password: Some(password.to_string()), ..Default::default() };
+        std::fs::write(&path, "[comp_ids.FIRM1]\npassword = \"first\"\n").unwrap();
+        let credentials = Credentials::load(&path).unwrap();
+        assert_eq!(credentials.verify("FIRM1", Some(&password("first"))), Ok(Authenticated::Verified));
+        assert_eq!(credentials.verify("FIRM2", None), Ok(Authenticated::Unchecked));
+
+        std::fs::write(&path, "require_credentials = true\n[comp_ids.FIRM1]\npassword = \"second\"\n").unwrap();
+        assert_eq!(credentials.reload(), Ok(1));
+        assert_eq!(credentials.verify("FIRM1", Some(&password("first"))), Err(LogonFailure::InvalidCredentials));
+        assert_eq!(credentials.verify("FIRM2", None), Err(LogonFailure::NotLoggedOn));
+
+        std::fs::write(&path, "[comp_ids.FIRM1]\nhmac_key = \"not hex\"\n").unwrap();
+        assert!(credentials.reload().is_err());
+        assert_eq!(credentials.verify("FIRM1", Some(&password("second"))), Ok(Authenticated::Verified));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use fefix::definitions::HardCodedFixFieldDefinition;
+use fefix::dict::{FieldLocation, FixDatatype, LayoutItem, LayoutItemKind};
+use fefix::Dictionary;
+
+// Defines each of the exchange's own tags as fefix defines a dictionary field, so every
+// message type reads and writes a tag through the one named, typed definition, and lists
+// them all for the dictionary the codec reads and writes with.
+macro_rules! custom_fields {
+    ($($constant:ident = $tag:literal, $name:literal, $data_type:ident;)*) => {
+        $(
+            pub(crate) const $constant: &HardCodedFixFieldDefinition = &HardCodedFixFieldDefinition {
+                name: $name,
+                tag: $tag,
+                is_group_leader: false,
+                data_type: FixDatatype::$data_type,
+                location: FieldLocation::Body,
+            };
+        )*
+
+        const CUSTOM_FIELDS: &[&HardCodedFixFieldDefinition] = &[$($constant),*];
+    };
+}
+
+// Tags from 8000 up are the exchange's, carried by its own message types and on standard ones
+// where FIX has no field for what it says
+custom_fields! {
+    MAX_BOOK_LEVELS = 8001, "MaxBookLevels", Int;
+    PRICE_LEVEL_POLICY = 8002, "PriceLevelPolicy", Char; // E to evict the worst level for a new one, R to refuse it
+    CASH_BALANCE = 8003, "ExchangeCashBalance", Amt;
+    SPLIT_NUMERATOR = 8004, "SplitNumerator", Int;
+    SPLIT_DENOMINATOR = 8005, "SplitDenominator", Int;
+    DIVIDEND_PER_SHARE = 8006, "DividendPerShare", Amt;
+    CASH_ADJUSTMENT = 8007, "CashAdjustment", Amt;
+    MAX_RESTING_ORDERS = 8008, "MaxRestingOrders", Int;
+    REPLAY_FROM_TIME = 8009, "ReplayFromTime", UtcTimestamp;
+    REPLAY_TO_TIME = 8010, "ReplayToTime", UtcTimestamp;
+    SMP_ACTION = 8011, "SmpAction", Char; // N to allow self-matches, R to reject the aggressor
+    MAX_ORDER_VALUE = 8012, "MaxOrderValue", Amt;
+    IMBALANCE_ALERT_THRESHOLD = 8013, "ImbalanceAlertThreshold", Float;
+    IMBALANCE_RATIO = 8014, "ImbalanceRatio", Float;
+    QUEUE_DEPTH_BUCKET = 8015, "QueueDepthBucket", Int;
+    BOOK_SPREAD = 8016, "BookSpread", PriceOffset;
+    BID_DEPTH = 8017, "BidDepth", Qty;
+    ASK_DEPTH = 8018, "AskDepth", Qty;
+    FILL_RATE = 8019, "FillRate", Float;
+    IMPACT_COST_ESTIMATE = 8020, "ImpactCostEstimate", Float;
+    CIRCUIT_BREAKER = 8021, "CircuitBreaker", Boolean;
+    BID_LEVELS = 8022, "BidLevels", Int;
+    ASK_LEVELS = 8023, "AskLevels", Int;
+    ARRIVAL_BID = 8024, "ArrivalBid", Price;
+    ARRIVAL_ASK = 8025, "ArrivalAsk", Price;
+    ORDER_TYPES = 8026, "OrderTypes", MultipleCharValue; // the OrdType(40) values an instrument takes, space separated
+    SPREAD_LEGS = 8027, "SpreadLegs", MultipleStringValue; // a spread's legs as Symbol:ratio, space separated, e.g. "ESZ4:1 ESH5:-1"
+    SPREAD_ANCHOR = 8028, "SpreadAnchor", String; // the leg whose last trade a spread's fills are priced off
+    UNCROSS_POLICY = 8029, "UncrossPolicy", Char; // R to trade a crossed book out at resting prices, A by auction
+    MAX_DAILY_LOSS = 8030, "MaxDailyLoss", Amt; // realized loss in a day after which an account's new orders are refused
+    EXCHANGE_RECEIVE_TIME = 8031, "ExchangeReceiveTime", UtcTimestamp; // when the exchange took in the message an execution report answers
+    OPTION_EXPIRY = 8032, "OptionExpiry", UtcTimestamp; // when an option expires, to the second unlike MaturityDate(541)
+    DELTA = 8033, "OptionDelta", Float;
+    GAMMA = 8034, "OptionGamma", Float;
+    VEGA = 8035, "OptionVega", Float;
+    SUBSCRIBE_ON_CREATE = 8036, "SubscribeOnCreate", Boolean; // Y to subscribe to a symbol not yet listed once it is, rather than be refused
+    HALT_POLICY = 8037, "HaltPolicy", Char; // C to cancel a book's resting orders when it halts, F to keep them for the resumption
+    MAX_TOUCH_DISTANCE = 8038, "MaxTouchDistance", PriceOffset; // how far from the touch a new price level may open, 0 = any distance
+    TOUCH_WINDOW_POLICY = 8039, "TouchWindowPolicy", Char; // G to keep levels the touch leaves outside that distance, C to cancel them
+    PING_ID = 8040, "PingID", Int; // chosen by the client, echoed on the Pong that answers it
+    UNTRIGGERED = 8041, "Untriggered", Boolean; // Y on an order status for a stop no trade has reached yet
+    ACTIVITY_REPORTS = 8042, "ActivityReports", Boolean; // Y on a market data subscription for each book's activity report too
+    TRADES_PER_SECOND = 8043, "TradesPerSecond", Float;
+    MESSAGES_PER_SECOND = 8044, "MessagesPerSecond", Float;
+    MAX_QUEUE_DELAY = 8045, "MaxQueueDelay", Int; // milliseconds
+    LAST_SALE_FEED = 8046, "LastSaleFeed", Boolean; // Y on a market data subscription for each trade on the tape too
+    CANCEL_ONLY = 8047, "CancelOnly", Boolean; // Y on a trading session status while new orders are refused
+    SPREAD_HALT_MULTIPLIER = 8048, "SpreadHaltMultiplier", Float; // times its reference spread a book's spread may widen before it halts, 0 = never
+    REFERENCE_SPREAD = 8049, "ReferenceSpread", PriceOffset; // the spread a book starts out measuring against, 0 = its first
+    HALT_DURATION = 8050, "HaltDuration", Int; // seconds a volatility halt lasts
+    HALT_ENDS = 8051, "HaltEnds", UtcTimestamp; // when a volatility halt lifts
+    SUMMARY_FILLS = 8052, "SummaryFills", Boolean; // Y on a Logon for one ExecutionReport per order as it finishes, rather than one per fill
+}
+
+// FIX 5.0 with the exchange's fields added, which messages are decoded with and checked
+// against as they are encoded. Built once: fefix only makes a dictionary from a QuickFIX
+// spec, so FIX 5.0 is written out as one with the exchange's fields after its own.
+pub(crate) fn dictionary() -> &'static Dictionary {
+    static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        Dictionary::from_quickfix_spec(quickfix_spec(&Dictionary::fix50(), CUSTOM_FIELDS)).expect("FIX 5.0 with the exchange's fields reads back")
+    })
+}
+
+// `dict` as a QuickFIX spec, with `extra` fields after its own
+fn quickfix_spec(dict: &Dictionary, extra: &[&HardCodedFixFieldDefinition]) -> String {
+    let mut spec = String::new();
+    let (major, minor) = dict.get_version().trim_start_matches("FIX.").split_once('.').unwrap_or(("5", "0"));
+    let _ = writeln!(spec, "<fix type='FIX' major='{}' minor='{}' servicepack='0'>", major, minor);
+    // Components are written once each, by name, as the dictionary resolves them
+    let component = |spec: &mut String, name: &str| {
+        if let Some(component) = dict.component_by_name(name) {
+            write_layout(spec, &component.items().collect::<Vec<_>>());
+        }
+    };
+    for (section, name) in [("header", "StandardHeader"), ("trailer", "StandardTrailer")] {
+        let _ = writeln!(spec, "<{}>", section);
+        component(&mut spec, name);
+        let _ = writeln!(spec, "</{}>", section);
+    }
+    spec.push_str("<messages>\n");
+    for message in dict.iter_messages() {
+        let _ = writeln!(spec, "<message name='{}' msgtype='{}' msgcat='app'>", escape(message.name()), escape(message.msg_type()));
+        write_layout(&mut spec, &message.layout().collect::<Vec<_>>());
+        spec.push_str("</message>\n");
+    }
+    spec.push_str("</messages>\n<components>\n");
+    let mut names: Vec<String> = dict.iter_components().map(|component| component.name().to_string()).collect();
+    names.sort();
+    names.dedup();
+    for name in names.iter().filter(|name| !matches!(name.as_str(), "StandardHeader" | "StandardTrailer")) {
+        let _ = writeln!(spec, "<component name='{}'>", escape(name));
+        component(&mut spec, name);
+        spec.push_str("</component>\n");
+    }
+    spec.push_str("</components>\n<fields>\n");
+    for field in dict.iter_fields() {
+        let _ = writeln!(spec, "<field number='{}' name='{}' type='{}'>", field.tag(), escape(field.name()), field.fix_datatype().to_quickfix_name());
+        for value in field.enums().into_iter().flatten() {
+            let _ = writeln!(spec, "<value enum='{}' description='{}'/>", escape(value.value()), escape(value.description()));
+        }
+        spec.push_str("</field>\n");
+    }
+    for field in extra {
+        let _ = writeln!(spec, "<field number='{}' name='{}' type='{}'/>", field.tag, escape(field.name), field.data_type.to_quickfix_name());
+    }
+    spec.push_str("</fields>\n</fix>\n");
+    spec
+}
+
+fn write_layout(spec: &mut String, items: &[LayoutItem]) {
+    for item in items {
+        let required = if item.required() { 'Y' } else { 'N' };
+        match item.kind() {
+            LayoutItemKind::Field(field) => {
+                let _ = writeln!(spec, "<field name='{}' required='{}'/>", escape(field.name()), required);
+            }
+            LayoutItemKind::Component(component) => {
+                let _ = writeln!(spec, "<component name='{}' required='{}'/>", escape(component.name()), required);
+            }
+            LayoutItemKind::Group(field, members) => {
+                let _ = writeln!(spec, "<group name='{}' required='{}'>", escape(field.name()), required);
+                write_layout(spec, &members);
+                spec.push_str("</group>\n");
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\'', "&apos;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use fefix::dict::IsFieldDefinition;
+
+    use super::*;
+
+    #[test]
+    fn every_custom_field_has_a_tag_and_name_of_its_own_in_the_custom_range() {
+        let mut tags = HashSet::new();
+        let mut names = HashSet::new();
+        for field in CUSTOM_FIELDS {
+            assert!((8000..=9999).contains(&field.tag), "{:?}", field);
+            assert!(tags.insert(field.tag), "{} is defined twice", field.tag);
+            assert!(names.insert(field.name), "{} is defined twice", field.name);
+        }
+    }
+
+    #[test]
+    fn the_dictionary_is_fix50_with_every_custom_field_added() {
+        let fix50 = Dictionary::fix50();
+        let dict = dictionary();
+        for field in CUSTOM_FIELDS {
+            assert!(fix50.field_by_tag(field.tag as u32).is_none() && fix50.field_by_name(field.name).is_none(), "{:?} shadows FIX 5.0", field);
+            let defined = dict.field_by_tag(field.tag as u32).unwrap();
+            assert_eq!((defined.name(), defined.fix_datatype()), (field.name, field.data_type));
+            assert_eq!(dict.field_by_name(field.name).map(|defined| defined.tag()), Some(field.tag()));
+        }
+        assert_eq!(dict.get_version(), fix50.get_version());
+        assert_eq!(dict.iter_fields().count(), fix50.iter_fields().count() + CUSTOM_FIELDS.len());
+        assert_eq!(dict.iter_messages().count(), fix50.iter_messages().count());
+        for message in fix50.iter_messages() {
+            assert_eq!(dict.message_by_msgtype(message.msg_type()).map(|kept| kept.layout().count()), Some(message.layout().count()), "{}", message.name());
+        }
+        let side = dict.field_by_name("Side").unwrap();
+        assert_eq!(side.enums().map(Iterator::count), fix50.field_by_name("Side").unwrap().enums().map(Iterator::count));
+    }
+}
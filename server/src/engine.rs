@@ -1,92 +1,996 @@
+use std::collections::BTreeMap;
+
 use fefix::definitions::fix50::*;
 use fefix::fix_values::Timestamp;
+use serde::{Deserialize, Serialize};
+use strum_macros::AsRefStr;
 
+use crate::framing::RawMessage;
+use crate::audit::{HistoryPage, OrderEvent, TradeQuery, TradeRecord};
+use crate::greeks::Greeks;
+use crate::instrument::{CorporateAction, SpecOverrides, SpreadDefinition};
+use crate::statements::AccountStatement;
 use crate::types::*;
+use crate::clock::{timestamp_now, ExchangeTime};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, AsRefStr)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")] // the message's type as its JSON gives it
 pub enum EngineMessage {
     NewOrder {
+        #[serde(with = "fix_value_serde")]
         sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
         receiving_time: Timestamp,
         client_id: ClientID,
         account_id: AccountID,
         client_order_id: Option<ClOrdID>,
         instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
         order_type: OrdType,
+        #[serde(with = "fix_value_serde")]
         side: Side,
         quantity: Quantity,
         price: Option<Price>,
+        #[serde(default, with = "optional_fix_value_serde")]
         time_in_force: Option<TimeInForce>,
+        #[serde(default, with = "optional_fix_value_serde")]
+        expire_time: Option<Timestamp>, // required for GoodTillDate
+        // SecurityExchange(207): the venue the order is for, when the symbol alone doesn't say
+        #[serde(default)]
+        exchange_code: Option<String>,
+        // TimeInForce(59)=B: trades only in auctions, resting off the book between them.
+        // fefix's FIX 5.0 TimeInForce has no such value, so it travels beside time_in_force.
+        #[serde(default)]
+        good_for_auction: bool,
+        // The NoStipulations(232) group, entry for entry as the client sent it
+        #[serde(default)]
+        stipulations: Vec<Stipulation>,
+    },
+    // NewOrderMultileg(AB): legs that all trade in full there and then, or none do
+    NewOrderMultileg {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        account_id: AccountID,
+        client_order_id: Option<ClOrdID>,
+        legs: Vec<MultilegLeg>,
     },
     CancelOrder {
+        #[serde(with = "fix_value_serde")]
         sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
         receiving_time: Timestamp,
         client_id: ClientID,
         account_id: AccountID,
-        order_id: OrderID
+        order_id: OrderID, // 0 when the cancel names the order by OrigClOrdID alone
+        #[serde(default)]
+        orig_client_order_id: Option<ClOrdID>, // OrigClOrdID(41)
+        #[serde(default)]
+        cancel_quantity: Option<Quantity>, // take only this much off the leaves, keeping priority; None = all
     },
     CreateInstrument {
+        #[serde(with = "fix_value_serde")]
         sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
         receiving_time: Timestamp,
         client_id: ClientID,
         instrument_id: InstrumentID,
+        #[serde(default)]
+        segment: Option<SegmentName>, // inherits the segment's settings
+        spec: SpecOverrides, // settings left out come from the segment, or the defaults without one
+        #[serde(default)]
+        spread: Option<SpreadDefinition>, // makes the instrument a spread of existing ones
+    },
+    UpdateInstrumentSpec {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        spec: SpecOverrides, // settings left out stay as they are
     },
     AmendOrder {
+        #[serde(with = "fix_value_serde")]
         sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
         receiving_time: Timestamp,
         client_id: ClientID,
         order_id: OrderID,
         new_quantity: Option<Quantity>,
         new_price: Option<Price>,
+        #[serde(default, with = "optional_fix_value_serde")]
         time_in_force: Option<TimeInForce>,
     },
+    PositionQuery {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        account_id: AccountID,
+    },
+    // An execution an upstream venue reported, as its drop copy sends it, booked into the
+    // account as if the fill had been matched here. Only trades move cash and positions.
+    InboundExecutionReport {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        exec_id: String, // the venue's, so a report sent twice is booked once
+        order_id: String, // likewise the venue's, not an OrderID of the exchange's
+        #[serde(with = "fix_value_serde")]
+        exec_type: ExecType,
+        account_id: AccountID,
+        instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        last_qty: Quantity,
+        last_px: Price,
+        cum_qty: Quantity,
+        leaves_qty: Quantity,
+    },
+    // The delta, gamma and vega of an account's options, at their underlyings' last trades
+    GreeksRequest {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        account_id: AccountID,
+    },
+    CorporateAction {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        effective_time: Timestamp,
+        action: CorporateAction,
+    },
+    SetRestingOrderLimit {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        instrument_id: Option<InstrumentID>, // None = the exchange-wide limit
+        max_resting_orders: usize, // 0 = unlimited
+    },
+    SetSmpAction {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        account_id: AccountID,
+        smp_action: SmpAction,
+    },
+    SetRiskLimits {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        account_id: AccountID,
+        limits: RiskLimits,
+    },
+    RequestReplay {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        from_timestamp: Timestamp,
+        #[serde(with = "fix_value_serde")]
+        to_timestamp: Timestamp,
+    },
+    Logon {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        cancel_previous_orders: bool, // cancel every order still resting from the client's earlier sessions
+        #[serde(default)]
+        heart_bt_int: u32, // HeartBtInt(108), seconds
+        #[serde(default)]
+        default_appl_ver_id: Option<String>, // DefaultApplVerID(1137)
+        #[serde(default)]
+        summary_fills: bool, // one report per order as it finishes, rather than one per fill
+    },
+    SubscribeOrderBook {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        symbols: Vec<InstrumentID>, // instruments, "*" for every one, or a prefix ending in "*" such as "FUT-*"
+        depth: u32, // top-N levels per side, 0 = full book
+        #[serde(default)]
+        subscribe_on_create: bool, // a symbol not yet listed is subscribed once it is, rather than refused
+        #[serde(default)]
+        activity: bool, // an ActivityReport on each book too, every activity interval
+        #[serde(default)]
+        last_sale: bool, // a LastSaleTape for every trade on the books covered when subscribing
+    },
+    // Ends the subscriptions the symbols cover, e.g. "*" ends all of them
+    UnsubscribeOrderBook {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        symbols: Vec<InstrumentID>,
+    },
+    // The client's session has gone, with no newer one in its place
+    SessionClosed {
+        client_id: ClientID,
+    },
+    // Surveillance alerts, such as depth imbalances, for every book
+    SubscribeAlerts {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+    },
+    UnsubscribeAlerts {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+    },
+    // Halted books take no new orders or amends; cancels still go through
+    SetTradingStatus {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        scope: InstrumentScope,
+        halted: bool,
+    },
+    // Ends the trading day for the books in scope, expiring their Day orders
+    RollSession {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        scope: InstrumentScope,
+    },
+    // Puts the books in scope into warm-up: they take orders but match none of them until
+    // simulated time reaches `open_time`, when whatever crosses trades
+    StartWarmUp {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        scope: InstrumentScope,
+        #[serde(with = "fix_value_serde")]
+        open_time: Timestamp,
+    },
     // Server -> Client responses
     OrderAccepted {
         client_id: ClientID,
         order_id: OrderID,
+        #[serde(default)]
+        client_order_id: Option<ClOrdID>,
+        instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        quantity: Quantity,
     },
     OrderRejected {
         client_id: ClientID,
         reason: String,
+        #[serde(default, with = "optional_fix_value_serde")]
+        code: Option<OrdRejReason>, // sent as OrdRejReason(103) when the rejection has one
     },
     OrderFilled {
         client_id: ClientID,
         order_id: OrderID,
+        #[serde(default)]
+        client_order_id: Option<ClOrdID>, // as the order was sent with it, so the client needn't map OrderIDs back
         filled_quantity: Quantity,
         remaining_quantity: Quantity,
+        #[serde(default)]
+        cumulative_quantity: Quantity, // of every fill of the order so far, this one included
         price: Price,
         instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        // The best bid and ask when the order arrived, for clients working out their own execution quality
+        #[serde(default)]
+        arrival_bid: Option<Price>,
+        #[serde(default)]
+        arrival_ask: Option<Price>,
+        #[serde(default)]
+        trade_match_id: u64, // the same on both sides' fills from one match
+        #[serde(default)]
+        average_price: Price, // of every fill of the order so far, this one included
+        #[serde(default)]
+        summary: bool, // the owner is sent only the fill that finishes the order, summing them all
     },
     OrderCancelled {
         client_id: ClientID,
         order_id: OrderID,
+        reason: CancelReason,
+        instrument_id: InstrumentID,
+        cancelled_price: Option<Price>, // None for a market order
+        cancelled_quantity: Quantity, // the leaves taken off the book
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        #[serde(default)]
+        cumulative_quantity: Quantity, // what filled before the cancel
+        #[serde(default)]
+        average_price: Price, // of those fills, 0 without any
+    },
+    OrderExpired {
+        client_id: ClientID,
+        order_id: OrderID,
+    },
+    CancelRejected {
+        client_id: ClientID,
+        order_id: OrderID,
+        reason: String,
+        #[serde(with = "fix_value_serde")]
+        status: OrdStatus, // what the order had already become
+        cumulative_quantity: Quantity,
+        average_price: Price, // of the fills so far
     },
     OrderAmended {
         client_id: ClientID,
         order_id: OrderID,
         new_quantity: Option<Quantity>,
         new_price: Option<Price>,
+        #[serde(with = "fix_value_serde")]
+        status: OrdStatus, // the order's status after the amendment
+        cumulative_quantity: Quantity,
+        leaves_quantity: Quantity, // 0 once amended down to what had already filled
+    },
+    AmendRejected {
+        client_id: ClientID,
+        order_id: OrderID,
+        reason: String,
+        #[serde(with = "fix_value_serde")]
+        status: OrdStatus, // the order's status, unchanged
+    },
+    // Asks after one of the sender's own orders; anyone else's is not found
+    OrderStatusRequest {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        order_id: OrderID,
+    },
+    OrderStatusReport {
+        client_id: ClientID,
+        order_id: OrderID,
+        instrument_id: Option<InstrumentID>, // None once the order is off its book
+        #[serde(with = "fix_value_serde")]
+        status: OrdStatus,
+        cumulative_quantity: Quantity,
+        leaves_quantity: Quantity,
+        average_price: Price, // of the fills so far, 0 before any
+        untriggered: bool, // a stop still waiting for a trade to reach it, New all the while
+    },
+    PositionReport {
+        client_id: ClientID,
+        account_id: AccountID,
+        cash: AccountBalance,
+        positions: Vec<(InstrumentID, Quantity, Price)>, // (instrument, quantity, average cost)
+    },
+    GreeksReport {
+        client_id: ClientID,
+        account_id: AccountID,
+        greeks: Greeks,
+    },
+    CorporateActionApplied {
+        client_id: ClientID,
+        account_id: AccountID,
+        instrument_id: InstrumentID,
+        action: CorporateAction,
+        position: Quantity, // holding after the action
+        cash_adjustment: AccountBalance, // dividend paid or cash in lieu of fractional shares
+    },
+    TradeReport {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        price: Price,
+        quantity: Quantity,
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp,
+    },
+    // A trade for the consolidated tape, sent to each of its book's last-sale subscribers as it happens
+    LastSaleTape {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        price: Price,
+        quantity: Quantity,
+        side: AggressorSide,
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp,
+        trade_id: u64, // the TrdMatchID both sides' fills carried
+    },
+    // Has the engine publish every book for readers off the matching thread; sent on a timer
+    PublishBookViews,
+    // Closes the activity interval, reporting each book's to the subscribers that asked; sent on a timer
+    PublishActivity,
+    // Asks how busy a book was over the last activity interval
+    ActivityRequest {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+    },
+    ActivityReport {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp, // when the interval closed
+        activity: ActivitySummary,
+    },
+    // Has the engine give back memory its books and order history no longer need, a bounded
+    // amount at a time; sent on a timer while the engine is quiet, or by the admin API
+    Compact {
+        #[serde(default)]
+        client_id: Option<ClientID>, // told what the pass did; None for the timer's passes
+    },
+    // Lets a client, such as a prime broker, manage accounts other clients opened, or takes
+    // that away. Privileged: only the admin API sends it, never a FIX session.
+    SetAdminFlag {
+        client_id: ClientID,
+        admin: bool,
+    },
+    // Adds an account to those the sender's CompID may trade, or takes it off them. Privileged
+    // like SetAdminFlag; only counts when the exchange was started with account authorizations.
+    SetAccountAuthorization {
+        client_id: ClientID,
+        account_id: AccountID,
+        authorized: bool,
+    },
+    // Asks for the trading state of one instrument, or of all of them, for operators watching the exchange
+    SymbolStatusRequest {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        #[serde(default)]
+        instrument_id: Option<InstrumentID>, // None = every instrument
+    },
+    SymbolStatusReport {
+        client_id: ClientID,
+        reports: Vec<InstrumentStatusEntry>, // in instrument order
+    },
+    // Answered at once with a Pong, so a client can time its link to the exchange:
+    // received_at - sent_at is the way in, exchange_time - received_at the wait to be handled
+    Ping {
+        client_id: ClientID,
+        ping_id: u64,
+        #[serde(with = "fix_value_serde")]
+        sent_at: Timestamp, // the Ping's SendingTime(52)
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        received_at: Timestamp, // when the session read it off the wire
+    },
+    Pong {
+        client_id: ClientID,
+        ping_id: u64,
+        #[serde(with = "fix_value_serde")]
+        sent_at: Timestamp,
+        #[serde(with = "fix_value_serde")]
+        received_at: Timestamp,
+        #[serde(with = "fix_value_serde")]
+        exchange_time: Timestamp, // when the engine got to it
+    },
+    // Session-level messages. The session answers these itself, and they never reach the engine.
+    LogonAccepted {
+        client_id: ClientID,
+        heart_bt_int: u32,
+        reset_seq_num: bool,
+        default_appl_ver_id: String,
+    },
+    Heartbeat {
+        client_id: ClientID,
+        test_req_id: Option<String>, // when answering a TestRequest
+    },
+    TestRequest {
+        client_id: ClientID,
+        test_req_id: String,
+    },
+    Logout {
+        client_id: ClientID,
+        text: Option<String>,
+    },
+    // Asks for every order rejection logged from `since` on, for compliance audits. Privileged
+    // like SetAdminFlag: only the admin API sends it.
+    RejectionLogQuery {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        #[serde(with = "fix_value_serde")]
+        since: Timestamp,
+    },
+    RejectionLogReport {
+        client_id: ClientID,
+        entries: Vec<RejectionEntry>, // oldest first
+        malformed: Vec<MalformedEntry>, // likewise
+    },
+    // Asks for the logged trades `query` picks out, a page at a time, for post-trade analysis.
+    // Privileged like SetAdminFlag: only the admin API sends it.
+    TradeHistoryQuery {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        query: TradeQuery,
+    },
+    TradeHistoryReport {
+        client_id: ClientID,
+        page: HistoryPage<TradeRecord, u64>, // oldest first; next is the TradeMatchID to ask after
+    },
+    // Asks for every step of one order's life so far, a page at a time, `after` being the
+    // last step of the page before. Privileged likewise.
+    OrderHistoryQuery {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        order_id: OrderID,
+        #[serde(default)]
+        after: Option<usize>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    OrderHistoryReport {
+        client_id: ClientID,
+        order_id: OrderID,
+        page: HistoryPage<OrderEvent, usize>, // in the order they happened, numbered from 0
+    },
+    // Asks for the statement of `account_id`, or of every account, for the session so far.
+    // Privileged likewise.
+    StatementRequest {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        #[serde(default)]
+        account_id: Option<AccountID>,
+    },
+    // Statements, in account order, for whoever asked, or for no one as a session closes.
+    // The outbound stage writes each out as CSV either way.
+    AccountStatements {
+        client_id: Option<ClientID>,
+        statements: Vec<AccountStatement>,
+    },
+    // A trade as it is logged, for the outbound stage to pass on to the trade webhook. Only
+    // emitted while one is configured.
+    TradeExecuted {
+        trade: TradeRecord,
+    },
+    // Attaches reference data such as a sector or currency to an instrument for downstream
+    // analytics. Matching never reads it; setting a key again replaces its value.
+    SetInstrumentMetadata {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        key: String,
+        value: String,
+    },
+    GetInstrumentMetadata {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+    },
+    InstrumentMetadataResponse {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        metadata: BTreeMap<String, String>, // in key order
+    },
+    // Holds `message` back until simulated time reaches `fire_at`, for strategies that act on
+    // a clock of their own (slicing an order over the day, cancelling at the close). It fires
+    // as if its sender had sent it then, so it must be the sender's own.
+    Schedule {
+        #[serde(with = "fix_value_serde")]
+        sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
+        receiving_time: Timestamp,
+        client_id: ClientID,
+        #[serde(with = "fix_value_serde")]
+        fire_at: Timestamp,
+        message: Box<EngineMessage>,
     },
     InvalidMessage {
         reason: String,
-        raw_message: String,
+        raw_message: RawMessage,
     },
     // Data collection & backtesting
     Snapshot {
         client_id: ClientID,
+        #[serde(with = "fix_value_serde")]
         timestamp: Timestamp,
         instrument_id: InstrumentID,
         bids: Vec<(Price, Quantity)>, // (price, quantity)
         asks: Vec<(Price, Quantity)>,
+        depth: Option<u32>, // top-N levels per side, 0 or None = full book
+        #[serde(default)]
+        granularity: BookGranularity,
+        // By order, these replace bids and asks: (order, price, quantity) in queue order
+        #[serde(default)]
+        bid_orders: Vec<(OrderID, Price, Quantity)>,
+        #[serde(default)]
+        ask_orders: Vec<(OrderID, Price, Quantity)>,
+    },
+    // An account's losses for the day have used up most of its max_daily_loss
+    RiskAlert {
+        client_id: ClientID, // the account's owner
+        account_id: AccountID,
+        reason: String,
+    },
+    // A book's resting volume became lopsided past its imbalance_alert_threshold
+    DepthImbalanceAlert {
+        client_id: ClientID, // an alert subscriber
+        instrument_id: InstrumentID,
+        ratio: f64, // total bid quantity / total ask quantity
+        direction: ImbalanceDirection,
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp,
+    },
+    // A book halted itself, its spread having widened past spread_halt_multiplier times its
+    // reference spread, or resumed once halt_duration_secs had passed
+    TradingHalt {
+        client_id: ClientID, // a market data or alert subscriber
+        instrument_id: InstrumentID,
+        halted: bool,
+        reason: String,
+        #[serde(default, with = "optional_fix_value_serde")]
+        resumes_at: Option<Timestamp>, // set on the halt
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp,
+    },
+    BookUpdate {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        changes: Vec<BookChange>, // removals first, then additions and updates
+    },
+    // Market quality after a book's latest fills, sent to its market-data subscribers
+    LiquidityReport {
+        client_id: ClientID,
+        instrument_id: InstrumentID,
+        score: LiquidityScore,
     },
     AdvanceTime {
+        #[serde(with = "fix_value_serde")]
         sending_time: Timestamp,
+        #[serde(with = "fix_value_serde", default = "timestamp_now")]
         receiving_time: Timestamp,
         client_id: ClientID,
+        #[serde(with = "fix_value_serde")]
         timestamp: Timestamp,
     },
     LogEvent {
         client_id: Option<ClientID>,
         message: String,
     },
-}
\ No newline at end of file
+}
+// Where a book is in its trading day. One warming up takes orders but matches none of them
+// until simulated time reaches `opens_at`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum InstrumentPhase {
+    WarmUp {
+        #[serde(with = "fix_value_serde")]
+        opens_at: Timestamp,
+    },
+    Open,
+}
+
+// One instrument's trading state as a status report gives it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstrumentStatusEntry {
+    pub instrument_id: InstrumentID,
+    pub phase: InstrumentPhase,
+    pub circuit_breaker: bool, // trading is halted
+    pub luld_band: Option<(Price, Price)>, // (lower, upper) limit-up/limit-down band; the exchange sets none yet
+    pub last_price: Option<Price>, // of the latest trade, None before the first
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+}
+
+// An OrderRejected as the rejection log keeps it, with what the rejected request asked for.
+// Requests naming no account are logged with an empty one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RejectionEntry {
+    #[serde(with = "fix_value_serde")]
+    pub timestamp: Timestamp,
+    pub client_id: ClientID,
+    pub account_id: AccountID,
+    pub instrument_id: Option<InstrumentID>,
+    pub reason: String,
+    pub original_price: Option<Price>,
+    pub original_qty: Option<Quantity>,
+}
+
+// An inbound message that could not be read, as the malformed log keeps it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MalformedEntry {
+    #[serde(with = "fix_value_serde")]
+    pub timestamp: Timestamp,
+    pub reason: String,
+    pub raw_message: RawMessage,
+}
+
+// How much of the book a snapshot shows: one entry per price level, or one per resting
+// order with its id and size but not who sent it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookGranularity {
+    #[default]
+    ByPrice,
+    ByOrder,
+}
+
+// One level's change between two views of a book, as seen by a depth-limited subscriber
+#[allow(clippy::enum_variant_names)] // named for what happens to the level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BookChange {
+    AddLevel {
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    },
+    UpdateLevel {
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        price: Price,
+        quantity: Quantity, // the level's new aggregate quantity
+    },
+    RemoveLevel {
+        #[serde(with = "fix_value_serde")]
+        side: Side,
+        price: Price,
+    },
+}
+
+// How easily a book can be traded, for best-execution analysis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LiquidityScore {
+    pub spread: f64, // best ask - best bid, 0 with either side empty
+    pub depth_bid: Quantity, // resting on the best 5 levels
+    pub depth_ask: Quantity,
+    pub fill_rate: f64, // fills per second of simulated time since the book's first fill
+    pub impact_cost_estimate: f64, // half the cost of buying then selling the last fill's quantity at once
+}
+
+// How busy a book was over one activity interval, with its levels as the interval closed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ActivitySummary {
+    pub trades_per_second: f64,
+    pub messages_per_second: f64, // orders, cancels and amends for the book
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub max_queue_delay_ms: u64, // the longest any of those messages waited between its session and the engine
+}
+
+// Which side of a book outweighs the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImbalanceDirection {
+    BidHeavy,
+    AskHeavy,
+}
+
+// The books an admin operation acts on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstrumentScope {
+    Instrument(InstrumentID),
+    Segment(SegmentName), // every instrument created in the segment
+    All,
+}
+
+// What happens when an account's incoming order would trade against one of its own resting orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmpAction {
+    // Let the account trade with itself
+    #[default]
+    Allow,
+    // Refuse the incoming order before it trades
+    RejectAggressor,
+}
+
+// The side whose order arrived to take liquidity in a trade; an auction's trades have neither
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AggressorSide {
+    Buy,
+    Sell,
+    Neither,
+}
+
+// Hard limits checked against each order an account sends, whatever its cash balance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    #[serde(default)]
+    pub max_single_order_value: f64, // price * quantity, 0 = unlimited
+    #[serde(default)]
+    pub max_daily_loss: Option<f64>, // realized loss a day may run to before new orders stop
+}
+
+// One entry of an order's NoStipulations(232) group, e.g. a minimum lot size on a bond order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stipulation {
+    pub stipulation_type: String, // StipulationType(233)
+    pub value: String, // StipulationValue(234)
+}
+
+// One entry of a multileg order's NoLegs(555) group, traded as an order of its own
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultilegLeg {
+    pub instrument_id: InstrumentID, // LegSymbol(600)
+    #[serde(with = "fix_value_serde")]
+    pub side: Side, // LegSide(624)
+    pub quantity: Quantity, // LegQty(687)
+    pub price: Option<Price>, // LegPrice(566), None for a market leg
+}
+
+// Why an order left the book without filling. Everything but ClientRequested is unsolicited.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    ClientRequested,
+    Expired,
+    Disconnect,
+    SelfTradePrevention, // a triggered stop that would have traded with its own account
+    TradingHalt,
+    ExecutionLimit, // the leaves of an order that traded max_executions_per_order times
+    Other(String),
+}
+
+impl std::fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelReason::ClientRequested => write!(f, "Cancelled at client request"),
+            CancelReason::Expired => write!(f, "Expired"),
+            CancelReason::Disconnect => write!(f, "Cancelled on disconnect"),
+            CancelReason::SelfTradePrevention => write!(f, "Cancelled to prevent a self-trade"),
+            CancelReason::TradingHalt => write!(f, "Cancelled for a trading halt"),
+            CancelReason::ExecutionLimit => write!(f, "Cancelled at the per-order execution limit"),
+            CancelReason::Other(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// When the exchange took in the message an event answers, and when it had matched it
+#[derive(Debug, Clone, Copy)]
+pub struct ReportTimes {
+    pub received: ExchangeTime,
+    pub matched: ExchangeTime,
+}
+
+// The events the engine answered a batch of messages with, each with the times of what it answered
+#[derive(Debug, Default)]
+pub struct OutboundBatch {
+    pub events: Vec<EngineMessage>,
+    pub times: Vec<ReportTimes>, // one per event
+}
+
+impl OutboundBatch {
+    pub fn extend(&mut self, events: Vec<EngineMessage>, times: ReportTimes) {
+        self.times.extend(std::iter::repeat_n(times, events.len()));
+        self.events.extend(events);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&EngineMessage, &ReportTimes)> {
+        self.events.iter().zip(&self.times)
+    }
+}
+
+// A reference to a message's ClientID field, shared or mutable as the message is
+macro_rules! client_id_of {
+    ($message:expr) => {
+        match $message {
+            EngineMessage::NewOrder { client_id, .. }
+            | EngineMessage::NewOrderMultileg { client_id, .. }
+            | EngineMessage::CancelOrder { client_id, .. }
+            | EngineMessage::CreateInstrument { client_id, .. }
+            | EngineMessage::UpdateInstrumentSpec { client_id, .. }
+            | EngineMessage::AmendOrder { client_id, .. }
+            | EngineMessage::PositionQuery { client_id, .. }
+            | EngineMessage::GreeksRequest { client_id, .. }
+            | EngineMessage::InboundExecutionReport { client_id, .. }
+            | EngineMessage::CorporateAction { client_id, .. }
+            | EngineMessage::SetRestingOrderLimit { client_id, .. }
+            | EngineMessage::SetSmpAction { client_id, .. }
+            | EngineMessage::SetRiskLimits { client_id, .. }
+            | EngineMessage::SetAdminFlag { client_id, .. }
+            | EngineMessage::SetAccountAuthorization { client_id, .. }
+            | EngineMessage::RequestReplay { client_id, .. }
+            | EngineMessage::Logon { client_id, .. }
+            | EngineMessage::SubscribeOrderBook { client_id, .. }
+            | EngineMessage::UnsubscribeOrderBook { client_id, .. }
+            | EngineMessage::SessionClosed { client_id }
+            | EngineMessage::OrderAccepted { client_id, .. }
+            | EngineMessage::OrderRejected { client_id, .. }
+            | EngineMessage::OrderFilled { client_id, .. }
+            | EngineMessage::OrderCancelled { client_id, .. }
+            | EngineMessage::OrderExpired { client_id, .. }
+            | EngineMessage::CancelRejected { client_id, .. }
+            | EngineMessage::OrderStatusRequest { client_id, .. }
+            | EngineMessage::OrderStatusReport { client_id, .. }
+            | EngineMessage::OrderAmended { client_id, .. }
+            | EngineMessage::AmendRejected { client_id, .. }
+            | EngineMessage::PositionReport { client_id, .. }
+            | EngineMessage::GreeksReport { client_id, .. }
+            | EngineMessage::CorporateActionApplied { client_id, .. }
+            | EngineMessage::TradeReport { client_id, .. }
+            | EngineMessage::LastSaleTape { client_id, .. }
+            | EngineMessage::Snapshot { client_id, .. }
+            | EngineMessage::SubscribeAlerts { client_id, .. }
+            | EngineMessage::UnsubscribeAlerts { client_id, .. }
+            | EngineMessage::DepthImbalanceAlert { client_id, .. }
+            | EngineMessage::TradingHalt { client_id, .. }
+            | EngineMessage::RiskAlert { client_id, .. }
+            | EngineMessage::LiquidityReport { client_id, .. }
+            | EngineMessage::ActivityRequest { client_id, .. }
+            | EngineMessage::ActivityReport { client_id, .. }
+            | EngineMessage::SetTradingStatus { client_id, .. }
+            | EngineMessage::RollSession { client_id, .. }
+            | EngineMessage::StartWarmUp { client_id, .. }
+            | EngineMessage::BookUpdate { client_id, .. }
+            | EngineMessage::AdvanceTime { client_id, .. }
+            | EngineMessage::Schedule { client_id, .. }
+            | EngineMessage::SymbolStatusRequest { client_id, .. }
+            | EngineMessage::Ping { client_id, .. }
+            | EngineMessage::Pong { client_id, .. }
+            | EngineMessage::LogonAccepted { client_id, .. }
+            | EngineMessage::Heartbeat { client_id, .. }
+            | EngineMessage::TestRequest { client_id, .. }
+            | EngineMessage::Logout { client_id, .. }
+            | EngineMessage::SymbolStatusReport { client_id, .. }
+            | EngineMessage::RejectionLogQuery { client_id, .. }
+            | EngineMessage::RejectionLogReport { client_id, .. }
+            | EngineMessage::TradeHistoryQuery { client_id, .. }
+            | EngineMessage::TradeHistoryReport { client_id, .. }
+            | EngineMessage::OrderHistoryQuery { client_id, .. }
+            | EngineMessage::OrderHistoryReport { client_id, .. }
+            | EngineMessage::StatementRequest { client_id, .. }
+            | EngineMessage::SetInstrumentMetadata { client_id, .. }
+            | EngineMessage::GetInstrumentMetadata { client_id, .. }
+            | EngineMessage::InstrumentMetadataResponse { client_id, .. } => Some(client_id),
+            EngineMessage::LogEvent { client_id, .. } | EngineMessage::AccountStatements { client_id, .. } | EngineMessage::Compact { client_id } => client_id.into(),
+            EngineMessage::InvalidMessage { .. } | EngineMessage::PublishBookViews | EngineMessage::PublishActivity | EngineMessage::TradeExecuted { .. } => None,
+        }
+    };
+}
+
+// The session an engine message is addressed to or originated from, if any
+pub fn extract_client_id(message: &EngineMessage) -> Option<ClientID> {
+    client_id_of!(message).cloned()
+}
+
+// The same, to re-address a message in place
+pub fn client_id_mut(message: &mut EngineMessage) -> Option<&mut ClientID> {
+    client_id_of!(message)
+}
+
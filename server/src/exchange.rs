@@ -1,27 +1,80 @@
-use std::collections::{BTreeMap, VecDeque, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::cmp::{Ordering, PartialEq};
+use std::path::Path;
+use std::sync::Arc;
 
 use fefix::definitions::fix50::*;
-use fefix::fix_values::Timestamp;
+use fefix::fix_values::{Date, Time, Timestamp};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::engine::EngineMessage;
+use crate::accounts::AccountAuthorizations;
+use crate::audit::{paginate, HistoryPage, OrderAuditEntry, OrderEvent, MalformedLog, RecentOrders, TradeParty, TradeQuery, TradeRecord, TRADE_LOG_RETENTION};
+use crate::book_views::{BookViews, PublishedBook};
+use crate::clock::{self, exchange_now, timestamp_now};
+use crate::framing::RawMessage;
+use crate::config::LiveConfig;
+use crate::compaction::{shrink_map, shrink_queue, CompactionMetrics, CompactionPass, CompactionStats, COMPACTION_BUDGET, FINISHED_ORDER_RETENTION};
+use crate::engine::{extract_client_id, ActivitySummary, AggressorSide, BookChange, BookGranularity, CancelReason, EngineMessage, ImbalanceDirection, InstrumentPhase, InstrumentScope, InstrumentStatusEntry, LiquidityScore, MultilegLeg, RejectionEntry, RiskLimits, SmpAction};
+use crate::execution_quality::{ArrivalTouch, ExecutionStatistics};
+use crate::greeks::portfolio_greeks;
+use crate::instrument::{CorporateAction, HaltPolicy, InstrumentHandle, InstrumentSpec, MarketSegments, PriceLevelPolicy, SpreadDefinition, TouchWindowPolicy, UncrossPolicy};
+use crate::order_state::is_valid_transition;
+use crate::overload::{OverloadGuard, SYSTEM_OVERLOADED};
+use crate::price_levels::{Levels, PriceLadder, PriceLevels};
+use crate::router::TickerMap;
+use crate::statements::{AccountHistory, AccountStatement, EntryKind, MarkedPosition, StatementEntry};
+use crate::surveillance::SurveillanceEvent;
 use crate::types::*;
 
-#[derive(Clone, Debug)]
-struct Order {
+// Trades one order may make on arriving before the rest of it is cancelled, unless the server
+// config says otherwise: far past any real sweep, short of one that would stall the engine
+pub const MAX_EXECUTIONS_PER_ORDER: usize = 10_000;
+
+// Aggregated (price, quantity) of one price level
+type Level = (Price, Quantity);
+// (bids, asks), best first
+type BookView = (Vec<Level>, Vec<Level>);
+// (order, price, quantity) of one resting order
+type DepthOrder = (OrderID, Price, Quantity);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Order {
     order_id: OrderID,
     client_order_id: ClOrdID,
-    price: Price,
+    price: Option<Price>, // None for a market order, which never rests
     quantity: Quantity,
+    #[serde(with = "fix_value_serde")]
     send_timestamp: Timestamp,
+    #[serde(with = "fix_value_serde")]
     receive_timestamp: Timestamp,
+    #[serde(with = "fix_value_serde")]
     side: Side,
+    #[serde(with = "fix_value_serde")]
     order_type: OrdType,
+    #[serde(with = "fix_value_serde")]
     time_in_force: TimeInForce,
+    #[serde(default, with = "optional_fix_value_serde")]
+    expire_time: Option<Timestamp>,
+    #[serde(with = "fix_value_serde")]
     exec_instruction: ExecInst,
     instrument_id: InstrumentID,
     account_id: AccountID,
     sender_id: ClientID,
+    #[serde(default)]
+    good_for_auction: bool, // trades only when the book uncrosses, resting off it otherwise
+}
+
+impl Order {
+    // The ClOrdID the client sent the order with, if it sent one
+    fn sent_client_order_id(&self) -> Option<ClOrdID> {
+        Some(self.client_order_id.clone()).filter(|id| !id.is_empty())
+    }
+
+    // The level the order rests on. Only priced orders are ever let onto the book.
+    fn level(&self) -> Price {
+        self.price.expect("resting orders have a price")
+    }
 }
 
 impl PartialEq for Order {
@@ -44,136 +97,902 @@ impl PartialOrd for Order {
     }
 }
 
+// A trade matched on a book, until the exchange logs it
+#[derive(Clone, Debug)]
+struct Execution {
+    trade_match_id: u64,
+    price: Price,
+    quantity: Quantity,
+    buyer: TradeParty,
+    seller: TradeParty,
+    aggressor: AggressorSide,
+}
+
+fn trade_party(order: &Order) -> TradeParty {
+    TradeParty {
+        order_id: order.order_id,
+        account_id: order.account_id.clone(),
+        client_id: order.sender_id.clone(),
+    }
+}
+
+// What a book has seen since the current activity interval began
+#[derive(Clone, Debug, Default)]
+struct ActivityWindow {
+    trades: u64,
+    messages: u64, // orders, cancels and amends
+    max_queue_delay_ms: u64,
+}
+
 #[derive(Clone, Debug)]
 struct OrderBook {
-    bids: BTreeMap<Price, VecDeque<Order>>, // descending order if needed
-    asks: BTreeMap<Price, VecDeque<Order>>, // ascending order
+    bids: Levels<Order>, // best last
+    asks: Levels<Order>, // best first
     order_index: HashMap<OrderID, Order>,
+    spec: InstrumentHandle,
+    segment: Option<SegmentName>, // the segment the instrument was created in
+    halted: bool, // takes no new orders, and amends rest without trading
+    phase: InstrumentPhase,
+    imbalance: Option<ImbalanceDirection>, // last imbalance alerted, until the book evens out again
+    published_views: HashMap<u32, BookView>, // last view sent to subscribers, by depth
+    executions: Vec<Execution>, // trades matched since the exchange last logged them
+    liquidity: LiquidityScore, // as of the latest fills
+    liquidity_unreported: bool, // the score changed since it was last sent to subscribers
+    fill_count: u64,
+    first_fill_at: Option<Timestamp>,
+    last_price: Option<Price>, // of the latest trade
+    activity: ActivityWindow, // since the current activity interval began
+    last_activity: Option<ActivitySummary>, // over the last activity interval to close
+    stops: Vec<Order>, // stop orders waiting for a trade to reach them, indexed but off the book, oldest first
+    auction_orders: Vec<Order>, // good-for-auction orders, indexed but off the book while it trades continuously, oldest first
+    spread: Option<SpreadDefinition>, // the legs its fills are booked into, for a spread
+    max_executions: usize, // trades an order may make before its leaves are cancelled
+    reference_spread: f64, // moving average of the spreads the book has shown, 0 until it has shown one
+    last_spread: Option<f64>, // the spread last taken into reference_spread
+    volatility_halt_ends: Option<Timestamp>, // set while the book is halted for its spread
 }
 
 
 impl OrderBook {
-    fn match_order(&mut self, mut order: Order, accounts: &mut HashMap<AccountID, Bankroll>) -> Vec<EngineMessage> {
-        let mut fills = Vec::new();
-        // Handle Stop orders
-        if let OrdType::Stop = order.order_type {
-            match order.side {
-                Side::Buy => {
-                    if let Some((&best_ask_price, _)) = self.asks.iter().next() {
-                        if best_ask_price < order.price {
-                            // Not triggered yet, buffer order
-                            return fills;
-                        }
-                    } else {
-                        // No market price, cannot trigger
-                        return fills;
-                    }
-                    // Triggered, convert to Market order for matching
-                    order.order_type = OrdType::Market;
+    fn new(spec: InstrumentSpec) -> Self {
+        Self {
+            bids: Levels::default(),
+            asks: Levels::default(),
+            order_index: HashMap::new(),
+            segment: None,
+            halted: false,
+            phase: InstrumentPhase::Open,
+            imbalance: None,
+            published_views: HashMap::new(),
+            executions: Vec::new(),
+            liquidity: LiquidityScore::default(),
+            liquidity_unreported: false,
+            fill_count: 0,
+            first_fill_at: None,
+            last_price: None,
+            activity: ActivityWindow::default(),
+            last_activity: None,
+            stops: Vec::new(),
+            auction_orders: Vec::new(),
+            spread: None,
+            max_executions: MAX_EXECUTIONS_PER_ORDER,
+            reference_spread: spec.reference_spread,
+            last_spread: None,
+            volatility_halt_ends: None,
+            spec: InstrumentHandle::new(spec),
+        }
+    }
+
+    // Drops any empty levels and shrinks the queues and index left mostly spare. Returns the
+    // work it did, a unit per level.
+    fn compact(&mut self, reclaimed: &mut CompactionMetrics) -> usize {
+        let mut work = 1;
+        for levels in [&mut self.bids, &mut self.asks] {
+            work += levels.len();
+            let before = levels.len();
+            levels.retain(|_, queue| !queue.is_empty());
+            let dropped = before - levels.len();
+            reclaimed.empty_levels_dropped += dropped as u64;
+            reclaimed.bytes_reclaimed += (dropped * std::mem::size_of::<(Price, VecDeque<Order>)>()) as u64;
+            for queue in levels.values_mut() {
+                reclaimed.shrunk(shrink_queue(queue));
+            }
+        }
+        reclaimed.shrunk(shrink_map(&mut self.order_index));
+        reclaimed.shrunk(shrink_map(&mut self.published_views));
+        work
+    }
+
+    // The bid/ask ratio of resting quantity and the heavy side, if it is past the alert threshold.
+    // A book with an empty side has no ratio to speak of.
+    fn depth_imbalance(&self) -> Option<(f64, ImbalanceDirection)> {
+        let threshold = self.spec.read().imbalance_alert_threshold;
+        let total = |levels: &Levels<Order>| levels.values().flatten().map(|order| order.quantity).sum::<Quantity>();
+        let (bid_quantity, ask_quantity) = (total(&self.bids), total(&self.asks));
+        if threshold <= 0.0 || bid_quantity == 0 || ask_quantity == 0 {
+            return None;
+        }
+        let ratio = bid_quantity as f64 / ask_quantity as f64;
+        if ratio > threshold {
+            Some((ratio, ImbalanceDirection::BidHeavy))
+        } else if ratio < 1.0 / threshold {
+            Some((ratio, ImbalanceDirection::AskHeavy))
+        } else {
+            None
+        }
+    }
+
+    fn status(&self, instrument_id: InstrumentID) -> InstrumentStatusEntry {
+        InstrumentStatusEntry {
+            instrument_id,
+            phase: self.phase.clone(),
+            circuit_breaker: self.halted,
+            luld_band: None,
+            last_price: self.last_price,
+            bid_levels: self.bids.len(),
+            ask_levels: self.asks.len(),
+        }
+    }
+
+    // Counts a message for the book against the current activity interval, with how long it
+    // queued between its session and the engine
+    fn count_message(&mut self, receiving_time: &Timestamp) {
+        self.activity.messages += 1;
+        if let Some(received) = receiving_time.to_chrono_utc() {
+            let waited = (exchange_now() - received).num_milliseconds().max(0) as u64;
+            self.activity.max_queue_delay_ms = self.activity.max_queue_delay_ms.max(waited);
+        }
+    }
+
+    // Closes the book's activity interval, `seconds` long, and starts the next
+    fn close_activity_interval(&mut self, seconds: f64) -> ActivitySummary {
+        let window = std::mem::take(&mut self.activity);
+        let per_second = |count: u64| if seconds > 0.0 { count as f64 / seconds } else { 0.0 };
+        let (bid_levels, ask_levels) = self.price_levels();
+        let activity = ActivitySummary {
+            trades_per_second: per_second(window.trades),
+            messages_per_second: per_second(window.messages),
+            bid_levels,
+            ask_levels,
+            max_queue_delay_ms: window.max_queue_delay_ms,
+        };
+        self.last_activity = Some(activity);
+        activity
+    }
+
+    // Moves both sides onto ladders from low to high on the book's tick. A side holding a level
+    // the ladder cannot stays on its tree, as it would once it left the range.
+    fn use_price_ladder(&mut self, range: &str) -> Result<(), &'static str> {
+        let (low, high) = range
+            .split_once("..")
+            .and_then(|(low, high)| Some((low.trim().parse::<f64>().ok()?, high.trim().parse::<f64>().ok()?)))
+            .ok_or("Invalid price ladder range")?;
+        let tick = self.spec.read().tick_size;
+        let ladder = || PriceLadder::new(Price::from(low), Price::from(high), tick);
+        self.bids = std::mem::take(&mut self.bids).onto(ladder()?);
+        self.asks = std::mem::take(&mut self.asks).onto(ladder()?);
+        Ok(())
+    }
+
+    // Prices with orders resting at them, (bid, ask); a level emptied stays until compaction
+    fn price_levels(&self) -> (usize, usize) {
+        let levels = |side: &Levels<Order>| side.values().filter(|queue| !queue.is_empty()).count();
+        (levels(&self.bids), levels(&self.asks))
+    }
+
+    // Rescores the book after `fills` more trades, the last of them for `last_quantity`, at `now`
+    fn update_liquidity(&mut self, fills: usize, last_quantity: Quantity, now: &Timestamp) {
+        self.fill_count += fills as u64;
+        let first_fill_at = self.first_fill_at.get_or_insert_with(|| now.clone());
+        let elapsed_seconds = (timestamp_millis(now) - timestamp_millis(first_fill_at)) as f64 / 1000.0;
+        let (bids, asks) = self.depth_levels(LIQUIDITY_DEPTH_LEVELS);
+        let depth = |levels: &[Level]| levels.iter().map(|(_, quantity)| quantity).sum();
+        let spread = match (self.bids.keys().next_back(), self.asks.keys().next()) {
+            (Some(bid), Some(ask)) => (ask - bid).into_inner(),
+            _ => 0.0,
+        };
+        let impact_cost_estimate = match (self.sweep_price(&self.asks, Side::Buy, last_quantity), self.sweep_price(&self.bids, Side::Sell, last_quantity)) {
+            (Some(buy), Some(sell)) => (buy - sell) / 2.0,
+            _ => 0.0,
+        };
+        self.liquidity = LiquidityScore {
+            spread,
+            depth_bid: depth(&bids),
+            depth_ask: depth(&asks),
+            fill_rate: if elapsed_seconds > 0.0 { self.fill_count as f64 / elapsed_seconds } else { 0.0 },
+            impact_cost_estimate,
+        };
+        self.liquidity_unreported = true;
+    }
+
+    // The spread at the touch, None with either side empty
+    fn touch_spread(&self) -> Option<f64> {
+        match (self.bids.keys().next_back(), self.asks.keys().next()) {
+            (Some(bid), Some(ask)) => Some((ask - bid).into_inner()),
+            _ => None,
+        }
+    }
+
+    // The spread, if it has widened past spread_halt_multiplier times the reference spread.
+    // Otherwise a spread new since the last is taken into the reference, so the reference
+    // follows the book's own spreads. Only an open book is measured.
+    fn spread_blowout(&mut self) -> Option<f64> {
+        let spread = self.touch_spread().filter(|_| self.is_open())?;
+        let multiplier = self.spec.read().spread_halt_multiplier;
+        if multiplier > 0.0 && self.reference_spread > 0.0 && spread > self.reference_spread * multiplier {
+            return Some(spread);
+        }
+        if self.last_spread != Some(spread) {
+            self.last_spread = Some(spread);
+            let reference = self.reference_spread;
+            self.reference_spread = if reference > 0.0 { reference + REFERENCE_SPREAD_WEIGHT * (spread - reference) } else { spread };
+        }
+        None
+    }
+
+    // The halt has given the market time to find its spread, so the book measures from the
+    // first one it reopens with rather than halting again at once
+    fn end_volatility_halt(&mut self) {
+        self.volatility_halt_ends = None;
+        self.reference_spread = 0.0;
+        self.last_spread = None;
+    }
+
+    // The average price an order for `quantity` would pay walking `levels` from the best,
+    // as far as they go; None if they are empty
+    fn sweep_price(&self, levels: &Levels<Order>, side: Side, quantity: Quantity) -> Option<f64> {
+        let best_first: Box<dyn Iterator<Item = (&Price, &VecDeque<Order>)>> = match side {
+            Side::Buy => Box::new(levels.iter()),
+            _ => Box::new(levels.iter().rev()),
+        };
+        let (mut remaining, mut filled, mut notional) = (quantity, 0, 0.0);
+        for (price, queue) in best_first {
+            let taken = remaining.min(queue.iter().map(|order| order.quantity).sum());
+            filled += taken;
+            notional += price.into_inner() * taken as f64;
+            remaining -= taken;
+            if remaining == 0 {
+                break;
+            }
+        }
+        (filled > 0).then(|| notional / filled as f64)
+    }
+
+    // How much of `quantity` an order at `price`, or at market, would fill against the opposite
+    // side now, trading with at most `max_orders` of the orders there
+    fn fillable(&self, side: Side, price: Option<Price>, quantity: Quantity, max_orders: usize) -> Quantity {
+        let reachable = |level: &Price| price.is_none_or(|limit| if side == Side::Buy { *level <= limit } else { *level >= limit });
+        let best_first: Box<dyn Iterator<Item = (&Price, &VecDeque<Order>)>> = match side {
+            Side::Buy => Box::new(self.asks.iter()),
+            _ => Box::new(self.bids.iter().rev()),
+        };
+        let available: Quantity = best_first
+            .take_while(|(level, _)| reachable(level))
+            .flat_map(|(_, queue)| queue.iter().map(|order| order.quantity))
+            .take(max_orders)
+            .sum();
+        available.min(quantity)
+    }
+
+    // True if an order at `price` would trade against the opposite side right away
+    fn crosses(&self, side: Side, price: Price) -> bool {
+        match side {
+            Side::Buy => self.asks.keys().next().is_some_and(|&ask| price >= ask),
+            Side::Sell => self.bids.keys().next_back().is_some_and(|&bid| price <= bid),
+            _ => false,
+        }
+    }
+
+    // True if the best bid is at or above the best ask
+    fn is_crossed(&self) -> bool {
+        self.bids.keys().next_back().is_some_and(|&bid| self.crosses(Side::Buy, bid))
+    }
+
+    // True if an order would trade on arrival; market orders trade against any opposite liquidity
+    fn is_marketable(&self, side: Side, price: Option<Price>) -> bool {
+        match (price, side) {
+            (Some(price), _) => self.crosses(side, price),
+            (None, Side::Buy) => !self.asks.is_empty(),
+            (None, Side::Sell) => !self.bids.is_empty(),
+            _ => false,
+        }
+    }
+
+    // Whether a stop arriving now triggers at once, the touch it would take from having reached it
+    fn stop_triggers(&self, side: Side, stop_price: Option<Price>) -> bool {
+        let touch = match side {
+            Side::Buy => self.asks.keys().next(),
+            _ => self.bids.keys().next_back(),
+        };
+        touch.is_some_and(|&touch| stop_reached(side, stop_price, touch))
+    }
+
+    // True if the opposite level the order would trade against first holds an order from its own account
+    fn crosses_own_order(&self, order: &Order) -> bool {
+        if !self.is_marketable(order.side, order.price) {
+            return false;
+        }
+        let top_level = match order.side {
+            Side::Buy => self.asks.values().next(),
+            Side::Sell => self.bids.values().next_back(),
+            _ => None,
+        };
+        top_level.is_some_and(|queue| queue.iter().any(|resting| resting.account_id == order.account_id))
+    }
+
+    // The same for an order entered now, as it would trade: a stop the touch hasn't reached
+    // only parks, and one it has trades as what it triggers into
+    fn entry_crosses_own_order(&self, order: &Order) -> bool {
+        if !is_stop(order) {
+            return self.crosses_own_order(order);
+        }
+        if self.warming_up() || !self.stop_triggers(order.side, order.price) {
+            return false;
+        }
+        let mut triggered = order.clone();
+        trigger_stop(&mut triggered, &mut HashMap::new());
+        self.crosses_own_order(&triggered)
+    }
+
+    // Whether resting at `price` fits within `max_price_levels`, possibly by evicting the worst level
+    fn admits_level(&self, side: Side, price: Price) -> bool {
+        if !self.level_limit_reached(side, price) {
+            return true;
+        }
+        if self.spec.read().price_level_policy == PriceLevelPolicy::Reject {
+            return false;
+        }
+        // Evicting only makes sense if the new level would not itself be the worst one
+        match side {
+            Side::Buy => self.bids.keys().next().is_some_and(|&worst| price > worst),
+            Side::Sell => self.asks.keys().next_back().is_some_and(|&worst| price < worst),
+            _ => false,
+        }
+    }
+
+    fn level_limit_reached(&self, side: Side, price: Price) -> bool {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+            _ => return false,
+        };
+        self.spec.read().max_price_levels != 0
+            && !levels.contains_key(&price)
+            && levels.len() >= self.spec.read().max_price_levels
+    }
+
+    // Where the window of prices new levels may open at ends on `side`, None if it has no end
+    fn window_edge(&self, side: Side) -> Option<Price> {
+        let distance = self.spec.read().max_touch_distance;
+        if distance <= Price::from(0.0) {
+            return None;
+        }
+        match side {
+            Side::Buy => self.bids.keys().next_back().or(self.asks.keys().next()).map(|&touch| touch - distance),
+            Side::Sell => self.asks.keys().next().or(self.bids.keys().next_back()).map(|&touch| touch + distance),
+            _ => None,
+        }
+    }
+
+    // Whether `price` lies past `edge` on `side`, allowing for prices such as 9.7 not being
+    // exactly 10 - 0.3 in binary
+    fn beyond(side: Side, price: Price, edge: Price) -> bool {
+        match side {
+            Side::Buy => price.into_inner() < edge.into_inner() - 1e-9,
+            _ => price.into_inner() > edge.into_inner() + 1e-9,
+        }
+    }
+
+    // Whether resting at `price` opens no level outside the touch window; levels already there
+    // may still be joined
+    fn within_window(&self, side: Side, price: Price) -> bool {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            _ => &self.asks,
+        };
+        levels.contains_key(&price) || self.window_edge(side).is_none_or(|edge| !Self::beyond(side, price, edge))
+    }
+
+    // Once a better price has moved the touch, cancels the levels it left outside the window,
+    // unless the instrument grandfathers them
+    fn cancel_outside_window(&mut self, side: Side, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+        if self.spec.read().touch_window_policy != TouchWindowPolicy::Cancel {
+            return;
+        }
+        let Some(edge) = self.window_edge(side) else { return };
+        let outside: Vec<Price> = match side {
+            Side::Buy => self.bids.keys().take_while(|&&price| Self::beyond(side, price, edge)).copied().collect(),
+            _ => self.asks.keys().rev().take_while(|&&price| Self::beyond(side, price, edge)).copied().collect(),
+        };
+        for price in outside {
+            self.evict_level(side, price, "Price level outside the touch window", accounts, events);
+        }
+    }
+
+    fn rest_order(&mut self, order: Order, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+        // A market order has no price to wait at, so whatever it leaves is cancelled
+        let Some(price) = order.price else {
+            cancel_remainder(&order, accounts, events);
+            return;
+        };
+        // Matching trades an order through all it crosses first, so resting one that still
+        // crosses means something upstream went wrong; it is refused rather than left to cross
+        // the book. Only warm-up and halts cross it on purpose.
+        if self.is_open() && self.crosses(order.side, price) {
+            refund_order(&order, accounts);
+            events.push(EngineMessage::InvalidMessage {
+                reason: "Order would cross the book".to_string(),
+                raw_message: RawMessage::text(&format!("Order {} to {:?} {} {} at {}", order.order_id, order.side, order.quantity, order.instrument_id, price)),
+            });
+            events.push(order_cancelled(order.sender_id.clone(), &order, CancelReason::Other("Order would cross the book".to_string())));
+            return;
+        }
+        if !self.admits_level(order.side, price) {
+            // No room for a new level: drop the remainder as if it had been cancelled
+            refund_order(&order, accounts);
+            events.push(order_cancelled(order.sender_id.clone(), &order, CancelReason::Other("Price level limit reached".to_string())));
+            return;
+        }
+        if !self.within_window(order.side, price) {
+            refund_order(&order, accounts);
+            events.push(order_cancelled(order.sender_id.clone(), &order, CancelReason::Other("Price too far from the touch".to_string())));
+            return;
+        }
+        if self.level_limit_reached(order.side, price) {
+            let worst = match order.side {
+                Side::Buy => self.bids.keys().next().cloned(),
+                _ => self.asks.keys().next_back().cloned(),
+            };
+            if let Some(worst) = worst {
+                self.evict_level(order.side, worst, "Price level evicted", accounts, events);
+            }
+        }
+        let side = order.side;
+        self.place(order);
+        self.cancel_outside_window(side, accounts, events);
+    }
+
+    // Puts a priced order at the back of its level's queue, with no checks at all
+    fn place(&mut self, order: Order) {
+        let levels = match order.side {
+            Side::Buy => &mut self.bids,
+            _ => &mut self.asks,
+        };
+        levels.level_mut(order.level()).push_back(order.clone());
+        self.order_index.insert(order.order_id, order);
+    }
+
+    fn evict_level(&mut self, side: Side, price: Price, reason: &str, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+        let queue = match side {
+            Side::Buy => self.bids.remove(&price),
+            Side::Sell => self.asks.remove(&price),
+            _ => None,
+        };
+        for order in queue.into_iter().flatten() {
+            self.order_index.remove(&order.order_id);
+            refund_order(&order, accounts);
+            events.push(order_cancelled(order.sender_id.clone(), &order, CancelReason::Other(reason.to_string())));
+        }
+    }
+
+    pub fn contains_order(&self, order_id: OrderID) -> bool {
+        self.order_index.contains_key(&order_id)
+    }
+
+    // Removes every resting order matching `selected` in OrderID order, refunding each,
+    // and returns the orders removed
+    fn remove_orders(&mut self, selected: impl Fn(&Order) -> bool, accounts: &mut HashMap<AccountID, Bankroll>) -> Vec<Order> {
+        let mut order_ids: Vec<OrderID> = self.order_index
+            .values()
+            .filter(|order| selected(order))
+            .map(|order| order.order_id)
+            .collect();
+        order_ids.sort();
+        order_ids.into_iter().filter_map(|order_id| self.remove_order(order_id, accounts)).collect()
+    }
+
+    // Removes every resting order matching `expired`, refunding it and reporting it as expired
+    fn expire_orders(&mut self, expired: impl Fn(&Order) -> bool, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+        for order in self.remove_orders(expired, accounts) {
+            events.push(EngineMessage::OrderExpired { client_id: order.sender_id, order_id: order.order_id });
+        }
+    }
+
+    // Removes every resting order matching `cancelled`, refunding it and reporting it as cancelled for `reason`
+    fn cancel_orders(
+        &mut self,
+        cancelled: impl Fn(&Order) -> bool,
+        reason: CancelReason,
+        accounts: &mut HashMap<AccountID, Bankroll>,
+        events: &mut Vec<EngineMessage>,
+    ) {
+        for order in self.remove_orders(cancelled, accounts) {
+            events.push(order_cancelled(order.sender_id.clone(), &order, reason.clone()));
+        }
+    }
+
+    // Aggregated (price, quantity) per level, best first, limited to `depth` levels per side (0 = all)
+    fn depth_levels(&self, depth: usize) -> (Vec<Level>, Vec<Level>) {
+        let depth = if depth == 0 { usize::MAX } else { depth };
+        let aggregate = |(price, queue): (&Price, &VecDeque<Order>)| (*price, queue.iter().map(|order| order.quantity).sum());
+        let bids = self.bids.iter().rev().take(depth).map(aggregate).collect();
+        let asks = self.asks.iter().take(depth).map(aggregate).collect();
+        (bids, asks)
+    }
+
+    // The orders on the best `depth` levels of each side (0 = all), best level first and in
+    // time priority within a level
+    fn depth_orders(&self, depth: usize) -> (Vec<DepthOrder>, Vec<DepthOrder>) {
+        let depth = if depth == 0 { usize::MAX } else { depth };
+        let orders = |(price, queue): (&Price, &VecDeque<Order>)| queue.iter().map(|order| (order.order_id, *price, order.quantity)).collect::<Vec<_>>();
+        let bids = self.bids.iter().rev().take(depth).flat_map(orders).collect();
+        let asks = self.asks.iter().take(depth).flat_map(orders).collect();
+        (bids, asks)
+    }
+
+    // Every level down to `depth`, as additions for a subscriber that has seen nothing yet
+    fn initial_view(&mut self, depth: u32) -> Vec<BookChange> {
+        self.published_views.remove(&depth);
+        self.publish_view(depth)
+    }
+
+    // What changed down to `depth` since the view last published there, which this replaces
+    fn publish_view(&mut self, depth: u32) -> Vec<BookChange> {
+        let view = self.depth_levels(depth as usize);
+        let changes = match self.published_views.get(&depth) {
+            Some(previous) => Self::diff_views(previous, &view),
+            None => Self::diff_views(&BookView::default(), &view),
+        };
+        self.published_views.insert(depth, view);
+        changes
+    }
+
+    // Removals come first, so a depth-limited subscriber applying the changes in order
+    // never holds more than its depth on a side
+    fn diff_views(previous: &BookView, current: &BookView) -> Vec<BookChange> {
+        let mut removals = Vec::new();
+        let mut upserts = Vec::new();
+        for (side, before, after) in [(Side::Buy, &previous.0, &current.0), (Side::Sell, &previous.1, &current.1)] {
+            let before_quantities: HashMap<Price, Quantity> = before.iter().copied().collect();
+            let after_quantities: HashMap<Price, Quantity> = after.iter().copied().collect();
+            for (price, _) in before {
+                if !after_quantities.contains_key(price) {
+                    removals.push(BookChange::RemoveLevel { side, price: *price });
                 }
-                Side::Sell => {
-                    if let Some((&best_bid_price, _)) = self.bids.iter().next_back() {
-                        if best_bid_price > order.price {
-                            // Not triggered yet, buffer order
-                            return fills;
-                        }
-                    } else {
-                        // No market price, cannot trigger
-                        return fills;
+            }
+            for &(price, quantity) in after {
+                match before_quantities.get(&price) {
+                    None => upserts.push(BookChange::AddLevel { side, price, quantity }),
+                    Some(&previous_quantity) if previous_quantity != quantity => {
+                        upserts.push(BookChange::UpdateLevel { side, price, quantity });
                     }
-                    // Triggered, convert to Market order for matching
-                    order.order_type = OrdType::Market;
+                    Some(_) => {}
                 }
-                _ => {}
             }
         }
+        removals.extend(upserts);
+        removals
+    }
 
-        // Handle StopLimit orders
-        else if let OrdType::StopLimit = order.order_type {
-            match order.side {
-                Side::Buy => {
-                    if let Some((&best_ask_price, _)) = self.asks.iter().next() {
-                        if best_ask_price < order.price {
-                            // Not triggered yet, buffer order
-                            return fills;
-                        }
-                    } else {
-                        // No market price, cannot trigger
-                        return fills;
-                    }
-                    // Triggered, convert to Limit order for matching
-                    order.order_type = OrdType::Limit;
+    // Rescales every resting order for a share split. Quantities round down, prices round to
+    // SPLIT_PRICE_DECIMALS, and a buy's reserved cash is trued up to its new notional.
+    fn apply_split(&mut self, numerator: u64, denominator: u64, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+        // Every price moves, so the sides start again on trees
+        let bids = std::mem::take(&mut self.bids).into_tree();
+        let asks = std::mem::take(&mut self.asks).into_tree();
+        let stops = std::mem::take(&mut self.stops);
+        let auction_orders = std::mem::take(&mut self.auction_orders);
+        self.order_index.clear();
+        // The last trade is quoted as the shares now trade, so bands measured from it still fit
+        self.last_price = self.last_price.map(|price| split_price(price, numerator, denominator));
+        // Best levels first, so orders that round onto the same level keep their priority
+        for (_, queue) in bids.into_iter().rev().chain(asks) {
+            for order in queue {
+                if let Some(order) = split_order(order, numerator, denominator, accounts, events) {
+                    let levels = match order.side {
+                        Side::Buy => &mut self.bids,
+                        _ => &mut self.asks,
+                    };
+                    levels.level_mut(order.level()).push_back(order.clone());
+                    self.order_index.insert(order.order_id, order);
                 }
-                Side::Sell => {
-                    if let Some((&best_bid_price, _)) = self.bids.iter().next_back() {
-                        if best_bid_price > order.price {
-                            // Not triggered yet, buffer order
-                            return fills;
-                        }
-                    } else {
-                        // No market price, cannot trigger
-                        return fills;
-                    }
-                    // Triggered, convert to Limit order for matching
-                    order.order_type = OrdType::Limit;
+            }
+        }
+        // Parked orders stay off the book at their new prices, oldest first as before
+        for order in stops {
+            if let Some(order) = split_order(order, numerator, denominator, accounts, events) {
+                self.park_stop(order);
+            }
+        }
+        for order in auction_orders {
+            if let Some(order) = split_order(order, numerator, denominator, accounts, events) {
+                self.park_for_auction(order);
+            }
+        }
+    }
+
+    fn warming_up(&self) -> bool {
+        matches!(self.phase, InstrumentPhase::WarmUp { .. })
+    }
+
+    // Neither warming up nor halted, so whatever crosses trades
+    fn is_open(&self) -> bool {
+        !self.halted && !self.warming_up()
+    }
+
+    // An order as it arrives or is amended: one good only for auctions waits off an open book
+    // for the next, and any other is matched
+    fn enter_order(&mut self, order: Order, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64) -> Result<Vec<EngineMessage>, String> {
+        if order.good_for_auction && self.is_open() {
+            self.park_for_auction(order);
+            return Ok(Vec::new());
+        }
+        self.match_order(order, accounts, trade_match_counter)
+    }
+
+    fn park_stop(&mut self, order: Order) {
+        self.order_index.insert(order.order_id, order.clone());
+        self.stops.push(order);
+    }
+
+    fn park_for_auction(&mut self, order: Order) {
+        self.order_index.insert(order.order_id, order.clone());
+        self.auction_orders.push(order);
+    }
+
+    // Every order the book holds, on it or parked off it, in time priority within each level
+    // and oldest first off it, as bulk_insert takes them back
+    fn saved_orders(&self) -> impl Iterator<Item = &Order> {
+        self.bids.iter().rev().chain(self.asks.iter()).flat_map(|(_, queue)| queue).chain(&self.stops).chain(&self.auction_orders)
+    }
+
+    // Puts the good-for-auction orders on the book for an uncross, behind whatever rests at their levels
+    fn unpark_auction_orders(&mut self) {
+        for order in std::mem::take(&mut self.auction_orders) {
+            self.place(order);
+        }
+    }
+
+    // Takes the good-for-auction orders an uncross left unfilled back off the book
+    fn park_auction_orders(&mut self) {
+        let mut order_ids: Vec<OrderID> = self.order_index.values().filter(|order| order.good_for_auction).map(|order| order.order_id).collect();
+        order_ids.sort();
+        for order_id in order_ids {
+            if let Some(order) = self.take_order(order_id) {
+                self.park_for_auction(order);
+            }
+        }
+    }
+
+    // The order's fills and any resting or cancellation that follows, or why it was refused
+    // before trading, then whatever the stops its trades triggered did
+    fn match_order(&mut self, order: Order, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64) -> Result<Vec<EngineMessage>, String> {
+        let first_execution = self.executions.len();
+        let mut fills = self.execute(order, accounts, trade_match_counter)?;
+        self.trigger_stops(first_execution, accounts, trade_match_counter, &mut fills);
+        debug_assert!(!self.is_open() || !self.is_crossed(), "book crossed after matching: {:?} against {:?}", self.bids.keys().next_back(), self.asks.keys().next());
+        Ok(fills)
+    }
+
+    // Parked stops that the trades from the `from`th on reached, oldest first, each traded as
+    // it triggers. The last of those trades is where the market is; a stop limit it has passed
+    // is cancelled rather than left to chase it. Trades the stops make trigger more in turn.
+    fn trigger_stops(&mut self, mut from: usize, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64, fills: &mut Vec<EngineMessage>) {
+        while let Some(last_trade) = self.executions[from..].last().map(|execution| execution.price) {
+            from = self.executions.len();
+            let (triggered, parked): (Vec<Order>, Vec<Order>) = std::mem::take(&mut self.stops)
+                .into_iter()
+                .partition(|stop| stop_reached(stop.side, stop.price, last_trade));
+            self.stops = parked;
+            for stop in &triggered {
+                self.order_index.remove(&stop.order_id);
+            }
+            for mut stop in triggered {
+                let passed_limit = stop.order_type == OrdType::StopLimit && stop.price.is_some_and(|limit| match stop.side {
+                    Side::Buy => last_trade > limit,
+                    _ => last_trade < limit,
+                });
+                if passed_limit {
+                    refund_order(&stop, accounts);
+                    fills.push(order_cancelled(stop.sender_id.clone(), &stop, CancelReason::Other("Market moved past stop limit price".to_string())));
+                    continue;
                 }
-                _ => {}
+                trigger_stop(&mut stop, accounts);
+                // Refused for trading with its own account, which the account asked for
+                let self_trade = accounts.get(&stop.account_id).is_some_and(|account| account.smp_action == SmpAction::RejectAggressor)
+                    && self.crosses_own_order(&stop);
+                match self.execute(stop.clone(), accounts, trade_match_counter) {
+                    Ok(stop_fills) => fills.extend(stop_fills),
+                    // Refunded already, as a rejected arrival would have been
+                    Err(_) if self_trade => fills.push(order_cancelled(stop.sender_id.clone(), &stop, CancelReason::SelfTradePrevention)),
+                    Err(reason) => fills.push(order_cancelled(stop.sender_id.clone(), &stop, CancelReason::Other(reason))),
+                }
+            }
+        }
+    }
+
+    // Trades everything that crosses at the single price that trades the most, best bid against
+    // best ask and in time priority within a level. Of prices trading as much, the one leaving
+    // least unmatched at it wins, then the one nearest the last trade, then the lower.
+    fn auction(&mut self, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64, fills: &mut Vec<EngineMessage>) {
+        let Some(price) = self.clearing_price() else { return };
+        let first_execution = self.executions.len();
+        while self.bids.keys().next_back().is_some_and(|&bid| bid >= price) && self.asks.keys().next().is_some_and(|&ask| ask <= price) {
+            self.cross_at(price, accounts, trade_match_counter, fills);
+        }
+        self.trigger_stops(first_execution, accounts, trade_match_counter, fills);
+    }
+
+    fn clearing_price(&self) -> Option<Price> {
+        let (&best_bid, &best_ask) = (self.bids.keys().next_back()?, self.asks.keys().next()?);
+        let quantity = |queue: &VecDeque<Order>| queue.iter().map(|order| order.quantity).sum::<Quantity>();
+        self.bids
+            .keys()
+            .chain(self.asks.keys())
+            .filter(|&&price| best_ask <= price && price <= best_bid)
+            .copied()
+            .min_by_key(|&price| {
+                let demand: Quantity = self.bids.iter().filter(|(&level, _)| level >= price).map(|(_, queue)| quantity(queue)).sum();
+                let supply: Quantity = self.asks.iter().filter(|(&level, _)| level <= price).map(|(_, queue)| quantity(queue)).sum();
+                let from_last = Price::from((price - self.last_price.unwrap_or(price)).abs());
+                (std::cmp::Reverse(demand.min(supply)), demand.abs_diff(supply), from_last, price)
+            })
+    }
+
+    // One trade at `price` between the first orders on the best bid and best ask, settled as
+    // matching settles it
+    fn cross_at(&mut self, price: Price, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64, fills: &mut Vec<EngineMessage>) {
+        let (Some(mut bid), Some(mut ask)) = (self.bids.values().next_back().and_then(|queue| queue.front().cloned()), self.asks.values().next().and_then(|queue| queue.front().cloned())) else {
+            return;
+        };
+        let quantity = bid.quantity.min(ask.quantity);
+        *trade_match_counter += 1;
+        for order in [&bid, &ask] {
+            fills.push(EngineMessage::OrderFilled {
+                order_id: order.order_id,
+                client_order_id: order.sent_client_order_id(),
+                filled_quantity: quantity,
+                remaining_quantity: order.quantity - quantity,
+                cumulative_quantity: 0, // stamped as the exchange sees the fill
+                price,
+                instrument_id: order.instrument_id.clone(),
+                side: order.side,
+                client_id: order.sender_id.clone(),
+                arrival_bid: None,
+                arrival_ask: None,
+                trade_match_id: *trade_match_counter,
+                average_price: price,
+                summary: false,
+            });
+        }
+        self.executions.push(Execution {
+            trade_match_id: *trade_match_counter,
+            price,
+            quantity,
+            buyer: trade_party(&bid),
+            seller: trade_party(&ask),
+            aggressor: AggressorSide::Neither,
+        });
+        if let Some(buyer_account) = accounts.get_mut(&bid.account_id) {
+            buyer_account.buy_filled(&bid, quantity, price);
+        }
+        if let Some(seller_account) = accounts.get_mut(&ask.account_id) {
+            seller_account.sell(Some(ask.order_id), &ask.instrument_id, quantity, price);
+        }
+        for order in [&mut bid, &mut ask] {
+            order.quantity -= quantity;
+            if order.quantity == 0 {
+                self.take_order(order.order_id);
+            } else {
+                self.amend_in_place(order.clone());
+            }
+        }
+    }
+
+    fn execute(&mut self, mut order: Order, accounts: &mut HashMap<AccountID, Bankroll>, trade_match_counter: &mut u64) -> Result<Vec<EngineMessage>, String> {
+        let mut fills = Vec::new();
+        // Nothing trades during warm-up, even across the spread; the book opens with it crossed
+        if self.warming_up() {
+            if is_stop(&order) {
+                self.park_stop(order);
+            } else {
+                self.rest_order(order, accounts, &mut fills);
+            }
+            return Ok(fills);
+        }
+        // A stop the touch hasn't reached waits on the book's side for a trade that reaches it
+        if is_stop(&order) {
+            if !self.stop_triggers(order.side, order.price) {
+                self.park_stop(order);
+                return Ok(fills);
+            }
+            trigger_stop(&mut order, accounts);
+        }
+
+        let smp_action = accounts.get(&order.account_id).map_or(SmpAction::Allow, |account| account.smp_action);
+        if smp_action == SmpAction::RejectAggressor && self.crosses_own_order(&order) {
+            // Give back exactly what arrival took, whichever side the order is on
+            if let Some(account) = accounts.get_mut(&order.account_id) {
+                account.release(&order, reserved_cash(&order));
             }
+            return Err("Would cross own resting order".to_string());
         }
 
+        // Trades the order has made, and whether it stopped at max_executions with more it could take
+        let (mut executions, mut capped) = (0, false);
         // Now proceed to matching logic
         match order.side {
             Side::Buy => {
                 while order.quantity > 0 {
-                    let best_ask_price = if order.order_type == OrdType::Market {
-                        self.asks.keys().next().cloned()
-                    } else {
-                        self.asks.keys().next().filter(|&p| order.price >= *p).cloned()
-                    };
+                    let best_ask_price = self.asks.keys().next().filter(|&&ask| order.price.is_none_or(|limit| limit >= ask)).cloned();
                     if let Some(price) = best_ask_price {
                         let queue = self.asks.get_mut(&price).unwrap();
                         while order.quantity > 0 && !queue.is_empty() {
+                            if executions == self.max_executions {
+                                capped = true;
+                                break;
+                            }
+                            executions += 1;
                             if let Some(mut best_ask) = queue.pop_front() {
                                 let trade_qty = order.quantity.min(best_ask.quantity);
+                                *trade_match_counter += 1;
                                 // Emit fill for incoming (buy) order
                                 fills.push(EngineMessage::OrderFilled {
                                     order_id: order.order_id,
+                                    client_order_id: order.sent_client_order_id(),
                                     filled_quantity: trade_qty,
                                     remaining_quantity: order.quantity - trade_qty,
-                                    price: price,
+                                    cumulative_quantity: 0, // stamped as the exchange sees the fill
+                                    price,
                                     instrument_id: order.instrument_id.clone(),
+                                    side: order.side,
                                     client_id: order.sender_id.clone(),
+                                    arrival_bid: None, // stamped from the order's arrival once the exchange sees the fill
+                                    arrival_ask: None,
+                                    trade_match_id: *trade_match_counter,
+                                    average_price: price,
+                                    summary: false,
                                 });
                                 // Emit fill for matched (sell) order
                                 fills.push(EngineMessage::OrderFilled {
                                     order_id: best_ask.order_id,
+                                    client_order_id: best_ask.sent_client_order_id(),
                                     filled_quantity: trade_qty,
                                     remaining_quantity: best_ask.quantity - trade_qty,
-                                    price: price,
+                                    cumulative_quantity: 0, // stamped as the exchange sees the fill
+                                    price,
                                     instrument_id: best_ask.instrument_id.clone(),
+                                    side: best_ask.side,
                                     client_id: best_ask.sender_id.clone(),
+                                    arrival_bid: None,
+                                    arrival_ask: None,
+                                    trade_match_id: *trade_match_counter,
+                                    average_price: price,
+                                    summary: false,
+                                });
+                                self.executions.push(Execution {
+                                    trade_match_id: *trade_match_counter,
+                                    price,
+                                    quantity: trade_qty,
+                                    buyer: trade_party(&order),
+                                    seller: trade_party(&best_ask),
+                                    aggressor: AggressorSide::Buy,
                                 });
                                 // --- Account updates for Buy ---
                                 // Buyer: order.account_id, Seller: best_ask.account_id
                                 // Buyer: deduct cash, increase position
                                 if let Some(buyer_account) = accounts.get_mut(&order.account_id) {
-                                    buyer_account.cash -= price * trade_qty as f64;
-                                    buyer_account.positions
-                                        .entry(order.instrument_id.clone())
-                                        .and_modify(|pos| *pos += trade_qty)
-                                        .or_insert(trade_qty);
+                                    buyer_account.buy_filled(&order, trade_qty, price);
                                 }
                                 // Seller: increase cash, decrease position
                                 if let Some(seller_account) = accounts.get_mut(&best_ask.account_id) {
-                                    seller_account.cash += price * trade_qty as f64;
-                                    seller_account.positions
-                                        .entry(best_ask.instrument_id.clone())
-                                        .and_modify(|pos| *pos -= trade_qty)
-                                        .or_insert(0);
+                                    seller_account.sell(Some(best_ask.order_id), &best_ask.instrument_id, trade_qty, price);
                                 }
                                 if best_ask.quantity > order.quantity {
                                     best_ask.quantity -= order.quantity;
                                     order.quantity = 0;
+                                    // Keep the index's copy at the leaves quantity too
+                                    self.order_index.insert(best_ask.order_id, best_ask.clone());
                                     queue.push_front(best_ask);
                                 } else if best_ask.quantity < order.quantity {
                                     order.quantity -= best_ask.quantity;
@@ -187,7 +1006,7 @@ impl OrderBook {
                         if queue.is_empty() {
                             self.asks.remove(&price);
                         }
-                        if order.quantity == 0 {
+                        if order.quantity == 0 || capped {
                             break;
                         }
                     } else {
@@ -196,87 +1015,101 @@ impl OrderBook {
                 }
 
                 match order.time_in_force {
+                    // Whatever the order's time in force, it trades no further
+                    _ if capped => cancel_capped(&order, accounts, &mut fills),
                     TimeInForce::ImmediateOrCancel => {
-                        // Immediate or Cancel: discard any unfilled quantity
+                        // Immediate or Cancel: cancel any unfilled quantity
                         if order.quantity > 0 {
-                            // Discard remaining quantity
-                            return fills;
-                        } else {
-                            // Fully or partially matched, no further action needed
-                            return fills;
+                            cancel_remainder(&order, accounts, &mut fills);
                         }
                     }
                     TimeInForce::FillOrKill => {
                         // Fill or Kill: if not fully filled, discard entire order
                         if order.quantity > 0 {
                             // Rollback any partial fills by re-adding asks consumed
-                            // Since we don't track partial fills separately, for simplicity, discard entire order without adding to book
-                            return fills;
-                        } else {
-                            // Fully filled
-                            return fills;
+                            // Since we don't track partial fills separately, for simplicity, only the remainder is cancelled
+                            cancel_remainder(&order, accounts, &mut fills);
                         }
                     }
                     _ => {
                         // Other TIF: post remaining quantity to book
                         if order.quantity > 0 {
-                            self.bids.entry(order.price).or_default().push_back(order.clone());
-                            self.order_index.insert(order.order_id, order);
+                            self.rest_order(order, accounts, &mut fills);
                         }
                     }
                 }
             }
             Side::Sell => {
                 while order.quantity > 0 {
-                    let best_bid_price = if order.order_type == OrdType::Market {
-                        self.bids.keys().next_back().cloned()
-                    } else {
-                        self.bids.keys().next_back().filter(|&p| order.price <= *p).cloned()
-                    };
+                    let best_bid_price = self.bids.keys().next_back().filter(|&&bid| order.price.is_none_or(|limit| limit <= bid)).cloned();
                     if let Some(price) = best_bid_price {
                         let queue = self.bids.get_mut(&price).unwrap();
                         while order.quantity > 0 && !queue.is_empty() {
+                            if executions == self.max_executions {
+                                capped = true;
+                                break;
+                            }
+                            executions += 1;
                             if let Some(mut best_bid) = queue.pop_front() {
                                 let trade_qty = order.quantity.min(best_bid.quantity);
+                                *trade_match_counter += 1;
                                 // Emit fill for incoming (sell) order
                                 fills.push(EngineMessage::OrderFilled {
                                     order_id: order.order_id,
+                                    client_order_id: order.sent_client_order_id(),
                                     filled_quantity: trade_qty,
                                     remaining_quantity: order.quantity - trade_qty,
-                                    price: price,
+                                    cumulative_quantity: 0, // stamped as the exchange sees the fill
+                                    price,
                                     instrument_id: order.instrument_id.clone(),
+                                    side: order.side,
                                     client_id: order.sender_id.clone(),
+                                    arrival_bid: None,
+                                    arrival_ask: None,
+                                    trade_match_id: *trade_match_counter,
+                                    average_price: price,
+                                    summary: false,
                                 });
                                 // Emit fill for matched (buy) order
                                 fills.push(EngineMessage::OrderFilled {
                                     order_id: best_bid.order_id,
+                                    client_order_id: best_bid.sent_client_order_id(),
                                     filled_quantity: trade_qty,
                                     remaining_quantity: best_bid.quantity - trade_qty,
-                                    price: price,
+                                    cumulative_quantity: 0, // stamped as the exchange sees the fill
+                                    price,
                                     instrument_id: best_bid.instrument_id.clone(),
+                                    side: best_bid.side,
                                     client_id: best_bid.sender_id.clone(),
+                                    arrival_bid: None,
+                                    arrival_ask: None,
+                                    trade_match_id: *trade_match_counter,
+                                    average_price: price,
+                                    summary: false,
+                                });
+                                self.executions.push(Execution {
+                                    trade_match_id: *trade_match_counter,
+                                    price,
+                                    quantity: trade_qty,
+                                    buyer: trade_party(&best_bid),
+                                    seller: trade_party(&order),
+                                    aggressor: AggressorSide::Sell,
                                 });
                                 // --- Account updates for Sell ---
                                 // Seller: order.account_id, Buyer: best_bid.account_id
                                 // Seller: increase cash, decrease position
                                 if let Some(seller_account) = accounts.get_mut(&order.account_id) {
-                                    seller_account.cash += price * trade_qty as f64;
-                                    seller_account.positions
-                                        .entry(order.instrument_id.clone())
-                                        .and_modify(|pos| *pos -= trade_qty)
-                                        .or_insert(0);
+                                    seller_account.sell(Some(order.order_id), &order.instrument_id, trade_qty, price);
                                 }
                                 // Buyer: deduct cash, increase position
                                 if let Some(buyer_account) = accounts.get_mut(&best_bid.account_id) {
-                                    buyer_account.cash -= price * trade_qty as f64;
-                                    buyer_account.positions
-                                        .entry(best_bid.instrument_id.clone())
-                                        .and_modify(|pos| *pos += trade_qty)
-                                        .or_insert(trade_qty);
+                                    buyer_account.buy_filled(&best_bid, trade_qty, price);
                                 }
                                 if best_bid.quantity > order.quantity {
                                     best_bid.quantity -= order.quantity;
                                     order.quantity = 0;
+                                    // Keep the index's copy at the leaves quantity too
+                                    self.order_index.insert(best_bid.order_id, best_bid.clone());
                                     queue.push_front(best_bid);
                                 } else if best_bid.quantity < order.quantity {
                                     order.quantity -= best_bid.quantity;
@@ -290,7 +1123,7 @@ impl OrderBook {
                         if queue.is_empty() {
                             self.bids.remove(&price);
                         }
-                        if order.quantity == 0 {
+                        if order.quantity == 0 || capped {
                             break;
                         }
                     } else {
@@ -299,46 +1132,73 @@ impl OrderBook {
                 }
 
                 match order.time_in_force {
+                    // Whatever the order's time in force, it trades no further
+                    _ if capped => cancel_capped(&order, accounts, &mut fills),
                     TimeInForce::ImmediateOrCancel => {
-                        // Immediate or Cancel: discard any unfilled quantity
+                        // Immediate or Cancel: cancel any unfilled quantity
                         if order.quantity > 0 {
-                            // Discard remaining quantity
-                            return fills;
-                        } else {
-                            // Fully or partially matched, no further action needed
-                            return fills;
+                            cancel_remainder(&order, accounts, &mut fills);
                         }
                     }
                     TimeInForce::FillOrKill => {
                         // Fill or Kill: if not fully filled, discard entire order
                         if order.quantity > 0 {
                             // Rollback any partial fills by re-adding bids consumed
-                            // Since we don't track partial fills separately, for simplicity, discard entire order without adding to book
-                            return fills;
-                        } else {
-                            // Fully filled
-                            return fills;
+                            // Since we don't track partial fills separately, for simplicity, only the remainder is cancelled
+                            cancel_remainder(&order, accounts, &mut fills);
                         }
                     }
                     _ => {
                         // Other TIF: post remaining quantity to book
                         if order.quantity > 0 {
-                            self.asks.entry(order.price).or_default().push_back(order.clone());
-                            self.order_index.insert(order.order_id, order);
+                            self.rest_order(order, accounts, &mut fills);
                         }
                     }
                 }
             }
             _ => {}
         }
-        fills
+        Ok(fills)
+    }
+
+    // Takes an order off the book and refunds it, returning it as it stood
+    fn remove_order(&mut self, order_id: OrderID, accounts: &mut HashMap<AccountID, Bankroll>) -> Option<Order> {
+        let order = self.take_order(order_id)?;
+        refund_order(&order, accounts);
+        Some(order)
+    }
+
+    // Changes a resting order where it stands, so it keeps its place in the queue
+    fn amend_in_place(&mut self, amended: Order) {
+        if let Some(order) = self.stops.iter_mut().chain(&mut self.auction_orders).find(|order| order.order_id == amended.order_id) {
+            *order = amended.clone();
+            self.order_index.insert(amended.order_id, amended);
+            return;
+        }
+        let levels = match amended.side {
+            Side::Buy => &mut self.bids,
+            _ => &mut self.asks,
+        };
+        if let Some(order) = levels.get_mut(&amended.level()).and_then(|queue| queue.iter_mut().find(|order| order.order_id == amended.order_id)) {
+            *order = amended.clone();
+            self.order_index.insert(amended.order_id, amended);
+        }
     }
 
-    fn remove_order(&mut self, order_id: OrderID, accounts: &mut HashMap<AccountID, Bankroll>) -> bool {
+    // Lifts a resting order off the book, leaving what its account holds for it untouched
+    fn take_order(&mut self, order_id: OrderID) -> Option<Order> {
+        if let Some(idx) = self.stops.iter().position(|order| order.order_id == order_id) {
+            self.order_index.remove(&order_id);
+            return Some(self.stops.remove(idx));
+        }
+        if let Some(idx) = self.auction_orders.iter().position(|order| order.order_id == order_id) {
+            self.order_index.remove(&order_id);
+            return Some(self.auction_orders.remove(idx));
+        }
         if let Some(order) = self.order_index.get(&order_id).cloned() {
             let queue_opt = match order.side {
-                Side::Buy => self.bids.get_mut(&order.price),
-                Side::Sell => self.asks.get_mut(&order.price),
+                Side::Buy => self.bids.get_mut(&order.level()),
+                Side::Sell => self.asks.get_mut(&order.level()),
                 _ => None,
             };
             if let Some(queue) = queue_opt {
@@ -346,196 +1206,4838 @@ impl OrderBook {
                     queue.remove(idx);
                     if queue.is_empty() {
                         match order.side {
-                            Side::Buy => { self.bids.remove(&order.price); }
-                            Side::Sell => { self.asks.remove(&order.price); }
+                            Side::Buy => { self.bids.remove(&order.level()); }
+                            Side::Sell => { self.asks.remove(&order.level()); }
                             _ => {}
                         }
                     }
                     self.order_index.remove(&order_id);
-                    // Refund cash or restore position on cancellation
-                    match order.side {
-                        Side::Buy => {
-                            if let Some(account) = accounts.get_mut(&order.account_id) {
-                                account.cash += order.price * order.quantity as f64;
-                            }
-                        }
-                        Side::Sell => {
-                            if let Some(account) = accounts.get_mut(&order.account_id) {
-                                account.positions
-                                    .entry(order.instrument_id.clone())
-                                    .and_modify(|pos| *pos += order.quantity)
-                                    .or_insert(order.quantity);
-                            }
-                        }
-                        _ => {}
-                    }
-                    return true;
+                    return Some(order);
                 }
             }
         }
-        false
+        None
     }
 }
 
-#[derive(Debug)]
-struct Bankroll {
-    pub cash: AccountBalance,
-    pub positions: HashMap<InstrumentID, Quantity>, // instrument -> quantity
-}
+const SPLIT_PRICE_DECIMALS: i32 = 4;
 
-#[derive(Debug)]
-pub struct Exchange {
-    order_counter: OrderID,
-    accounts: HashMap<AccountID, Bankroll>,
-    books: HashMap<InstrumentID, OrderBook>,
+const RESTING_ORDER_ALERT_PERCENT: usize = 90;
+
+// How much of each new spread a book's reference spread takes in, as an exponential moving average
+const REFERENCE_SPREAD_WEIGHT: f64 = 0.1;
+
+// Levels per side counted toward a book's liquidity depth
+const LIQUIDITY_DEPTH_LEVELS: usize = 5;
+
+// Most trades sent back for one replay request; clients page by moving the start time
+const MAX_REPLAY_TRADES: usize = 1_000;
+
+// The metadata key, "low..high", that puts a book's levels on ladders over that range
+const PRICE_LADDER: &str = "price_ladder";
+
+fn split_price(price: Price, numerator: u64, denominator: u64) -> Price {
+    let scale = 10f64.powi(SPLIT_PRICE_DECIMALS);
+    Price::from((price.into_inner() * denominator as f64 / numerator as f64 * scale).round() / scale)
 }
 
-impl Exchange {
-    pub fn new() -> Self {
-        Self {
-            order_counter: 1,
-            accounts: HashMap::new(),
-            books: HashMap::new(),
+// `order` as a split leaves it, with what its owner holds for it trued up and told to them;
+// None if it rounds to less than one share, which cancels it
+fn split_order(mut order: Order, numerator: u64, denominator: u64, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) -> Option<Order> {
+    let old_notional = reserved_cash(&order);
+    // Reported if the order rounds away, so clients can drop what they hold for it
+    let (old_price, old_quantity) = (order.price, order.quantity);
+    order.quantity = order.quantity * numerator / denominator;
+    order.price = Some(split_price(order.level(), numerator, denominator));
+    if order.side == Side::Buy {
+        if let Some(account) = accounts.get_mut(&order.account_id) {
+            account.release(&order, old_notional - reserved_cash(&order));
         }
     }
+    if order.quantity == 0 {
+        events.push(EngineMessage::OrderCancelled {
+            order_id: order.order_id,
+            client_id: order.sender_id.clone(),
+            reason: CancelReason::Other("Split left less than one share".to_string()),
+            instrument_id: order.instrument_id.clone(),
+            cancelled_price: old_price,
+            cancelled_quantity: old_quantity,
+            side: order.side,
+            cumulative_quantity: 0,
+            average_price: Price::from(0.0),
+        });
+        return None;
+    }
+    events.push(EngineMessage::OrderAmended {
+        client_id: order.sender_id.clone(),
+        order_id: order.order_id,
+        new_quantity: Some(order.quantity),
+        new_price: order.price,
+        // The exchange stamps the tracked status and fills on the way out
+        status: OrdStatus::New,
+        cumulative_quantity: 0,
+        leaves_quantity: order.quantity,
+    });
+    Some(order)
+}
 
-    pub fn handle_message(&mut self, message: EngineMessage) -> Option<EngineMessage> {
-        match message {
-            EngineMessage::CreateInstrument { instrument_id, .. } => {
-                // Extract sending_time and receiving_time if present (future logic)
-                self.books.entry(instrument_id).or_insert_with(|| OrderBook {
-                    bids: BTreeMap::new(),
-                    asks: BTreeMap::new(),
-                    order_index: HashMap::new(),
-                });
-                None
-            }
-            EngineMessage::NewOrder {
-                sending_time,
-                receiving_time,
-                client_id,
-                account_id,
-                client_order_id,
-                instrument_id,
-                order_type,
-                side,
-                quantity,
-                price,
-                time_in_force,
-            } => {
-                // Extract sending_time and receiving_time at the beginning of the branch
-                let receiving_time = receiving_time;
+fn amend_rejected(client_id: ClientID, order_id: OrderID, reason: &str, status: OrdStatus) -> EngineMessage {
+    EngineMessage::AmendRejected {
+        client_id,
+        order_id,
+        reason: reason.to_string(),
+        status,
+    }
+}
 
-                if !self.books.contains_key(&instrument_id) {
-                    return Some(EngineMessage::OrderRejected {
-                        reason: "Unknown instrument".to_string(),
-                        client_id,
-                    });
-                }
+fn position_report(client_id: ClientID, account_id: AccountID, account: &Bankroll) -> EngineMessage {
+    let mut positions: Vec<(InstrumentID, Quantity, Price)> = account.positions
+        .iter()
+        .map(|(instrument_id, &quantity)| {
+            let cost = account.average_cost.get(instrument_id).copied().unwrap_or(Price::from(0.0));
+            (instrument_id.clone(), quantity, cost)
+        })
+        .collect();
+    positions.sort_by(|a, b| a.0.cmp(&b.0));
+    EngineMessage::PositionReport {
+        client_id,
+        account_id,
+        cash: account.cash,
+        positions,
+    }
+}
 
-                let unit_price = price.unwrap_or(Price::from(0.0));
-                let total_cost = unit_price * quantity as f64;
+// Whether a subscription to `symbol` takes in `instrument_id`: "*" takes in every instrument,
+// "FUT-*" those starting "FUT-", and anything else only itself. A wildcard can be what is
+// taken in, so unsubscribing "FUT-*" ends a subscription to "FUT-ES*" too.
+fn covers(symbol: &str, instrument_id: &str) -> bool {
+    match symbol.strip_suffix('*') {
+        Some(prefix) => instrument_id.starts_with(prefix),
+        None => symbol == instrument_id,
+    }
+}
 
-                let account = self.accounts.entry(account_id.clone()).or_insert_with(|| Bankroll {
-                    cash: Price::from(1000.0),
-                    positions: HashMap::new(),
-                });
+// A session as statements name it, by its date: YYYYMMDD
+fn session_name(date: &Date) -> String {
+    format!("{:04}{:02}{:02}", date.year(), date.month(), date.day())
+}
 
-                if account.cash < total_cost {
-                    return Some(EngineMessage::OrderRejected {
-                        reason: "Insufficient funds".to_string(),
-                        client_id,
-                    });
-                }
+// A stop or stop limit no trade has reached yet; triggering one makes it the order it trades as
+fn is_stop(order: &Order) -> bool {
+    matches!(order.order_type, OrdType::Stop | OrdType::StopLimit)
+}
 
-                account.cash -= total_cost;
+// Whether a market at `price` has reached a stop at `stop_price`: at or above it for a buy, at or below for a sell
+fn stop_reached(side: Side, stop_price: Option<Price>, price: Price) -> bool {
+    stop_price.is_none_or(|stop_price| match side {
+        Side::Buy => price >= stop_price,
+        _ => price <= stop_price,
+    })
+}
 
-                let order_id = self.order_counter;
-                self.order_counter += 1;
+// Turns a triggered stop into the order it trades as. A stop trades as a market order; its
+// stop price was only a trigger, so what arrival reserved at it goes back. A stop limit
+// trades as a limit at its price.
+fn trigger_stop(order: &mut Order, accounts: &mut HashMap<AccountID, Bankroll>) {
+    if order.order_type == OrdType::Stop {
+        if let Some(account) = accounts.get_mut(&order.account_id) {
+            account.release(order, reserved_cash(order));
+        }
+        order.order_type = OrdType::Market;
+        order.price = None;
+    } else {
+        order.order_type = OrdType::Limit;
+    }
+}
 
-                let order = Order {
-                    order_id: order_id.clone(),
-                    client_order_id: client_order_id.unwrap_or("".to_string()),
-                    send_timestamp: sending_time,
-                    receive_timestamp: receiving_time,
-                    price: price.unwrap_or(Price::from(0.0)),
-                    quantity,
-                    side,
-                    order_type,
-                    time_in_force: time_in_force.unwrap_or(TimeInForce::Day),
-                    exec_instruction: ExecInst::StayOnOfferSide,
-                    instrument_id: instrument_id.clone(),
-                    account_id: account_id,
-                    sender_id: client_id.clone(),
-                };
+// Moves a spread fill out of the spread and into its legs: the buyer takes each leg's ratio
+// and the seller the opposite, at the legs' prices. Cash has already moved at the spread's
+// price, which the leg prices add up to.
+fn book_spread_fill(accounts: &mut HashMap<AccountID, Bankroll>, spread_id: &InstrumentID, spread: &SpreadDefinition, anchor_price: Price, execution: &Execution) {
+    for (party, bought) in [(&execution.buyer, true), (&execution.seller, false)] {
+        let Some(account) = accounts.get_mut(&party.account_id) else { continue };
+        // The spread itself is never held
+        account.positions.remove(spread_id);
+        account.average_cost.remove(spread_id);
+        for (leg, price) in spread.leg_prices(execution.price, anchor_price) {
+            let quantity = leg.ratio.unsigned_abs() * execution.quantity;
+            if (leg.ratio > 0) == bought {
+                account.add_position(&leg.instrument_id, quantity, price);
+            } else {
+                // As for any sale, short positions are not tracked
+                account.positions
+                    .entry(leg.instrument_id.clone())
+                    .and_modify(|pos| *pos = pos.saturating_sub(quantity))
+                    .or_insert(0);
+            }
+        }
+    }
+}
 
-                let book = self.books.get_mut(&instrument_id).unwrap();
-                let mut responses = book.match_order(order, &mut self.accounts);
-                responses.push(EngineMessage::OrderAccepted {
-                    client_id,
-                    order_id
-                });
-                // If any responses, return them as a batch (or just the first if Option)
-                // Here, for compatibility, if only one response, return it, else log or batch
-                // For now, return only first, or all in a Vec in future
-                // For demonstration, return all as a LogEvent if multiple
-                if responses.len() == 1 {
-                    Some(responses.remove(0))
-                } else if !responses.is_empty() {
-                    // In real use, would return Vec<EngineMessage>. For now, just log all.
-                    // This is a limitation of the Option<EngineMessage> return type.
-                    // So we return the first, but in practice the caller should handle Vec<EngineMessage>.
-                    Some(responses.remove(0))
-                } else {
-                    None
+// Cancels whatever an immediate order could not fill on arrival
+fn cancel_remainder(order: &Order, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+    refund_order(order, accounts);
+    events.push(order_cancelled(order.sender_id.clone(), order, CancelReason::Other("Unfilled remainder of immediate order".to_string())));
+}
+
+// Cancels the leaves of an aggressor that reached max_executions
+fn cancel_capped(order: &Order, accounts: &mut HashMap<AccountID, Bankroll>, events: &mut Vec<EngineMessage>) {
+    refund_order(order, accounts);
+    events.push(order_cancelled(order.sender_id.clone(), order, CancelReason::ExecutionLimit));
+}
+
+// Reports to `client_id` the leaves of an order taken off the book for `reason`
+fn order_cancelled(client_id: ClientID, order: &Order, reason: CancelReason) -> EngineMessage {
+    EngineMessage::OrderCancelled {
+        client_id,
+        order_id: order.order_id,
+        reason,
+        instrument_id: order.instrument_id.clone(),
+        cancelled_price: order.price,
+        cancelled_quantity: order.quantity,
+        side: order.side,
+        cumulative_quantity: 0, // stamped as the exchange sees the cancel
+        average_price: Price::from(0.0),
+    }
+}
+
+// What an order's leaves come to at its limit; a market order has none to value them at
+fn order_notional(order: &Order) -> AccountBalance {
+    order.price.map_or(AccountBalance::from(0.0), |price| price * order.quantity as f64)
+}
+
+// Cash taken from the account for an order's leaves, as on arrival. Only a buy takes any: a
+// sale is paid as it fills, and a market order pays for its fills as they happen.
+fn reserved_cash(order: &Order) -> AccountBalance {
+    if order.side == Side::Buy { order_notional(order) } else { AccountBalance::from(0.0) }
+}
+
+// Refund cash when a resting order leaves the book unfilled: what arrival held for its leaves,
+// fills having taken back what they used. A sale's position only goes as it fills.
+fn refund_order(order: &Order, accounts: &mut HashMap<AccountID, Bankroll>) {
+    if let Some(account) = accounts.get_mut(&order.account_id) {
+        account.release(order, reserved_cash(order));
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Bankroll {
+    pub cash: AccountBalance,
+    pub positions: HashMap<InstrumentID, Quantity>, // instrument -> quantity
+    pub average_cost: HashMap<InstrumentID, Price>, // instrument -> volume-weighted purchase price
+    #[serde(default)]
+    pub smp_action: SmpAction,
+    #[serde(default)]
+    pub risk_limits: RiskLimits,
+    #[serde(default)]
+    pub daily_pnl: AccountBalance, // realized on sales since the day began, against average cost
+    #[serde(default)]
+    pub loss_alerted: bool, // warned today that losses near max_daily_loss
+    #[serde(default)]
+    pub history: AccountHistory, // every cash movement this session, for its statement
+}
+
+impl Bankroll {
+    pub(crate) fn new(cash: AccountBalance) -> Self {
+        Self {
+            cash,
+            positions: HashMap::new(),
+            average_cost: HashMap::new(),
+            smp_action: SmpAction::Allow,
+            risk_limits: RiskLimits::default(),
+            daily_pnl: AccountBalance::from(0.0),
+            loss_alerted: false,
+            history: AccountHistory { opening_cash: cash, ..AccountHistory::default() },
+        }
+    }
+
+    // True once the day's losses have gone past `fraction` of max_daily_loss
+    fn daily_loss_past(&self, fraction: f64) -> bool {
+        self.risk_limits.max_daily_loss.is_some_and(|limit| self.daily_pnl < AccountBalance::from(-limit * fraction))
+    }
+
+    // Adds bought quantity to a position and folds its price into the average cost
+    fn add_position(&mut self, instrument_id: &InstrumentID, quantity: Quantity, price: Price) {
+        let held = self.positions.get(instrument_id).copied().unwrap_or(0);
+        let cost = self.average_cost.get(instrument_id).copied().unwrap_or(Price::from(0.0));
+        let total = held + quantity;
+        if total > 0 {
+            let average = (cost * held as f64 + price * quantity as f64) / total as f64;
+            self.average_cost.insert(instrument_id.clone(), average);
+        }
+        self.positions.insert(instrument_id.clone(), total);
+    }
+
+    // Moves the entry's amount into cash and notes it, with the balance it leaves, for the
+    // statement. Nothing moving is nothing to note.
+    fn post(&mut self, mut entry: StatementEntry) {
+        if entry.amount == AccountBalance::from(0.0) {
+            return;
+        }
+        self.cash += entry.amount;
+        entry.balance = self.cash;
+        self.history.entries.push(entry);
+    }
+
+    // Sets `amount` aside for `order` as it stands, or hands it back if negative
+    fn hold(&mut self, order: &Order, amount: AccountBalance) {
+        let kind = if amount < AccountBalance::from(0.0) { EntryKind::Release } else { EntryKind::Hold };
+        self.post(StatementEntry {
+            kind,
+            order_id: Some(order.order_id),
+            instrument_id: order.instrument_id.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            amount: -amount,
+            balance: self.cash,
+        });
+    }
+
+    fn release(&mut self, order: &Order, amount: AccountBalance) {
+        self.hold(order, -amount);
+    }
+
+    // Pays for `quantity` of a buy filled at `price`, out of what arrival held for them first
+    fn buy_filled(&mut self, order: &Order, quantity: Quantity, price: Price) {
+        self.post(StatementEntry {
+            kind: EntryKind::Release,
+            order_id: Some(order.order_id),
+            instrument_id: order.instrument_id.clone(),
+            quantity,
+            price: order.price,
+            amount: order.price.map_or(AccountBalance::from(0.0), |limit| limit * quantity as f64),
+            balance: self.cash,
+        });
+        self.buy(Some(order.order_id), &order.instrument_id, quantity, price);
+    }
+
+    // Pays for a fill and takes on its position
+    fn buy(&mut self, order_id: Option<OrderID>, instrument_id: &InstrumentID, quantity: Quantity, price: Price) {
+        self.post(StatementEntry {
+            kind: EntryKind::Buy,
+            order_id,
+            instrument_id: instrument_id.clone(),
+            quantity,
+            price: Some(price),
+            amount: -(price * quantity as f64),
+            balance: self.cash,
+        });
+        self.add_position(instrument_id, quantity, price);
+    }
+
+    // Is paid for a fill and gives up its position; short sales are not tracked, so a
+    // position bottoms out at zero
+    fn sell(&mut self, order_id: Option<OrderID>, instrument_id: &InstrumentID, quantity: Quantity, price: Price) {
+        self.post(StatementEntry {
+            kind: EntryKind::Sell,
+            order_id,
+            instrument_id: instrument_id.clone(),
+            quantity,
+            price: Some(price),
+            amount: price * quantity as f64,
+            balance: self.cash,
+        });
+        self.positions
+            .entry(instrument_id.clone())
+            .and_modify(|pos| *pos = pos.saturating_sub(quantity))
+            .or_insert(0);
+    }
+
+    // The statement so far, each holding marked at `marks`
+    fn statement(&self, account_id: &AccountID, session: &str, marks: impl Fn(&InstrumentID) -> Option<Price>) -> AccountStatement {
+        let mut closing_positions: Vec<MarkedPosition> = self.positions
+            .iter()
+            .map(|(instrument_id, &quantity)| {
+                let mark = marks(instrument_id);
+                MarkedPosition { instrument_id: instrument_id.clone(), quantity, mark, value: mark.map(|mark| mark * quantity as f64) }
+            })
+            .collect();
+        closing_positions.sort_by(|a, b| a.instrument_id.cmp(&b.instrument_id));
+        AccountStatement {
+            account_id: account_id.clone(),
+            session: session.to_string(),
+            opening_cash: self.history.opening_cash,
+            opening_positions: self.history.opening_positions.iter().map(|(instrument_id, &quantity)| (instrument_id.clone(), quantity)).collect(),
+            entries: self.history.entries.clone(),
+            closing_cash: self.cash,
+            closing_positions,
+            realized_pnl: self.daily_pnl - self.history.opening_pnl,
+        }
+    }
+
+    // Starts the next session's statement from where this one closed
+    fn open_statement(&mut self) {
+        self.history = AccountHistory {
+            opening_cash: self.cash,
+            opening_positions: self.positions.iter().map(|(instrument_id, &quantity)| (instrument_id.clone(), quantity)).collect(),
+            opening_pnl: self.daily_pnl,
+            entries: Vec::new(),
+        };
+    }
+}
+
+// Running totals of an order's fills, kept after it leaves the book
+#[derive(Clone, Debug)]
+struct FillSummary {
+    owner: ClientID,
+    cumulative_quantity: Quantity,
+    notional: Price,
+}
+
+// A multileg order whose legs have passed their checks but not yet traded. Each leg's order
+// holds its cash as a single order would, and a sell leg has taken what it delivers out of the
+// account's position; all of it goes back if the order is rolled back.
+#[derive(Clone, Debug)]
+struct PendingMultilegOrder {
+    legs: Vec<(InstrumentID, Order)>,
+    notional: f64, // across every leg, for the fat-finger limit
+}
+
+// What a request asked for, kept while it is handled in case it is rejected
+struct RejectedRequest {
+    account_id: AccountID,
+    instrument_id: Option<InstrumentID>,
+    price: Option<Price>,
+    quantity: Option<Quantity>,
+}
+
+impl RejectedRequest {
+    // A scheduled message is logged as the message it holds
+    fn of(message: &EngineMessage) -> Self {
+        let (account_id, instrument_id, price, quantity) = match message {
+            EngineMessage::NewOrder { account_id, instrument_id, price, quantity, .. } => (Some(account_id), Some(instrument_id), *price, Some(*quantity)),
+            EngineMessage::CancelOrder { account_id, cancel_quantity, .. } => (Some(account_id), None, None, *cancel_quantity),
+            EngineMessage::AmendOrder { new_price, new_quantity, .. } => (None, None, *new_price, *new_quantity),
+            EngineMessage::InboundExecutionReport { account_id, instrument_id, last_px, last_qty, .. } => (Some(account_id), Some(instrument_id), Some(*last_px), Some(*last_qty)),
+            EngineMessage::PositionQuery { account_id, .. }
+            | EngineMessage::GreeksRequest { account_id, .. }
+            | EngineMessage::SetSmpAction { account_id, .. }
+            | EngineMessage::SetRiskLimits { account_id, .. }
+            | EngineMessage::SetAccountAuthorization { account_id, .. }
+            | EngineMessage::NewOrderMultileg { account_id, .. } => (Some(account_id), None, None, None),
+            EngineMessage::CreateInstrument { instrument_id, .. }
+            | EngineMessage::UpdateInstrumentSpec { instrument_id, .. }
+            | EngineMessage::CorporateAction { instrument_id, .. }
+            | EngineMessage::RequestReplay { instrument_id, .. }
+            | EngineMessage::SetInstrumentMetadata { instrument_id, .. }
+            | EngineMessage::GetInstrumentMetadata { instrument_id, .. } => (None, Some(instrument_id), None, None),
+            EngineMessage::SetRestingOrderLimit { instrument_id, .. } | EngineMessage::SymbolStatusRequest { instrument_id, .. } => (None, instrument_id.as_ref(), None, None),
+            EngineMessage::SubscribeOrderBook { symbols, .. } | EngineMessage::UnsubscribeOrderBook { symbols, .. } => (None, symbols.first(), None, None),
+            EngineMessage::Schedule { message, .. } => return Self::of(message),
+            _ => (None, None, None, None),
+        };
+        Self {
+            account_id: account_id.cloned().unwrap_or_default(),
+            instrument_id: instrument_id.cloned(),
+            price,
+            quantity,
+        }
+    }
+}
+
+// What persistent_state keeps, as save_state writes it for a restart to pick up
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedState {
+    order_counter: OrderID,
+    trade_match_counter: u64,
+    instruments: Vec<SavedInstrument>, // in instrument order
+    accounts: HashMap<AccountID, Bankroll>,
+    account_owners: HashMap<AccountID, ClientID>,
+    orders: Vec<Order>, // resting and parked, in the priority each book held them in
+    rejection_log: Vec<RejectionEntry>,
+}
+
+// A book as it is listed again, without its orders
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedInstrument {
+    instrument_id: InstrumentID,
+    spec: InstrumentSpec,
+    segment: Option<SegmentName>,
+    spread: Option<SpreadDefinition>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    order_counter: OrderID,
+    trade_match_counter: u64, // the last TrdMatchID given to a match
+    accounts: HashMap<AccountID, Bankroll>,
+    account_owners: HashMap<AccountID, ClientID>, // the client that opened each account
+    admins: HashSet<ClientID>, // clients that may manage any account, e.g. prime brokers
+    account_authorizations: AccountAuthorizations, // the accounts each CompID may trade, if limited
+    books: HashMap<InstrumentID, OrderBook>,
+    order_instruments: HashMap<OrderID, InstrumentID>, // every open order -> its book, whether on it or parked off it
+    order_statuses: HashMap<OrderID, OrdStatus>, // every order's last reported status
+    order_owners: HashMap<OrderID, ClientID>, // the client that sent each accepted order
+    order_fills: HashMap<OrderID, FillSummary>, // every order that has traded
+    cl_ord_id_to_order_id: HashMap<(ClientID, ClOrdID), OrderID>, // each accepted order by the ClOrdID its sender gave it, kept as long as its status
+    finished_orders: VecDeque<OrderID>, // oldest first, for forgetting those past retention
+    finished_order_retention: usize, // finished orders whose statuses, owners and fills are kept
+    trade_log: VecDeque<TradeRecord>, // in memory only; there is no database behind it yet
+    trade_log_retention: usize, // trades kept, oldest dropped first
+    instrument_metadata: HashMap<InstrumentID, HashMap<String, String>>, // reference data clients attach, never matched on; PRICE_LADDER only changes how a book holds its levels
+    order_histories: HashMap<OrderID, Vec<OrderEvent>>, // every step of each order's life, kept as long as its status
+    rejection_log: Vec<RejectionEntry>, // every OrderRejected, oldest first, for compliance audits
+    malformed_log: Arc<MalformedLog>, // inbound messages the sessions could not read
+    upstream_exec_ids: HashSet<String>, // ExecIDs of the upstream fills booked, so none is booked twice
+    max_resting_orders: usize, // across all books, 0 = unlimited
+    max_session_notional: f64, // what one client may trade in a day, 0 = unlimited
+    session_turnover: HashMap<ClientID, f64>, // price * quantity filled today, by client
+    summary_sessions: HashSet<ClientID>, // logged on for one report per order as it finishes, not one per fill
+    arrival_touches: HashMap<OrderID, ArrivalTouch>, // the market each unfinished order arrived into
+    pending_multileg: Option<PendingMultilegOrder>, // the multileg order between its two phases, if one is
+    execution_statistics: Arc<ExecutionStatistics>, // the session's execution quality, shared with the admin API
+    compaction_cursor: Option<InstrumentID>, // the last book compacted, which the next pass carries on after
+    compaction_stats: Arc<CompactionStats>,
+    simulated_time: Option<Timestamp>, // set by AdvanceTime
+    default_time_in_force: TimeInForce, // for orders that omit TimeInForce
+    pending_corporate_actions: Vec<(Timestamp, InstrumentID, CorporateAction)>,
+    scheduled_messages: BTreeMap<(Date, Time), Vec<EngineMessage>>, // by fire time, in the order scheduled
+    book_subscribers: HashMap<InstrumentID, Vec<(ClientID, u32)>>, // (subscriber, depth) per book
+    market_data_subscriptions: HashMap<ClientID, Vec<(InstrumentID, u32)>>, // each client's symbols and wildcards with their depths, oldest first
+    activity_subscribers: HashSet<ClientID>, // clients whose market-data subscriptions take activity reports too
+    tape_subscriptions: HashMap<InstrumentID, HashSet<ClientID>>, // clients sent every trade, by the symbol or wildcard they subscribed
+    activity_since: Option<Timestamp>, // when the current activity interval began, None before the first
+    recent_orders: Arc<RecentOrders>, // shared with readers outside the engine
+    book_views: Arc<BookViews>, // the books as last published, for readers outside the engine
+    ticker_map: TickerMap, // client tickers -> canonical instrument IDs
+    segments: MarketSegments, // settings instruments inherit from their segment
+    alert_subscribers: Vec<ClientID>, // surveillance clients sent every book's alerts
+    surveillance: Option<UnboundedSender<SurveillanceEvent>>, // order entries, cancels and trades, for wash-trade checks
+    config: Option<Arc<LiveConfig>>, // the server settings the exchange reads as it runs, e.g. price bands
+    overload: Option<Arc<OverloadGuard>>, // set while new orders are refused for the engine to catch up
+    recovery_mode: bool, // rebuilding the books from a saved state: orders are bulk inserted and none are taken
+}
+
+impl Exchange {
+    pub fn new() -> Self {
+        Self {
+            order_counter: 1,
+            trade_match_counter: 0,
+            accounts: HashMap::new(),
+            account_owners: HashMap::new(),
+            admins: HashSet::new(),
+            account_authorizations: AccountAuthorizations::default(),
+            books: HashMap::new(),
+            order_instruments: HashMap::new(),
+            order_statuses: HashMap::new(),
+            order_owners: HashMap::new(),
+            order_fills: HashMap::new(),
+            cl_ord_id_to_order_id: HashMap::new(),
+            finished_orders: VecDeque::new(),
+            finished_order_retention: FINISHED_ORDER_RETENTION,
+            trade_log: VecDeque::new(),
+            trade_log_retention: TRADE_LOG_RETENTION,
+            instrument_metadata: HashMap::new(),
+            order_histories: HashMap::new(),
+            rejection_log: Vec::new(),
+            malformed_log: MalformedLog::new(),
+            upstream_exec_ids: HashSet::new(),
+            max_resting_orders: 0,
+            max_session_notional: 0.0,
+            session_turnover: HashMap::new(),
+            summary_sessions: HashSet::new(),
+            arrival_touches: HashMap::new(),
+            pending_multileg: None,
+            execution_statistics: ExecutionStatistics::new(),
+            compaction_cursor: None,
+            compaction_stats: CompactionStats::new(),
+            simulated_time: None,
+            pending_corporate_actions: Vec::new(),
+            scheduled_messages: BTreeMap::new(),
+            default_time_in_force: TimeInForce::Day,
+            book_subscribers: HashMap::new(),
+            market_data_subscriptions: HashMap::new(),
+            activity_subscribers: HashSet::new(),
+            tape_subscriptions: HashMap::new(),
+            activity_since: None,
+            recent_orders: Arc::new(RecentOrders::new()),
+            book_views: BookViews::new(),
+            ticker_map: TickerMap::default(),
+            segments: MarketSegments::default(),
+            alert_subscribers: Vec::new(),
+            surveillance: None,
+            config: None,
+            overload: None,
+            recovery_mode: false,
+        }
+    }
+
+    // The last accepted orders, readable from other threads while the engine runs
+    pub fn recent_orders(&self) -> Arc<RecentOrders> {
+        Arc::clone(&self.recent_orders)
+    }
+
+    // Execution quality so far this session, readable from other threads while the engine runs
+    pub fn execution_statistics(&self) -> Arc<ExecutionStatistics> {
+        Arc::clone(&self.execution_statistics)
+    }
+
+    pub fn compaction_stats(&self) -> Arc<CompactionStats> {
+        Arc::clone(&self.compaction_stats)
+    }
+
+    // The books as of their last publication, readable from other threads while the engine runs
+    pub fn book_views(&self) -> Arc<BookViews> {
+        Arc::clone(&self.book_views)
+    }
+
+    // Copies every book's levels and orders out for readers off the matching thread
+    fn publish_book_views(&self) {
+        let books = self.books
+            .iter()
+            .map(|(instrument_id, book)| {
+                let (bids, asks) = book.depth_levels(0);
+                let (bid_orders, ask_orders) = book.depth_orders(0);
+                (instrument_id.clone(), PublishedBook { bids, asks, bid_orders, ask_orders })
+            })
+            .collect();
+        self.book_views.publish(self.now(), books);
+    }
+
+    // Simulated time once AdvanceTime has been seen, wall-clock time before that
+    fn now(&self) -> Timestamp {
+        self.simulated_time.clone().unwrap_or_else(timestamp_now)
+    }
+
+    // Logs the trades a book has matched since it was last asked, passing each on to surveillance
+    // and returning them as events while a trade webhook wants them
+    fn record_trades(&mut self, instrument_id: &InstrumentID) -> Vec<EngineMessage> {
+        let mut events = Vec::new();
+        let announce = self.config.as_ref().is_some_and(|config| config.snapshot().webhook_url.is_some());
+        let timestamp = self.now();
+        let spread = self.books.get(instrument_id).and_then(|book| book.spread.clone());
+        let anchor_price = spread.as_ref().and_then(|spread| self.books.get(&spread.anchor_leg().instrument_id)?.last_price);
+        let mut tape: Vec<ClientID> = self.tape_subscriptions
+            .iter()
+            .filter(|(symbol, _)| covers(symbol, instrument_id))
+            .flat_map(|(_, subscribers)| subscribers.iter().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        tape.sort_by_key(ClientID::to_string);
+        let Some(book) = self.books.get_mut(instrument_id) else { return events };
+        book.activity.trades += book.executions.len() as u64;
+        if let Some(last) = book.executions.last() {
+            let (fills, last_quantity) = (book.executions.len(), last.quantity);
+            book.last_price = Some(last.price);
+            book.update_liquidity(fills, last_quantity, &timestamp);
+        }
+        for execution in book.executions.drain(..) {
+            if let (Some(spread), Some(anchor_price)) = (&spread, anchor_price) {
+                book_spread_fill(&mut self.accounts, instrument_id, spread, anchor_price, &execution);
+            }
+            // A sale realizes the difference from what was paid; none is known for a spread or a short
+            if let Some(seller) = self.accounts.get_mut(&execution.seller.account_id) {
+                if let Some(&cost) = seller.average_cost.get(instrument_id) {
+                    seller.daily_pnl += (execution.price - cost) * execution.quantity as f64;
                 }
             }
-            EngineMessage::CancelOrder {
-                sending_time,
-                receiving_time,
-                order_id,
-                client_id,
-                ..
-            } => {
-                // Extract sending_time and receiving_time at the beginning of the branch (future logic)
-                let _sending_time = sending_time;
-                let _receiving_time = receiving_time;
-                for (_instrument, book) in &mut self.books {
-                    let removed = book.remove_order(order_id, &mut self.accounts);
-                    if removed {
-                        return Some(EngineMessage::OrderCancelled {
-                            order_id,
-                            client_id: client_id.clone(),
-                        });
-                    }
+            if self.trade_log.len() >= self.trade_log_retention {
+                self.trade_log.pop_front();
+            }
+            let trade = TradeRecord {
+                trade_match_id: execution.trade_match_id,
+                instrument_id: instrument_id.clone(),
+                price: execution.price,
+                quantity: execution.quantity,
+                timestamp: timestamp.clone(),
+                buyer: execution.buyer,
+                seller: execution.seller,
+            };
+            if let Some(surveillance) = &self.surveillance {
+                let _ = surveillance.send(SurveillanceEvent::Trade(Box::new(trade.clone())));
+            }
+            if announce {
+                events.push(EngineMessage::TradeExecuted { trade: trade.clone() });
+            }
+            events.extend(tape.iter().map(|client_id| EngineMessage::LastSaleTape {
+                client_id: client_id.clone(),
+                instrument_id: instrument_id.clone(),
+                price: trade.price,
+                quantity: trade.quantity,
+                side: execution.aggressor,
+                timestamp: trade.timestamp.clone(),
+                trade_id: trade.trade_match_id,
+            }));
+            self.trade_log.push_back(trade);
+        }
+        events
+    }
+
+    // Read for each order, as the price band is, so a reloaded limit holds from the next
+    fn max_executions(&self) -> usize {
+        self.config.as_ref().map_or(MAX_EXECUTIONS_PER_ORDER, |config| config.snapshot().max_executions())
+    }
+
+    // Passes an order's entry or cancellation on to surveillance, if anything is watching
+    fn surveil(&self, event: SurveillanceEvent) {
+        if let Some(surveillance) = &self.surveillance {
+            let _ = surveillance.send(event);
+        }
+    }
+
+    pub fn with_default_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.default_time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_ticker_map(mut self, ticker_map: TickerMap) -> Self {
+        self.ticker_map = ticker_map;
+        self
+    }
+
+    pub fn with_segments(mut self, segments: MarketSegments) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    pub fn with_account_authorizations(mut self, account_authorizations: AccountAuthorizations) -> Self {
+        self.account_authorizations = account_authorizations;
+        self
+    }
+
+    pub fn with_finished_order_retention(mut self, finished_order_retention: usize) -> Self {
+        self.finished_order_retention = finished_order_retention;
+        self
+    }
+
+    pub fn with_max_session_notional(mut self, max_session_notional: f64) -> Self {
+        self.max_session_notional = max_session_notional;
+        self
+    }
+
+    pub fn with_trade_log_retention(mut self, trade_log_retention: usize) -> Self {
+        self.trade_log_retention = trade_log_retention;
+        self
+    }
+
+    pub fn with_config(mut self, config: Arc<LiveConfig>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // Shares `malformed_log` with the sessions that write it, for the rejection log to read
+    pub fn with_malformed_log(mut self, malformed_log: Arc<MalformedLog>) -> Self {
+        self.malformed_log = malformed_log;
+        self
+    }
+
+    pub fn with_surveillance(mut self, surveillance: UnboundedSender<SurveillanceEvent>) -> Self {
+        self.surveillance = Some(surveillance);
+        self
+    }
+
+    pub fn with_overload(mut self, overload: Arc<OverloadGuard>) -> Self {
+        self.overload = Some(overload);
+        self
+    }
+
+    // The logged trades a query asks for, oldest first, a page at a time
+    pub fn trade_history(&self, query: &TradeQuery) -> HistoryPage<TradeRecord, u64> {
+        let (from, to) = (timestamp_key(&query.from), timestamp_key(&query.to));
+        let trades = self.trade_log
+            .iter()
+            .filter(|trade| query.instrument_id.as_ref().is_none_or(|instrument_id| trade.instrument_id == *instrument_id))
+            .filter(|trade| query.account_id.as_ref().is_none_or(|account_id| trade.buyer.account_id == *account_id || trade.seller.account_id == *account_id))
+            .filter(|trade| (from..=to).contains(&timestamp_key(&trade.timestamp)))
+            .filter(|trade| query.after.is_none_or(|after| trade.trade_match_id > after))
+            .cloned();
+        paginate(trades, query.limit, |trade| trade.trade_match_id)
+    }
+
+    // Every step of an order's life after the `after`th, a page at a time. None if the exchange
+    // has never seen the order or has since forgotten it.
+    pub fn order_history(&self, order_id: OrderID, after: Option<usize>, limit: Option<usize>) -> Option<HistoryPage<OrderEvent, usize>> {
+        let history = self.order_histories.get(&order_id)?;
+        let events = history
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|(step, _)| after.is_none_or(|after| *step > after));
+        let page = paginate(events, limit, |(step, _)| *step);
+        Some(HistoryPage { entries: page.entries.into_iter().map(|(_, event)| event).collect(), next: page.next })
+    }
+
+    // The instruments an admin operation acts on, in instrument order
+    fn instruments_in(&self, scope: &InstrumentScope) -> Vec<InstrumentID> {
+        let mut instrument_ids: Vec<InstrumentID> = self.books
+            .iter()
+            .filter(|(instrument_id, book)| match scope {
+                InstrumentScope::Instrument(scoped) => *instrument_id == scoped,
+                InstrumentScope::Segment(segment) => book.segment.as_ref() == Some(segment),
+                InstrumentScope::All => true,
+            })
+            .map(|(instrument_id, _)| instrument_id.clone())
+            .collect();
+        instrument_ids.sort();
+        instrument_ids
+    }
+
+    // The state that survives a restart: accounts, books and their GTC/GTD orders, and the rejection log.
+    // Day orders belong to the session that placed them, so they are refunded and dropped.
+    pub fn persistent_state(&self) -> Exchange {
+        let mut state = self.clone();
+        let mut dropped = Vec::new();
+        for book in state.books.values_mut() {
+            // The state is kept apart from the running books, specs included
+            book.spec = book.spec.detached();
+            book.expire_orders(|order| order.time_in_force == TimeInForce::Day, &mut state.accounts, &mut dropped);
+        }
+        state.forget_finished_orders(&dropped);
+        state
+    }
+
+    // Writes what persistent_state keeps to `path`, for with_saved_state to restart from
+    pub fn save_state(&self, path: &Path) -> Result<(), String> {
+        let state = self.persistent_state();
+        let mut instrument_ids: Vec<&InstrumentID> = state.books.keys().collect();
+        instrument_ids.sort();
+        let saved = SavedState {
+            order_counter: state.order_counter,
+            trade_match_counter: state.trade_match_counter,
+            instruments: instrument_ids.iter().map(|instrument_id| {
+                let book = &state.books[*instrument_id];
+                SavedInstrument {
+                    instrument_id: (*instrument_id).clone(),
+                    spec: book.spec.read().clone(),
+                    segment: book.segment.clone(),
+                    spread: book.spread.clone(),
                 }
-                Some(EngineMessage::OrderRejected {
-                    reason: "Order not found".to_string(),
-                    client_id: client_id.clone(),
-                })
+            }).collect(),
+            orders: instrument_ids.iter().flat_map(|instrument_id| state.books[*instrument_id].saved_orders()).cloned().collect(),
+            accounts: state.accounts,
+            account_owners: state.account_owners,
+            rejection_log: state.rejection_log,
+        };
+        let json = serde_json::to_string(&saved).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Could not save the exchange state to {}: {}", path.display(), e))
+    }
+
+    // Restarts from what save_state left at `path`: its instruments listed again, its accounts
+    // as they stood, holding for their orders already, and the orders bulk inserted back onto
+    // their books. No file there is a first start, with nothing to recover.
+    pub fn with_saved_state(mut self, path: &Path) -> Result<Self, String> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(self),
+            Err(e) => return Err(format!("Could not read the exchange state in {}: {}", path.display(), e)),
+        };
+        let saved: SavedState = serde_json::from_str(&json).map_err(|e| format!("Could not read the exchange state in {}: {}", path.display(), e))?;
+        for instrument in saved.instruments {
+            let book = OrderBook { segment: instrument.segment, spread: instrument.spread, ..OrderBook::new(instrument.spec) };
+            self.books.insert(instrument.instrument_id, book);
+        }
+        self.order_counter = saved.order_counter;
+        self.trade_match_counter = saved.trade_match_counter;
+        self.accounts = saved.accounts;
+        self.account_owners = saved.account_owners;
+        self.rejection_log = saved.rejection_log;
+        self.begin_recovery();
+        let recovered = self.bulk_insert(saved.orders);
+        self.finish_recovery();
+        recovered.map_err(|reason| format!("Could not recover the exchange state in {}: {}", path.display(), reason))?;
+        Ok(self)
+    }
+
+    // Stops taking orders, cancels and amends so the books can be rebuilt with bulk_insert
+    pub fn begin_recovery(&mut self) {
+        self.recovery_mode = true;
+    }
+
+    pub fn finish_recovery(&mut self) {
+        self.recovery_mode = false;
+    }
+
+    // Rests `orders` on their books as they stood, in the time priority they are given in,
+    // without matching them, checking their risk or taking their cash again: the accounts
+    // are recovered as they were, holding for these orders already. Untriggered stops are
+    // parked again rather than rested. Only open in recovery mode, and nothing is inserted if
+    // any order cannot be.
+    pub(crate) fn bulk_insert(&mut self, orders: Vec<Order>) -> Result<(), String> {
+        if !self.recovery_mode {
+            return Err("Bulk insert is only open in recovery mode".to_string());
+        }
+        let mut order_ids = HashSet::new();
+        for order in &orders {
+            let refusal = if order.price.is_none() {
+                Some("has no price")
+            } else if !self.books.contains_key(&order.instrument_id) {
+                Some("is for an unknown instrument")
+            } else if self.order_instruments.contains_key(&order.order_id) || !order_ids.insert(order.order_id) {
+                Some("is already on a book")
+            } else {
+                None
+            };
+            if let Some(refusal) = refusal {
+                return Err(format!("Order {} {}", order.order_id, refusal));
             }
-            EngineMessage::AmendOrder {
-                client_id,
-                ..
-            } => {
-                // Amend logic not implemented yet
-                Some(EngineMessage::LogEvent {
-                    client_id: Some(client_id),
-                    message: "Amend not yet implemented".to_string(),
-                })
+        }
+        for order in orders {
+            let order_id = order.order_id;
+            self.order_counter = self.order_counter.max(order_id + 1);
+            self.order_instruments.insert(order_id, order.instrument_id.clone());
+            self.order_owners.insert(order_id, order.sender_id.clone());
+            self.order_statuses.entry(order_id).or_insert(OrdStatus::New);
+            self.account_owners.entry(order.account_id.clone()).or_insert_with(|| order.sender_id.clone());
+            let book = self.books.get_mut(&order.instrument_id).unwrap();
+            if is_stop(&order) {
+                book.park_stop(order);
+            } else if order.good_for_auction && book.is_open() {
+                book.park_for_auction(order);
+            } else {
+                book.place(order);
             }
-            EngineMessage::AdvanceTime { client_id, .. } => {
+        }
+        Ok(())
+    }
 
-                // AdvanceTime logic not implemented yet
-                Some(EngineMessage::LogEvent {
-                    client_id: Some(client_id),
-                    message: "AdvanceTime not yet implemented".to_string(),
-                })
+    // The statements of `account_id`, or of every account, for `session` so far, in account order
+    fn statements(&self, account_id: Option<&AccountID>, session: &str) -> Vec<AccountStatement> {
+        let mut account_ids: Vec<&AccountID> = self.accounts.keys().filter(|id| account_id.is_none_or(|wanted| *id == wanted)).collect();
+        account_ids.sort();
+        let marks = |instrument_id: &InstrumentID| self.books.get(instrument_id).and_then(|book| book.last_price);
+        account_ids.into_iter().map(|id| self.accounts[id].statement(id, session, marks)).collect()
+    }
+
+    // Closes every account's statement for `session`, each starting afresh from where it closed
+    fn close_statements(&mut self, session: &str) -> Vec<EngineMessage> {
+        let statements = self.statements(None, session);
+        for account in self.accounts.values_mut() {
+            account.open_statement();
+        }
+        if statements.is_empty() {
+            return Vec::new();
+        }
+        vec![EngineMessage::AccountStatements { client_id: None, statements }]
+    }
+
+    // Ends the trading day: every Day order on the books in scope expires
+    fn roll_session(&mut self, scope: &InstrumentScope) -> Vec<EngineMessage> {
+        let mut events = Vec::new();
+        for instrument_id in self.instruments_in(scope) {
+            let book = self.books.get_mut(&instrument_id).unwrap();
+            book.expire_orders(|order| order.time_in_force == TimeInForce::Day, &mut self.accounts, &mut events);
+        }
+        events
+    }
+
+    // Opens every warming-up book whose open time `now` has reached, trading out whatever
+    // crossed during warm-up
+    fn open_warmed_up_books(&mut self, now: &Timestamp) -> Vec<EngineMessage> {
+        let now = timestamp_key(now);
+        let mut instrument_ids: Vec<InstrumentID> = self.books
+            .iter()
+            .filter(|(_, book)| matches!(&book.phase, InstrumentPhase::WarmUp { opens_at } if timestamp_key(opens_at) <= now))
+            .map(|(instrument_id, _)| instrument_id.clone())
+            .collect();
+        instrument_ids.sort();
+        let mut events = Vec::new();
+        for instrument_id in instrument_ids {
+            self.books.get_mut(&instrument_id).unwrap().phase = InstrumentPhase::Open;
+            events.extend(self.uncross(&instrument_id));
+        }
+        events
+    }
+
+    // Trades out a book that crossed while it wasn't matching, now that it is open, as its
+    // uncross policy says: by auction first if it calls for one, then by its crossing bids
+    // running through matching again, best first and in time priority within a level, as if
+    // they had arrived now
+    fn uncross(&mut self, instrument_id: &InstrumentID) -> Vec<EngineMessage> {
+        let max_executions = self.max_executions();
+        let book = self.books.get_mut(instrument_id).unwrap();
+        book.max_executions = max_executions;
+        if !book.is_open() {
+            return Vec::new();
+        }
+        // An uncross is the auction good-for-auction orders wait for; whatever of them it
+        // leaves goes back off the book to wait for the next
+        book.unpark_auction_orders();
+        if !book.is_crossed() {
+            book.park_auction_orders();
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if book.spec.read().uncross_policy == UncrossPolicy::Auction {
+            book.auction(&mut self.accounts, &mut self.trade_match_counter, &mut events);
+        }
+        // All taken off first, so the bids still waiting never leave the book crossed
+        let (bids, _) = book.depth_orders(0);
+        let crossing: Vec<OrderID> = bids.into_iter().filter(|&(_, price, _)| book.crosses(Side::Buy, price)).map(|(order_id, ..)| order_id).collect();
+        let bids: Vec<Order> = crossing.into_iter().filter_map(|order_id| book.take_order(order_id)).collect();
+        for order in bids {
+            let book = self.books.get_mut(instrument_id).unwrap();
+            match book.match_order(order.clone(), &mut self.accounts, &mut self.trade_match_counter) {
+                Ok(fills) => events.extend(fills),
+                // Refunded already, as a rejected arrival would have been
+                Err(reason) => events.push(order_cancelled(order.sender_id.clone(), &order, CancelReason::Other(reason))),
             }
-            _ => Some(EngineMessage::LogEvent {
-                client_id: None,
-                message: "Unsupported message received".to_string(),
-            }),
         }
+        self.books.get_mut(instrument_id).unwrap().park_auction_orders();
+        events.extend(self.record_trades(instrument_id));
+        events
+    }
+
+    // Cancels every order the client has resting on any book
+    fn mass_cancel_for_client(&mut self, client_id: &ClientID) -> Vec<EngineMessage> {
+        let mut events = Vec::new();
+        let mut instrument_ids: Vec<InstrumentID> = self.books.keys().cloned().collect();
+        instrument_ids.sort();
+        for instrument_id in instrument_ids {
+            let book = self.books.get_mut(&instrument_id).unwrap();
+            book.cancel_orders(|order| &order.sender_id == client_id, CancelReason::Disconnect, &mut self.accounts, &mut events);
+        }
+        events
     }
-}
\ No newline at end of file
+
+    // A position report for each account the client opened, in account order
+    fn position_reports_for_client(&self, client_id: &ClientID) -> Vec<EngineMessage> {
+        let mut account_ids: Vec<&AccountID> = self.account_owners
+            .iter()
+            .filter(|(_, owner)| *owner == client_id)
+            .map(|(account_id, _)| account_id)
+            .collect();
+        account_ids.sort();
+        account_ids
+            .into_iter()
+            .filter_map(|account_id| {
+                let account = self.accounts.get(account_id)?;
+                Some(position_report(client_id.clone(), account_id.clone(), account))
+            })
+            .collect()
+    }
+
+    fn expire_good_till_date_orders(&mut self, now: &Timestamp) -> Vec<EngineMessage> {
+        let now = timestamp_key(now);
+        let mut events = Vec::new();
+        for book in self.books.values_mut() {
+            book.expire_orders(
+                |order| order.time_in_force == TimeInForce::GoodTillDate
+                    && order.expire_time.as_ref().is_some_and(|expire_time| timestamp_key(expire_time) <= now),
+                &mut self.accounts,
+                &mut events,
+            );
+        }
+        events
+    }
+
+    // Applies, in effective-time order, every queued corporate action that simulated time has reached
+    fn apply_due_corporate_actions(&mut self) -> Vec<EngineMessage> {
+        let Some(now) = self.simulated_time.as_ref().map(timestamp_key) else {
+            return Vec::new();
+        };
+        self.pending_corporate_actions.sort_by_key(|(effective_time, ..)| timestamp_key(effective_time));
+        let due = self.pending_corporate_actions
+            .iter()
+            .take_while(|(effective_time, ..)| timestamp_key(effective_time) <= now)
+            .count();
+        let mut events = Vec::new();
+        for (_, instrument_id, action) in self.pending_corporate_actions.drain(..due).collect::<Vec<_>>() {
+            self.apply_corporate_action(&instrument_id, action, &mut events);
+        }
+        events
+    }
+
+    // Handles, earliest first, every scheduled message due by `now`
+    fn fire_scheduled_messages(&mut self, now: &Timestamp) -> Vec<EngineMessage> {
+        let now = timestamp_key(now);
+        let mut events = Vec::new();
+        while let Some(due) = self.scheduled_messages.first_entry().filter(|entry| *entry.key() <= now) {
+            for message in due.remove() {
+                events.extend(self.dispatch_audited(message));
+            }
+        }
+        events
+    }
+
+    fn apply_corporate_action(&mut self, instrument_id: &InstrumentID, action: CorporateAction, events: &mut Vec<EngineMessage>) {
+        if let CorporateAction::Split { numerator, denominator } = action {
+            if let Some(book) = self.books.get_mut(instrument_id) {
+                book.apply_split(numerator, denominator, &mut self.accounts, events);
+            }
+        }
+
+        let mut holders: Vec<AccountID> = self.accounts
+            .iter()
+            .filter(|(_, account)| account.positions.get(instrument_id).is_some_and(|&held| held > 0))
+            .map(|(account_id, _)| account_id.clone())
+            .collect();
+        holders.sort();
+        for account_id in holders {
+            let account = self.accounts.get_mut(&account_id).unwrap();
+            let held = account.positions[instrument_id];
+            let cash_adjustment = match action {
+                CorporateAction::Split { numerator, denominator } => {
+                    let cost = account.average_cost.get(instrument_id).copied().unwrap_or(Price::from(0.0));
+                    let split_cost = cost * denominator as f64 / numerator as f64;
+                    // Fractional shares are paid out in cash at the adjusted cost
+                    let fraction = (held * numerator % denominator) as f64 / denominator as f64;
+                    account.positions.insert(instrument_id.clone(), held * numerator / denominator);
+                    account.average_cost.insert(instrument_id.clone(), split_cost);
+                    split_cost * fraction
+                }
+                CorporateAction::Dividend { amount } => amount * held as f64,
+            };
+            account.post(StatementEntry {
+                kind: EntryKind::Adjustment,
+                order_id: None,
+                instrument_id: instrument_id.clone(),
+                quantity: held,
+                price: None,
+                amount: cash_adjustment,
+                balance: account.cash,
+            });
+            if let Some(owner) = self.account_owners.get(&account_id) {
+                events.push(EngineMessage::CorporateActionApplied {
+                    client_id: owner.clone(),
+                    account_id: account_id.clone(),
+                    instrument_id: instrument_id.clone(),
+                    action,
+                    position: account.positions[instrument_id],
+                    cash_adjustment,
+                });
+            }
+        }
+    }
+
+    pub fn handle_message(&mut self, mut message: EngineMessage) -> Vec<EngineMessage> {
+        self.ticker_map.translate(&mut message);
+        self.count_activity(&message);
+        let (cause, cause_client_id) = (message.as_ref().to_string(), extract_client_id(&message));
+        let mut events = self.dispatch_audited(message);
+        events.extend(self.volatility_halts());
+        self.track_order_states(&mut events, &cause, cause_client_id.as_ref());
+        self.forget_finished_orders(&events);
+        events.extend(self.publish_book_updates());
+        events.extend(self.imbalance_alerts());
+        events.extend(self.liquidity_reports());
+        events.extend(self.daily_loss_alerts());
+        events
+    }
+
+    // Counts an order, cancel or amend against its book's activity, with how long it queued
+    // between its session and the engine
+    fn count_activity(&mut self, message: &EngineMessage) {
+        let (instrument_id, receiving_time) = match message {
+            EngineMessage::NewOrder { instrument_id, receiving_time, .. } => (Some(instrument_id), receiving_time),
+            EngineMessage::CancelOrder { client_id, order_id, orig_client_order_id, receiving_time, .. } => {
+                (self.order_instruments.get(&self.cancelled_order_id(client_id, *order_id, orig_client_order_id.as_ref())), receiving_time)
+            }
+            EngineMessage::AmendOrder { order_id, receiving_time, .. } => (self.order_instruments.get(order_id), receiving_time),
+            // A multileg order counts against each of its legs' books
+            EngineMessage::NewOrderMultileg { legs, receiving_time, .. } => {
+                for leg in legs {
+                    if let Some(book) = self.books.get_mut(&leg.instrument_id) {
+                        book.count_message(receiving_time);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Some(book) = instrument_id.and_then(|instrument_id| self.books.get_mut(instrument_id)) {
+            book.count_message(receiving_time);
+        }
+    }
+
+    // Closes the activity interval, reporting each book's to those of its subscribers that asked,
+    // book by book. The first only opens one, there being no telling how long the books have counted.
+    fn close_activity_interval(&mut self) -> Vec<EngineMessage> {
+        let now = self.now();
+        let since = self.activity_since.replace(now.clone());
+        let seconds = since.as_ref().map_or(0.0, |since| (timestamp_millis(&now) - timestamp_millis(since)) as f64 / 1000.0);
+        let mut instrument_ids: Vec<InstrumentID> = self.books.keys().cloned().collect();
+        instrument_ids.sort();
+        let mut reports = Vec::new();
+        for instrument_id in instrument_ids {
+            let book = self.books.get_mut(&instrument_id).unwrap();
+            let activity = book.close_activity_interval(seconds);
+            if since.is_none() {
+                book.last_activity = None;
+                continue;
+            }
+            let subscribers = self.book_subscribers.get(&instrument_id).into_iter().flatten();
+            for (client_id, _) in subscribers.filter(|(client_id, _)| self.activity_subscribers.contains(client_id)) {
+                reports.push(EngineMessage::ActivityReport {
+                    client_id: client_id.clone(),
+                    instrument_id: instrument_id.clone(),
+                    timestamp: now.clone(),
+                    activity,
+                });
+            }
+        }
+        reports
+    }
+
+    // Warns each account's owner once a day when its losses pass 80% of its max_daily_loss
+    fn daily_loss_alerts(&mut self) -> Vec<EngineMessage> {
+        let mut accounts: Vec<(&AccountID, &mut Bankroll)> = self.accounts.iter_mut().filter(|(_, account)| !account.loss_alerted && account.daily_loss_past(0.8)).collect();
+        accounts.sort_by_key(|(account_id, _)| *account_id);
+        let mut alerts = Vec::new();
+        for (account_id, account) in accounts {
+            account.loss_alerted = true;
+            if let Some(owner) = self.account_owners.get(account_id) {
+                alerts.push(EngineMessage::RiskAlert {
+                    client_id: owner.clone(),
+                    account_id: account_id.clone(),
+                    reason: "Approaching daily loss limit".to_string(),
+                });
+            }
+        }
+        alerts
+    }
+
+    // Sends each market-data subscriber of a book its new liquidity score once fills have changed it
+    fn liquidity_reports(&mut self) -> Vec<EngineMessage> {
+        let mut books: Vec<(&InstrumentID, &mut OrderBook)> = self.books.iter_mut().filter(|(_, book)| book.liquidity_unreported).collect();
+        books.sort_by_key(|(instrument_id, _)| *instrument_id);
+        let mut reports = Vec::new();
+        for (instrument_id, book) in books {
+            book.liquidity_unreported = false;
+            for (client_id, _) in self.book_subscribers.get(instrument_id).into_iter().flatten() {
+                reports.push(EngineMessage::LiquidityReport {
+                    client_id: client_id.clone(),
+                    instrument_id: instrument_id.clone(),
+                    score: book.liquidity,
+                });
+            }
+        }
+        reports
+    }
+
+    // Halts each book whose spread has blown out, for its halt_duration_secs, and resumes
+    // those whose halt has run its course, telling their market data and alert subscribers
+    fn volatility_halts(&mut self) -> Vec<EngineMessage> {
+        if self.recovery_mode {
+            return Vec::new();
+        }
+        let now = self.now();
+        let mut instrument_ids: Vec<InstrumentID> = self.books.keys().cloned().collect();
+        instrument_ids.sort();
+        let mut events = Vec::new();
+        for instrument_id in instrument_ids {
+            let book = self.books.get_mut(&instrument_id).unwrap();
+            let (halted, reason, resumes_at) = if book.volatility_halt_ends.as_ref().is_some_and(|ends| timestamp_key(ends) <= timestamp_key(&now)) {
+                book.end_volatility_halt();
+                book.halted = false;
+                events.extend(self.uncross(&instrument_id));
+                (false, "Volatility halt lifted".to_string(), None)
+            } else if let Some(spread) = book.spread_blowout() {
+                let reason = format!("Volatility halt: spread {:.4} past {} times the reference {:.4}", spread, book.spec.read().spread_halt_multiplier, book.reference_spread);
+                let resumes_at = timestamp_after(&now, book.spec.read().halt_duration_secs);
+                // As an admin's halt would, unless the instrument freezes its book for the resumption
+                if book.spec.read().halt_policy == HaltPolicy::CancelAll {
+                    book.cancel_orders(|_| true, CancelReason::TradingHalt, &mut self.accounts, &mut events);
+                }
+                book.halted = true;
+                book.volatility_halt_ends = Some(resumes_at.clone());
+                (true, reason, Some(resumes_at))
+            } else {
+                continue;
+            };
+            let mut subscribers: Vec<&ClientID> = self.book_subscribers.get(&instrument_id).into_iter().flatten().map(|(client_id, _)| client_id).collect();
+            subscribers.extend(self.alert_subscribers.iter().filter(|client_id| !subscribers.contains(client_id)).collect::<Vec<_>>());
+            for client_id in subscribers {
+                events.push(EngineMessage::TradingHalt {
+                    client_id: client_id.clone(),
+                    instrument_id: instrument_id.clone(),
+                    halted,
+                    reason: reason.clone(),
+                    resumes_at: resumes_at.clone(),
+                    timestamp: now.clone(),
+                });
+            }
+        }
+        events
+    }
+
+    // Alerts each subscriber once when a book tips past its imbalance threshold, and again
+    // only after it has evened out or tipped the other way
+    fn imbalance_alerts(&mut self) -> Vec<EngineMessage> {
+        if self.alert_subscribers.is_empty() {
+            return Vec::new();
+        }
+        let timestamp = self.now();
+        let mut books: Vec<(&InstrumentID, &mut OrderBook)> = self.books.iter_mut().collect();
+        books.sort_by_key(|(instrument_id, _)| *instrument_id);
+        let mut alerts = Vec::new();
+        for (instrument_id, book) in books {
+            let imbalance = book.depth_imbalance();
+            let direction = imbalance.map(|(_, direction)| direction);
+            if direction == book.imbalance {
+                continue;
+            }
+            book.imbalance = direction;
+            let Some((ratio, direction)) = imbalance else { continue };
+            for client_id in &self.alert_subscribers {
+                alerts.push(EngineMessage::DepthImbalanceAlert {
+                    client_id: client_id.clone(),
+                    instrument_id: instrument_id.clone(),
+                    ratio,
+                    direction,
+                    timestamp: timestamp.clone(),
+                });
+            }
+        }
+        alerts
+    }
+
+    // Sends each subscriber what changed in its view of a book since the last message.
+    // Fills, cancels, expiries and splits all move levels, so every subscribed book is checked.
+    fn publish_book_updates(&mut self) -> Vec<EngineMessage> {
+        let mut instrument_ids: Vec<&InstrumentID> = self.book_subscribers.keys().collect();
+        instrument_ids.sort();
+        let mut updates = Vec::new();
+        for instrument_id in instrument_ids {
+            let Some(book) = self.books.get_mut(instrument_id) else { continue };
+            let subscribers = &self.book_subscribers[instrument_id];
+            let mut depths: Vec<u32> = subscribers.iter().map(|(_, depth)| *depth).collect();
+            depths.sort();
+            depths.dedup();
+            for depth in depths {
+                let changes = book.publish_view(depth);
+                if changes.is_empty() {
+                    continue;
+                }
+                for (client_id, _) in subscribers.iter().filter(|(_, subscribed)| *subscribed == depth) {
+                    updates.push(EngineMessage::BookUpdate {
+                        client_id: client_id.clone(),
+                        instrument_id: instrument_id.clone(),
+                        changes: changes.clone(),
+                    });
+                }
+            }
+        }
+        updates
+    }
+
+    // Brings the books the client is sent in line with the symbols it subscribed to, the latest
+    // to cover a book deciding its depth, and starts each newly covered book with its initial view
+    fn sync_subscriptions(&mut self, client_id: &ClientID) -> Vec<EngineMessage> {
+        let symbols = self.market_data_subscriptions.get(client_id).cloned().unwrap_or_default();
+        let mut instrument_ids: Vec<InstrumentID> = self.books.keys().cloned().collect();
+        instrument_ids.sort();
+        let mut updates = Vec::new();
+        for instrument_id in instrument_ids {
+            let wanted = symbols.iter().rev().find(|(symbol, _)| covers(symbol, &instrument_id)).map(|(_, depth)| *depth);
+            let current = self.book_subscribers
+                .get(&instrument_id)
+                .and_then(|subscribers| subscribers.iter().find(|(subscriber, _)| subscriber == client_id))
+                .map(|(_, depth)| *depth);
+            if wanted == current {
+                continue;
+            }
+            self.unsubscribe(&instrument_id, client_id);
+            if let Some(depth) = wanted {
+                let changes = self.books.get_mut(&instrument_id).unwrap().initial_view(depth);
+                self.book_subscribers.entry(instrument_id.clone()).or_default().push((client_id.clone(), depth));
+                updates.push(EngineMessage::BookUpdate { client_id: client_id.clone(), instrument_id, changes });
+            }
+        }
+        updates
+    }
+
+    // Takes the client off the tape for one symbol or wildcard it subscribed
+    fn untape(&mut self, symbol: &InstrumentID, client_id: &ClientID) {
+        if let Some(subscribers) = self.tape_subscriptions.get_mut(symbol) {
+            subscribers.remove(client_id);
+            if subscribers.is_empty() {
+                self.tape_subscriptions.remove(symbol);
+            }
+        }
+    }
+
+    // Ends the client's subscription to a book, returning false if it had none
+    fn unsubscribe(&mut self, instrument_id: &InstrumentID, client_id: &ClientID) -> bool {
+        let Some(subscribers) = self.book_subscribers.get_mut(instrument_id) else {
+            return false;
+        };
+        let Some(position) = subscribers.iter().position(|(subscriber, _)| subscriber == client_id) else {
+            return false;
+        };
+        let (_, depth) = subscribers.remove(position);
+        let depth_still_watched = subscribers.iter().any(|(_, subscribed)| *subscribed == depth);
+        if subscribers.is_empty() {
+            self.book_subscribers.remove(instrument_id);
+        }
+        if !depth_still_watched {
+            if let Some(book) = self.books.get_mut(instrument_id) {
+                book.published_views.remove(&depth);
+            }
+        }
+        true
+    }
+
+    // Moves each order through its OrdStatus as its reports go out, adding each report to its
+    // order's history along with `cause`, the request that led to it. Amendments are stamped
+    // with the current status, and any report the lifecycle does not allow is logged.
+    fn track_order_states(&mut self, events: &mut Vec<EngineMessage>, cause: &str, cause_client_id: Option<&ClientID>) {
+        let mut violations = Vec::new();
+        for event in events.iter_mut() {
+            violations.extend(self.track_order_state(event));
+            self.record_order_event(event, cause, cause_client_id);
+        }
+        events.extend(violations);
+    }
+
+    // The report's transition, if it makes one, or what to log if the lifecycle does not allow it
+    fn track_order_state(&mut self, event: &mut EngineMessage) -> Option<EngineMessage> {
+        if let EngineMessage::OrderFilled { client_id, order_id, filled_quantity, cumulative_quantity, price, instrument_id, arrival_bid, arrival_ask, average_price, summary, .. } = event {
+            if let Some(touch) = self.arrival_touches.get(order_id) {
+                (*arrival_bid, *arrival_ask) = (touch.bid, touch.ask);
+                self.execution_statistics.record(instrument_id, client_id, touch, *price, *filled_quantity);
+            }
+            let fills = self.order_fills.entry(*order_id).or_insert_with(|| FillSummary {
+                owner: client_id.clone(),
+                cumulative_quantity: 0,
+                notional: Price::from(0.0),
+            });
+            fills.cumulative_quantity += *filled_quantity;
+            fills.notional += *price * *filled_quantity as f64;
+            *cumulative_quantity = fills.cumulative_quantity;
+            *average_price = fills.notional / fills.cumulative_quantity as f64;
+            *summary = self.summary_sessions.contains(client_id);
+            *self.session_turnover.entry(client_id.clone()).or_default() += price.into_inner() * *filled_quantity as f64;
+        }
+        let (order_id, next) = match event {
+            EngineMessage::OrderAccepted { client_id, order_id, client_order_id, .. } => {
+                if let Some(client_order_id) = client_order_id {
+                    self.cl_ord_id_to_order_id.insert((client_id.clone(), client_order_id.clone()), *order_id);
+                }
+                (*order_id, OrdStatus::New)
+            }
+            EngineMessage::OrderFilled { order_id, remaining_quantity: 0, .. } => (*order_id, OrdStatus::Filled),
+            EngineMessage::OrderFilled { order_id, .. } => (*order_id, OrdStatus::PartiallyFilled),
+            EngineMessage::OrderCancelled { order_id, reason, cumulative_quantity, average_price, .. } => {
+                if let Some(fills) = self.order_fills.get(order_id) {
+                    *cumulative_quantity = fills.cumulative_quantity;
+                    *average_price = fills.notional / fills.cumulative_quantity as f64;
+                }
+                (*order_id, if *reason == CancelReason::Expired { OrdStatus::Expired } else { OrdStatus::Canceled })
+            }
+            EngineMessage::OrderExpired { order_id, .. } => (*order_id, OrdStatus::Expired),
+            EngineMessage::OrderAmended { order_id, status, cumulative_quantity, leaves_quantity, .. } => {
+                if let Some(fills) = self.order_fills.get(order_id) {
+                    *cumulative_quantity = fills.cumulative_quantity;
+                }
+                // Amended down to what had already filled, which finishes the order
+                if *leaves_quantity == 0 {
+                    *status = OrdStatus::Filled;
+                    (*order_id, OrdStatus::Filled)
+                } else {
+                    if let Some(current) = self.order_statuses.get(order_id) {
+                        *status = *current;
+                    }
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        match self.order_statuses.get(&order_id).copied() {
+            Some(current) if is_valid_transition(current, next) => {
+                self.order_statuses.insert(order_id, next);
+                None
+            }
+            current => Some(EngineMessage::LogEvent {
+                client_id: None,
+                message: format!("Order {} reported {:?} while {:?}", order_id, next, current),
+            }),
+        }
+    }
+
+    fn record_order_event(&mut self, event: &EngineMessage, cause: &str, cause_client_id: Option<&ClientID>) {
+        let order_id = match event {
+            EngineMessage::OrderAccepted { order_id, .. }
+            | EngineMessage::OrderFilled { order_id, .. }
+            | EngineMessage::OrderCancelled { order_id, .. }
+            | EngineMessage::OrderExpired { order_id, .. }
+            | EngineMessage::OrderAmended { order_id, .. }
+            | EngineMessage::CancelRejected { order_id, .. }
+            | EngineMessage::AmendRejected { order_id, .. } => *order_id,
+            _ => return,
+        };
+        // Orders the exchange has no status for, never accepted or long forgotten, have no history to add to
+        let Some(&status) = self.order_statuses.get(&order_id) else { return };
+        let timestamp = self.now();
+        self.order_histories.entry(order_id).or_default().push(OrderEvent {
+            timestamp,
+            status,
+            report: event.clone(),
+            cause: cause.to_string(),
+            cause_client_id: cause_client_id.cloned(),
+        });
+    }
+
+    fn resting_capacity_exhausted(&self, book: &OrderBook) -> bool {
+        (book.spec.read().max_resting_orders != 0 && book.order_index.len() >= book.spec.read().max_resting_orders)
+            || (self.max_resting_orders != 0 && self.order_instruments.len() >= self.max_resting_orders)
+    }
+
+    // Warns once each time a book or the whole exchange fills up to RESTING_ORDER_ALERT_PERCENT of its cap
+    fn resting_capacity_alerts(&self, instrument_id: &InstrumentID) -> Vec<EngineMessage> {
+        let reached_alert = |count: usize, cap: usize| cap != 0 && count == (cap * RESTING_ORDER_ALERT_PERCENT / 100).max(1);
+        let mut alerts = Vec::new();
+        let book = &self.books[instrument_id];
+        if reached_alert(book.order_index.len(), book.spec.read().max_resting_orders) {
+            alerts.push(EngineMessage::LogEvent {
+                client_id: None,
+                message: format!("{} resting orders on {} of {} allowed", book.order_index.len(), instrument_id, book.spec.read().max_resting_orders),
+            });
+        }
+        if reached_alert(self.order_instruments.len(), self.max_resting_orders) {
+            alerts.push(EngineMessage::LogEvent {
+                client_id: None,
+                message: format!("{} resting orders on the exchange of {} allowed", self.order_instruments.len(), self.max_resting_orders),
+            });
+        }
+        alerts
+    }
+
+    // Drops global index entries for orders that left their book
+    fn forget_finished_orders(&mut self, events: &[EngineMessage]) {
+        for event in events {
+            match event {
+                EngineMessage::OrderFilled { order_id, remaining_quantity: 0, .. }
+                | EngineMessage::OrderAmended { order_id, leaves_quantity: 0, .. }
+                | EngineMessage::OrderCancelled { order_id, .. }
+                | EngineMessage::OrderExpired { order_id, .. } => {
+                    self.order_instruments.remove(order_id);
+                    self.arrival_touches.remove(order_id);
+                    self.finished_orders.push_back(*order_id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // One pass of compaction, doing at most about `budget` units of work: forgets the oldest
+    // finished orders past retention, then works through the books in instrument order from
+    // where the last pass stopped, then shrinks the exchange's own maps
+    fn compact(&mut self, budget: usize) -> CompactionPass {
+        let mut reclaimed = CompactionMetrics { passes: 1, ..CompactionMetrics::default() };
+        let mut work = 0;
+        while self.finished_orders.len() > self.finished_order_retention && work < budget {
+            let order_id = self.finished_orders.pop_front().unwrap();
+            self.order_statuses.remove(&order_id);
+            self.order_owners.remove(&order_id);
+            self.order_fills.remove(&order_id);
+            if let Some(history) = self.order_histories.remove(&order_id) {
+                // Its acceptance says which ClOrdID to forget, unless a later order took it since
+                if let Some(EngineMessage::OrderAccepted { client_id, client_order_id: Some(client_order_id), .. }) = history.first().map(|event| &event.report) {
+                    let key = (client_id.clone(), client_order_id.clone());
+                    if self.cl_ord_id_to_order_id.get(&key) == Some(&order_id) {
+                        self.cl_ord_id_to_order_id.remove(&key);
+                    }
+                }
+                reclaimed.bytes_reclaimed += (history.capacity() * std::mem::size_of::<OrderEvent>()) as u64;
+            }
+            reclaimed.finished_orders_forgotten += 1;
+            work += 1;
+        }
+
+        let mut instrument_ids: Vec<InstrumentID> = self.books
+            .keys()
+            .filter(|instrument_id| self.compaction_cursor.as_ref().is_none_or(|cursor| *instrument_id > cursor))
+            .cloned()
+            .collect();
+        instrument_ids.sort();
+        let mut books_left = instrument_ids.len();
+        for instrument_id in instrument_ids {
+            if work >= budget {
+                break;
+            }
+            work += self.books.get_mut(&instrument_id).unwrap().compact(&mut reclaimed);
+            self.compaction_cursor = Some(instrument_id);
+            books_left -= 1;
+        }
+
+        let complete = work < budget && books_left == 0 && self.finished_orders.len() <= self.finished_order_retention;
+        if complete {
+            self.compaction_cursor = None;
+            reclaimed.shrunk(shrink_queue(&mut self.finished_orders));
+            reclaimed.shrunk(shrink_map(&mut self.order_instruments));
+            reclaimed.shrunk(shrink_map(&mut self.order_statuses));
+            reclaimed.shrunk(shrink_map(&mut self.order_owners));
+            reclaimed.shrunk(shrink_map(&mut self.order_fills));
+            reclaimed.shrunk(shrink_map(&mut self.cl_ord_id_to_order_id));
+            reclaimed.shrunk(shrink_map(&mut self.order_histories));
+            reclaimed.shrunk(shrink_map(&mut self.arrival_touches));
+        }
+        CompactionPass { reclaimed, complete }
+    }
+
+    // Takes in a new order that has passed its checks and holds its cash: acknowledges it,
+    // then trades, rests or parks it, answering with everything that followed
+    fn accept_order(&mut self, order: Order) -> Vec<EngineMessage> {
+        let (order_id, client_id, instrument_id, client_order_id) = (order.order_id, order.sender_id.clone(), order.instrument_id.clone(), order.sent_client_order_id());
+        let (side, order_type, time_in_force, price, quantity) = (order.side, order.order_type, order.time_in_force, order.price, order.quantity);
+        self.order_statuses.insert(order_id, OrdStatus::PendingNew);
+
+        let received = timestamp_key(&order.receive_timestamp);
+        let entered = SurveillanceEvent::OrderEntered {
+            order_id,
+            account_id: order.account_id.clone(),
+            received: order.receive_timestamp.clone(),
+        };
+        let max_executions = self.max_executions();
+        let book = self.books.get_mut(&instrument_id).unwrap();
+        book.max_executions = max_executions;
+        let touch = ArrivalTouch {
+            side,
+            bid: book.bids.keys().next_back().copied(),
+            ask: book.asks.keys().next().copied(),
+        };
+        let fills = match book.enter_order(order, &mut self.accounts, &mut self.trade_match_counter) {
+            Ok(fills) => fills,
+            Err(reason) => {
+                self.order_statuses.remove(&order_id);
+                return vec![EngineMessage::OrderRejected { reason, client_id, code: None }];
+            }
+        };
+        self.arrival_touches.insert(order_id, touch);
+
+        self.order_owners.insert(order_id, client_id.clone());
+        self.recent_orders.push(OrderAuditEntry {
+            order_id,
+            side,
+            order_type,
+            time_in_force,
+            price,
+            quantity,
+            received,
+        });
+
+        // Acknowledge first so every fill or cancel for this order follows its acceptance
+        let mut responses = vec![EngineMessage::OrderAccepted {
+            client_id,
+            order_id,
+            client_order_id,
+            instrument_id: instrument_id.clone(),
+            side,
+            quantity,
+        }];
+        responses.extend(fills);
+        let rested = book.contains_order(order_id);
+        self.surveil(entered);
+        responses.extend(self.record_trades(&instrument_id));
+        if rested {
+            self.order_instruments.insert(order_id, instrument_id.clone());
+            responses.extend(self.resting_capacity_alerts(&instrument_id));
+        }
+        responses
+    }
+
+    // Phase one of a multileg order: checks each leg as a new order of its own, and that the book
+    // has enough to fill it now, then reserves for it. The first leg to fail rolls back the
+    // legs reserved before it, so the account is left as it was.
+    fn prepare_multileg(
+        &mut self,
+        times: (Timestamp, Timestamp),
+        client_id: &ClientID,
+        account_id: &AccountID,
+        client_order_id: Option<ClOrdID>,
+        legs: Vec<MultilegLeg>,
+    ) -> Result<(), String> {
+        if legs.len() < 2 {
+            return Err("Multileg order needs at least two legs".to_string());
+        }
+        let mut instruments = HashSet::new();
+        if !legs.iter().all(|leg| instruments.insert(&leg.instrument_id)) {
+            return Err("Multileg order names an instrument twice".to_string());
+        }
+        if let Some(reason) = self.account_refusal(account_id, client_id) {
+            return Err(reason.to_string());
+        }
+        self.account_owners.entry(account_id.clone()).or_insert_with(|| client_id.clone());
+        let account = self.accounts.entry(account_id.clone()).or_insert_with(|| Bankroll::new(Price::from(1000.0)));
+        if account.daily_loss_past(1.0) {
+            return Err("Daily loss limit reached".to_string());
+        }
+
+        self.pending_multileg = Some(PendingMultilegOrder { legs: Vec::new(), notional: 0.0 });
+        let first_order_id = self.order_counter;
+        let mut estimated_cost = 0.0;
+        for (number, leg) in legs.into_iter().enumerate() {
+            match self.reserve_leg(&times, client_id, account_id, client_order_id.clone(), leg) {
+                Ok(cost) => estimated_cost += cost,
+                Err(reason) => {
+                    self.roll_back_multileg(first_order_id);
+                    return Err(format!("Leg {}: {}", number + 1, reason));
+                }
+            }
+        }
+
+        // To the fat-finger and session limits the legs are one order
+        let notional = self.pending_multileg.as_ref().map_or(0.0, |pending| pending.notional);
+        let max_value = self.accounts[account_id].risk_limits.max_single_order_value;
+        let turnover = self.session_turnover.get(client_id).copied().unwrap_or(0.0);
+        let refusal = if max_value != 0.0 && notional > max_value {
+            Some("Single order value exceeds limit")
+        } else if self.max_session_notional != 0.0 && turnover + estimated_cost > self.max_session_notional {
+            Some("Session notional limit exceeded")
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            self.roll_back_multileg(first_order_id);
+            return Err(reason.to_string());
+        }
+        Ok(())
+    }
+
+    // Checks one leg and reserves for it, giving what filling it would cost
+    fn reserve_leg(
+        &mut self,
+        (sending_time, receiving_time): &(Timestamp, Timestamp),
+        client_id: &ClientID,
+        account_id: &AccountID,
+        client_order_id: Option<ClOrdID>,
+        leg: MultilegLeg,
+    ) -> Result<f64, &'static str> {
+        let book = self.books.get(&leg.instrument_id).ok_or("Unknown instrument")?;
+        let order_type = if leg.price.is_some() { OrdType::Limit } else { OrdType::Market };
+        if book.halted {
+            return Err("Instrument halted");
+        }
+        if book.spread.is_some() {
+            return Err("Spread instruments cannot be legs");
+        }
+        if !book.spec.read().takes(order_type) {
+            return Err("Order type not supported on this instrument");
+        }
+        // Nothing trades during warm-up, so no leg could fill
+        if book.warming_up() {
+            return Err("Instrument warming up");
+        }
+        if let Some(reason) = book.spec.read().increment_violation(leg.price, leg.quantity) {
+            return Err(reason);
+        }
+        let band = self.config.as_ref().map_or(0.0, |config| config.snapshot().price_band(&leg.instrument_id));
+        if let (Some(limit_price), Some(last_price)) = (leg.price, book.last_price) {
+            if band > 0.0 && (limit_price - last_price).into_inner().abs() > last_price.into_inner() * band / 100.0 {
+                return Err("Price outside band");
+            }
+        }
+        // A leg that could fill only in part would leave the others unhedged
+        if book.fillable(leg.side, leg.price, leg.quantity, self.max_executions()) < leg.quantity {
+            return Err("Insufficient liquidity");
+        }
+
+        let order = Order {
+            order_id: self.order_counter,
+            client_order_id: client_order_id.unwrap_or_default(),
+            send_timestamp: sending_time.clone(),
+            receive_timestamp: receiving_time.clone(),
+            price: leg.price,
+            quantity: leg.quantity,
+            side: leg.side,
+            order_type,
+            time_in_force: TimeInForce::ImmediateOrCancel,
+            expire_time: None,
+            exec_instruction: ExecInst::StayOnOfferSide,
+            instrument_id: leg.instrument_id.clone(),
+            account_id: account_id.clone(),
+            sender_id: client_id.clone(),
+            good_for_auction: false,
+        };
+        let account = &self.accounts[account_id];
+        if account.smp_action == SmpAction::RejectAggressor && book.crosses_own_order(&order) {
+            return Err("Would cross own resting order");
+        }
+        let total_cost = reserved_cash(&order);
+        let estimated_cost = match leg.price {
+            Some(_) => order_notional(&order).into_inner(),
+            None => {
+                let opposite = if leg.side == Side::Buy { &book.asks } else { &book.bids };
+                book.sweep_price(opposite, leg.side, leg.quantity).map_or(0.0, |average| average * leg.quantity as f64)
+            }
+        };
+        // What earlier legs reserved has already left the account's cash and positions
+        if account.cash < total_cost {
+            return Err("Insufficient funds");
+        }
+        if leg.side == Side::Sell && account.positions.get(&leg.instrument_id).copied().unwrap_or(0) < leg.quantity {
+            return Err("Insufficient position");
+        }
+
+        let account = self.accounts.get_mut(account_id).unwrap();
+        account.hold(&order, total_cost);
+        if leg.side == Side::Sell {
+            *account.positions.get_mut(&leg.instrument_id).unwrap() -= leg.quantity;
+        }
+        self.order_counter += 1;
+        let pending = self.pending_multileg.as_mut().unwrap();
+        pending.notional += order_notional(&order).into_inner();
+        pending.legs.push((leg.instrument_id, order));
+        Ok(estimated_cost)
+    }
+
+    // Hands every reservation of the pending multileg order back, latest first, along with the
+    // order IDs its legs were to have
+    fn roll_back_multileg(&mut self, first_order_id: OrderID) {
+        let Some(pending) = self.pending_multileg.take() else { return };
+        for (instrument_id, order) in pending.legs.iter().rev() {
+            let account = self.accounts.get_mut(&order.account_id).unwrap();
+            account.release(order, reserved_cash(order));
+            if order.side == Side::Sell {
+                *account.positions.entry(instrument_id.clone()).or_insert(0) += order.quantity;
+            }
+        }
+        self.order_counter = first_order_id;
+    }
+
+    // Phase two: trades every leg of the pending multileg order, each as an order of its own.
+    // Phase one saw each can fill in full, so none is left part done.
+    fn commit_multileg(&mut self) -> Vec<EngineMessage> {
+        let Some(pending) = self.pending_multileg.take() else { return Vec::new() };
+        let mut responses = Vec::new();
+        for (instrument_id, order) in pending.legs {
+            // The sale takes the position as it fills
+            if order.side == Side::Sell {
+                *self.accounts.get_mut(&order.account_id).unwrap().positions.entry(instrument_id).or_insert(0) += order.quantity;
+            }
+            responses.extend(self.accept_order(order));
+        }
+        responses
+    }
+
+    // An amend sets the order's total quantity, fills included (0 cancels it), its price or its TimeInForce.
+    // Cutting the quantity keeps the order's place in its queue; any other change sends it to
+    // the back, and a new price may trade at once. The cash held for it follows its leaves.
+    fn amend_order(
+        &mut self,
+        client_id: ClientID,
+        order_id: OrderID,
+        new_quantity: Option<Quantity>,
+        new_price: Option<Price>,
+        time_in_force: Option<TimeInForce>,
+    ) -> Vec<EngineMessage> {
+        let max_executions = self.max_executions();
+        let resting = self.order_instruments.get(&order_id).cloned().and_then(|instrument_id| {
+            let order = self.books.get(&instrument_id)?.order_index.get(&order_id)?;
+            Some((instrument_id, order.clone()))
+        });
+        let Some((instrument_id, current)) = resting else {
+            // Off the book already; its owner learns what became of it
+            if let Some(&status) = self.order_statuses.get(&order_id).filter(|_| self.order_owners.get(&order_id) == Some(&client_id)) {
+                return vec![amend_rejected(client_id, order_id, "Too late to amend", status)];
+            }
+            return vec![EngineMessage::OrderRejected {
+                reason: "Order not found".to_string(),
+                client_id,
+                code: None,
+            }];
+        };
+        if let Some(reason) = self.account_refusal(&current.account_id, &client_id) {
+            return vec![EngineMessage::OrderRejected {
+                reason: reason.to_string(),
+                client_id,
+                code: None,
+            }];
+        }
+        // Amending to nothing cancels the order
+        if new_quantity == Some(0) {
+            let book = self.books.get_mut(&instrument_id).unwrap();
+            if let Some(order) = book.remove_order(order_id, &mut self.accounts) {
+                return vec![order_cancelled(client_id, &order, CancelReason::ClientRequested)];
+            }
+        }
+        let status = self.order_statuses[&order_id];
+        let cumulative_quantity = self.order_fills.get(&order_id).map_or(0, |fills| fills.cumulative_quantity);
+        let total_quantity = new_quantity.unwrap_or(current.quantity + cumulative_quantity);
+        let price = new_price.unwrap_or(current.level());
+        if total_quantity == 0 || price <= Price::from(0.0) {
+            return vec![amend_rejected(client_id, order_id, "Invalid quantity or price", status)];
+        }
+
+        let mut amended = current.clone();
+        amended.price = Some(price);
+        // Asking for less than has filled amends the order to what has filled
+        amended.quantity = total_quantity.saturating_sub(cumulative_quantity);
+        amended.time_in_force = time_in_force.unwrap_or(current.time_in_force);
+        let shrinks_in_place = price == current.level() && amended.quantity <= current.quantity && amended.time_in_force == current.time_in_force;
+
+        let book = &self.books[&instrument_id];
+        if let Some(reason) = book.spec.read().increment_violation(new_price, total_quantity) {
+            return vec![amend_rejected(client_id, order_id, reason, status)];
+        }
+
+        let account = self.accounts.get_mut(&current.account_id).unwrap();
+        let max_value = account.risk_limits.max_single_order_value;
+        if max_value != 0.0 && price * total_quantity as f64 > Price::from(max_value) {
+            return vec![amend_rejected(client_id, order_id, "Single order value exceeds limit", status)];
+        }
+        let extra_cash = reserved_cash(&amended) - reserved_cash(&current);
+        if account.cash < extra_cash {
+            return vec![amend_rejected(client_id, order_id, "Insufficient funds", status)];
+        }
+        let book = self.books.get_mut(&instrument_id).unwrap();
+        if account.smp_action == SmpAction::RejectAggressor && amended.quantity > 0 && book.entry_crosses_own_order(&amended) {
+            return vec![amend_rejected(client_id, order_id, "Would cross own resting order", status)];
+        }
+        account.hold(&amended, extra_cash);
+
+        let mut responses = vec![EngineMessage::OrderAmended {
+            client_id,
+            order_id,
+            new_quantity: Some(total_quantity.max(cumulative_quantity)),
+            new_price: Some(price),
+            // The exchange stamps the tracked status and fills on the way out
+            status,
+            cumulative_quantity,
+            leaves_quantity: amended.quantity,
+        }];
+        if amended.quantity == 0 {
+            book.take_order(order_id);
+        } else if shrinks_in_place {
+            book.amend_in_place(amended);
+        } else if book.halted {
+            // Nothing trades until the halt lifts, even across the spread; resuming trades it out
+            book.take_order(order_id);
+            if is_stop(&amended) {
+                book.park_stop(amended);
+            } else {
+                book.rest_order(amended, &mut self.accounts, &mut responses);
+            }
+        } else {
+            book.take_order(order_id);
+            book.max_executions = max_executions;
+            let fills = book.enter_order(amended, &mut self.accounts, &mut self.trade_match_counter).expect("crossing own orders was checked above");
+            responses.extend(fills);
+            responses.extend(self.record_trades(&instrument_id));
+        }
+        responses
+    }
+
+    // The order a cancel names: its OrderID, or failing one the order its sender gave the
+    // OrigClOrdID, or 0, which is no order, if it gave none
+    fn cancelled_order_id(&self, client_id: &ClientID, order_id: OrderID, orig_client_order_id: Option<&ClOrdID>) -> OrderID {
+        match orig_client_order_id {
+            Some(client_order_id) if order_id == 0 => self.cl_ord_id_to_order_id.get(&(client_id.clone(), client_order_id.clone())).copied().unwrap_or(0),
+            _ => order_id,
+        }
+    }
+
+    // Why a client may not trade or configure an account, if it may not. Admins may manage any
+    // account. Otherwise the configured authorizations decide, or without them the account is
+    // its opener's, and anyone's to open until then.
+    fn account_refusal(&self, account_id: &AccountID, client_id: &ClientID) -> Option<&'static str> {
+        if self.admins.contains(client_id) {
+            return None;
+        }
+        if self.account_authorizations.enforced() {
+            return self.account_authorizations.refusal(client_id.comp_id(), account_id);
+        }
+        match self.account_owners.get(account_id) {
+            Some(owner) if owner != client_id => Some("Account not owned by client"),
+            _ => None,
+        }
+    }
+
+    // The account for its owner to configure, opened if this is the first it has been seen,
+    // just as a first order would. Err with why if the client may not configure it.
+    fn owned_account(&mut self, account_id: &AccountID, client_id: &ClientID) -> Result<&mut Bankroll, &'static str> {
+        if let Some(reason) = self.account_refusal(account_id, client_id) {
+            return Err(reason);
+        }
+        self.account_owners.entry(account_id.clone()).or_insert_with(|| client_id.clone());
+        Ok(self.accounts.entry(account_id.clone()).or_insert_with(|| Bankroll::new(Price::from(1000.0))))
+    }
+
+    // Dispatches `message`, logging each OrderRejected it gets. Simulated time's own events
+    // are left out: the scheduled messages it fires are logged as each is dispatched.
+    fn dispatch_audited(&mut self, message: EngineMessage) -> Vec<EngineMessage> {
+        let request = (!matches!(message, EngineMessage::AdvanceTime { .. })).then(|| RejectedRequest::of(&message));
+        let events = self.dispatch_message(message);
+        if let Some(request) = request {
+            let timestamp = self.now();
+            for event in &events {
+                if let EngineMessage::OrderRejected { client_id, reason, .. } = event {
+                    self.rejection_log.push(RejectionEntry {
+                        timestamp: timestamp.clone(),
+                        client_id: client_id.clone(),
+                        account_id: request.account_id.clone(),
+                        instrument_id: request.instrument_id.clone(),
+                        reason: reason.clone(),
+                        original_price: request.price,
+                        original_qty: request.quantity,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    fn dispatch_message(&mut self, message: EngineMessage) -> Vec<EngineMessage> {
+        if self.recovery_mode {
+            if let EngineMessage::NewOrder { client_id, .. }
+            | EngineMessage::NewOrderMultileg { client_id, .. }
+            | EngineMessage::CancelOrder { client_id, .. }
+            | EngineMessage::AmendOrder { client_id, .. }
+            | EngineMessage::InboundExecutionReport { client_id, .. } = &message
+            {
+                return vec![EngineMessage::OrderRejected {
+                    reason: "Exchange is recovering".to_string(),
+                    client_id: client_id.clone(),
+                    code: None,
+                }];
+            }
+        }
+        // Cancel-only: cancels and amends still go through, so clients can take risk off
+        if let EngineMessage::NewOrder { client_id, .. } | EngineMessage::NewOrderMultileg { client_id, .. } = &message {
+            if self.overload.as_ref().is_some_and(|overload| overload.cancel_only()) {
+                return vec![EngineMessage::OrderRejected {
+                    reason: SYSTEM_OVERLOADED.to_string(),
+                    client_id: client_id.clone(),
+                    code: None,
+                }];
+            }
+        }
+        match message {
+            EngineMessage::CreateInstrument { client_id, instrument_id, segment, spec, spread, .. } => {
+                // Extract sending_time and receiving_time if present (future logic)
+                let defaults = InstrumentSpec::default();
+                let base = match &segment {
+                    Some(segment) => match self.segments.get(segment) {
+                        Some(base) => base,
+                        None => {
+                            return vec![EngineMessage::OrderRejected {
+                                reason: "Unknown segment".to_string(),
+                                client_id,
+                                code: None,
+                            }];
+                        }
+                    },
+                    None => &defaults,
+                };
+                if let Some(spread) = &spread {
+                    // Legs are outright instruments already listed, so every fill has books to price off
+                    let unlisted = spread.legs.iter().any(|leg| self.books.get(&leg.instrument_id).is_none_or(|book| book.spread.is_some()));
+                    if let Some(reason) = spread.invalidity().or(unlisted.then_some("Unknown spread leg")) {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: reason.to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                }
+                let spec = spec.apply(base);
+                self.books.entry(instrument_id.clone()).or_insert_with(|| OrderBook { segment, spread, ..OrderBook::new(spec) });
+                // Clients whose wildcards, or symbols subscribed ahead of time, cover the new book
+                let mut subscribers: Vec<ClientID> = self.market_data_subscriptions
+                    .iter()
+                    .filter(|(_, symbols)| symbols.iter().any(|(symbol, _)| covers(symbol, &instrument_id)))
+                    .map(|(client_id, _)| client_id.clone())
+                    .collect();
+                subscribers.sort_by_key(ClientID::to_string);
+                subscribers.iter().flat_map(|client_id| self.sync_subscriptions(client_id)).collect()
+            }
+            EngineMessage::NewOrder {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+                client_order_id,
+                instrument_id,
+                order_type,
+                side,
+                quantity,
+                price,
+                time_in_force,
+                expire_time,
+                // The venue was Namespaces' to check; within one exchange the symbol is enough
+                exchange_code: _,
+                good_for_auction,
+                stipulations: _, // no stipulation changes how this exchange matches an order
+            } => {
+                let time_in_force = time_in_force.unwrap_or(self.default_time_in_force);
+                if time_in_force == TimeInForce::GoodTillDate && expire_time.is_none() {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "GoodTillDate order requires ExpireTime".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                // An auction order waits at its price, so it needs one
+                if good_for_auction && order_type != OrdType::Limit {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "GoodForAuction order must be a limit order".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                let Some(book) = self.books.get(&instrument_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                if book.halted {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Instrument halted".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                // A spread's fills are priced off its anchor leg's last trade, so it trades once there is one
+                if book.spread.as_ref().is_some_and(|spread| self.books[&spread.anchor_leg().instrument_id].last_price.is_none()) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Spread anchor leg has not traded".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                if !book.spec.read().takes(order_type) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Order type not supported on this instrument".to_string(),
+                        client_id,
+                        code: Some(OrdRejReason::UnsupportedOrderCharacteristic),
+                    }];
+                }
+                // Only orders that can rest at a price of their own wait for the open
+                let rests = !matches!(time_in_force, TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill);
+                if book.warming_up() && (order_type != OrdType::Limit || !rests) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Only resting limit orders are taken during warm-up".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                if let Some(reason) = book.spec.read().increment_violation(price, quantity) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                // Refuse up front an order that could only rest on a level the book has no room for
+                if let Some(limit_price) = price.filter(|&limit_price| rests && !book.crosses(side, limit_price)) {
+                    if !book.admits_level(side, limit_price) {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: "Price level limit reached".to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                    if !book.within_window(side, limit_price) {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: "Price too far from the touch".to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                }
+                // Read from the settings in force for each order, so a reloaded band holds the next one
+                let band = self.config.as_ref().map_or(0.0, |config| config.snapshot().price_band(&instrument_id));
+                if let (Some(limit_price), Some(last_price)) = (price, book.last_price) {
+                    if band > 0.0 && (limit_price - last_price).into_inner().abs() > last_price.into_inner() * band / 100.0 {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: "Price outside band".to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                }
+
+                // A marketable order removes at least one resting order for any remainder it leaves,
+                // so only orders that would rest outright, or stops that would park, can push a book past its cap
+                let parks = matches!(order_type, OrdType::Stop | OrdType::StopLimit) && !book.stop_triggers(side, price);
+                if rests && (parks || !book.is_marketable(side, price)) && self.resting_capacity_exhausted(book) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Book full".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                // What the order is worth for the risk limits; what it holds is reserved_cash
+                let total_cost = price.map_or(AccountBalance::from(0.0), |price| price * quantity as f64);
+                // As if the order fills in full; a market order at what sweeping the book for it would cost now
+                let estimated_cost = match price {
+                    Some(_) => total_cost.into_inner(),
+                    None => {
+                        let opposite = if side == Side::Buy { &book.asks } else { &book.bids };
+                        book.sweep_price(opposite, side, quantity).map_or(0.0, |average| average * quantity as f64)
+                    }
+                };
+
+                if let Some(reason) = self.account_refusal(&account_id, &client_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                // The first order for an account opens it, for whoever sent it
+                self.account_owners.entry(account_id.clone()).or_insert_with(|| client_id.clone());
+                let account = self.accounts.entry(account_id.clone()).or_insert_with(|| Bankroll::new(Price::from(1000.0)));
+
+                if account.daily_loss_past(1.0) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Daily loss limit reached".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                // Fat-finger check, made before and regardless of the cash check
+                let max_value = account.risk_limits.max_single_order_value;
+                if max_value != 0.0 && total_cost > Price::from(max_value) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Single order value exceeds limit".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                let turnover = self.session_turnover.get(&client_id).copied().unwrap_or(0.0);
+                if self.max_session_notional != 0.0 && turnover + estimated_cost > self.max_session_notional {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Session notional limit exceeded".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                if side == Side::Buy && account.cash < total_cost {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Insufficient funds".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+
+                let order_id = self.order_counter;
+                self.order_counter += 1;
+
+                let order = Order {
+                    order_id,
+                    client_order_id: client_order_id.unwrap_or("".to_string()),
+                    send_timestamp: sending_time,
+                    receive_timestamp: receiving_time,
+                    price,
+                    quantity,
+                    side,
+                    order_type,
+                    time_in_force,
+                    expire_time,
+                    exec_instruction: ExecInst::StayOnOfferSide,
+                    instrument_id: instrument_id.clone(),
+                    account_id,
+                    sender_id: client_id.clone(),
+                    good_for_auction,
+                };
+                self.accounts.get_mut(&order.account_id).unwrap().hold(&order, reserved_cash(&order));
+                self.accept_order(order)
+            }
+            EngineMessage::NewOrderMultileg { sending_time, receiving_time, client_id, account_id, client_order_id, legs } => {
+                match self.prepare_multileg((sending_time, receiving_time), &client_id, &account_id, client_order_id, legs) {
+                    Ok(()) => self.commit_multileg(),
+                    Err(reason) => vec![EngineMessage::OrderRejected { reason, client_id, code: None }],
+                }
+            }
+            EngineMessage::CancelOrder {
+                sending_time,
+                receiving_time,
+                order_id,
+                client_id,
+                account_id,
+                orig_client_order_id,
+                cancel_quantity,
+            } => {
+                // Extract sending_time at the beginning of the branch (future logic)
+                let _sending_time = sending_time;
+                let order_id = self.cancelled_order_id(&client_id, order_id, orig_client_order_id.as_ref());
+                // A partial cancel is an amend down, so the order keeps its place in the queue.
+                // Cancelling all of its leaves or more cancels it outright.
+                let resting = self.order_instruments
+                    .get(&order_id)
+                    .and_then(|instrument_id| self.books.get(instrument_id)?.order_index.get(&order_id));
+                // Both the account the cancel names and the one the order is on
+                let refusal = self.account_refusal(&account_id, &client_id)
+                    .or_else(|| resting.and_then(|order| self.account_refusal(&order.account_id, &client_id)));
+                if let Some(reason) = refusal {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let leaves = resting.map(|order| order.quantity);
+                if let (Some(cancel_quantity), Some(leaves)) = (cancel_quantity, leaves) {
+                    if cancel_quantity < leaves {
+                        let filled = self.order_fills.get(&order_id).map_or(0, |fills| fills.cumulative_quantity);
+                        return self.amend_order(client_id, order_id, Some(filled + leaves - cancel_quantity), None, None);
+                    }
+                }
+                let book = self.order_instruments
+                    .get(&order_id)
+                    .and_then(|instrument_id| self.books.get_mut(instrument_id))
+                    .filter(|book| book.contains_order(order_id));
+                if let Some(book) = book {
+                    if let Some(order) = book.remove_order(order_id, &mut self.accounts) {
+                        self.surveil(SurveillanceEvent::OrderCancelled { order_id, received: receiving_time });
+                        return vec![order_cancelled(client_id.clone(), &order, CancelReason::ClientRequested)];
+                    }
+                }
+                // The order traded away before the cancel got here; its owner learns how far it got
+                if let Some(fills) = self.order_fills.get(&order_id).filter(|fills| fills.owner == client_id) {
+                    return vec![EngineMessage::CancelRejected {
+                        client_id,
+                        order_id,
+                        reason: "Too late to cancel".to_string(),
+                        status: self.order_statuses[&order_id],
+                        cumulative_quantity: fills.cumulative_quantity,
+                        average_price: fills.notional / fills.cumulative_quantity as f64,
+                    }];
+                }
+                vec![EngineMessage::OrderRejected {
+                    reason: "Order not found".to_string(),
+                    client_id: client_id.clone(),
+                    code: None,
+                }]
+            }
+            EngineMessage::AmendOrder { client_id, order_id, new_quantity, new_price, time_in_force, .. } => {
+                self.amend_order(client_id, order_id, new_quantity, new_price, time_in_force)
+            }
+            EngineMessage::OrderStatusRequest { client_id, order_id, .. } => {
+                let Some(&status) = self.order_statuses.get(&order_id).filter(|_| self.order_owners.get(&order_id) == Some(&client_id)) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Order not found".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                let open = self.order_instruments
+                    .get(&order_id)
+                    .and_then(|instrument_id| self.books.get(instrument_id)?.order_index.get(&order_id));
+                let (cumulative_quantity, notional) = self.order_fills.get(&order_id).map_or((0, Price::from(0.0)), |fills| (fills.cumulative_quantity, fills.notional));
+                vec![EngineMessage::OrderStatusReport {
+                    client_id,
+                    order_id,
+                    instrument_id: open.map(|order| order.instrument_id.clone()),
+                    status,
+                    cumulative_quantity,
+                    leaves_quantity: open.map_or(0, |order| order.quantity),
+                    average_price: if cumulative_quantity == 0 { notional } else { notional / cumulative_quantity as f64 },
+                    untriggered: open.is_some_and(is_stop),
+                }]
+            }
+            EngineMessage::PositionQuery { client_id, account_id, .. } => {
+                let Some(account) = self.accounts.get(&account_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown account".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                if let Some(reason) = self.account_refusal(&account_id, &client_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                vec![position_report(client_id, account_id, account)]
+            }
+            EngineMessage::InboundExecutionReport { client_id, exec_id, exec_type, account_id, instrument_id, side, last_qty, last_px, .. } => {
+                // Only a trade moves anything, and a venue resending one is not a second trade
+                if exec_type != ExecType::Trade || self.upstream_exec_ids.contains(&exec_id) {
+                    return vec![];
+                }
+                if last_qty == 0 || last_px <= Price::from(0.0) || !matches!(side, Side::Buy | Side::Sell) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Invalid upstream fill".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let account = match self.owned_account(&account_id, &client_id) {
+                    Ok(account) => account,
+                    Err(reason) => {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: reason.to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                };
+                if side == Side::Buy {
+                    account.buy(None, &instrument_id, last_qty, last_px);
+                } else {
+                    // Realized against average cost, as a sale matched here would be
+                    if let Some(&cost) = account.average_cost.get(&instrument_id) {
+                        account.daily_pnl += (last_px - cost) * last_qty as f64;
+                    }
+                    account.sell(None, &instrument_id, last_qty, last_px);
+                }
+                let report = position_report(client_id, account_id, account);
+                self.upstream_exec_ids.insert(exec_id);
+                vec![report]
+            }
+            EngineMessage::GreeksRequest { client_id, account_id, .. } => {
+                let Some(account) = self.accounts.get(&account_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown account".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                if let Some(reason) = self.account_refusal(&account_id, &client_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let specs = self.books.iter().map(|(instrument_id, book)| (instrument_id.clone(), book.spec.read().clone())).collect();
+                let spot_prices = self.books.iter().filter_map(|(instrument_id, book)| Some((instrument_id.clone(), book.last_price?))).collect();
+                let greeks = portfolio_greeks(account, &specs, &spot_prices, &self.now());
+                vec![EngineMessage::GreeksReport { client_id, account_id, greeks }]
+            }
+            EngineMessage::CorporateAction { client_id, instrument_id, effective_time, action, .. } => {
+                if !self.books.contains_key(&instrument_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                if let CorporateAction::Split { numerator: 0, .. } | CorporateAction::Split { denominator: 0, .. } = action {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Invalid split ratio".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                // Actions only take effect as simulated time passes their effective time
+                self.pending_corporate_actions.push((effective_time, instrument_id, action));
+                self.apply_due_corporate_actions()
+            }
+            EngineMessage::Snapshot { client_id, instrument_id, depth, granularity, .. } => {
+                let Some(book) = self.books.get(&instrument_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                let depth_levels = depth.unwrap_or(0) as usize;
+                let ((bids, asks), (bid_orders, ask_orders)) = match granularity {
+                    BookGranularity::ByPrice => (book.depth_levels(depth_levels), Default::default()),
+                    BookGranularity::ByOrder => (Default::default(), book.depth_orders(depth_levels)),
+                };
+                vec![EngineMessage::Snapshot {
+                    client_id,
+                    timestamp: self.now(),
+                    instrument_id,
+                    bids,
+                    asks,
+                    depth,
+                    granularity,
+                    bid_orders,
+                    ask_orders,
+                }]
+            }
+            EngineMessage::SubscribeOrderBook { client_id, symbols, depth, subscribe_on_create, activity, last_sale, .. } => {
+                if !subscribe_on_create && symbols.iter().any(|symbol| !symbol.ends_with('*') && !self.books.contains_key(symbol)) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                // Subscribing again just changes the depth and whether activity is reported, and
+                // starts the books covered over
+                if activity {
+                    self.activity_subscribers.insert(client_id.clone());
+                } else {
+                    self.activity_subscribers.remove(&client_id);
+                }
+                // The tape is asked for symbol by symbol, so a wildcard takes the books listed later
+                for symbol in &symbols {
+                    if last_sale {
+                        self.tape_subscriptions.entry(symbol.clone()).or_default().insert(client_id.clone());
+                    } else {
+                        self.untape(symbol, &client_id);
+                    }
+                }
+                let subscribed = self.market_data_subscriptions.entry(client_id.clone()).or_default();
+                subscribed.retain(|(symbol, _)| !symbols.contains(symbol));
+                subscribed.extend(symbols.iter().map(|symbol| (symbol.clone(), depth)));
+                let covered: Vec<InstrumentID> = self.books.keys().filter(|instrument_id| symbols.iter().any(|symbol| covers(symbol, instrument_id))).cloned().collect();
+                for instrument_id in &covered {
+                    self.unsubscribe(instrument_id, &client_id);
+                }
+                self.sync_subscriptions(&client_id)
+            }
+            EngineMessage::UnsubscribeOrderBook { client_id, symbols, .. } => {
+                let Some(subscribed) = self.market_data_subscriptions.get_mut(&client_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Not subscribed".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                let before = subscribed.len();
+                subscribed.retain(|(subscribed, _)| !symbols.iter().any(|symbol| covers(symbol, subscribed)));
+                if subscribed.len() == before {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Not subscribed".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                if subscribed.is_empty() {
+                    self.market_data_subscriptions.remove(&client_id);
+                    self.activity_subscribers.remove(&client_id);
+                }
+                let taped: Vec<InstrumentID> = self.tape_subscriptions.keys().filter(|taped| symbols.iter().any(|symbol| covers(symbol, taped))).cloned().collect();
+                for symbol in &taped {
+                    self.untape(symbol, &client_id);
+                }
+                self.sync_subscriptions(&client_id)
+            }
+            EngineMessage::SessionClosed { client_id } => {
+                self.market_data_subscriptions.remove(&client_id);
+                self.activity_subscribers.remove(&client_id);
+                self.tape_subscriptions.retain(|_, subscribers| {
+                    subscribers.remove(&client_id);
+                    !subscribers.is_empty()
+                });
+                self.sync_subscriptions(&client_id)
+            }
+            EngineMessage::SubscribeAlerts { client_id, .. } => {
+                if !self.alert_subscribers.contains(&client_id) {
+                    self.alert_subscribers.push(client_id);
+                }
+                Vec::new()
+            }
+            EngineMessage::UnsubscribeAlerts { client_id, .. } => {
+                let Some(position) = self.alert_subscribers.iter().position(|subscriber| *subscriber == client_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Not subscribed".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                self.alert_subscribers.remove(position);
+                Vec::new()
+            }
+            EngineMessage::SetRestingOrderLimit { client_id, instrument_id, max_resting_orders, .. } => {
+                // Lowering a limit keeps existing orders; it only stops new ones from resting
+                let scope = match instrument_id {
+                    Some(instrument_id) => {
+                        let Some(book) = self.books.get_mut(&instrument_id) else {
+                            return vec![EngineMessage::OrderRejected {
+                                reason: "Unknown instrument".to_string(),
+                                client_id,
+                                code: None,
+                            }];
+                        };
+                        book.spec.update_spec(|spec| spec.max_resting_orders = max_resting_orders);
+                        instrument_id
+                    }
+                    None => {
+                        self.max_resting_orders = max_resting_orders;
+                        "the exchange".to_string()
+                    }
+                };
+                vec![EngineMessage::LogEvent {
+                    client_id: Some(client_id),
+                    message: format!("Resting order limit for {} set to {}", scope, max_resting_orders),
+                }]
+            }
+            EngineMessage::UpdateInstrumentSpec { client_id, instrument_id, spec, .. } => {
+                // Orders already resting stay; the orders after are held to the new spec
+                let Some(book) = self.books.get(&instrument_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                book.spec.update_spec(|current| *current = spec.apply(current));
+                vec![EngineMessage::LogEvent {
+                    client_id: Some(client_id),
+                    message: format!("Spec for {} updated", instrument_id),
+                }]
+            }
+            EngineMessage::SetSmpAction { client_id, account_id, smp_action, .. } => {
+                let account = match self.owned_account(&account_id, &client_id) {
+                    Ok(account) => account,
+                    Err(reason) => {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: reason.to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                };
+                account.smp_action = smp_action;
+                vec![EngineMessage::LogEvent {
+                    client_id: Some(client_id),
+                    message: format!("Self-match prevention for {} set to {:?}", account_id, smp_action),
+                }]
+            }
+            EngineMessage::SetRiskLimits { client_id, account_id, limits, .. } => {
+                let account = match self.owned_account(&account_id, &client_id) {
+                    Ok(account) => account,
+                    Err(reason) => {
+                        return vec![EngineMessage::OrderRejected {
+                            reason: reason.to_string(),
+                            client_id,
+                            code: None,
+                        }];
+                    }
+                };
+                account.risk_limits = limits;
+                vec![EngineMessage::LogEvent {
+                    client_id: Some(client_id),
+                    message: format!("Risk limits for {} set to {:?}", account_id, limits),
+                }]
+            }
+            EngineMessage::SetAdminFlag { client_id, admin } => {
+                let change = if admin {
+                    self.admins.insert(client_id.clone());
+                    "granted"
+                } else {
+                    self.admins.remove(&client_id);
+                    "revoked"
+                };
+                vec![EngineMessage::LogEvent {
+                    message: format!("Admin rights {} for {}", change, client_id),
+                    client_id: Some(client_id),
+                }]
+            }
+            EngineMessage::SetAccountAuthorization { client_id, account_id, authorized } => {
+                let comp_id = client_id.comp_id();
+                let message = if authorized {
+                    self.account_authorizations.authorize(comp_id, account_id.clone());
+                    format!("{} may trade account {}", comp_id, account_id)
+                } else {
+                    self.account_authorizations.revoke(comp_id, &account_id);
+                    format!("{} may no longer trade account {}", comp_id, account_id)
+                };
+                vec![EngineMessage::LogEvent { client_id: Some(client_id), message }]
+            }
+            EngineMessage::RequestReplay { client_id, instrument_id, from_timestamp, to_timestamp, .. } => {
+                let (from, to) = (timestamp_key(&from_timestamp), timestamp_key(&to_timestamp));
+                let mut trades = self.trade_log
+                    .iter()
+                    .filter(|trade| trade.instrument_id == instrument_id)
+                    .filter(|trade| (from..=to).contains(&timestamp_key(&trade.timestamp)));
+                let mut events: Vec<EngineMessage> = trades
+                    .by_ref()
+                    .take(MAX_REPLAY_TRADES)
+                    .map(|trade| EngineMessage::TradeReport {
+                        client_id: client_id.clone(),
+                        instrument_id: trade.instrument_id.clone(),
+                        price: trade.price,
+                        quantity: trade.quantity,
+                        timestamp: trade.timestamp.clone(),
+                    })
+                    .collect();
+                if let Some(next) = trades.next() {
+                    events.push(EngineMessage::LogEvent {
+                        client_id: Some(client_id),
+                        message: format!("Replay truncated after {} trades; resume from {}", MAX_REPLAY_TRADES, format_timestamp(&next.timestamp)),
+                    });
+                }
+                events
+            }
+            EngineMessage::Logon { client_id, cancel_previous_orders, summary_fills, .. } => {
+                if summary_fills {
+                    self.summary_sessions.insert(client_id.clone());
+                } else {
+                    self.summary_sessions.remove(&client_id);
+                }
+                // A reconnecting client can start clean, then learns where its accounts stand
+                let mut events = Vec::new();
+                if cancel_previous_orders {
+                    events.extend(self.mass_cancel_for_client(&client_id));
+                }
+                events.extend(self.position_reports_for_client(&client_id));
+                events
+            }
+            EngineMessage::SetTradingStatus { client_id, scope, halted, .. } => {
+                let instrument_ids = self.instruments_in(&scope);
+                if instrument_ids.is_empty() && scope != InstrumentScope::All {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "No instruments in scope".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let mut events = Vec::new();
+                for instrument_id in instrument_ids {
+                    let book = self.books.get_mut(&instrument_id).unwrap();
+                    // Halting clears the book unless the instrument freezes it for the resumption
+                    if halted && !book.halted && book.spec.read().halt_policy == HaltPolicy::CancelAll {
+                        book.cancel_orders(|_| true, CancelReason::TradingHalt, &mut self.accounts, &mut events);
+                    }
+                    book.halted = halted;
+                    // An admin's word outlasts a volatility halt's timer
+                    if book.volatility_halt_ends.is_some() {
+                        book.end_volatility_halt();
+                    }
+                    events.extend(self.uncross(&instrument_id));
+                }
+                events
+            }
+            EngineMessage::RollSession { client_id, scope, .. } => {
+                if self.instruments_in(&scope).is_empty() && scope != InstrumentScope::All {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "No instruments in scope".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let mut events = self.roll_session(&scope);
+                // Rolling every instrument ends the session for the accounts too
+                if scope == InstrumentScope::All {
+                    events.extend(self.close_statements(&session_name(&self.now().date())));
+                }
+                events
+            }
+            EngineMessage::StartWarmUp { client_id, scope, open_time, .. } => {
+                let instrument_ids = self.instruments_in(&scope);
+                if instrument_ids.is_empty() && scope != InstrumentScope::All {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "No instruments in scope".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                for instrument_id in instrument_ids {
+                    self.books.get_mut(&instrument_id).unwrap().phase = InstrumentPhase::WarmUp { opens_at: open_time.clone() };
+                }
+                Vec::new()
+            }
+            EngineMessage::AdvanceTime { timestamp, .. } => {
+                let mut events = Vec::new();
+                // Crossing into a new date rolls the session
+                if let Some(previous) = self.simulated_time.as_ref().filter(|previous| previous.date() < timestamp.date()) {
+                    let date = previous.date();
+                    events.extend(self.roll_session(&InstrumentScope::All));
+                    events.extend(self.close_statements(&session_name(&date)));
+                    self.session_turnover.clear();
+                    for account in self.accounts.values_mut() {
+                        account.daily_pnl = AccountBalance::from(0.0);
+                        account.loss_alerted = false;
+                    }
+                    let summary = self.execution_statistics.end_session().summary();
+                    if !summary.is_empty() {
+                        events.push(EngineMessage::LogEvent {
+                            client_id: None,
+                            message: format!("End of day {}: {}", session_name(&date), summary.join("; ")),
+                        });
+                    }
+                }
+                events.extend(self.expire_good_till_date_orders(&timestamp));
+                if let Some(time) = timestamp.to_chrono_utc() {
+                    clock::current().set(time);
+                }
+                self.simulated_time = Some(timestamp.clone());
+                events.extend(self.open_warmed_up_books(&timestamp));
+                events.extend(self.apply_due_corporate_actions());
+                events.extend(self.fire_scheduled_messages(&timestamp));
+                events
+            }
+            EngineMessage::PublishBookViews => {
+                self.publish_book_views();
+                Vec::new()
+            }
+            EngineMessage::PublishActivity => self.close_activity_interval(),
+            EngineMessage::ActivityRequest { client_id, instrument_id, .. } => {
+                let Some(book) = self.books.get(&instrument_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                // The last interval's rates with the levels as they are now; before the first
+                // interval closes there are only the levels to report
+                let (bid_levels, ask_levels) = book.price_levels();
+                let activity = ActivitySummary { bid_levels, ask_levels, ..book.last_activity.unwrap_or_default() };
+                vec![EngineMessage::ActivityReport {
+                    client_id,
+                    instrument_id,
+                    timestamp: self.activity_since.clone().unwrap_or_else(|| self.now()),
+                    activity,
+                }]
+            }
+            EngineMessage::Compact { client_id } => {
+                let pass = self.compact(COMPACTION_BUDGET);
+                self.compaction_stats.record(&pass.reclaimed);
+                match client_id {
+                    Some(client_id) => vec![EngineMessage::LogEvent { client_id: Some(client_id), message: pass.summary() }],
+                    None => Vec::new(),
+                }
+            }
+            EngineMessage::Schedule { client_id, fire_at, mut message, .. } => {
+                if extract_client_id(&message).as_ref() != Some(&client_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Only a client's own messages can be scheduled".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                self.ticker_map.translate(&mut message);
+                // Already due: nothing to wait for
+                if self.simulated_time.as_ref().is_some_and(|now| timestamp_key(&fire_at) <= timestamp_key(now)) {
+                    return self.dispatch_message(*message);
+                }
+                self.scheduled_messages.entry(timestamp_key(&fire_at)).or_default().push(*message);
+                Vec::new()
+            }
+            EngineMessage::Ping { client_id, ping_id, sent_at, received_at } => {
+                vec![EngineMessage::Pong { client_id, ping_id, sent_at, received_at, exchange_time: timestamp_now() }]
+            }
+            EngineMessage::SymbolStatusRequest { client_id, instrument_id, .. } => {
+                let scope = instrument_id.map_or(InstrumentScope::All, InstrumentScope::Instrument);
+                let instrument_ids = self.instruments_in(&scope);
+                if instrument_ids.is_empty() && scope != InstrumentScope::All {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let reports = instrument_ids.into_iter().map(|instrument_id| self.books[&instrument_id].status(instrument_id)).collect();
+                vec![EngineMessage::SymbolStatusReport { client_id, reports }]
+            }
+            EngineMessage::RejectionLogQuery { client_id, since, .. } => {
+                let malformed = self.malformed_log.since(&since);
+                let since = timestamp_key(&since);
+                let entries = self.rejection_log.iter().filter(|entry| timestamp_key(&entry.timestamp) >= since).cloned().collect();
+                vec![EngineMessage::RejectionLogReport { client_id, entries, malformed }]
+            }
+            EngineMessage::TradeHistoryQuery { client_id, query, .. } => {
+                vec![EngineMessage::TradeHistoryReport { client_id, page: self.trade_history(&query) }]
+            }
+            EngineMessage::StatementRequest { client_id, account_id, .. } => {
+                if account_id.as_ref().is_some_and(|account_id| !self.accounts.contains_key(account_id)) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown account".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let statements = self.statements(account_id.as_ref(), &session_name(&self.now().date()));
+                vec![EngineMessage::AccountStatements { client_id: Some(client_id), statements }]
+            }
+            EngineMessage::OrderHistoryQuery { client_id, order_id, after, limit, .. } => match self.order_history(order_id, after, limit) {
+                Some(page) => vec![EngineMessage::OrderHistoryReport { client_id, order_id, page }],
+                None => vec![EngineMessage::OrderRejected {
+                    reason: "Unknown order".to_string(),
+                    client_id,
+                    code: None,
+                }],
+            },
+            EngineMessage::SetInstrumentMetadata { client_id, instrument_id, key, value } => {
+                let Some(book) = self.books.get_mut(&instrument_id) else {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                };
+                if let Some(Err(reason)) = (key == PRICE_LADDER).then(|| book.use_price_ladder(&value)) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: reason.to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let message = format!("{} {} set to {}", instrument_id, key, value);
+                self.instrument_metadata.entry(instrument_id).or_default().insert(key, value);
+                vec![EngineMessage::LogEvent { client_id: Some(client_id), message }]
+            }
+            EngineMessage::GetInstrumentMetadata { client_id, instrument_id } => {
+                if !self.books.contains_key(&instrument_id) {
+                    return vec![EngineMessage::OrderRejected {
+                        reason: "Unknown instrument".to_string(),
+                        client_id,
+                        code: None,
+                    }];
+                }
+                let metadata = self.instrument_metadata.get(&instrument_id).map(|metadata| metadata.clone().into_iter().collect()).unwrap_or_default();
+                vec![EngineMessage::InstrumentMetadataResponse { client_id, instrument_id, metadata }]
+            }
+            _ => vec![EngineMessage::LogEvent {
+                client_id: None,
+                message: "Unsupported message received".to_string(),
+            }],
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::testkit::{accepted_order_id, advance_time, client, order_in};
+    use crate::instrument::{HaltPolicy, SpecOverrides, TouchWindowPolicy, UncrossPolicy};
+
+    fn at(time: &str) -> Timestamp {
+        Timestamp::parse(time.as_bytes()).unwrap()
+    }
+
+    fn create_instrument(exchange: &mut Exchange, instrument_id: &str) {
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: instrument_id.to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+    }
+
+    fn limit_order(exchange: &mut Exchange, account: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+        timed_order(exchange, account, side, quantity, price, None, None)
+    }
+
+    fn timed_order(
+        exchange: &mut Exchange,
+        account: &str,
+        side: Side,
+        quantity: Quantity,
+        price: f64,
+        time_in_force: Option<TimeInForce>,
+        expire_time: Option<&str>,
+    ) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side,
+            quantity,
+            price: Some(Price::from(price)),
+            time_in_force,
+            expire_time: expire_time.map(at),
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        })
+    }
+
+    fn corporate_action(exchange: &mut Exchange, effective_time: &str, action: CorporateAction) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::CorporateAction {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            effective_time: at(effective_time),
+            action,
+        })
+    }
+
+    #[test]
+    fn split_halves_prices_and_doubles_quantities() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+
+        // SELLER sells 4 to BUYER, then BUYER rests a GTC bid for 5 more that survives the session roll
+        limit_order(&mut exchange, "SELLER", Side::Sell, 4, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 4, 10.0);
+        timed_order(&mut exchange, "BUYER", Side::Buy, 5, 9.0, Some(TimeInForce::GoodTillCancel), None);
+        // and parks a GTC stop to buy 2 more once a trade reaches 20
+        let stop = accepted_order_id(&exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Stop,
+            side: Side::Buy,
+            quantity: 2,
+            price: Some(Price::from(20.0)),
+            time_in_force: Some(TimeInForce::GoodTillCancel),
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        }));
+        let cash_before = exchange.accounts["BUYER"].cash;
+
+        let split = CorporateAction::Split { numerator: 2, denominator: 1 };
+        assert!(corporate_action(&mut exchange, "20240102-09:30:00.000", split).is_empty());
+        assert!(advance_time(&mut exchange, "20240101-16:00:00.000").is_empty());
+
+        let events = advance_time(&mut exchange, "20240102-09:30:00.000");
+        assert!(events.iter().any(|event| matches!(event,
+            EngineMessage::OrderAmended { new_quantity: Some(10), new_price: Some(price), .. } if *price == Price::from(4.5))));
+        assert!(events.iter().any(|event| matches!(event,
+            EngineMessage::CorporateActionApplied { account_id, position: 8, .. } if account_id == "BUYER")));
+
+        let book = &exchange.books["AAPL"];
+        let bids: Vec<(Price, Quantity)> = book.bids.iter()
+            .map(|(price, queue)| (*price, queue.iter().map(|order| order.quantity).sum()))
+            .collect();
+        assert_eq!(bids, vec![(Price::from(4.5), 10)]);
+        // The parked stop waits for a trade at the split price, for the split quantity, and the
+        // last trade is quoted as the shares now trade
+        let stops: Vec<(OrderID, Option<Price>, Quantity)> = book.stops.iter().map(|order| (order.order_id, order.price, order.quantity)).collect();
+        assert_eq!(stops, vec![(stop, Some(Price::from(10.0)), 4)]);
+        assert_eq!(book.order_index[&stop].price, Some(Price::from(10.0)));
+        assert_eq!(book.last_price, Some(Price::from(5.0)));
+
+        let buyer = &exchange.accounts["BUYER"];
+        assert_eq!(buyer.positions["AAPL"], 8);
+        assert_eq!(buyer.average_cost["AAPL"], Price::from(5.0));
+        // What the resting bid and the stop hold is unchanged (5 @ 9 == 10 @ 4.5, 2 @ 20 == 4 @ 10)
+        assert_eq!(buyer.cash, cash_before);
+    }
+
+    #[test]
+    fn dividend_credits_position_times_amount() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+
+        limit_order(&mut exchange, "SELLER", Side::Sell, 7, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 7, 10.0);
+        let cash_before = exchange.accounts["BUYER"].cash;
+
+        let dividend = CorporateAction::Dividend { amount: Price::from(0.25) };
+        let events = corporate_action(&mut exchange, "20240101-09:00:00.000", dividend);
+
+        assert_eq!(exchange.accounts["BUYER"].cash, cash_before + Price::from(7.0 * 0.25));
+        assert_eq!(exchange.accounts["BUYER"].positions["AAPL"], 7);
+        assert!(events.iter().any(|event| matches!(event,
+            EngineMessage::CorporateActionApplied { account_id, cash_adjustment, .. }
+                if account_id == "BUYER" && *cash_adjustment == Price::from(1.75))));
+    }
+
+    fn snapshot_request(depth: Option<u32>, granularity: BookGranularity) -> EngineMessage {
+        EngineMessage::Snapshot {
+            client_id: client("BUYER"),
+            timestamp: Timestamp::utc_now(),
+            instrument_id: "AAPL".to_string(),
+            bids: Vec::new(),
+            asks: Vec::new(),
+            depth,
+            granularity,
+            bid_orders: Vec::new(),
+            ask_orders: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn snapshot_returns_top_levels_only() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        for price in [9.0, 10.0, 8.0] {
+            limit_order(&mut exchange, "BUYER", Side::Buy, 1, price);
+        }
+        for price in [12.0, 11.0, 13.0] {
+            limit_order(&mut exchange, "SELLER", Side::Sell, 2, price);
+        }
+
+        let snapshot = |exchange: &mut Exchange, depth| exchange.handle_message(snapshot_request(depth, BookGranularity::ByPrice));
+
+        match snapshot(&mut exchange, Some(2)).as_slice() {
+            [EngineMessage::Snapshot { bids, asks, .. }] => {
+                assert_eq!(bids, &vec![(Price::from(10.0), 1), (Price::from(9.0), 1)]);
+                assert_eq!(asks, &vec![(Price::from(11.0), 2), (Price::from(12.0), 2)]);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        for depth in [None, Some(0)] {
+            match snapshot(&mut exchange, depth).as_slice() {
+                [EngineMessage::Snapshot { bids, asks, .. }] => assert_eq!((bids.len(), asks.len()), (3, 3)),
+                other => panic!("unexpected response: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn by_order_snapshot_breaks_down_the_by_price_levels() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let low = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        let first = accepted_order_id(&limit_order(&mut exchange, "OTHER", Side::Buy, 2, 10.0));
+        let second = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 3, 10.0));
+        let ask = accepted_order_id(&limit_order(&mut exchange, "SELLER", Side::Sell, 4, 11.0));
+        // A partial fill shows as the order's leaves
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+
+        let (levels, orders) = match (
+            exchange.handle_message(snapshot_request(None, BookGranularity::ByPrice)).as_slice(),
+            exchange.handle_message(snapshot_request(None, BookGranularity::ByOrder)).as_slice(),
+        ) {
+            ([EngineMessage::Snapshot { bids, asks, bid_orders, .. }], [EngineMessage::Snapshot { bids: no_bids, bid_orders: by_order, ask_orders, .. }]) => {
+                assert!(bid_orders.is_empty() && no_bids.is_empty());
+                assert_eq!(ask_orders, &vec![(ask, Price::from(11.0), 4)]);
+                assert_eq!(asks, &vec![(Price::from(11.0), 4)]);
+                (bids.clone(), by_order.clone())
+            }
+            other => panic!("unexpected responses: {:?}", other),
+        };
+        assert_eq!(orders, vec![(first, Price::from(10.0), 1), (second, Price::from(10.0), 3), (low, Price::from(9.0), 1)]);
+
+        // Summing the orders of each level gives the by-price view back
+        let mut summed: Vec<(Price, Quantity)> = Vec::new();
+        for (_, price, quantity) in orders {
+            match summed.last_mut() {
+                Some((level, total)) if *level == price => *total += quantity,
+                _ => summed.push((price, quantity)),
+            }
+        }
+        assert_eq!(summed, levels);
+
+        match exchange.handle_message(snapshot_request(Some(1), BookGranularity::ByOrder)).as_slice() {
+            [EngineMessage::Snapshot { bid_orders, .. }] => assert_eq!(bid_orders.len(), 2),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    fn subscribe(exchange: &mut Exchange, name: &str, depth: u32) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::SubscribeOrderBook {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(name),
+            symbols: vec!["AAPL".to_string()],
+            depth,
+            subscribe_on_create: false,
+            activity: false,
+            last_sale: false,
+        })
+    }
+
+    // The changes pushed to `name` among the events
+    fn book_changes(events: &[EngineMessage], name: &str) -> Vec<BookChange> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::BookUpdate { client_id, changes, .. } if *client_id == client(name) => Some(changes.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn add(side: Side, price: f64, quantity: Quantity) -> BookChange {
+        BookChange::AddLevel { side, price: Price::from(price), quantity }
+    }
+
+    fn remove(side: Side, price: f64) -> BookChange {
+        BookChange::RemoveLevel { side, price: Price::from(price) }
+    }
+
+    #[test]
+    fn subscribers_get_the_book_then_only_what_changed_within_their_depth() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 11.0);
+
+        assert_eq!(book_changes(&subscribe(&mut exchange, "WATCHER", 2), "WATCHER"), vec![
+            add(Side::Buy, 10.0, 1),
+            add(Side::Buy, 9.0, 1),
+            add(Side::Sell, 11.0, 2),
+        ]);
+
+        // A better bid pushes 9.0 out of the top two
+        let events = limit_order(&mut exchange, "BIDDER", Side::Buy, 1, 9.5);
+        assert_eq!(book_changes(&events, "WATCHER"), vec![remove(Side::Buy, 9.0), add(Side::Buy, 9.5, 1)]);
+        let better_bid = accepted_order_id(&events);
+        let events = limit_order(&mut exchange, "BIDDER", Side::Buy, 3, 9.5);
+        assert_eq!(book_changes(&events, "WATCHER"), vec![BookChange::UpdateLevel {
+            side: Side::Buy,
+            price: Price::from(9.5),
+            quantity: 4,
+        }]);
+        let events = limit_order(&mut exchange, "TAKER", Side::Buy, 2, 11.0);
+        assert_eq!(book_changes(&events, "WATCHER"), vec![remove(Side::Sell, 11.0)]);
+
+        // Below the subscribed depth nothing is sent
+        assert!(book_changes(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 8.0), "WATCHER").is_empty());
+
+        let events = cancel(&mut exchange, "BIDDER", better_bid);
+        assert_eq!(book_changes(&events, "WATCHER"), vec![BookChange::UpdateLevel {
+            side: Side::Buy,
+            price: Price::from(9.5),
+            quantity: 3,
+        }]);
+    }
+
+    #[test]
+    fn a_level_leaving_the_view_makes_room_for_the_next_one() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let best = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0);
+        subscribe(&mut exchange, "WATCHER", 1);
+        subscribe(&mut exchange, "FULL", 0);
+
+        let events = cancel(&mut exchange, "BUYER", best);
+        assert_eq!(book_changes(&events, "WATCHER"), vec![remove(Side::Buy, 10.0), add(Side::Buy, 9.0, 1)]);
+        assert_eq!(book_changes(&events, "FULL"), vec![remove(Side::Buy, 10.0)]);
+    }
+
+    #[test]
+    fn unsubscribing_stops_updates() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        subscribe(&mut exchange, "WATCHER", 0);
+        let unsubscribe = |exchange: &mut Exchange| exchange.handle_message(EngineMessage::UnsubscribeOrderBook {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("WATCHER"),
+            symbols: vec!["AAPL".to_string()],
+        });
+
+        assert!(unsubscribe(&mut exchange).is_empty());
+        assert!(book_changes(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0), "WATCHER").is_empty());
+        assert!(matches!(unsubscribe(&mut exchange).as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Not subscribed"));
+        assert!(exchange.book_subscribers.is_empty());
+        assert!(exchange.books["AAPL"].published_views.is_empty());
+    }
+
+    fn resting_order_ids(exchange: &Exchange) -> Vec<OrderID> {
+        let mut order_ids: Vec<OrderID> = exchange.books["AAPL"].order_index.keys().copied().collect();
+        order_ids.sort();
+        order_ids
+    }
+
+    #[test]
+    fn session_roll_expires_day_orders_but_keeps_gtc_and_unexpired_gtd() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+
+        let day = accepted_order_id(&timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, None, None));
+        let gtc = accepted_order_id(&timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillCancel), None));
+        let gtd = accepted_order_id(&timed_order(
+            &mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillDate), Some("20240103-16:00:00.000"),
+        ));
+        let cash_with_orders = exchange.accounts["BUYER"].cash;
+
+        let events = advance_time(&mut exchange, "20240102-09:30:00.000");
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderExpired { order_id, .. }, EngineMessage::AccountStatements { .. }] if *order_id == day));
+        assert_eq!(resting_order_ids(&exchange), vec![gtc, gtd]);
+        assert_eq!(exchange.accounts["BUYER"].cash, cash_with_orders + Price::from(10.0));
+
+        let events = advance_time(&mut exchange, "20240103-16:00:00.000");
+        assert!(matches!(events.as_slice(), [EngineMessage::AccountStatements { .. }, EngineMessage::OrderExpired { order_id, .. }] if *order_id == gtd));
+        assert_eq!(resting_order_ids(&exchange), vec![gtc]);
+    }
+
+    #[test]
+    fn restart_keeps_only_gtc_and_gtd_orders() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+
+        timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::Day), None);
+        let gtc = accepted_order_id(&timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillCancel), None));
+        let gtd = accepted_order_id(&timed_order(
+            &mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillDate), Some("20240105-16:00:00.000"),
+        ));
+
+        let restarted = exchange.persistent_state();
+        assert_eq!(resting_order_ids(&restarted), vec![gtc, gtd]);
+        assert_eq!(restarted.accounts["BUYER"].cash, exchange.accounts["BUYER"].cash + Price::from(10.0));
+    }
+
+    // Saved at shutdown and started from again, the GTC orders rest where they stood, the
+    // Day order is refunded, and new orders trade with what was recovered
+    #[test]
+    fn gtc_orders_survive_a_restart_from_the_saved_state() {
+        let path = std::env::temp_dir().join(format!("exchange-state-{}.json", std::process::id()));
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+        timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::Day), None);
+        let first = accepted_order_id(&timed_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0, Some(TimeInForce::GoodTillCancel), None));
+        let second = accepted_order_id(&timed_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0, Some(TimeInForce::GoodTillCancel), None));
+        let ask = accepted_order_id(&timed_order(&mut exchange, "SELLER", Side::Sell, 1, 12.0, Some(TimeInForce::GoodTillCancel), None));
+        exchange.save_state(&path).unwrap();
+
+        let mut restarted = Exchange::new().with_saved_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resting_order_ids(&restarted), vec![first, second, ask]);
+        assert_eq!(restarted.accounts["BUYER"].cash, exchange.accounts["BUYER"].cash + Price::from(10.0));
+        assert_eq!(restarted.account_owners, exchange.account_owners);
+
+        // The first GTC bid is still ahead of the second, and new orders are numbered on from the old
+        let sold = limit_order(&mut restarted, "SELLER", Side::Sell, 2, 10.0);
+        assert_eq!(accepted_order_id(&sold), ask + 1);
+        assert!(sold.iter().any(|event| matches!(event, EngineMessage::OrderFilled { order_id, remaining_quantity: 0, .. } if *order_id == first)), "{:?}", sold);
+        assert_eq!(resting_order_ids(&restarted), vec![second, ask]);
+
+        // With nothing saved there is nothing to recover
+        assert!(Exchange::new().with_saved_state(&path).unwrap().books.is_empty());
+    }
+
+    // A GTC stop no trade has reached survives the restart still parked, and one recovered by
+    // bulk insert is parked again rather than rested at its stop price
+    #[test]
+    fn untriggered_stops_are_kept_parked_through_a_restart_and_a_recovery() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+        let stop = |exchange: &mut Exchange, time_in_force| accepted_order_id(&exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("STOPPER"),
+            account_id: "STOPPER".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Stop,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(11.0)),
+            time_in_force: Some(time_in_force),
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        }));
+        let day = stop(&mut exchange, TimeInForce::Day);
+        let gtc = stop(&mut exchange, TimeInForce::GoodTillCancel);
+
+        let restarted = exchange.persistent_state();
+        assert_eq!(resting_order_ids(&restarted), vec![gtc]);
+        assert_eq!(restarted.books["AAPL"].stops.iter().map(|order| order.order_id).collect::<Vec<_>>(), vec![gtc]);
+        assert!(restarted.order_instruments.contains_key(&gtc) && !restarted.order_instruments.contains_key(&day));
+
+        let mut recovered = Exchange::new();
+        create_instrument(&mut recovered, "AAPL");
+        recovered.begin_recovery();
+        assert_eq!(recovered.bulk_insert(vec![book_order(5, Side::Sell, OrdType::Limit, 1, 10.0), book_order(6, Side::Buy, OrdType::Stop, 1, 11.0)]), Ok(()));
+        recovered.finish_recovery();
+        let book = &recovered.books["AAPL"];
+        assert_eq!((book.depth_levels(0).0.len(), book.stops.len()), (0, 1));
+        assert!(matches!(cancel(&mut recovered, "T6", 6).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+    }
+
+    #[test]
+    fn recovery_rests_bulk_inserted_orders_as_they_stood_and_takes_no_orders_until_it_is_done() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let orders = vec![
+            book_order(7, Side::Buy, OrdType::Limit, 2, 10.0),
+            book_order(3, Side::Buy, OrdType::Limit, 1, 10.0),
+            book_order(5, Side::Sell, OrdType::Limit, 4, 11.0),
+        ];
+        assert_eq!(exchange.bulk_insert(orders.clone()), Err("Bulk insert is only open in recovery mode".to_string()));
+
+        exchange.begin_recovery();
+        let refused = limit_order(&mut exchange, "BUYER", Side::Buy, 1, 11.0);
+        assert!(matches!(refused.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Exchange is recovering"), "{:?}", refused);
+        let twice = vec![book_order(8, Side::Buy, OrdType::Limit, 1, 9.0), book_order(8, Side::Buy, OrdType::Limit, 1, 9.0)];
+        assert_eq!(exchange.bulk_insert(twice), Err("Order 8 is already on a book".to_string()));
+        assert!(resting_order_ids(&exchange).is_empty());
+        assert_eq!(exchange.bulk_insert(orders), Ok(()));
+        exchange.finish_recovery();
+
+        // Nothing was charged for them, and the queue keeps the order they were given in
+        assert!(exchange.accounts.is_empty());
+        assert_eq!(resting_order_ids(&exchange), vec![3, 5, 7]);
+        // New orders are numbered after the highest recovered
+        let sold = limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+        assert_eq!(accepted_order_id(&sold), 8);
+        let resting_filled: Vec<_> = sold.iter().filter_map(|event| match event {
+            EngineMessage::OrderFilled { order_id, filled_quantity, .. } if *order_id != 8 => Some((*order_id, *filled_quantity)),
+            _ => None,
+        }).collect();
+        assert_eq!(resting_filled, vec![(7, 2)]);
+        assert!(matches!(cancel(&mut exchange, "T3", 3).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+    }
+
+    // A restart whose saved orders cannot all be bulk inserted is refused, not run on books
+    // missing some of them
+    #[test]
+    fn a_saved_state_that_cannot_be_recovered_whole_is_refused() {
+        let path = std::env::temp_dir().join(format!("unrecoverable-state-{}.json", std::process::id()));
+        let mut exchange = Exchange::new().with_default_time_in_force(TimeInForce::GoodTillCancel);
+        create_instrument(&mut exchange, "AAPL");
+        create_instrument(&mut exchange, "MSFT");
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        let msft = accepted_order_id(&order_in(&mut exchange, "BUYER", "MSFT", Side::Buy, 1, 10.0));
+        exchange.save_state(&path).unwrap();
+
+        let mut saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        saved["instruments"].as_array_mut().unwrap().retain(|instrument| instrument["instrument_id"] != "MSFT");
+        std::fs::write(&path, saved.to_string()).unwrap();
+        let refused = Exchange::new().with_saved_state(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(refused.err(), Some(format!("Could not recover the exchange state in {}: Order {} is for an unknown instrument", path.display(), msft)));
+    }
+
+    #[test]
+    fn default_time_in_force_is_configurable() {
+        let mut exchange = Exchange::new().with_default_time_in_force(TimeInForce::GoodTillCancel);
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240101-09:30:00.000");
+
+        let order_id = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        assert!(matches!(advance_time(&mut exchange, "20240102-09:30:00.000").as_slice(), [EngineMessage::AccountStatements { .. }]));
+        assert_eq!(resting_order_ids(&exchange), vec![order_id]);
+
+        let rejected = timed_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillDate), None);
+        assert!(matches!(rejected.as_slice(), [EngineMessage::OrderRejected { .. }]));
+    }
+
+    fn cancel(exchange: &mut Exchange, account: &str, order_id: OrderID) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            order_id,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        })
+    }
+
+    #[test]
+    fn second_cancel_and_cancel_after_fill_are_rejected() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        create_instrument(&mut exchange, "MSFT");
+
+        let resting = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        assert!(exchange.books["AAPL"].contains_order(resting));
+        assert!(!exchange.books["MSFT"].contains_order(resting));
+
+        assert!(matches!(cancel(&mut exchange, "BUYER", resting).as_slice(), [EngineMessage::OrderCancelled { reason: CancelReason::ClientRequested, .. }]));
+        assert!(!exchange.books["AAPL"].contains_order(resting));
+        assert!(matches!(cancel(&mut exchange, "BUYER", resting).as_slice(), [EngineMessage::OrderRejected { .. }]));
+
+        let filled = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0));
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 9.0);
+        limit_order(&mut exchange, "OTHER SELLER", Side::Sell, 1, 10.0);
+        assert!(matches!(cancel(&mut exchange, "SELLER", filled).as_slice(), [EngineMessage::OrderRejected { .. }]));
+        match cancel(&mut exchange, "BUYER", filled).as_slice() {
+            [EngineMessage::CancelRejected { order_id, status, cumulative_quantity, average_price, .. }] => {
+                assert_eq!(*order_id, filled);
+                assert_eq!(*status, OrdStatus::Filled);
+                assert_eq!(*cumulative_quantity, 2);
+                assert_eq!(*average_price, Price::from(10.0));
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(exchange.order_instruments.is_empty());
+    }
+
+    #[test]
+    fn cancels_report_the_leaves_they_take_off_the_book() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let resting = accepted_order_id(&limit_order(&mut exchange, "SELLER", Side::Sell, 5, 10.0));
+        limit_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0);
+
+        assert!(matches!(cancel(&mut exchange, "SELLER", resting).as_slice(), [EngineMessage::OrderCancelled {
+            order_id, instrument_id, cancelled_price, cancelled_quantity: 3, ..
+        }] if *order_id == resting && instrument_id == "AAPL" && *cancelled_price == Some(Price::from(10.0))));
+    }
+
+    #[test]
+    fn cancelling_what_is_left_of_a_part_filled_buy_refunds_its_leaves() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        // Filled 2 of 5 as it rests, then 2 of 5 as it arrives
+        let resting = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 5, 10.0));
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 11.0);
+        let arriving = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 5, 11.0));
+
+        assert!(matches!(cancel(&mut exchange, "BUYER", resting).as_slice(), [EngineMessage::OrderCancelled { cancelled_quantity: 3, .. }]));
+        assert!(matches!(cancel(&mut exchange, "BUYER", arriving).as_slice(), [EngineMessage::OrderCancelled { cancelled_quantity: 3, .. }]));
+        // Only the fills are paid for: 2 at 10 and 2 at 11
+        assert_eq!(exchange.accounts["BUYER"].cash, Price::from(958.0));
+        assert_eq!(exchange.accounts["BUYER"].positions["AAPL"], 4);
+    }
+
+    #[test]
+    fn cancelling_a_resting_sell_leaves_the_account_as_it_was() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let ask = accepted_order_id(&limit_order(&mut exchange, "LONER", Side::Sell, 3, 10.0));
+        assert_eq!(exchange.accounts["LONER"].cash, Price::from(1000.0));
+
+        assert!(matches!(cancel(&mut exchange, "LONER", ask).as_slice(), [EngineMessage::OrderCancelled { cancelled_quantity: 3, .. }]));
+        assert_eq!(exchange.accounts["LONER"].cash, Price::from(1000.0));
+        assert_eq!(exchange.accounts["LONER"].positions.get("AAPL"), None);
+    }
+
+    fn set_resting_order_limit(exchange: &mut Exchange, instrument_id: Option<&str>, max_resting_orders: usize) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::SetRestingOrderLimit {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: instrument_id.map(str::to_string),
+            max_resting_orders,
+        })
+    }
+
+    fn set_smp_action(exchange: &mut Exchange, owner: &str, account: &str, smp_action: SmpAction) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::SetSmpAction {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(owner),
+            account_id: account.to_string(),
+            smp_action,
+        })
+    }
+
+    #[test]
+    fn reject_aggressor_refuses_an_order_that_would_cross_its_own_account() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        set_smp_action(&mut exchange, "TRADER", "TRADER", SmpAction::RejectAggressor);
+        let bid = accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Buy, 2, 10.0));
+        let cash_before = exchange.accounts["TRADER"].cash;
+
+        let events = limit_order(&mut exchange, "TRADER", Side::Sell, 1, 9.0);
+        assert!(matches!(events.as_slice(),
+            [EngineMessage::OrderRejected { reason, .. }] if reason == "Would cross own resting order"));
+        assert_eq!(exchange.accounts["TRADER"].cash, cash_before);
+        assert_eq!(resting_order_ids(&exchange), vec![bid]);
+
+        // Orders that don't reach the bid, and other accounts' orders that do, are unaffected
+        let offer = accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Sell, 1, 11.0));
+        let fills = limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+        assert!(fills.iter().any(|event| matches!(event, EngineMessage::OrderFilled { order_id, .. } if *order_id == bid)));
+        assert_eq!(resting_order_ids(&exchange), vec![bid, offer]);
+    }
+
+    #[test]
+    fn accounts_trade_with_themselves_unless_they_opt_in() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let bid = accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Buy, 1, 10.0));
+        let fills = limit_order(&mut exchange, "TRADER", Side::Sell, 1, 10.0);
+        assert!(fills.iter().any(|event| matches!(event, EngineMessage::OrderFilled { order_id, .. } if *order_id == bid)));
+
+        // Only the account's owner may change the setting
+        let events = set_smp_action(&mut exchange, "OTHER", "TRADER", SmpAction::RejectAggressor);
+        assert!(matches!(events.as_slice(),
+            [EngineMessage::OrderRejected { reason, .. }] if reason == "Account not owned by client"));
+        assert_eq!(exchange.accounts["TRADER"].smp_action, SmpAction::Allow);
+    }
+
+    #[test]
+    fn orders_worth_more_than_the_account_limit_are_rejected_whatever_the_cash() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        exchange.handle_message(EngineMessage::SetRiskLimits {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("TRADER"),
+            account_id: "TRADER".to_string(),
+            limits: RiskLimits { max_single_order_value: 50.0, ..RiskLimits::default() },
+        });
+
+        // 10 x 6 = 60 is well inside the 1000 cash but over the limit
+        let events = limit_order(&mut exchange, "TRADER", Side::Buy, 10, 6.0);
+        assert!(matches!(events.as_slice(),
+            [EngineMessage::OrderRejected { reason, .. }] if reason == "Single order value exceeds limit"));
+        assert_eq!(exchange.accounts["TRADER"].cash, Price::from(1000.0));
+        accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Buy, 10, 5.0));
+        // Other accounts keep the default, unlimited
+        accepted_order_id(&limit_order(&mut exchange, "OTHER", Side::Buy, 10, 60.0));
+    }
+
+    #[test]
+    fn losing_past_the_daily_loss_limit_stops_new_orders_until_the_next_day() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        exchange.handle_message(EngineMessage::SetRiskLimits {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("TRADER"),
+            account_id: "TRADER".to_string(),
+            limits: RiskLimits { max_daily_loss: Some(20.0), ..RiskLimits::default() },
+        });
+        limit_order(&mut exchange, "SELLER", Side::Sell, 10, 10.0);
+        limit_order(&mut exchange, "TRADER", Side::Buy, 10, 10.0);
+        let is_alert = |event: &EngineMessage| matches!(event, EngineMessage::RiskAlert { client_id, account_id, reason }
+            if *client_id == client("TRADER") && account_id == "TRADER" && reason == "Approaching daily loss limit");
+
+        // Selling 5 bought at 10 for 7 loses 15, three quarters of the limit
+        limit_order(&mut exchange, "BUYER", Side::Buy, 5, 7.0);
+        let events = limit_order(&mut exchange, "TRADER", Side::Sell, 5, 7.0);
+        assert!(!events.iter().any(is_alert), "{:?}", events);
+        assert_eq!(exchange.accounts["TRADER"].daily_pnl, AccountBalance::from(-15.0));
+
+        // Another 2 takes it to 21: warned once, then refused anything new
+        limit_order(&mut exchange, "BUYER", Side::Buy, 3, 7.0);
+        let events = limit_order(&mut exchange, "TRADER", Side::Sell, 2, 7.0);
+        assert_eq!(events.iter().filter(|event| is_alert(event)).count(), 1, "{:?}", events);
+        let events = limit_order(&mut exchange, "TRADER", Side::Sell, 1, 7.0);
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Daily loss limit reached"), "{:?}", events);
+        accepted_order_id(&limit_order(&mut exchange, "OTHER", Side::Buy, 1, 7.0));
+
+        // A new day starts from nothing
+        advance_time(&mut exchange, "20240103-14:00:00.000");
+        assert_eq!(exchange.accounts["TRADER"].daily_pnl, AccountBalance::from(0.0));
+        accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Sell, 1, 7.0));
+    }
+
+    fn amend(exchange: &mut Exchange, account: &str, order_id: OrderID, new_quantity: Option<Quantity>, new_price: Option<f64>) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::AmendOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            order_id,
+            new_quantity,
+            new_price: new_price.map(Price::from),
+            time_in_force: None,
+        })
+    }
+
+    #[test]
+    fn amends_hold_cash_for_the_leaves_not_the_original_quantity() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let bid = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 5, 10.0));
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+
+        // 3 left at 10 become 3 at 12
+        let cash = exchange.accounts["BUYER"].cash;
+        amend(&mut exchange, "BUYER", bid, None, Some(12.0));
+        assert_eq!(exchange.accounts["BUYER"].cash, cash - Price::from(6.0));
+
+        // Growing the order past what the account can cover is refused outright
+        let events = amend(&mut exchange, "BUYER", bid, Some(1000), None);
+        assert!(matches!(events.as_slice(),
+            [EngineMessage::AmendRejected { reason, status: OrdStatus::PartiallyFilled, .. }] if reason == "Insufficient funds"));
+
+        // Amending to what has filled hands back everything still held
+        let cash = exchange.accounts["BUYER"].cash;
+        amend(&mut exchange, "BUYER", bid, Some(2), None);
+        assert_eq!(exchange.accounts["BUYER"].cash, cash + Price::from(36.0));
+        assert_eq!(exchange.order_statuses[&bid], OrdStatus::Filled);
+        assert!(exchange.order_instruments.is_empty());
+        assert!(resting_order_ids(&exchange).is_empty());
+    }
+
+    #[test]
+    fn partial_cancels_hand_back_the_cash_held_for_what_they_take_off() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let bid = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 5, 10.0));
+        let cash = exchange.accounts["BUYER"].cash;
+
+        let events = exchange.handle_message(EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            order_id: bid,
+            orig_client_order_id: None,
+            cancel_quantity: Some(2),
+        });
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderAmended { leaves_quantity: 3, .. }]), "{:?}", events);
+        assert_eq!(exchange.accounts["BUYER"].cash, cash + Price::from(20.0));
+        assert_eq!(exchange.order_statuses[&bid], OrdStatus::New);
+    }
+
+    fn is_not_owned(events: &[EngineMessage]) -> bool {
+        matches!(events, [EngineMessage::OrderRejected { reason, .. }] if reason == "Account not owned by client")
+    }
+
+    #[test]
+    fn only_an_accounts_owner_or_an_admin_manages_it() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let bid = accepted_order_id(&limit_order(&mut exchange, "CLIENT", Side::Buy, 5, 10.0));
+        let order_for_client = |exchange: &mut Exchange, sender: &str| {
+            exchange.handle_message(EngineMessage::NewOrder {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: client(sender),
+                account_id: "CLIENT".to_string(),
+                client_order_id: None,
+                instrument_id: "AAPL".to_string(),
+                order_type: OrdType::Limit,
+                side: Side::Buy,
+                quantity: 1,
+                price: Some(Price::from(9.0)),
+                time_in_force: None,
+                expire_time: None,
+                exchange_code: None,
+                good_for_auction: false,
+                stipulations: Vec::new(),
+            })
+        };
+        let cancel_for_client = |exchange: &mut Exchange, sender: &str, order_id| {
+            exchange.handle_message(EngineMessage::CancelOrder {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: client(sender),
+                account_id: "CLIENT".to_string(),
+                order_id,
+                orig_client_order_id: None,
+                cancel_quantity: None,
+            })
+        };
+
+        assert!(is_not_owned(&order_for_client(&mut exchange, "BROKER")));
+        assert!(is_not_owned(&cancel_for_client(&mut exchange, "BROKER", bid)));
+        assert!(is_not_owned(&amend(&mut exchange, "BROKER", bid, Some(3), None)));
+        assert_eq!(resting_order_ids(&exchange), vec![bid]);
+
+        let granted = exchange.handle_message(EngineMessage::SetAdminFlag { client_id: client("BROKER"), admin: true });
+        assert!(matches!(granted.as_slice(), [EngineMessage::LogEvent { client_id: Some(to), .. }] if *to == client("BROKER")));
+        let placed = accepted_order_id(&order_for_client(&mut exchange, "BROKER"));
+        assert!(matches!(amend(&mut exchange, "BROKER", bid, Some(3), None).as_slice(), [EngineMessage::OrderAmended { .. }]));
+        assert!(matches!(cancel_for_client(&mut exchange, "BROKER", placed).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+        // Trading an account doesn't take it over
+        assert_eq!(exchange.account_owners["CLIENT"], client("CLIENT"));
+
+        exchange.handle_message(EngineMessage::SetAdminFlag { client_id: client("BROKER"), admin: false });
+        assert!(is_not_owned(&cancel_for_client(&mut exchange, "BROKER", bid)));
+        assert!(matches!(cancel(&mut exchange, "CLIENT", bid).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+    }
+
+    fn is_book_full(events: &[EngineMessage]) -> bool {
+        matches!(events, [EngineMessage::OrderRejected { reason, .. }] if reason == "Book full")
+    }
+
+    #[test]
+    fn instrument_cap_rejects_new_resting_orders_until_a_cancel_frees_room() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        set_resting_order_limit(&mut exchange, Some("AAPL"), 3);
+
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        let alert = limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        assert!(alert.iter().any(|event| matches!(event, EngineMessage::LogEvent { client_id: None, .. })));
+        let last = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+
+        assert!(is_book_full(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0)));
+        // An order that trades against the book is still allowed
+        let marketable = limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+        accepted_order_id(&marketable);
+
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        assert!(is_book_full(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0)));
+        cancel(&mut exchange, "BUYER", last);
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        assert!(is_book_full(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0)));
+
+        set_resting_order_limit(&mut exchange, Some("AAPL"), 0);
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+    }
+
+    #[test]
+    fn global_cap_spans_instruments() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        create_instrument(&mut exchange, "MSFT");
+        set_resting_order_limit(&mut exchange, None, 2);
+
+        let aapl = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        let msft = |exchange: &mut Exchange| exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            client_order_id: None,
+            instrument_id: "MSFT".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(10.0)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        });
+        assert!(is_book_full(&msft(&mut exchange)));
+
+        cancel(&mut exchange, "BUYER", aapl);
+        accepted_order_id(&msft(&mut exchange));
+    }
+
+    #[test]
+    fn acceptance_precedes_fills_and_statuses_follow_the_lifecycle() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+
+        let resting = accepted_order_id(&limit_order(&mut exchange, "SELLER", Side::Sell, 5, 10.0));
+        assert_eq!(exchange.order_statuses[&resting], OrdStatus::New);
+
+        let events = limit_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0);
+        let incoming = accepted_order_id(&events);
+        assert!(matches!(events.as_slice(), [
+            EngineMessage::OrderAccepted { .. },
+            EngineMessage::OrderFilled { order_id: a, remaining_quantity: 0, .. },
+            EngineMessage::OrderFilled { order_id: b, remaining_quantity: 3, .. },
+        ] if *a == incoming && *b == resting));
+        assert_eq!(exchange.order_statuses[&incoming], OrdStatus::Filled);
+        assert_eq!(exchange.order_statuses[&resting], OrdStatus::PartiallyFilled);
+
+        cancel(&mut exchange, "SELLER", resting);
+        assert_eq!(exchange.order_statuses[&resting], OrdStatus::Canceled);
+    }
+
+    #[test]
+    fn immediate_or_cancel_remainder_is_cancelled_after_its_fills() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+
+        let events = timed_order(&mut exchange, "BUYER", Side::Buy, 3, 10.0, Some(TimeInForce::ImmediateOrCancel), None);
+        let order_id = accepted_order_id(&events);
+        assert!(matches!(events.as_slice(), [
+            EngineMessage::OrderAccepted { .. },
+            EngineMessage::OrderFilled { remaining_quantity: 2, .. },
+            EngineMessage::OrderFilled { .. },
+            EngineMessage::OrderCancelled { order_id: cancelled, reason: CancelReason::Other(_), .. },
+        ] if *cancelled == order_id));
+        assert_eq!(exchange.order_statuses[&order_id], OrdStatus::Canceled);
+        assert!(!exchange.books["AAPL"].contains_order(order_id));
+    }
+
+    fn book_order(order_id: OrderID, side: Side, order_type: OrdType, quantity: Quantity, price: f64) -> Order {
+        Order {
+            order_id,
+            client_order_id: String::new(),
+            price: Some(Price::from(price)),
+            quantity,
+            send_timestamp: Timestamp::utc_now(),
+            receive_timestamp: Timestamp::utc_now(),
+            side,
+            order_type,
+            time_in_force: TimeInForce::Day,
+            expire_time: None,
+            exec_instruction: ExecInst::StayOnOfferSide,
+            instrument_id: "AAPL".to_string(),
+            account_id: format!("T{}", order_id),
+            sender_id: client(&format!("T{}", order_id)),
+            good_for_auction: false,
+        }
+    }
+
+    #[test]
+    fn buy_stop_triggers_once_the_best_ask_reaches_its_stop_price() {
+        let mut book = OrderBook::new(InstrumentSpec::default());
+        let (mut accounts, mut trade_match_counter) = (HashMap::new(), 0);
+        let mut submit = |book: &mut OrderBook, order: Order| book.match_order(order, &mut accounts, &mut trade_match_counter).unwrap();
+
+        assert!(submit(&mut book, book_order(1, Side::Sell, OrdType::Limit, 1, 100.0)).is_empty());
+        // The ask is still below the stop
+        assert!(submit(&mut book, book_order(2, Side::Buy, OrdType::Stop, 1, 101.0)).is_empty());
+        assert_eq!(submit(&mut book, book_order(3, Side::Buy, OrdType::Limit, 1, 100.0)).len(), 2);
+        // With the ask taken there is no market price to trigger on
+        assert!(submit(&mut book, book_order(4, Side::Buy, OrdType::Stop, 1, 100.0)).is_empty());
+
+        submit(&mut book, book_order(5, Side::Sell, OrdType::Limit, 1, 101.0));
+        let fills = submit(&mut book, book_order(6, Side::Buy, OrdType::Stop, 1, 100.0));
+        // Filling above the stop price shows it traded as a market order, not a limit at 100.
+        // The trade reaches both parked stops, which find nothing left to buy.
+        assert!(matches!(fills.as_slice(), [
+            EngineMessage::OrderFilled { order_id: 6, remaining_quantity: 0, price: stop_fill, .. },
+            EngineMessage::OrderFilled { order_id: 5, remaining_quantity: 0, price: resting_fill, .. },
+            EngineMessage::OrderCancelled { order_id: 2, cancelled_price: None, .. },
+            EngineMessage::OrderCancelled { order_id: 4, cancelled_price: None, .. },
+        ] if *stop_fill == Price::from(101.0) && *resting_fill == Price::from(101.0)), "{:?}", fills);
+        assert!(book.asks.is_empty() && book.stops.is_empty());
+    }
+
+    #[test]
+    fn an_order_that_would_cross_the_book_trades_or_is_refused_but_never_rests() {
+        let mut book = OrderBook::new(InstrumentSpec::default());
+        let (mut accounts, mut trade_match_counter) = (HashMap::new(), 0);
+        accounts.insert("T3".to_string(), Bankroll::new(Price::from(1000.0)));
+        book.match_order(book_order(1, Side::Sell, OrdType::Limit, 1, 10.0), &mut accounts, &mut trade_match_counter).unwrap();
+        book.match_order(book_order(2, Side::Buy, OrdType::Limit, 1, 9.0), &mut accounts, &mut trade_match_counter).unwrap();
+
+        // Matched, a bid through the ask trades with it
+        let fills = book.match_order(book_order(3, Side::Buy, OrdType::Limit, 2, 11.0), &mut accounts, &mut trade_match_counter).unwrap();
+        assert!(matches!(fills.first(), Some(EngineMessage::OrderFilled { order_id: 3, .. })), "{:?}", fills);
+        assert!(!book.is_crossed());
+
+        // Put straight onto the book instead, it is refused and its reserve returned
+        book.match_order(book_order(4, Side::Sell, OrdType::Limit, 1, 12.0), &mut accounts, &mut trade_match_counter).unwrap();
+        let (mut events, cash) = (Vec::new(), accounts["T3"].cash);
+        book.rest_order(book_order(3, Side::Buy, OrdType::Limit, 1, 13.0), &mut accounts, &mut events);
+        assert!(matches!(events.as_slice(), [
+            EngineMessage::InvalidMessage { reason, .. },
+            EngineMessage::OrderCancelled { order_id: 3, .. },
+        ] if reason == "Order would cross the book"), "{:?}", events);
+        assert_eq!(accounts["T3"].cash, cash + Price::from(13.0));
+        assert!(!book.is_crossed() && book.bids.keys().next_back() == Some(&Price::from(11.0)));
+    }
+
+    #[test]
+    fn reports_that_break_the_lifecycle_are_logged() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let order_id = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        cancel(&mut exchange, "BUYER", order_id);
+
+        let mut events = vec![EngineMessage::OrderExpired { client_id: client("BUYER"), order_id }];
+        exchange.track_order_states(&mut events, "advance_time", None);
+        assert!(matches!(events.last(), Some(EngineMessage::LogEvent { client_id: None, .. })));
+        assert_eq!(exchange.order_statuses[&order_id], OrdStatus::Canceled);
+    }
+
+    #[test]
+    fn replay_returns_trades_within_the_requested_range() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let trades = [("20240101-09:30:00.000", 10.0, "SELLER1"), ("20240101-10:00:00.000", 11.0, "SELLER2"), ("20240101-11:00:00.000", 12.0, "SELLER3")];
+        for (time, price, seller) in trades {
+            advance_time(&mut exchange, time);
+            limit_order(&mut exchange, seller, Side::Sell, 1, price);
+            limit_order(&mut exchange, "BUYER", Side::Buy, 1, price);
+        }
+
+        let events = exchange.handle_message(EngineMessage::RequestReplay {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("LATECOMER"),
+            instrument_id: "AAPL".to_string(),
+            from_timestamp: at("20240101-09:45:00.000"),
+            to_timestamp: at("20240101-11:00:00.000"),
+        });
+        let replayed: Vec<Price> = events.iter()
+            .map(|event| match event {
+                EngineMessage::TradeReport { price, quantity: 1, .. } => *price,
+                other => panic!("unexpected replay event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(replayed, vec![Price::from(11.0), Price::from(12.0)]);
+    }
+
+    fn logon(exchange: &mut Exchange, name: &str, cancel_previous_orders: bool) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::Logon {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(name),
+            cancel_previous_orders,
+            heart_bt_int: 30,
+            default_appl_ver_id: None,
+            summary_fills: false,
+        })
+    }
+
+    #[test]
+    fn reset_logon_cancels_the_clients_resting_orders_before_reporting_positions() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let first = accepted_order_id(&timed_order(&mut exchange, "TRADER", Side::Buy, 1, 10.0, Some(TimeInForce::GoodTillCancel), None));
+        let second = accepted_order_id(&timed_order(&mut exchange, "TRADER", Side::Buy, 2, 9.0, Some(TimeInForce::GoodTillCancel), None));
+        let other = accepted_order_id(&limit_order(&mut exchange, "OTHER", Side::Buy, 1, 8.0));
+
+        let events = logon(&mut exchange, "TRADER", true);
+        let cancelled: Vec<OrderID> = events.iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderCancelled { order_id, reason: CancelReason::Disconnect, .. } => Some(*order_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cancelled, vec![first, second]);
+        match events.last() {
+            Some(EngineMessage::PositionReport { account_id, cash, .. }) => {
+                assert_eq!(account_id, "TRADER");
+                assert_eq!(*cash, Price::from(1000.0));
+            }
+            other => panic!("expected a position report last, got {:?}", other),
+        }
+        assert_eq!(events.len(), 3);
+        assert_eq!(resting_order_ids(&exchange), vec![other]);
+    }
+
+    #[test]
+    fn plain_logon_keeps_resting_orders() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let order_id = accepted_order_id(&limit_order(&mut exchange, "TRADER", Side::Buy, 1, 10.0));
+
+        let events = logon(&mut exchange, "TRADER", false);
+        assert!(matches!(events.as_slice(), [EngineMessage::PositionReport { cash, .. }] if *cash == Price::from(990.0)), "{:?}", events);
+        assert_eq!(resting_order_ids(&exchange), vec![order_id]);
+
+        // A client with no accounts has nothing to cancel or report
+        assert!(logon(&mut exchange, "NEWCOMER", true).is_empty());
+    }
+
+    #[test]
+    fn instruments_inherit_their_segment_and_halt_with_it() {
+        let segments: MarketSegments = toml::from_str(
+            "[segments.EQUITIES]\ntick_size = 0.01\n[segments.FUTURES]\ntick_size = 0.25\nlot_size = 5\n",
+        ).unwrap();
+        let mut exchange = Exchange::new().with_segments(segments);
+        let admin = |exchange: &mut Exchange, message: fn(ClientID, InstrumentScope) -> EngineMessage, scope| {
+            exchange.handle_message(message(client("ADMIN"), scope))
+        };
+        let create = |exchange: &mut Exchange, instrument_id: &str, segment: &str, spec: SpecOverrides| {
+            exchange.handle_message(EngineMessage::CreateInstrument {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: client("ADMIN"),
+                instrument_id: instrument_id.to_string(),
+                segment: Some(segment.to_string()),
+                spec,
+                spread: None,
+            })
+        };
+        let order = |exchange: &mut Exchange, instrument_id: &str, quantity: Quantity, price: f64| {
+            exchange.handle_message(EngineMessage::NewOrder {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: client("BUYER"),
+                account_id: "BUYER".to_string(),
+                client_order_id: None,
+                instrument_id: instrument_id.to_string(),
+                order_type: OrdType::Limit,
+                side: Side::Buy,
+                quantity,
+                price: Some(Price::from(price)),
+                time_in_force: None,
+                expire_time: None,
+                exchange_code: None,
+                good_for_auction: false,
+                stipulations: Vec::new(),
+            })
+        };
+        let rejection = |events: Vec<EngineMessage>| match events.as_slice() {
+            [EngineMessage::OrderRejected { reason, .. }] => reason.clone(),
+            _ => panic!("not rejected: {:?}", events),
+        };
+
+        create(&mut exchange, "AAPL", "EQUITIES", SpecOverrides::default());
+        create(&mut exchange, "ES", "FUTURES", SpecOverrides { halt_policy: Some(HaltPolicy::Freeze), ..SpecOverrides::default() });
+        create(&mut exchange, "NQ", "FUTURES", SpecOverrides { lot_size: Some(1), ..SpecOverrides::default() });
+        assert_eq!(rejection(create(&mut exchange, "GOLD", "METALS", SpecOverrides::default())), "Unknown segment");
+        assert!(!exchange.books.contains_key("GOLD"));
+
+        accepted_order_id(&order(&mut exchange, "AAPL", 1, 10.01));
+        assert_eq!(rejection(order(&mut exchange, "ES", 5, 10.1)), "Price is not a multiple of the tick size");
+        assert_eq!(rejection(order(&mut exchange, "ES", 1, 10.25)), "Quantity is not a multiple of the lot size");
+        let cleared = accepted_order_id(&order(&mut exchange, "NQ", 1, 10.25));
+        let resting = accepted_order_id(&order(&mut exchange, "ES", 5, 10.25));
+
+        let halt = |client_id, scope| EngineMessage::SetTradingStatus {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id,
+            scope,
+            halted: true,
+        };
+        // NQ's book is cleared by the halt, ES's frozen as it is
+        let halted = admin(&mut exchange, halt, InstrumentScope::Segment("FUTURES".to_string()));
+        assert!(matches!(halted.as_slice(), [EngineMessage::OrderCancelled { order_id, reason: CancelReason::TradingHalt, .. }] if *order_id == cleared), "{:?}", halted);
+        assert_eq!(rejection(order(&mut exchange, "ES", 5, 10.25)), "Instrument halted");
+        assert_eq!(rejection(order(&mut exchange, "NQ", 1, 10.25)), "Instrument halted");
+        accepted_order_id(&order(&mut exchange, "AAPL", 1, 10.01));
+        // Halted orders can still leave the book
+        assert!(matches!(cancel(&mut exchange, "BUYER", resting).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+
+        let resume = |client_id, scope| EngineMessage::SetTradingStatus {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id,
+            scope,
+            halted: false,
+        };
+        admin(&mut exchange, resume, InstrumentScope::Segment("FUTURES".to_string()));
+        accepted_order_id(&order(&mut exchange, "ES", 5, 10.25));
+        assert_eq!(rejection(admin(&mut exchange, resume, InstrumentScope::Segment("METALS".to_string()))), "No instruments in scope");
+    }
+
+    // Bids amended through the asks while AAPL is halted, trading out once it resumes under
+    // `policy`: the trades' prices and quantities
+    fn trades_on_resuming_crossed(policy: UncrossPolicy) -> Vec<(Price, Quantity)> {
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides { uncross_policy: Some(policy), halt_policy: Some(HaltPolicy::Freeze), ..SpecOverrides::default() },
+            spread: None,
+        });
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 11.0);
+        let first = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        let second = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 3, 8.0));
+        let trading_status = |exchange: &mut Exchange, halted| exchange.handle_message(EngineMessage::SetTradingStatus {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Instrument("AAPL".to_string()),
+            halted,
+        });
+        trading_status(&mut exchange, true);
+
+        // Halted, the amends rest where they cross rather than trade
+        for amended in [amend(&mut exchange, "BUYER", first, Some(2), Some(12.0)), amend(&mut exchange, "BUYER", second, None, Some(11.0))] {
+            assert!(matches!(amended.as_slice(), [EngineMessage::OrderAmended { .. }]), "{:?}", amended);
+        }
+        assert!(exchange.books["AAPL"].is_crossed());
+
+        let resumed = trading_status(&mut exchange, false);
+        let book = &exchange.books["AAPL"];
+        assert!(!book.is_crossed());
+        assert_eq!((book.depth_levels(0), book.executions.len()), ((vec![(Price::from(11.0), 1)], vec![]), 0));
+        let trades: Vec<_> = resumed
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderFilled { order_id, price, filled_quantity, .. } if *order_id == first || *order_id == second => Some((*price, *filled_quantity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(resumed.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { .. })).count(), trades.len() * 2, "{:?}", resumed);
+        trades
+    }
+
+    #[test]
+    fn a_book_crossed_by_amends_during_a_halt_trades_out_at_the_resting_asks_when_it_resumes() {
+        let trades = trades_on_resuming_crossed(UncrossPolicy::RestingPrices);
+        assert_eq!(trades, vec![(Price::from(10.0), 2), (Price::from(11.0), 2)]);
+    }
+
+    #[test]
+    fn a_book_crossed_by_amends_during_a_halt_can_trade_out_by_auction_at_one_price() {
+        // 11 trades four, where 10 and 12 would trade two
+        let trades = trades_on_resuming_crossed(UncrossPolicy::Auction);
+        assert_eq!(trades, vec![(Price::from(11.0), 2), (Price::from(11.0), 2)]);
+    }
+
+    #[test]
+    fn a_halt_cancels_every_order_resting_on_the_book_and_gives_back_what_each_held() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let first = accepted_order_id(&limit_order(&mut exchange, "FIRST", Side::Buy, 2, 12.0));
+        let second = accepted_order_id(&limit_order(&mut exchange, "SECOND", Side::Buy, 3, 9.0));
+        let halted = exchange.handle_message(EngineMessage::SetTradingStatus {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Instrument("AAPL".to_string()),
+            halted: true,
+        });
+        let cancelled: Vec<_> = halted
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderCancelled { order_id, reason: CancelReason::TradingHalt, .. } => Some(*order_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cancelled, vec![first, second], "{:?}", halted);
+        // Each account has back the cash its bid held
+        let cash = |account: &str| exchange.accounts[account].cash;
+        assert_eq!((cash("FIRST"), cash("SECOND")), (Price::from(1000.0), Price::from(1000.0)));
+        let book = &exchange.books["AAPL"];
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+    }
+
+    // AAPL with new levels held to a dollar from the touch
+    fn touch_windowed(policy: TouchWindowPolicy) -> Exchange {
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides { max_touch_distance: Some(Price::from(1.0)), touch_window_policy: Some(policy), ..SpecOverrides::default() },
+            spread: None,
+        });
+        exchange
+    }
+
+    fn refusal(events: &[EngineMessage]) -> Option<&str> {
+        match events {
+            [EngineMessage::OrderRejected { reason, .. }] => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn a_new_level_further_from_the_touch_than_the_window_allows_is_refused() {
+        let mut exchange = touch_windowed(TouchWindowPolicy::Grandfather);
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        // Right at the window's edge is inside it
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        assert_eq!(refusal(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 8.99)), Some("Price too far from the touch"));
+        // With no asks yet, the first is held to the bids' touch
+        assert_eq!(refusal(&limit_order(&mut exchange, "SELLER", Side::Sell, 1, 11.01)), Some("Price too far from the touch"));
+        accepted_order_id(&limit_order(&mut exchange, "SELLER", Side::Sell, 1, 11.0));
+        accepted_order_id(&limit_order(&mut exchange, "SELLER", Side::Sell, 1, 12.0));
+        assert_eq!(exchange.books["AAPL"].depth_levels(0), (vec![(Price::from(10.0), 1), (Price::from(9.0), 1)], vec![(Price::from(11.0), 1), (Price::from(12.0), 1)]));
+    }
+
+    #[test]
+    fn levels_the_touch_leaves_outside_the_window_are_grandfathered_and_can_still_be_joined() {
+        let mut exchange = touch_windowed(TouchWindowPolicy::Grandfather);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0);
+        assert_eq!(accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.5)), 3);
+
+        accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 2, 9.0));
+        assert_eq!(refusal(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.25)), Some("Price too far from the touch"));
+        assert_eq!(exchange.books["AAPL"].depth_levels(0).0, vec![(Price::from(10.5), 1), (Price::from(10.0), 1), (Price::from(9.0), 3)]);
+    }
+
+    #[test]
+    fn levels_the_touch_leaves_outside_the_window_are_cancelled_when_the_instrument_says_so() {
+        let mut exchange = touch_windowed(TouchWindowPolicy::Cancel);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        let first = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0));
+        let second = accepted_order_id(&limit_order(&mut exchange, "OTHER", Side::Buy, 2, 9.25));
+        let cash = exchange.accounts["OTHER"].cash;
+
+        let improved = limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.5);
+        let cancelled: Vec<_> = improved
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderCancelled { order_id, reason: CancelReason::Other(reason), .. } if reason == "Price level outside the touch window" => Some(*order_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cancelled, vec![first, second], "{:?}", improved);
+        assert_eq!(exchange.accounts["OTHER"].cash, cash + Price::from(18.5));
+        assert_eq!(exchange.books["AAPL"].depth_levels(0).0, vec![(Price::from(10.5), 1), (Price::from(10.0), 1)]);
+    }
+
+    #[test]
+    fn a_segment_session_roll_expires_only_its_day_orders() {
+        let segments: MarketSegments = toml::from_str("[segments.EQUITIES]\n[segments.FUTURES]\n").unwrap();
+        let mut exchange = Exchange::new().with_segments(segments);
+        for (instrument_id, segment) in [("AAPL", "EQUITIES"), ("ES", "FUTURES")] {
+            exchange.handle_message(EngineMessage::CreateInstrument {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: client("ADMIN"),
+                instrument_id: instrument_id.to_string(),
+                segment: Some(segment.to_string()),
+                spec: SpecOverrides::default(),
+                spread: None,
+            });
+        }
+        let equity = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        let future = accepted_order_id(&exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            client_order_id: None,
+            instrument_id: "ES".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(10.0)),
+            time_in_force: Some(TimeInForce::Day),
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        }));
+
+        let events = exchange.handle_message(EngineMessage::RollSession {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Segment("FUTURES".to_string()),
+        });
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderExpired { order_id, .. }] if *order_id == future), "{:?}", events);
+        assert_eq!(resting_order_ids(&exchange), vec![equity]);
+    }
+
+    #[test]
+    fn imbalance_alerts_fire_once_each_time_a_book_tips_past_its_threshold() {
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides { imbalance_alert_threshold: Some(3.0), ..SpecOverrides::default() },
+            spread: None,
+        });
+        exchange.handle_message(EngineMessage::SubscribeAlerts {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("SURVEILLANCE"),
+        });
+        let alerts = |events: &[EngineMessage]| -> Vec<(f64, ImbalanceDirection)> {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    EngineMessage::DepthImbalanceAlert { client_id, ratio, direction, .. } if *client_id == client("SURVEILLANCE") => {
+                        Some((*ratio, *direction))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // One-sided, then exactly at the threshold: no alert
+        assert!(alerts(&limit_order(&mut exchange, "BUYER", Side::Buy, 3, 10.0)).is_empty());
+        assert!(alerts(&limit_order(&mut exchange, "SELLER", Side::Sell, 1, 11.0)).is_empty());
+        assert_eq!(alerts(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0)), vec![(4.0, ImbalanceDirection::BidHeavy)]);
+        // Still bid-heavy, so no repeat
+        assert!(alerts(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0)).is_empty());
+
+        assert!(alerts(&limit_order(&mut exchange, "SELLER", Side::Sell, 4, 12.0)).is_empty());
+        assert_eq!(alerts(&limit_order(&mut exchange, "SELLER", Side::Sell, 15, 12.0)), vec![(0.25, ImbalanceDirection::AskHeavy)]);
+    }
+
+    #[test]
+    fn subscribers_get_a_fresh_liquidity_score_after_each_fill() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        for (quantity, price) in [(2, 10.0), (3, 11.0), (1, 12.0)] {
+            limit_order(&mut exchange, "SELLER", Side::Sell, quantity, price);
+        }
+        for (quantity, price) in [(2, 8.0), (4, 7.0)] {
+            limit_order(&mut exchange, "BUYER", Side::Buy, quantity, price);
+        }
+        subscribe(&mut exchange, "WATCHER", 1);
+        let scores = |events: &[EngineMessage]| -> Vec<LiquidityScore> {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    EngineMessage::LiquidityReport { client_id, score, .. } if *client_id == client("WATCHER") => Some(*score),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // Resting without a fill leaves the score as it was
+        assert!(scores(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 5.0)).is_empty());
+        assert_eq!(scores(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0)), vec![LiquidityScore {
+            spread: 2.0,
+            depth_bid: 7,
+            depth_ask: 5,
+            fill_rate: 0.0,
+            impact_cost_estimate: 1.0,
+        }]);
+
+        // Two more fills two seconds after the first; the last was for one share
+        advance_time(&mut exchange, "20240102-14:00:02.000");
+        assert_eq!(scores(&limit_order(&mut exchange, "BUYER", Side::Buy, 2, 11.0)), vec![LiquidityScore {
+            spread: 3.0,
+            depth_bid: 7,
+            depth_ask: 3,
+            fill_rate: 1.5,
+            impact_cost_estimate: 1.5,
+        }]);
+    }
+
+    #[test]
+    fn orders_under_a_client_ticker_trade_on_the_canonical_book() {
+        let mut ticker_map = TickerMap::default();
+        ticker_map.insert(client("SELLER"), "APPLE".to_string(), "AAPL".to_string());
+        let mut exchange = Exchange::new().with_ticker_map(ticker_map);
+        create_instrument(&mut exchange, "AAPL");
+        let apple = |exchange: &mut Exchange, account: &str| exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "APPLE".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Sell,
+            quantity: 1,
+            price: Some(Price::from(10.0)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        });
+
+        let resting = accepted_order_id(&apple(&mut exchange, "SELLER"));
+        let events = limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        assert!(events.iter().any(|event| matches!(event,
+            EngineMessage::OrderFilled { order_id, instrument_id, .. } if *order_id == resting && instrument_id == "AAPL"
+        )), "{:?}", events);
+
+        // The ticker is SELLER's alone
+        let events = apple(&mut exchange, "OTHER");
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { .. }]), "{:?}", events);
+    }
+
+    #[test]
+    fn a_warming_up_book_takes_crossing_orders_and_trades_them_only_once_it_opens() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        exchange.handle_message(EngineMessage::StartWarmUp {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Instrument("AAPL".to_string()),
+            open_time: at("20240102-14:30:00.000"),
+        });
+
+        let early = accepted_order_id(&limit_order(&mut exchange, "EARLY", Side::Buy, 2, 11.0));
+        let late = accepted_order_id(&limit_order(&mut exchange, "LATE", Side::Buy, 3, 10.0));
+        let events = limit_order(&mut exchange, "SELLER", Side::Sell, 4, 10.0);
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderAccepted { .. }]), "{:?}", events);
+        let seller = accepted_order_id(&events);
+        let events = timed_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0, Some(TimeInForce::ImmediateOrCancel), None);
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { .. }]), "{:?}", events);
+        assert!(advance_time(&mut exchange, "20240102-14:29:59.999").is_empty());
+
+        // The best bid trades first, each at the resting ask's price
+        let fills: Vec<(OrderID, Quantity, Price)> = advance_time(&mut exchange, "20240102-14:30:00.000")
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderFilled { order_id, filled_quantity, price, .. } => Some((*order_id, *filled_quantity, *price)),
+                _ => None,
+            })
+            .collect();
+        let ten = Price::from(10.0);
+        assert_eq!(fills, vec![(early, 2, ten), (seller, 2, ten), (late, 2, ten), (seller, 2, ten)]);
+        assert_eq!(resting_order_ids(&exchange), vec![late]);
+
+        // Open now, so a crossing order trades on arrival
+        let events = limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+        assert!(events.iter().any(|event| matches!(event, EngineMessage::OrderFilled { order_id, .. } if *order_id == late)), "{:?}", events);
+    }
+
+    fn unpriced_order(exchange: &mut Exchange, account: &str, order_type: OrdType, quantity: Quantity, price: Option<f64>) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type,
+            side: Side::Buy,
+            quantity,
+            price: price.map(Price::from),
+            time_in_force: Some(TimeInForce::Day),
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn a_market_order_cancels_what_it_cannot_fill_instead_of_resting_at_zero() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+
+        let events = unpriced_order(&mut exchange, "BUYER", OrdType::Market, 5, None);
+        assert!(matches!(events.last(), Some(EngineMessage::OrderCancelled { cancelled_price: None, cancelled_quantity: 3, .. })), "{:?}", events);
+        // Only the fills were paid for
+        assert_eq!(exchange.accounts["BUYER"].cash, Price::from(980.0));
+
+        // Into an empty book it cancels outright, still leaving nothing behind
+        let events = unpriced_order(&mut exchange, "BUYER", OrdType::Market, 1, None);
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderAccepted { .. }, EngineMessage::OrderCancelled { .. }]), "{:?}", events);
+        assert!(resting_order_ids(&exchange).is_empty());
+        assert!(exchange.books["AAPL"].bids.is_empty());
+        assert_eq!(exchange.accounts["BUYER"].cash, Price::from(980.0));
+    }
+
+    #[test]
+    fn a_triggered_stop_trades_as_a_market_order_and_returns_its_reserve() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+
+        // Stop at 9 with the best ask at 10: triggered on arrival
+        let events = unpriced_order(&mut exchange, "BUYER", OrdType::Stop, 3, Some(9.0));
+        let filled: Quantity = events.iter().filter_map(|event| match event {
+            EngineMessage::OrderFilled { client_id, filled_quantity, .. } if *client_id == client("BUYER") => Some(*filled_quantity),
+            _ => None,
+        }).sum();
+        assert_eq!(filled, 2);
+        assert!(matches!(events.last(), Some(EngineMessage::OrderCancelled { cancelled_price: None, cancelled_quantity: 1, .. })), "{:?}", events);
+        assert!(resting_order_ids(&exchange).is_empty());
+        assert_eq!(exchange.accounts["BUYER"].cash, Price::from(980.0));
+    }
+
+    #[test]
+    fn scheduled_messages_fire_in_time_order_once_simulated_time_reaches_them() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        let resting = accepted_order_id(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0));
+        let bid = |price: f64| EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(price)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        };
+        let schedule = |exchange: &mut Exchange, scheduler: &str, fire_at: &str, message: EngineMessage| exchange.handle_message(EngineMessage::Schedule {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(scheduler),
+            fire_at: at(fire_at),
+            message: Box::new(message),
+        });
+
+        // Scheduled out of order, to fire in order
+        let cancel = EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            order_id: resting,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        };
+        assert!(schedule(&mut exchange, "BUYER", "20240102-14:30:00.000", cancel).is_empty());
+        assert!(schedule(&mut exchange, "BUYER", "20240102-14:10:00.000", bid(9.0)).is_empty());
+        let events = schedule(&mut exchange, "OTHER", "20240102-14:10:00.000", bid(8.0));
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { .. }]), "{:?}", events);
+
+        assert!(advance_time(&mut exchange, "20240102-14:05:00.000").is_empty());
+        let events = advance_time(&mut exchange, "20240102-14:30:00.000");
+        assert!(matches!(events.as_slice(), [
+            EngineMessage::OrderAccepted { .. },
+            EngineMessage::OrderCancelled { order_id, .. },
+        ] if *order_id == resting), "{:?}", events);
+        assert_eq!(resting_order_ids(&exchange), vec![resting + 1]);
+        assert!(exchange.scheduled_messages.is_empty());
+
+        // One already due goes straight through
+        let events = schedule(&mut exchange, "BUYER", "20240102-14:00:00.000", bid(7.0));
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderAccepted { .. }]), "{:?}", events);
+    }
+
+    #[test]
+    fn every_rejection_is_logged_with_what_was_asked_for_and_kept_across_a_restart() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        limit_order(&mut exchange, "BUYER", Side::Buy, 2_000, 10.0);
+        let unsubscribe = exchange.handle_message(EngineMessage::UnsubscribeOrderBook {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("WATCHER"),
+            symbols: vec!["AAPL".to_string()],
+        });
+        assert!(matches!(unsubscribe.as_slice(), [EngineMessage::OrderRejected { .. }]), "{:?}", unsubscribe);
+
+        // A scheduled order is logged once, when it fires, as the order it was
+        let unfunded = EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            account_id: "BUYER".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 500,
+            price: Some(Price::from(3.0)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        };
+        exchange.handle_message(EngineMessage::Schedule {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("BUYER"),
+            fire_at: at("20240102-14:30:00.000"),
+            message: Box::new(unfunded),
+        });
+        advance_time(&mut exchange, "20240102-14:30:00.000");
+
+        let entry = |time: &str, client_id: &str, account_id: &str, reason: &str, price: Option<f64>, quantity: Option<Quantity>| RejectionEntry {
+            timestamp: at(time),
+            client_id: client(client_id),
+            account_id: account_id.to_string(),
+            instrument_id: Some("AAPL".to_string()),
+            reason: reason.to_string(),
+            original_price: price.map(Price::from),
+            original_qty: quantity,
+        };
+        assert_eq!(exchange.rejection_log, vec![
+            entry("20240102-14:00:00.000", "BUYER", "BUYER", "Insufficient funds", Some(10.0), Some(2_000)),
+            entry("20240102-14:00:00.000", "WATCHER", "", "Not subscribed", None, None),
+            entry("20240102-14:30:00.000", "BUYER", "BUYER", "Insufficient funds", Some(3.0), Some(500)),
+        ]);
+
+        let query = exchange.handle_message(EngineMessage::RejectionLogQuery {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            since: at("20240102-14:30:00.000"),
+        });
+        assert!(matches!(query.as_slice(), [EngineMessage::RejectionLogReport { entries, .. }] if *entries == exchange.rejection_log[2..]), "{:?}", query);
+        assert_eq!(exchange.persistent_state().rejection_log, exchange.rejection_log);
+    }
+
+    #[test]
+    fn a_client_is_cut_off_once_its_days_turnover_would_pass_the_session_limit() {
+        let mut exchange = Exchange::new().with_max_session_notional(50.0);
+        create_instrument(&mut exchange, "AAPL");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        let rejected = |events: &[EngineMessage]| matches!(events, [EngineMessage::OrderRejected { reason, .. }] if reason == "Session notional limit exceeded");
+
+        limit_order(&mut exchange, "SELLER", Side::Sell, 5, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 3, 10.0);
+        // Exactly at the limit is allowed
+        assert!(!rejected(&limit_order(&mut exchange, "BUYER", Side::Buy, 2, 10.0)));
+        assert!(rejected(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 1.0)));
+        // Both sides of a fill count toward their own client's turnover
+        assert!(rejected(&limit_order(&mut exchange, "SELLER", Side::Sell, 1, 20.0)));
+
+        // A market order is estimated at what it would cost to sweep the book
+        limit_order(&mut exchange, "MAKER", Side::Sell, 3, 10.0);
+        limit_order(&mut exchange, "MAKER", Side::Sell, 2, 20.0);
+        let events = unpriced_order(&mut exchange, "TAKER", OrdType::Market, 5, None);
+        assert!(rejected(&events), "{:?}", events);
+        assert!(!rejected(&unpriced_order(&mut exchange, "TAKER", OrdType::Market, 3, None)));
+
+        // A new day starts every client afresh
+        advance_time(&mut exchange, "20240103-09:30:00.000");
+        assert!(!rejected(&limit_order(&mut exchange, "BUYER", Side::Buy, 1, 1.0)));
+    }
+
+    #[test]
+    fn symbol_status_gathers_each_books_phase_halt_last_trade_and_levels() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        create_instrument(&mut exchange, "MSFT");
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        limit_order(&mut exchange, "SELLER", Side::Sell, 2, 10.0);
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 11.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        limit_order(&mut exchange, "BUYER", Side::Buy, 1, 9.0);
+        exchange.handle_message(EngineMessage::SetTradingStatus {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Instrument("MSFT".to_string()),
+            halted: true,
+        });
+        exchange.handle_message(EngineMessage::StartWarmUp {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Instrument("MSFT".to_string()),
+            open_time: at("20240102-14:30:00.000"),
+        });
+        let status = |exchange: &mut Exchange, instrument_id: Option<&str>| exchange.handle_message(EngineMessage::SymbolStatusRequest {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("OPERATOR"),
+            instrument_id: instrument_id.map(str::to_string),
+        });
+
+        let apple = InstrumentStatusEntry {
+            instrument_id: "AAPL".to_string(),
+            phase: InstrumentPhase::Open,
+            circuit_breaker: false,
+            luld_band: None,
+            last_price: Some(Price::from(10.0)),
+            bid_levels: 1,
+            ask_levels: 2,
+        };
+        let microsoft = InstrumentStatusEntry {
+            instrument_id: "MSFT".to_string(),
+            phase: InstrumentPhase::WarmUp { opens_at: at("20240102-14:30:00.000") },
+            circuit_breaker: true,
+            luld_band: None,
+            last_price: None,
+            bid_levels: 0,
+            ask_levels: 0,
+        };
+        let events = status(&mut exchange, None);
+        assert!(matches!(events.as_slice(), [EngineMessage::SymbolStatusReport { client_id, reports }]
+            if *client_id == client("OPERATOR") && *reports == vec![apple.clone(), microsoft.clone()]
+        ), "{:?}", events);
+        let events = status(&mut exchange, Some("MSFT"));
+        assert!(matches!(events.as_slice(), [EngineMessage::SymbolStatusReport { reports, .. }] if *reports == vec![microsoft.clone()]), "{:?}", events);
+        let events = status(&mut exchange, Some("GOLD"));
+        assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown instrument"), "{:?}", events);
+    }
+
+    #[test]
+    fn a_ping_is_answered_at_once_with_its_times_and_the_exchange_time() {
+        use crate::fix::serialize_engine_message;
+        use crate::fix::testkit::{Outbound, Ping};
+
+        let mut exchange = Exchange::new();
+        let ping = Ping::builder().sender("FIRM1").sending_time("20240102-14:30:00.125").ping_id(42).parse();
+        let EngineMessage::Ping { received_at, .. } = &ping else { panic!("{:?}", ping) };
+        let received_at = received_at.clone();
+        let events = exchange.handle_message(ping);
+        let [EngineMessage::Pong { client_id, ping_id, sent_at, received_at: echoed, exchange_time }] = events.as_slice() else { panic!("{:?}", events) };
+        assert_eq!((client_id, *ping_id, sent_at, echoed), (&client("FIRM1"), 42, &at("20240102-14:30:00.125"), &received_at));
+        assert!(timestamp_key(exchange_time) >= timestamp_key(&received_at));
+
+        let pong = Outbound::parse(&serialize_engine_message(&events[0]).unwrap());
+        assert_eq!(pong.msg_type(), "UPO");
+        pong.expect(8040u32, 42u64).expect(ORIG_SENDING_TIME, at("20240102-14:30:00.125")).expect(TRANSACT_TIME, exchange_time.clone());
+    }
+
+    #[test]
+    fn instrument_metadata_is_kept_per_instrument_and_left_out_of_matching() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        create_instrument(&mut exchange, "MSFT");
+        let set = |exchange: &mut Exchange, instrument_id: &str, key: &str, value: &str| exchange.handle_message(EngineMessage::SetInstrumentMetadata {
+            client_id: client("REFDATA"),
+            instrument_id: instrument_id.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+        let get = |exchange: &mut Exchange, instrument_id: &str| exchange.handle_message(EngineMessage::GetInstrumentMetadata {
+            client_id: client("ANALYST"),
+            instrument_id: instrument_id.to_string(),
+        });
+        let set_sector = set(&mut exchange, "AAPL", "sector", "Technology");
+        assert!(matches!(set_sector.as_slice(), [EngineMessage::LogEvent { message, .. }] if message == "AAPL sector set to Technology"), "{:?}", set_sector);
+        set(&mut exchange, "AAPL", "currency", "EUR");
+        set(&mut exchange, "AAPL", "currency", "USD");
+        set(&mut exchange, "MSFT", "sector", "Software");
+
+        let events = get(&mut exchange, "AAPL");
+        let expected = BTreeMap::from([("currency".to_string(), "USD".to_string()), ("sector".to_string(), "Technology".to_string())]);
+        assert!(matches!(events.as_slice(), [EngineMessage::InstrumentMetadataResponse { client_id, instrument_id, metadata }]
+            if *client_id == client("ANALYST") && instrument_id == "AAPL" && *metadata == expected
+        ), "{:?}", events);
+        // Trading goes on as before
+        limit_order(&mut exchange, "SELLER", Side::Sell, 1, 10.0);
+        let trade = limit_order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+        assert!(trade.iter().any(|event| matches!(event, EngineMessage::OrderFilled { remaining_quantity: 0, .. })), "{:?}", trade);
+
+        for events in [get(&mut exchange, "GOLD"), set(&mut exchange, "GOLD", "sector", "Metals")] {
+            assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown instrument"), "{:?}", events);
+        }
+    }
+
+    // AAPL on a 0.5 tick with the clock at 14:30, on trees or, given a range, on ladders over it
+    fn tick_book(ladder: Option<&str>) -> Exchange {
+        let mut exchange = Exchange::new();
+        advance_time(&mut exchange, "20240102-14:30:00.000");
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides { tick_size: Some(Price::from(0.5)), ..SpecOverrides::default() },
+            spread: None,
+        });
+        if let Some(range) = ladder {
+            set_price_ladder(&mut exchange, range);
+        }
+        exchange
+    }
+
+    fn set_price_ladder(exchange: &mut Exchange, range: &str) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::SetInstrumentMetadata {
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            key: PRICE_LADDER.to_string(),
+            value: range.to_string(),
+        })
+    }
+
+    // Levels opened on both sides, a sweep through several, cancels from inside a side and at
+    // its touch, and a new best price on each side
+    fn ladder_scenario(exchange: &mut Exchange) -> Vec<EngineMessage> {
+        let mut events = Vec::new();
+        for (account, side, quantity, price) in [
+            ("SELLER1", Side::Sell, 3, 11.0),
+            ("SELLER2", Side::Sell, 2, 10.5),
+            ("SELLER3", Side::Sell, 4, 12.0),
+            ("BUYER1", Side::Buy, 5, 9.5),
+            ("BUYER2", Side::Buy, 1, 10.0),
+            ("BUYER3", Side::Buy, 2, 9.0),
+        ] {
+            events.extend(limit_order(exchange, account, side, quantity, price));
+        }
+        events.extend(cancel(exchange, "BUYER1", 4));
+        events.extend(limit_order(exchange, "TAKER1", Side::Buy, 6, 11.5));
+        events.extend(limit_order(exchange, "TAKER2", Side::Sell, 4, 9.0));
+        events.extend(cancel(exchange, "SELLER3", 3));
+        events.extend(limit_order(exchange, "SELLER4", Side::Sell, 1, 9.5));
+        events
+    }
+
+    // What the book sent and how it stands after, to compare one backend with the other
+    fn outcome(exchange: &Exchange, events: &[EngineMessage]) -> String {
+        format!("{:?} {:?}", events, exchange.books["AAPL"].depth_orders(0))
+    }
+
+    #[test]
+    fn a_book_on_price_ladders_trades_as_one_on_trees_does() {
+        let mut trees = tick_book(None);
+        let mut ladders = tick_book(Some("5..15"));
+        assert!(ladders.books["AAPL"].bids.is_ladder() && ladders.books["AAPL"].asks.is_ladder());
+
+        let (on_trees, on_ladders) = (ladder_scenario(&mut trees), ladder_scenario(&mut ladders));
+        assert!(on_trees.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { .. })).count() >= 6, "{:?}", on_trees);
+        assert_eq!(outcome(&ladders, &on_ladders), outcome(&trees, &on_trees));
+        assert!(ladders.books["AAPL"].bids.is_ladder() && ladders.books["AAPL"].asks.is_ladder());
+    }
+
+    #[test]
+    fn a_price_beyond_the_ladder_moves_its_side_onto_a_tree_and_trading_goes_on() {
+        let mut trees = tick_book(None);
+        let mut ladders = tick_book(Some("9..12"));
+        // An offer at 14 leaves the ladder's range, then is taken
+        let scenario = |exchange: &mut Exchange| {
+            let mut events = ladder_scenario(exchange);
+            events.extend(limit_order(exchange, "SELLER5", Side::Sell, 1, 14.0));
+            events.extend(limit_order(exchange, "TAKER3", Side::Buy, 2, 14.0));
+            events
+        };
+        let (on_trees, on_ladders) = (scenario(&mut trees), scenario(&mut ladders));
+        assert!(!ladders.books["AAPL"].asks.is_ladder() && ladders.books["AAPL"].bids.is_ladder());
+        assert_eq!(outcome(&ladders, &on_ladders), outcome(&trees, &on_trees));
+    }
+
+    #[test]
+    fn a_price_ladder_needs_the_book_to_have_a_tick_and_a_range_to_cover() {
+        let mut exchange = Exchange::new();
+        create_instrument(&mut exchange, "AAPL");
+        let rejection = |events: Vec<EngineMessage>| match events.as_slice() {
+            [EngineMessage::OrderRejected { reason, .. }] => reason.clone(),
+            _ => panic!("not rejected: {:?}", events),
+        };
+        assert_eq!(rejection(set_price_ladder(&mut exchange, "5..15")), "A price ladder needs a tick size");
+
+        let mut exchange = tick_book(None);
+        for range in ["15..5", "5-15", "5..", "0..1000000"] {
+            assert_eq!(rejection(set_price_ladder(&mut exchange, range)), "Invalid price ladder range", "{}", range);
+        }
+        assert!(!exchange.books["AAPL"].bids.is_ladder());
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use fefix::definitions::fix50::Side;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::types::*;
+
+// The market an order arrived into: its side and the best bid and ask it saw, before it traded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ArrivalTouch {
+    pub(crate) side: Side,
+    pub(crate) bid: Option<Price>,
+    pub(crate) ask: Option<Price>,
+}
+
+impl ArrivalTouch {
+    // Twice the fill's distance from the midpoint at arrival, None unless both sides were quoted
+    pub(crate) fn effective_spread(&self, price: Price) -> Option<f64> {
+        let midpoint = (self.bid?.into_inner() + self.ask?.into_inner()) / 2.0;
+        Some(2.0 * (price.into_inner() - midpoint).abs())
+    }
+
+    // How much better than the opposite touch at arrival the fill was, per share: below the
+    // ask for a buy, above the bid for a sell. Negative for a fill through the touch.
+    pub(crate) fn price_improvement(&self, price: Price) -> Option<f64> {
+        match self.side {
+            Side::Buy => Some(self.ask?.into_inner() - price.into_inner()),
+            _ => Some(price.into_inner() - self.bid?.into_inner()),
+        }
+    }
+}
+
+// How well a set of fills executed. Averages are weighted by quantity, over the fills the
+// arrival touch had the quotes for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExecutionQuality {
+    pub fills: u64,
+    pub quantity: Quantity,
+    pub effective_spread: Option<f64>,
+    pub price_improvement: Option<f64>,
+    #[serde(skip)]
+    spread_totals: (Quantity, f64), // (quantity, effective spread * quantity)
+    #[serde(skip)]
+    improvement_totals: (Quantity, f64),
+}
+
+impl ExecutionQuality {
+    fn record(&mut self, touch: &ArrivalTouch, price: Price, quantity: Quantity) {
+        self.fills += 1;
+        self.quantity += quantity;
+        if let Some(spread) = touch.effective_spread(price) {
+            self.effective_spread = Some(weigh_in(&mut self.spread_totals, spread, quantity));
+        }
+        if let Some(improvement) = touch.price_improvement(price) {
+            self.price_improvement = Some(weigh_in(&mut self.improvement_totals, improvement, quantity));
+        }
+    }
+}
+
+// Adds `value` for `quantity` to running totals and returns the new average
+fn weigh_in(totals: &mut (Quantity, f64), value: f64, quantity: Quantity) -> f64 {
+    totals.0 += quantity;
+    totals.1 += value * quantity as f64;
+    totals.1 / totals.0 as f64
+}
+
+// One session's execution quality. Every order's fills count, so each trade counts once for
+// its buyer and once for its seller.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionStatistics {
+    pub by_instrument: BTreeMap<InstrumentID, ExecutionQuality>,
+    pub by_client: BTreeMap<String, ExecutionQuality>, // by ClientID as text, e.g. "FIRM1::ALGO"
+}
+
+impl SessionStatistics {
+    // One line per instrument and client, for the end-of-day summary
+    pub fn summary(&self) -> Vec<String> {
+        let line = |kind: &str, name: &str, quality: &ExecutionQuality| {
+            let average = |value: Option<f64>| value.map_or("n/a".to_string(), |value| format!("{:.4}", value));
+            format!(
+                "{} {}: {} fills, {} filled, effective spread {}, price improvement {}",
+                kind,
+                name,
+                quality.fills,
+                quality.quantity,
+                average(quality.effective_spread),
+                average(quality.price_improvement)
+            )
+        };
+        let instruments = self.by_instrument.iter().map(|(instrument_id, quality)| line("Instrument", instrument_id, quality));
+        let clients = self.by_client.iter().map(|(client_id, quality)| line("Client", client_id, quality));
+        instruments.chain(clients).collect()
+    }
+}
+
+// The running session's statistics, kept by the matching thread and read by the admin API
+#[derive(Debug, Default)]
+pub struct ExecutionStatistics {
+    session: Mutex<SessionStatistics>,
+}
+
+impl ExecutionStatistics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn session(&self) -> SessionStatistics {
+        self.session.lock().clone()
+    }
+
+    pub(crate) fn record(&self, instrument_id: &InstrumentID, client_id: &ClientID, touch: &ArrivalTouch, price: Price, quantity: Quantity) {
+        let mut session = self.session.lock();
+        session.by_instrument.entry(instrument_id.clone()).or_default().record(touch, price, quantity);
+        session.by_client.entry(client_id.to_string()).or_default().record(touch, price, quantity);
+    }
+
+    // Closes the session, handing back its statistics and starting the next from nothing
+    pub(crate) fn end_session(&self) -> SessionStatistics {
+        std::mem::take(&mut *self.session.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fefix::definitions::fix50::OrdType;
+    use fefix::fix_values::Timestamp;
+
+    use super::*;
+    use crate::engine::EngineMessage;
+    use crate::exchange::Exchange;
+    use crate::fix::testkit::{advance_time, client};
+    use crate::instrument::SpecOverrides;
+
+    fn limit_order(exchange: &mut Exchange, account: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client(account),
+            account_id: account.to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side,
+            quantity,
+            price: Some(Price::from(price)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        })
+    }
+
+    // Bids 9, asks 11 and 12: a spread of 2 around a midpoint of 10
+    fn scripted_book() -> Exchange {
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+        advance_time(&mut exchange, "20240102-14:00:00.000");
+        limit_order(&mut exchange, "BIDDER", Side::Buy, 5, 9.0);
+        limit_order(&mut exchange, "OFFERER", Side::Sell, 2, 11.0);
+        limit_order(&mut exchange, "OFFERER", Side::Sell, 2, 12.0);
+        exchange
+    }
+
+    fn arrival_touches(events: &[EngineMessage]) -> Vec<(Price, Option<Price>, Option<Price>)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::OrderFilled { price, arrival_bid, arrival_ask, .. } => Some((*price, *arrival_bid, *arrival_ask)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_order_resting_at_the_arrival_midpoint_pays_no_spread_and_improves_on_the_touch() {
+        let mut exchange = scripted_book();
+        let statistics = exchange.execution_statistics();
+        limit_order(&mut exchange, "MIDPOINT", Side::Sell, 1, 10.0);
+        let events = limit_order(&mut exchange, "TAKER", Side::Buy, 1, 10.0);
+
+        // Each side's fill report carries the touch its own order arrived into
+        let (nine, ten, eleven) = (Some(Price::from(9.0)), Price::from(10.0), Some(Price::from(11.0)));
+        assert_eq!(arrival_touches(&events), vec![(ten, nine, Some(ten)), (ten, nine, eleven)]);
+
+        let session = statistics.session();
+        let midpoint = &session.by_client["MIDPOINT"];
+        assert_eq!((midpoint.fills, midpoint.quantity, midpoint.effective_spread, midpoint.price_improvement), (1, 1, Some(0.0), Some(1.0)));
+        // The taker arrived to a spread of 9 to 10, so paid half of it and bought at the ask
+        let taker = &session.by_client["TAKER"];
+        assert_eq!((taker.effective_spread, taker.price_improvement), (Some(1.0), Some(0.0)));
+        let instrument = &session.by_instrument["AAPL"];
+        assert_eq!((instrument.fills, instrument.quantity, instrument.effective_spread, instrument.price_improvement), (2, 2, Some(0.5), Some(0.5)));
+    }
+
+    #[test]
+    fn a_sweep_through_the_touch_is_worse_than_it_for_the_levels_past_it() {
+        let mut exchange = scripted_book();
+        let statistics = exchange.execution_statistics();
+        limit_order(&mut exchange, "SWEEPER", Side::Buy, 4, 12.0);
+
+        // 2 at 11, the touch, and 2 at 12, one through it; both against a midpoint of 10
+        let sweeper = &statistics.session().by_client["SWEEPER"];
+        assert_eq!((sweeper.fills, sweeper.quantity), (2, 4));
+        assert_eq!(sweeper.effective_spread, Some(3.0));
+        assert_eq!(sweeper.price_improvement, Some(-0.5));
+
+        // The day's statistics are summed up when it ends, and the next starts from nothing
+        let summary = advance_time(&mut exchange, "20240103-09:30:00.000");
+        let summaries: Vec<&String> = summary
+            .iter()
+            .filter_map(|event| match event {
+                EngineMessage::LogEvent { client_id: None, message } if message.starts_with("End of day") => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(summaries.len(), 1, "{:?}", summary);
+        assert!(summaries[0].contains("Client SWEEPER: 2 fills, 4 filled, effective spread 3.0000, price improvement -0.5000"), "{}", summaries[0]);
+        assert!(summaries[0].contains("Instrument AAPL: 4 fills, 8 filled"), "{}", summaries[0]);
+        assert_eq!(statistics.session(), SessionStatistics::default());
+    }
+}
@@ -1,22 +1,269 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use bytes::{Bytes, BytesMut};
+
 use fefix::{prelude::*};
-use fefix::tagvalue::{Decoder, Config};
+use fefix::tagvalue::{Decoder, Config, Message};
+use fefix::dict::{FixDatatype, IsFieldDefinition};
 use fefix::definitions::fix50::*;
 use fefix::fix_values::Timestamp;
 
 use crate::types::*;
-use crate::engine::EngineMessage;
+use crate::framing::RawMessage;
+use crate::engine::{AggressorSide, BookChange, BookGranularity, CancelReason, EngineMessage, ImbalanceDirection, InstrumentPhase, InstrumentScope, MultilegLeg, ReportTimes, RiskLimits, SmpAction, Stipulation};
+use crate::instrument::{CorporateAction, HaltPolicy, OptionType, OptionsSpec, OrderType, PriceLevelPolicy, SpecOverrides, SpreadDefinition, SpreadLeg, TouchWindowPolicy, UncrossPolicy};
+use crate::config::{logs, LogLevel};
+use crate::credentials::LogonCredentials;
+use crate::custom_fields::*;
+use crate::heartbeat::ExchangeStatus;
+use crate::clock::{self, exchange_now, timestamp_now};
+use crate::wire::{buffer_pool, restamp_checksum, FixWriter};
+
+#[cfg(test)]
+pub(crate) mod testkit;
+
+const BEGIN_STRING: &[u8] = b"FIXT.1.1";
+const EXCHANGE_COMP_ID: &str = "EXCHANGE";
+
+// MarketSegmentID(1300), LowLimitPrice(1148) and HighLimitPrice(1149) are from FIX 5.0 SP1,
+// newer than the dictionary
+const MARKET_SEGMENT_ID: u32 = 1300;
+const LOW_LIMIT_PRICE: u32 = 1148;
+const HIGH_LIMIT_PRICE: u32 = 1149;
+// Volatility(1188), also FIX 5.0 SP1, is an option's implied volatility
+const VOLATILITY: u32 = 1188;
+// TimeInForce(59) Good for Auction, from a later FIX than the dictionary's TimeInForce
+const GOOD_FOR_AUCTION: &str = "B";
+
+// The repeating groups the exchange reads, each with the tags its entries hold. fefix's
+// dictionary lists no component's fields, so it cannot say where a group ends.
+const REPEATING_GROUPS: &[(u32, &[u32])] = &[
+    (146, &[55]), // NoRelatedSym: Symbol
+    (232, &[233, 234]), // NoStipulations: StipulationType, StipulationValue
+    (555, &[600, 624, 687, 566]), // NoLegs: LegSymbol, LegSide, LegQty, LegPrice
+];
+
+// FIX's own field separator, which FIX engines send unless told otherwise
+const SOH: char = '\x01';
+// ApplVerID(1128) and DefaultApplVerID(1137) values the FIX 5.0 dictionary covers: FIX50, FIX50SP1, FIX50SP2
+const FIX50_APPL_VER_IDS: [&str; 3] = ["7", "8", "9"];
+
+// How closely inbound messages are held to FIXT.1.1. --lenient-fix makes it Lenient.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Conformance {
+    #[default]
+    Strict, // a message that deviates at all is refused
+    Lenient, // the deviations real clients commonly make are logged and looked past
+}
+
+// The field separator a session speaks, settled by its first message: '|' unless the
+// client opens with SOH, as FIX engines do out of the box
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Separator {
+    #[default]
+    Pipe,
+    Soh,
+}
+
+impl Separator {
+    pub fn of(message: &str) -> Self {
+        if message.contains(SOH) { Self::Soh } else { Self::Pipe }
+    }
+
+    // Outgoing messages, one per line, rewritten to this separator with checksums to match.
+    // Messages no other session holds are rewritten where they lie. A FIX engine frames on
+    // the SOH after CheckSum, so SOH messages lose the newline that would read as junk.
+    pub fn apply(self, messages: Bytes) -> Bytes {
+        if self == Self::Pipe {
+            return messages;
+        }
+        let mut messages = messages.try_into_mut().unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+        for line in messages.split_mut(|byte| *byte == b'\n') {
+            line.iter_mut().filter(|byte| **byte == b'|').for_each(|byte| *byte = SOH as u8);
+            restamp_checksum(line, SOH as u8);
+        }
+        let mut kept = 0;
+        for at in 0..messages.len() {
+            if messages[at] != b'\n' {
+                messages[kept] = messages[at];
+                kept += 1;
+            }
+        }
+        messages.truncate(kept);
+        messages.freeze()
+    }
+}
+
+// Sum of the bytes a CheckSum(10) covers, mod 256
+fn checksum(text: &str) -> u8 {
+    text.bytes().fold(0, u8::wrapping_add)
+}
+
+// Recomputes a message's CheckSum after its text has been changed
+fn with_checksum(line: &str, separator: char) -> String {
+    let (message, newline) = line.strip_suffix('\n').map_or((line, ""), |message| (message, "\n"));
+    match message.rfind(&format!("{}10=", separator)) {
+        Some(at) => {
+            let covered = &message[..at + separator.len_utf8()];
+            format!("{}10={:03}{}{}", covered, checksum(covered), separator, newline)
+        }
+        None => line.to_string(),
+    }
+}
+
+// Everything about a message that strays from FIXT.1.1 without making it unreadable
+fn deviations(message: &str, separator: char) -> Vec<String> {
+    let mut deviations = Vec::new();
+    let fields = message.strip_suffix(separator).unwrap_or(message);
+    for (tag, value) in fields.split(separator).filter_map(|field| field.split_once('=')) {
+        match tag {
+            "8" if value != "FIXT.1.1" => deviations.push(format!("BeginString {} is not FIXT.1.1", value)),
+            "1128" if !FIX50_APPL_VER_IDS.contains(&value) => deviations.push(format!("ApplVerID {} is not FIX 5.0", value)),
+            "1137" if !FIX50_APPL_VER_IDS.contains(&value) => deviations.push(format!("DefaultApplVerID {} is not FIX 5.0", value)),
+            _ => {}
+        }
+    }
+    if let Some(at) = fields.rfind(&format!("{}10=", separator)) {
+        let (covered, value) = (&fields[..at + separator.len_utf8()], &fields[at + separator.len_utf8() + 3..]);
+        let expected = checksum(covered);
+        if value.len() != 3 || !value.bytes().all(|b| b.is_ascii_digit()) {
+            deviations.push(format!("CheckSum {} is not three digits", value));
+        } else if value.parse::<u8>().ok() != Some(expected) {
+            deviations.push(format!("CheckSum {} should be {:03}", value, expected));
+        }
+    }
+    if !message.ends_with(separator) {
+        deviations.push("No separator after CheckSum".to_string());
+    }
+    deviations
+}
+
+// The message as the parser reads it, '|'-separated whichever separator the client used.
+// A strict exchange refuses a message with any deviation, saying why; a lenient one logs
+// each, puts right what the parser could not read past, and carries on.
+pub fn conform(message: &str, conformance: Conformance) -> Result<Cow<'_, str>, String> {
+    let separator = match Separator::of(message) {
+        Separator::Pipe => '|',
+        Separator::Soh => SOH,
+    };
+    let deviations = deviations(message, separator);
+    let mut conformed = match separator {
+        SOH => Cow::Owned(message.replace(SOH, "|")),
+        _ => Cow::Borrowed(message),
+    };
+    if deviations.is_empty() {
+        return Ok(conformed);
+    }
+    if conformance == Conformance::Strict {
+        return Err(deviations.join("; "));
+    }
+    if logs(LogLevel::Warn) {
+        for deviation in &deviations {
+            eprintln!("Tolerated FIX deviation: {}", deviation);
+        }
+    }
+    if !conformed.ends_with('|') {
+        conformed.to_mut().push('|');
+    }
+    // The parser needs a three-digit CheckSum, though it never checks the sum itself
+    Ok(Cow::Owned(with_checksum(&conformed, '|')))
+}
+
+// Where the first field that is not tag=value starts, as the decoder does not say where it
+// gave up. None if every field is well formed, and the fault lies elsewhere.
+fn malformed_field(message: &str) -> Option<usize> {
+    let mut start = 0;
+    for field in message.split(['|', SOH]) {
+        let tag = field.split_once('=').map(|(tag, _)| tag).unwrap_or_default();
+        if !field.is_empty() && (tag.is_empty() || !tag.bytes().all(|byte| byte.is_ascii_digit())) {
+            return Some(start);
+        }
+        start += field.len() + 1;
+    }
+    None
+}
+
+// The namespace a session addresses with its TargetCompID(56); the exchange's own CompID, or none, is the default one
+fn namespace_of(target_comp_id: Option<&str>) -> Option<Namespace> {
+    target_comp_id.filter(|target| *target != EXCHANGE_COMP_ID).map(str::to_string)
+}
+
+// The message with the repeating groups the exchange reads moved behind its other fields, in
+// the order they came. fefix never closes a group, reading whatever follows it as part of its
+// last entry, so a field after one would otherwise go missing. A NumInGroup that is not a
+// count is refused here, as the decoder would panic on it.
+fn groups_last<'a>(dict: &Dictionary, message: &'a str) -> Result<Cow<'a, str>, String> {
+    let body = message.strip_suffix('|').unwrap_or(message);
+    let tag_of = |field: &str| field.split_once('=').and_then(|(tag, _)| tag.parse::<u32>().ok());
+    for (tag, value) in body.split('|').filter_map(|field| Some((tag_of(field)?, field.split_once('=')?.1))) {
+        if let Some(field) = dict.field_by_tag(tag).filter(|field| field.fix_datatype() == FixDatatype::NumInGroup) {
+            if value.parse::<usize>().is_err() {
+                return Err(format!("Invalid {}({})", field.name(), tag));
+            }
+        }
+    }
+    let members = |tag| REPEATING_GROUPS.iter().find(|(group, _)| *group == tag).map(|(_, members)| *members);
+    if !body.split('|').any(|field| tag_of(field).and_then(members).is_some()) {
+        return Ok(Cow::Borrowed(message));
+    }
+
+    let fields: Vec<&str> = body.split('|').collect();
+    let Some((checksum, fields)) = fields.split_last() else { return Ok(Cow::Borrowed(message)) };
+    let (mut rest, mut moved) = (Vec::new(), Vec::new());
+    let mut remaining = fields.iter().peekable();
+    while let Some(field) = remaining.next() {
+        match tag_of(field).and_then(members) {
+            Some(members) => {
+                moved.push(*field);
+                while let Some(member) = remaining.next_if(|field| tag_of(field).is_some_and(|tag| members.contains(&tag))) {
+                    moved.push(*member);
+                }
+            }
+            None => rest.push(*field),
+        }
+    }
+    if fields.ends_with(&moved) {
+        return Ok(Cow::Borrowed(message));
+    }
+    // The same bytes in another order, so BodyLength and CheckSum still hold
+    Ok(Cow::Owned(format!("{}|{}|{}|", rest.join("|"), moved.join("|"), checksum)))
+}
+
+// The entries of a repeating group, none if the message has no such group. Every entry must
+// start with `first`, and there must be exactly as many as the group counts; None if not,
+// so a group that is short of or past its count is refused rather than read as far as it goes.
+fn group_entries<'a, T, F>(msg: &Message<'a, T>, group: &F, first: &F) -> Option<Vec<Message<'a, T>>>
+where
+    T: AsRef<[u8]> + Clone,
+    F: IsFieldDefinition,
+{
+    let Ok(group) = msg.group(group) else { return Some(Vec::new()) };
+    let entries: Vec<_> = group.entries().collect();
+    let overlong = group.entry(group.len()).fv_raw(first).is_some();
+    (!overlong && entries.iter().all(|entry| entry.fv_raw(first).is_some())).then_some(entries)
+}
 
 pub fn handle_fix_message(message: &str) -> EngineMessage {
-    let dict = Dictionary::fix50();
-    let mut decoder = Decoder::<Config>::new(dict);
+    let dict = dictionary();
+    let decodable = match groups_last(dict, message) {
+        Ok(decodable) => decodable,
+        Err(reason) => {
+            return EngineMessage::InvalidMessage {
+                reason,
+                raw_message: RawMessage::text(message),
+            };
+        }
+    };
+    let mut decoder = Decoder::<Config>::new(dict.clone());
     decoder.config_mut().set_separator(b'|');
 
-    let msg = match decoder.decode(message) {
+    let msg = match decoder.decode(decodable.as_ref()) {
         Ok(msg) => msg,
         Err(e) => {
             return EngineMessage::InvalidMessage {
                 reason: e.to_string(),
-                raw_message: message.to_string(),
+                raw_message: RawMessage::capture(message.as_bytes(), malformed_field(message)),
             };
         }
     };
@@ -24,19 +271,20 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
     // Common fields
     let sender_comp_id = msg.fv::<&str>(SENDER_COMP_ID).unwrap_or("UNKNOWN");
     let sender_sub_id = msg.fv::<&str>(SENDER_SUB_ID).ok();
-    let client_id = ClientID::new(sender_comp_id.to_string(), sender_sub_id.map(str::to_string));
+    let client_id = ClientID::new(sender_comp_id.to_string(), sender_sub_id.map(str::to_string))
+        .in_namespace(namespace_of(msg.fv::<&str>(TARGET_COMP_ID).ok()));
 
     let sending_time = match msg.fv::<Timestamp>(SENDING_TIME) {
         Ok(ts) => ts,
         Err(e) => {
             return EngineMessage::InvalidMessage {
                 reason: e.unwrap().to_string(),
-                raw_message: message.to_string(),
+                raw_message: RawMessage::text(message),
             };
         }
     };
 
-    let receiving_time = Timestamp::utc_now();
+    let receiving_time = timestamp_now();
 
     // MsgType determines what we should parse
     let msg_type = match msg.fv::<&str>(MSG_TYPE) {
@@ -44,7 +292,7 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
         Err(e) => {
             return EngineMessage::InvalidMessage {
                 reason: e.unwrap().to_string(),
-                raw_message: message.to_string(),
+                raw_message: RawMessage::text(message),
             };
         }
     };
@@ -57,7 +305,7 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 Err(e) => {
                     return EngineMessage::InvalidMessage {
                         reason: e.unwrap().to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
@@ -67,17 +315,18 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid Side".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
 
-            let quantity = match msg.fv::<Quantity>(QUANTITY) {
+            // OrderQty(38) is what FIX engines send; Quantity(53) is what this exchange always read
+            let quantity = match msg.fv::<Quantity>(QUANTITY).or_else(|_| msg.fv::<Quantity>(ORDER_QTY)) {
                 Ok(qty) => qty,
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid Quantity".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
@@ -87,12 +336,24 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid OrdType".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
 
             let time_in_force = msg.fv::<TimeInForce>(TIME_IN_FORCE).ok();
+            let good_for_auction = msg.fv::<&str>(TIME_IN_FORCE) == Ok(GOOD_FOR_AUCTION);
+
+            let expire_time = match msg.fv::<Timestamp>(EXPIRE_TIME) {
+                Ok(ts) => Some(ts),
+                Err(None) => None,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid ExpireTime".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
 
             // Only parse price if order type requires it
             let price: Option<Price> = match order_type {
@@ -101,7 +362,7 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                     Err(_) => {
                         return EngineMessage::InvalidMessage {
                             reason: "Missing or invalid Price for limit/stop-limit order.".to_string(),
-                            raw_message: message.to_string(),
+                            raw_message: RawMessage::text(message),
                         };
                     }
                 },
@@ -113,12 +374,26 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid Account ID".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
 
             let client_order_id = msg.fv::<&str>(CL_ORD_ID).ok().map(|id| id.to_string());
+            let exchange_code = msg.fv::<&str>(SECURITY_EXCHANGE).ok().map(str::to_string);
+
+            let stipulations = group_entries(&msg, NO_STIPULATIONS, STIPULATION_TYPE).and_then(|entries| {
+                entries.iter().map(|entry| Some(Stipulation {
+                    stipulation_type: entry.fv::<&str>(STIPULATION_TYPE).ok()?.to_string(),
+                    value: entry.fv::<&str>(STIPULATION_VALUE).ok()?.to_string(),
+                })).collect::<Option<Vec<_>>>()
+            });
+            let Some(stipulations) = stipulations else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Invalid NoStipulations group".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
 
             EngineMessage::NewOrder {
                 sending_time,
@@ -131,17 +406,64 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 side,
                 quantity,
                 price,
-                time_in_force
+                time_in_force,
+                expire_time,
+                exchange_code,
+                good_for_auction,
+                stipulations,
+            }
+        }
+        "AB" => {
+            // New Order - Multileg: each leg priced, or at market, on its own
+            let account_id: AccountID = match msg.fv::<&str>(ACCOUNT) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid Account ID".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+            let client_order_id = msg.fv::<&str>(CL_ORD_ID).ok().map(|id| id.to_string());
+
+            let legs = group_entries(&msg, NO_LEGS, LEG_SYMBOL).and_then(|entries| {
+                entries.iter().map(|entry| Some(MultilegLeg {
+                    instrument_id: entry.fv::<&str>(LEG_SYMBOL).ok()?.to_string(),
+                    side: entry.fv::<Side>(LEG_SIDE).ok()?,
+                    quantity: entry.fv::<Quantity>(LEG_QTY).ok()?,
+                    price: match entry.fv::<f64>(LEG_PRICE) {
+                        Ok(price) => Some(Price::from(price)),
+                        Err(None) => None,
+                        Err(Some(_)) => return None,
+                    },
+                })).collect::<Option<Vec<_>>>()
+            });
+            let Some(legs) = legs else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Invalid NoLegs group".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::NewOrderMultileg {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+                client_order_id,
+                legs,
             }
         }
         "F" => {
-            // Cancel Order
+            // Cancel Order, naming the order by OrderID or else by OrigClOrdID(41)
+            let orig_client_order_id = msg.fv::<&str>(ORIG_CL_ORD_ID).ok().map(str::to_string);
             let order_id = match msg.fv::<OrderID>(ORDER_ID) {
                 Ok(id) => id,
+                Err(None) if orig_client_order_id.is_some() => 0,
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid ClOrdID".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
@@ -151,7 +473,19 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid account ID".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            // CxlQty(84) on a cancel request asks for a partial cancel of that much
+            let cancel_quantity = match msg.fv::<Quantity>(CXL_QTY) {
+                Ok(quantity) if quantity > 0 => Some(quantity),
+                Err(None) => None,
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid CxlQty".to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
@@ -162,52 +496,112 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 client_id,
                 account_id,
                 order_id,
+                orig_client_order_id,
+                cancel_quantity,
             }
         }
         "UCI" => {
-
-            let sender_comp_id = msg.fv::<&str>(SENDER_COMP_ID).unwrap_or("UNKNOWN");
-            let sender_sub_id = msg.fv::<&str>(SENDER_SUB_ID).ok();
-
             // Custom type: Create Instrument
             let instrument_id: InstrumentID = match msg.fv::<&str>(SYMBOL) {
                 Ok(id) => id.to_string(),
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid Symbol".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let segment = msg.fv::<&str>(&MARKET_SEGMENT_ID).ok().map(str::to_string);
+            let spec = match spec_overrides(&msg) {
+                Ok(spec) => spec,
+                Err(reason) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: reason.to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let legs = msg.fv::<&str>(SPREAD_LEGS).map(|values| {
+                values
+                    .split(' ')
+                    .map(|value| {
+                        let (instrument_id, ratio) = value.split_once(':')?;
+                        Some(SpreadLeg { instrument_id: instrument_id.to_string(), ratio: ratio.parse().ok()? })
+                    })
+                    .collect::<Option<Vec<_>>>()
+            });
+            let anchor = msg.fv::<&str>(SPREAD_ANCHOR).ok().map(str::to_string);
+            let spread = match legs {
+                Ok(Some(legs)) => Some(SpreadDefinition { legs, anchor }),
+                Err(None) if anchor.is_none() => None,
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid SpreadLegs".to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
 
             EngineMessage::CreateInstrument {
-                client_id: ClientID::new(sender_comp_id.to_string(), sender_sub_id.map(str::to_string)),
+                client_id,
                 sending_time,
                 receiving_time,
                 instrument_id,
+                segment,
+                spec,
+                spread,
             }
         }
-        "G" => {
-            let sender_comp_id = msg.fv::<&str>(SENDER_COMP_ID).unwrap_or("UNKNOWN");
-            let sender_sub_id = msg.fv::<&str>(SENDER_SUB_ID).ok();
+        "UUS" => {
+            // Custom type: Update instrument Spec, changing only the settings it gives
+            let instrument_id: InstrumentID = match msg.fv::<&str>(SYMBOL) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid Symbol".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let spec = match spec_overrides(&msg) {
+                Ok(spec) => spec,
+                Err(reason) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: reason.to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
 
+            EngineMessage::UpdateInstrumentSpec {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id,
+                spec,
+            }
+        }
+        "G" => {
             // Amend Order
             let order_id = match msg.fv::<OrderID>(ORDER_ID) {
                 Ok(id) => id,
                 Err(_) => {
                     return EngineMessage::InvalidMessage {
                         reason: "Missing or invalid Order ID".to_string(),
-                        raw_message: message.to_string(),
+                        raw_message: RawMessage::text(message),
                     };
                 }
             };
 
             let new_quantity = msg.fv::<Quantity>(ORDER_QTY).ok();
-            let new_price: Option<Price> = msg.fv::<f64>(PRICE).ok().map(|p| Price::from(p));
+            let new_price: Option<Price> = msg.fv::<f64>(PRICE).ok().map(Price::from);
             let time_in_force = msg.fv::<TimeInForce>(TIME_IN_FORCE).ok();
 
             EngineMessage::AmendOrder {
-                client_id: ClientID::new(sender_comp_id.to_string(), sender_sub_id.map(str::to_string)),
+                client_id,
                 sending_time,
                 receiving_time,
                 order_id,
@@ -216,11 +610,1509 @@ pub fn handle_fix_message(message: &str) -> EngineMessage {
                 time_in_force,
             }
         }
-        _ => {
-            EngineMessage::InvalidMessage {
-                reason: format!("Unhandled MsgType: {}", msg_type),
-                raw_message: message.to_string(),
+        "H" => {
+            // Order Status Request, by the exchange's OrderID
+            let Ok(order_id) = msg.fv::<OrderID>(ORDER_ID) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid Order ID".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::OrderStatusRequest {
+                sending_time,
+                receiving_time,
+                client_id,
+                order_id,
             }
         }
-    }
-}
\ No newline at end of file
+        "UPQ" => {
+            // Custom type: Position Query
+            let account_id = match msg.fv::<&str>(ACCOUNT) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid account ID".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::PositionQuery {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+            }
+        }
+        "8" => {
+            // Execution Report, from an upstream venue's drop copy
+            let (Ok(exec_id), Ok(order_id), Ok(account_id), Ok(instrument_id)) =
+                (msg.fv::<&str>(EXEC_ID), msg.fv::<&str>(ORDER_ID), msg.fv::<&str>(ACCOUNT), msg.fv::<&str>(SYMBOL))
+            else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing ExecID, OrderID, Account or Symbol".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+            let (Ok(exec_type), Ok(side)) = (msg.fv::<ExecType>(EXEC_TYPE), msg.fv::<Side>(SIDE)) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid ExecType or Side".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+            // Reports of anything but a trade need not say what traded
+            let quantity = |tag| match msg.fv::<Quantity>(tag) {
+                Err(None) => Ok(0),
+                parsed => parsed.map_err(|_| ()),
+            };
+            let (Ok(last_qty), Ok(cum_qty), Ok(leaves_qty), Ok(last_px)) = (
+                quantity(LAST_QTY),
+                quantity(CUM_QTY),
+                quantity(LEAVES_QTY),
+                match msg.fv::<f64>(LAST_PX) {
+                    Err(None) => Ok(0.0),
+                    parsed => parsed.map_err(|_| ()),
+                },
+            ) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Invalid LastQty, CumQty, LeavesQty or LastPx".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::InboundExecutionReport {
+                sending_time,
+                receiving_time,
+                client_id,
+                exec_id: exec_id.to_string(),
+                order_id: order_id.to_string(),
+                exec_type,
+                account_id: account_id.to_string(),
+                instrument_id: instrument_id.to_string(),
+                side,
+                last_qty,
+                last_px: Price::from(last_px),
+                cum_qty,
+                leaves_qty,
+            }
+        }
+        "UGQ" => {
+            // Custom type: Greeks Request
+            let account_id = match msg.fv::<&str>(ACCOUNT) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid account ID".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::GreeksRequest {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+            }
+        }
+        "UCA" => {
+            // Custom type: Corporate Action, either a split ratio or a dividend per share
+            let instrument_id: InstrumentID = match msg.fv::<&str>(SYMBOL) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid Symbol".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let effective_time = match msg.fv::<Timestamp>(EFFECTIVE_TIME) {
+                Ok(ts) => ts,
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid EffectiveTime".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let split = (msg.fv::<u64>(SPLIT_NUMERATOR), msg.fv::<u64>(SPLIT_DENOMINATOR));
+            let dividend = msg.fv::<f64>(DIVIDEND_PER_SHARE);
+            let action = match (split, dividend) {
+                ((Ok(numerator), Ok(denominator)), Err(None)) if numerator > 0 && denominator > 0 => {
+                    CorporateAction::Split { numerator, denominator }
+                }
+                ((Err(None), Err(None)), Ok(amount)) => CorporateAction::Dividend { amount: Price::from(amount) },
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Corporate action needs either a split ratio or a dividend".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::CorporateAction {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id,
+                effective_time,
+                action,
+            }
+        }
+        "V" => {
+            // Market Data Request: a one-off book snapshot, or a subscription to incremental updates.
+            // The symbols are a NoRelatedSym(146) group, or a lone Symbol without one.
+            let symbols: Option<Vec<InstrumentID>> = match group_entries(&msg, NO_RELATED_SYM, SYMBOL) {
+                Some(entries) if entries.is_empty() => msg.fv::<&str>(SYMBOL).ok().map(|id| vec![id.to_string()]),
+                Some(entries) => entries.iter().map(|entry| entry.fv::<&str>(SYMBOL).ok().map(str::to_string)).collect(),
+                None => None,
+            };
+            let Some(symbols) = symbols.filter(|symbols| !symbols.is_empty()) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid Symbol".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            let subscribe_on_create = match msg.fv::<bool>(SUBSCRIBE_ON_CREATE) {
+                Ok(subscribe_on_create) => subscribe_on_create,
+                Err(None) => false,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid SubscribeOnCreate".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let activity = match msg.fv::<bool>(ACTIVITY_REPORTS) {
+                Ok(activity) => activity,
+                Err(None) => false,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid ActivityReports".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let last_sale = match msg.fv::<bool>(LAST_SALE_FEED) {
+                Ok(last_sale) => last_sale,
+                Err(None) => false,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid LastSaleFeed".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let depth = match msg.fv::<u32>(MARKET_DEPTH) {
+                Ok(depth) => Some(depth),
+                Err(None) => None,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid MarketDepth".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            // MDBookType: price depth unless order depth is asked for; top of book is one level
+            let (granularity, depth) = match msg.fv::<MdBookType>(MD_BOOK_TYPE) {
+                Ok(MdBookType::PriceDepth) | Err(None) => (BookGranularity::ByPrice, depth),
+                Ok(MdBookType::OrderDepth) => (BookGranularity::ByOrder, depth),
+                Ok(MdBookType::TopOfBook) => (BookGranularity::ByPrice, Some(1)),
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid MDBookType".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            match msg.fv::<SubscriptionRequestType>(SUBSCRIPTION_REQUEST_TYPE) {
+                Ok(SubscriptionRequestType::SnapshotPlusUpdates) => EngineMessage::SubscribeOrderBook {
+                    sending_time,
+                    receiving_time,
+                    client_id,
+                    symbols,
+                    depth: depth.unwrap_or(0),
+                    subscribe_on_create,
+                    activity,
+                    last_sale,
+                },
+                Ok(SubscriptionRequestType::DisablePreviousSnapshotPlusUpdateRequest) => EngineMessage::UnsubscribeOrderBook {
+                    sending_time,
+                    receiving_time,
+                    client_id,
+                    symbols,
+                },
+                // A snapshot is of one book, as published, so names it outright
+                Ok(SubscriptionRequestType::Snapshot) | Err(None) => match <[InstrumentID; 1]>::try_from(symbols) {
+                    Ok([instrument_id]) if !instrument_id.ends_with('*') => EngineMessage::Snapshot {
+                        client_id,
+                        timestamp: sending_time,
+                        instrument_id,
+                        bids: Vec::new(),
+                        asks: Vec::new(),
+                        depth,
+                        granularity,
+                        bid_orders: Vec::new(),
+                        ask_orders: Vec::new(),
+                    },
+                    _ => EngineMessage::InvalidMessage {
+                        reason: "A snapshot takes a single Symbol".to_string(),
+                        raw_message: RawMessage::text(message),
+                    },
+                },
+                Err(Some(_)) => EngineMessage::InvalidMessage {
+                    reason: "Invalid SubscriptionRequestType".to_string(),
+                    raw_message: RawMessage::text(message),
+                },
+            }
+        }
+        "URL" => {
+            // Custom type: Resting order Limit, per instrument or exchange-wide without a Symbol
+            let instrument_id = msg.fv::<&str>(SYMBOL).ok().map(str::to_string);
+
+            let max_resting_orders = match msg.fv::<usize>(MAX_RESTING_ORDERS) {
+                Ok(orders) => orders,
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid MaxRestingOrders".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::SetRestingOrderLimit {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id,
+                max_resting_orders,
+            }
+        }
+        "USM" => {
+            // Custom type: Self-Match prevention setting for an Account
+            let account_id = match msg.fv::<&str>(ACCOUNT) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid account ID".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let smp_action = match msg.fv::<&str>(SMP_ACTION) {
+                Ok("N") => SmpAction::Allow,
+                Ok("R") => SmpAction::RejectAggressor,
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid SmpAction".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::SetSmpAction {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+                smp_action,
+            }
+        }
+        "URK" => {
+            // Custom type: Risk limits for an Account
+            let account_id = match msg.fv::<&str>(ACCOUNT) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid account ID".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let mut limits = RiskLimits::default();
+
+            match msg.fv::<f64>(MAX_ORDER_VALUE) {
+                Ok(value) if value >= 0.0 => limits.max_single_order_value = value,
+                Err(None) => {}
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid MaxOrderValue".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            }
+
+            match msg.fv::<f64>(MAX_DAILY_LOSS) {
+                Ok(value) if value >= 0.0 => limits.max_daily_loss = Some(value),
+                Err(None) => {}
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid MaxDailyLoss".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            }
+
+            EngineMessage::SetRiskLimits {
+                sending_time,
+                receiving_time,
+                client_id,
+                account_id,
+                limits,
+            }
+        }
+        "URR" => {
+            // Custom type: Request Replay of the trades on a Symbol within a time range
+            let instrument_id: InstrumentID = match msg.fv::<&str>(SYMBOL) {
+                Ok(id) => id.to_string(),
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid Symbol".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            let (from_timestamp, to_timestamp) = match (msg.fv::<Timestamp>(REPLAY_FROM_TIME), msg.fv::<Timestamp>(REPLAY_TO_TIME)) {
+                (Ok(from), Ok(to)) => (from, to),
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid replay time range".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::RequestReplay {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id,
+                from_timestamp,
+                to_timestamp,
+            }
+        }
+        "UAT" => {
+            // Custom type: Advance Time, moves the simulated clock to TransactTime
+            let timestamp = match msg.fv::<Timestamp>(TRANSACT_TIME) {
+                Ok(ts) => ts,
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid TransactTime".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::AdvanceTime {
+                sending_time,
+                receiving_time,
+                client_id,
+                timestamp,
+            }
+        }
+        "UAS" => {
+            // Custom type: Alert Subscription, SubscriptionRequestType(263) 1 subscribes and 2 unsubscribes
+            match msg.fv::<SubscriptionRequestType>(SUBSCRIPTION_REQUEST_TYPE) {
+                Ok(SubscriptionRequestType::SnapshotPlusUpdates) => EngineMessage::SubscribeAlerts {
+                    sending_time,
+                    receiving_time,
+                    client_id,
+                },
+                Ok(SubscriptionRequestType::DisablePreviousSnapshotPlusUpdateRequest) => EngineMessage::UnsubscribeAlerts {
+                    sending_time,
+                    receiving_time,
+                    client_id,
+                },
+                _ => EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid SubscriptionRequestType".to_string(),
+                    raw_message: RawMessage::text(message),
+                },
+            }
+        }
+        "UTS" => {
+            // Custom type: Trading Status, SecurityTradingStatus(326) 2 halts and 3 resumes the
+            // Symbol, the MarketSegmentID or, with neither, the whole exchange
+            let Some(scope) = instrument_scope(msg.fv::<&str>(SYMBOL).ok(), msg.fv::<&str>(&MARKET_SEGMENT_ID).ok()) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Symbol and MarketSegmentID cannot both be set".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            let halted = match msg.fv::<&str>(SECURITY_TRADING_STATUS) {
+                Ok("2") => true,
+                Ok("3") => false,
+                _ => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid SecurityTradingStatus".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::SetTradingStatus {
+                sending_time,
+                receiving_time,
+                client_id,
+                scope,
+                halted,
+            }
+        }
+        "URS" => {
+            // Custom type: Roll Session, for the Symbol, the MarketSegmentID or the whole exchange
+            let Some(scope) = instrument_scope(msg.fv::<&str>(SYMBOL).ok(), msg.fv::<&str>(&MARKET_SEGMENT_ID).ok()) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Symbol and MarketSegmentID cannot both be set".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::RollSession {
+                sending_time,
+                receiving_time,
+                client_id,
+                scope,
+            }
+        }
+        "UWU" => {
+            // Custom type: Warm Up, for the Symbol, the MarketSegmentID or the whole exchange,
+            // until TradSesOpenTime(342)
+            let Some(scope) = instrument_scope(msg.fv::<&str>(SYMBOL).ok(), msg.fv::<&str>(&MARKET_SEGMENT_ID).ok()) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Symbol and MarketSegmentID cannot both be set".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            let open_time = match msg.fv::<Timestamp>(TRAD_SES_OPEN_TIME) {
+                Ok(open_time) => open_time,
+                Err(_) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Missing or invalid TradSesOpenTime".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::StartWarmUp {
+                sending_time,
+                receiving_time,
+                client_id,
+                scope,
+                open_time,
+            }
+        }
+        "USR" => {
+            // Custom type: Symbol Status Request, for the Symbol or, without one, every instrument
+            EngineMessage::SymbolStatusRequest {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id: msg.fv::<&str>(SYMBOL).ok().map(str::to_string),
+            }
+        }
+        "UAQ" => {
+            // Custom type: Activity Query, for how busy a book was over the last activity interval
+            let Ok(instrument_id) = msg.fv::<&str>(SYMBOL) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid Symbol".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::ActivityRequest {
+                sending_time,
+                receiving_time,
+                client_id,
+                instrument_id: instrument_id.to_string(),
+            }
+        }
+        "UPN" => {
+            // Custom type: Ping, timed from its SendingTime(52)
+            let Ok(ping_id) = msg.fv::<u64>(PING_ID) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid PingID".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+
+            EngineMessage::Ping {
+                client_id,
+                ping_id,
+                sent_at: sending_time,
+                received_at: receiving_time,
+            }
+        }
+        "A" => {
+            // Logon; ResetSeqNumFlag(141)=Y starts the session clean, cancelling the previous session's orders
+            let cancel_previous_orders = match msg.fv::<bool>(RESET_SEQ_NUM_FLAG) {
+                Ok(reset) => reset,
+                Err(None) => false,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid ResetSeqNumFlag".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+            let heart_bt_int = match msg.fv::<u32>(HEART_BT_INT) {
+                Ok(heart_bt_int) => heart_bt_int,
+                Err(None) => 0,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid HeartBtInt".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+            let summary_fills = match msg.fv::<bool>(SUMMARY_FILLS) {
+                Ok(summary_fills) => summary_fills,
+                Err(None) => false,
+                Err(Some(_)) => {
+                    return EngineMessage::InvalidMessage {
+                        reason: "Invalid SummaryFills".to_string(),
+                        raw_message: RawMessage::text(message),
+                    };
+                }
+            };
+
+            EngineMessage::Logon {
+                sending_time,
+                receiving_time,
+                client_id,
+                cancel_previous_orders,
+                heart_bt_int,
+                default_appl_ver_id: msg.fv::<&str>(DEFAULT_APPL_VER_ID).ok().map(str::to_string),
+                summary_fills,
+            }
+        }
+        "0" => EngineMessage::Heartbeat {
+            client_id,
+            test_req_id: msg.fv::<&str>(TEST_REQ_ID).ok().map(str::to_string),
+        },
+        "1" => {
+            let Ok(test_req_id) = msg.fv::<&str>(TEST_REQ_ID) else {
+                return EngineMessage::InvalidMessage {
+                    reason: "Missing or invalid TestReqID".to_string(),
+                    raw_message: RawMessage::text(message),
+                };
+            };
+            EngineMessage::TestRequest { client_id, test_req_id: test_req_id.to_string() }
+        }
+        "5" => EngineMessage::Logout {
+            client_id,
+            text: msg.fv::<&str>(TEXT).ok().map(str::to_string),
+        },
+        _ => {
+            EngineMessage::InvalidMessage {
+                reason: format!("Unhandled MsgType: {}", msg_type),
+                raw_message: RawMessage::text(message),
+            }
+        }
+    }
+}
+
+// The settings a CreateInstrument or UpdateInstrumentSpec gives, each optional
+fn spec_overrides(msg: &Message<'_, &str>) -> Result<SpecOverrides, &'static str> {
+    let mut spec = SpecOverrides::default();
+
+    match msg.fv::<usize>(MAX_BOOK_LEVELS) {
+        Ok(levels) => spec.max_price_levels = Some(levels),
+        Err(None) => {}
+        Err(Some(_)) => return Err("Invalid MaxBookLevels"),
+    }
+
+    match msg.fv::<usize>(MAX_RESTING_ORDERS) {
+        Ok(orders) => spec.max_resting_orders = Some(orders),
+        Err(None) => {}
+        Err(Some(_)) => return Err("Invalid MaxRestingOrders"),
+    }
+
+    match msg.fv::<&str>(PRICE_LEVEL_POLICY) {
+        Ok("E") => spec.price_level_policy = Some(PriceLevelPolicy::EvictWorst),
+        Ok("R") => spec.price_level_policy = Some(PriceLevelPolicy::Reject),
+        Err(None) => {}
+        _ => return Err("Invalid PriceLevelPolicy"),
+    }
+
+    match msg.fv::<f64>(MAX_TOUCH_DISTANCE) {
+        Ok(distance) if distance >= 0.0 => spec.max_touch_distance = Some(Price::from(distance)),
+        Err(None) => {}
+        _ => return Err("Invalid MaxTouchDistance"),
+    }
+
+    match msg.fv::<&str>(TOUCH_WINDOW_POLICY) {
+        Ok("G") => spec.touch_window_policy = Some(TouchWindowPolicy::Grandfather),
+        Ok("C") => spec.touch_window_policy = Some(TouchWindowPolicy::Cancel),
+        Err(None) => {}
+        _ => return Err("Invalid TouchWindowPolicy"),
+    }
+
+    match msg.fv::<&str>(UNCROSS_POLICY) {
+        Ok("R") => spec.uncross_policy = Some(UncrossPolicy::RestingPrices),
+        Ok("A") => spec.uncross_policy = Some(UncrossPolicy::Auction),
+        Err(None) => {}
+        _ => return Err("Invalid UncrossPolicy"),
+    }
+
+    match msg.fv::<&str>(HALT_POLICY) {
+        Ok("C") => spec.halt_policy = Some(HaltPolicy::CancelAll),
+        Ok("F") => spec.halt_policy = Some(HaltPolicy::Freeze),
+        Err(None) => {}
+        _ => return Err("Invalid HaltPolicy"),
+    }
+
+    match msg.fv::<f64>(SPREAD_HALT_MULTIPLIER) {
+        Ok(multiplier) if multiplier >= 0.0 => spec.spread_halt_multiplier = Some(multiplier),
+        Err(None) => {}
+        _ => return Err("Invalid SpreadHaltMultiplier"),
+    }
+
+    match msg.fv::<f64>(REFERENCE_SPREAD) {
+        Ok(spread) if spread >= 0.0 => spec.reference_spread = Some(spread),
+        Err(None) => {}
+        _ => return Err("Invalid ReferenceSpread"),
+    }
+
+    match msg.fv::<u64>(HALT_DURATION) {
+        Ok(seconds) => spec.halt_duration_secs = Some(seconds),
+        Err(None) => {}
+        Err(Some(_)) => return Err("Invalid HaltDuration"),
+    }
+
+    match msg.fv::<f64>(MIN_PRICE_INCREMENT) {
+        Ok(tick_size) if tick_size >= 0.0 => spec.tick_size = Some(Price::from(tick_size)),
+        Err(None) => {}
+        _ => return Err("Invalid MinPriceIncrement"),
+    }
+
+    match msg.fv::<Quantity>(ROUND_LOT) {
+        Ok(lot_size) => spec.lot_size = Some(lot_size),
+        Err(None) => {}
+        Err(Some(_)) => return Err("Invalid RoundLot"),
+    }
+
+    match msg.fv::<f64>(IMBALANCE_ALERT_THRESHOLD) {
+        Ok(threshold) if threshold >= 0.0 => spec.imbalance_alert_threshold = Some(threshold),
+        Err(None) => {}
+        _ => return Err("Invalid ImbalanceAlertThreshold"),
+    }
+
+    let order_types = msg.fv::<&str>(ORDER_TYPES).map(|values| {
+        values.split(' ').map(|value| OrdType::deserialize(value.as_bytes()).ok().and_then(OrderType::of)).collect::<Option<BTreeSet<_>>>()
+    });
+    match order_types {
+        Ok(Some(order_types)) => spec.order_types = Some(order_types),
+        Err(None) => {}
+        _ => return Err("Invalid OrderTypes"),
+    }
+
+    // An option names its underlying, and then all of its terms
+    if let Ok(underlying) = msg.fv::<&str>(UNDERLYING_SYMBOL) {
+        let terms = (msg.fv::<f64>(STRIKE_PRICE), msg.fv::<Timestamp>(OPTION_EXPIRY), msg.fv::<PutOrCall>(PUT_OR_CALL), msg.fv::<f64>(&VOLATILITY));
+        let (Ok(strike), Ok(expiry), Ok(put_or_call), Ok(implied_volatility)) = terms else {
+            return Err("Missing or invalid option terms");
+        };
+        spec.options = Some(OptionsSpec {
+            underlying: underlying.to_string(),
+            strike: Price::from(strike),
+            expiry,
+            option_type: match put_or_call {
+                PutOrCall::Call => OptionType::Call,
+                PutOrCall::Put => OptionType::Put,
+            },
+            implied_volatility,
+        });
+    }
+    Ok(spec)
+}
+
+fn instrument_scope(symbol: Option<&str>, segment: Option<&str>) -> Option<InstrumentScope> {
+    match (symbol, segment) {
+        (Some(symbol), None) => Some(InstrumentScope::Instrument(symbol.to_string())),
+        (None, Some(segment)) => Some(InstrumentScope::Segment(segment.to_string())),
+        (None, None) => Some(InstrumentScope::All),
+        (Some(_), Some(_)) => None,
+    }
+}
+
+// The credentials a Logon carries, or None if the message is not a Logon. Parsed apart
+// from `handle_fix_message` so the session can check them without the engine ever seeing them.
+pub fn logon_credentials(message: &str) -> Option<LogonCredentials> {
+    let mut decoder = Decoder::<Config>::new(dictionary().clone());
+    decoder.config_mut().set_separator(b'|');
+    let msg = decoder.decode(message).ok()?;
+    if msg.fv::<&str>(MSG_TYPE).ok()? != "A" {
+        return None;
+    }
+    Some(LogonCredentials {
+        password: msg.fv::<&str>(PASSWORD).ok().map(str::to_string),
+        signature: msg.fv::<&str>(RAW_DATA).ok().map(str::to_string),
+        sending_time: msg.fv::<&str>(SENDING_TIME).unwrap_or("").to_string(),
+        msg_seq_num: msg.fv::<u64>(MSG_SEQ_NUM).ok(),
+    })
+}
+
+fn start_message<'a>(buffer: &'a mut BytesMut, msg_type: &[u8], client_id: Option<&ClientID>) -> FixWriter<'a> {
+    let mut msg = FixWriter::start(buffer, BEGIN_STRING, msg_type);
+    // Answering as whichever CompID the client addressed
+    msg.set(SENDER_COMP_ID, client_id.and_then(ClientID::namespace).unwrap_or(EXCHANGE_COMP_ID));
+    if let Some(client_id) = client_id {
+        msg.set(TARGET_COMP_ID, client_id.comp_id());
+        if let Some(sub_id) = client_id.sub_id() {
+            msg.set(TARGET_SUB_ID, sub_id);
+        }
+    }
+    // Both stamped as the session writes the message; see stamp_outbound
+    msg.reserve_msg_seq_num();
+    msg.set(SENDING_TIME, exchange_now());
+    msg
+}
+
+// Header for an ExecutionReport, with TransactTime(60) when the exchange matched what it
+// reports, or now if that isn't known, and when it took in the message that led to it
+fn start_execution_report<'a>(buffer: &'a mut BytesMut, client_id: &ClientID, times: Option<&ReportTimes>) -> FixWriter<'a> {
+    let mut msg = start_message(buffer, b"8", Some(client_id));
+    msg.set(EXEC_ID, clock::current().next_exec_id());
+    match times {
+        Some(times) => {
+            msg.set(TRANSACT_TIME, times.matched);
+            msg.set(EXCHANGE_RECEIVE_TIME, times.received);
+        }
+        None => msg.set(TRANSACT_TIME, exchange_now()),
+    }
+    msg
+}
+
+// Header for a message as the client would have sent it
+fn start_client_message<'a>(buffer: &'a mut BytesMut, msg_type: &[u8], client_id: &ClientID, sending_time: &Timestamp) -> FixWriter<'a> {
+    let mut msg = FixWriter::start(buffer, BEGIN_STRING, msg_type);
+    msg.set(SENDER_COMP_ID, client_id.comp_id());
+    if let Some(sub_id) = client_id.sub_id() {
+        msg.set(SENDER_SUB_ID, sub_id);
+    }
+    msg.set(TARGET_COMP_ID, client_id.namespace().unwrap_or(EXCHANGE_COMP_ID));
+    msg.set(SENDING_TIME, sending_time.clone());
+    msg
+}
+
+// The settings given, as spec_overrides reads them back
+fn set_spec_overrides(msg: &mut FixWriter<'_>, spec: &SpecOverrides) {
+    if let Some(levels) = spec.max_price_levels {
+        msg.set(MAX_BOOK_LEVELS, levels);
+    }
+    if let Some(orders) = spec.max_resting_orders {
+        msg.set(MAX_RESTING_ORDERS, orders);
+    }
+    if let Some(policy) = spec.price_level_policy {
+        msg.set(PRICE_LEVEL_POLICY, match policy {
+            PriceLevelPolicy::EvictWorst => "E",
+            PriceLevelPolicy::Reject => "R",
+        });
+    }
+    if let Some(distance) = spec.max_touch_distance {
+        msg.set(MAX_TOUCH_DISTANCE, distance.into_inner());
+    }
+    if let Some(policy) = spec.touch_window_policy {
+        msg.set(TOUCH_WINDOW_POLICY, match policy {
+            TouchWindowPolicy::Grandfather => "G",
+            TouchWindowPolicy::Cancel => "C",
+        });
+    }
+    if let Some(policy) = spec.uncross_policy {
+        msg.set(UNCROSS_POLICY, match policy {
+            UncrossPolicy::RestingPrices => "R",
+            UncrossPolicy::Auction => "A",
+        });
+    }
+    if let Some(policy) = spec.halt_policy {
+        msg.set(HALT_POLICY, match policy {
+            HaltPolicy::CancelAll => "C",
+            HaltPolicy::Freeze => "F",
+        });
+    }
+    if let Some(multiplier) = spec.spread_halt_multiplier {
+        msg.set(SPREAD_HALT_MULTIPLIER, multiplier);
+    }
+    if let Some(spread) = spec.reference_spread {
+        msg.set(REFERENCE_SPREAD, spread);
+    }
+    if let Some(seconds) = spec.halt_duration_secs {
+        msg.set(HALT_DURATION, seconds);
+    }
+    if let Some(tick_size) = spec.tick_size {
+        msg.set(MIN_PRICE_INCREMENT, tick_size.into_inner());
+    }
+    if let Some(lot_size) = spec.lot_size {
+        msg.set(ROUND_LOT, lot_size);
+    }
+    if let Some(threshold) = spec.imbalance_alert_threshold {
+        msg.set(IMBALANCE_ALERT_THRESHOLD, threshold);
+    }
+    if let Some(order_types) = &spec.order_types {
+        let values: Vec<String> = order_types.iter().map(|order_type| order_type.ord_type().to_string()).collect();
+        msg.set(ORDER_TYPES, values.join(" ").as_str());
+    }
+    if let Some(option) = &spec.options {
+        msg.set(UNDERLYING_SYMBOL, option.underlying.as_str());
+        msg.set(STRIKE_PRICE, option.strike.into_inner());
+        msg.set(OPTION_EXPIRY, option.expiry.clone());
+        msg.set(PUT_OR_CALL, match option.option_type {
+            OptionType::Call => PutOrCall::Call,
+            OptionType::Put => PutOrCall::Put,
+        });
+        msg.set_fv(&VOLATILITY, option.implied_volatility);
+    }
+}
+
+fn set_scope(msg: &mut FixWriter<'_>, scope: &InstrumentScope) {
+    match scope {
+        InstrumentScope::Instrument(instrument_id) => msg.set(SYMBOL, instrument_id.as_str()),
+        InstrumentScope::Segment(segment) => msg.set_fv(&MARKET_SEGMENT_ID, segment.as_str()),
+        InstrumentScope::All => {}
+    }
+}
+
+// The engine message as text, for tests to read
+#[cfg(test)]
+pub fn serialize_engine_message(message: &EngineMessage) -> Option<String> {
+    let mut buffer = BytesMut::new();
+    encode_engine_message(message, None, &mut buffer).then(|| String::from_utf8_lossy(&buffer).into_owned())
+}
+
+// The engine message in a pooled buffer, ready for a session's writer
+pub fn encode_outbound(message: &EngineMessage, times: Option<&ReportTimes>) -> Option<Bytes> {
+    buffer_pool().encode(|buffer| encode_engine_message(message, times, buffer))
+}
+
+// Encodes an engine message onto `buffer` as a '|'-separated, newline-terminated FIX message.
+// Order entry and instrument creation are encoded as the client would send them; returns
+// false, writing nothing, for the other messages that only ever flow into the engine.
+// Execution reports carry `times`, when the engine answered with them.
+pub fn encode_engine_message(message: &EngineMessage, times: Option<&ReportTimes>, buffer: &mut BytesMut) -> bool {
+    match message {
+        EngineMessage::NewOrder {
+            sending_time, client_id, account_id, client_order_id, instrument_id, order_type, side, quantity, price, time_in_force, expire_time, exchange_code, good_for_auction, stipulations, ..
+        } => {
+            let mut msg = start_client_message(buffer, b"D", client_id, sending_time);
+            msg.set(ACCOUNT, account_id.as_str());
+            if let Some(client_order_id) = client_order_id {
+                msg.set(CL_ORD_ID, client_order_id.as_str());
+            }
+            msg.set(SYMBOL, instrument_id.as_str());
+            if let Some(exchange_code) = exchange_code {
+                msg.set(SECURITY_EXCHANGE, exchange_code.as_str());
+            }
+            msg.set(SIDE, *side);
+            msg.set(QUANTITY, *quantity);
+            msg.set(ORD_TYPE, *order_type);
+            if let Some(price) = price {
+                msg.set(PRICE, price.into_inner());
+            }
+            if *good_for_auction {
+                msg.set(TIME_IN_FORCE, GOOD_FOR_AUCTION);
+            } else if let Some(time_in_force) = time_in_force {
+                msg.set(TIME_IN_FORCE, *time_in_force);
+            }
+            if let Some(expire_time) = expire_time {
+                msg.set(EXPIRE_TIME, expire_time.clone());
+            }
+            if !stipulations.is_empty() {
+                msg.set(NO_STIPULATIONS, stipulations.len());
+                for stipulation in stipulations {
+                    msg.set(STIPULATION_TYPE, stipulation.stipulation_type.as_str());
+                    msg.set(STIPULATION_VALUE, stipulation.value.as_str());
+                }
+            }
+            msg.wrap()
+        }
+        EngineMessage::NewOrderMultileg { sending_time, client_id, account_id, client_order_id, legs, .. } => {
+            let mut msg = start_client_message(buffer, b"AB", client_id, sending_time);
+            msg.set(ACCOUNT, account_id.as_str());
+            if let Some(client_order_id) = client_order_id {
+                msg.set(CL_ORD_ID, client_order_id.as_str());
+            }
+            msg.set(NO_LEGS, legs.len());
+            for leg in legs {
+                msg.set(LEG_SYMBOL, leg.instrument_id.as_str());
+                msg.set(LEG_SIDE, leg.side);
+                msg.set(LEG_QTY, leg.quantity);
+                if let Some(price) = leg.price {
+                    msg.set(LEG_PRICE, price.into_inner());
+                }
+            }
+            msg.wrap()
+        }
+        EngineMessage::CancelOrder { sending_time, client_id, account_id, order_id, orig_client_order_id, cancel_quantity, .. } => {
+            let mut msg = start_client_message(buffer, b"F", client_id, sending_time);
+            if *order_id != 0 {
+                msg.set(ORDER_ID, *order_id);
+            }
+            if let Some(orig_client_order_id) = orig_client_order_id {
+                msg.set(ORIG_CL_ORD_ID, orig_client_order_id.as_str());
+            }
+            msg.set(ACCOUNT, account_id.as_str());
+            if let Some(quantity) = cancel_quantity {
+                msg.set(CXL_QTY, *quantity);
+            }
+            msg.wrap()
+        }
+        EngineMessage::AmendOrder { sending_time, client_id, order_id, new_quantity, new_price, time_in_force, .. } => {
+            let mut msg = start_client_message(buffer, b"G", client_id, sending_time);
+            msg.set(ORDER_ID, *order_id);
+            if let Some(quantity) = new_quantity {
+                msg.set(ORDER_QTY, *quantity);
+            }
+            if let Some(price) = new_price {
+                msg.set(PRICE, price.into_inner());
+            }
+            if let Some(time_in_force) = time_in_force {
+                msg.set(TIME_IN_FORCE, *time_in_force);
+            }
+            msg.wrap()
+        }
+        EngineMessage::CreateInstrument { sending_time, client_id, instrument_id, segment, spec, spread, .. } => {
+            let mut msg = start_client_message(buffer, b"UCI", client_id, sending_time);
+            msg.set(SYMBOL, instrument_id.as_str());
+            if let Some(segment) = segment {
+                msg.set_fv(&MARKET_SEGMENT_ID, segment.as_str());
+            }
+            set_spec_overrides(&mut msg, spec);
+            if let Some(spread) = spread {
+                let legs: Vec<String> = spread.legs.iter().map(|leg| format!("{}:{}", leg.instrument_id, leg.ratio)).collect();
+                msg.set(SPREAD_LEGS, legs.join(" ").as_str());
+                if let Some(anchor) = &spread.anchor {
+                    msg.set(SPREAD_ANCHOR, anchor.as_str());
+                }
+            }
+            msg.wrap()
+        }
+        EngineMessage::UpdateInstrumentSpec { sending_time, client_id, instrument_id, spec, .. } => {
+            let mut msg = start_client_message(buffer, b"UUS", client_id, sending_time);
+            msg.set(SYMBOL, instrument_id.as_str());
+            set_spec_overrides(&mut msg, spec);
+            msg.wrap()
+        }
+        EngineMessage::SubscribeAlerts { sending_time, client_id, .. } => {
+            let mut msg = start_client_message(buffer, b"UAS", client_id, sending_time);
+            msg.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::SnapshotPlusUpdates);
+            msg.wrap()
+        }
+        EngineMessage::UnsubscribeAlerts { sending_time, client_id, .. } => {
+            let mut msg = start_client_message(buffer, b"UAS", client_id, sending_time);
+            msg.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::DisablePreviousSnapshotPlusUpdateRequest);
+            msg.wrap()
+        }
+        EngineMessage::SetTradingStatus { sending_time, client_id, scope, halted, .. } => {
+            let mut msg = start_client_message(buffer, b"UTS", client_id, sending_time);
+            set_scope(&mut msg, scope);
+            msg.set(SECURITY_TRADING_STATUS, if *halted { "2" } else { "3" });
+            msg.wrap()
+        }
+        EngineMessage::RollSession { sending_time, client_id, scope, .. } => {
+            let mut msg = start_client_message(buffer, b"URS", client_id, sending_time);
+            set_scope(&mut msg, scope);
+            msg.wrap()
+        }
+        EngineMessage::StartWarmUp { sending_time, client_id, scope, open_time, .. } => {
+            let mut msg = start_client_message(buffer, b"UWU", client_id, sending_time);
+            set_scope(&mut msg, scope);
+            msg.set(TRAD_SES_OPEN_TIME, open_time.clone());
+            msg.wrap()
+        }
+        EngineMessage::SymbolStatusRequest { sending_time, client_id, instrument_id, .. } => {
+            let mut msg = start_client_message(buffer, b"USR", client_id, sending_time);
+            if let Some(instrument_id) = instrument_id {
+                msg.set(SYMBOL, instrument_id.as_str());
+            }
+            msg.wrap()
+        }
+        EngineMessage::ActivityRequest { sending_time, client_id, instrument_id, .. } => {
+            let mut msg = start_client_message(buffer, b"UAQ", client_id, sending_time);
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.wrap()
+        }
+        EngineMessage::OrderStatusRequest { sending_time, client_id, order_id, .. } => {
+            let mut msg = start_client_message(buffer, b"H", client_id, sending_time);
+            msg.set(ORDER_ID, *order_id);
+            msg.wrap()
+        }
+        EngineMessage::Ping { client_id, ping_id, sent_at, .. } => {
+            let mut msg = start_client_message(buffer, b"UPN", client_id, sent_at);
+            msg.set(PING_ID, *ping_id);
+            msg.wrap()
+        }
+        EngineMessage::Pong { client_id, ping_id, sent_at, received_at, exchange_time } => {
+            // Custom type: Pong, with the Ping's SendingTime(52) as OrigSendingTime(122) and
+            // when the engine handled it as TransactTime(60)
+            let mut msg = start_message(buffer, b"UPO", Some(client_id));
+            msg.set(PING_ID, *ping_id);
+            msg.set(ORIG_SENDING_TIME, sent_at.clone());
+            msg.set(EXCHANGE_RECEIVE_TIME, received_at.clone());
+            msg.set(TRANSACT_TIME, exchange_time.clone());
+            msg.wrap()
+        }
+        EngineMessage::SymbolStatusReport { client_id, reports } => {
+            // Custom type: Symbol Status, one entry per instrument. SecurityTradingStatus(326) is
+            // 17 (ready to trade) once open and 21 (pre-open) while warming up.
+            let mut msg = start_message(buffer, b"USS", Some(client_id));
+            msg.set(NO_RELATED_SYM, reports.len());
+            for report in reports {
+                msg.set(SYMBOL, report.instrument_id.as_str());
+                match &report.phase {
+                    InstrumentPhase::Open => msg.set(SECURITY_TRADING_STATUS, "17"),
+                    InstrumentPhase::WarmUp { opens_at } => {
+                        msg.set(SECURITY_TRADING_STATUS, "21");
+                        msg.set(TRAD_SES_OPEN_TIME, opens_at.clone());
+                    }
+                }
+                msg.set(CIRCUIT_BREAKER, report.circuit_breaker);
+                if let Some((lower, upper)) = report.luld_band {
+                    msg.set_fv(&LOW_LIMIT_PRICE, lower.into_inner());
+                    msg.set_fv(&HIGH_LIMIT_PRICE, upper.into_inner());
+                }
+                if let Some(last_price) = report.last_price {
+                    msg.set(LAST_PX, last_price.into_inner());
+                }
+                msg.set(BID_LEVELS, report.bid_levels);
+                msg.set(ASK_LEVELS, report.ask_levels);
+            }
+            msg.wrap()
+        }
+        EngineMessage::OrderAccepted { client_id, order_id, client_order_id, instrument_id, side, quantity } => {
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            if let Some(client_order_id) = client_order_id {
+                msg.set(CL_ORD_ID, client_order_id.as_str());
+            }
+            msg.set(EXEC_TYPE, ExecType::New);
+            msg.set(ORD_STATUS, OrdStatus::New);
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(SIDE, *side);
+            msg.set(ORDER_QTY, *quantity);
+            msg.set(LEAVES_QTY, *quantity);
+            msg.set(CUM_QTY, 0u64);
+            msg.wrap()
+        }
+        EngineMessage::OrderStatusReport { client_id, order_id, instrument_id, status, cumulative_quantity, leaves_quantity, average_price, untriggered } => {
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            msg.set(EXEC_TYPE, ExecType::OrderStatus);
+            msg.set(ORD_STATUS, *status);
+            if let Some(instrument_id) = instrument_id {
+                msg.set(SYMBOL, instrument_id.as_str());
+            }
+            msg.set(CUM_QTY, *cumulative_quantity);
+            msg.set(LEAVES_QTY, *leaves_quantity);
+            msg.set(AVG_PX, average_price.into_inner());
+            msg.set(UNTRIGGERED, *untriggered);
+            msg.wrap()
+        }
+        EngineMessage::OrderRejected { client_id, reason, code } => {
+            // Nothing was ever open, so NONE stands in for an OrderID
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, "NONE");
+            msg.set(EXEC_TYPE, ExecType::Rejected);
+            msg.set(ORD_STATUS, OrdStatus::Rejected);
+            msg.set(LEAVES_QTY, 0u64);
+            msg.set(CUM_QTY, 0u64);
+            if let Some(code) = code {
+                msg.set(ORD_REJ_REASON, *code);
+            }
+            msg.set(TEXT, reason.as_str());
+            msg.wrap()
+        }
+        EngineMessage::OrderFilled { client_id, order_id, client_order_id, filled_quantity, remaining_quantity, cumulative_quantity, price, instrument_id, side, arrival_bid, arrival_ask, trade_match_id, average_price, summary } => {
+            // A session taking summaries hears of an order's fills only as the last of them
+            // finishes it, and then of all of them at once
+            if *summary && *remaining_quantity > 0 {
+                return false;
+            }
+            let (last_quantity, last_price) = if *summary { (*cumulative_quantity, *average_price) } else { (*filled_quantity, *price) };
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            if let Some(client_order_id) = client_order_id {
+                msg.set(CL_ORD_ID, client_order_id.as_str());
+            }
+            msg.set(EXEC_TYPE, ExecType::Trade);
+            msg.set(ORD_STATUS, if *remaining_quantity == 0 { OrdStatus::Filled } else { OrdStatus::PartiallyFilled });
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(SIDE, *side);
+            msg.set(LAST_QTY, last_quantity);
+            msg.set(LAST_PX, last_price.into_inner());
+            msg.set(LEAVES_QTY, *remaining_quantity);
+            msg.set(CUM_QTY, *cumulative_quantity);
+            msg.set(AVG_PX, average_price.into_inner());
+            msg.set(TRD_MATCH_ID, *trade_match_id);
+            if let Some(bid) = arrival_bid {
+                msg.set(ARRIVAL_BID, bid.into_inner());
+            }
+            if let Some(ask) = arrival_ask {
+                msg.set(ARRIVAL_ASK, ask.into_inner());
+            }
+            msg.wrap()
+        }
+        EngineMessage::OrderCancelled { client_id, order_id, reason, instrument_id, cancelled_price, cancelled_quantity, side, cumulative_quantity, average_price } => {
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(SIDE, *side);
+            if let Some(price) = cancelled_price {
+                msg.set(PRICE, price.into_inner());
+            }
+            msg.set(CXL_QTY, *cancelled_quantity);
+            msg.set(LEAVES_QTY, 0u64);
+            msg.set(CUM_QTY, *cumulative_quantity);
+            msg.set(AVG_PX, average_price.into_inner());
+            if *reason == CancelReason::Expired {
+                msg.set(EXEC_TYPE, ExecType::Expired);
+                msg.set(ORD_STATUS, OrdStatus::Expired);
+            } else {
+                msg.set(EXEC_TYPE, ExecType::Canceled);
+                msg.set(ORD_STATUS, OrdStatus::Canceled);
+            }
+            // Only cancels the client did not ask for say why; 8 is "Market (Exchange) option"
+            if *reason != CancelReason::ClientRequested {
+                msg.set(EXEC_RESTATEMENT_REASON, match reason {
+                    CancelReason::Other(_) => ExecRestatementReason::Other,
+                    _ => ExecRestatementReason::Market,
+                });
+                msg.set(TEXT, reason.to_string().as_str());
+            }
+            msg.wrap()
+        }
+        EngineMessage::OrderExpired { client_id, order_id } => {
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            msg.set(EXEC_TYPE, ExecType::Expired);
+            msg.set(ORD_STATUS, OrdStatus::Expired);
+            msg.wrap()
+        }
+        EngineMessage::CancelRejected { client_id, order_id, reason, status, cumulative_quantity, average_price } => {
+            let mut msg = start_message(buffer, b"9", Some(client_id));
+            msg.set(ORDER_ID, *order_id);
+            msg.set(ORD_STATUS, *status);
+            msg.set(CXL_REJ_RESPONSE_TO, CxlRejResponseTo::OrderCancelRequest);
+            msg.set(CXL_REJ_REASON, CxlRejReason::TooLateToCancel);
+            msg.set(TEXT, reason.as_str());
+            msg.set(CUM_QTY, *cumulative_quantity);
+            msg.set(AVG_PX, average_price.into_inner());
+            msg.wrap()
+        }
+        EngineMessage::OrderAmended { client_id, order_id, new_quantity, new_price, status, cumulative_quantity, leaves_quantity } => {
+            let mut msg = start_execution_report(buffer, client_id, times);
+            msg.set(ORDER_ID, *order_id);
+            msg.set(EXEC_TYPE, ExecType::Replaced);
+            msg.set(ORD_STATUS, *status);
+            if let Some(quantity) = new_quantity {
+                msg.set(ORDER_QTY, *quantity);
+            }
+            if let Some(price) = new_price {
+                msg.set(PRICE, price.into_inner());
+            }
+            msg.set(CUM_QTY, *cumulative_quantity);
+            msg.set(LEAVES_QTY, *leaves_quantity);
+            msg.wrap()
+        }
+        EngineMessage::AmendRejected { client_id, order_id, reason, status } => {
+            let mut msg = start_message(buffer, b"9", Some(client_id));
+            msg.set(ORDER_ID, *order_id);
+            msg.set(ORD_STATUS, *status);
+            msg.set(CXL_REJ_RESPONSE_TO, CxlRejResponseTo::OrderCancelReplaceRequest);
+            msg.set(CXL_REJ_REASON, match status {
+                OrdStatus::Filled | OrdStatus::Canceled | OrdStatus::Expired => CxlRejReason::TooLateToCancel,
+                _ => CxlRejReason::Other,
+            });
+            msg.set(TEXT, reason.as_str());
+            msg.wrap()
+        }
+        EngineMessage::PositionReport { client_id, account_id, cash, positions } => {
+            let mut msg = start_message(buffer, b"UPR", Some(client_id));
+            msg.set(ACCOUNT, account_id.as_str());
+            msg.set(CASH_BALANCE, cash.into_inner());
+            msg.set(NO_POSITIONS, positions.len());
+            for (instrument_id, quantity, average_cost) in positions {
+                msg.set(SYMBOL, instrument_id.as_str());
+                msg.set(LONG_QTY, *quantity);
+                msg.set(AVG_PX, average_cost.into_inner());
+            }
+            msg.wrap()
+        }
+        EngineMessage::GreeksReport { client_id, account_id, greeks } => {
+            let mut msg = start_message(buffer, b"UGR", Some(client_id));
+            msg.set(ACCOUNT, account_id.as_str());
+            msg.set(DELTA, greeks.delta);
+            msg.set(GAMMA, greeks.gamma);
+            msg.set(VEGA, greeks.vega);
+            msg.wrap()
+        }
+        EngineMessage::CorporateActionApplied { client_id, account_id, instrument_id, action, position, cash_adjustment } => {
+            let mut msg = start_message(buffer, b"UCN", Some(client_id));
+            msg.set(ACCOUNT, account_id.as_str());
+            msg.set(SYMBOL, instrument_id.as_str());
+            match action {
+                CorporateAction::Split { numerator, denominator } => {
+                    msg.set(SPLIT_NUMERATOR, *numerator);
+                    msg.set(SPLIT_DENOMINATOR, *denominator);
+                }
+                CorporateAction::Dividend { amount } => msg.set(DIVIDEND_PER_SHARE, amount.into_inner()),
+            }
+            msg.set(LONG_QTY, *position);
+            msg.set(CASH_ADJUSTMENT, cash_adjustment.into_inner());
+            msg.wrap()
+        }
+        EngineMessage::TradeReport { client_id, instrument_id, price, quantity, timestamp } => {
+            let mut msg = start_message(buffer, b"AE", Some(client_id));
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(LAST_QTY, *quantity);
+            msg.set(LAST_PX, price.into_inner());
+            msg.set(TRANSACT_TIME, timestamp.clone());
+            msg.wrap()
+        }
+        EngineMessage::Snapshot { client_id, timestamp, instrument_id, bids, asks, depth, granularity, bid_orders, ask_orders } => {
+            let mut msg = start_message(buffer, b"W", Some(client_id));
+            msg.set(SYMBOL, instrument_id.as_str());
+            // When the book looked like this, which may be a little before it was asked for
+            msg.set(TRANSACT_TIME, timestamp.clone());
+            if let Some(depth) = depth {
+                msg.set(MARKET_DEPTH, *depth);
+            }
+            match granularity {
+                BookGranularity::ByPrice => {
+                    msg.set(NO_MD_ENTRIES, bids.len() + asks.len());
+                    for (entry_type, levels) in [(MdEntryType::Bid, bids), (MdEntryType::Offer, asks)] {
+                        for (price, quantity) in levels {
+                            msg.set(MD_ENTRY_TYPE, entry_type);
+                            msg.set(MD_ENTRY_PX, price.into_inner());
+                            msg.set(MD_ENTRY_SIZE, *quantity);
+                        }
+                    }
+                }
+                BookGranularity::ByOrder => {
+                    msg.set(MD_BOOK_TYPE, MdBookType::OrderDepth);
+                    msg.set(NO_MD_ENTRIES, bid_orders.len() + ask_orders.len());
+                    for (entry_type, orders) in [(MdEntryType::Bid, bid_orders), (MdEntryType::Offer, ask_orders)] {
+                        for (order_id, price, quantity) in orders {
+                            msg.set(MD_ENTRY_TYPE, entry_type);
+                            msg.set(ORDER_ID, *order_id);
+                            msg.set(MD_ENTRY_PX, price.into_inner());
+                            msg.set(MD_ENTRY_SIZE, *quantity);
+                        }
+                    }
+                }
+            }
+            msg.wrap()
+        }
+        EngineMessage::BookUpdate { client_id, instrument_id, changes } => {
+            // Market Data Incremental Refresh; deletions carry no size
+            let mut msg = start_message(buffer, b"X", Some(client_id));
+            msg.set(NO_MD_ENTRIES, changes.len());
+            for change in changes {
+                let (action, side, price, quantity) = match change {
+                    BookChange::AddLevel { side, price, quantity } => (MdUpdateAction::New, side, price, Some(quantity)),
+                    BookChange::UpdateLevel { side, price, quantity } => (MdUpdateAction::Change, side, price, Some(quantity)),
+                    BookChange::RemoveLevel { side, price } => (MdUpdateAction::Delete, side, price, None),
+                };
+                msg.set(MD_UPDATE_ACTION, action);
+                msg.set(MD_ENTRY_TYPE, if *side == Side::Buy { MdEntryType::Bid } else { MdEntryType::Offer });
+                msg.set(SYMBOL, instrument_id.as_str());
+                msg.set(MD_ENTRY_PX, price.into_inner());
+                if let Some(quantity) = quantity {
+                    msg.set(MD_ENTRY_SIZE, *quantity);
+                }
+            }
+            msg.wrap()
+        }
+        EngineMessage::DepthImbalanceAlert { client_id, instrument_id, ratio, direction, timestamp } => {
+            // News, as exchange-wide alerts are, with the ratio alongside for machine readers
+            let mut msg = start_message(buffer, b"B", Some(client_id));
+            let heavy_side = match direction {
+                ImbalanceDirection::BidHeavy => "bid",
+                ImbalanceDirection::AskHeavy => "ask",
+            };
+            let headline = format!("Depth imbalance on {}: {}-heavy, bid/ask {:.2}", instrument_id, heavy_side, ratio);
+            msg.set(HEADLINE, headline.as_str());
+            msg.set(ORIG_TIME, timestamp.clone());
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(IMBALANCE_RATIO, *ratio);
+            msg.wrap()
+        }
+        EngineMessage::TradingHalt { client_id, instrument_id, halted, reason, resumes_at, timestamp } => {
+            // SecurityStatus: SecurityTradingStatus(326) 2 for the halt and 3 for the resumption
+            let mut msg = start_message(buffer, b"f", Some(client_id));
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(SECURITY_TRADING_STATUS, if *halted { "2" } else { "3" });
+            msg.set(TRANSACT_TIME, timestamp.clone());
+            if let Some(resumes_at) = resumes_at {
+                msg.set(HALT_ENDS, resumes_at.clone());
+            }
+            msg.set(TEXT, reason.as_str());
+            msg.wrap()
+        }
+        EngineMessage::RiskAlert { client_id, account_id, reason } => {
+            let mut msg = start_message(buffer, b"B", Some(client_id));
+            msg.set(HEADLINE, format!("{} on account {}", reason, account_id).as_str());
+            msg.set(ACCOUNT, account_id.as_str());
+            msg.wrap()
+        }
+        EngineMessage::LiquidityReport { client_id, instrument_id, score } => {
+            // Custom type: Liquidity Report
+            let mut msg = start_message(buffer, b"ULR", Some(client_id));
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(BOOK_SPREAD, score.spread);
+            msg.set(BID_DEPTH, score.depth_bid);
+            msg.set(ASK_DEPTH, score.depth_ask);
+            msg.set(FILL_RATE, score.fill_rate);
+            msg.set(IMPACT_COST_ESTIMATE, score.impact_cost_estimate);
+            msg.wrap()
+        }
+        EngineMessage::ActivityReport { client_id, instrument_id, timestamp, activity } => {
+            // Custom type: Activity Report, as of the interval's close at TransactTime(60)
+            let mut msg = start_message(buffer, b"UAR", Some(client_id));
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(TRANSACT_TIME, timestamp.clone());
+            msg.set(TRADES_PER_SECOND, activity.trades_per_second);
+            msg.set(MESSAGES_PER_SECOND, activity.messages_per_second);
+            msg.set(BID_LEVELS, activity.bid_levels);
+            msg.set(ASK_LEVELS, activity.ask_levels);
+            msg.set(MAX_QUEUE_DELAY, activity.max_queue_delay_ms);
+            msg.wrap()
+        }
+        EngineMessage::LastSaleTape { client_id, instrument_id, price, quantity, side, timestamp, trade_id } => {
+            // Trade Capture Report; both sides are listed, the aggressor's flagged, neither after an auction
+            let mut msg = start_message(buffer, b"AE", Some(client_id));
+            msg.set(TRADE_REPORT_ID, *trade_id);
+            msg.set(TRADE_REPORT_TRANS_TYPE, TradeReportTransType::New);
+            msg.set(TRD_MATCH_ID, *trade_id);
+            msg.set(SYMBOL, instrument_id.as_str());
+            msg.set(LAST_QTY, *quantity);
+            msg.set(LAST_PX, price.into_inner());
+            msg.set(TRANSACT_TIME, timestamp.clone());
+            msg.set(NO_SIDES, 2usize);
+            for (listed, aggressor) in [(Side::Buy, AggressorSide::Buy), (Side::Sell, AggressorSide::Sell)] {
+                msg.set(SIDE, listed);
+                msg.set(AGGRESSOR_INDICATOR, *side == aggressor);
+            }
+            msg.wrap()
+        }
+        EngineMessage::LogonAccepted { client_id, heart_bt_int, reset_seq_num, default_appl_ver_id } => {
+            let mut msg = start_message(buffer, b"A", Some(client_id));
+            msg.set(ENCRYPT_METHOD, 0u32);
+            msg.set(HEART_BT_INT, *heart_bt_int);
+            if *reset_seq_num {
+                msg.set(RESET_SEQ_NUM_FLAG, true);
+            }
+            msg.set(DEFAULT_APPL_VER_ID, default_appl_ver_id.as_str());
+            msg.wrap()
+        }
+        EngineMessage::Heartbeat { client_id, test_req_id } => {
+            let mut msg = start_message(buffer, b"0", Some(client_id));
+            if let Some(test_req_id) = test_req_id {
+                msg.set(TEST_REQ_ID, test_req_id.as_str());
+            }
+            msg.wrap()
+        }
+        EngineMessage::Logout { client_id, text } => {
+            let mut msg = start_message(buffer, b"5", Some(client_id));
+            if let Some(text) = text {
+                msg.set(TEXT, text.as_str());
+            }
+            msg.wrap()
+        }
+        EngineMessage::LogEvent { client_id, message } => {
+            let mut msg = start_message(buffer, b"B", client_id.as_ref());
+            msg.set(HEADLINE, message.as_str());
+            msg.wrap()
+        }
+        EngineMessage::InvalidMessage { reason, .. } => {
+            let mut msg = start_message(buffer, b"3", None);
+            msg.set(TEXT, reason.as_str());
+            msg.wrap()
+        }
+        EngineMessage::PositionQuery { .. }
+        | EngineMessage::GreeksRequest { .. }
+        | EngineMessage::InboundExecutionReport { .. }
+        | EngineMessage::CorporateAction { .. }
+        | EngineMessage::SetRestingOrderLimit { .. }
+        | EngineMessage::SetSmpAction { .. }
+        | EngineMessage::SetRiskLimits { .. }
+        | EngineMessage::SetAdminFlag { .. }
+        | EngineMessage::SetAccountAuthorization { .. }
+        | EngineMessage::RequestReplay { .. }
+        | EngineMessage::Logon { .. }
+        | EngineMessage::TestRequest { .. }
+        | EngineMessage::SubscribeOrderBook { .. }
+        | EngineMessage::UnsubscribeOrderBook { .. }
+        | EngineMessage::SessionClosed { .. }
+        | EngineMessage::AdvanceTime { .. }
+        | EngineMessage::Schedule { .. }
+        | EngineMessage::PublishBookViews
+        | EngineMessage::PublishActivity
+        | EngineMessage::Compact { .. }
+        | EngineMessage::RejectionLogQuery { .. }
+        | EngineMessage::RejectionLogReport { .. }
+        | EngineMessage::TradeHistoryQuery { .. }
+        | EngineMessage::TradeHistoryReport { .. }
+        | EngineMessage::OrderHistoryQuery { .. }
+        | EngineMessage::OrderHistoryReport { .. }
+        | EngineMessage::StatementRequest { .. }
+        | EngineMessage::AccountStatements { .. }
+        | EngineMessage::TradeExecuted { .. }
+        | EngineMessage::SetInstrumentMetadata { .. }
+        | EngineMessage::GetInstrumentMetadata { .. }
+        | EngineMessage::InstrumentMetadataResponse { .. } => return false,
+    }
+    buffer.extend_from_slice(b"\n");
+    true
+}
+
+// Logout (5) for a connection turned away before it has a session
+pub fn serialize_logout(text: &str) -> Bytes {
+    let mut buffer = BytesMut::new();
+    let mut msg = start_message(&mut buffer, b"5", None);
+    msg.set(TEXT, text);
+    msg.wrap();
+    buffer.extend_from_slice(b"\n");
+    buffer.freeze()
+}
+
+// Heartbeat (0) and TradingSessionStatus (h) telling a session the exchange is alive, what
+// phase it is in and how far behind the engine is. The exchange trades one session a day.
+pub fn serialize_exchange_status(status: &ExchangeStatus, client_id: &ClientID) -> Bytes {
+    let mut buffer = BytesMut::new();
+    start_message(&mut buffer, b"0", Some(client_id)).wrap();
+    buffer.extend_from_slice(b"\n");
+    write_trading_session_status(&mut buffer, status, client_id);
+    buffer.freeze()
+}
+
+// The TradingSessionStatus alone, as sent the moment the exchange goes cancel-only or
+// comes out of it
+pub fn serialize_trading_session_status(status: &ExchangeStatus, client_id: &ClientID) -> Bytes {
+    let mut buffer = BytesMut::new();
+    write_trading_session_status(&mut buffer, status, client_id);
+    buffer.freeze()
+}
+
+fn write_trading_session_status(buffer: &mut BytesMut, status: &ExchangeStatus, client_id: &ClientID) {
+    let mut msg = start_message(buffer, b"h", Some(client_id));
+    msg.set(TRADING_SESSION_ID, "1");
+    msg.set(TRAD_SES_STATUS, status.phase as u32);
+    msg.set(TRANSACT_TIME, status.timestamp.clone());
+    msg.set(QUEUE_DEPTH_BUCKET, status.queue_depth as u32);
+    msg.set(CANCEL_ONLY, status.cancel_only);
+    msg.wrap();
+    buffer.extend_from_slice(b"\n");
+}
@@ -0,0 +1,1024 @@
+// Typed builders for the messages the exchange takes in, and checks on those it sends, so that
+// tests name fields instead of assembling tag=value strings by hand. A builder starts as FIRM1
+// writing to the exchange at 2024-01-02 14:30:00.125 and encodes with BodyLength and CheckSum
+// worked out, any repeating group last.
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use fefix::definitions::HardCodedFixFieldDefinition;
+use fefix::dict::IsFieldDefinition;
+use fefix::tagvalue::Encoder;
+use fefix::TagU16;
+
+use super::*;
+use crate::exchange::Exchange;
+
+// A field of the dictionary's, or one of the exchange's own tags
+pub(crate) trait Tag {
+    fn number(&self) -> u16;
+}
+
+impl Tag for &HardCodedFixFieldDefinition {
+    fn number(&self) -> u16 {
+        IsFieldDefinition::tag(*self).get()
+    }
+}
+
+impl Tag for u32 {
+    fn number(&self) -> u16 {
+        *self as u16
+    }
+}
+
+impl Tag for u16 {
+    fn number(&self) -> u16 {
+        *self
+    }
+}
+
+// The value as it goes on the wire
+fn wire<V: for<'a> FixValue<'a>>(value: V) -> String {
+    String::from_utf8_lossy(&value.to_bytes()).into_owned()
+}
+
+// A message of the kind `M`, as a client would send it
+#[derive(Debug, Clone)]
+pub(crate) struct Builder<M> {
+    sender: String,
+    sub_id: Option<String>,
+    target: String,
+    sending_time: String,
+    fields: Vec<(u16, String)>,
+    group: Vec<(u16, String)>, // written after every other field
+    kind: PhantomData<M>,
+}
+
+pub(crate) trait MsgType {
+    const MSG_TYPE: &'static [u8];
+}
+
+impl<M: MsgType> Builder<M> {
+    fn new() -> Self {
+        Self {
+            sender: "FIRM1".to_string(),
+            sub_id: None,
+            target: EXCHANGE_COMP_ID.to_string(),
+            sending_time: "20240102-14:30:00.125".to_string(),
+            fields: Vec::new(),
+            group: Vec::new(),
+            kind: PhantomData,
+        }
+    }
+
+    pub(crate) fn sender(mut self, sender: &str) -> Self {
+        self.sender = sender.to_string();
+        self
+    }
+
+    pub(crate) fn sub_id(mut self, sub_id: &str) -> Self {
+        self.sub_id = Some(sub_id.to_string());
+        self
+    }
+
+    pub(crate) fn sending_time(mut self, sending_time: &str) -> Self {
+        self.sending_time = sending_time.to_string();
+        self
+    }
+
+    // Any field at all, for what the typed setters do not cover, invalid values included
+    pub(crate) fn field(mut self, tag: impl Tag, value: &str) -> Self {
+        self.fields.push((tag.number(), value.to_string()));
+        self
+    }
+
+    fn set<V: for<'a> FixValue<'a>>(self, tag: impl Tag, value: V) -> Self {
+        self.field(tag, &wire(value))
+    }
+
+    fn set_text(self, tag: impl Tag, value: &str) -> Self {
+        self.field(tag, value)
+    }
+
+    pub(crate) fn build_string(&self, separator: u8) -> String {
+        let mut encoder = Encoder::<Config>::default();
+        encoder.config_mut().set_separator(separator);
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(BEGIN_STRING, &mut buffer, M::MSG_TYPE);
+        msg.set(SENDER_COMP_ID, self.sender.as_str());
+        if let Some(sub_id) = &self.sub_id {
+            msg.set(SENDER_SUB_ID, sub_id.as_str());
+        }
+        msg.set(TARGET_COMP_ID, self.target.as_str());
+        msg.set_any(IsFieldDefinition::tag(SENDING_TIME), self.sending_time.as_str());
+        for (tag, value) in self.fields.iter().chain(&self.group) {
+            msg.set_any(TagU16::new(*tag).expect("tag 0"), value.as_str());
+        }
+        String::from_utf8_lossy(msg.wrap()).into_owned()
+    }
+
+    // '|'-separated, as the parser reads it
+    pub(crate) fn build(&self) -> String {
+        self.build_string(b'|')
+    }
+
+    pub(crate) fn parse(&self) -> EngineMessage {
+        handle_fix_message(&self.build())
+    }
+}
+
+macro_rules! message_types {
+    ($($name:ident = $msg_type:literal,)*) => {$(
+        #[derive(Debug, Clone)]
+        pub(crate) struct $name;
+
+        impl MsgType for $name {
+            const MSG_TYPE: &'static [u8] = $msg_type;
+        }
+
+        impl $name {
+            pub(crate) fn builder() -> Builder<Self> {
+                Builder::new()
+            }
+        }
+    )*};
+}
+
+message_types! {
+    NewOrderSingle = b"D",
+    NewOrderMultileg = b"AB",
+    OrderCancelRequest = b"F",
+    OrderCancelReplaceRequest = b"G",
+    OrderStatusRequest = b"H",
+    MarketDataRequest = b"V",
+    ExecutionReport = b"8",
+    Logon = b"A",
+    Heartbeat = b"0",
+    TestRequest = b"1",
+    Logout = b"5",
+    CreateInstrument = b"UCI",
+    PositionQuery = b"UPQ",
+    GreeksRequest = b"UGQ",
+    CorporateActionRequest = b"UCA",
+    RestingOrderLimit = b"URL",
+    SmpSetting = b"USM",
+    RiskLimitsRequest = b"URK",
+    ReplayRequest = b"URR",
+    AdvanceTime = b"UAT",
+    AlertSubscription = b"UAS",
+    TradingStatus = b"UTS",
+    RollSession = b"URS",
+    WarmUp = b"UWU",
+    SymbolStatusRequest = b"USR",
+    Ping = b"UPN",
+    ActivityQuery = b"UAQ",
+    InstrumentSpecUpdate = b"UUS",
+}
+
+// Setters shared by several message types, each of which carries the field
+macro_rules! setters {
+    ($($name:ident),*; account) => {$(
+        impl Builder<$name> {
+            pub(crate) fn account(self, account_id: &str) -> Self {
+                self.set_text(ACCOUNT, account_id)
+            }
+        }
+    )*};
+    ($($name:ident),*; symbol) => {$(
+        impl Builder<$name> {
+            pub(crate) fn symbol(self, instrument_id: &str) -> Self {
+                self.set_text(SYMBOL, instrument_id)
+            }
+        }
+    )*};
+    ($($name:ident),*; segment) => {$(
+        impl Builder<$name> {
+            pub(crate) fn segment(self, segment: &str) -> Self {
+                self.set_text(MARKET_SEGMENT_ID, segment)
+            }
+        }
+    )*};
+    ($($name:ident),*; increments) => {$(
+        impl Builder<$name> {
+            pub(crate) fn tick_size(self, tick_size: f64) -> Self {
+                self.set(MIN_PRICE_INCREMENT, tick_size)
+            }
+
+            pub(crate) fn lot_size(self, lot_size: Quantity) -> Self {
+                self.set(ROUND_LOT, lot_size)
+            }
+        }
+    )*};
+}
+
+setters!(NewOrderSingle, NewOrderMultileg, OrderCancelRequest, ExecutionReport, PositionQuery, GreeksRequest, SmpSetting, RiskLimitsRequest; account);
+setters!(
+    NewOrderSingle, ExecutionReport, CreateInstrument, CorporateActionRequest, ReplayRequest, TradingStatus, WarmUp,
+    MarketDataRequest, ActivityQuery, InstrumentSpecUpdate;
+    symbol
+);
+setters!(CreateInstrument, TradingStatus; segment);
+setters!(CreateInstrument, InstrumentSpecUpdate; increments);
+
+impl Builder<NewOrderSingle> {
+    pub(crate) fn cl_ord_id(self, client_order_id: &str) -> Self {
+        self.set_text(CL_ORD_ID, client_order_id)
+    }
+
+    pub(crate) fn side(self, side: Side) -> Self {
+        self.set(SIDE, side)
+    }
+
+    pub(crate) fn qty(self, quantity: Quantity) -> Self {
+        self.set(ORDER_QTY, quantity)
+    }
+
+    pub(crate) fn order_type(self, order_type: OrdType) -> Self {
+        self.set(ORD_TYPE, order_type)
+    }
+
+    pub(crate) fn price(self, price: f64) -> Self {
+        self.set(PRICE, price)
+    }
+
+    pub(crate) fn limit(self, price: f64) -> Self {
+        self.order_type(OrdType::Limit).price(price)
+    }
+
+    pub(crate) fn market(self) -> Self {
+        self.order_type(OrdType::Market)
+    }
+
+    pub(crate) fn time_in_force(self, time_in_force: TimeInForce) -> Self {
+        self.set(TIME_IN_FORCE, time_in_force)
+    }
+
+    pub(crate) fn expire_time(self, expire_time: &str) -> Self {
+        self.set_text(EXPIRE_TIME, expire_time)
+    }
+
+    pub(crate) fn good_for_auction(self) -> Self {
+        self.set_text(TIME_IN_FORCE, GOOD_FOR_AUCTION)
+    }
+
+    // A NoStipulations(232) group of (StipulationType, StipulationValue) entries
+    pub(crate) fn stipulations(mut self, stipulations: &[(&str, &str)]) -> Self {
+        self.group = vec![(NO_STIPULATIONS.number(), format!("{}", stipulations.len()))];
+        for (stipulation_type, value) in stipulations {
+            self.group.push((STIPULATION_TYPE.number(), String::from(*stipulation_type)));
+            self.group.push((STIPULATION_VALUE.number(), String::from(*value)));
+        }
+        self
+    }
+}
+
+impl Builder<NewOrderMultileg> {
+    pub(crate) fn cl_ord_id(self, client_order_id: &str) -> Self {
+        self.set_text(CL_ORD_ID, client_order_id)
+    }
+
+    // A NoLegs(555) group of (LegSymbol, LegSide, LegQty, LegPrice) entries, None for a market leg
+    pub(crate) fn legs(mut self, legs: &[(&str, Side, Quantity, Option<f64>)]) -> Self {
+        self.group = vec![(NO_LEGS.number(), format!("{}", legs.len()))];
+        for (instrument_id, side, quantity, price) in legs {
+            self.group.push((LEG_SYMBOL.number(), String::from(*instrument_id)));
+            self.group.push((LEG_SIDE.number(), wire(*side)));
+            self.group.push((LEG_QTY.number(), wire(*quantity)));
+            if let Some(price) = price {
+                self.group.push((LEG_PRICE.number(), wire(*price)));
+            }
+        }
+        self
+    }
+}
+
+impl Builder<OrderCancelRequest> {
+    pub(crate) fn order_id(self, order_id: OrderID) -> Self {
+        self.set(ORDER_ID, order_id)
+    }
+
+    pub(crate) fn orig_cl_ord_id(self, client_order_id: &str) -> Self {
+        self.set_text(ORIG_CL_ORD_ID, client_order_id)
+    }
+
+    // Cancels only this much of the order
+    pub(crate) fn cxl_qty(self, quantity: Quantity) -> Self {
+        self.set(CXL_QTY, quantity)
+    }
+}
+
+impl Builder<OrderStatusRequest> {
+    pub(crate) fn order_id(self, order_id: OrderID) -> Self {
+        self.set(ORDER_ID, order_id)
+    }
+}
+
+impl Builder<OrderCancelReplaceRequest> {
+    pub(crate) fn order_id(self, order_id: OrderID) -> Self {
+        self.set(ORDER_ID, order_id)
+    }
+
+    // The new total, what has filled included
+    pub(crate) fn qty(self, quantity: Quantity) -> Self {
+        self.set(ORDER_QTY, quantity)
+    }
+
+    pub(crate) fn price(self, price: f64) -> Self {
+        self.set(PRICE, price)
+    }
+
+    pub(crate) fn time_in_force(self, time_in_force: TimeInForce) -> Self {
+        self.set(TIME_IN_FORCE, time_in_force)
+    }
+}
+
+impl Builder<MarketDataRequest> {
+    pub(crate) fn snapshot(self) -> Self {
+        self.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::Snapshot)
+    }
+
+    pub(crate) fn subscribe(self) -> Self {
+        self.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::SnapshotPlusUpdates)
+    }
+
+    pub(crate) fn unsubscribe(self) -> Self {
+        self.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::DisablePreviousSnapshotPlusUpdateRequest)
+    }
+
+    // A NoRelatedSym(146) group in place of a lone Symbol
+    pub(crate) fn symbols(mut self, instrument_ids: &[&str]) -> Self {
+        self.group = vec![(NO_RELATED_SYM.number(), format!("{}", instrument_ids.len()))];
+        self.group.extend(instrument_ids.iter().map(|instrument_id| (SYMBOL.number(), String::from(*instrument_id))));
+        self
+    }
+
+    pub(crate) fn depth(self, depth: u32) -> Self {
+        self.set(MARKET_DEPTH, depth)
+    }
+
+    pub(crate) fn book_type(self, book_type: MdBookType) -> Self {
+        self.set(MD_BOOK_TYPE, book_type)
+    }
+
+    // Each book's activity report too, every activity interval
+    pub(crate) fn activity_reports(self, activity: bool) -> Self {
+        self.set(ACTIVITY_REPORTS, activity)
+    }
+
+    // A trade capture report for every trade on the books subscribed
+    pub(crate) fn last_sale(self, last_sale: bool) -> Self {
+        self.set(LAST_SALE_FEED, last_sale)
+    }
+}
+
+impl Builder<ExecutionReport> {
+    pub(crate) fn exec_id(self, exec_id: &str) -> Self {
+        self.set_text(EXEC_ID, exec_id)
+    }
+
+    // The venue's order ID, whatever form it takes
+    pub(crate) fn order_id(self, order_id: &str) -> Self {
+        self.set_text(ORDER_ID, order_id)
+    }
+
+    pub(crate) fn exec_type(self, exec_type: ExecType) -> Self {
+        self.set(EXEC_TYPE, exec_type)
+    }
+
+    pub(crate) fn side(self, side: Side) -> Self {
+        self.set(SIDE, side)
+    }
+
+    pub(crate) fn last_qty(self, quantity: Quantity) -> Self {
+        self.set(LAST_QTY, quantity)
+    }
+
+    pub(crate) fn last_px(self, price: f64) -> Self {
+        self.set(LAST_PX, price)
+    }
+}
+
+impl Builder<Logon> {
+    pub(crate) fn msg_seq_num(self, msg_seq_num: u64) -> Self {
+        self.set(MSG_SEQ_NUM, msg_seq_num)
+    }
+
+    pub(crate) fn reset_seq_num(self, reset: bool) -> Self {
+        self.set(RESET_SEQ_NUM_FLAG, reset)
+    }
+
+    pub(crate) fn heart_bt_int(self, seconds: u32) -> Self {
+        self.set(HEART_BT_INT, seconds)
+    }
+
+    pub(crate) fn summary_fills(self, summary: bool) -> Self {
+        self.set(SUMMARY_FILLS, summary)
+    }
+}
+
+impl Builder<Heartbeat> {
+    pub(crate) fn test_req_id(self, test_req_id: &str) -> Self {
+        self.set_text(TEST_REQ_ID, test_req_id)
+    }
+}
+
+impl Builder<TestRequest> {
+    pub(crate) fn test_req_id(self, test_req_id: &str) -> Self {
+        self.set_text(TEST_REQ_ID, test_req_id)
+    }
+}
+
+impl Builder<Logout> {
+    pub(crate) fn text(self, text: &str) -> Self {
+        self.set_text(TEXT, text)
+    }
+}
+
+impl Builder<CreateInstrument> {
+    pub(crate) fn max_price_levels(self, levels: usize) -> Self {
+        self.set(MAX_BOOK_LEVELS, levels)
+    }
+
+    pub(crate) fn price_level_policy(self, policy: PriceLevelPolicy) -> Self {
+        let policy = match policy {
+            PriceLevelPolicy::EvictWorst => "E",
+            PriceLevelPolicy::Reject => "R",
+        };
+        self.set_text(PRICE_LEVEL_POLICY, policy)
+    }
+
+    pub(crate) fn max_resting_orders(self, orders: usize) -> Self {
+        self.set(MAX_RESTING_ORDERS, orders)
+    }
+
+    pub(crate) fn uncross_policy(self, policy: UncrossPolicy) -> Self {
+        let policy = match policy {
+            UncrossPolicy::RestingPrices => "R",
+            UncrossPolicy::Auction => "A",
+        };
+        self.set_text(UNCROSS_POLICY, policy)
+    }
+
+    pub(crate) fn halt_policy(self, policy: HaltPolicy) -> Self {
+        let policy = match policy {
+            HaltPolicy::CancelAll => "C",
+            HaltPolicy::Freeze => "F",
+        };
+        self.set_text(HALT_POLICY, policy)
+    }
+
+    // Halts the book for `seconds` once its spread passes `multiplier` times the reference,
+    // which starts at `reference_spread`
+    pub(crate) fn spread_halt(self, multiplier: f64, reference_spread: f64, seconds: u64) -> Self {
+        self.set(SPREAD_HALT_MULTIPLIER, multiplier).set(REFERENCE_SPREAD, reference_spread).set(HALT_DURATION, seconds)
+    }
+
+    pub(crate) fn order_types(self, order_types: &[OrdType]) -> Self {
+        let order_types: Vec<_> = order_types.iter().map(|order_type| wire(*order_type)).collect();
+        self.set_text(ORDER_TYPES, &order_types.join(" "))
+    }
+
+    pub(crate) fn option(self, underlying: &str, strike: f64, expiry: &str, put_or_call: PutOrCall, implied_volatility: f64) -> Self {
+        self.set_text(UNDERLYING_SYMBOL, underlying)
+            .set(STRIKE_PRICE, strike)
+            .set_text(OPTION_EXPIRY, expiry)
+            .set(PUT_OR_CALL, put_or_call)
+            .set(VOLATILITY, implied_volatility)
+    }
+
+    // Each leg as Symbol and ratio, the ratio negative for a leg sold
+    pub(crate) fn spread_legs(self, legs: &[(&str, i64)]) -> Self {
+        let legs: Vec<_> = legs.iter().map(|(instrument_id, ratio)| format!("{}:{}", instrument_id, ratio)).collect();
+        self.set_text(SPREAD_LEGS, &legs.join(" "))
+    }
+
+    pub(crate) fn spread_anchor(self, instrument_id: &str) -> Self {
+        self.set_text(SPREAD_ANCHOR, instrument_id)
+    }
+}
+
+impl Builder<CorporateActionRequest> {
+    pub(crate) fn effective_time(self, effective_time: &str) -> Self {
+        self.set_text(EFFECTIVE_TIME, effective_time)
+    }
+
+    pub(crate) fn split(self, numerator: u64, denominator: u64) -> Self {
+        self.set(SPLIT_NUMERATOR, numerator).set(SPLIT_DENOMINATOR, denominator)
+    }
+}
+
+impl Builder<Ping> {
+    pub(crate) fn ping_id(self, ping_id: u64) -> Self {
+        self.set(PING_ID, ping_id)
+    }
+}
+
+impl Builder<RestingOrderLimit> {
+    pub(crate) fn max_resting_orders(self, orders: usize) -> Self {
+        self.set(MAX_RESTING_ORDERS, orders)
+    }
+}
+
+impl Builder<SmpSetting> {
+    pub(crate) fn smp_action(self, smp_action: SmpAction) -> Self {
+        let smp_action = match smp_action {
+            SmpAction::Allow => "N",
+            SmpAction::RejectAggressor => "R",
+        };
+        self.set_text(SMP_ACTION, smp_action)
+    }
+}
+
+impl Builder<RiskLimitsRequest> {
+    pub(crate) fn max_order_value(self, value: f64) -> Self {
+        self.set(MAX_ORDER_VALUE, value)
+    }
+
+    pub(crate) fn max_daily_loss(self, loss: f64) -> Self {
+        self.set(MAX_DAILY_LOSS, loss)
+    }
+}
+
+impl Builder<ReplayRequest> {
+    pub(crate) fn between(self, from: &str, to: &str) -> Self {
+        self.set_text(REPLAY_FROM_TIME, from).set_text(REPLAY_TO_TIME, to)
+    }
+}
+
+impl Builder<AdvanceTime> {
+    pub(crate) fn to(self, time: &str) -> Self {
+        self.set_text(TRANSACT_TIME, time)
+    }
+}
+
+impl Builder<AlertSubscription> {
+    pub(crate) fn subscribe(self) -> Self {
+        self.set(SUBSCRIPTION_REQUEST_TYPE, SubscriptionRequestType::SnapshotPlusUpdates)
+    }
+}
+
+impl Builder<TradingStatus> {
+    pub(crate) fn halt(self) -> Self {
+        self.set_text(SECURITY_TRADING_STATUS, "2")
+    }
+
+    pub(crate) fn resume(self) -> Self {
+        self.set_text(SECURITY_TRADING_STATUS, "3")
+    }
+}
+
+impl Builder<WarmUp> {
+    pub(crate) fn open_time(self, open_time: &str) -> Self {
+        self.set_text(TRAD_SES_OPEN_TIME, open_time)
+    }
+}
+
+// A message the exchange sent, read back field by field. Panics unless it is well framed:
+// BeginString, BodyLength and MsgType first, the body as long as it says and CheckSum last
+// and right.
+#[derive(Debug)]
+pub(crate) struct Outbound {
+    text: String,
+    fields: Vec<(u16, String)>,
+}
+
+impl Outbound {
+    pub(crate) fn parse(text: &str) -> Self {
+        let message = text.trim_end_matches('\n');
+        let separator = if message.contains(SOH) { SOH } else { '|' };
+        let fields: Vec<(u16, String)> = message
+            .strip_suffix(separator)
+            .unwrap_or(message)
+            .split(separator)
+            .map(|field| {
+                let (tag, value) = field.split_once('=').unwrap_or_else(|| panic!("field {:?} is not tag=value in {}", field, text));
+                (tag.parse().unwrap_or_else(|_| panic!("tag {:?} is not a number in {}", tag, text)), value.to_string())
+            })
+            .collect();
+        let tags: Vec<u16> = fields.iter().map(|(tag, _)| *tag).collect();
+        assert!(tags.starts_with(&[8, 9, 35]) && tags.last() == Some(&10), "misordered header or trailer: {}", text);
+
+        let body_start = message.find(&format!("{}35=", separator)).unwrap() + 1;
+        let trailer_start = message.rfind(&format!("{}10=", separator)).unwrap() + 1;
+        assert_eq!(fields[1].1.parse::<usize>().ok(), Some(trailer_start - body_start), "wrong BodyLength: {}", text);
+        let checksum = checksum(&message[..trailer_start]);
+        assert_eq!(fields[fields.len() - 1].1, format!("{:03}", checksum), "wrong CheckSum: {}", text);
+        Self { text: text.to_string(), fields }
+    }
+
+    pub(crate) fn msg_type(&self) -> &str {
+        &self.fields[2].1
+    }
+
+    pub(crate) fn get(&self, tag: impl Tag) -> Option<&str> {
+        let tag = tag.number();
+        self.fields.iter().find(|(field, _)| *field == tag).map(|(_, value)| value.as_str())
+    }
+
+    // Asserts the field is there and reads as `expected`
+    pub(crate) fn expect<V>(self, tag: impl Tag, expected: V) -> Self
+    where
+        V: for<'a> FixValue<'a> + PartialEq + Debug,
+    {
+        let tag = tag.number();
+        let value = self.get(tag).unwrap_or_else(|| panic!("no tag {} in {}", tag, self.text));
+        let actual = V::deserialize(value.as_bytes()).unwrap_or_else(|_| panic!("tag {} is {:?}, not a {}", tag, value, std::any::type_name::<V>()));
+        assert_eq!(actual, expected, "tag {} in {}", tag, self.text);
+        self
+    }
+
+    pub(crate) fn expect_text(self, tag: impl Tag, expected: &str) -> Self {
+        assert_eq!(self.get(tag.number()), Some(expected), "tag {} in {}", tag.number(), self.text);
+        self
+    }
+
+    pub(crate) fn expect_absent(self, tag: impl Tag) -> Self {
+        assert_eq!(self.get(tag.number()), None, "tag {} in {}", tag.number(), self.text);
+        self
+    }
+
+    pub(crate) fn ord_status(self, status: OrdStatus) -> Self {
+        self.expect(ORD_STATUS, status)
+    }
+
+    pub(crate) fn exec_type(self, exec_type: ExecType) -> Self {
+        self.expect(EXEC_TYPE, exec_type)
+    }
+
+    pub(crate) fn order_id(self, order_id: OrderID) -> Self {
+        self.expect(ORDER_ID, order_id)
+    }
+
+    pub(crate) fn symbol(self, instrument_id: &str) -> Self {
+        self.expect_text(SYMBOL, instrument_id)
+    }
+
+    pub(crate) fn last_px(self, price: f64) -> Self {
+        self.expect(LAST_PX, price)
+    }
+
+    pub(crate) fn last_qty(self, quantity: Quantity) -> Self {
+        self.expect(LAST_QTY, quantity)
+    }
+
+    pub(crate) fn cum_qty(self, quantity: Quantity) -> Self {
+        self.expect(CUM_QTY, quantity)
+    }
+
+    pub(crate) fn avg_px(self, price: f64) -> Self {
+        self.expect(AVG_PX, price)
+    }
+
+    pub(crate) fn leaves_qty(self, quantity: Quantity) -> Self {
+        self.expect(LEAVES_QTY, quantity)
+    }
+}
+
+// An ExecutionReport as the exchange would send `message`, to check field by field
+pub(crate) fn expect_exec_report(message: &EngineMessage) -> Outbound {
+    let report = Outbound::parse(&serialize_engine_message(message).unwrap_or_else(|| panic!("no FIX encoding for {:?}", message)));
+    assert_eq!(report.msg_type(), "8", "not an ExecutionReport: {}", report.text);
+    report
+}
+
+// Shorthands for driving an Exchange in tests, as a client and an admin would
+
+pub(crate) fn client(name: &str) -> ClientID {
+    ClientID::new(name.to_string(), None)
+}
+
+// Moves the exchange's clock to `time`, given as FIX text
+pub(crate) fn advance_time(exchange: &mut Exchange, time: &str) -> Vec<EngineMessage> {
+    let time = Timestamp::parse(time.as_bytes()).unwrap_or_else(|| panic!("not a FIX timestamp: {}", time));
+    exchange.handle_message(EngineMessage::AdvanceTime {
+        sending_time: time.clone(),
+        receiving_time: time.clone(),
+        client_id: client("ADMIN"),
+        timestamp: time,
+    })
+}
+
+// A limit order for AAPL, sent by `firm` for its own account
+pub(crate) fn order(exchange: &mut Exchange, firm: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+    order_in(exchange, firm, "AAPL", side, quantity, price)
+}
+
+pub(crate) fn order_in(exchange: &mut Exchange, firm: &str, symbol: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderSingle::builder().sender(firm).account(firm).symbol(symbol).side(side).qty(quantity).limit(price).parse())
+}
+
+// The OrderID the exchange gave the order `events` answer, which open with its acceptance
+pub(crate) fn accepted_order_id(events: &[EngineMessage]) -> OrderID {
+    match events.first() {
+        Some(EngineMessage::OrderAccepted { order_id, .. }) => *order_id,
+        _ => panic!("order not accepted: {:?}", events),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{BookGranularity, CancelReason, InstrumentScope};
+
+    fn at(time: &str) -> Timestamp {
+        Timestamp::parse(time.as_bytes()).unwrap()
+    }
+
+    // The parse, with its receive time put back to the one expected, as it is stamped on parsing
+    fn parsed_as(parsed: EngineMessage, expected: &EngineMessage) -> EngineMessage {
+        let mut parsed = parsed;
+        let received = |message: &EngineMessage| match message {
+            EngineMessage::NewOrder { receiving_time, .. }
+            | EngineMessage::CancelOrder { receiving_time, .. }
+            | EngineMessage::AmendOrder { receiving_time, .. }
+            | EngineMessage::CreateInstrument { receiving_time, .. }
+            | EngineMessage::SetTradingStatus { receiving_time, .. }
+            | EngineMessage::AdvanceTime { receiving_time, .. }
+            | EngineMessage::InboundExecutionReport { receiving_time, .. } => Some(receiving_time.clone()),
+            _ => None,
+        };
+        if let Some(expected_time) = received(expected) {
+            match &mut parsed {
+                EngineMessage::NewOrder { receiving_time, .. }
+                | EngineMessage::CancelOrder { receiving_time, .. }
+                | EngineMessage::AmendOrder { receiving_time, .. }
+                | EngineMessage::CreateInstrument { receiving_time, .. }
+                | EngineMessage::SetTradingStatus { receiving_time, .. }
+                | EngineMessage::AdvanceTime { receiving_time, .. }
+                | EngineMessage::InboundExecutionReport { receiving_time, .. } => *receiving_time = expected_time,
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    fn assert_decodes<M: MsgType>(builder: Builder<M>, expected: EngineMessage) {
+        assert_eq!(parsed_as(builder.parse(), &expected), expected, "{}", builder.build());
+    }
+
+    #[test]
+    fn a_new_order_single_decodes_to_the_order_it_describes() {
+        let order = NewOrderSingle::builder()
+            .sender("CLIENT1")
+            .account("CLIENT1")
+            .cl_ord_id("C-7")
+            .symbol("AAPL")
+            .side(Side::Buy)
+            .qty(100)
+            .limit(10.5)
+            .time_in_force(TimeInForce::GoodTillDate)
+            .expire_time("20240105-21:00:00.000");
+        assert_decodes(order, EngineMessage::NewOrder {
+            sending_time: at("20240102-14:30:00.125"),
+            receiving_time: timestamp_now(),
+            client_id: client("CLIENT1"),
+            account_id: "CLIENT1".to_string(),
+            client_order_id: Some("C-7".to_string()),
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 100,
+            price: Some(Price::from(10.5)),
+            time_in_force: Some(TimeInForce::GoodTillDate),
+            expire_time: Some(at("20240105-21:00:00.000")),
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        });
+    }
+
+    #[test]
+    fn an_orders_stipulations_decode_entry_for_entry_wherever_the_group_sits() {
+        let stipulations = vec![
+            Stipulation { stipulation_type: "MINQTY".to_string(), value: "100".to_string() },
+            Stipulation { stipulation_type: "LOTVAR".to_string(), value: "5".to_string() },
+        ];
+        let last = NewOrderSingle::builder().account("FIRM1").symbol("AAPL").side(Side::Buy).qty(100).limit(10.5).stipulations(&[("MINQTY", "100"), ("LOTVAR", "5")]);
+        // Where FIX puts it, with the quantity, type and price still to come
+        let within = NewOrderSingle::builder()
+            .account("FIRM1")
+            .symbol("AAPL")
+            .side(Side::Buy)
+            .field(NO_STIPULATIONS, "2")
+            .field(STIPULATION_TYPE, "MINQTY")
+            .field(STIPULATION_VALUE, "100")
+            .field(STIPULATION_TYPE, "LOTVAR")
+            .field(STIPULATION_VALUE, "5")
+            .qty(100)
+            .limit(10.5);
+        for order in [last, within] {
+            let EngineMessage::NewOrder { quantity, order_type, price, stipulations: decoded, .. } = order.parse() else {
+                panic!("{} did not decode to an order", order.build());
+            };
+            assert_eq!((quantity, order_type, price), (100, OrdType::Limit, Some(Price::from(10.5))));
+            assert_eq!(decoded, stipulations);
+        }
+    }
+
+    #[test]
+    fn a_multileg_order_decodes_each_leg_with_its_own_price_or_none() {
+        let order = NewOrderMultileg::builder().account("FIRM1").cl_ord_id("S-1").legs(&[("ESZ4", Side::Buy, 2, Some(101.5)), ("ESH5", Side::Sell, 2, None)]);
+        let EngineMessage::NewOrderMultileg { account_id, client_order_id, legs, .. } = order.parse() else {
+            panic!("{} did not decode to a multileg order", order.build());
+        };
+        assert_eq!((account_id.as_str(), client_order_id.as_deref()), ("FIRM1", Some("S-1")));
+        assert_eq!(legs, [
+            MultilegLeg { instrument_id: "ESZ4".to_string(), side: Side::Buy, quantity: 2, price: Some(Price::from(101.5)) },
+            MultilegLeg { instrument_id: "ESH5".to_string(), side: Side::Sell, quantity: 2, price: None },
+        ]);
+
+        let sideless = NewOrderMultileg::builder().account("FIRM1").field(NO_LEGS, "1").field(LEG_SYMBOL, "ESZ4").field(LEG_QTY, "2");
+        assert!(matches!(sideless.parse(), EngineMessage::InvalidMessage { reason, .. } if reason == "Invalid NoLegs group"));
+    }
+
+    #[test]
+    fn a_stipulations_group_that_does_not_match_its_count_is_refused() {
+        let order = || NewOrderSingle::builder().account("FIRM1").symbol("AAPL").side(Side::Buy).qty(100).limit(10.5);
+        let short = order().field(NO_STIPULATIONS, "3").field(STIPULATION_TYPE, "MINQTY").field(STIPULATION_VALUE, "100");
+        let overlong = order()
+            .field(NO_STIPULATIONS, "1")
+            .field(STIPULATION_TYPE, "MINQTY")
+            .field(STIPULATION_VALUE, "100")
+            .field(STIPULATION_TYPE, "LOTVAR")
+            .field(STIPULATION_VALUE, "5");
+        let valueless = order().field(NO_STIPULATIONS, "1").field(STIPULATION_TYPE, "MINQTY");
+        for order in [short, overlong, valueless] {
+            assert!(
+                matches!(order.parse(), EngineMessage::InvalidMessage { reason, .. } if reason == "Invalid NoStipulations group"),
+                "{}", order.build()
+            );
+        }
+        // The decoder cannot count what is not a number
+        let uncounted = order().field(NO_STIPULATIONS, "two").field(STIPULATION_TYPE, "MINQTY").field(STIPULATION_VALUE, "100");
+        assert!(matches!(uncounted.parse(), EngineMessage::InvalidMessage { reason, .. } if reason == "Invalid NoStipulations(232)"));
+    }
+
+    #[test]
+    fn cancels_amends_and_admin_messages_decode_to_what_they_ask_for() {
+        let sent = at("20240102-14:30:00.125");
+        assert_decodes(OrderCancelRequest::builder().order_id(42).account("FIRM1").cxl_qty(3), EngineMessage::CancelOrder {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: client("FIRM1"),
+            account_id: "FIRM1".to_string(),
+            order_id: 42,
+            orig_client_order_id: None,
+            cancel_quantity: Some(3),
+        });
+        // Without an OrderID, the OrigClOrdID alone names the order
+        assert_decodes(OrderCancelRequest::builder().orig_cl_ord_id("C-7").account("FIRM1"), EngineMessage::CancelOrder {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: client("FIRM1"),
+            account_id: "FIRM1".to_string(),
+            order_id: 0,
+            orig_client_order_id: Some("C-7".to_string()),
+            cancel_quantity: None,
+        });
+        assert_decodes(OrderCancelReplaceRequest::builder().sub_id("ALGO").order_id(42).qty(7).price(11.25), EngineMessage::AmendOrder {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())),
+            order_id: 42,
+            new_quantity: Some(7),
+            new_price: Some(Price::from(11.25)),
+            time_in_force: None,
+        });
+        let instrument = CreateInstrument::builder()
+            .sender("ADMIN")
+            .symbol("ESZ4")
+            .segment("FUT")
+            .max_price_levels(5)
+            .price_level_policy(PriceLevelPolicy::Reject)
+            .halt_policy(HaltPolicy::Freeze)
+            .tick_size(0.25)
+            .order_types(&[OrdType::Limit, OrdType::Market]);
+        assert_decodes(instrument, EngineMessage::CreateInstrument {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: client("ADMIN"),
+            instrument_id: "ESZ4".to_string(),
+            segment: Some("FUT".to_string()),
+            spec: SpecOverrides {
+                max_price_levels: Some(5),
+                price_level_policy: Some(PriceLevelPolicy::Reject),
+                halt_policy: Some(HaltPolicy::Freeze),
+                tick_size: Some(Price::from(0.25)),
+                order_types: Some(BTreeSet::from([OrderType::Limit, OrderType::Market])),
+                ..SpecOverrides::default()
+            },
+            spread: None,
+        });
+        assert_decodes(TradingStatus::builder().sender("ADMIN").segment("FUT").halt(), EngineMessage::SetTradingStatus {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: client("ADMIN"),
+            scope: InstrumentScope::Segment("FUT".to_string()),
+            halted: true,
+        });
+        assert_decodes(AdvanceTime::builder().sender("ADMIN").to("20240102-15:00:00.000"), EngineMessage::AdvanceTime {
+            sending_time: sent.clone(),
+            receiving_time: timestamp_now(),
+            client_id: client("ADMIN"),
+            timestamp: at("20240102-15:00:00.000"),
+        });
+        let snapshot = MarketDataRequest::builder().symbol("AAPL").snapshot().depth(5).book_type(MdBookType::OrderDepth);
+        assert_decodes(snapshot, EngineMessage::Snapshot {
+            client_id: client("FIRM1"),
+            timestamp: sent,
+            instrument_id: "AAPL".to_string(),
+            bids: Vec::new(),
+            asks: Vec::new(),
+            depth: Some(5),
+            granularity: BookGranularity::ByOrder,
+            bid_orders: Vec::new(),
+            ask_orders: Vec::new(),
+        });
+    }
+
+    fn both<M: MsgType>(builder: Builder<M>) -> [String; 2] {
+        [builder.build_string(b'|'), builder.build_string(SOH as u8)]
+    }
+
+    // Every builder produces a message the parser takes, whichever separator it is written with
+    #[test]
+    fn every_message_type_builds_to_something_the_parser_accepts() {
+        let built = [
+            both(NewOrderSingle::builder().account("FIRM1").symbol("AAPL").side(Side::Sell).qty(1).market()),
+            both(NewOrderMultileg::builder().account("FIRM1").legs(&[("ESZ4", Side::Buy, 1, Some(10.0)), ("ESH5", Side::Sell, 1, None)])),
+            both(OrderCancelRequest::builder().order_id(1).account("FIRM1")),
+            both(OrderCancelReplaceRequest::builder().order_id(1).qty(2)),
+            both(OrderStatusRequest::builder().order_id(1)),
+            both(MarketDataRequest::builder().subscribe().symbols(&["AAPL", "FUT-*"])),
+            both(MarketDataRequest::builder().unsubscribe().symbol("AAPL")),
+            both(ExecutionReport::builder().exec_id("X1").order_id("V-1").exec_type(ExecType::Trade).account("FIRM1").symbol("AAPL").side(Side::Buy).last_qty(1).last_px(10.0)),
+            both(Logon::builder().msg_seq_num(1).reset_seq_num(true).heart_bt_int(30)),
+            both(Heartbeat::builder().test_req_id("T1")),
+            both(TestRequest::builder().test_req_id("T1")),
+            both(Logout::builder().text("done")),
+            both(CreateInstrument::builder().symbol("AAPL-C100").option("AAPL", 100.0, "20250102-14:30:00.000", PutOrCall::Call, 0.2)),
+            both(CreateInstrument::builder().symbol("ES-SPREAD").spread_legs(&[("ESZ4", 1), ("ESH5", -1)]).spread_anchor("ESZ4")),
+            both(PositionQuery::builder().account("FIRM1")),
+            both(GreeksRequest::builder().account("FIRM1")),
+            both(CorporateActionRequest::builder().symbol("AAPL").effective_time("20240103-00:00:00.000").split(2, 1)),
+            both(RestingOrderLimit::builder().max_resting_orders(10)),
+            both(SmpSetting::builder().account("FIRM1").smp_action(SmpAction::RejectAggressor)),
+            both(RiskLimitsRequest::builder().account("FIRM1").max_order_value(1000.0).max_daily_loss(50.0)),
+            both(ReplayRequest::builder().symbol("AAPL").between("20240102-00:00:00.000", "20240103-00:00:00.000")),
+            both(AdvanceTime::builder().to("20240102-15:00:00.000")),
+            both(AlertSubscription::builder().subscribe()),
+            both(TradingStatus::builder().symbol("AAPL").resume()),
+            both(RollSession::builder()),
+            both(WarmUp::builder().symbol("AAPL").open_time("20240102-14:35:00.000")),
+            both(SymbolStatusRequest::builder()),
+            both(Ping::builder().ping_id(1)),
+            both(ActivityQuery::builder().symbol("AAPL")),
+            both(InstrumentSpecUpdate::builder().symbol("AAPL").tick_size(0.05).lot_size(100)),
+        ];
+        for message in built.iter().flatten() {
+            let conformed = conform(message, Conformance::Strict).unwrap_or_else(|deviations| panic!("{}: {}", deviations, message));
+            let parsed = handle_fix_message(&conformed);
+            assert!(!matches!(parsed, EngineMessage::InvalidMessage { .. } | EngineMessage::LogEvent { .. }), "{:?} from {}", parsed, message);
+        }
+    }
+
+    #[test]
+    fn an_outbound_fill_is_checked_field_by_field() {
+        let fill = EngineMessage::OrderFilled {
+            client_id: client("CLIENT1"),
+            order_id: 7,
+            client_order_id: None,
+            filled_quantity: 40,
+            remaining_quantity: 60,
+            cumulative_quantity: 40,
+            price: Price::from(10.5),
+            instrument_id: "AAPL".to_string(),
+            side: Side::Buy,
+            arrival_bid: None,
+            arrival_ask: None,
+            trade_match_id: 1,
+            average_price: Price::from(10.5),
+            summary: false,
+        };
+        expect_exec_report(&fill)
+            .exec_type(ExecType::Trade)
+            .ord_status(OrdStatus::PartiallyFilled)
+            .order_id(7)
+            .symbol("AAPL")
+            .last_px(10.5)
+            .last_qty(40)
+            .leaves_qty(60);
+
+        let cancel = EngineMessage::OrderCancelled {
+            client_id: client("CLIENT1"),
+            order_id: 7,
+            reason: CancelReason::ClientRequested,
+            instrument_id: "AAPL".to_string(),
+            cancelled_price: Some(Price::from(10.5)),
+            cancelled_quantity: 60,
+            side: Side::Buy,
+            cumulative_quantity: 40,
+            average_price: Price::from(10.5),
+        };
+        expect_exec_report(&cancel).exec_type(ExecType::Canceled).ord_status(OrdStatus::Canceled).expect_absent(TEXT);
+    }
+}
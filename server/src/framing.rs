@@ -0,0 +1,346 @@
+use std::fmt::Display;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Where a message begins. It is only taken for a boundary where neither a digit nor an '='
+// leads into it, so neither 58=FIX... nor 58=8=FIX... inside a message is one.
+const BEGIN: &[u8] = b"8=FIX";
+const SOH: u8 = 0x01;
+// The longest a message may run before it is taken for junk and skipped
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+// The most of a failed message's bytes kept for logs and the audit log
+pub const MAX_RAW_CAPTURE: usize = 1024;
+const READ_SIZE: usize = 8 * 1024;
+
+// The bytes of something that could not be read as a message, hex encoded wherever they are
+// written, as they need not be text. Only the first MAX_RAW_CAPTURE are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawMessage {
+    #[serde(with = "hex::serde")]
+    pub bytes: Vec<u8>,
+    pub length: usize, // of the whole, however much was kept
+    pub failed_at: Option<usize>, // offset of the byte reading broke down at, when known
+}
+
+impl RawMessage {
+    pub fn capture(bytes: &[u8], failed_at: Option<usize>) -> Self {
+        Self {
+            bytes: bytes[..bytes.len().min(MAX_RAW_CAPTURE)].to_vec(),
+            length: bytes.len(),
+            failed_at,
+        }
+    }
+
+    // A message that was read as text but made no sense as FIX
+    pub fn text(message: &str) -> Self {
+        Self::capture(message.as_bytes(), None)
+    }
+}
+
+impl Display for RawMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes", self.length)?;
+        if let Some(failed_at) = self.failed_at {
+            write!(f, ", failed at byte {}", failed_at)?;
+        }
+        write!(f, ": {}", hex::encode(&self.bytes))?;
+        if self.bytes.len() < self.length {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+// What the framer cuts a session's byte stream into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    // From BeginString to a newline, or to the SOH after CheckSum(10), neither included but the SOH
+    Message(Bytes),
+    // Bytes that were no message: junk between messages, a message cut short by the next one,
+    // or one that ran past MAX_FRAME_LEN. `offset` is where in the stream they began.
+    Garbage { raw: RawMessage, offset: u64 },
+}
+
+// Cuts a byte stream into messages, resyncing past whatever is not one at the next plausible
+// BeginString. Scanning picks up where it left off as bytes arrive, and junk is held to
+// MAX_FRAME_LEN before it goes as one frame, so a burst of it costs no more than its length.
+#[derive(Debug, Default)]
+pub struct MessageFramer {
+    buffer: BytesMut,
+    scanned: usize, // the buffer's bytes already searched, from its start
+    field_start: usize, // where the field being scanned began
+    offset: u64, // of the buffer's first byte in the stream
+}
+
+impl MessageFramer {
+    // The next whole frame, or None until more bytes arrive
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        // Blank lines, and the \r of a \r\n, fall between messages
+        let blank = self.buffer.iter().take_while(|byte| is_blank(**byte)).count();
+        if blank > 0 {
+            self.take(blank);
+        }
+        if self.buffer.is_empty() || BEGIN.starts_with(&self.buffer) {
+            return None;
+        }
+        if !self.buffer.starts_with(BEGIN) {
+            return self.skip_to_boundary();
+        }
+        let limit = self.buffer.len().min(MAX_FRAME_LEN);
+        let mut at = self.scanned.max(BEGIN.len());
+        while at < limit {
+            match self.buffer[at] {
+                b'\n' => {
+                    let length = if self.buffer[at - 1] == b'\r' { at - 1 } else { at };
+                    return Some(Frame::Message(self.take(length)));
+                }
+                SOH if is_checksum(&self.buffer[self.field_start..at]) => return Some(Frame::Message(self.take(at + 1))),
+                SOH | b'|' => self.field_start = at + 1,
+                b'8' if !leads_into(self.buffer[at - 1]) => match self.boundary_at(at) {
+                    // Cut short by the next message
+                    Some(true) => return Some(self.garbage(at)),
+                    // Perhaps the next message; more bytes will tell
+                    None => {
+                        self.scanned = at;
+                        return None;
+                    }
+                    Some(false) => {}
+                },
+                _ => {}
+            }
+            at += 1;
+        }
+        self.scanned = at;
+        (at == MAX_FRAME_LEN).then(|| self.garbage(at))
+    }
+
+    // Junk runs to the next plausible BeginString, or goes once it reaches MAX_FRAME_LEN
+    fn skip_to_boundary(&mut self) -> Option<Frame> {
+        let limit = self.buffer.len().min(MAX_FRAME_LEN);
+        let mut at = self.scanned.max(1);
+        while at < limit {
+            if self.buffer[at] == b'8' && !leads_into(self.buffer[at - 1]) {
+                match self.boundary_at(at) {
+                    Some(true) => return Some(self.garbage(at)),
+                    None => break,
+                    Some(false) => {}
+                }
+            }
+            at += 1;
+        }
+        self.scanned = at;
+        (at == MAX_FRAME_LEN).then(|| self.garbage(at))
+    }
+
+    // Whether a BeginString starts at `at`, or None if too few bytes have arrived to tell
+    fn boundary_at(&self, at: usize) -> Option<bool> {
+        let candidate = &self.buffer[at..self.buffer.len().min(at + BEGIN.len())];
+        match (candidate.len() == BEGIN.len(), BEGIN.starts_with(candidate)) {
+            (false, true) => None,
+            (_, is_begin) => Some(is_begin),
+        }
+    }
+
+    // What is left once no more bytes will come: a message without its terminator, or junk
+    fn finish(&mut self) -> Option<Frame> {
+        if self.buffer.iter().all(|byte| is_blank(*byte)) {
+            return None;
+        }
+        let length = self.buffer.len();
+        match self.buffer.starts_with(BEGIN) {
+            true => Some(Frame::Message(self.take(length))),
+            false => Some(self.garbage(length)),
+        }
+    }
+
+    fn garbage(&mut self, length: usize) -> Frame {
+        let (offset, raw) = (self.offset, RawMessage::capture(&self.buffer[..length], Some(0)));
+        self.take(length);
+        Frame::Garbage { raw, offset }
+    }
+
+    // Splits off the buffer's first `length` bytes, and starts scanning what follows afresh
+    fn take(&mut self, length: usize) -> Bytes {
+        self.offset += length as u64;
+        self.scanned = 0;
+        self.field_start = 0;
+        self.buffer.split_to(length).freeze()
+    }
+}
+
+fn leads_into(byte: u8) -> bool {
+    byte.is_ascii_digit() || byte == b'='
+}
+
+fn is_blank(byte: u8) -> bool {
+    matches!(byte, b'\r' | b'\n' | b' ')
+}
+
+// Whether a field is CheckSum(10), three digits or fewer
+fn is_checksum(field: &[u8]) -> bool {
+    field.strip_prefix(b"10=").is_some_and(|value| (1..=3).contains(&value.len()) && value.iter().all(u8::is_ascii_digit))
+}
+
+// A session's frames as they arrive from its connection
+pub struct FrameReader<R> {
+    reader: R,
+    framer: MessageFramer,
+    closed: bool,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, framer: MessageFramer::default(), closed: false }
+    }
+
+    // The next frame, or None once the connection has closed and everything it sent is framed.
+    // Whatever is left unterminated at the close is framed as it stands.
+    pub async fn next_frame(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.framer.next_frame() {
+                return Some(frame);
+            }
+            if self.closed {
+                return self.framer.finish();
+            }
+            self.framer.buffer.reserve(READ_SIZE);
+            match self.reader.read_buf(&mut self.framer.buffer).await {
+                Ok(0) | Err(_) => self.closed = true,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const PIPED: &[u8] = b"8=FIXT.1.1|9=40|35=D|49=FIRM1|56=EXCHANGE|55=AAPL|10=123|";
+    const SOH_FRAMED: &[u8] = b"8=FIXT.1.1\x019=40\x0135=D\x0149=FIRM1\x0156=EXCHANGE\x0158=8=FIX\x0110=045\x01";
+
+    // Every frame the framer cuts `stream` into, fed `chunk` bytes at a time
+    fn frames(stream: &[u8], chunk: usize) -> Vec<Frame> {
+        let mut framer = MessageFramer::default();
+        let mut frames = Vec::new();
+        for bytes in stream.chunks(chunk) {
+            framer.buffer.extend_from_slice(bytes);
+            frames.extend(std::iter::from_fn(|| framer.next_frame()));
+        }
+        frames.extend(framer.finish());
+        frames
+    }
+
+    fn message(bytes: &[u8]) -> Frame {
+        Frame::Message(Bytes::copy_from_slice(bytes))
+    }
+
+    fn garbage(bytes: &[u8], offset: u64) -> Frame {
+        Frame::Garbage { raw: RawMessage::capture(bytes, Some(0)), offset }
+    }
+
+    #[test]
+    fn messages_end_at_a_newline_or_the_soh_after_their_checksum_whatever_they_arrive_in() {
+        let stream = [PIPED, b"\r\n", SOH_FRAMED, SOH_FRAMED, b"\n\n", PIPED].concat();
+        for chunk in [1, 3, 7, stream.len()] {
+            assert_eq!(frames(&stream, chunk), vec![message(PIPED), message(SOH_FRAMED), message(SOH_FRAMED), message(PIPED)], "chunk {}", chunk);
+        }
+    }
+
+    #[test]
+    fn junk_between_messages_is_skipped_to_the_next_begin_string_and_says_where_it_was() {
+        let junk: &[u8] = &[0xff, 0xfe, 0x00, b'5', b'8', b'=', b'F', b'I', b'X', 0x80, b'|'];
+        let stream = [junk, PIPED, b"\n", junk, SOH_FRAMED].concat();
+        let offset = (junk.len() + PIPED.len() + 1) as u64;
+        assert_eq!(frames(&stream, 2), vec![garbage(junk, 0), message(PIPED), garbage(junk, offset), message(SOH_FRAMED)]);
+    }
+
+    #[test]
+    fn a_message_cut_short_by_the_next_is_skipped_and_the_next_is_read_whole() {
+        let truncated = &PIPED[..20];
+        let stream = [truncated, SOH_FRAMED, truncated].concat();
+        assert_eq!(frames(&stream, 5), vec![garbage(truncated, 0), message(SOH_FRAMED), message(truncated)]);
+        // Left unterminated at the close, what remains is a message all the same
+        let cut_off = [truncated, b"\x01\xff"].concat();
+        assert_eq!(frames(&cut_off, 5), vec![message(&cut_off)]);
+    }
+
+    #[test]
+    fn a_message_running_past_the_limit_is_skipped_with_only_its_start_kept() {
+        let endless = [&PIPED[..20], &vec![b'x'; MAX_FRAME_LEN + 100]].concat();
+        let frames = frames(&[&endless[..], b"\n", PIPED, b"\n"].concat(), 4096);
+        let [Frame::Garbage { raw, offset: 0 }, Frame::Garbage { .. }, last] = frames.as_slice() else { panic!("{:?}", frames) };
+        assert_eq!((raw.length, raw.bytes.len(), &raw.bytes[..20]), (MAX_FRAME_LEN, MAX_RAW_CAPTURE, &PIPED[..20]));
+        assert!(raw.to_string().ends_with("..."), "{}", raw);
+        assert_eq!(*last, message(PIPED));
+    }
+
+    #[test]
+    fn a_burst_of_junk_arriving_in_small_pieces_is_scanned_once() {
+        // Rescanning the buffer on every arrival would take hours over this much
+        let junk: Vec<u8> = b"x8=FI".iter().cycle().take(8 * 1024 * 1024).copied().collect();
+        let frames = frames(&[&junk[..], b"|", PIPED].concat(), 64);
+        let skipped: usize = frames.iter().map(|frame| match frame {
+            Frame::Garbage { raw, .. } => raw.length,
+            Frame::Message(_) => 0,
+        }).sum();
+        assert_eq!((skipped, frames.last()), (junk.len() + 1, Some(&message(PIPED))));
+        assert!(frames.len() <= junk.len() / MAX_FRAME_LEN + 2, "{}", frames.len());
+    }
+
+    // Random binary junk between messages, in random pieces: every message comes out whole and
+    // in order, and every skip names bytes the stream really had where it says
+    #[test]
+    fn fuzzed_junk_interleaved_with_messages_never_costs_a_message() {
+        let mut rng = ChaCha8Rng::seed_from_u64(419);
+        for _ in 0..200 {
+            let (mut stream, mut expected) = (Vec::new(), Vec::new());
+            for _ in 0..rng.gen_range(1..20) {
+                let mut junk: Vec<u8> = (0..rng.gen_range(0..200)).map(|_| rng.gen()).collect();
+                if junk.last().is_some_and(|byte| leads_into(*byte)) {
+                    junk.push(0xff);
+                }
+                stream.extend(junk);
+                let framed = if rng.gen_bool(0.5) { PIPED } else { SOH_FRAMED };
+                stream.extend(framed);
+                if framed == PIPED || rng.gen_bool(0.2) {
+                    stream.push(b'\n');
+                }
+                expected.push(message(framed));
+            }
+            let mut framer = MessageFramer::default();
+            let mut framed = Vec::new();
+            let mut rest = &stream[..];
+            while !rest.is_empty() {
+                let (bytes, remaining) = rest.split_at(rng.gen_range(1..=300).min(rest.len()));
+                framer.buffer.extend_from_slice(bytes);
+                framed.extend(std::iter::from_fn(|| framer.next_frame()));
+                rest = remaining;
+            }
+            framed.extend(framer.finish());
+
+            for frame in &framed {
+                if let Frame::Garbage { raw, offset } = frame {
+                    let offset = *offset as usize;
+                    assert_eq!(&stream[offset..offset + raw.bytes.len()], &raw.bytes[..]);
+                }
+            }
+            let messages: Vec<_> = framed.into_iter().filter(|frame| matches!(frame, Frame::Message(_))).collect();
+            assert_eq!(messages, expected);
+        }
+    }
+
+    #[test]
+    fn raw_bytes_are_written_and_read_back_as_hex() {
+        let raw = RawMessage::capture(&[0x38, 0x3d, 0xff, 0x01], Some(2));
+        assert_eq!(raw.to_string(), "4 bytes, failed at byte 2: 383dff01");
+        let json = serde_json::to_string(&raw).unwrap();
+        assert_eq!(json, r#"{"bytes":"383dff01","length":4,"failed_at":2}"#);
+        assert_eq!(serde_json::from_str::<RawMessage>(&json).unwrap(), raw);
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+#[cfg(target_os = "linux")]
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::LiveConfig;
+use crate::fix::serialize_logout;
+use crate::wire::stamp_outbound;
+
+// Past this many tracked source addresses, idle ones are forgotten
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+// Connections each listener queues before the kernel refuses more
+#[cfg(target_os = "linux")]
+const ACCEPT_BACKLOG: i32 = 1024;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    pub max_connections: usize, // open at once across all sources, 0 = unlimited
+    pub max_connects_per_window: usize, // per source address, 0 = unlimited
+    pub rate_window: Duration,
+    pub violations_before_ban: usize, // rate limit breaches that get a source banned
+    pub ban_duration: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_connects_per_window: 20,
+            rate_window: Duration::from_secs(1),
+            violations_before_ban: 5,
+            ban_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    AtCapacity,
+    RateLimited,
+    Banned,
+}
+
+impl Refusal {
+    fn reason(&self) -> &'static str {
+        match self {
+            Refusal::AtCapacity => "Too many connections",
+            Refusal::RateLimited => "Connection rate limit exceeded",
+            Refusal::Banned => "Source temporarily banned",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GatewayMetrics {
+    pub active_connections: usize,
+    pub refused_at_capacity: u64,
+    pub refused_rate_limited: u64,
+    pub refused_banned: u64, // attempts from a source while it was banned
+    pub bans: u64,
+    pub failed_logons: u64,
+}
+
+#[derive(Default)]
+struct SourceHistory {
+    recent_connects: VecDeque<Instant>, // within the rate window
+    violations: usize,
+    banned_until: Option<Instant>,
+}
+
+// Decides, before anything is spawned for it, whether an accepted connection may open a session
+pub struct ConnectionGate {
+    config: Arc<LiveConfig>, // the limits are read afresh for every attempt, so a reload applies at once
+    active: AtomicUsize,
+    sources: Mutex<HashMap<IpAddr, SourceHistory>>,
+    refused_at_capacity: AtomicU64,
+    refused_rate_limited: AtomicU64,
+    refused_banned: AtomicU64,
+    bans: AtomicU64,
+    failed_logons: AtomicU64,
+}
+
+// Holds one of the gate's connection slots until dropped
+pub struct ConnectionPermit {
+    gate: Arc<ConnectionGate>,
+    source: IpAddr,
+}
+
+impl ConnectionPermit {
+    // Counts a failed logon on this connection against the source it came from
+    pub fn record_failed_logon(&self) {
+        self.gate.record_failed_logon(self.source, Instant::now());
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.gate.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ConnectionGate {
+    // Limits that never change, as tests want
+    #[cfg(test)]
+    pub fn new(limits: ConnectionLimits) -> Arc<Self> {
+        Self::with_config(LiveConfig::fixed(crate::config::ServerConfig::default().with_connection_limits(&limits)))
+    }
+
+    pub fn with_config(config: Arc<LiveConfig>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            active: AtomicUsize::new(0),
+            sources: Mutex::new(HashMap::new()),
+            refused_at_capacity: AtomicU64::new(0),
+            refused_rate_limited: AtomicU64::new(0),
+            refused_banned: AtomicU64::new(0),
+            bans: AtomicU64::new(0),
+            failed_logons: AtomicU64::new(0),
+        })
+    }
+
+    // Every attempt counts toward the source's rate, refused or not, so a client stuck in
+    // a reconnect loop keeps breaching its limit until it is banned
+    pub fn admit(self: &Arc<Self>, source: IpAddr, now: Instant) -> Result<ConnectionPermit, Refusal> {
+        let limits = self.config.snapshot().connection_limits();
+        let mut sources = self.sources.lock();
+        if sources.len() > MAX_TRACKED_SOURCES {
+            let window = limits.rate_window;
+            sources.retain(|_, history| {
+                history.banned_until.is_some_and(|until| until > now)
+                    || history.recent_connects.back().is_some_and(|last| now.duration_since(*last) < window)
+            });
+        }
+        let history = sources.entry(source).or_default();
+
+        match history.banned_until {
+            Some(until) if until > now => {
+                self.refused_banned.fetch_add(1, Ordering::Relaxed);
+                return Err(Refusal::Banned);
+            }
+            Some(_) => {
+                history.banned_until = None;
+                history.violations = 0;
+            }
+            None => {}
+        }
+
+        while history.recent_connects.front().is_some_and(|at| now.duration_since(*at) >= limits.rate_window) {
+            history.recent_connects.pop_front();
+        }
+        history.recent_connects.push_back(now);
+        if limits.max_connects_per_window != 0 && history.recent_connects.len() > limits.max_connects_per_window {
+            history.violations += 1;
+            if limits.violations_before_ban != 0 && history.violations >= limits.violations_before_ban {
+                history.banned_until = Some(now + limits.ban_duration);
+                history.recent_connects.clear();
+                self.bans.fetch_add(1, Ordering::Relaxed);
+                self.refused_banned.fetch_add(1, Ordering::Relaxed);
+                return Err(Refusal::Banned);
+            }
+            self.refused_rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::RateLimited);
+        }
+
+        // Slots are only taken under the lock, so concurrent accepts cannot overshoot the cap
+        if limits.max_connections != 0 && self.active.load(Ordering::SeqCst) >= limits.max_connections {
+            self.refused_at_capacity.fetch_add(1, Ordering::Relaxed);
+            return Err(Refusal::AtCapacity);
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(ConnectionPermit { gate: Arc::clone(self), source })
+    }
+
+    // A failed logon is a violation like a rate limit breach, so guessing credentials gets a source banned
+    pub fn record_failed_logon(&self, source: IpAddr, now: Instant) {
+        self.failed_logons.fetch_add(1, Ordering::Relaxed);
+        let limits = self.config.snapshot().connection_limits();
+        let mut sources = self.sources.lock();
+        let history = sources.entry(source).or_default();
+        history.violations += 1;
+        if limits.violations_before_ban != 0 && history.violations >= limits.violations_before_ban {
+            history.banned_until = Some(now + limits.ban_duration);
+            history.recent_connects.clear();
+            self.bans.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> GatewayMetrics {
+        GatewayMetrics {
+            active_connections: self.active.load(Ordering::SeqCst),
+            refused_at_capacity: self.refused_at_capacity.load(Ordering::Relaxed),
+            refused_rate_limited: self.refused_rate_limited.load(Ordering::Relaxed),
+            refused_banned: self.refused_banned.load(Ordering::Relaxed),
+            bans: self.bans.load(Ordering::Relaxed),
+            failed_logons: self.failed_logons.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// `count` non-blocking listeners on `address`, all with SO_REUSEPORT, so each producer thread
+// accepts from a queue of its own and the kernel spreads new connections across them rather
+// than every thread waking for each one. A port of 0 is picked by the first and shared.
+#[cfg(target_os = "linux")]
+pub fn reuse_port_listeners(address: &str, count: usize) -> std::io::Result<Vec<std::net::TcpListener>> {
+    use std::net::ToSocketAddrs;
+    let mut address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to bind"))?;
+    let mut listeners = Vec::with_capacity(count);
+    for _ in 0..count.max(1) {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into())?;
+        socket.listen(ACCEPT_BACKLOG)?;
+        let listener = std::net::TcpListener::from(socket);
+        address = listener.local_addr()?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+// Accepts connections forever. Admitted ones are handed to `serve` with their permit;
+// refused ones get a Logout, written without blocking, and are closed on the spot.
+pub async fn accept_connections<F, Fut>(listener: TcpListener, gate: Arc<ConnectionGate>, serve: F)
+where
+    F: Fn(TcpStream, ConnectionPermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, address)) => match gate.admit(address.ip(), Instant::now()) {
+                Ok(permit) => {
+                    tokio::spawn(serve(stream, permit));
+                }
+                Err(refusal) => {
+                    // A plain write on the still non-blocking socket: tokio's try_write would
+                    // refuse until the stream had been polled for readiness
+                    if let Ok(mut stream) = stream.into_std() {
+                        let _ = stream.write(&stamp_outbound(serialize_logout(refusal.reason()), &mut 1));
+                    }
+                    eprintln!("Refused connection from {}: {} ({:?})", address, refusal.reason(), gate.metrics());
+                }
+            },
+            Err(e) => {
+                eprintln!("TCP connection failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+    use super::*;
+
+    fn limits(max_connections: usize, max_connects_per_window: usize) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections,
+            max_connects_per_window,
+            rate_window: Duration::from_secs(1),
+            violations_before_ban: 3,
+            ban_duration: Duration::from_secs(10),
+        }
+    }
+
+    fn source(last_octet: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet))
+    }
+
+    #[test]
+    fn capacity_frees_up_when_a_permit_is_dropped() {
+        let gate = ConnectionGate::new(limits(2, 0));
+        let now = Instant::now();
+        let first = gate.admit(source(1), now).unwrap();
+        let _second = gate.admit(source(2), now).unwrap();
+        assert_eq!(gate.admit(source(3), now).err(), Some(Refusal::AtCapacity));
+
+        drop(first);
+        assert!(gate.admit(source(3), now).is_ok());
+        assert_eq!(gate.metrics().refused_at_capacity, 1);
+    }
+
+    #[test]
+    fn repeated_rate_violations_ban_the_source_until_the_ban_expires() {
+        let gate = ConnectionGate::new(limits(0, 2));
+        let start = Instant::now();
+        let mut permits = Vec::new();
+        for _ in 0..2 {
+            permits.push(gate.admit(source(1), start).unwrap());
+        }
+        assert_eq!(gate.admit(source(1), start).err(), Some(Refusal::RateLimited));
+        assert_eq!(gate.admit(source(1), start).err(), Some(Refusal::RateLimited));
+        assert_eq!(gate.admit(source(1), start).err(), Some(Refusal::Banned));
+
+        // A ban outlasts the rate window and does not touch other sources
+        let later = start + Duration::from_secs(5);
+        assert_eq!(gate.admit(source(1), later).err(), Some(Refusal::Banned));
+        assert!(gate.admit(source(2), later).is_ok());
+
+        assert!(gate.admit(source(1), start + Duration::from_secs(11)).is_ok());
+        assert_eq!(gate.metrics(), GatewayMetrics {
+            active_connections: 2,
+            refused_at_capacity: 0,
+            refused_rate_limited: 2,
+            refused_banned: 2,
+            bans: 1,
+            failed_logons: 0,
+        });
+    }
+
+    #[test]
+    fn failed_logons_count_toward_a_ban() {
+        let gate = ConnectionGate::new(limits(0, 0));
+        let now = Instant::now();
+        for _ in 0..3 {
+            gate.admit(source(1), now).unwrap().record_failed_logon();
+        }
+        assert_eq!(gate.admit(source(1), now).err(), Some(Refusal::Banned));
+        assert!(gate.admit(source(2), now).is_ok());
+        assert_eq!((gate.metrics().failed_logons, gate.metrics().bans), (3, 1));
+    }
+
+    // Echoes lines back until the client hangs up
+    async fn echo(stream: TcpStream, _permit: ConnectionPermit) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if writer.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    type Session = (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf);
+
+    async fn connect(address: std::net::SocketAddr) -> Session {
+        let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+        (BufReader::new(reader).lines(), writer)
+    }
+
+    async fn round_trip(session: &mut Session, text: &str) -> Option<String> {
+        session.1.write_all(format!("{}\n", text).as_bytes()).await.unwrap();
+        session.0.next_line().await.ok().flatten()
+    }
+
+    #[tokio::test]
+    async fn connections_past_the_cap_are_refused_while_existing_sessions_keep_working() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let gate = ConnectionGate::new(limits(2, 0));
+        tokio::spawn(accept_connections(listener, Arc::clone(&gate), echo));
+
+        let mut first = connect(address).await;
+        let mut second = connect(address).await;
+        assert_eq!(round_trip(&mut first, "one").await.as_deref(), Some("one"));
+        assert_eq!(round_trip(&mut second, "two").await.as_deref(), Some("two"));
+
+        let (mut refused, _writer) = connect(address).await;
+        let logout = refused.next_line().await.unwrap().unwrap();
+        assert!(logout.contains("|35=5|") && logout.contains("58=Too many connections"), "{}", logout);
+        assert_eq!(refused.next_line().await.unwrap(), None);
+
+        assert_eq!(round_trip(&mut first, "still").await.as_deref(), Some("still"));
+        assert_eq!(round_trip(&mut second, "here").await.as_deref(), Some("here"));
+        assert_eq!(gate.metrics().refused_at_capacity, 1);
+        assert_eq!(gate.metrics().active_connections, 2);
+    }
+
+    // Each socket's own queue holds what the kernel handed it, so both are drained to count
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reuse_port_listeners_share_one_port_and_each_takes_connections() {
+        let listeners = reuse_port_listeners("127.0.0.1:0", 2).unwrap();
+        let address = listeners[0].local_addr().unwrap();
+        assert_eq!(listeners[1].local_addr().unwrap(), address);
+        assert!(std::net::TcpListener::bind(address).is_err());
+
+        let clients: Vec<_> = (0..64).map(|_| std::net::TcpStream::connect(address).unwrap()).collect();
+        std::thread::sleep(Duration::from_millis(50));
+        let accepted: Vec<usize> = listeners.iter().map(|listener| std::iter::from_fn(|| listener.accept().ok()).count()).collect();
+        assert_eq!(accepted.iter().sum::<usize>(), clients.len());
+        assert!(accepted.iter().all(|&count| count > 0), "{:?}", accepted);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::f64::consts::{PI, SQRT_2};
+
+use fefix::fix_values::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::Bankroll;
+use crate::instrument::{InstrumentSpec, OptionType, OptionsSpec};
+use crate::types::*;
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+// An account's sensitivities to its underlyings, summed over its holdings. Vega is per unit of
+// volatility, so per 0.01 it is a hundredth of this.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Greeks {
+    pub(crate) delta: f64,
+    pub(crate) gamma: f64,
+    pub(crate) vega: f64,
+}
+
+impl std::ops::AddAssign for Greeks {
+    fn add_assign(&mut self, other: Self) {
+        self.delta += other.delta;
+        self.gamma += other.gamma;
+        self.vega += other.vega;
+    }
+}
+
+// Black-Scholes greeks of an account's options as of `now`, with no interest rate or dividends.
+// Shares held outright count one delta each; options whose underlying has no spot price are
+// left out. Delta, like the sum, mixes underlyings, so it is in shares rather than money.
+pub(crate) fn portfolio_greeks(
+    account: &Bankroll,
+    specs: &HashMap<InstrumentID, InstrumentSpec>,
+    spot_prices: &HashMap<InstrumentID, Price>,
+    now: &Timestamp,
+) -> Greeks {
+    let mut greeks = Greeks::default();
+    for (instrument_id, &quantity) in &account.positions {
+        let per_unit = match specs.get(instrument_id).and_then(|spec| spec.options.as_ref()) {
+            Some(option) => match spot_prices.get(&option.underlying) {
+                Some(&spot) => option_greeks(option, spot, years_between(now, &option.expiry)),
+                None => continue,
+            },
+            None => Greeks { delta: 1.0, ..Greeks::default() },
+        };
+        greeks += Greeks {
+            delta: per_unit.delta * quantity as f64,
+            gamma: per_unit.gamma * quantity as f64,
+            vega: per_unit.vega * quantity as f64,
+        };
+    }
+    greeks
+}
+
+// One option's greeks with `years` to run. Once expired, or without volatility, it is worth
+// only what it is in the money, so its delta is all or nothing and the rest are zero.
+fn option_greeks(option: &OptionsSpec, spot: Price, years: f64) -> Greeks {
+    let (spot, strike, volatility) = (spot.into_inner(), option.strike.into_inner(), option.implied_volatility);
+    let in_the_money = match option.option_type {
+        OptionType::Call => spot > strike,
+        OptionType::Put => spot < strike,
+    };
+    if years <= 0.0 || volatility <= 0.0 || spot <= 0.0 || strike <= 0.0 {
+        let delta = match (option.option_type, in_the_money) {
+            (_, false) => 0.0,
+            (OptionType::Call, true) => 1.0,
+            (OptionType::Put, true) => -1.0,
+        };
+        return Greeks { delta, ..Greeks::default() };
+    }
+    let spread = volatility * years.sqrt();
+    let d1 = ((spot / strike).ln() + spread * spread / 2.0) / spread;
+    let delta = match option.option_type {
+        OptionType::Call => normal_cdf(d1),
+        OptionType::Put => normal_cdf(d1) - 1.0,
+    };
+    Greeks {
+        delta,
+        gamma: normal_pdf(d1) / (spot * spread),
+        vega: spot * normal_pdf(d1) * years.sqrt(),
+    }
+}
+
+fn years_between(from: &Timestamp, to: &Timestamp) -> f64 {
+    match (from.to_chrono_utc(), to.to_chrono_utc()) {
+        (Some(from), Some(to)) => (to - from).num_milliseconds() as f64 / 1000.0 / SECONDS_PER_YEAR,
+        _ => 0.0,
+    }
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-x * x / 2.0).exp() / (2.0 * PI).sqrt()
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    (1.0 + erf(x / SQRT_2)) / 2.0
+}
+
+// Abramowitz and Stegun 7.1.26, good to 1.5e-7, as std has no erf
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> Timestamp {
+        Timestamp::parse(time.as_bytes()).unwrap()
+    }
+
+    fn option(option_type: OptionType, strike: f64) -> OptionsSpec {
+        OptionsSpec {
+            underlying: "AAPL".to_string(),
+            strike: Price::from(strike),
+            expiry: at("20250101-00:00:00.000"),
+            option_type,
+            implied_volatility: 0.2,
+        }
+    }
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-4
+    }
+
+    #[test]
+    fn an_at_the_money_option_a_year_out_has_the_textbook_greeks() {
+        // d1 = 0.1 for spot and strike 100, 20% volatility and a year to run
+        let call = option_greeks(&option(OptionType::Call, 100.0), Price::from(100.0), 1.0);
+        assert!(close(call.delta, 0.539828) && close(call.gamma, 0.019848) && close(call.vega, 39.695255), "{:?}", call);
+        // Put-call parity: the put's delta is the call's less one, the rest alike
+        let put = option_greeks(&option(OptionType::Put, 100.0), Price::from(100.0), 1.0);
+        assert!(close(put.delta, call.delta - 1.0) && close(put.gamma, call.gamma) && close(put.vega, call.vega), "{:?}", put);
+    }
+
+    #[test]
+    fn an_accounts_greeks_add_up_its_options_and_shares_and_skip_options_without_a_spot() {
+        let specs = HashMap::from([
+            ("AAPL-C100".to_string(), InstrumentSpec { options: Some(option(OptionType::Call, 100.0)), ..InstrumentSpec::default() }),
+            ("AAPL-P90".to_string(), InstrumentSpec { options: Some(option(OptionType::Put, 90.0)), ..InstrumentSpec::default() }),
+            ("MSFT-C100".to_string(), InstrumentSpec {
+                options: Some(OptionsSpec { underlying: "MSFT".to_string(), ..option(OptionType::Call, 100.0) }),
+                ..InstrumentSpec::default()
+            }),
+        ]);
+        let mut account = Bankroll::new(AccountBalance::from(0.0));
+        account.positions = HashMap::from([
+            ("AAPL-C100".to_string(), 10),
+            ("AAPL-P90".to_string(), 5),
+            ("MSFT-C100".to_string(), 7),
+            ("AAPL".to_string(), 3),
+        ]);
+        let spot_prices = HashMap::from([("AAPL".to_string(), Price::from(100.0))]);
+        let now = at("20240101-00:00:00.000");
+        let greeks = portfolio_greeks(&account, &specs, &spot_prices, &now);
+
+        // 2024 is a leap year, so the options have 366 days to run
+        let years = 366.0 / 365.0;
+        let call = option_greeks(&option(OptionType::Call, 100.0), Price::from(100.0), years);
+        let put = option_greeks(&option(OptionType::Put, 90.0), Price::from(100.0), years);
+        assert!(close(greeks.delta, 10.0 * call.delta + 5.0 * put.delta + 3.0), "{:?}", greeks);
+        assert!(close(greeks.gamma, 10.0 * call.gamma + 5.0 * put.gamma), "{:?}", greeks);
+        assert!(close(greeks.vega, 10.0 * call.vega + 5.0 * put.vega), "{:?}", greeks);
+
+        // Past expiry only the options in the money carry delta
+        let expired = portfolio_greeks(&account, &specs, &HashMap::from([("AAPL".to_string(), Price::from(95.0))]), &at("20250102-00:00:00.000"));
+        assert_eq!(expired, Greeks { delta: 3.0, gamma: 0.0, vega: 0.0 });
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use fefix::fix_values::Timestamp;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::clock::timestamp_now;
+use crate::fix::serialize_exchange_status;
+use crate::inbound::QueueDepth;
+use crate::overload::OverloadGuard;
+use crate::supervisor::EngineHealth;
+use crate::types::ClientID;
+
+// How often sessions hear from the exchange when nothing is said with --heartbeat-interval
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+// TradSesStatus(340) values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionPhase {
+    Halted = 1,
+    Open = 2,
+}
+
+// How many messages are waiting for the engine, coarse enough that clients can act on it.
+// Sent as its number in tag 8015.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueDepthBucket {
+    Empty = 0,
+    Light = 1,      // under 100
+    Busy = 2,       // under 1000
+    Backlogged = 3, // 1000 or more
+}
+
+impl QueueDepthBucket {
+    pub fn of(depth: usize) -> Self {
+        match depth {
+            0 => Self::Empty,
+            1..100 => Self::Light,
+            100..1000 => Self::Busy,
+            _ => Self::Backlogged,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExchangeStatus {
+    pub phase: SessionPhase,
+    pub queue_depth: QueueDepthBucket,
+    pub cancel_only: bool, // new orders refused while the engine catches up; sent in tag 8047
+    pub timestamp: Timestamp,
+}
+
+impl ExchangeStatus {
+    pub fn now(health: &EngineHealth, queue_depth: &QueueDepth, overload: &OverloadGuard) -> Self {
+        Self {
+            phase: if health.status().running { SessionPhase::Open } else { SessionPhase::Halted },
+            queue_depth: QueueDepthBucket::of(queue_depth.get()),
+            cancel_only: overload.cancel_only(),
+            timestamp: timestamp_now(),
+        }
+    }
+}
+
+// Tells every connected session the exchange's status once per `interval`, the first time
+// one interval after starting. Runs beside the engine rather than on the matching thread,
+// so a busy or stopped engine still gets reported. Returns once `shutdown` reads true or
+// its sender is dropped, without sending anything more.
+pub async fn broadcast_status(
+    interval: Duration,
+    health: Arc<EngineHealth>,
+    queue_depth: QueueDepth,
+    overload: Arc<OverloadGuard>,
+    sessions: &'static DashMap<ClientID, UnboundedSender<Bytes>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    // A stalled runtime sends one late status, not a burst of them
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                let status = ExchangeStatus::now(&health, &queue_depth, &overload);
+                for session in sessions.iter() {
+                    let _ = session.send(serialize_exchange_status(&status, session.key()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::*;
+    use crate::engine::EngineMessage;
+    use crate::framing::RawMessage;
+    use crate::inbound::inbound_channel;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    fn invalid() -> EngineMessage {
+        EngineMessage::InvalidMessage { reason: String::new(), raw_message: RawMessage::text("") }
+    }
+
+    // A session map of the test's own, so its broadcasts reach no other test's sessions
+    fn one_session() -> (&'static DashMap<ClientID, UnboundedSender<Bytes>>, UnboundedReceiver<Bytes>) {
+        let sessions = Box::leak(Box::new(DashMap::new()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        sessions.insert(ClientID::new("WATCHER".to_string(), None), tx);
+        (sessions, rx)
+    }
+
+    async fn next_status(rx: &mut UnboundedReceiver<Bytes>) -> String {
+        String::from_utf8_lossy(&rx.recv().await.unwrap()).into_owned()
+    }
+
+    fn field(message: &str, tag: &str) -> Option<String> {
+        message.split('|').find_map(|pair| pair.split_once('=').filter(|(t, _)| *t == tag)).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn queue_depth_buckets() {
+        let buckets: Vec<_> = [0, 1, 99, 100, 999, 1000].into_iter().map(QueueDepthBucket::of).collect();
+        use QueueDepthBucket::*;
+        assert_eq!(buckets, vec![Empty, Light, Light, Busy, Busy, Backlogged]);
+    }
+
+    #[tokio::test]
+    async fn sessions_hear_a_heartbeat_and_the_exchange_status_every_interval() {
+        let (sessions, mut rx) = one_session();
+        let (tx, _rx) = inbound_channel(true);
+        for _ in 0..150 {
+            tx.send(invalid()).unwrap();
+        }
+        let health = EngineHealth::new();
+        let _running = health.start();
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let started = Instant::now();
+        tokio::spawn(broadcast_status(INTERVAL, Arc::clone(&health), tx.queue_depth(), OverloadGuard::new(), sessions, shutdown));
+
+        for _ in 0..3 {
+            let status = next_status(&mut rx).await;
+            let lines: Vec<&str> = status.lines().collect();
+            assert_eq!(lines.len(), 2, "{}", status);
+            assert_eq!(field(lines[0], "35").as_deref(), Some("0"));
+            assert_eq!(field(lines[1], "35").as_deref(), Some("h"));
+            assert_eq!(field(lines[1], "340").as_deref(), Some("2"));
+            assert_eq!(field(lines[1], "8015").as_deref(), Some("2"));
+            assert_eq!(field(lines[1], "8047").as_deref(), Some("N"));
+            assert!(field(lines[1], "60").is_some(), "{}", status);
+        }
+        // Never early: the third status comes no sooner than three intervals in
+        assert!(started.elapsed() >= 3 * INTERVAL, "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn a_stopped_engine_is_reported_halted() {
+        let (sessions, mut rx) = one_session();
+        let (tx, _rx) = inbound_channel(true);
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        tokio::spawn(broadcast_status(INTERVAL, EngineHealth::new(), tx.queue_depth(), OverloadGuard::new(), sessions, shutdown));
+
+        let status = next_status(&mut rx).await;
+        let trading_session_status = status.lines().nth(1).unwrap();
+        assert_eq!(field(trading_session_status, "340").as_deref(), Some("1"));
+        assert_eq!(field(trading_session_status, "8015").as_deref(), Some("0"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_the_broadcast_before_its_next_tick() {
+        let (sessions, mut rx) = one_session();
+        let (tx, _rx) = inbound_channel(true);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let broadcast = tokio::spawn(broadcast_status(INTERVAL, EngineHealth::new(), tx.queue_depth(), OverloadGuard::new(), sessions, shutdown));
+        rx.recv().await.unwrap();
+
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(INTERVAL, broadcast).await.expect("broadcast still running").unwrap();
+        tokio::time::sleep(2 * INTERVAL).await;
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,507 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::error::SendError;
+
+use crate::engine::EngineMessage;
+use crate::types::{ClientID, OrderID};
+use crate::clock::{exchange_now, ExchangeTime};
+
+// After this many priority messages in a row the consumer lets one regular message
+// through, so a cancel storm cannot starve new orders completely
+const MAX_PRIORITY_STREAK: usize = 16;
+
+// Cancels skip the queue of new orders. A cancel can never overtake the order it targets,
+// since OrderIDs are assigned by the engine, but it could overtake an earlier amend of that
+// order; order_key keeps those two in arrival order. A cancel naming its order by ClOrdID
+// alone could overtake the order itself, so it waits its turn.
+fn is_priority(message: &EngineMessage) -> bool {
+    matches!(message, EngineMessage::CancelOrder { order_id, orig_client_order_id, .. } if *order_id != 0 || orig_client_order_id.is_none())
+}
+
+// What a message must stay in order with: messages from one client about one order are
+// handled in the order they arrived. Cancels and amends name orders by OrderID only.
+fn order_key(message: &EngineMessage) -> Option<(ClientID, OrderID)> {
+    match message {
+        EngineMessage::CancelOrder { client_id, order_id, .. }
+        | EngineMessage::AmendOrder { client_id, order_id, .. } => Some((client_id.clone(), *order_id)),
+        _ => None,
+    }
+}
+
+// A message and when the exchange took it in, for the reports that answer it to say
+pub type Received = (EngineMessage, ExchangeTime);
+
+// Keyed messages sitting in the regular queue, by key
+type QueuedKeys = Arc<DashMap<(ClientID, OrderID), usize>>;
+
+// How many messages are waiting for the consumer across both queues. Readable without
+// holding a sender, so whoever reports on it does not keep the channel open.
+#[derive(Clone, Debug, Default)]
+pub struct QueueDepth(Arc<AtomicUsize>);
+
+impl QueueDepth {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub fn inbound_channel(prioritize_cancels: bool) -> (InboundSender, InboundReceiver) {
+    let (priority_tx, priority_rx) = mpsc::unbounded_channel();
+    let (regular_tx, regular_rx) = mpsc::unbounded_channel();
+    let queued_keys = QueuedKeys::default();
+    let depth = QueueDepth::default();
+    (
+        InboundSender { priority: priority_tx, regular: regular_tx, prioritize_cancels, queued_keys: Arc::clone(&queued_keys), depth: depth.clone() },
+        InboundReceiver { priority: priority_rx, regular: regular_rx, priority_streak: 0, queued_keys, depth },
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct InboundSender {
+    priority: UnboundedSender<Received>,
+    regular: UnboundedSender<Received>,
+    prioritize_cancels: bool,
+    queued_keys: QueuedKeys,
+    depth: QueueDepth,
+}
+
+impl InboundSender {
+    // A cancel only takes the priority queue when nothing about its order is still waiting
+    // in the regular one; otherwise it queues up behind it
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, message: EngineMessage) -> Result<(), SendError<EngineMessage>> {
+        // Counted before sending for the same reason as the keys below
+        self.depth.0.fetch_add(1, Ordering::Relaxed);
+        let sent = self.route((message, exchange_now()));
+        if sent.is_err() {
+            self.depth.0.fetch_sub(1, Ordering::Relaxed);
+        }
+        sent.map_err(|SendError((message, _))| SendError(message))
+    }
+
+    // True once the consumer has gone, so nothing sent would ever be handled
+    pub fn is_closed(&self) -> bool {
+        self.regular.is_closed()
+    }
+
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.depth.clone()
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn route(&self, message: Received) -> Result<(), SendError<Received>> {
+        if !self.prioritize_cancels {
+            return self.regular.send(message);
+        }
+        let key = order_key(&message.0);
+        if is_priority(&message.0) && key.as_ref().is_none_or(|key| !self.queued_keys.contains_key(key)) {
+            return self.priority.send(message);
+        }
+        // Counted before sending, so the receiver never sees a message it has no count for
+        if let Some(key) = key {
+            *self.queued_keys.entry(key).or_insert(0) += 1;
+        }
+        self.regular.send(message)
+    }
+}
+
+#[derive(Debug)]
+pub struct InboundReceiver {
+    priority: UnboundedReceiver<Received>,
+    regular: UnboundedReceiver<Received>,
+    priority_streak: usize,
+    queued_keys: QueuedKeys,
+    depth: QueueDepth,
+}
+
+impl InboundReceiver {
+    // Drains the priority queue first, yielding to the regular queue every
+    // MAX_PRIORITY_STREAK messages. Returns None once both queues are closed and empty.
+    pub async fn recv(&mut self) -> Option<Received> {
+        if let Some(message) = self.try_recv() {
+            return Some(message);
+        }
+        let message = tokio::select! {
+            biased;
+            Some(message) = self.priority.recv() => {
+                self.priority_streak = 1;
+                Some(message)
+            }
+            Some(message) = self.regular.recv() => Some(self.dequeued(message)),
+            else => None,
+        };
+        self.taken(message)
+    }
+
+    // Waits for one message, then takes whatever else is already queued, up to `limit`
+    // in total, without waiting for more. Returns 0 once both queues are closed and empty.
+    pub async fn recv_many(&mut self, buffer: &mut Vec<Received>, limit: usize) -> usize {
+        let Some(first) = self.recv().await else {
+            return 0;
+        };
+        buffer.push(first);
+        let mut received = 1;
+        while received < limit {
+            let Some(message) = self.try_recv() else {
+                break;
+            };
+            buffer.push(message);
+            received += 1;
+        }
+        received
+    }
+
+    // Same priority rules as recv, but never waits
+    fn try_recv(&mut self) -> Option<Received> {
+        let message = self.try_recv_queued();
+        self.taken(message)
+    }
+
+    fn try_recv_queued(&mut self) -> Option<Received> {
+        if self.priority_streak >= MAX_PRIORITY_STREAK {
+            self.priority_streak = 0;
+            if let Ok(message) = self.regular.try_recv() {
+                return Some(self.dequeued(message));
+            }
+        }
+        if let Ok(message) = self.priority.try_recv() {
+            self.priority_streak += 1;
+            return Some(message);
+        }
+        self.priority_streak = 0;
+        self.regular.try_recv().ok().map(|message| self.dequeued(message))
+    }
+
+    // Takes a message leaving either queue off the depth
+    fn taken(&self, message: Option<Received>) -> Option<Received> {
+        if message.is_some() {
+            self.depth.0.fetch_sub(1, Ordering::Relaxed);
+        }
+        message
+    }
+
+    // Takes a message leaving the regular queue off the count of its key
+    fn dequeued(&self, message: Received) -> Received {
+        if let Some(key) = order_key(&message.0) {
+            if let dashmap::Entry::Occupied(mut queued) = self.queued_keys.entry(key) {
+                *queued.get_mut() -= 1;
+                if *queued.get() == 0 {
+                    queued.remove();
+                }
+            }
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use fefix::definitions::fix50::*;
+    use fefix::fix_values::Timestamp;
+
+    use super::*;
+    use crate::exchange::Exchange;
+    use crate::instrument::SpecOverrides;
+    use crate::types::*;
+
+    fn new_order() -> EngineMessage {
+        EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("FLOOD".to_string(), None),
+            account_id: "FLOOD".to_string(),
+            client_order_id: None,
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 1,
+            price: Some(Price::from(1.0)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        }
+    }
+
+    fn cancel(order_id: OrderID) -> EngineMessage {
+        EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("STRAT".to_string(), None),
+            account_id: "STRAT".to_string(),
+            order_id,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        }
+    }
+
+    fn amend(order_id: OrderID) -> EngineMessage {
+        EngineMessage::AmendOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("STRAT".to_string(), None),
+            order_id,
+            new_quantity: Some(2),
+            new_price: None,
+            time_in_force: None,
+        }
+    }
+
+    // Floods the queue with new orders, then sends one cancel, and reports how many
+    // messages the consumer handled before reaching it
+    async fn messages_ahead_of_cancel(prioritize_cancels: bool, flood: usize) -> usize {
+        let (tx, mut rx) = inbound_channel(prioritize_cancels);
+        for _ in 0..flood {
+            tx.send(new_order()).unwrap();
+        }
+        tx.send(cancel(1)).unwrap();
+        drop(tx);
+
+        let mut ahead = 0;
+        while let Some((message, _)) = rx.recv().await {
+            if let EngineMessage::CancelOrder { .. } = message {
+                return ahead;
+            }
+            ahead += 1;
+        }
+        panic!("cancel was never dequeued");
+    }
+
+    #[tokio::test]
+    async fn cancel_overtakes_flood_of_new_orders() {
+        let flood = 100_000;
+        assert_eq!(messages_ahead_of_cancel(false, flood).await, flood);
+        assert_eq!(messages_ahead_of_cancel(true, flood).await, 0);
+    }
+
+    #[tokio::test]
+    async fn new_orders_progress_during_cancel_storm() {
+        let (tx, mut rx) = inbound_channel(true);
+        for order_id in 0..100 {
+            tx.send(cancel(order_id)).unwrap();
+        }
+        tx.send(new_order()).unwrap();
+        drop(tx);
+
+        let mut position = 0;
+        while let Some((message, _)) = rx.recv().await {
+            if let EngineMessage::NewOrder { .. } = message {
+                break;
+            }
+            position += 1;
+        }
+        assert_eq!(position, MAX_PRIORITY_STREAK);
+    }
+
+    #[tokio::test]
+    async fn per_queue_order_is_preserved() {
+        let (tx, mut rx) = inbound_channel(true);
+        for order_id in 1..=5 {
+            tx.send(cancel(order_id)).unwrap();
+        }
+        drop(tx);
+
+        let mut seen = Vec::new();
+        while let Some((EngineMessage::CancelOrder { order_id, .. }, _)) = rx.recv().await {
+            seen.push(order_id);
+        }
+        assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn cancel_waits_behind_an_earlier_amend_of_the_same_order() {
+        let (tx, mut rx) = inbound_channel(true);
+        tx.send(new_order()).unwrap();
+        tx.send(amend(7)).unwrap();
+        tx.send(cancel(7)).unwrap();
+        tx.send(cancel(8)).unwrap();
+        drop(tx);
+
+        let mut seen = Vec::new();
+        while let Some((message, _)) = rx.recv().await {
+            seen.push(match message {
+                EngineMessage::NewOrder { .. } => "new".to_string(),
+                EngineMessage::AmendOrder { order_id, .. } => format!("amend {}", order_id),
+                EngineMessage::CancelOrder { order_id, .. } => format!("cancel {}", order_id),
+                other => panic!("unexpected message: {:?}", other),
+            });
+        }
+        // The unrelated cancel still jumps the queue
+        assert_eq!(seen, vec!["cancel 8", "new", "amend 7", "cancel 7"]);
+        assert!(rx.queued_keys.is_empty());
+    }
+
+    // It could otherwise reach the engine before the order it names
+    #[tokio::test]
+    async fn a_cancel_by_client_order_id_waits_its_turn() {
+        let (tx, mut rx) = inbound_channel(true);
+        tx.send(new_order()).unwrap();
+        let mut by_client_order_id = cancel(0);
+        if let EngineMessage::CancelOrder { orig_client_order_id, .. } = &mut by_client_order_id {
+            *orig_client_order_id = Some("C-1".to_string());
+        }
+        tx.send(by_client_order_id).unwrap();
+        assert!(matches!(rx.recv().await, Some((EngineMessage::NewOrder { .. }, _))));
+        assert!(matches!(rx.recv().await, Some((EngineMessage::CancelOrder { .. }, _))));
+    }
+
+    #[tokio::test]
+    async fn cancel_is_prioritized_again_once_the_amend_is_dequeued() {
+        let (tx, mut rx) = inbound_channel(true);
+        tx.send(amend(7)).unwrap();
+        assert!(matches!(rx.recv().await, Some((EngineMessage::AmendOrder { .. }, _))));
+        tx.send(new_order()).unwrap();
+        tx.send(cancel(7)).unwrap();
+        assert!(matches!(rx.recv().await, Some((EngineMessage::CancelOrder { .. }, _))));
+    }
+
+    #[tokio::test]
+    async fn recv_many_returns_a_lone_message_without_waiting() {
+        let (tx, mut rx) = inbound_channel(true);
+        tx.send(new_order()).unwrap();
+
+        let mut batch = Vec::new();
+        let received = tokio::time::timeout(std::time::Duration::from_millis(100), rx.recv_many(&mut batch, 64))
+            .await
+            .expect("recv_many waited for a full batch");
+        assert_eq!(received, 1);
+        assert_eq!(batch.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn recv_many_caps_batches_and_keeps_cancel_priority() {
+        let (tx, mut rx) = inbound_channel(true);
+        for _ in 0..100 {
+            tx.send(new_order()).unwrap();
+        }
+        tx.send(cancel(1)).unwrap();
+        drop(tx);
+
+        let mut batch = Vec::new();
+        assert_eq!(rx.recv_many(&mut batch, 64).await, 64);
+        assert!(matches!(batch[0], (EngineMessage::CancelOrder { .. }, _)));
+        batch.clear();
+        assert_eq!(rx.recv_many(&mut batch, 64).await, 37);
+        batch.clear();
+        assert_eq!(rx.recv_many(&mut batch, 64).await, 0);
+    }
+
+    // Drains a queued flood through a consumer stage that forwards every message to an
+    // outbound channel, either one send per message or one send per batch
+    async fn consumer_throughput(batch_size: usize, messages: usize) -> std::time::Duration {
+        let (tx, mut rx) = inbound_channel(true);
+        for _ in 0..messages {
+            tx.send(new_order()).unwrap();
+        }
+        drop(tx);
+
+        let started = Instant::now();
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Vec<Received>>();
+        let consumer = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(batch_size);
+            while rx.recv_many(&mut batch, batch_size).await > 0 {
+                let _ = outbound_tx.send(std::mem::take(&mut batch));
+            }
+        });
+        let drain = tokio::spawn(async move {
+            let mut seen = 0;
+            while let Some(batch) = outbound_rx.recv().await {
+                seen += batch.len();
+            }
+            seen
+        });
+
+        consumer.await.unwrap();
+        assert_eq!(drain.await.unwrap(), messages);
+        started.elapsed()
+    }
+
+    // A benchmark, so it only runs on request:
+    //   cargo test --release -p exchange-server batched_consumer_throughput -- --ignored --nocapture
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore]
+    async fn batched_consumer_throughput() {
+        let messages = 100_000;
+        let single = consumer_throughput(1, messages).await;
+        let batched = consumer_throughput(256, messages).await;
+        println!("{} messages: one per send {:?}, batched {:?}", messages, single, batched);
+        assert!(batched <= single, "one per send {:?}, batched {:?}", single, batched);
+    }
+
+    // Sends orders one at a time, each once the last one's events are out, through a consumer
+    // that runs what it takes through an Exchange as the engine does. Gives the median time
+    // from send to events, and the most messages the consumer took at once.
+    async fn low_rate_latency(batch_size: usize, messages: usize) -> (std::time::Duration, usize) {
+        let (tx, mut rx) = inbound_channel(true);
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<(usize, Vec<EngineMessage>)>();
+        let consumer = tokio::spawn(async move {
+            let mut exchange = Exchange::new();
+            exchange.handle_message(EngineMessage::CreateInstrument {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: ClientID::new("ADMIN".to_string(), None),
+                instrument_id: "AAPL".to_string(),
+                segment: None,
+                spec: SpecOverrides::default(),
+                spread: None,
+            });
+            let mut batch = Vec::with_capacity(batch_size);
+            while rx.recv_many(&mut batch, batch_size).await > 0 {
+                let taken = batch.len();
+                let events = batch.drain(..).flat_map(|(message, _)| exchange.handle_message(message)).collect();
+                let _ = outbound_tx.send((taken, events));
+            }
+        });
+
+        let mut latencies = Vec::with_capacity(messages);
+        let mut largest_batch = 0;
+        for _ in 0..messages {
+            let sent = Instant::now();
+            tx.send(new_order()).unwrap();
+            let (taken, events) = outbound_rx.recv().await.unwrap();
+            latencies.push(sent.elapsed());
+            largest_batch = largest_batch.max(taken);
+            assert!(matches!(events.as_slice(), [EngineMessage::OrderAccepted { .. }]), "{:?}", events);
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        drop(tx);
+        consumer.await.unwrap();
+        latencies.sort();
+        (latencies[messages / 2], largest_batch)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn batching_adds_no_latency_at_low_rates() {
+        let (single, _) = low_rate_latency(1, 50).await;
+        let (batched, largest_batch) = low_rate_latency(256, 50).await;
+        // Nothing waits for a batch to fill: each message goes through on its own
+        assert_eq!(largest_batch, 1);
+        assert!(batched <= single + std::time::Duration::from_millis(1), "median one per send {:?}, batched {:?}", single, batched);
+    }
+
+    #[tokio::test]
+    async fn queue_depth_counts_what_the_consumer_has_yet_to_take() {
+        let (tx, mut rx) = inbound_channel(true);
+        let depth = tx.queue_depth();
+        tx.send(new_order()).unwrap();
+        tx.send(cancel(1)).unwrap();
+        tx.send(new_order()).unwrap();
+        assert_eq!(depth.get(), 3);
+
+        rx.recv().await.unwrap();
+        assert_eq!(depth.get(), 2);
+        let mut buffer = Vec::new();
+        rx.recv_many(&mut buffer, 10).await;
+        assert_eq!(depth.get(), 0);
+
+        drop(rx);
+        assert!(tx.send(new_order()).is_err());
+        assert_eq!(depth.get(), 0);
+    }
+}
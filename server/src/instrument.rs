@@ -0,0 +1,375 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use fefix::definitions::fix50::OrdType;
+use fefix::fix_values::Timestamp;
+use parking_lot::{RwLock, RwLockReadGuard};
+use serde::{Deserialize, Serialize};
+
+use crate::types::*;
+
+// What a book does when a new resting order would open more price levels
+// on one side than `max_price_levels` allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PriceLevelPolicy {
+    // Cancel every order on the worst level of that side to make room
+    EvictWorst,
+    // Refuse the incoming order
+    Reject,
+}
+
+// What becomes of the levels a better price leaves beyond `max_touch_distance`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TouchWindowPolicy {
+    // They stay, and may still be joined; only new levels must be within the window
+    Grandfather,
+    // Every order on them is cancelled
+    Cancel,
+}
+
+// How a book left crossed while it wasn't matching, by amends during a halt or orders taken
+// during warm-up, trades out once it opens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UncrossPolicy {
+    // The crossing bids trade again, best first, against the asks at the asks' prices
+    RestingPrices,
+    // Everything that crosses trades at a single price, the one that trades the most
+    Auction,
+}
+
+// How long a volatility halt lasts when the instrument doesn't say, as long as an LULD pause
+pub(crate) const DEFAULT_HALT_DURATION_SECS: u64 = 300;
+
+// What a halt does to the orders resting on a book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HaltPolicy {
+    // Cancel them all, refunding them, so nothing is left to trade at pre-halt prices
+    CancelAll,
+    // Keep them on the book, unmatched, for when the halt lifts
+    Freeze,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OptionType {
+    Call,
+    Put,
+}
+
+// What makes an instrument a European option on another, exercised only at expiry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OptionsSpec {
+    pub(crate) underlying: InstrumentID,
+    pub(crate) strike: Price,
+    #[serde(with = "fix_value_serde")]
+    pub(crate) expiry: Timestamp,
+    pub(crate) option_type: OptionType,
+    pub(crate) implied_volatility: f64, // annualised, e.g. 0.2 for 20%
+}
+
+// The order types the engine matches. `of` maps every OrdType, so taking a new one on means
+// adding it here and there, and nowhere else decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OrderType {
+    Market,
+    Limit,
+    Stop,
+    StopLimit,
+}
+
+impl OrderType {
+    pub(crate) const ALL: [OrderType; 4] = [OrderType::Market, OrderType::Limit, OrderType::Stop, OrderType::StopLimit];
+
+    pub(crate) fn of(order_type: OrdType) -> Option<Self> {
+        match order_type {
+            OrdType::Market => Some(OrderType::Market),
+            OrdType::Limit => Some(OrderType::Limit),
+            OrdType::Stop => Some(OrderType::Stop),
+            OrdType::StopLimit => Some(OrderType::StopLimit),
+            OrdType::MarketOnClose
+            | OrdType::WithOrWithout
+            | OrdType::LimitOrBetter
+            | OrdType::LimitWithOrWithout
+            | OrdType::OnBasis
+            | OrdType::OnClose
+            | OrdType::LimitOnClose
+            | OrdType::ForexMarket
+            | OrdType::PreviouslyQuoted
+            | OrdType::PreviouslyIndicated
+            | OrdType::ForexLimit
+            | OrdType::ForexSwap
+            | OrdType::ForexPreviouslyQuoted
+            | OrdType::Funari
+            | OrdType::MarketIfTouched
+            | OrdType::MarketWithLeftOverAsLimit
+            | OrdType::PreviousFundValuationPoint
+            | OrdType::NextFundValuationPoint
+            | OrdType::Pegged
+            | OrdType::CounterOrderSelection => None,
+        }
+    }
+
+    pub(crate) fn ord_type(self) -> OrdType {
+        match self {
+            OrderType::Market => OrdType::Market,
+            OrderType::Limit => OrdType::Limit,
+            OrderType::Stop => OrdType::Stop,
+            OrderType::StopLimit => OrdType::StopLimit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct InstrumentSpec {
+    pub(crate) max_price_levels: usize, // per side, 0 = unlimited
+    pub(crate) price_level_policy: PriceLevelPolicy,
+    // How far a new level may open from its side's best price, or the other side's when it has
+    // none, 0 = any distance
+    pub(crate) max_touch_distance: Price,
+    pub(crate) touch_window_policy: TouchWindowPolicy,
+    pub(crate) max_resting_orders: usize, // 0 = unlimited
+    pub(crate) tick_size: Price, // prices must be a multiple, 0 = any price
+    pub(crate) lot_size: Quantity, // quantities must be a multiple, 0 = any quantity
+    // Alert when one side's resting quantity reaches more than this many times the other's, 0 = never
+    pub(crate) imbalance_alert_threshold: f64,
+    pub(crate) order_types: BTreeSet<OrderType>, // those the instrument takes, e.g. only limit orders for an auction
+    pub(crate) uncross_policy: UncrossPolicy,
+    pub(crate) halt_policy: HaltPolicy,
+    // Halt when the bid-ask spread widens past this many times the reference spread, 0 = never
+    pub(crate) spread_halt_multiplier: f64,
+    // The spread to start from, 0 = the first one seen; the book then follows its own spreads
+    pub(crate) reference_spread: f64,
+    pub(crate) halt_duration_secs: u64, // how long a volatility halt lasts
+    pub(crate) options: Option<OptionsSpec>, // set for an option, priced off its underlying
+}
+
+impl Default for InstrumentSpec {
+    fn default() -> Self {
+        Self {
+            max_price_levels: 0,
+            price_level_policy: PriceLevelPolicy::EvictWorst,
+            max_touch_distance: Price::from(0.0),
+            touch_window_policy: TouchWindowPolicy::Grandfather,
+            max_resting_orders: 0,
+            tick_size: Price::from(0.0),
+            lot_size: 0,
+            imbalance_alert_threshold: 0.0,
+            order_types: BTreeSet::from(OrderType::ALL),
+            uncross_policy: UncrossPolicy::RestingPrices,
+            halt_policy: HaltPolicy::CancelAll,
+            spread_halt_multiplier: 0.0,
+            reference_spread: 0.0,
+            halt_duration_secs: DEFAULT_HALT_DURATION_SECS,
+            options: None,
+        }
+    }
+}
+
+impl InstrumentSpec {
+    // Why an order at `price` for `quantity` does not fit the tick and lot sizes, if it does not
+    pub(crate) fn increment_violation(&self, price: Option<Price>, quantity: Quantity) -> Option<&'static str> {
+        if let Some(price) = price.filter(|_| self.tick_size > Price::from(0.0)) {
+            let ticks = price.into_inner() / self.tick_size.into_inner();
+            // Prices such as 10.01 are never an exact multiple of 0.01 in binary
+            if (ticks - ticks.round()).abs() > 1e-6 {
+                return Some("Price is not a multiple of the tick size");
+            }
+        }
+        if self.lot_size != 0 && !quantity.is_multiple_of(self.lot_size) {
+            return Some("Quantity is not a multiple of the lot size");
+        }
+        None
+    }
+
+    pub(crate) fn takes(&self, order_type: OrdType) -> bool {
+        OrderType::of(order_type).is_some_and(|order_type| self.order_types.contains(&order_type))
+    }
+}
+
+// The settings an instrument sets for itself; the rest come from its segment, or from the
+// defaults if it has none
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct SpecOverrides {
+    pub(crate) max_price_levels: Option<usize>,
+    pub(crate) price_level_policy: Option<PriceLevelPolicy>,
+    pub(crate) max_touch_distance: Option<Price>,
+    pub(crate) touch_window_policy: Option<TouchWindowPolicy>,
+    pub(crate) max_resting_orders: Option<usize>,
+    pub(crate) tick_size: Option<Price>,
+    pub(crate) lot_size: Option<Quantity>,
+    pub(crate) imbalance_alert_threshold: Option<f64>,
+    pub(crate) order_types: Option<BTreeSet<OrderType>>,
+    pub(crate) uncross_policy: Option<UncrossPolicy>,
+    pub(crate) halt_policy: Option<HaltPolicy>,
+    pub(crate) spread_halt_multiplier: Option<f64>,
+    pub(crate) reference_spread: Option<f64>,
+    pub(crate) halt_duration_secs: Option<u64>,
+    pub(crate) options: Option<OptionsSpec>,
+}
+
+impl SpecOverrides {
+    pub(crate) fn apply(&self, base: &InstrumentSpec) -> InstrumentSpec {
+        InstrumentSpec {
+            max_price_levels: self.max_price_levels.unwrap_or(base.max_price_levels),
+            price_level_policy: self.price_level_policy.unwrap_or(base.price_level_policy),
+            max_touch_distance: self.max_touch_distance.unwrap_or(base.max_touch_distance),
+            touch_window_policy: self.touch_window_policy.unwrap_or(base.touch_window_policy),
+            max_resting_orders: self.max_resting_orders.unwrap_or(base.max_resting_orders),
+            tick_size: self.tick_size.unwrap_or(base.tick_size),
+            lot_size: self.lot_size.unwrap_or(base.lot_size),
+            imbalance_alert_threshold: self.imbalance_alert_threshold.unwrap_or(base.imbalance_alert_threshold),
+            order_types: self.order_types.clone().unwrap_or_else(|| base.order_types.clone()),
+            uncross_policy: self.uncross_policy.unwrap_or(base.uncross_policy),
+            halt_policy: self.halt_policy.unwrap_or(base.halt_policy),
+            spread_halt_multiplier: self.spread_halt_multiplier.unwrap_or(base.spread_halt_multiplier),
+            reference_spread: self.reference_spread.unwrap_or(base.reference_spread),
+            halt_duration_secs: self.halt_duration_secs.unwrap_or(base.halt_duration_secs),
+            options: self.options.clone().or_else(|| base.options.clone()),
+        }
+    }
+}
+
+// A spec that may change while the instrument trades, as its tick and lot sizes do intraday
+pub(crate) type SharedInstrumentSpec = Arc<RwLock<InstrumentSpec>>;
+
+// An instrument's spec as its book sees it: an update takes the write lock, and the next read
+// sees the change
+#[derive(Debug, Clone)]
+pub(crate) struct InstrumentHandle {
+    spec: SharedInstrumentSpec,
+}
+
+impl InstrumentHandle {
+    pub(crate) fn new(spec: InstrumentSpec) -> Self {
+        Self { spec: Arc::new(RwLock::new(spec)) }
+    }
+
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, InstrumentSpec> {
+        self.spec.read()
+    }
+
+    pub(crate) fn update_spec<F: FnOnce(&mut InstrumentSpec)>(&self, f: F) {
+        f(&mut self.spec.write());
+    }
+
+    // A handle to a copy of the spec as it stands, which later updates to this one leave alone
+    pub(crate) fn detached(&self) -> Self {
+        Self::new(self.read().clone())
+    }
+}
+
+// One leg of a spread: `ratio` of the instrument for each spread bought, negative for a leg
+// that buying the spread sells
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SpreadLeg {
+    pub(crate) instrument_id: InstrumentID,
+    pub(crate) ratio: i64,
+}
+
+// A spread traded on its own book, e.g. a calendar spread of the front month less the next.
+// Its price is what its legs add up to at their ratios. A fill is booked into the legs, the
+// anchor leg at its own last trade and the other at whatever makes them add up to the fill.
+// Implied matching against the legs' own books is left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SpreadDefinition {
+    pub(crate) legs: Vec<SpreadLeg>,
+    #[serde(default)]
+    pub(crate) anchor: Option<InstrumentID>, // the front leg, the first listed, unless another is named
+}
+
+impl SpreadDefinition {
+    // Why the legs don't make a spread, if they don't; whether they are instruments is for the exchange
+    pub(crate) fn invalidity(&self) -> Option<&'static str> {
+        let [first, second] = self.legs.as_slice() else {
+            return Some("A spread takes two legs");
+        };
+        if first.instrument_id == second.instrument_id {
+            return Some("Spread legs must be different instruments");
+        }
+        if first.ratio == 0 || second.ratio == 0 {
+            return Some("Spread leg ratio must not be zero");
+        }
+        if self.anchor.as_ref().is_some_and(|anchor| !self.legs.iter().any(|leg| leg.instrument_id == *anchor)) {
+            return Some("Spread anchor is not one of its legs");
+        }
+        None
+    }
+
+    pub(crate) fn anchor_leg(&self) -> &SpreadLeg {
+        self.anchor
+            .as_ref()
+            .and_then(|anchor| self.legs.iter().find(|leg| leg.instrument_id == *anchor))
+            .unwrap_or(&self.legs[0])
+    }
+
+    // The price each leg of a fill at `price` is booked at, given the anchor leg's last trade
+    pub(crate) fn leg_prices(&self, price: Price, anchor_price: Price) -> Vec<(&SpreadLeg, Price)> {
+        let anchor = self.anchor_leg();
+        self.legs
+            .iter()
+            .map(|leg| if leg == anchor {
+                (leg, anchor_price)
+            } else {
+                (leg, (price - anchor_price * anchor.ratio as f64) / leg.ratio as f64)
+            })
+            .collect()
+    }
+}
+
+// Named groups of instruments sharing their settings, e.g.
+//   [segments.EQUITIES]
+//   tick_size = 0.01
+//   lot_size = 100
+//   order_types = ["limit", "stop_limit"]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MarketSegments {
+    #[serde(default)]
+    segments: HashMap<SegmentName, InstrumentSpec>,
+}
+
+impl MarketSegments {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub(crate) fn get(&self, segment: &str) -> Option<&InstrumentSpec> {
+        self.segments.get(segment)
+    }
+}
+
+// Reference-data event applied to an instrument once simulated time reaches its effective time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum CorporateAction {
+    // `numerator` new shares for every `denominator` held, e.g. 2:1 doubles quantities and halves prices
+    Split { numerator: u64, denominator: u64 },
+    // Cash paid per share held
+    Dividend { amount: Price },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_update_is_seen_through_every_clone_of_a_handle_but_not_a_detached_one() {
+        let handle = InstrumentHandle::new(InstrumentSpec::default());
+        let shared = handle.clone();
+        let detached = handle.detached();
+
+        handle.update_spec(|spec| spec.tick_size = Price::from(0.05));
+        assert_eq!(shared.read().tick_size, Price::from(0.05));
+        assert_eq!(detached.read().tick_size, Price::from(0.0));
+    }
+}
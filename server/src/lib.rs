@@ -0,0 +1,800 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+#[cfg(target_os = "linux")]
+use fork_union::{ThreadPool};
+
+mod accounts;
+mod activity;
+mod audit;
+mod book_views;
+mod clock;
+mod compaction;
+mod config;
+mod credentials;
+mod custom_fields;
+mod exchange;
+mod fix;
+mod framing;
+mod engine;
+mod execution_quality;
+mod gateway;
+mod greeks;
+mod heartbeat;
+mod inbound;
+mod instrument;
+mod namespace;
+mod order_state;
+mod overload;
+mod price_levels;
+mod rest;
+mod router;
+mod simulation;
+mod statements;
+mod supervisor;
+mod surveillance;
+mod types;
+mod webhook;
+mod wire;
+#[cfg(test)]
+mod tests;
+
+// What tools outside the server read FIX with
+pub use fix::Conformance;
+
+use fefix::definitions::fix50::TimeInForce;
+use types::ClientID;
+use clock::{exchange_now, Clock};
+use wire::{buffer_pool, stamp_outbound};
+use audit::{MalformedLog, TRADE_LOG_RETENTION};
+use activity::summarize_periodically;
+use book_views::{publish_periodically, BookViews};
+use compaction::{compact_periodically, COMPACTION_INTERVAL, FINISHED_ORDER_RETENTION};
+use exchange::Exchange;
+use config::{logs, LiveConfig, LogLevel, ServerConfig};
+use credentials::{Authenticated, Credentials, LogonFailure};
+use fix::{conform, encode_outbound, handle_fix_message, logon_credentials, serialize_logout, Separator};
+use framing::{Frame, FrameReader, RawMessage};
+use engine::{EngineMessage, OutboundBatch, ReportTimes, client_id_mut, extract_client_id};
+use gateway::{accept_connections, ConnectionGate, ConnectionPermit};
+#[cfg(target_os = "linux")]
+use gateway::reuse_port_listeners;
+use heartbeat::{broadcast_status, DEFAULT_HEARTBEAT_INTERVAL};
+use inbound::{inbound_channel, InboundReceiver, InboundSender};
+use overload::{watch_overload, OverloadGuard, OVERLOAD_SAMPLE_INTERVAL};
+use accounts::AccountAuthorizations;
+use instrument::MarketSegments;
+use namespace::{state_file, Namespaces, NamespaceViews};
+use router::TickerMap;
+use simulation::{Simulation, SimulationConfig};
+use supervisor::EngineHealth;
+use surveillance::{SurveillanceConfig, SurveillanceReport};
+use webhook::TradeWebhook;
+
+// Most inbound messages the consumer takes per wakeup
+const CONSUMER_BATCH_SIZE: usize = 256;
+
+// The application version a Logon is answered with when it names none: FIX 5.0, as parsed
+const DEFAULT_APPL_VER_ID: &str = "7";
+
+// What a session is told when the engine has stopped taking messages
+const EXCHANGE_UNAVAILABLE: &str = "exchange unavailable";
+
+// Each session's outbound messages, encoded and waiting for its writer
+static CLIENT_SENDERS: OnceLock<DashMap<ClientID, UnboundedSender<Bytes>>> = OnceLock::new();
+
+fn client_senders() -> &'static DashMap<ClientID, UnboundedSender<Bytes>> {
+    CLIENT_SENDERS.get_or_init(DashMap::new)
+}
+
+// What a session is told when it sends a message as a CompID it has not logged on as
+const COMP_ID_MISMATCH: &str = "SenderCompID does not match the session";
+// Or to a namespace other than the one it opened in
+const NAMESPACE_MISMATCH: &str = "TargetCompID does not match the session";
+// Or as a SenderSubID other than the one it logged on with
+const SUB_ID_MISMATCH: &str = "SenderSubID does not match the session";
+
+// Turns a session away before anything it sent reaches the engine
+async fn refuse_session(writer: &mut OwnedWriteHalf, separator: Separator, next_seq_num: &mut u64, permit: &ConnectionPermit, failure: LogonFailure) {
+    permit.record_failed_logon();
+    let _ = writer.write_all(&separator.apply(stamp_outbound(serialize_logout(failure.reason()), next_seq_num))).await;
+}
+
+// Logout (5) ending a session the exchange is closing, or answering the client's own
+fn logout(client_id: &ClientID, text: Option<&str>) -> Bytes {
+    encode_outbound(&EngineMessage::Logout { client_id: client_id.clone(), text: text.map(str::to_string) }, None).unwrap_or_default()
+}
+
+// FIX Reject (3) for a message the session was not allowed to send
+fn session_mismatch(reason: &str, line: &str) -> Bytes {
+    encode_outbound(&EngineMessage::InvalidMessage { reason: reason.to_string(), raw_message: RawMessage::text(line) }, None)
+        .unwrap_or_default()
+}
+
+// What a session is told as it is logged out for sending too many unreadable messages in a row
+const TOO_MANY_INVALID_MESSAGES: &str = "Too many invalid messages";
+
+// Keeps what a session could not read from ever reaching the engine: each unreadable message
+// is logged as it came and answered with a Reject, and too many in a row end the session
+struct InvalidMessages {
+    malformed: Arc<MalformedLog>,
+    config: Arc<LiveConfig>,
+    in_a_row: usize,
+}
+
+impl InvalidMessages {
+    fn new(malformed: Arc<MalformedLog>, config: Arc<LiveConfig>) -> Self {
+        Self { malformed, config, in_a_row: 0 }
+    }
+
+    // The message back if it could be read, or else the Reject to answer it with
+    fn screen(&mut self, engine_message: EngineMessage) -> Result<EngineMessage, Bytes> {
+        if !matches!(engine_message, EngineMessage::InvalidMessage { .. }) {
+            self.in_a_row = 0;
+            return Ok(engine_message);
+        }
+        let reject = encode_outbound(&engine_message, None).unwrap_or_default();
+        let EngineMessage::InvalidMessage { reason, raw_message } = engine_message else { unreachable!() };
+        if logs(LogLevel::Warn) {
+            eprintln!("Invalid FIX message: {}: {}", reason, raw_message);
+        }
+        self.malformed.record(reason, raw_message);
+        self.in_a_row += 1;
+        Err(reject)
+    }
+
+    // Whether the session has sent as many unreadable messages in a row as it may
+    fn exhausted(&self) -> bool {
+        let limit = self.config.snapshot().max_invalid_messages;
+        limit != 0 && self.in_a_row >= limit
+    }
+}
+
+// A line as the parser reads it, and what it parses to
+fn read_message(line: &str, conformance: Conformance) -> (String, EngineMessage) {
+    match conform(line.trim(), conformance) {
+        Ok(message) => {
+            let engine_message = handle_fix_message(&message);
+            (message.into_owned(), engine_message)
+        }
+        Err(reason) => (line.trim().to_string(), EngineMessage::InvalidMessage { reason, raw_message: RawMessage::text(line.trim()) }),
+    }
+}
+
+// Why a line of a FIX log would not reach the engine were a session to send it, or None if it
+// would: what the tools that read FIX logs go by
+pub fn fix_parse_error(line: &str, conformance: Conformance) -> Option<String> {
+    match read_message(line, conformance).1 {
+        EngineMessage::InvalidMessage { reason, .. } => Some(reason),
+        _ => None,
+    }
+}
+
+// A frame as text, or why it is none, with its bytes: skipped junk, or a message that is not
+// UTF-8, kept as it came so that nothing of it is lost to a lossy conversion
+fn frame_text(frame: Frame) -> Result<String, (String, RawMessage)> {
+    match frame {
+        Frame::Message(bytes) => String::from_utf8(bytes.to_vec())
+            .map_err(|error| ("Message is not valid UTF-8".to_string(), RawMessage::capture(&bytes, Some(error.utf8_error().valid_up_to())))),
+        Frame::Garbage { raw, offset } => Err((format!("Skipped {} bytes with no message at stream offset {}", raw.length, offset), raw)),
+    }
+}
+
+// A frame as the parser reads it, and what it parses to
+fn read_frame(frame: Frame, conformance: Conformance) -> (String, EngineMessage) {
+    match frame_text(frame) {
+        Ok(line) => read_message(&line, conformance),
+        Err((reason, raw_message)) => (String::new(), EngineMessage::InvalidMessage { reason, raw_message }),
+    }
+}
+
+// The permit holds the connection's gateway slot until the client disconnects. The first
+// message decides the session: a CompID with credentials must open with a Logon proving it,
+// and is then held to that CompID; other sessions may only speak for CompIDs that need none.
+// Passes a message on to the engine, except a snapshot request, which is answered here from the
+// books as last published so reads never queue up behind matching. False once the engine is
+// gone, since its books are then only getting staler.
+fn forward(engine_message: EngineMessage, tx: &InboundSender, book_views: &BookViews, out_tx: &UnboundedSender<Bytes>) -> bool {
+    if tx.is_closed() {
+        return false;
+    }
+    match engine_message {
+        EngineMessage::Snapshot { client_id, instrument_id, depth, granularity, .. } => {
+            if let Some(fix_msg) = encode_outbound(&book_views.snapshot(client_id, instrument_id, depth, granularity), None) {
+                let _ = out_tx.send(fix_msg);
+            }
+            true
+        }
+        // The session's own liveness checks, answered without the engine
+        EngineMessage::Heartbeat { .. } => true,
+        EngineMessage::TestRequest { client_id, test_req_id } => {
+            if let Some(fix_msg) = encode_outbound(&EngineMessage::Heartbeat { client_id, test_req_id: Some(test_req_id) }, None) {
+                let _ = out_tx.send(fix_msg);
+            }
+            true
+        }
+        engine_message => tx.send(engine_message).is_ok(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    tx: InboundSender,
+    credentials: Arc<Credentials>,
+    namespace_views: Arc<NamespaceViews>,
+    conformance: Conformance,
+    malformed: Arc<MalformedLog>,
+    config: Arc<LiveConfig>,
+    permit: ConnectionPermit,
+) {
+    // Split the stream into reader and writer
+    let (reader, mut writer) = stream.into_split();
+    let mut frames = FrameReader::new(reader);
+    let mut invalid = InvalidMessages::new(malformed, config);
+    // Counted from the connection's first message, whether or not it becomes a session
+    let mut next_seq_num = 1;
+
+    // Await the first valid message to get client_id and set up outbound channel, rejecting
+    // whatever comes ahead of it that cannot be read, in the separator it came in
+    let (separator, message, engine_message) = loop {
+        let Some(frame) = frames.next_frame().await else { return };
+        let (separator, (message, engine_message)) = match frame_text(frame) {
+            Ok(line) => (Separator::of(&line), read_message(&line, conformance)),
+            Err((reason, raw_message)) => (Separator::Pipe, (String::new(), EngineMessage::InvalidMessage { reason, raw_message })),
+        };
+        match invalid.screen(engine_message) {
+            Ok(engine_message) => break (separator, message, engine_message),
+            Err(reject) => {
+                let _ = writer.write_all(&separator.apply(stamp_outbound(reject, &mut next_seq_num))).await;
+                if invalid.exhausted() {
+                    let _ = writer.write_all(&separator.apply(stamp_outbound(serialize_logout(TOO_MANY_INVALID_MESSAGES), &mut next_seq_num))).await;
+                    return;
+                }
+            }
+        }
+    };
+    match &engine_message {
+        EngineMessage::NewOrder {client_id, ..}
+        | EngineMessage::NewOrderMultileg {client_id, ..}
+        | EngineMessage::CreateInstrument {client_id, ..}
+        | EngineMessage::UpdateInstrumentSpec {client_id, ..}
+        | EngineMessage::AdvanceTime {client_id, ..}
+        | EngineMessage::CancelOrder {client_id, ..}
+        | EngineMessage::OrderStatusRequest {client_id, ..}
+        | EngineMessage::PositionQuery {client_id, ..}
+        | EngineMessage::GreeksRequest {client_id, ..}
+        | EngineMessage::InboundExecutionReport {client_id, ..}
+        | EngineMessage::CorporateAction {client_id, ..}
+        | EngineMessage::SetRestingOrderLimit {client_id, ..}
+        | EngineMessage::SetSmpAction {client_id, ..}
+        | EngineMessage::SetRiskLimits {client_id, ..}
+        | EngineMessage::RequestReplay {client_id, ..}
+        | EngineMessage::Logon {client_id, ..}
+        | EngineMessage::SubscribeOrderBook {client_id, ..}
+        | EngineMessage::UnsubscribeOrderBook {client_id, ..}
+        | EngineMessage::SubscribeAlerts {client_id, ..}
+        | EngineMessage::UnsubscribeAlerts {client_id, ..}
+        | EngineMessage::SetTradingStatus {client_id, ..}
+        | EngineMessage::RollSession {client_id, ..}
+        | EngineMessage::StartWarmUp {client_id, ..}
+        | EngineMessage::SymbolStatusRequest {client_id, ..}
+        | EngineMessage::Ping {client_id, ..}
+        | EngineMessage::Heartbeat {client_id, ..}
+        | EngineMessage::TestRequest {client_id, ..}
+        | EngineMessage::ActivityRequest {client_id, ..}
+        | EngineMessage::Snapshot {client_id, ..} => {
+            let client_id = client_id.clone();
+            let Some(book_views) = namespace_views.get(client_id.namespace()) else {
+                eprintln!("Refused logon from {}: no such namespace", client_id);
+                refuse_session(&mut writer, separator, &mut next_seq_num, &permit, LogonFailure::UnknownNamespace).await;
+                return;
+            };
+            let verified = match credentials.verify(client_id.comp_id(), logon_credentials(&message).as_ref()) {
+                Ok(Authenticated::Verified) => Some(client_id.comp_id().to_string()),
+                Ok(Authenticated::Unchecked) => None,
+                Err(failure) => {
+                    eprintln!("Refused logon from {}: {}", client_id, failure.reason());
+                    refuse_session(&mut writer, separator, &mut next_seq_num, &permit, failure).await;
+                    return;
+                }
+            };
+            let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Bytes>();
+            client_senders().insert(client_id.clone(), out_tx.clone());
+            let session_client_id = client_id.clone();
+            // A Logon is accepted in kind ahead of anything the engine says to the session
+            if let EngineMessage::Logon { cancel_previous_orders, heart_bt_int, default_appl_ver_id, .. } = &engine_message {
+                let accepted = EngineMessage::LogonAccepted {
+                    client_id: client_id.clone(),
+                    heart_bt_int: *heart_bt_int,
+                    reset_seq_num: *cancel_previous_orders,
+                    default_appl_ver_id: default_appl_ver_id.clone().unwrap_or_else(|| DEFAULT_APPL_VER_ID.to_string()),
+                };
+                let _ = out_tx.send(encode_outbound(&accepted, None).unwrap_or_default());
+            }
+
+            // Spawn writer task for outbound messages, in the separator the client opened with
+            tokio::spawn(async move {
+                while let Some(msg) = out_rx.recv().await {
+                    let msg = separator.apply(stamp_outbound(msg, &mut next_seq_num));
+                    if let Err(e) = writer.write_all(&msg).await {
+                        eprintln!("Failed to write to client {}: {}", client_id, e);
+                        break;
+                    }
+                    buffer_pool().restore(msg);
+                }
+            });
+
+            // Send the first message to exchange, then keep forwarding until either side goes away
+            let mut forwarded = forward(engine_message, &tx, book_views, &out_tx);
+            while forwarded {
+                let Some(frame) = frames.next_frame().await else { break };
+                let (line, engine_message) = read_frame(frame, conformance);
+                let mut engine_message = match invalid.screen(engine_message) {
+                    Ok(engine_message) => engine_message,
+                    Err(reject) => {
+                        let _ = out_tx.send(reject);
+                        if invalid.exhausted() {
+                            let _ = out_tx.send(logout(&session_client_id, Some(TOO_MANY_INVALID_MESSAGES)));
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                if let Some(sender) = client_id_mut(&mut engine_message) {
+                    if !credentials.may_send_as(verified.as_deref(), sender.comp_id()) {
+                        let _ = out_tx.send(session_mismatch(COMP_ID_MISMATCH, &line));
+                        continue;
+                    }
+                    if sender.namespace() != session_client_id.namespace() {
+                        let _ = out_tx.send(session_mismatch(NAMESPACE_MISMATCH, &line));
+                        continue;
+                    }
+                    // Replies go where the session registered, whatever SenderSubID it leaves out
+                    let Some(routed) = sender.clone().routed_within(&session_client_id) else {
+                        let _ = out_tx.send(session_mismatch(SUB_ID_MISMATCH, &line));
+                        continue;
+                    };
+                    *sender = routed;
+                }
+                // A Logout is answered with one, and ends the session
+                if matches!(engine_message, EngineMessage::Logout { .. }) {
+                    let _ = out_tx.send(logout(&session_client_id, None));
+                    break;
+                }
+                forwarded = forward(engine_message, &tx, book_views, &out_tx);
+            }
+            if !forwarded {
+                // The engine is gone; say so rather than leave the client talking to nobody
+                eprintln!("Exchange unavailable, logging out {}", session_client_id);
+                let _ = out_tx.send(logout(&session_client_id, Some(EXCHANGE_UNAVAILABLE)));
+            }
+
+            // Deregister so the writer task ends, unless a newer connection took over the client,
+            // and have the engine stop sending it market data
+            if client_senders().remove_if(&session_client_id, |_, sender| sender.same_channel(&out_tx)).is_some() {
+                let _ = tx.send(EngineMessage::SessionClosed { client_id: session_client_id });
+            }
+        }
+        _ => {
+            let refusal = match extract_client_id(&engine_message) {
+                Some(sender) => credentials.verify(sender.comp_id(), None).err(),
+                None if credentials.required() => Some(LogonFailure::NotLoggedOn),
+                None => None,
+            };
+            if let Some(failure) = refusal {
+                refuse_session(&mut writer, separator, &mut next_seq_num, &permit, failure).await;
+                return;
+            }
+            // For messages without client_id, just forward
+            let mut forwarded = tx.send(engine_message).is_ok();
+            while forwarded {
+                let Some(frame) = frames.next_frame().await else { break };
+                let (line, engine_message) = read_frame(frame, conformance);
+                let engine_message = match invalid.screen(engine_message) {
+                    Ok(engine_message) => engine_message,
+                    Err(reject) => {
+                        let _ = writer.write_all(&separator.apply(stamp_outbound(reject, &mut next_seq_num))).await;
+                        if invalid.exhausted() {
+                            let _ = writer.write_all(&separator.apply(stamp_outbound(serialize_logout(TOO_MANY_INVALID_MESSAGES), &mut next_seq_num))).await;
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                if extract_client_id(&engine_message).is_some_and(|sender| !credentials.may_send_as(None, sender.comp_id())) {
+                    let _ = writer.write_all(&separator.apply(stamp_outbound(session_mismatch(COMP_ID_MISMATCH, &line), &mut next_seq_num))).await;
+                    continue;
+                }
+                forwarded = tx.send(engine_message).is_ok();
+            }
+            if !forwarded {
+                eprintln!("Exchange unavailable, closing connection");
+                let _ = writer.write_all(&separator.apply(stamp_outbound(serialize_logout(EXCHANGE_UNAVAILABLE), &mut next_seq_num))).await;
+            }
+        }
+    }
+}
+
+// Writes each account statement in a batch to the configured directory as CSV, whether it
+// closed a session or was asked for
+fn write_statements(events: &[EngineMessage], config: &LiveConfig) {
+    for event in events {
+        let EngineMessage::AccountStatements { statements, .. } = event else { continue };
+        let dir = config.snapshot().statement_dir.clone();
+        for statement in statements {
+            if let Err(e) = statement.write_csv(&dir) {
+                eprintln!("Could not write the statement of {}: {}", statement.account_id, e);
+            }
+        }
+    }
+}
+
+// Runs the engine over batches of whatever is queued, so a busy consumer pays one
+// outbound send per batch. A lone message is still handled as soon as it arrives.
+// Events leave as EngineMessages; turning them into FIX text is the outbound stage's job,
+// since formatting every event here would cost more than matching it. `health` reads as
+// running for exactly as long as this loop does, which is until `shutdown` completes between
+// batches, handing back the namespaces as the last batch left them.
+async fn consume(
+    mut namespaces: Namespaces,
+    mut rx: InboundReceiver,
+    outbound_tx: UnboundedSender<OutboundBatch>,
+    health: Arc<EngineHealth>,
+    shutdown: impl Future<Output = ()>,
+) -> Namespaces {
+    let _running = health.start();
+    let mut batch = Vec::with_capacity(CONSUMER_BATCH_SIZE);
+    let mut outbound = OutboundBatch::default();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            received = rx.recv_many(&mut batch, CONSUMER_BATCH_SIZE) => {
+                if received == 0 {
+                    break;
+                }
+                for (engine_message, received) in batch.drain(..) {
+                    let events = namespaces.handle_supervised(engine_message, &health, client_senders());
+                    outbound.extend(events, ReportTimes { received, matched: exchange_now() });
+                }
+                if !outbound.events.is_empty() && outbound_tx.send(std::mem::take(&mut outbound)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    namespaces
+}
+
+// The whole server, as the binary runs it, until shutdown
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    // Pin main and parser threads to the first two cores (no NUMA awareness)
+    let mut parser_core = None;
+    if let Some(core_ids) = core_affinity::get_core_ids() {
+        if let Some(main_core) = core_ids.first() {
+            core_affinity::set_for_current(*main_core);
+            println!("Pinned main thread to core {:?}", main_core.id);
+        }
+        parser_core = core_ids.get(1).copied();
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+
+    // --config <file> holds the settings below that can be reloaded while running, by SIGHUP or the admin API
+    let config = match args.iter().position(|arg| arg == "--config") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--config needs a server config file")?;
+            println!("Loading server config from {}", path);
+            LiveConfig::load(std::path::Path::new(path))?
+        }
+        None => LiveConfig::fixed(ServerConfig::default()),
+    };
+    #[cfg(unix)]
+    tokio::spawn(config::reload_on_hangup(Arc::clone(&config)));
+
+    // --tickers <file> lets each client trade under its own names for instruments
+    let ticker_map = match args.iter().position(|arg| arg == "--tickers") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--tickers needs a ticker map file")?;
+            println!("Translating client tickers from {}", path);
+            TickerMap::load(std::path::Path::new(path))?
+        }
+        None => TickerMap::default(),
+    };
+
+    // --segments <file> names groups of instrument settings that UCI messages can create instruments in
+    let segments = match args.iter().position(|arg| arg == "--segments") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--segments needs a segments file")?;
+            println!("Loading market segments from {}", path);
+            MarketSegments::load(std::path::Path::new(path))?
+        }
+        None => MarketSegments::default(),
+    };
+
+    // --accounts <file> limits each CompID to the accounts it lists; without one any order opens the account it names
+    let account_authorizations = match args.iter().position(|arg| arg == "--accounts") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--accounts needs an account authorizations file")?;
+            println!("Authorizing accounts from {}", path);
+            AccountAuthorizations::load(std::path::Path::new(path))?
+        }
+        None => AccountAuthorizations::default(),
+    };
+
+    // --max-session-notional <amount> caps what each client may trade in a day
+    let max_session_notional = match args.iter().position(|arg| arg == "--max-session-notional") {
+        Some(flag) => args.get(flag + 1).and_then(|amount| amount.parse::<f64>().ok()).filter(|amount| *amount >= 0.0).ok_or("--max-session-notional needs an amount")?,
+        None => 0.0,
+    };
+
+    // --finished-order-retention <count> sets how many finished orders a late cancel or amend can still be told about
+    let finished_order_retention = match args.iter().position(|arg| arg == "--finished-order-retention") {
+        Some(flag) => args.get(flag + 1).and_then(|count| count.parse::<usize>().ok()).ok_or("--finished-order-retention needs a count")?,
+        None => FINISHED_ORDER_RETENTION,
+    };
+    // --trade-log-retention <count> sets how many trades are kept for replay and history queries
+    let trade_log_retention = match args.iter().position(|arg| arg == "--trade-log-retention") {
+        Some(flag) => args.get(flag + 1).and_then(|count| count.parse::<usize>().ok()).ok_or("--trade-log-retention needs a count")?,
+        None => TRADE_LOG_RETENTION,
+    };
+
+    // --surveillance <file> checks every trade and cancel for wash trading and spoofing
+    let surveillance_report = SurveillanceReport::new();
+    let surveillance_tx = match args.iter().position(|arg| arg == "--surveillance") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--surveillance needs a surveillance config file")?;
+            let config = SurveillanceConfig::load(std::path::Path::new(path))?;
+            let (surveillance_tx, surveillance_rx) = mpsc::unbounded_channel();
+            tokio::spawn(surveillance::run(surveillance_rx, config, Arc::clone(&surveillance_report)));
+            println!("Running trade surveillance from {}", path);
+            Some(surveillance_tx)
+        }
+        None => None,
+    };
+
+    // Written by the sessions, which never pass on what they could not read, and read by each
+    // namespace's exchange for its rejection log
+    let malformed_log = MalformedLog::new();
+
+    // Set from the inbound queue's depth, or by an admin, while new orders are refused; the
+    // namespaces share one queue, so they go cancel-only together
+    let overload = OverloadGuard::new();
+
+    // Orders that omit TimeInForce(59) rest as Day orders. Every namespace's exchange is set up alike.
+    let new_exchange = || {
+        let exchange = Exchange::new()
+            .with_default_time_in_force(TimeInForce::Day)
+            .with_ticker_map(ticker_map.clone())
+            .with_segments(segments.clone())
+            .with_account_authorizations(account_authorizations.clone())
+            .with_max_session_notional(max_session_notional)
+            .with_finished_order_retention(finished_order_retention)
+            .with_trade_log_retention(trade_log_retention)
+            .with_config(Arc::clone(&config))
+            .with_malformed_log(Arc::clone(&malformed_log))
+            .with_overload(Arc::clone(&overload));
+        match &surveillance_tx {
+            Some(surveillance_tx) => exchange.with_surveillance(surveillance_tx.clone()),
+            None => exchange,
+        }
+    };
+
+    // --namespaces DEV,UAT runs an exchange of its own for sessions addressing TargetCompID DEV or UAT
+    // The admin API's order, statistics and compaction reads are of the default namespace
+    // --state <dir> restarts each namespace's exchange from what it saved there when the server last shut down
+    let state_dir = match args.iter().position(|arg| arg == "--state") {
+        Some(flag) => Some(PathBuf::from(args.get(flag + 1).ok_or("--state needs a directory")?)),
+        None => None,
+    };
+    let restored = |namespace: Option<&str>| match &state_dir {
+        Some(dir) => new_exchange().with_saved_state(&state_file(dir, namespace)),
+        None => Ok(new_exchange()),
+    };
+    let exchange = restored(None)?;
+    let (recent_orders, execution_statistics, compaction_stats) = (exchange.recent_orders(), exchange.execution_statistics(), exchange.compaction_stats());
+    let mut namespaces = Namespaces::new(exchange);
+    if let Some(flag) = args.iter().position(|arg| arg == "--namespaces") {
+        let names = args.get(flag + 1).ok_or("--namespaces needs a comma-separated list of namespaces")?;
+        for namespace in names.split(',').map(str::trim).filter(|namespace| !namespace.is_empty()) {
+            namespaces = namespaces.with_namespace(namespace.to_string(), restored(Some(namespace))?);
+            println!("Running namespace {}", namespace);
+        }
+    }
+    if let Some(dir) = &state_dir {
+        println!("Keeping the exchange state in {}", dir.display());
+    }
+
+    // Published by the engine, read by sessions answering snapshots and the admin API
+    let namespace_views = namespaces.book_views();
+
+    // Shared by the consumer, which keeps it current, and the REST health endpoint
+    let health = EngineHealth::new();
+
+    #[cfg(target_os = "linux")]
+    let mut producer_pool = ThreadPool::try_named_spawn("producer", 2).expect("Failed to start producer pool");
+
+    // Shared by every accept loop so the connection cap is exchange-wide
+    let gate = ConnectionGate::with_config(Arc::clone(&config));
+
+    // Cancels are drained ahead of new orders so they stay fast under load
+    let (tx, rx) = inbound_channel(true);
+    let (outbound_tx, mut outbound_rx): (UnboundedSender<OutboundBatch>, UnboundedReceiver<OutboundBatch>) = mpsc::unbounded_channel();
+    // Spawned onto this runtime from the outbound stage, whichever thread that runs on
+    let trade_webhook = TradeWebhook::new(tokio::runtime::Handle::current());
+
+    // --simulate <agents.toml> trades synthetic agents through the same inbound path as clients
+    if let Some(flag) = args.iter().position(|arg| arg == "--simulate") {
+        let path = args.get(flag + 1).ok_or("--simulate needs an agents file")?;
+        let simulation = Simulation::new(SimulationConfig::load(std::path::Path::new(path))?)?;
+        // Everything is stamped in the agents' time, which only their ticks move on
+        clock::install(Clock::manual(simulation.start_time()))?;
+        simulation::start(simulation, tx.clone())?;
+        println!("Simulating agents from {}", path);
+    }
+
+    // --credentials <file> checks each CompID's Logon against it; without one sessions are unchecked
+    let credentials = match args.iter().position(|arg| arg == "--credentials") {
+        Some(flag) => {
+            let path = args.get(flag + 1).ok_or("--credentials needs a credentials file")?;
+            println!("Checking logons against {}", path);
+            Credentials::load(std::path::Path::new(path))?
+        }
+        None => Credentials::open(),
+    };
+
+    // --heartbeat-interval <seconds> sets how often every session is sent the exchange's status
+    let heartbeat_interval = match args.iter().position(|arg| arg == "--heartbeat-interval") {
+        Some(flag) => {
+            let seconds: u64 = args.get(flag + 1).and_then(|seconds| seconds.parse().ok()).ok_or("--heartbeat-interval needs a number of seconds")?;
+            if seconds == 0 {
+                return Err("--heartbeat-interval must be at least one second".into());
+            }
+            std::time::Duration::from_secs(seconds)
+        }
+        None => DEFAULT_HEARTBEAT_INTERVAL,
+    };
+
+    // --lenient-fix logs the deviations common FIX engines make instead of refusing the message
+    let conformance = if args.iter().any(|arg| arg == "--lenient-fix") {
+        println!("Tolerating benign FIX deviations");
+        Conformance::Lenient
+    } else {
+        Conformance::Strict
+    };
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(publish_periodically(Arc::clone(&config), tx.clone(), shutdown_rx.clone()));
+    tokio::spawn(summarize_periodically(Arc::clone(&config), tx.clone(), shutdown_rx.clone()));
+    tokio::spawn(compact_periodically(COMPACTION_INTERVAL, tx.clone(), client_senders(), Arc::clone(&compaction_stats), shutdown_rx.clone()));
+    tokio::spawn(watch_overload(OVERLOAD_SAMPLE_INTERVAL, Arc::clone(&overload), tx.queue_depth(), Arc::clone(&config), Arc::clone(&health), client_senders(), shutdown_rx.clone()));
+    tokio::spawn(broadcast_status(heartbeat_interval, Arc::clone(&health), tx.queue_depth(), Arc::clone(&overload), client_senders(), shutdown_rx.clone()));
+
+    // JSON over HTTP for clients that don't speak FIX
+    let addresses = config.snapshot();
+    let rest_listener = tokio::net::TcpListener::bind(&addresses.rest_address).await?;
+    println!("Exchange server REST API on {}", addresses.rest_address);
+    tokio::spawn(rest::serve(rest_listener, tx.clone(), recent_orders, Arc::clone(&health), Arc::clone(&credentials), Arc::clone(&config), surveillance_report, Arc::clone(&namespace_views), execution_statistics, compaction_stats, overload));
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let listener = tokio::net::TcpListener::bind(&addresses.fix_address).await?;
+        println!("Exchange server TCP socket on {}", addresses.fix_address);
+
+        let gate = Arc::clone(&gate);
+        let tx = tx.clone();
+        let credentials = Arc::clone(&credentials);
+        let namespace_views = Arc::clone(&namespace_views);
+        let malformed_log = Arc::clone(&malformed_log);
+        let config = Arc::clone(&config);
+        tokio::spawn(accept_connections(listener, gate, move |stream, permit| handle_connection(stream, tx.clone(), Arc::clone(&credentials), Arc::clone(&namespace_views), conformance, Arc::clone(&malformed_log), Arc::clone(&config), permit)));
+    }
+
+    // The consumer gets a thread of its own everywhere, so nothing else shares the matching thread.
+    // fork_union runs a pool's work on the calling thread and joins before returning, which is
+    // also why the outbound stage below gets a dedicated thread rather than a pool.
+    // It stops with the server, handing the books back to be saved
+    let mut shutdown = shutdown_rx;
+    let consumer = std::thread::Builder::new().name("consumer".to_string()).spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stopping| *stopping).await;
+        };
+        rt.block_on(consume(namespaces, rx, outbound_tx, health, stopped))
+    })?;
+
+    #[cfg(target_os = "linux")]
+    {
+        // A socket per producer thread, all on the one port
+        let threads = producer_pool.threads();
+        let listeners = reuse_port_listeners(&addresses.fix_address, threads)?;
+        println!("Exchange server TCP socket on {}", addresses.fix_address);
+
+        let tx = tx.clone();
+        let credentials = Arc::clone(&credentials);
+        let namespace_views = Arc::clone(&namespace_views);
+        let malformed_log = Arc::clone(&malformed_log);
+        let config = Arc::clone(&config);
+        std::thread::Builder::new().name("producer".to_string()).spawn(move || {
+            if let Some(core) = parser_core {
+                core_affinity::set_for_current(core);
+            }
+            producer_pool.for_n_dynamic(threads, move |prong| {
+                let tx = tx.clone();
+                let gate = Arc::clone(&gate);
+                let credentials = Arc::clone(&credentials);
+                let namespace_views = Arc::clone(&namespace_views);
+                let malformed_log = Arc::clone(&malformed_log);
+                let config = Arc::clone(&config);
+                let listener = listeners[prong.task_index].try_clone().expect("Failed to clone TCP listener");
+                let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+                rt.block_on(async {
+                    let listener = tokio::net::TcpListener::from_std(listener).expect("Failed to register TCP listener");
+                    accept_connections(listener, gate, move |stream, permit| handle_connection(stream, tx.clone(), Arc::clone(&credentials), Arc::clone(&namespace_views), conformance, Arc::clone(&malformed_log), Arc::clone(&config), permit)).await;
+                });
+            });
+        })?;
+    }
+
+    #[cfg(target_os = "linux")]
+    std::thread::Builder::new().name("outbound".to_string()).spawn(move || {
+        while let Some(batch) = outbound_rx.blocking_recv() {
+            rest::deliver_responses(&batch.events);
+            simulation::deliver_events(&batch.events);
+            write_statements(&batch.events, &config);
+            trade_webhook.deliver(&batch.events, &config);
+            for (message, times) in batch.iter() {
+                // Exchange-wide alerts have no session to go to
+                if let EngineMessage::LogEvent { client_id: None, message } = message {
+                    if logs(LogLevel::Info) {
+                        println!("{}", message);
+                    }
+                    continue;
+                }
+                if let Some(client_id) = extract_client_id(message) {
+                    if let Some(tx) = client_senders().get(&client_id) {
+                        if let Some(fix_msg) = encode_outbound(message, Some(times)) {
+                            let _ = tx.send(fix_msg);
+                        }
+                    }
+                }
+            }
+        }
+    })?;
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        tokio::spawn(async move {
+            while let Some(batch) = outbound_rx.recv().await {
+                rest::deliver_responses(&batch.events);
+                simulation::deliver_events(&batch.events);
+                write_statements(&batch.events, &config);
+                trade_webhook.deliver(&batch.events, &config);
+                if logs(LogLevel::Debug) {
+                    for message in batch.events {
+                        println!("Outbound: {:?}", message);
+                    }
+                }
+            }
+        });
+    }
+
+    // Sessions stop hearing the exchange is alive before it goes away
+    tokio::signal::ctrl_c().await?;
+    println!("Shutting down");
+    let _ = shutdown_tx.send(true);
+    let namespaces = tokio::task::spawn_blocking(move || consumer.join()).await?.map_err(|_| "the consumer panicked")?;
+    if let Some(dir) = &state_dir {
+        namespaces.save_state(dir)?;
+        println!("Saved the exchange state to {}", dir.display());
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::book_views::BookViews;
+use crate::engine::{EngineMessage, extract_client_id};
+use crate::exchange::Exchange;
+use crate::supervisor::{handle_supervised, EngineHealth};
+use crate::types::*;
+
+// Logical environments, e.g. DEV and UAT, sharing one server. A session is in the namespace
+// named by the TargetCompID it addresses, and each namespace has an exchange of its own, so
+// the books, accounts and orders of one are out of sight of every other. Sessions addressing
+// the exchange itself are in the default namespace, as are all of them when none are named.
+pub struct Namespaces {
+    default: Exchange,
+    named: HashMap<Namespace, Exchange>,
+}
+
+impl Namespaces {
+    pub fn new(default: Exchange) -> Self {
+        Self { default, named: HashMap::new() }
+    }
+
+    pub fn with_namespace(mut self, namespace: Namespace, exchange: Exchange) -> Self {
+        self.named.insert(namespace, exchange);
+        self
+    }
+
+    // Saves each namespace's exchange to its own file under `dir`, for a restart to pick up
+    pub fn save_state(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        self.default.save_state(&state_file(dir, None))?;
+        for (namespace, exchange) in &self.named {
+            exchange.save_state(&state_file(dir, Some(namespace)))?;
+        }
+        Ok(())
+    }
+
+    // Each namespace's books as last published, for sessions and the admin API to read
+    pub fn book_views(&self) -> Arc<NamespaceViews> {
+        Arc::new(NamespaceViews {
+            default: self.default.book_views(),
+            named: self.named.iter().map(|(namespace, exchange)| (namespace.clone(), exchange.book_views())).collect(),
+        })
+    }
+
+    // Handles a message in its sender's namespace. Housekeeping no client sent, like publishing
+    // and compaction, runs in every namespace.
+    pub fn handle_supervised(
+        &mut self,
+        message: EngineMessage,
+        health: &EngineHealth,
+        sessions: &DashMap<ClientID, UnboundedSender<Bytes>>,
+    ) -> Vec<EngineMessage> {
+        if self.named.is_empty() {
+            return handle_supervised(&mut self.default, message, health, sessions);
+        }
+        if matches!(message, EngineMessage::PublishBookViews | EngineMessage::PublishActivity | EngineMessage::Compact { client_id: None }) {
+            let mut events = Vec::new();
+            for exchange in self.named.values_mut() {
+                events.extend(handle_supervised(exchange, message.clone(), health, sessions));
+            }
+            events.extend(handle_supervised(&mut self.default, message, health, sessions));
+            return events;
+        }
+        let sender = extract_client_id(&message);
+        let namespace = sender.as_ref().and_then(ClientID::namespace);
+        // An order naming a SecurityExchange(207) is for that namespace's book of its symbol, and
+        // no other namespace's book of the same name
+        if let (EngineMessage::NewOrder { exchange_code: Some(exchange_code), .. }, Some(client_id)) = (&message, &sender) {
+            if namespace != Some(exchange_code.as_str()) {
+                return vec![EngineMessage::OrderRejected {
+                    client_id: client_id.clone(),
+                    reason: format!("SecurityExchange {} is not the session's namespace", exchange_code),
+                    code: None,
+                }];
+            }
+        }
+        let exchange = match namespace {
+            Some(namespace) => self.named.get_mut(namespace),
+            None => Some(&mut self.default),
+        };
+        match exchange {
+            Some(exchange) => handle_supervised(exchange, message, health, sessions),
+            None => vec![EngineMessage::LogEvent { message: format!("Unknown namespace {}", namespace.unwrap_or_default()), client_id: sender.clone() }],
+        }
+    }
+}
+
+// The published books of every namespace
+pub struct NamespaceViews {
+    default: Arc<BookViews>,
+    named: HashMap<Namespace, Arc<BookViews>>,
+}
+
+impl NamespaceViews {
+    pub fn get(&self, namespace: Option<&str>) -> Option<&Arc<BookViews>> {
+        resolve(&self.default, &self.named, namespace)
+    }
+}
+
+// The file under `dir` the exchange of `namespace`, or of the default namespace, is saved to
+pub fn state_file(dir: &Path, namespace: Option<&str>) -> PathBuf {
+    dir.join(format!("{}.json", namespace.unwrap_or("default")))
+}
+
+// What `namespace` stands for: the default when it names none, or when the server names none and
+// so takes every TargetCompID as the exchange's own
+fn resolve<'a, T>(default: &'a T, named: &'a HashMap<Namespace, T>, namespace: Option<&str>) -> Option<&'a T> {
+    match namespace {
+        Some(namespace) if !named.is_empty() => named.get(namespace),
+        _ => Some(default),
+    }
+}
@@ -0,0 +1,41 @@
+use fefix::definitions::fix50::OrdStatus;
+
+// Lifecycle of an order as reported to the client:
+// PendingNew -> New -> PartiallyFilled* -> Filled | Canceled | Expired, or PendingNew -> Rejected.
+// Amendments report the current status and are not transitions.
+pub(crate) fn is_valid_transition(from: OrdStatus, to: OrdStatus) -> bool {
+    match from {
+        OrdStatus::PendingNew => matches!(to, OrdStatus::New | OrdStatus::Rejected),
+        OrdStatus::New | OrdStatus::PartiallyFilled => matches!(
+            to,
+            OrdStatus::PartiallyFilled | OrdStatus::Filled | OrdStatus::Canceled | OrdStatus::Expired
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_order_lifecycle() {
+        assert!(is_valid_transition(OrdStatus::PendingNew, OrdStatus::New));
+        assert!(is_valid_transition(OrdStatus::PendingNew, OrdStatus::Rejected));
+        assert!(is_valid_transition(OrdStatus::New, OrdStatus::PartiallyFilled));
+        assert!(is_valid_transition(OrdStatus::PartiallyFilled, OrdStatus::PartiallyFilled));
+        assert!(is_valid_transition(OrdStatus::PartiallyFilled, OrdStatus::Filled));
+        assert!(is_valid_transition(OrdStatus::PartiallyFilled, OrdStatus::Canceled));
+        assert!(is_valid_transition(OrdStatus::New, OrdStatus::Expired));
+    }
+
+    #[test]
+    fn rejects_skipped_and_terminal_transitions() {
+        assert!(!is_valid_transition(OrdStatus::PendingNew, OrdStatus::Filled));
+        assert!(!is_valid_transition(OrdStatus::New, OrdStatus::New));
+        for terminal in [OrdStatus::Filled, OrdStatus::Canceled, OrdStatus::Rejected, OrdStatus::Expired] {
+            assert!(!is_valid_transition(terminal, OrdStatus::Canceled));
+            assert!(!is_valid_transition(terminal, OrdStatus::PartiallyFilled));
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::{logs, LiveConfig, LogLevel, ServerConfig};
+use crate::fix::serialize_trading_session_status;
+use crate::heartbeat::ExchangeStatus;
+use crate::inbound::QueueDepth;
+use crate::supervisor::EngineHealth;
+use crate::types::ClientID;
+
+// How often the inbound queue's depth is read; the engine itself never looks
+pub const OVERLOAD_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+pub const OVERLOAD_QUEUE_DEPTH: usize = 10_000;
+pub const OVERLOAD_RESUME_DEPTH: usize = 1_000;
+pub const OVERLOAD_SAMPLES: usize = 5;
+
+// Why a new order is turned away while the engine is cancel-only
+pub const SYSTEM_OVERLOADED: &str = "System overloaded";
+
+// Whether the exchange decides cancel-only for itself or an admin has forced it one way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelOnlyMode {
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl CancelOnlyMode {
+    fn of(value: u8) -> Self {
+        match value {
+            1 => Self::On,
+            2 => Self::Off,
+            _ => Self::Auto,
+        }
+    }
+}
+
+// What the admin API reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OverloadStatus {
+    pub cancel_only: bool,
+    pub overloaded: bool, // as last detected, whatever the mode
+    pub mode: CancelOnlyMode,
+    pub episodes: u64, // times the exchange has gone cancel-only, detected or forced
+}
+
+// Cancel-only mode, read by the engine for every new order and set by the sampler and the
+// admin API. While it holds, new orders are rejected and cancels are taken as ever.
+#[derive(Debug, Default)]
+pub struct OverloadGuard {
+    overloaded: AtomicBool,
+    mode: AtomicU8,
+    episodes: AtomicU64,
+}
+
+impl OverloadGuard {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn cancel_only(&self) -> bool {
+        match self.mode() {
+            CancelOnlyMode::Auto => self.overloaded.load(Ordering::Relaxed),
+            CancelOnlyMode::On => true,
+            CancelOnlyMode::Off => false,
+        }
+    }
+
+    pub fn mode(&self) -> CancelOnlyMode {
+        CancelOnlyMode::of(self.mode.load(Ordering::Relaxed))
+    }
+
+    // Takes effect for the next order, and is broadcast with the next sample
+    pub fn set_mode(&self, mode: CancelOnlyMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> OverloadStatus {
+        OverloadStatus {
+            cancel_only: self.cancel_only(),
+            overloaded: self.overloaded.load(Ordering::Relaxed),
+            mode: self.mode(),
+            episodes: self.episodes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// The sampler's view between samples: how many in a row have found the queue too deep, and
+// the state sessions were last told of
+#[derive(Debug, Default)]
+struct OverloadDetector {
+    over_in_a_row: usize,
+    cancel_only: bool,
+}
+
+impl OverloadDetector {
+    // Overloaded once the depth has been over overload_queue_depth for overload_samples samples
+    // in a row, and not until it is back under overload_resume_depth. Returns the new state
+    // if cancel-only changed, by this sample or by an admin since the last.
+    fn sample(&mut self, guard: &OverloadGuard, depth: usize, config: &ServerConfig) -> Option<bool> {
+        let overloaded = guard.overloaded.load(Ordering::Relaxed);
+        if config.overload_queue_depth == 0 {
+            guard.overloaded.store(false, Ordering::Relaxed);
+        } else if !overloaded {
+            self.over_in_a_row = if depth > config.overload_queue_depth { self.over_in_a_row + 1 } else { 0 };
+            if self.over_in_a_row >= config.overload_samples {
+                guard.overloaded.store(true, Ordering::Relaxed);
+            }
+        } else if depth < config.overload_resume_depth {
+            self.over_in_a_row = 0;
+            guard.overloaded.store(false, Ordering::Relaxed);
+        }
+
+        let cancel_only = guard.cancel_only();
+        if cancel_only == self.cancel_only {
+            return None;
+        }
+        self.cancel_only = cancel_only;
+        if cancel_only {
+            guard.episodes.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(cancel_only)
+    }
+}
+
+// Samples the inbound queue's depth once per `interval` and tells every connected session,
+// with a TradingSessionStatus, each time the exchange goes cancel-only or comes out of it.
+// Returns once `shutdown` reads true or its sender is dropped.
+pub async fn watch_overload(
+    interval: Duration,
+    guard: Arc<OverloadGuard>,
+    queue_depth: QueueDepth,
+    config: Arc<LiveConfig>,
+    health: Arc<EngineHealth>,
+    sessions: &'static DashMap<ClientID, UnboundedSender<Bytes>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut detector = OverloadDetector::default();
+    loop {
+        tokio::select! {
+            biased;
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    return;
+                }
+            }
+            _ = ticks.tick() => {
+                let depth = queue_depth.get();
+                let Some(cancel_only) = detector.sample(&guard, depth, &config.snapshot()) else { continue };
+                if logs(LogLevel::Warn) {
+                    let mode = guard.mode();
+                    match cancel_only {
+                        true => eprintln!("Cancel-only: new orders refused, {} messages queued, mode {:?}", depth, mode),
+                        false => eprintln!("Cancel-only lifted, {} messages queued, mode {:?}", depth, mode),
+                    }
+                }
+                let status = ExchangeStatus::now(&health, &queue_depth, &guard);
+                for session in sessions.iter() {
+                    let _ = session.send(serialize_trading_session_status(&status, session.key()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::*;
+    use crate::engine::EngineMessage;
+    use crate::framing::RawMessage;
+    use crate::inbound::{inbound_channel, InboundReceiver, InboundSender};
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    fn config() -> ServerConfig {
+        ServerConfig { overload_queue_depth: 100, overload_resume_depth: 20, overload_samples: 3, ..ServerConfig::default() }
+    }
+
+    fn flood(tx: &InboundSender, messages: usize) {
+        for _ in 0..messages {
+            tx.send(EngineMessage::InvalidMessage { reason: String::new(), raw_message: RawMessage::text("") }).unwrap();
+        }
+    }
+
+    async fn drain(rx: &mut InboundReceiver, messages: usize) {
+        for _ in 0..messages {
+            rx.recv().await.unwrap();
+        }
+    }
+
+    // Each sample's cancel-only state, as the engine would read it after it
+    fn samples(detector: &mut OverloadDetector, guard: &OverloadGuard, depth: &QueueDepth, count: usize) -> Vec<bool> {
+        (0..count).map(|_| {
+            detector.sample(guard, depth.get(), &config());
+            guard.cancel_only()
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn a_flooded_queue_goes_cancel_only_after_enough_samples_and_stays_until_it_drains() {
+        let (tx, mut rx) = inbound_channel(true);
+        let (guard, depth, mut detector) = (OverloadGuard::new(), tx.queue_depth(), OverloadDetector::default());
+
+        // A spike one sample long is not overload
+        flood(&tx, 150);
+        assert_eq!(samples(&mut detector, &guard, &depth, 2), [false, false]);
+        drain(&mut rx, 100).await;
+        assert_eq!(samples(&mut detector, &guard, &depth, 1), [false]);
+        flood(&tx, 100);
+        assert_eq!(samples(&mut detector, &guard, &depth, 3), [false, false, true]);
+
+        // Between the thresholds it holds, and only draining past the lower one lifts it
+        drain(&mut rx, 100).await;
+        assert_eq!(depth.get(), 50);
+        assert_eq!(samples(&mut detector, &guard, &depth, 3), [true, true, true]);
+        drain(&mut rx, 31).await;
+        assert_eq!(samples(&mut detector, &guard, &depth, 1), [false]);
+        assert_eq!(guard.status().episodes, 1);
+    }
+
+    #[test]
+    fn an_admin_override_wins_over_detection_either_way() {
+        let (guard, mut detector) = (OverloadGuard::new(), OverloadDetector::default());
+        guard.set_mode(CancelOnlyMode::On);
+        assert_eq!(detector.sample(&guard, 0, &config()), Some(true));
+        assert_eq!(detector.sample(&guard, 0, &config()), None);
+
+        guard.set_mode(CancelOnlyMode::Off);
+        for _ in 0..5 {
+            detector.sample(&guard, 1_000, &config());
+        }
+        assert!(!guard.cancel_only() && guard.status().overloaded);
+        guard.set_mode(CancelOnlyMode::Auto);
+        assert_eq!(detector.sample(&guard, 1_000, &config()), Some(true));
+        assert_eq!(guard.status().episodes, 2);
+
+        // A threshold of 0 never detects overload
+        let never = ServerConfig { overload_queue_depth: 0, ..config() };
+        assert_eq!(detector.sample(&guard, 1_000, &never), Some(false));
+    }
+
+    fn one_session() -> (&'static DashMap<ClientID, UnboundedSender<Bytes>>, UnboundedReceiver<Bytes>) {
+        let sessions = Box::leak(Box::new(DashMap::new()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        sessions.insert(ClientID::new("WATCHER".to_string(), None), tx);
+        (sessions, rx)
+    }
+
+    #[tokio::test]
+    async fn sessions_are_told_as_the_exchange_goes_cancel_only_and_comes_out_of_it() {
+        let (sessions, mut statuses) = one_session();
+        let (tx, mut rx) = inbound_channel(true);
+        let guard = OverloadGuard::new();
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        tokio::spawn(watch_overload(INTERVAL, Arc::clone(&guard), tx.queue_depth(), LiveConfig::fixed(config()), EngineHealth::new(), sessions, shutdown));
+
+        flood(&tx, 150);
+        let status = String::from_utf8_lossy(&statuses.recv().await.unwrap()).into_owned();
+        assert!(status.contains("|35=h|") && status.contains("|8047=Y|"), "{}", status);
+        assert!(guard.cancel_only());
+
+        drain(&mut rx, 150).await;
+        let status = String::from_utf8_lossy(&statuses.recv().await.unwrap()).into_owned();
+        assert!(status.contains("|35=h|") && status.contains("|8047=N|"), "{}", status);
+        assert_eq!(guard.status().episodes, 1);
+    }
+}
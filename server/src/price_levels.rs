@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::types::Price;
+
+// Ladders past this many ticks are refused rather than allocated
+const MAX_LADDER_TICKS: usize = 1_000_000;
+
+// One side of a book: a queue of resting orders per price, in price order. A level stays until
+// removed, even once its queue is empty.
+pub(crate) trait PriceLevels<T: 'static> {
+    fn len(&self) -> usize;
+    fn contains_key(&self, price: &Price) -> bool;
+    fn get_mut(&mut self, price: &Price) -> Option<&mut VecDeque<T>>;
+    fn remove(&mut self, price: &Price) -> Option<VecDeque<T>>;
+    // The queue at `price`, opening the level if there is none
+    fn level_mut(&mut self, price: Price) -> &mut VecDeque<T>;
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &VecDeque<T>)>;
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut VecDeque<T>>;
+    fn retain(&mut self, keep: impl FnMut(&Price, &mut VecDeque<T>) -> bool);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn keys(&self) -> impl DoubleEndedIterator<Item = &Price> {
+        self.iter().map(|(price, _)| price)
+    }
+
+    fn values(&self) -> impl DoubleEndedIterator<Item = &VecDeque<T>> {
+        self.iter().map(|(_, queue)| queue)
+    }
+}
+
+impl<T: 'static> PriceLevels<T> for BTreeMap<Price, VecDeque<T>> {
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn contains_key(&self, price: &Price) -> bool {
+        BTreeMap::contains_key(self, price)
+    }
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut VecDeque<T>> {
+        BTreeMap::get_mut(self, price)
+    }
+
+    fn remove(&mut self, price: &Price) -> Option<VecDeque<T>> {
+        BTreeMap::remove(self, price)
+    }
+
+    fn level_mut(&mut self, price: Price) -> &mut VecDeque<T> {
+        self.entry(price).or_default()
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &VecDeque<T>)> {
+        BTreeMap::iter(self)
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut VecDeque<T>> {
+        BTreeMap::values_mut(self)
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&Price, &mut VecDeque<T>) -> bool) {
+        BTreeMap::retain(self, keep)
+    }
+}
+
+// Levels on a fixed tick grid from `low`, one slot per tick, for instruments that trade in a
+// narrow band: a level is found by its offset rather than searched for. The slots between the
+// lowest and highest open ones bound every walk of the levels.
+#[derive(Debug, Clone)]
+pub(crate) struct PriceLadder<T> {
+    low: Price,
+    tick: f64,
+    slots: Vec<Option<(Price, VecDeque<T>)>>, // the price as given, so keys read back exactly
+    len: usize,
+    open: Option<(usize, usize)>, // the lowest and highest open slots
+}
+
+impl<T> PriceLadder<T> {
+    pub(crate) fn new(low: Price, high: Price, tick: Price) -> Result<Self, &'static str> {
+        if tick <= Price::from(0.0) {
+            return Err("A price ladder needs a tick size");
+        }
+        let ticks = ((high - low).into_inner() / tick.into_inner()).round();
+        if !(0.0..MAX_LADDER_TICKS as f64).contains(&ticks) {
+            return Err("Invalid price ladder range");
+        }
+        let slots = std::iter::repeat_with(|| None).take(ticks as usize + 1).collect();
+        Ok(Self { low, tick: tick.into_inner(), slots, len: 0, open: None })
+    }
+
+    // The slot `price` falls on, if it is on the grid and within the range
+    fn slot(&self, price: &Price) -> Option<usize> {
+        let offset = (price.into_inner() - self.low.into_inner()) / self.tick;
+        let index = offset.round();
+        // As with tick sizes, on the grid up to binary rounding
+        ((offset - index).abs() <= 1e-6 && index >= 0.0 && (index as usize) < self.slots.len()).then_some(index as usize)
+    }
+
+    // The slot of the level open at exactly `price`
+    fn find(&self, price: &Price) -> Option<usize> {
+        self.slot(price).filter(|&index| self.slots[index].as_ref().is_some_and(|(at, _)| at == price))
+    }
+
+    // Whether a level at `price` has a slot of its own, open already or free
+    pub(crate) fn fits(&self, price: &Price) -> bool {
+        self.slot(price).is_some_and(|index| self.slots[index].as_ref().is_none_or(|(at, _)| at == price))
+    }
+
+    fn open_slots(&self) -> &[Option<(Price, VecDeque<T>)>] {
+        match self.open {
+            Some((lowest, highest)) => &self.slots[lowest..=highest],
+            None => &[],
+        }
+    }
+
+    // Narrows the open range to the slots still open
+    fn reopen(&mut self) {
+        let lowest = self.slots.iter().position(Option::is_some);
+        let highest = self.slots.iter().rposition(Option::is_some);
+        self.open = lowest.zip(highest);
+    }
+
+    fn into_tree(self) -> BTreeMap<Price, VecDeque<T>> {
+        self.slots.into_iter().flatten().collect()
+    }
+}
+
+impl<T: 'static> PriceLevels<T> for PriceLadder<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn contains_key(&self, price: &Price) -> bool {
+        self.find(price).is_some()
+    }
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut VecDeque<T>> {
+        let index = self.find(price)?;
+        self.slots[index].as_mut().map(|(_, queue)| queue)
+    }
+
+    fn remove(&mut self, price: &Price) -> Option<VecDeque<T>> {
+        let index = self.find(price)?;
+        let (_, queue) = self.slots[index].take()?;
+        self.len -= 1;
+        // Only the ends of the open range move; a level inside leaves them be
+        match self.open {
+            Some((lowest, highest)) if index == lowest || index == highest => {
+                let lowest = (lowest..=highest).find(|&index| self.slots[index].is_some());
+                let highest = (lowest.unwrap_or(highest)..=highest).rev().find(|&index| self.slots[index].is_some());
+                self.open = lowest.zip(highest);
+            }
+            _ => {}
+        }
+        Some(queue)
+    }
+
+    // `price` must fit; a side that may be handed other prices is a `Levels`
+    fn level_mut(&mut self, price: Price) -> &mut VecDeque<T> {
+        let index = self.slot(&price).expect("price on the ladder");
+        if self.slots[index].is_none() {
+            self.slots[index] = Some((price, VecDeque::new()));
+            self.len += 1;
+            self.open = Some(self.open.map_or((index, index), |(lowest, highest)| (lowest.min(index), highest.max(index))));
+        }
+        &mut self.slots[index].as_mut().expect("slot just opened").1
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &VecDeque<T>)> {
+        self.open_slots().iter().flatten().map(|(price, queue)| (price, queue))
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut VecDeque<T>> {
+        let slots = match self.open {
+            Some((lowest, highest)) => &mut self.slots[lowest..=highest],
+            None => &mut [],
+        };
+        slots.iter_mut().flatten().map(|(_, queue)| queue)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&Price, &mut VecDeque<T>) -> bool) {
+        for slot in &mut self.slots {
+            if slot.as_mut().is_some_and(|(price, queue)| !keep(price, queue)) {
+                *slot = None;
+                self.len -= 1;
+            }
+        }
+        self.reopen();
+    }
+}
+
+// A book side on whichever backend suits its instrument: a tree by default, or a ladder for
+// an instrument that asks for one. A ladder handed a price off its grid or out of its range
+// moves its levels into a tree and stays there.
+#[derive(Debug, Clone)]
+pub(crate) enum Levels<T> {
+    Tree(BTreeMap<Price, VecDeque<T>>),
+    Ladder(PriceLadder<T>),
+}
+
+impl<T> Default for Levels<T> {
+    fn default() -> Self {
+        Levels::Tree(BTreeMap::new())
+    }
+}
+
+impl<T: 'static> Levels<T> {
+    // The same levels moved onto `ladder`, or kept in a tree if any does not fit it
+    pub(crate) fn onto(self, mut ladder: PriceLadder<T>) -> Self {
+        let mut levels = self.into_tree().into_iter();
+        while let Some((price, queue)) = levels.next() {
+            if !ladder.fits(&price) {
+                let mut tree = ladder.into_tree();
+                tree.insert(price, queue);
+                tree.extend(levels);
+                return Levels::Tree(tree);
+            }
+            *ladder.level_mut(price) = queue;
+        }
+        Levels::Ladder(ladder)
+    }
+
+    pub(crate) fn into_tree(self) -> BTreeMap<Price, VecDeque<T>> {
+        match self {
+            Levels::Tree(tree) => tree,
+            Levels::Ladder(ladder) => ladder.into_tree(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_ladder(&self) -> bool {
+        matches!(self, Levels::Ladder(_))
+    }
+}
+
+// Either backend's iterator, so one side reads the same whichever it is on
+enum Either<A, B> {
+    Tree(A),
+    Ladder(B),
+}
+
+impl<A: Iterator, B: Iterator<Item = A::Item>> Iterator for Either<A, B> {
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Either::Tree(levels) => levels.next(),
+            Either::Ladder(levels) => levels.next(),
+        }
+    }
+}
+
+impl<A: DoubleEndedIterator, B: DoubleEndedIterator<Item = A::Item>> DoubleEndedIterator for Either<A, B> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Either::Tree(levels) => levels.next_back(),
+            Either::Ladder(levels) => levels.next_back(),
+        }
+    }
+}
+
+impl<T: 'static> PriceLevels<T> for Levels<T> {
+    fn len(&self) -> usize {
+        match self {
+            Levels::Tree(tree) => PriceLevels::len(tree),
+            Levels::Ladder(ladder) => ladder.len(),
+        }
+    }
+
+    fn contains_key(&self, price: &Price) -> bool {
+        match self {
+            Levels::Tree(tree) => PriceLevels::contains_key(tree, price),
+            Levels::Ladder(ladder) => ladder.contains_key(price),
+        }
+    }
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut VecDeque<T>> {
+        match self {
+            Levels::Tree(tree) => PriceLevels::get_mut(tree, price),
+            Levels::Ladder(ladder) => ladder.get_mut(price),
+        }
+    }
+
+    fn remove(&mut self, price: &Price) -> Option<VecDeque<T>> {
+        match self {
+            Levels::Tree(tree) => PriceLevels::remove(tree, price),
+            Levels::Ladder(ladder) => ladder.remove(price),
+        }
+    }
+
+    fn level_mut(&mut self, price: Price) -> &mut VecDeque<T> {
+        if matches!(self, Levels::Ladder(ladder) if !ladder.fits(&price)) {
+            *self = Levels::Tree(std::mem::take(self).into_tree());
+        }
+        match self {
+            Levels::Tree(tree) => tree.level_mut(price),
+            Levels::Ladder(ladder) => ladder.level_mut(price),
+        }
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &VecDeque<T>)> {
+        match self {
+            Levels::Tree(tree) => Either::Tree(PriceLevels::iter(tree)),
+            Levels::Ladder(ladder) => Either::Ladder(ladder.iter()),
+        }
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut VecDeque<T>> {
+        match self {
+            Levels::Tree(tree) => Either::Tree(PriceLevels::values_mut(tree)),
+            Levels::Ladder(ladder) => Either::Ladder(ladder.values_mut()),
+        }
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&Price, &mut VecDeque<T>) -> bool) {
+        match self {
+            Levels::Tree(tree) => PriceLevels::retain(tree, keep),
+            Levels::Ladder(ladder) => ladder.retain(keep),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(price: f64) -> Price {
+        Price::from(price)
+    }
+
+    // A ladder from 9.00 to 11.00 on a 0.01 tick
+    fn ladder() -> PriceLadder<u64> {
+        PriceLadder::new(price(9.0), price(11.0), price(0.01)).unwrap()
+    }
+
+    #[test]
+    fn the_best_prices_follow_levels_opening_and_closing() {
+        let mut levels = ladder();
+        for (at, order) in [(10.01, 1), (9.5, 2), (10.5, 3), (10.01, 4)] {
+            levels.level_mut(price(at)).push_back(order);
+        }
+        assert_eq!(levels.keys().next(), Some(&price(9.5)));
+        assert_eq!(levels.keys().next_back(), Some(&price(10.5)));
+        assert_eq!(levels.get_mut(&price(10.01)).map(|queue| queue.len()), Some(2));
+
+        levels.remove(&price(9.5));
+        levels.remove(&price(10.5));
+        assert_eq!(levels.keys().collect::<Vec<_>>(), [&price(10.01)]);
+        levels.remove(&price(10.01));
+        assert!(levels.is_empty() && levels.iter().next().is_none());
+        assert_eq!(levels.remove(&price(10.01)), None);
+    }
+
+    #[test]
+    fn a_price_off_the_grid_or_out_of_range_moves_the_side_onto_a_tree() {
+        let mut levels = Levels::default().onto(ladder());
+        levels.level_mut(price(10.0)).push_back(1);
+        assert!(levels.is_ladder());
+
+        levels.level_mut(price(10.005)).push_back(2);
+        assert!(!levels.is_ladder());
+        assert_eq!(levels.keys().collect::<Vec<_>>(), [&price(10.0), &price(10.005)]);
+
+        let mut tree = BTreeMap::new();
+        tree.insert(price(12.0), VecDeque::from([3]));
+        assert!(!Levels::Tree(tree).onto(ladder()).is_ladder());
+    }
+
+    #[test]
+    fn a_ladder_needs_a_tick_and_a_range_it_can_hold() {
+        assert!(PriceLadder::<u64>::new(price(9.0), price(11.0), price(0.0)).is_err());
+        assert!(PriceLadder::<u64>::new(price(11.0), price(9.0), price(0.01)).is_err());
+        assert!(PriceLadder::<u64>::new(price(0.0), price(1e6), price(0.0001)).is_err());
+    }
+}
@@ -0,0 +1,734 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use fefix::fix_values::Timestamp;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+use crate::audit::{RecentOrders, TradeQuery, MAX_HISTORY_PAGE};
+use crate::clock::timestamp_now;
+use crate::compaction::CompactionStats;
+use crate::config::LiveConfig;
+use crate::credentials::{constant_time_eq, Credentials};
+use crate::engine::{EngineMessage, extract_client_id};
+use crate::execution_quality::ExecutionStatistics;
+use crate::inbound::InboundSender;
+use crate::namespace::NamespaceViews;
+use crate::overload::{CancelOnlyMode, OverloadGuard};
+use crate::supervisor::EngineHealth;
+use crate::surveillance::SurveillanceReport;
+use crate::types::{AccountID, ClientID, Namespace};
+
+// Largest request body accepted
+const MAX_BODY_BYTES: usize = 64 * 1024;
+// How long a request waits for the engine before giving up
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+// Recent orders returned when the request names no limit, and the most it may ask for
+const DEFAULT_RECENT_ORDERS: usize = 100;
+const MAX_RECENT_ORDERS: usize = 10_000;
+
+// REST requests waiting on the engine, by the client they submitted for. The outbound
+// stage hands each one the JSON of its client's events from the next batch.
+static PENDING_RESPONSES: OnceLock<DashMap<ClientID, oneshot::Sender<String>>> = OnceLock::new();
+
+pub(crate) fn pending_responses() -> &'static DashMap<ClientID, oneshot::Sender<String>> {
+    PENDING_RESPONSES.get_or_init(DashMap::new)
+}
+
+// Serves JSON over HTTP/1.1 for clients that don't speak FIX, one request per connection:
+//   POST /orders                body: a new_order EngineMessage  ->  the engine's events for that client
+//   GET  /orders/recent?limit=N ->  the last N accepted orders, oldest first, read without the engine
+//   GET  /health                ->  whether the engine is running, 503 once it has stopped
+//   POST /admin/credentials/reload  ->  rereads the credentials file; later logons are checked against it
+//   POST /admin/config/reload   ->  rereads the server config file, saying which settings took effect and which need a restart
+//   GET  /admin/surveillance    ->  every wash-trade and spoofing flag raised so far, oldest first
+//   GET  /admin/books           ->  every book's levels and orders as last published, read without the engine
+//   GET  /admin/statistics      ->  this session's effective spread and price improvement, by instrument and by client
+//   POST /admin/compact         ->  runs one compaction pass in the engine and says what it gave back
+//   GET  /admin/compaction      ->  what compaction has given back since the exchange started
+//   GET  /admin/overload        ->  whether new orders are refused as cancel-only, why, and how often they have been
+//   POST /admin/overload?mode=on  ->  forces cancel-only on or off, or with mode=auto leaves it to the queue's depth
+//   POST /admin/admin-flag      body: {"client_id": {...}, "admin": true}  ->  lets that client manage every account, or stops it
+//   POST /admin/accounts        body: {"comp_id": "FIRM1", "account_id": "ACC3", "authorized": true, "namespace": "UAT"}  ->  adds or removes an account a CompID may trade
+//   GET  /admin/rejections?since=20240102-14:30:00.000  ->  every order rejection from then on, oldest first, with what was asked for, and every inbound message that could not be read
+//   GET  /admin/trades?from=...&to=...&instrument=AAPL&account=ACC1&after=N&limit=N  ->  a page of logged trades in [from, to], oldest first
+//   GET  /admin/order-history?order_id=N&after=N&limit=N  ->  a page of an order's reports, with the status each left it in and what caused it
+//   POST /admin/statements?account=ACC1  ->  the statement of that account, or of every account, for the session so far, each also written out as CSV
+// Admin requests answered by the engine, and /admin/books, take ?namespace=UAT to act on that
+// namespace rather than the default one; bodies naming a client_id give it a "namespace" instead.
+// Pages say where the next one starts as "next", which goes back as "after"; a page holds at most 1000.
+// fefix enums and timestamps use their FIX text, e.g. "side": "1", "sending_time": "20240102-14:30:00.000".
+// /admin requests are refused with 401 unless they carry the config's admin_token as a bearer token.
+// The API is for operators and the exchange's own tools: POST /orders trades as whatever client_id
+// its body names, so rest_address stays on loopback unless something that authenticates sits in front.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    listener: TcpListener,
+    tx: InboundSender,
+    recent_orders: Arc<RecentOrders>,
+    health: Arc<EngineHealth>,
+    credentials: Arc<Credentials>,
+    config: Arc<LiveConfig>,
+    surveillance: Arc<SurveillanceReport>,
+    book_views: Arc<NamespaceViews>,
+    statistics: Arc<ExecutionStatistics>,
+    compaction: Arc<CompactionStats>,
+    overload: Arc<OverloadGuard>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let tx = tx.clone();
+                let recent_orders = Arc::clone(&recent_orders);
+                let health = Arc::clone(&health);
+                let credentials = Arc::clone(&credentials);
+                let config = Arc::clone(&config);
+                let surveillance = Arc::clone(&surveillance);
+                let book_views = Arc::clone(&book_views);
+                let statistics = Arc::clone(&statistics);
+                let compaction = Arc::clone(&compaction);
+                let overload = Arc::clone(&overload);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_request(stream, tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await {
+                        eprintln!("REST request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("REST connection failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+// Completes the REST requests addressed by a batch of outbound events
+pub fn deliver_responses(batch: &[EngineMessage]) {
+    let pending = pending_responses();
+    if pending.is_empty() {
+        return;
+    }
+    let mut responses: Vec<(ClientID, Vec<&EngineMessage>)> = Vec::new();
+    for message in batch {
+        let Some(client_id) = extract_client_id(message) else { continue };
+        if !pending.contains_key(&client_id) {
+            continue;
+        }
+        match responses.iter_mut().find(|(waiting, _)| *waiting == client_id) {
+            Some((_, messages)) => messages.push(message),
+            None => responses.push((client_id, vec![message])),
+        }
+    }
+    for (client_id, messages) in responses {
+        if let Some((_, waiter)) = pending.remove(&client_id) {
+            let json = serde_json::to_string(&messages).unwrap_or_else(|e| error_body(&e.to_string()));
+            let _ = waiter.send(json);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_request(
+    stream: TcpStream,
+    tx: InboundSender,
+    recent_orders: &RecentOrders,
+    health: &EngineHealth,
+    credentials: &Credentials,
+    config: &LiveConfig,
+    surveillance: &SurveillanceReport,
+    book_views: &NamespaceViews,
+    statistics: &ExecutionStatistics,
+    compaction: &CompactionStats,
+    overload: &OverloadGuard,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let (status, body) = if content_length > MAX_BODY_BYTES {
+        ("413 Payload Too Large", error_body("request body too large"))
+    } else if is_admin_path(path) && !admin_authorized(authorization.as_deref(), config) {
+        ("401 Unauthorized", error_body("admin requests need the admin token"))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await?;
+        route(method, path, &body, &tx, recent_orders, health, credentials, config, surveillance, book_views, statistics, compaction, overload).await
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+fn is_admin_path(target: &str) -> bool {
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+// Never while no admin_token is configured
+fn admin_authorized(authorization: Option<&str>, config: &LiveConfig) -> bool {
+    let Some(token) = config.snapshot().admin_token.clone() else { return false };
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn route(
+    method: &str,
+    target: &str,
+    body: &[u8],
+    tx: &InboundSender,
+    recent_orders: &RecentOrders,
+    health: &EngineHealth,
+    credentials: &Credentials,
+    config: &LiveConfig,
+    surveillance: &SurveillanceReport,
+    book_views: &NamespaceViews,
+    statistics: &ExecutionStatistics,
+    compaction: &CompactionStats,
+    overload: &OverloadGuard,
+) -> (&'static str, String) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    match (method, path) {
+        ("POST", "/orders") => submit_order(body, tx).await,
+        (_, "/orders") => ("405 Method Not Allowed", error_body("use POST")),
+        ("GET", "/orders/recent") => list_recent_orders(query, recent_orders),
+        (_, "/orders/recent") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/health") => report_health(health),
+        (_, "/health") => ("405 Method Not Allowed", error_body("use GET")),
+        ("POST", "/admin/credentials/reload") => reload_credentials(credentials),
+        (_, "/admin/credentials/reload") => ("405 Method Not Allowed", error_body("use POST")),
+        ("POST", "/admin/config/reload") => reload_config(config),
+        (_, "/admin/config/reload") => ("405 Method Not Allowed", error_body("use POST")),
+        ("GET", "/admin/surveillance") => list_surveillance_flags(surveillance),
+        (_, "/admin/surveillance") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/admin/books") => dump_books(query, book_views),
+        (_, "/admin/books") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/admin/statistics") => report_statistics(statistics),
+        (_, "/admin/statistics") => ("405 Method Not Allowed", error_body("use GET")),
+        ("POST", "/admin/compact") => compact(query, tx).await,
+        (_, "/admin/compact") => ("405 Method Not Allowed", error_body("use POST")),
+        ("GET", "/admin/compaction") => report_compaction(compaction),
+        (_, "/admin/compaction") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/admin/overload") => report_overload(overload),
+        ("POST", "/admin/overload") => set_cancel_only_mode(query, overload),
+        (_, "/admin/overload") => ("405 Method Not Allowed", error_body("use GET or POST")),
+        ("POST", "/admin/admin-flag") => set_admin_flag(body, tx).await,
+        (_, "/admin/admin-flag") => ("405 Method Not Allowed", error_body("use POST")),
+        ("POST", "/admin/accounts") => set_account_authorization(body, tx).await,
+        (_, "/admin/accounts") => ("405 Method Not Allowed", error_body("use POST")),
+        ("GET", "/admin/rejections") => list_rejections(query, tx).await,
+        (_, "/admin/rejections") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/admin/trades") => list_trades(query, tx).await,
+        (_, "/admin/trades") => ("405 Method Not Allowed", error_body("use GET")),
+        ("GET", "/admin/order-history") => list_order_history(query, tx).await,
+        (_, "/admin/order-history") => ("405 Method Not Allowed", error_body("use GET")),
+        ("POST", "/admin/statements") => generate_statements(query, tx).await,
+        (_, "/admin/statements") => ("405 Method Not Allowed", error_body("use POST")),
+        _ => ("404 Not Found", error_body("no such endpoint")),
+    }
+}
+
+fn list_recent_orders(query: &str, recent_orders: &RecentOrders) -> (&'static str, String) {
+    let limit = match query.split('&').find_map(|pair| pair.strip_prefix("limit=")) {
+        None => DEFAULT_RECENT_ORDERS,
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) if limit <= MAX_RECENT_ORDERS => limit,
+            _ => return ("400 Bad Request", error_body(&format!("limit must be a number up to {}", MAX_RECENT_ORDERS))),
+        },
+    };
+    let orders: Vec<_> = recent_orders.iter_recent(limit).collect();
+    match serde_json::to_string(&orders) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn report_health(health: &EngineHealth) -> (&'static str, String) {
+    let status = health.status();
+    let code = if status.running { "200 OK" } else { "503 Service Unavailable" };
+    match serde_json::to_string(&status) {
+        Ok(json) => (code, json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn list_surveillance_flags(surveillance: &SurveillanceReport) -> (&'static str, String) {
+    match serde_json::to_string(&surveillance.flags()) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn dump_books(query: &str, book_views: &NamespaceViews) -> (&'static str, String) {
+    let Some(book_views) = book_views.get(query_param(query, "namespace")) else {
+        return ("404 Not Found", error_body("no such namespace"));
+    };
+    match serde_json::to_string(&*book_views.latest()) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn report_statistics(statistics: &ExecutionStatistics) -> (&'static str, String) {
+    match serde_json::to_string(&statistics.session()) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn report_compaction(compaction: &CompactionStats) -> (&'static str, String) {
+    match serde_json::to_string(&compaction.totals()) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+fn report_overload(overload: &OverloadGuard) -> (&'static str, String) {
+    match serde_json::to_string(&overload.status()) {
+        Ok(json) => ("200 OK", json),
+        Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+    }
+}
+
+// Sessions hear of the change with the watcher's next sample
+fn set_cancel_only_mode(query: &str, overload: &OverloadGuard) -> (&'static str, String) {
+    let mode = match query_param(query, "mode") {
+        Some("on") => CancelOnlyMode::On,
+        Some("off") => CancelOnlyMode::Off,
+        Some("auto") => CancelOnlyMode::Auto,
+        _ => return ("400 Bad Request", error_body("mode must be on, off or auto")),
+    };
+    overload.set_mode(mode);
+    report_overload(overload)
+}
+
+fn reload_credentials(credentials: &Credentials) -> (&'static str, String) {
+    match credentials.reload() {
+        Ok(comp_ids) => ("200 OK", serde_json::json!({ "comp_ids": comp_ids }).to_string()),
+        // A file that no longer loads leaves the credentials in force untouched
+        Err(e) => ("500 Internal Server Error", error_body(&e)),
+    }
+}
+
+fn reload_config(config: &LiveConfig) -> (&'static str, String) {
+    match config.reload() {
+        Ok(report) => ("200 OK", serde_json::to_string(&report).unwrap()),
+        Err(e) => ("500 Internal Server Error", error_body(&e)),
+    }
+}
+
+async fn submit_order(body: &[u8], tx: &InboundSender) -> (&'static str, String) {
+    let mut message = match serde_json::from_slice::<EngineMessage>(body) {
+        Ok(message) => message,
+        Err(e) => return ("400 Bad Request", error_body(&e.to_string())),
+    };
+    let EngineMessage::NewOrder { receiving_time, client_id, .. } = &mut message else {
+        return ("400 Bad Request", error_body("expected a new_order message"));
+    };
+    // Stamped on arrival like FIX orders; whatever the client sent is ignored
+    *receiving_time = timestamp_now();
+    let client_id = client_id.clone();
+    send_and_wait(message, client_id, tx).await
+}
+
+async fn compact(query: &str, tx: &InboundSender) -> (&'static str, String) {
+    let client_id = admin(query);
+    send_and_wait(EngineMessage::Compact { client_id: Some(client_id.clone()) }, client_id, tx).await
+}
+
+#[derive(Deserialize)]
+struct AdminFlag {
+    client_id: ClientID,
+    admin: bool,
+}
+
+// The only way in for SetAdminFlag; FIX sessions cannot send it
+async fn set_admin_flag(body: &[u8], tx: &InboundSender) -> (&'static str, String) {
+    let AdminFlag { client_id, admin } = match serde_json::from_slice(body) {
+        Ok(flag) => flag,
+        Err(e) => return ("400 Bad Request", error_body(&e.to_string())),
+    };
+    send_and_wait(EngineMessage::SetAdminFlag { client_id: client_id.clone(), admin }, client_id, tx).await
+}
+
+#[derive(Deserialize)]
+struct AccountAuthorization {
+    comp_id: String,
+    account_id: AccountID,
+    authorized: bool,
+    #[serde(default)]
+    namespace: Option<Namespace>,
+}
+
+async fn set_account_authorization(body: &[u8], tx: &InboundSender) -> (&'static str, String) {
+    let AccountAuthorization { comp_id, account_id, authorized, namespace } = match serde_json::from_slice(body) {
+        Ok(authorization) => authorization,
+        Err(e) => return ("400 Bad Request", error_body(&e.to_string())),
+    };
+    let client_id = ClientID::new(comp_id, None).in_namespace(namespace);
+    send_and_wait(EngineMessage::SetAccountAuthorization { client_id: client_id.clone(), account_id, authorized }, client_id, tx).await
+}
+
+// The only way in for RejectionLogQuery; FIX sessions cannot send it
+async fn list_rejections(query: &str, tx: &InboundSender) -> (&'static str, String) {
+    let since = match query.split('&').find_map(|pair| pair.strip_prefix("since=")) {
+        Some(since) => match Timestamp::parse(since.as_bytes()) {
+            Some(since) => since,
+            None => return ("400 Bad Request", error_body("since must be a FIX timestamp, e.g. 20240102-14:30:00.000")),
+        },
+        None => return ("400 Bad Request", error_body("since is required")),
+    };
+    let client_id = admin(query);
+    let query = EngineMessage::RejectionLogQuery { sending_time: timestamp_now(), receiving_time: timestamp_now(), client_id: client_id.clone(), since };
+    send_and_wait(query, client_id, tx).await
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+// Who the engine hears an admin request from, in the namespace it names
+fn admin(query: &str) -> ClientID {
+    ClientID::new("ADMIN".to_string(), None).in_namespace(query_param(query, "namespace").map(str::to_string))
+}
+
+// The optional `after` and `limit` of a history page, or why they won't do
+fn page_params<C: std::str::FromStr>(query: &str) -> Result<(Option<C>, Option<usize>), String> {
+    let after = match query_param(query, "after") {
+        None => None,
+        Some(after) => Some(after.parse::<C>().map_err(|_| "after must be the next of an earlier page".to_string())?),
+    };
+    let limit = match query_param(query, "limit") {
+        None => None,
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) if (1..=MAX_HISTORY_PAGE).contains(&limit) => Some(limit),
+            _ => return Err(format!("limit must be a number from 1 to {}", MAX_HISTORY_PAGE)),
+        },
+    };
+    Ok((after, limit))
+}
+
+async fn list_trades(query: &str, tx: &InboundSender) -> (&'static str, String) {
+    let timestamp = |name: &str| match query_param(query, name) {
+        Some(time) => Timestamp::parse(time.as_bytes()).ok_or_else(|| format!("{} must be a FIX timestamp, e.g. 20240102-14:30:00.000", name)),
+        None => Err(format!("{} is required", name)),
+    };
+    let (from, to) = match (timestamp("from"), timestamp("to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return ("400 Bad Request", error_body(&e)),
+    };
+    let (after, limit) = match page_params(query) {
+        Ok(page) => page,
+        Err(e) => return ("400 Bad Request", error_body(&e)),
+    };
+    let client_id = admin(query);
+    let query = TradeQuery {
+        instrument_id: query_param(query, "instrument").map(str::to_string),
+        account_id: query_param(query, "account").map(str::to_string),
+        from,
+        to,
+        after,
+        limit,
+    };
+    let query = EngineMessage::TradeHistoryQuery { sending_time: timestamp_now(), receiving_time: timestamp_now(), client_id: client_id.clone(), query };
+    send_and_wait(query, client_id, tx).await
+}
+
+async fn list_order_history(query: &str, tx: &InboundSender) -> (&'static str, String) {
+    let Some(order_id) = query_param(query, "order_id").and_then(|order_id| order_id.parse().ok()) else {
+        return ("400 Bad Request", error_body("order_id must be an OrderID"));
+    };
+    let (after, limit) = match page_params(query) {
+        Ok(page) => page,
+        Err(e) => return ("400 Bad Request", error_body(&e)),
+    };
+    let client_id = admin(query);
+    let query = EngineMessage::OrderHistoryQuery { sending_time: timestamp_now(), receiving_time: timestamp_now(), client_id: client_id.clone(), order_id, after, limit };
+    send_and_wait(query, client_id, tx).await
+}
+
+async fn generate_statements(query: &str, tx: &InboundSender) -> (&'static str, String) {
+    let client_id = admin(query);
+    let account_id = query_param(query, "account").map(str::to_string);
+    let request = EngineMessage::StatementRequest { sending_time: timestamp_now(), receiving_time: timestamp_now(), client_id: client_id.clone(), account_id };
+    send_and_wait(request, client_id, tx).await
+}
+
+// Sends `message` to the engine and answers with its events for `client_id` from the next batch
+async fn send_and_wait(message: EngineMessage, client_id: ClientID, tx: &InboundSender) -> (&'static str, String) {
+    let (waiter, response) = oneshot::channel();
+    match pending_responses().entry(client_id.clone()) {
+        dashmap::Entry::Occupied(_) => return ("409 Conflict", error_body("a request for this client is already in flight")),
+        dashmap::Entry::Vacant(entry) => {
+            entry.insert(waiter);
+        }
+    }
+    if tx.send(message).is_err() {
+        pending_responses().remove(&client_id);
+        return ("503 Service Unavailable", error_body("exchange is not running"));
+    }
+    match tokio::time::timeout(RESPONSE_TIMEOUT, response).await {
+        Ok(Ok(json)) => ("200 OK", json),
+        _ => {
+            pending_responses().remove(&client_id);
+            ("504 Gateway Timeout", error_body("no response from the exchange"))
+        }
+    }
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use fefix::definitions::fix50::*;
+
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::exchange::Exchange;
+    use crate::namespace::Namespaces;
+    use crate::inbound::inbound_channel;
+    use crate::instrument::SpecOverrides;
+    use crate::types::*;
+
+    #[test]
+    fn new_order_json_round_trips() {
+        let order = EngineMessage::NewOrder {
+            sending_time: Timestamp::parse(b"20240102-14:30:00.125").unwrap(),
+            receiving_time: Timestamp::parse(b"20240102-14:30:00.126").unwrap(),
+            client_id: ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())),
+            account_id: "ACC1".to_string(),
+            client_order_id: Some("CLIENT-7".to_string()),
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side: Side::Buy,
+            quantity: 100,
+            price: Some(Price::from(101.25)),
+            time_in_force: Some(TimeInForce::GoodTillDate),
+            expire_time: Some(Timestamp::parse(b"20240105-21:00:00.000").unwrap()),
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        };
+        let json = serde_json::to_string(&order).unwrap();
+        assert!(json.contains(r#""type":"new_order""#), "{}", json);
+        assert!(json.contains(r#""side":"1""#), "{}", json);
+        assert!(json.contains(r#""sending_time":"20240102-14:30:00.125""#), "{}", json);
+
+        let parsed: EngineMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", order));
+    }
+
+    #[tokio::test]
+    async fn only_new_orders_are_accepted() {
+        let (tx, _rx) = inbound_channel(true);
+        let recent_orders = RecentOrders::new();
+        let health = EngineHealth::default();
+        let credentials = Credentials::open();
+        let config = LiveConfig::fixed(ServerConfig::default());
+        let surveillance = SurveillanceReport::default();
+        let book_views = Namespaces::new(Exchange::new()).book_views();
+        let statistics = ExecutionStatistics::new();
+        let compaction = CompactionStats::default();
+        let overload = OverloadGuard::default();
+        let cancel = r#"{"type":"cancel_order","sending_time":"20240102-14:30:00.000","client_id":{"comp_id":"WEB"},"account_id":"WEB","order_id":1}"#;
+        assert_eq!(route("POST", "/orders", cancel.as_bytes(), &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        assert_eq!(route("POST", "/orders", b"not json", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        assert_eq!(route("GET", "/orders", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+        assert_eq!(route("POST", "/trades", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "404 Not Found");
+        let flags = route("GET", "/admin/surveillance", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(flags, ("200 OK", "[]".to_string()));
+        let (status, books) = route("GET", "/admin/books", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(status, "200 OK");
+        let books: serde_json::Value = serde_json::from_str(&books).unwrap();
+        assert_eq!((books["sequence"].as_u64(), books["books"].as_object().map(|books| books.len())), (Some(0), Some(0)));
+        assert_eq!(route("POST", "/admin/admin-flag", br#"{"admin":true}"#, &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        assert_eq!(route("POST", "/admin/accounts", br#"{"comp_id":"FIRM1","authorized":true}"#, &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        assert_eq!(route("GET", "/admin/admin-flag", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+        assert_eq!(route("GET", "/admin/statements", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+        let reload = route("POST", "/admin/config/reload", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(reload, ("500 Internal Server Error", error_body("no config file configured")));
+        assert_eq!(route("GET", "/admin/rejections?since=yesterday", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        for target in [
+            "/admin/trades?from=20240102-14:00:00.000",
+            "/admin/trades?from=20240102-14:00:00.000&to=20240102-15:00:00.000&limit=1001",
+            "/admin/order-history?order_id=first",
+            "/admin/order-history?order_id=1&after=-1",
+        ] {
+            assert_eq!(route("GET", target, b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request", "{}", target);
+        }
+        assert_eq!(route("POST", "/admin/overload?mode=maybe", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        let forced = route("POST", "/admin/overload?mode=on", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(forced, ("200 OK", r#"{"cancel_only":true,"overloaded":false,"mode":"on","episodes":0}"#.to_string()));
+        assert!(overload.cancel_only());
+        assert_eq!(route("DELETE", "/admin/overload", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+        let statistics = route("GET", "/admin/statistics", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(statistics, ("200 OK", r#"{"by_instrument":{},"by_client":{}}"#.to_string()));
+    }
+
+    #[tokio::test]
+    async fn health_turns_unavailable_once_the_engine_stops() {
+        let (tx, _rx) = inbound_channel(true);
+        let recent_orders = RecentOrders::new();
+        let health = EngineHealth::default();
+        let credentials = Credentials::open();
+        let config = LiveConfig::fixed(ServerConfig::default());
+        let surveillance = SurveillanceReport::default();
+        let book_views = Namespaces::new(Exchange::new()).book_views();
+        let statistics = ExecutionStatistics::new();
+        let compaction = CompactionStats::default();
+        let overload = OverloadGuard::default();
+        let running = health.start();
+        let (status, json) = route("GET", "/health", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!((status, json.as_str()), ("200 OK", r#"{"running":true,"recovered_panics":0}"#));
+
+        drop(running);
+        assert_eq!(route("GET", "/health", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "503 Service Unavailable");
+        assert_eq!(route("POST", "/health", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+    }
+
+    #[tokio::test]
+    async fn recent_orders_are_listed_oldest_first_up_to_the_limit() {
+        let (tx, _rx) = inbound_channel(true);
+        let (exchange_tx, mut exchange_rx) = inbound_channel(true);
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("ADMIN".to_string(), None),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+        for quantity in 1..=3 {
+            let body = format!(r#"{{"type":"new_order","sending_time":"20240102-14:30:00.000","receiving_time":"20240102-14:30:00.000","client_id":{{"comp_id":"WEB"}},"account_id":"WEB","instrument_id":"AAPL","order_type":"2","side":"1","quantity":{},"price":1.0}}"#, quantity);
+            exchange_tx.send(serde_json::from_str(&body).unwrap()).unwrap();
+            exchange.handle_message(exchange_rx.recv().await.unwrap().0);
+        }
+        let recent_orders = exchange.recent_orders();
+        let health = EngineHealth::default();
+        let credentials = Credentials::open();
+        let config = LiveConfig::fixed(ServerConfig::default());
+        let surveillance = SurveillanceReport::default();
+        let book_views = Namespaces::new(Exchange::new()).book_views();
+        let statistics = ExecutionStatistics::new();
+        let compaction = CompactionStats::default();
+        let overload = OverloadGuard::default();
+
+        let (status, json) = route("GET", "/orders/recent?limit=2", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(status, "200 OK");
+        let orders: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(orders.as_array().unwrap().len(), 2);
+        assert_eq!(orders[0]["quantity"], 2);
+        assert_eq!(orders[1]["order_id"], 3);
+        assert_eq!(orders[1]["side"], "1");
+        assert_eq!(orders[1]["received"], "20240102-14:30:00.000");
+
+        let (_, json) = route("GET", "/orders/recent", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(route("GET", "/orders/recent?limit=lots", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "400 Bad Request");
+        assert_eq!(route("POST", "/orders/recent", b"", &tx, &recent_orders, &health, &credentials, &config, &surveillance, &book_views, &statistics, &compaction, &overload).await.0, "405 Method Not Allowed");
+    }
+
+    #[tokio::test]
+    async fn post_orders_returns_the_engine_events() {
+        let (tx, mut rx) = inbound_channel(true);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let exchange = Exchange::new();
+        tokio::spawn(serve(listener, tx, exchange.recent_orders(), EngineHealth::new(), Credentials::open(), LiveConfig::fixed(ServerConfig::default()), SurveillanceReport::new(), Namespaces::new(Exchange::new()).book_views(), exchange.execution_statistics(), exchange.compaction_stats(), OverloadGuard::new()));
+
+        // Stand-in for the consumer and outbound stages
+        tokio::spawn(async move {
+            let mut exchange = exchange;
+            exchange.handle_message(EngineMessage::CreateInstrument {
+                sending_time: Timestamp::utc_now(),
+                receiving_time: Timestamp::utc_now(),
+                client_id: ClientID::new("ADMIN".to_string(), None),
+                instrument_id: "AAPL".to_string(),
+                segment: None,
+                spec: SpecOverrides::default(),
+                spread: None,
+            });
+            while let Some((message, _)) = rx.recv().await {
+                deliver_responses(&exchange.handle_message(message));
+            }
+        });
+
+        let body = r#"{"type":"new_order","sending_time":"20240102-14:30:00.000","client_id":{"comp_id":"WEB"},"account_id":"WEB","instrument_id":"AAPL","order_type":"2","side":"1","quantity":5,"price":10.0}"#;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("POST /orders HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let (_, json) = response.split_once("\r\n\r\n").unwrap();
+        let events: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(events[0]["type"], "order_accepted");
+        assert_eq!(events[0]["client_id"]["comp_id"], "WEB");
+        assert_eq!(events.as_array().unwrap().len(), 1);
+    }
+
+    async fn exchange_http(address: std::net::SocketAddr, request: String) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn admin_requests_without_the_admin_token_are_refused() {
+        let (tx, _rx) = inbound_channel(true);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let exchange = Exchange::new();
+        let config = LiveConfig::fixed(ServerConfig { admin_token: Some("s3cret".to_string()), ..ServerConfig::default() });
+        tokio::spawn(serve(listener, tx, exchange.recent_orders(), EngineHealth::new(), Credentials::open(), config, SurveillanceReport::new(), Namespaces::new(Exchange::new()).book_views(), exchange.execution_statistics(), exchange.compaction_stats(), OverloadGuard::new()));
+
+        let body = r#"{"comp_id":"FIRM1","account_id":"ACC3","authorized":true}"#;
+        let unauthenticated = exchange_http(address, format!("POST /admin/accounts HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)).await;
+        assert!(unauthenticated.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", unauthenticated);
+        let wrong_token = exchange_http(address, "POST /admin/overload?mode=on HTTP/1.1\r\nAuthorization: Bearer guess\r\n\r\n".to_string()).await;
+        assert!(wrong_token.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", wrong_token);
+        let authenticated = exchange_http(address, "GET /admin/surveillance HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n".to_string()).await;
+        assert!(authenticated.starts_with("HTTP/1.1 200 OK\r\n"), "{}", authenticated);
+
+        // With no token configured there is no way in at all
+        assert!(!admin_authorized(Some("Bearer "), &LiveConfig::fixed(ServerConfig::default())));
+        assert!(!is_admin_path("/administrator") && is_admin_path("/admin/books?namespace=UAT"));
+    }
+}
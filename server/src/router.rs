@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::engine::EngineMessage;
+use crate::types::*;
+
+// Tickers each client trades under, by ClientID ("FIRM1" or "FIRM1::ALGO"), e.g.
+//   [clients.FIRM1]
+//   APPLE = "AAPL"
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TickerMapConfig {
+    #[serde(default)]
+    clients: HashMap<String, HashMap<String, InstrumentID>>,
+}
+
+// Client-facing tickers and the canonical instruments they stand for, so clients can name
+// the same instrument differently. A ticker a client has no mapping for is taken as canonical.
+#[derive(Debug, Clone, Default)]
+pub struct TickerMap {
+    client_ticker_to_canonical: HashMap<(ClientID, String), InstrumentID>,
+}
+
+impl TickerMap {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: TickerMapConfig = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut map = Self::default();
+        for (client, tickers) in config.clients {
+            let client_id = match client.split_once("::") {
+                Some((comp_id, sub_id)) => ClientID::new(comp_id.to_string(), Some(sub_id.to_string())),
+                None => ClientID::new(client, None),
+            };
+            for (ticker, instrument_id) in tickers {
+                map.insert(client_id.clone(), ticker, instrument_id);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn insert(&mut self, client_id: ClientID, ticker: String, instrument_id: InstrumentID) {
+        self.client_ticker_to_canonical.insert((client_id, ticker), instrument_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.client_ticker_to_canonical.is_empty()
+    }
+
+    // Rewrites the instrument a client message names to its canonical ID. Admin messages
+    // (instrument setup, corporate actions, limits) already use canonical IDs.
+    pub fn translate(&self, message: &mut EngineMessage) {
+        if self.is_empty() {
+            return;
+        }
+        let canonical = |client_id: &ClientID, instrument_id: &mut InstrumentID| {
+            if let Some(canonical) = self.client_ticker_to_canonical.get(&(client_id.clone(), instrument_id.clone())) {
+                *instrument_id = canonical.clone();
+            }
+        };
+        // Each leg names an instrument of its own
+        if let EngineMessage::NewOrderMultileg { client_id, legs, .. } = message {
+            for leg in legs {
+                canonical(client_id, &mut leg.instrument_id);
+            }
+            return;
+        }
+        let (client_id, instrument_ids) = match message {
+            EngineMessage::NewOrder { client_id, instrument_id, .. }
+            | EngineMessage::Snapshot { client_id, instrument_id, .. }
+            | EngineMessage::RequestReplay { client_id, instrument_id, .. }
+            | EngineMessage::ActivityRequest { client_id, instrument_id, .. }
+            | EngineMessage::InboundExecutionReport { client_id, instrument_id, .. } => (client_id, std::slice::from_mut(instrument_id)),
+            // A wildcard is left as it is, and matches canonical IDs
+            EngineMessage::SubscribeOrderBook { client_id, symbols, .. }
+            | EngineMessage::UnsubscribeOrderBook { client_id, symbols, .. } => (client_id, symbols.as_mut_slice()),
+            _ => return,
+        };
+        for instrument_id in instrument_ids {
+            canonical(client_id, instrument_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_reads_tickers_per_client_and_sub_id() {
+        let path = std::env::temp_dir().join(format!("tickers-{}.toml", std::process::id()));
+        std::fs::write(&path, "[clients.FIRM1]\nAPPLE = \"AAPL\"\n[clients.\"FIRM2::ALGO\"]\n\"AAPL.O\" = \"AAPL\"\n").unwrap();
+        let map = TickerMap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let canonical = |comp_id: &str, sub_id: Option<&str>, ticker: &str| {
+            let client_id = ClientID::new(comp_id.to_string(), sub_id.map(str::to_string));
+            map.client_ticker_to_canonical.get(&(client_id, ticker.to_string())).cloned()
+        };
+        assert_eq!(canonical("FIRM1", None, "APPLE").as_deref(), Some("AAPL"));
+        assert_eq!(canonical("FIRM2", Some("ALGO"), "AAPL.O").as_deref(), Some("AAPL"));
+        assert_eq!(canonical("FIRM2", None, "AAPL.O"), None);
+    }
+}
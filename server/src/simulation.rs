@@ -0,0 +1,540 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{Datelike, Timelike};
+use fefix::definitions::fix50::*;
+use fefix::fix_values::{Date, Time, Timestamp};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::clock::ExchangeTime;
+use crate::engine::{CancelReason, EngineMessage, extract_client_id};
+use crate::inbound::InboundSender;
+use crate::instrument::SpecOverrides;
+use crate::types::*;
+
+// How many recent trade prices the agents remember
+const TRADE_HISTORY: usize = 256;
+
+// Events for the running simulation's agents, filled in by the outbound stage
+static SIMULATION_EVENTS: OnceLock<(HashSet<ClientID>, UnboundedSender<EngineMessage>)> = OnceLock::new();
+
+// A simulation as configured in TOML (see agents.toml):
+//   seed = 7
+//   initial_price = 100.0
+//   volatility = 0.001
+//   [[market_maker]]
+//   name = "MM1"
+//   half_spread = 0.05
+//   quantity = 2
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    #[serde(default = "default_instrument")]
+    pub instrument: InstrumentID,
+    #[serde(default = "default_start_time")]
+    pub start_time: String,
+    #[serde(default = "default_tick_millis")]
+    pub tick_millis: u64, // simulated time per tick
+    #[serde(default)]
+    pub ticks: u64, // 0 = until the process exits
+    #[serde(default = "default_realtime")]
+    pub realtime: bool, // pace ticks to the wall clock
+    pub initial_price: f64,
+    pub volatility: f64, // largest fair price move per tick, as a fraction of the price
+    #[serde(default = "default_price_increment")]
+    pub price_increment: f64,
+    #[serde(default, rename = "market_maker")]
+    pub market_makers: Vec<MarketMakerConfig>,
+    #[serde(default, rename = "noise_trader")]
+    pub noise_traders: Vec<NoiseTraderConfig>,
+    #[serde(default, rename = "momentum_trader")]
+    pub momentum_traders: Vec<MomentumTraderConfig>,
+}
+
+// Requotes a bid and an ask around the fair price every tick
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketMakerConfig {
+    pub name: String,
+    pub half_spread: f64,
+    pub quantity: Quantity,
+}
+
+// Sends orders on either side at a Poisson rate
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoiseTraderConfig {
+    pub name: String,
+    pub orders_per_second: f64,
+    pub market_order_probability: f64, // the rest are limits up to max_offset either side of fair
+    pub max_quantity: Quantity,
+    pub max_offset: f64,
+    pub order_lifetime_millis: u64, // unfilled limits are cancelled after this long
+}
+
+// Buys when the last trade is above the recent average by more than `threshold`, sells when below
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MomentumTraderConfig {
+    pub name: String,
+    pub lookback: usize, // trades averaged
+    pub threshold: f64, // as a fraction of the average
+    pub quantity: Quantity,
+}
+
+fn default_instrument() -> InstrumentID {
+    "SIM".to_string()
+}
+
+fn default_start_time() -> String {
+    "20240102-14:30:00.000".to_string()
+}
+
+fn default_tick_millis() -> u64 {
+    100
+}
+
+fn default_realtime() -> bool {
+    true
+}
+
+fn default_price_increment() -> f64 {
+    0.01
+}
+
+impl SimulationConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[derive(Debug)]
+enum Strategy {
+    MarketMaker(MarketMakerConfig),
+    Noise { config: NoiseTraderConfig, next_arrival_millis: f64 },
+    Momentum(MomentumTraderConfig),
+}
+
+#[derive(Debug)]
+struct Agent {
+    client_id: ClientID,
+    generation: u32, // bumped each time the agent's account runs out of cash
+    strategy: Strategy,
+    live_orders: Vec<(OrderID, u64)>, // (order, simulated millis when placed)
+    unacknowledged: VecDeque<u64>, // placement times of orders sent but not yet accepted or rejected
+}
+
+impl Agent {
+    fn new(name: &str, strategy: Strategy) -> Self {
+        Self {
+            client_id: ClientID::new(name.to_string(), None),
+            generation: 0,
+            strategy,
+            live_orders: Vec::new(),
+            unacknowledged: VecDeque::new(),
+        }
+    }
+
+    fn account_id(&self) -> AccountID {
+        format!("{}-{}", self.client_id, self.generation)
+    }
+}
+
+// Synthetic agents trading one instrument, driven by a seeded RNG. Every decision is
+// made from the seed and the events the agents have seen, so replaying the same seed
+// against a quiet exchange sends the same messages.
+pub struct Simulation {
+    config: SimulationConfig,
+    rng: ChaCha8Rng,
+    start: chrono::NaiveDateTime,
+    elapsed_millis: u64,
+    fair_price: f64,
+    agents: Vec<Agent>,
+    recent_trades: VecDeque<Price>,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig) -> Result<Self, String> {
+        let start = Timestamp::parse(config.start_time.as_bytes())
+            .and_then(|start| start.to_chrono_naive())
+            .ok_or_else(|| format!("invalid start_time {:?}", config.start_time))?;
+        if config.tick_millis == 0 || config.initial_price <= 0.0 || config.price_increment <= 0.0 {
+            return Err("tick_millis, initial_price and price_increment must be positive".to_string());
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+        let mut agents = Vec::new();
+        agents.extend(config.market_makers.iter().map(|mm| Agent::new(&mm.name, Strategy::MarketMaker(mm.clone()))));
+        for noise in &config.noise_traders {
+            let first_arrival = exponential_millis(&mut rng, noise.orders_per_second);
+            agents.push(Agent::new(&noise.name, Strategy::Noise { config: noise.clone(), next_arrival_millis: first_arrival }));
+        }
+        agents.extend(config.momentum_traders.iter().map(|momentum| Agent::new(&momentum.name, Strategy::Momentum(momentum.clone()))));
+
+        let names: HashSet<&ClientID> = agents.iter().map(|agent| &agent.client_id).collect();
+        if names.len() != agents.len() {
+            return Err("agent names must be unique".to_string());
+        }
+
+        Ok(Self {
+            fair_price: config.initial_price,
+            config,
+            rng,
+            start,
+            elapsed_millis: 0,
+            agents,
+            recent_trades: VecDeque::new(),
+        })
+    }
+
+    // Where the simulated clock starts, before the first tick moves it on
+    pub(crate) fn start_time(&self) -> ExchangeTime {
+        self.start.and_utc()
+    }
+
+    pub fn agent_ids(&self) -> HashSet<ClientID> {
+        self.agents.iter().map(|agent| agent.client_id.clone()).collect()
+    }
+
+    fn now(&self) -> Timestamp {
+        let now = self.start + chrono::Duration::milliseconds(self.elapsed_millis as i64);
+        Timestamp::new(
+            Date::new(now.year() as u32, now.month(), now.day()).unwrap(),
+            Time::from_hmsm(now.hour(), now.minute(), now.second(), now.nanosecond() / 1_000_000).unwrap(),
+        )
+    }
+
+    fn round_price(&self, price: f64) -> Price {
+        // Dividing by the increments per unit keeps e.g. 100.6 from printing as 100.60000000000001
+        let increment = self.config.price_increment;
+        Price::from(((price / increment).round() / (1.0 / increment)).max(increment))
+    }
+
+    fn create_instrument(&self) -> EngineMessage {
+        EngineMessage::CreateInstrument {
+            sending_time: self.now(),
+            receiving_time: self.now(),
+            client_id: ClientID::new("SIM".to_string(), None),
+            instrument_id: self.config.instrument.clone(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        }
+    }
+
+    // Moves the clock and the fair price on by one tick
+    fn advance(&mut self) {
+        self.elapsed_millis += self.config.tick_millis;
+        let step = self.rng.gen_range(-1.0..=1.0) * self.config.volatility;
+        self.fair_price = (self.fair_price * (1.0 + step)).max(self.config.price_increment);
+    }
+
+    // The tick's cancels: market makers pull their quotes, noise traders their stale limits
+    fn cancels(&mut self) -> Vec<EngineMessage> {
+        let now = self.now();
+        let elapsed = self.elapsed_millis;
+        let mut cancels = Vec::new();
+        for agent in &mut self.agents {
+            let stale: Vec<OrderID> = match &agent.strategy {
+                Strategy::MarketMaker(_) => agent.live_orders.iter().map(|(order_id, _)| *order_id).collect(),
+                Strategy::Noise { config, .. } => agent.live_orders
+                    .iter()
+                    .filter(|(_, placed)| elapsed - placed >= config.order_lifetime_millis)
+                    .map(|(order_id, _)| *order_id)
+                    .collect(),
+                Strategy::Momentum(_) => Vec::new(),
+            };
+            // Forgotten right away: whatever the answer, the order is no longer the agent's concern
+            agent.live_orders.retain(|(order_id, _)| !stale.contains(order_id));
+            for order_id in stale {
+                cancels.push(EngineMessage::CancelOrder {
+                    sending_time: now.clone(),
+                    receiving_time: now.clone(),
+                    client_id: agent.client_id.clone(),
+                    account_id: agent.account_id(),
+                    order_id,
+                    orig_client_order_id: None,
+                    cancel_quantity: None,
+                });
+            }
+        }
+        cancels
+    }
+
+    // The tick's new orders, behind the clock advance that stamps them
+    fn orders(&mut self) -> Vec<EngineMessage> {
+        let mut messages = vec![EngineMessage::AdvanceTime {
+            sending_time: self.now(),
+            receiving_time: self.now(),
+            client_id: ClientID::new("SIM".to_string(), None),
+            timestamp: self.now(),
+        }];
+        let mut orders = Vec::new(); // (agent, side, quantity, limit price)
+        for index in 0..self.agents.len() {
+            match &self.agents[index].strategy {
+                Strategy::MarketMaker(config) => {
+                    let bid = self.round_price(self.fair_price - config.half_spread);
+                    let ask = self.round_price(self.fair_price + config.half_spread).max(bid + self.config.price_increment);
+                    orders.push((index, Side::Buy, config.quantity, Some(bid)));
+                    orders.push((index, Side::Sell, config.quantity, Some(ask)));
+                }
+                Strategy::Noise { config, next_arrival_millis } => {
+                    let config = config.clone();
+                    let mut next_arrival = *next_arrival_millis;
+                    while next_arrival <= self.elapsed_millis as f64 {
+                        let side = if self.rng.gen_bool(0.5) { Side::Buy } else { Side::Sell };
+                        let quantity = self.rng.gen_range(1..=config.max_quantity.max(1));
+                        let price = if self.rng.gen_bool(config.market_order_probability.clamp(0.0, 1.0)) {
+                            None
+                        } else {
+                            let offset = self.rng.gen_range(-config.max_offset..=config.max_offset);
+                            let passive = if side == Side::Buy { -offset } else { offset };
+                            Some(self.round_price(self.fair_price + passive))
+                        };
+                        orders.push((index, side, quantity, price));
+                        next_arrival += exponential_millis(&mut self.rng, config.orders_per_second);
+                    }
+                    if let Strategy::Noise { next_arrival_millis, .. } = &mut self.agents[index].strategy {
+                        *next_arrival_millis = next_arrival;
+                    }
+                }
+                Strategy::Momentum(config) => {
+                    if self.recent_trades.len() < config.lookback.max(1) {
+                        continue;
+                    }
+                    let window = self.recent_trades.iter().rev().take(config.lookback.max(1));
+                    let average = window.map(|price| price.into_inner()).sum::<f64>() / config.lookback.max(1) as f64;
+                    let last = self.recent_trades.back().unwrap().into_inner();
+                    if last > average * (1.0 + config.threshold) {
+                        orders.push((index, Side::Buy, config.quantity, None));
+                    } else if last < average * (1.0 - config.threshold) {
+                        orders.push((index, Side::Sell, config.quantity, None));
+                    }
+                }
+            }
+        }
+
+        let now = self.now();
+        for (index, side, quantity, price) in orders {
+            let agent = &mut self.agents[index];
+            agent.unacknowledged.push_back(self.elapsed_millis);
+            messages.push(EngineMessage::NewOrder {
+                sending_time: now.clone(),
+                receiving_time: now.clone(),
+                client_id: agent.client_id.clone(),
+                account_id: agent.account_id(),
+                client_order_id: None,
+                instrument_id: self.config.instrument.clone(),
+                order_type: if price.is_some() { OrdType::Limit } else { OrdType::Market },
+                side,
+                quantity,
+                price,
+                // A market order must not rest, and limits are cleaned up by their owners
+                time_in_force: Some(if price.is_some() { TimeInForce::GoodTillCancel } else { TimeInForce::ImmediateOrCancel }),
+                expire_time: None,
+                exchange_code: None,
+                good_for_auction: false,
+                stipulations: Vec::new(),
+            });
+        }
+        messages
+    }
+
+    // Lets the agents learn their order IDs, fills and the prices trades print at
+    fn observe(&mut self, event: &EngineMessage) {
+        if let EngineMessage::OrderFilled { price, .. } = event {
+            if self.recent_trades.len() == TRADE_HISTORY {
+                self.recent_trades.pop_front();
+            }
+            self.recent_trades.push_back(*price);
+        }
+        let Some(client_id) = extract_client_id(event) else { return };
+        let Some(agent) = self.agents.iter_mut().find(|agent| agent.client_id == client_id) else { return };
+        match event {
+            EngineMessage::OrderAccepted { order_id, .. } => {
+                if let Some(placed) = agent.unacknowledged.pop_front() {
+                    agent.live_orders.push((*order_id, placed));
+                }
+            }
+            EngineMessage::OrderRejected { reason, .. } => {
+                agent.unacknowledged.pop_front();
+                // The engine keeps no credit line, so a broke agent carries on in a fresh account
+                if reason == "Insufficient funds" {
+                    agent.generation += 1;
+                }
+            }
+            EngineMessage::OrderFilled { order_id, remaining_quantity: 0, .. }
+            | EngineMessage::OrderCancelled { order_id, .. } => {
+                agent.live_orders.retain(|(live, _)| live != order_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Waiting time to the next event of a Poisson process with `rate` events per second
+fn exponential_millis(rng: &mut ChaCha8Rng, rate: f64) -> f64 {
+    if rate <= 0.0 {
+        return f64::INFINITY;
+    }
+    let uniform: f64 = rng.gen_range(0.0..1.0);
+    -(1.0 - uniform).ln() / rate * 1000.0
+}
+
+// Whether an event is the engine's one answer to a message an agent sent
+fn answers_request(event: &EngineMessage) -> bool {
+    matches!(
+        event,
+        EngineMessage::OrderAccepted { .. }
+            | EngineMessage::OrderRejected { .. }
+            | EngineMessage::CancelRejected { .. }
+            | EngineMessage::OrderCancelled { reason: CancelReason::ClientRequested, .. }
+    )
+}
+
+// Sends one phase of a tick and waits until every message in it has been answered.
+// Returns false once the engine or the event feed has gone away.
+async fn run_phase(
+    simulation: &mut Simulation,
+    messages: Vec<EngineMessage>,
+    tx: &InboundSender,
+    events: &mut UnboundedReceiver<EngineMessage>,
+) -> bool {
+    let mut unanswered = messages
+        .iter()
+        .filter(|message| matches!(message, EngineMessage::NewOrder { .. } | EngineMessage::CancelOrder { .. }))
+        .count();
+    for message in messages {
+        if tx.send(message).is_err() {
+            return false;
+        }
+    }
+    while unanswered > 0 {
+        let Some(event) = events.recv().await else { return false };
+        if answers_request(&event) {
+            unanswered -= 1;
+        }
+        simulation.observe(&event);
+    }
+    true
+}
+
+// Runs the agents against the engine behind `tx`, reading their events from `events`.
+// Cancels and new orders go out in separate phases, each waiting for its answers, so
+// cancel prioritization cannot reorder them: with no other traffic the engine sees
+// the same messages in the same order on every run.
+pub async fn drive(mut simulation: Simulation, tx: InboundSender, mut events: UnboundedReceiver<EngineMessage>) {
+    if tx.send(simulation.create_instrument()).is_err() {
+        return;
+    }
+    let mut pacing = simulation.config.realtime.then(|| tokio::time::interval(Duration::from_millis(simulation.config.tick_millis)));
+    let mut tick = 0;
+    while simulation.config.ticks == 0 || tick < simulation.config.ticks {
+        if let Some(pacing) = &mut pacing {
+            pacing.tick().await;
+        }
+        simulation.advance();
+        let cancels = simulation.cancels();
+        if !run_phase(&mut simulation, cancels, &tx, &mut events).await {
+            return;
+        }
+        let orders = simulation.orders();
+        if !run_phase(&mut simulation, orders, &tx, &mut events).await {
+            return;
+        }
+        tick += 1;
+    }
+}
+
+// Starts the simulation in the background. Its agents' events arrive via deliver_events.
+pub fn start(simulation: Simulation, tx: InboundSender) -> Result<(), String> {
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    SIMULATION_EVENTS
+        .set((simulation.agent_ids(), events_tx))
+        .map_err(|_| "a simulation is already running".to_string())?;
+    tokio::spawn(drive(simulation, tx, events_rx));
+    Ok(())
+}
+
+// Forwards the simulation's agents their events from a batch of outbound messages
+pub fn deliver_events(batch: &[EngineMessage]) {
+    let Some((agents, events)) = SIMULATION_EVENTS.get() else { return };
+    for message in batch {
+        if extract_client_id(message).is_some_and(|client_id| agents.contains(&client_id)) {
+            let _ = events.send(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::Exchange;
+    use crate::namespace::Namespaces;
+    use crate::inbound::inbound_channel;
+    use crate::supervisor::EngineHealth;
+
+    fn config(seed: u64) -> SimulationConfig {
+        let mut config = SimulationConfig::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("agents.toml")).unwrap();
+        config.seed = seed;
+        config.ticks = 300;
+        config.realtime = false;
+        config
+    }
+
+    // Runs a simulation through the inbound channel, consumer and an outbound stand-in,
+    // and returns every fill the engine reported
+    async fn trade_log(config: SimulationConfig) -> Vec<String> {
+        let simulation = Simulation::new(config).unwrap();
+        let agents = simulation.agent_ids();
+        let (tx, rx) = inbound_channel(true);
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(crate::consume(Namespaces::new(Exchange::new()), rx, outbound_tx, EngineHealth::new(), std::future::pending()));
+        let outbound = tokio::spawn(async move {
+            let mut fills = Vec::new();
+            while let Some(batch) = outbound_rx.recv().await {
+                for message in batch.events {
+                    if let EngineMessage::OrderFilled { .. } = message {
+                        fills.push(format!("{:?}", message));
+                    }
+                    if extract_client_id(&message).is_some_and(|client_id| agents.contains(&client_id)) {
+                        let _ = events_tx.send(message);
+                    }
+                }
+            }
+            fills
+        });
+        drive(simulation, tx, events_rx).await;
+        outbound.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_same_seed_reproduces_the_same_trades() {
+        let first = trade_log(config(7)).await;
+        let second = trade_log(config(7)).await;
+        assert!(first.len() > 20, "only {} fills", first.len());
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn a_different_seed_trades_differently() {
+        assert_ne!(trade_log(config(7)).await, trade_log(config(8)).await);
+    }
+
+    #[test]
+    fn clock_rolls_over_midnight() {
+        let mut config = config(1);
+        config.start_time = "20240102-23:59:59.950".to_string();
+        let mut simulation = Simulation::new(config).unwrap();
+        simulation.advance();
+        assert_eq!(format_timestamp(&simulation.now()), "20240103-00:00:00.050");
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::*;
+
+// What moved an account's cash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Hold, // set aside for an order as it arrives or grows
+    Release, // handed back from what an order held
+    Buy,
+    Sell,
+    Adjustment, // paid out by a corporate action
+}
+
+impl EntryKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::Hold => "hold",
+            EntryKind::Release => "release",
+            EntryKind::Buy => "buy",
+            EntryKind::Sell => "sell",
+            EntryKind::Adjustment => "adjustment",
+        }
+    }
+}
+
+// One movement of an account's cash, in the order the account saw them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementEntry {
+    pub kind: EntryKind,
+    pub order_id: Option<OrderID>, // None for upstream fills and corporate actions
+    pub instrument_id: InstrumentID,
+    pub quantity: Quantity,
+    pub price: Option<Price>,
+    pub amount: AccountBalance, // into the account, negative out of it
+    pub balance: AccountBalance, // the account's cash after it
+}
+
+// An account's statement so far this session: where it opened and every movement since
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountHistory {
+    pub opening_cash: AccountBalance,
+    pub opening_positions: BTreeMap<InstrumentID, Quantity>,
+    pub opening_pnl: AccountBalance, // realized before the session began, when it did not start a day
+    pub entries: Vec<StatementEntry>,
+}
+
+// A holding as the session closed, valued at the book's last trade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarkedPosition {
+    pub instrument_id: InstrumentID,
+    pub quantity: Quantity,
+    pub mark: Option<Price>, // None if the book has never traded
+    pub value: Option<AccountBalance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountStatement {
+    pub account_id: AccountID,
+    pub session: String, // the session's date, YYYYMMDD
+    pub opening_cash: AccountBalance,
+    pub opening_positions: Vec<(InstrumentID, Quantity)>,
+    pub entries: Vec<StatementEntry>,
+    pub closing_cash: AccountBalance,
+    pub closing_positions: Vec<MarkedPosition>,
+    pub realized_pnl: AccountBalance,
+}
+
+impl AccountStatement {
+    // One row per line under a header shared by every kind of row:
+    //   row,order_id,instrument,quantity,price,amount,balance
+    //   opening_cash,,,,,,1000
+    //   hold,1,AAPL,4,10,-40,960
+    //   closing_position,,AAPL,4,10.5,42,
+    // A closing position's price is its mark and its amount what it is worth there.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("row,order_id,instrument,quantity,price,amount,balance\n");
+        let _ = writeln!(csv, "opening_cash,,,,,,{}", self.opening_cash);
+        for (instrument_id, quantity) in &self.opening_positions {
+            let _ = writeln!(csv, "opening_position,,{},{},,,", instrument_id, quantity);
+        }
+        for entry in &self.entries {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                entry.kind.as_str(),
+                optional(entry.order_id),
+                entry.instrument_id,
+                entry.quantity,
+                optional(entry.price),
+                entry.amount,
+                entry.balance,
+            );
+        }
+        for position in &self.closing_positions {
+            let _ = writeln!(csv, "closing_position,,{},{},{},{},", position.instrument_id, position.quantity, optional(position.mark), optional(position.value));
+        }
+        let _ = writeln!(csv, "realized_pnl,,,,,{},", self.realized_pnl);
+        let _ = writeln!(csv, "closing_cash,,,,,,{}", self.closing_cash);
+        csv
+    }
+
+    // Writes the statement to `dir` as <account>-<session>.csv, replacing any there before
+    pub fn write_csv(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}.csv", self.account_id, self.session));
+        std::fs::write(&path, self.to_csv())?;
+        Ok(path)
+    }
+}
+
+fn optional<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,89 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::engine::{EngineMessage, extract_client_id};
+use crate::exchange::Exchange;
+use crate::types::ClientID;
+
+// What the health endpoint reports about the matching thread
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EngineStatus {
+    pub running: bool,
+    pub recovered_panics: u64, // messages skipped because handling them panicked
+}
+
+// Kept current by the consumer thread and read by anyone reporting on it
+#[derive(Debug, Default)]
+pub struct EngineHealth {
+    running: AtomicBool,
+    recovered_panics: AtomicU64,
+}
+
+// Holds the engine's running flag up until dropped, however the consumer ends
+pub struct RunningGuard<'a> {
+    health: &'a EngineHealth,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.health.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl EngineHealth {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn start(&self) -> RunningGuard<'_> {
+        self.running.store(true, Ordering::SeqCst);
+        RunningGuard { health: self }
+    }
+
+    pub fn status(&self) -> EngineStatus {
+        EngineStatus {
+            running: self.running.load(Ordering::SeqCst),
+            recovered_panics: self.recovered_panics.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Handles one message, skipping it if the engine panics so one bad message cannot take the
+// exchange down. The exchange keeps whatever the panic left half done; that is still better
+// than every session losing its engine. Every connected session, and the sender, hears of
+// the panic, since whatever it left half done may touch their orders.
+pub fn handle_supervised(
+    exchange: &mut Exchange,
+    message: EngineMessage,
+    health: &EngineHealth,
+    sessions: &DashMap<ClientID, UnboundedSender<Bytes>>,
+) -> Vec<EngineMessage> {
+    // Only read if handling panics, to say which message was skipped
+    let skipped = message.clone();
+    match catch_unwind(AssertUnwindSafe(|| exchange.handle_message(message))) {
+        Ok(events) => events,
+        Err(panic) => {
+            health.recovered_panics.fetch_add(1, Ordering::Relaxed);
+            let reason = panic.downcast_ref::<&str>().copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            eprintln!("Engine panicked handling {:?}: {}; message skipped", skipped, reason);
+            // The sender hears its message went nowhere instead of waiting on it, even once logged out
+            let mut recipients: Vec<ClientID> = sessions.iter().map(|session| session.key().clone()).collect();
+            recipients.extend(extract_client_id(&skipped).filter(|sender| !sessions.contains_key(sender)));
+            recipients
+                .into_iter()
+                .map(|client_id| EngineMessage::LogEvent {
+                    client_id: Some(client_id),
+                    message: format!("Engine panic: {}", reason),
+                })
+                .collect()
+        }
+    }
+}
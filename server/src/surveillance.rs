@@ -0,0 +1,264 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fefix::definitions::fix50::Side;
+use fefix::fix_values::Timestamp;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::audit::{TradeParty, TradeRecord};
+use crate::types::*;
+
+// What the engine tells surveillance, in the order it happened
+#[derive(Debug, Clone)]
+pub enum SurveillanceEvent {
+    OrderEntered { order_id: OrderID, account_id: AccountID, received: Timestamp },
+    OrderCancelled { order_id: OrderID, received: Timestamp }, // in full, at its client's request
+    Trade(Box<TradeRecord>),
+}
+
+// Thresholds for the wash-trade and spoofing checks, e.g.
+//   round_trip_window_ms = 1000
+//   quick_cancel_ms = 500
+//   quick_cancel_rate = 0.5
+//   min_orders = 20
+//   report_path = "surveillance.jsonl"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SurveillanceConfig {
+    pub round_trip_window_ms: i64, // a buy and a sell at one price this close together is a round trip
+    pub quick_cancel_ms: i64, // a cancel this soon after entry is a quick one
+    pub quick_cancel_rate: f64, // share of an account's orders cancelled quickly, above which it is flagged
+    pub min_orders: usize, // orders an account enters before its cancel rate is judged
+    pub report_path: Option<PathBuf>, // flags are appended here as JSON lines
+}
+
+impl Default for SurveillanceConfig {
+    fn default() -> Self {
+        Self {
+            round_trip_window_ms: 1_000,
+            quick_cancel_ms: 500,
+            quick_cancel_rate: 0.5,
+            min_orders: 20,
+            report_path: None,
+        }
+    }
+}
+
+impl SurveillanceConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SurveillanceFlag {
+    // Both sides of a trade share an account or a ClientID, which self-match prevention would have stopped
+    SelfTrade {
+        instrument_id: InstrumentID,
+        price: Price,
+        quantity: Quantity,
+        #[serde(with = "fix_value_serde")]
+        timestamp: Timestamp,
+        buyer: TradeParty,
+        seller: TradeParty,
+    },
+    // An account bought and sold at the same price within the round-trip window
+    RoundTrip {
+        account_id: AccountID,
+        instrument_id: InstrumentID,
+        price: Price,
+        opening_order_id: OrderID,
+        closing_order_id: OrderID,
+        elapsed_ms: i64,
+    },
+    // An account cancels more of its orders just after entering them than the rate allows.
+    // Raised once per account.
+    QuickCancels {
+        account_id: AccountID,
+        orders: usize,
+        quick_cancels: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+struct OrderActivity {
+    orders: usize,
+    quick_cancels: usize,
+    flagged: bool,
+}
+
+#[derive(Debug)]
+struct RecentFill {
+    side: Side,
+    price: Price,
+    at: i64,
+    order_id: OrderID,
+}
+
+// The checks themselves, fed one event at a time
+#[derive(Debug)]
+pub struct Surveillance {
+    config: SurveillanceConfig,
+    young_orders: VecDeque<(OrderID, i64)>, // orders a cancel could still be quick for, oldest first
+    entries: HashMap<OrderID, (AccountID, i64)>, // when each young order was entered, and for whom
+    activity: HashMap<AccountID, OrderActivity>,
+    recent_fills: HashMap<(AccountID, InstrumentID), VecDeque<RecentFill>>, // within the round-trip window
+}
+
+impl Surveillance {
+    pub fn new(config: SurveillanceConfig) -> Self {
+        Self {
+            config,
+            young_orders: VecDeque::new(),
+            entries: HashMap::new(),
+            activity: HashMap::new(),
+            recent_fills: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, event: SurveillanceEvent) -> Vec<SurveillanceFlag> {
+        match event {
+            SurveillanceEvent::OrderEntered { order_id, account_id, received } => {
+                let at = timestamp_millis(&received);
+                self.forget_old_orders(at);
+                self.young_orders.push_back((order_id, at));
+                self.entries.insert(order_id, (account_id.clone(), at));
+                self.activity.entry(account_id).or_default().orders += 1;
+                Vec::new()
+            }
+            SurveillanceEvent::OrderCancelled { order_id, received } => {
+                let at = timestamp_millis(&received);
+                self.forget_old_orders(at);
+                let quick = self.entries.remove(&order_id).filter(|(_, entered)| at - entered <= self.config.quick_cancel_ms);
+                let Some((account_id, _)) = quick else {
+                    return Vec::new();
+                };
+                let activity = self.activity.entry(account_id.clone()).or_default();
+                activity.quick_cancels += 1;
+                let rate = activity.quick_cancels as f64 / activity.orders as f64;
+                if activity.flagged || activity.orders < self.config.min_orders || rate <= self.config.quick_cancel_rate {
+                    return Vec::new();
+                }
+                activity.flagged = true;
+                vec![SurveillanceFlag::QuickCancels {
+                    account_id,
+                    orders: activity.orders,
+                    quick_cancels: activity.quick_cancels,
+                }]
+            }
+            SurveillanceEvent::Trade(trade) => {
+                let trade = *trade;
+                if trade.buyer.account_id == trade.seller.account_id || trade.buyer.client_id == trade.seller.client_id {
+                    return vec![SurveillanceFlag::SelfTrade {
+                        instrument_id: trade.instrument_id,
+                        price: trade.price,
+                        quantity: trade.quantity,
+                        timestamp: trade.timestamp,
+                        buyer: trade.buyer,
+                        seller: trade.seller,
+                    }];
+                }
+                let at = timestamp_millis(&trade.timestamp);
+                [(&trade.buyer, Side::Buy), (&trade.seller, Side::Sell)]
+                    .into_iter()
+                    .filter_map(|(party, side)| self.round_trip(&trade, party, side, at))
+                    .collect()
+            }
+        }
+    }
+
+    // Past the quick-cancel window a cancel no longer counts against an order, so it is dropped
+    fn forget_old_orders(&mut self, now: i64) {
+        while let Some(&(order_id, at)) = self.young_orders.front() {
+            if now - at <= self.config.quick_cancel_ms {
+                break;
+            }
+            self.young_orders.pop_front();
+            self.entries.remove(&order_id);
+        }
+    }
+
+    // Pairs one side of a trade with an earlier opposite fill of the same account at the
+    // same price inside the window, or keeps it to pair with a later one
+    fn round_trip(&mut self, trade: &TradeRecord, party: &TradeParty, side: Side, at: i64) -> Option<SurveillanceFlag> {
+        let window = self.config.round_trip_window_ms;
+        let fills = self.recent_fills.entry((party.account_id.clone(), trade.instrument_id.clone())).or_default();
+        while fills.front().is_some_and(|fill| at - fill.at > window) {
+            fills.pop_front();
+        }
+        match fills.iter().position(|fill| fill.side != side && fill.price == trade.price) {
+            Some(position) => {
+                let opening = fills.remove(position).unwrap();
+                Some(SurveillanceFlag::RoundTrip {
+                    account_id: party.account_id.clone(),
+                    instrument_id: trade.instrument_id.clone(),
+                    price: trade.price,
+                    opening_order_id: opening.order_id,
+                    closing_order_id: party.order_id,
+                    elapsed_ms: at - opening.at,
+                })
+            }
+            None => {
+                fills.push_back(RecentFill { side, price: trade.price, at, order_id: party.order_id });
+                None
+            }
+        }
+    }
+}
+
+// Every flag raised so far, for the admin API
+#[derive(Debug, Default)]
+pub struct SurveillanceReport {
+    flags: Mutex<Vec<SurveillanceFlag>>,
+}
+
+impl SurveillanceReport {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn flags(&self) -> Vec<SurveillanceFlag> {
+        self.flags.lock().clone()
+    }
+
+    fn record(&self, flag: SurveillanceFlag) {
+        self.flags.lock().push(flag);
+    }
+}
+
+// Checks the engine's events as they come, on a task of its own so the matching thread only
+// ever pays for a channel send. Returns once the engine drops its end.
+pub async fn run(mut events: UnboundedReceiver<SurveillanceEvent>, config: SurveillanceConfig, report: Arc<SurveillanceReport>) {
+    let mut file = match &config.report_path {
+        Some(path) => match tokio::fs::OpenOptions::new().create(true).append(true).open(path).await {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Surveillance report {} unavailable: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut surveillance = Surveillance::new(config);
+    while let Some(event) = events.recv().await {
+        for flag in surveillance.observe(event) {
+            if let Some(file) = &mut file {
+                let mut line = serde_json::to_string(&flag).unwrap_or_default();
+                line.push('\n');
+                if let Err(e) = file.write_all(line.as_bytes()).await {
+                    eprintln!("Failed to write surveillance flag: {}", e);
+                }
+            }
+            report.record(flag);
+        }
+    }
+    if let Some(file) = &mut file {
+        let _ = file.flush().await;
+    }
+}
@@ -0,0 +1,64 @@
+// Producer threads accepting from one shared socket against each accepting from a SO_REUSEPORT
+// socket of its own. Linux only, and only run on request:
+//   cargo test --release -p exchange-server accept_scaling -- --ignored --nocapture
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::gateway::reuse_port_listeners;
+
+const THREADS: usize = 4;
+const CONNECTIONS_PER_CLIENT: usize = 1_000;
+const RUNS: usize = 3;
+
+// Seconds for THREADS clients to get CONNECTIONS_PER_CLIENT connections each accepted, one
+// accepting thread per listener as the producer pool runs them
+fn accept_all(listeners: Vec<std::net::TcpListener>, address: SocketAddr) -> f64 {
+    let total = THREADS * CONNECTIONS_PER_CLIENT;
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let acceptors: Vec<_> = listeners.into_iter().map(|listener| {
+        let accepted = Arc::clone(&accepted);
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            rt.block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                while accepted.load(Ordering::Relaxed) < total {
+                    if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(10), listener.accept()).await {
+                        accepted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        })
+    }).collect();
+    let clients: Vec<_> = (0..THREADS).map(|_| std::thread::spawn(move || {
+        for _ in 0..CONNECTIONS_PER_CLIENT {
+            drop(std::net::TcpStream::connect(address).unwrap());
+        }
+    })).collect();
+    for thread in clients.into_iter().chain(acceptors) {
+        thread.join().unwrap();
+    }
+    started.elapsed().as_secs_f64()
+}
+
+#[test]
+#[ignore]
+fn accept_rates_on_one_shared_socket_and_on_a_socket_per_producer_thread() {
+    let mut best = (f64::MAX, f64::MAX);
+    for _ in 0..RUNS {
+        let shared = reuse_port_listeners("127.0.0.1:0", 1).unwrap().remove(0);
+        let address = shared.local_addr().unwrap();
+        let clones = (0..THREADS).map(|_| shared.try_clone().unwrap()).collect();
+        drop(shared);
+        best.0 = best.0.min(accept_all(clones, address));
+
+        let own = reuse_port_listeners("127.0.0.1:0", THREADS).unwrap();
+        let address = own[0].local_addr().unwrap();
+        best.1 = best.1.min(accept_all(own, address));
+    }
+    let rate = |seconds: f64| (THREADS * CONNECTIONS_PER_CLIENT) as f64 / seconds / 1e3;
+    println!("shared socket: {:.1}k accepts/s, socket per thread: {:.1}k accepts/s", rate(best.0), rate(best.1));
+}
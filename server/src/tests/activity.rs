@@ -0,0 +1,112 @@
+use chrono::Utc;
+use fefix::definitions::fix50::Side;
+use fefix::fix_values::Timestamp;
+
+use crate::engine::{ActivitySummary, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+
+fn at(exchange: &mut Exchange, time: &str) {
+    exchange.handle_message(AdvanceTime::builder().sender("ADMIN").to(time).parse());
+}
+
+// The reports each client was sent, in order
+fn reports(events: &[EngineMessage]) -> Vec<(String, ActivitySummary)> {
+    events.iter().filter_map(|event| match event {
+        EngineMessage::ActivityReport { client_id, instrument_id, activity, .. } if instrument_id == "AAPL" => Some((client_id.to_string(), *activity)),
+        _ => None,
+    }).collect()
+}
+
+fn listed() -> Exchange {
+    let mut exchange = Exchange::new();
+    at(&mut exchange, "20240102-14:30:00.000");
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").parse());
+    exchange
+}
+
+// Each interval's counts are its own: trades and messages are rated over the interval they
+// fell in, and the next interval starts again from nothing
+#[test]
+fn activity_is_reported_each_interval_to_the_subscribers_that_asked_for_it() {
+    let mut exchange = listed();
+    exchange.handle_message(MarketDataRequest::builder().sender("STRAT").subscribe().symbols(&["AAPL"]).activity_reports(true).parse());
+    exchange.handle_message(MarketDataRequest::builder().sender("VIEWER").subscribe().symbols(&["AAPL"]).parse());
+    // The first close only opens an interval, having nothing to measure from
+    assert!(reports(&exchange.handle_message(EngineMessage::PublishActivity)).is_empty());
+
+    order(&mut exchange, "FIRM2", Side::Sell, 2, 10.0);
+    order(&mut exchange, "FIRM3", Side::Sell, 2, 10.0);
+    order(&mut exchange, "FIRM1", Side::Buy, 2, 10.0);
+    order(&mut exchange, "FIRM4", Side::Buy, 2, 10.0);
+    order(&mut exchange, "FIRM5", Side::Buy, 1, 9.0);
+    order(&mut exchange, "FIRM6", Side::Sell, 1, 11.0);
+    at(&mut exchange, "20240102-14:30:02.000");
+    let first = reports(&exchange.handle_message(EngineMessage::PublishActivity));
+    assert_eq!(first.len(), 1, "{:?}", first);
+    let (subscriber, activity) = &first[0];
+    assert_eq!(subscriber, "STRAT");
+    assert_eq!((activity.trades_per_second, activity.messages_per_second), (1.0, 3.0));
+    assert_eq!((activity.bid_levels, activity.ask_levels), (1, 1));
+
+    // Across the boundary: one order in a one-second interval, and no trades
+    order(&mut exchange, "FIRM7", Side::Buy, 1, 8.0);
+    at(&mut exchange, "20240102-14:30:03.000");
+    let second = reports(&exchange.handle_message(EngineMessage::PublishActivity));
+    let (_, activity) = &second[0];
+    assert_eq!((activity.trades_per_second, activity.messages_per_second), (0.0, 1.0));
+    assert_eq!((activity.bid_levels, activity.ask_levels), (2, 1));
+
+    // Subscribing again without asking for them stops them
+    exchange.handle_message(MarketDataRequest::builder().sender("STRAT").subscribe().symbols(&["AAPL"]).parse());
+    at(&mut exchange, "20240102-14:30:04.000");
+    assert!(reports(&exchange.handle_message(EngineMessage::PublishActivity)).is_empty());
+}
+
+// A poll answers with the last interval's rates, and the levels as they are when asked
+#[test]
+fn a_poll_is_answered_with_the_last_interval_closed() {
+    let mut exchange = listed();
+    let poll = |exchange: &mut Exchange| reports(&exchange.handle_message(ActivityQuery::builder().sender("STRAT").symbol("AAPL").parse()));
+    order(&mut exchange, "FIRM1", Side::Buy, 1, 9.0);
+    assert_eq!(poll(&mut exchange), [("STRAT".to_string(), ActivitySummary { bid_levels: 1, ..ActivitySummary::default() })]);
+
+    exchange.handle_message(EngineMessage::PublishActivity);
+    order(&mut exchange, "FIRM2", Side::Buy, 1, 8.0);
+    order(&mut exchange, "FIRM3", Side::Buy, 1, 7.0);
+    at(&mut exchange, "20240102-14:30:04.000");
+    exchange.handle_message(EngineMessage::PublishActivity);
+    order(&mut exchange, "FIRM4", Side::Sell, 1, 12.0);
+    let [(_, activity)] = <[_; 1]>::try_from(poll(&mut exchange)).unwrap();
+    assert_eq!((activity.messages_per_second, activity.bid_levels, activity.ask_levels), (0.5, 3, 1));
+
+    let unknown = exchange.handle_message(ActivityQuery::builder().sender("STRAT").symbol("MSFT").parse());
+    assert!(matches!(&unknown[..], [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown instrument"), "{:?}", unknown);
+}
+
+// The watermark is the longest wait of any message in the interval, and falls back once it closes
+#[test]
+fn the_queue_delay_watermark_is_the_longest_wait_in_the_interval() {
+    let mut exchange = listed();
+    exchange.handle_message(MarketDataRequest::builder().sender("STRAT").subscribe().symbols(&["AAPL"]).activity_reports(true).parse());
+    exchange.handle_message(EngineMessage::PublishActivity);
+
+    let waited = |millis| {
+        let received = (Utc::now() - chrono::Duration::milliseconds(millis)).format("%Y%m%d-%H:%M:%S%.3f").to_string();
+        let mut order = NewOrderSingle::builder().sender("FIRM1").account("FIRM1").symbol("AAPL").side(Side::Buy).qty(1).limit(9.0).parse();
+        if let EngineMessage::NewOrder { receiving_time, .. } = &mut order {
+            *receiving_time = Timestamp::parse(received.as_bytes()).unwrap();
+        }
+        order
+    };
+    exchange.handle_message(waited(400));
+    exchange.handle_message(waited(0));
+    at(&mut exchange, "20240102-14:30:01.000");
+    let (_, activity) = reports(&exchange.handle_message(EngineMessage::PublishActivity))[0];
+    assert!((400..5_000).contains(&activity.max_queue_delay_ms), "{:?}", activity);
+
+    exchange.handle_message(waited(0));
+    at(&mut exchange, "20240102-14:30:02.000");
+    let (_, activity) = reports(&exchange.handle_message(EngineMessage::PublishActivity))[0];
+    assert!(activity.max_queue_delay_ms < 400, "{:?}", activity);
+}
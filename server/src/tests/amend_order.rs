@@ -0,0 +1,68 @@
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::handle_fix_message;
+use crate::fix::testkit::accepted_order_id;
+use crate::instrument::SpecOverrides;
+use crate::types::*;
+
+// Each account trades under a CompID of its own name
+fn send(exchange: &mut Exchange, msg_type: &[u8], account: &str, fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    let mut encoder = Encoder::<Config>::default();
+    encoder.config_mut().set_separator(b'|');
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+    msg.set(SENDER_COMP_ID, account);
+    msg.set(TARGET_COMP_ID, "EXCHANGE");
+    msg.set(SENDING_TIME, Timestamp::utc_now());
+    msg.set(ACCOUNT, account);
+    for (tag, value) in fields {
+        msg.set_any(TagU16::new(*tag).unwrap(), *value);
+    }
+    let message = handle_fix_message(&String::from_utf8_lossy(msg.wrap()));
+    assert!(!matches!(message, EngineMessage::InvalidMessage { .. }), "{:?}", message);
+    exchange.handle_message(message)
+}
+
+fn limit_order(exchange: &mut Exchange, account: &str, side: &str, quantity: Quantity) -> Vec<EngineMessage> {
+    let quantity = quantity.to_string();
+    send(exchange, b"D", account, &[(55, "AAPL"), (54, side), (53, &quantity), (40, "2"), (44, "100")])
+}
+
+#[test]
+fn reducing_only_the_quantity_keeps_the_order_ahead_of_later_ones_at_its_price() {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    });
+    let a = accepted_order_id(&limit_order(&mut exchange, "A", "1", 4));
+    let b = accepted_order_id(&limit_order(&mut exchange, "B", "1", 4));
+    let c = accepted_order_id(&limit_order(&mut exchange, "C", "1", 4));
+
+    // Half of B's order, with its price left alone
+    let events = send(&mut exchange, b"G", "B", &[(37, &b.to_string()), (38, "2")]);
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderAmended { leaves_quantity: 2, .. }]), "{:?}", events);
+
+    let events = limit_order(&mut exchange, "SELLER", "2", 6);
+    let fills: Vec<(OrderID, Quantity)> = events
+        .iter()
+        .filter_map(|event| match event {
+            EngineMessage::OrderFilled { client_id, order_id, filled_quantity, .. } if client_id.comp_id() != "SELLER" => {
+                Some((*order_id, *filled_quantity))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(fills, vec![(a, 4), (b, 2)], "{:?}", events);
+    assert!(!fills.iter().any(|(order_id, _)| *order_id == c));
+}
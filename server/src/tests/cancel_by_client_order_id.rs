@@ -0,0 +1,50 @@
+use fefix::definitions::fix50::Side;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+
+fn order_with_cl_ord_id(exchange: &mut Exchange, firm: &str, client_order_id: &str, side: Side, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderSingle::builder().sender(firm).account(firm).cl_ord_id(client_order_id).symbol("AAPL").side(side).qty(2).limit(price).parse())
+}
+
+fn cancel(exchange: &mut Exchange, firm: &str, client_order_id: &str) -> Vec<EngineMessage> {
+    exchange.handle_message(OrderCancelRequest::builder().sender(firm).account(firm).orig_cl_ord_id(client_order_id).parse())
+}
+
+fn listed() -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").parse());
+    exchange
+}
+
+fn not_found(events: &[EngineMessage]) -> bool {
+    matches!(events, [EngineMessage::OrderRejected { reason, .. }] if reason == "Order not found")
+}
+
+// A ClOrdID is the sender's own, so it names nothing in another client's cancel
+#[test]
+fn a_cancel_names_its_order_by_the_client_order_id_it_was_sent_with() {
+    let mut exchange = listed();
+    let order_id = accepted_order_id(&order_with_cl_ord_id(&mut exchange, "FIRM1", "C-1", Side::Buy, 9.0));
+
+    assert!(not_found(&cancel(&mut exchange, "FIRM2", "C-1")));
+    assert!(not_found(&cancel(&mut exchange, "FIRM1", "C-2")));
+    let events = cancel(&mut exchange, "FIRM1", "C-1");
+    assert!(matches!(&events[..], [EngineMessage::OrderCancelled { order_id: cancelled, .. }] if *cancelled == order_id), "{:?}", events);
+
+    // Sent again on a later order, the ClOrdID names that one
+    let reused = accepted_order_id(&order_with_cl_ord_id(&mut exchange, "FIRM1", "C-1", Side::Buy, 8.0));
+    let events = cancel(&mut exchange, "FIRM1", "C-1");
+    assert!(matches!(&events[..], [EngineMessage::OrderCancelled { order_id: cancelled, .. }] if *cancelled == reused), "{:?}", events);
+}
+
+#[test]
+fn a_cancel_by_client_order_id_after_the_order_filled_is_too_late() {
+    let mut exchange = listed();
+    order_with_cl_ord_id(&mut exchange, "FIRM2", "S-1", Side::Sell, 10.0);
+    let order_id = accepted_order_id(&order_with_cl_ord_id(&mut exchange, "FIRM1", "B-1", Side::Buy, 10.0));
+
+    let events = cancel(&mut exchange, "FIRM1", "B-1");
+    assert!(matches!(&events[..], [EngineMessage::CancelRejected { order_id: rejected, cumulative_quantity: 2, .. }] if *rejected == order_id), "{:?}", events);
+}
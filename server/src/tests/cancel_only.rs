@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use fefix::definitions::fix50::Side;
+
+use crate::config::{LiveConfig, ServerConfig};
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+use crate::framing::RawMessage;
+use crate::inbound::inbound_channel;
+use crate::overload::{watch_overload, CancelOnlyMode, OverloadGuard, SYSTEM_OVERLOADED};
+use crate::supervisor::EngineHealth;
+
+fn overloaded(events: &[EngineMessage]) -> bool {
+    matches!(events, [EngineMessage::OrderRejected { reason, .. }] if reason == SYSTEM_OVERLOADED)
+}
+
+fn listed(overload: &Arc<OverloadGuard>) -> Exchange {
+    let mut exchange = Exchange::new().with_overload(Arc::clone(overload));
+    for symbol in ["AAPL", "MSFT"] {
+        exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol(symbol).parse());
+    }
+    exchange
+}
+
+// Cancel-only turns away what would add risk and takes everything that takes it off
+#[test]
+fn a_cancel_only_exchange_refuses_new_orders_and_still_cancels_resting_ones() {
+    let overload = OverloadGuard::new();
+    let mut exchange = listed(&overload);
+    let order_id = accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0));
+
+    overload.set_mode(CancelOnlyMode::On);
+    assert!(overloaded(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0)));
+    let multileg = exchange.handle_message(NewOrderMultileg::builder().sender("FIRM1").account("FIRM1").cl_ord_id("SPREAD-1").legs(&[("AAPL", Side::Buy, 2, Some(9.0)), ("MSFT", Side::Sell, 2, None)]).parse());
+    assert!(overloaded(&multileg), "{:?}", multileg);
+    let cancelled = exchange.handle_message(OrderCancelRequest::builder().sender("FIRM1").account("FIRM1").order_id(order_id).parse());
+    assert!(matches!(&cancelled[..], [EngineMessage::OrderCancelled { order_id: cancelled, .. }] if *cancelled == order_id), "{:?}", cancelled);
+
+    overload.set_mode(CancelOnlyMode::Auto);
+    accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0));
+}
+
+// The watcher samples a flooded inbound queue, so the exchange goes cancel-only on its own and
+// takes new orders again once the queue drains
+#[tokio::test]
+async fn a_flooded_queue_makes_the_exchange_cancel_only_until_it_drains() {
+    let overload = OverloadGuard::new();
+    let mut exchange = listed(&overload);
+    let order_id = accepted_order_id(&order(&mut exchange, "FIRM1", Side::Sell, 2, 11.0));
+    let (tx, mut rx) = inbound_channel(true);
+    let config = ServerConfig { overload_queue_depth: 100, overload_resume_depth: 10, overload_samples: 2, ..ServerConfig::default() };
+    let (_shutdown_tx, shutdown) = tokio::sync::watch::channel(false);
+    let sessions = Box::leak(Box::new(DashMap::new()));
+    tokio::spawn(watch_overload(Duration::from_millis(5), Arc::clone(&overload), tx.queue_depth(), LiveConfig::fixed(config), EngineHealth::new(), sessions, shutdown));
+
+    for _ in 0..150 {
+        tx.send(EngineMessage::InvalidMessage { reason: String::new(), raw_message: RawMessage::text("") }).unwrap();
+    }
+    while !overload.cancel_only() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert!(overloaded(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0)));
+    let cancelled = exchange.handle_message(OrderCancelRequest::builder().sender("FIRM1").account("FIRM1").order_id(order_id).parse());
+    assert!(matches!(&cancelled[..], [EngineMessage::OrderCancelled { .. }]), "{:?}", cancelled);
+
+    for _ in 0..150 {
+        rx.recv().await.unwrap();
+    }
+    while overload.cancel_only() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0));
+    assert_eq!(overload.status().episodes, 1);
+}
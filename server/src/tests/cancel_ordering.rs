@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::engine::{extract_client_id, EngineMessage};
+use crate::exchange::Exchange;
+use crate::inbound::{inbound_channel, InboundSender};
+use crate::instrument::SpecOverrides;
+use crate::types::*;
+
+const CLIENTS: usize = 8;
+const ROUNDS: usize = 200;
+
+fn client(index: usize) -> ClientID {
+    ClientID::new(format!("CLIENT{}", index), None)
+}
+
+fn order(client_id: &ClientID, account_id: String, side: Side) -> EngineMessage {
+    EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client_id.clone(),
+        account_id,
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity: 1,
+        price: Some(Price::from(1.0)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    }
+}
+
+// Runs one exchange behind a prioritizing inbound channel, routing its events back by client
+fn start_exchange() -> (InboundSender, Vec<UnboundedReceiver<EngineMessage>>) {
+    let (tx, mut rx) = inbound_channel(true);
+    let (senders, receivers): (HashMap<ClientID, UnboundedSender<EngineMessage>>, Vec<_>) = (0..CLIENTS)
+        .map(|index| {
+            let (event_tx, event_rx) = mpsc::unbounded_channel();
+            ((client(index), event_tx), event_rx)
+        })
+        .unzip();
+    tokio::spawn(async move {
+        let mut exchange = Exchange::new();
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("ADMIN".to_string(), None),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+        let mut batch = Vec::new();
+        while rx.recv_many(&mut batch, 64).await > 0 {
+            for (message, _) in batch.drain(..) {
+                for event in exchange.handle_message(message) {
+                    if let Some(sender) = extract_client_id(&event).and_then(|client_id| senders.get(&client_id)) {
+                        let _ = sender.send(event);
+                    }
+                }
+            }
+        }
+    });
+    (tx, receivers)
+}
+
+// Each round rests a bid, then fires an amend and a cancel for it without waiting, on odd
+// rounds right behind a sell that may fill it first. Meanwhile the other clients flood
+// the queue. Every cancel must come back exactly once, for its own order, after the amend.
+async fn hammer(index: usize, tx: InboundSender, mut events: UnboundedReceiver<EngineMessage>) -> (usize, usize) {
+    let client_id = client(index);
+    let (mut cancelled, mut too_late) = (0, 0);
+    for round in 0..ROUNDS {
+        tx.send(order(&client_id, client_id.to_string(), Side::Buy)).unwrap();
+        let order_id = loop {
+            if let Some(EngineMessage::OrderAccepted { order_id, .. }) = events.recv().await {
+                break order_id;
+            }
+        };
+
+        if round % 2 == 1 {
+            // Every sell comes from a fresh account, which sidesteps seller position bookkeeping
+            tx.send(order(&client_id, format!("{}-{}", client_id, round), Side::Sell)).unwrap();
+        }
+        tx.send(EngineMessage::AmendOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client_id.clone(),
+            order_id,
+            new_quantity: Some(1),
+            new_price: None,
+            time_in_force: None,
+        })
+        .unwrap();
+        tx.send(EngineMessage::CancelOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client_id.clone(),
+            account_id: client_id.to_string(),
+            order_id,
+            orig_client_order_id: None,
+            cancel_quantity: None,
+        })
+        .unwrap();
+
+        let mut amended = false;
+        let mut filled = false;
+        loop {
+            match events.recv().await.expect("exchange stopped") {
+                EngineMessage::OrderAmended { order_id: amended_id, .. }
+                | EngineMessage::AmendRejected { order_id: amended_id, .. } if amended_id == order_id => amended = true,
+                EngineMessage::OrderFilled { order_id: filled_id, remaining_quantity: 0, .. } if filled_id == order_id => filled = true,
+                EngineMessage::OrderCancelled { order_id: cancelled_id, .. } => {
+                    assert_eq!(cancelled_id, order_id, "{} cancelled the wrong order", client_id);
+                    assert!(amended && !filled, "{} round {}: cancel overtook its amend or a fill", client_id, round);
+                    cancelled += 1;
+                    break;
+                }
+                EngineMessage::CancelRejected { order_id: rejected_id, status, cumulative_quantity, average_price, .. } => {
+                    assert_eq!(rejected_id, order_id, "{} got the reject for the wrong order", client_id);
+                    assert!(amended && filled, "{} round {}: reject without its amend or fill", client_id, round);
+                    assert_eq!((status, cumulative_quantity, average_price), (OrdStatus::Filled, 1, Price::from(1.0)));
+                    too_late += 1;
+                    break;
+                }
+                EngineMessage::OrderRejected { reason, .. } => panic!("{} round {}: {}", client_id, round, reason),
+                _ => {}
+            }
+        }
+    }
+    (cancelled, too_late)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn no_cancel_is_lost_or_applied_to_the_wrong_order() {
+    let (tx, receivers) = start_exchange();
+    let clients: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(index, events)| tokio::spawn(hammer(index, tx.clone(), events)))
+        .collect();
+
+    let (mut cancelled, mut too_late) = (0, 0);
+    for client in clients {
+        let (client_cancelled, client_too_late) = client.await.unwrap();
+        cancelled += client_cancelled;
+        too_late += client_too_late;
+    }
+    assert_eq!(cancelled + too_late, CLIENTS * ROUNDS);
+    println!("{} cancels applied, {} too late", cancelled, too_late);
+}
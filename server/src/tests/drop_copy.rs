@@ -0,0 +1,49 @@
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::handle_fix_message;
+use crate::tests::fix_round_trip::encode;
+use crate::types::Price;
+
+fn send(exchange: &mut Exchange, msg_type: &[u8], fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    exchange.handle_message(handle_fix_message(&encode(msg_type, fields)))
+}
+
+// An upstream fill of `quantity` at `price`, as the venue's drop copy reports it
+fn upstream_fill(exchange: &mut Exchange, exec_id: &str, side: &str, quantity: &str, price: &str) -> Vec<EngineMessage> {
+    let fields = [(17, exec_id), (37, "VENUE-7"), (150, "F"), (1, "FIRM1"), (55, "AAPL"), (54, side), (32, quantity), (31, price), (14, quantity), (151, "0")];
+    send(exchange, b"8", &fields)
+}
+
+fn positions(exchange: &mut Exchange) -> (f64, Vec<(String, u64, Price)>) {
+    let report = send(exchange, b"UPQ", &[(1, "FIRM1")]);
+    let [EngineMessage::PositionReport { cash, positions, .. }] = report.as_slice() else { panic!("{:?}", report) };
+    (cash.into_inner(), positions.clone())
+}
+
+#[test]
+fn fills_from_an_upstream_drop_copy_move_cash_and_positions_once_each() {
+    let mut exchange = Exchange::new();
+    let bought = upstream_fill(&mut exchange, "X1", "1", "10", "20");
+    assert!(matches!(bought.as_slice(), [EngineMessage::PositionReport { .. }]), "{:?}", bought);
+    assert_eq!(positions(&mut exchange), (800.0, vec![("AAPL".to_string(), 10, Price::from(20.0))]));
+
+    // The venue resending an execution is not a second fill
+    assert!(upstream_fill(&mut exchange, "X1", "1", "10", "20").is_empty());
+    assert_eq!(positions(&mut exchange).0, 800.0);
+
+    upstream_fill(&mut exchange, "X2", "2", "4", "25");
+    assert_eq!(positions(&mut exchange), (900.0, vec![("AAPL".to_string(), 6, Price::from(20.0))]));
+
+    // An acknowledgement moves nothing, and needs no LastQty or LastPx
+    let ack = send(&mut exchange, b"8", &[(17, "X3"), (37, "VENUE-8"), (150, "0"), (1, "FIRM1"), (55, "AAPL"), (54, "1")]);
+    assert!(ack.is_empty(), "{:?}", ack);
+    assert_eq!(positions(&mut exchange).0, 900.0);
+}
+
+#[test]
+fn an_execution_report_missing_its_ids_or_with_a_malformed_quantity_is_invalid() {
+    let missing = handle_fix_message(&encode(b"8", &[(37, "VENUE-7"), (150, "F"), (1, "FIRM1"), (55, "AAPL"), (54, "1")]));
+    assert!(matches!(&missing, EngineMessage::InvalidMessage { reason, .. } if reason == "Missing ExecID, OrderID, Account or Symbol"), "{:?}", missing);
+    let malformed = handle_fix_message(&encode(b"8", &[(17, "X1"), (37, "VENUE-7"), (150, "F"), (1, "FIRM1"), (55, "AAPL"), (54, "1"), (32, "ten"), (31, "20")]));
+    assert!(matches!(&malformed, EngineMessage::InvalidMessage { reason, .. } if reason == "Invalid LastQty, CumQty, LeavesQty or LastPx"), "{:?}", malformed);
+}
@@ -0,0 +1,74 @@
+use fefix::definitions::fix50::Side;
+
+use crate::config::{LiveConfig, ServerConfig};
+use crate::engine::{CancelReason, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+use crate::types::*;
+
+// An exchange that lets an order trade `max_executions_per_order` times, with a deep book of
+// one-lot offers at 1, each from an account of its own
+fn one_lot_book(max_executions_per_order: usize, offers: usize) -> Exchange {
+    let config = LiveConfig::fixed(ServerConfig { max_executions_per_order, ..ServerConfig::default() });
+    let mut exchange = Exchange::new().with_config(config);
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").parse());
+    // Parsed once, as building the dictionary for every parse would dwarf the matching
+    let offer = NewOrderSingle::builder().symbol("AAPL").side(Side::Sell).qty(1).limit(1.0).account("SELLER").parse();
+    for seller in 0..offers {
+        let mut offer = offer.clone();
+        if let EngineMessage::NewOrder { client_id, account_id, .. } = &mut offer {
+            *account_id = format!("SELLER{}", seller);
+            *client_id = ClientID::new(account_id.clone(), None);
+        }
+        exchange.handle_message(offer);
+    }
+    exchange
+}
+
+fn buy(exchange: &mut Exchange, quantity: Quantity) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderSingle::builder().sender("SWEEPER").account("SWEEPER").symbol("AAPL").side(Side::Buy).qty(quantity).limit(1.0).parse())
+}
+
+fn holdings(exchange: &mut Exchange) -> (AccountBalance, Vec<(InstrumentID, Quantity)>) {
+    let report = exchange.handle_message(PositionQuery::builder().sender("SWEEPER").account("SWEEPER").parse());
+    let [EngineMessage::PositionReport { cash, positions, .. }] = report.as_slice() else { panic!("{:?}", report) };
+    (*cash, positions.iter().map(|(instrument_id, quantity, _)| (instrument_id.clone(), *quantity)).collect())
+}
+
+fn sweeper_fills(events: &[EngineMessage]) -> usize {
+    events.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { client_id, .. } if client_id.to_string() == "SWEEPER")).count()
+}
+
+// Past the limit the sweep stops and its leaves are cancelled, saying why, rather than rested;
+// the account ends up exactly as if it had sent an order for what did trade
+#[test]
+fn a_sweep_past_the_execution_limit_cancels_its_leaves_and_accounts_for_what_traded() {
+    let mut capped = one_lot_book(5, 200);
+    let events = buy(&mut capped, 200);
+    assert_eq!(sweeper_fills(&events), 5);
+    let cancelled = events.iter().find_map(|event| match event {
+        EngineMessage::OrderCancelled { client_id, reason, cancelled_quantity, .. } if client_id.to_string() == "SWEEPER" => Some((reason.clone(), *cancelled_quantity)),
+        _ => None,
+    });
+    assert_eq!(cancelled, Some((CancelReason::ExecutionLimit, 195)), "{:?}", events);
+
+    let mut sized = one_lot_book(5, 200);
+    assert_eq!(sweeper_fills(&buy(&mut sized, 5)), 5);
+    assert_eq!(holdings(&mut capped), holdings(&mut sized));
+    assert_eq!(holdings(&mut capped).1, [("AAPL".to_string(), 5)]);
+
+    // The offers it never reached are still there for the next order
+    let events = buy(&mut capped, 5);
+    assert_eq!(sweeper_fills(&events), 5);
+    assert!(!events.iter().any(|event| matches!(event, EngineMessage::OrderCancelled { .. })), "{:?}", events);
+}
+
+#[test]
+fn the_default_limit_leaves_a_deep_sweep_alone_and_zero_lifts_it() {
+    for max_executions_per_order in [ServerConfig::default().max_executions_per_order, 0] {
+        let mut exchange = one_lot_book(max_executions_per_order, 500);
+        let events = buy(&mut exchange, 500);
+        assert_eq!(sweeper_fills(&events), 500);
+        assert!(!events.iter().any(|event| matches!(event, EngineMessage::OrderCancelled { .. })));
+    }
+}
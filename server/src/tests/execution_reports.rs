@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use fefix::definitions::fix50::{OrdStatus, OrdType, Side};
+use fefix::fix_values::Timestamp;
+use tokio::sync::mpsc;
+
+use crate::engine::{CancelReason, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::{encode_outbound, serialize_engine_message};
+use crate::inbound::inbound_channel;
+use crate::instrument::SpecOverrides;
+use crate::namespace::Namespaces;
+use crate::supervisor::EngineHealth;
+use crate::types::{ClientID, Price, Quantity};
+use crate::wire::stamp_outbound;
+
+// Serializes a cancel for `reason` and returns its (ExecType, OrdStatus, ExecRestatementReason, Text)
+fn cancel_on_the_wire(reason: CancelReason) -> (String, String, Option<String>, Option<String>) {
+    let report = serialize_engine_message(&EngineMessage::OrderCancelled {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        order_id: 42,
+        reason,
+        instrument_id: "AAPL".to_string(),
+        cancelled_price: Some(Price::from(10.5)),
+        cancelled_quantity: 3,
+        side: Side::Sell,
+        cumulative_quantity: 0,
+        average_price: Price::from(0.0),
+    })
+    .unwrap();
+    let field = |tag: &str| {
+        report.trim().split('|').find_map(|pair| pair.split_once('=').filter(|(t, _)| *t == tag)).map(|(_, value)| value.to_string())
+    };
+    assert_eq!(field("35").as_deref(), Some("8"));
+    assert_eq!(field("37").as_deref(), Some("42"));
+    (field("150").unwrap(), field("39").unwrap(), field("378"), field("58"))
+}
+
+#[test]
+fn client_requested_cancel_carries_no_reason() {
+    assert_eq!(cancel_on_the_wire(CancelReason::ClientRequested), ("4".to_string(), "4".to_string(), None, None));
+}
+
+#[test]
+fn unsolicited_cancels_carry_their_reason() {
+    let cases = [
+        (CancelReason::Disconnect, "Cancelled on disconnect"),
+        (CancelReason::SelfTradePrevention, "Cancelled to prevent a self-trade"),
+    ];
+    for (reason, text) in cases {
+        let expected = ("4".to_string(), "4".to_string(), Some("8".to_string()), Some(text.to_string()));
+        assert_eq!(cancel_on_the_wire(reason.clone()), expected, "{:?}", reason);
+    }
+    assert_eq!(
+        cancel_on_the_wire(CancelReason::Other("Price level evicted".to_string())),
+        ("4".to_string(), "4".to_string(), Some("99".to_string()), Some("Price level evicted".to_string())),
+    );
+}
+
+#[test]
+fn expiry_is_reported_as_expired() {
+    assert_eq!(
+        cancel_on_the_wire(CancelReason::Expired),
+        ("C".to_string(), "C".to_string(), Some("8".to_string()), Some("Expired".to_string())),
+    );
+}
+
+// The values of `tags` in the serialized message, in order
+fn fields_on_the_wire(message: EngineMessage, tags: &[&str]) -> Vec<Option<String>> {
+    let report = serialize_engine_message(&message).unwrap();
+    tags.iter()
+        .map(|tag| report.trim().split('|').find_map(|pair| pair.split_once('=').filter(|(t, _)| t == tag)).map(|(_, value)| value.to_string()))
+        .collect()
+}
+
+#[test]
+fn cancels_carry_the_price_and_quantity_taken_off_the_book() {
+    let cancelled = EngineMessage::OrderCancelled {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        order_id: 42,
+        reason: CancelReason::ClientRequested,
+        instrument_id: "AAPL".to_string(),
+        cancelled_price: Some(Price::from(10.5)),
+        cancelled_quantity: 3,
+        side: Side::Sell,
+        cumulative_quantity: 0,
+        average_price: Price::from(0.0),
+    };
+    let expected = ["4", "AAPL", "10.5", "3", "0"].map(|value| Some(value.to_string()));
+    assert_eq!(fields_on_the_wire(cancelled, &["150", "55", "44", "84", "151"]), expected);
+}
+
+#[test]
+fn fills_carry_the_touch_their_order_arrived_into_when_there_was_one() {
+    let fill = |arrival_bid: Option<f64>, arrival_ask: Option<f64>| EngineMessage::OrderFilled {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        order_id: 42,
+        client_order_id: None,
+        filled_quantity: 2,
+        remaining_quantity: 0,
+        cumulative_quantity: 2,
+        price: Price::from(10.5),
+        instrument_id: "AAPL".to_string(),
+        side: Side::Buy,
+        arrival_bid: arrival_bid.map(Price::from),
+        arrival_ask: arrival_ask.map(Price::from),
+        trade_match_id: 7,
+        average_price: Price::from(10.5),
+        summary: false,
+    };
+    let on_the_wire = |values: [Option<&str>; 3]| values.map(|value| value.map(str::to_string)).to_vec();
+    assert_eq!(fields_on_the_wire(fill(Some(10.0), Some(11.0)), &["31", "8024", "8025"]), on_the_wire([Some("10.5"), Some("10"), Some("11")]));
+    assert_eq!(fields_on_the_wire(fill(None, Some(11.0)), &["31", "8024", "8025"]), on_the_wire([Some("10.5"), None, Some("11")]));
+}
+
+fn exchange_trading_aapl() -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    });
+    exchange
+}
+
+fn limit_order(exchange: &mut Exchange, account: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new(account.to_string(), None),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity,
+        price: Some(Price::from(price)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    })
+}
+
+#[test]
+fn both_sides_of_a_match_report_its_trdmatchid() {
+    let mut exchange = exchange_trading_aapl();
+    limit_order(&mut exchange, "MAKER", Side::Sell, 2, 10.0);
+    limit_order(&mut exchange, "MAKER", Side::Sell, 1, 11.0);
+    let events = limit_order(&mut exchange, "TAKER", Side::Buy, 3, 11.0);
+
+    // Two matches, each reported to the taker then the maker: (OrderID, TrdMatchID)
+    let fills: Vec<Vec<Option<String>>> = events
+        .into_iter()
+        .filter(|event| matches!(event, EngineMessage::OrderFilled { .. }))
+        .map(|fill| fields_on_the_wire(fill, &["37", "880"]))
+        .collect();
+    let fill = |order_id: &str, trade_match_id: &str| vec![Some(order_id.to_string()), Some(trade_match_id.to_string())];
+    assert_eq!(fills, vec![fill("3", "1"), fill("1", "1"), fill("3", "2"), fill("2", "2")]);
+}
+
+#[test]
+fn fills_carry_the_clordid_their_order_was_sent_with() {
+    let mut exchange = exchange_trading_aapl();
+    let mut order = |account: &str, client_order_id: Option<&str>, side| {
+        exchange.handle_message(EngineMessage::NewOrder {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new(account.to_string(), None),
+            account_id: account.to_string(),
+            client_order_id: client_order_id.map(str::to_string),
+            instrument_id: "AAPL".to_string(),
+            order_type: OrdType::Limit,
+            side,
+            quantity: 1,
+            price: Some(Price::from(10.0)),
+            time_in_force: None,
+            expire_time: None,
+            exchange_code: None,
+            good_for_auction: false,
+            stipulations: Vec::new(),
+        })
+    };
+    order("MAKER", Some("MAKER-1"), Side::Sell);
+    let events = order("TAKER", None, Side::Buy);
+
+    let fills: Vec<Vec<Option<String>>> = events
+        .into_iter()
+        .filter(|event| matches!(event, EngineMessage::OrderFilled { .. }))
+        .map(|fill| fields_on_the_wire(fill, &["49", "56", "11"]))
+        .collect();
+    // The taker sent no ClOrdID, so its report has none to give back
+    let party = |comp_id: &str, client_order_id: Option<&str>| vec![Some("EXCHANGE".to_string()), Some(comp_id.to_string()), client_order_id.map(str::to_string)];
+    assert_eq!(fills, vec![party("TAKER", None), party("MAKER", Some("MAKER-1"))]);
+}
+
+#[test]
+fn amend_reports_carry_cumulative_and_leaves_quantities() {
+    let amended = EngineMessage::OrderAmended {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        order_id: 42,
+        new_quantity: Some(5),
+        new_price: Some(Price::from(11.0)),
+        status: OrdStatus::PartiallyFilled,
+        cumulative_quantity: 2,
+        leaves_quantity: 3,
+    };
+    let expected = ["8", "5", "1", "5", "2", "3"].map(|value| Some(value.to_string()));
+    assert_eq!(fields_on_the_wire(amended, &["35", "150", "39", "38", "14", "151"]), expected);
+}
+
+#[test]
+fn amend_of_a_finished_order_is_a_cancel_replace_reject() {
+    let rejected = EngineMessage::AmendRejected {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        order_id: 42,
+        reason: "Too late to amend".to_string(),
+        status: OrdStatus::Canceled,
+    };
+    // 434=2 answers an Order Cancel/Replace Request; 102=0 is "Too late to cancel"
+    let expected = ["9", "4", "2", "0", "Too late to amend"].map(|value| Some(value.to_string()));
+    assert_eq!(fields_on_the_wire(rejected, &["35", "39", "434", "102", "58"]), expected);
+}
+
+fn new_order(account: &str, side: Side) -> EngineMessage {
+    EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new(account.to_string(), None),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity: 1,
+        price: Some(Price::from(10.0)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn reports_say_when_the_exchange_received_matched_and_sent_them() {
+    let (tx, rx) = inbound_channel(true);
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+    tx.send(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    })
+    .unwrap();
+    tx.send(new_order("MAKER", Side::Sell)).unwrap();
+    tx.send(new_order("TAKER", Side::Buy)).unwrap();
+    drop(tx);
+    crate::consume(Namespaces::new(Exchange::new()), rx, outbound_tx, EngineHealth::new(), std::future::pending()).await;
+
+    let mut encoded = Vec::new();
+    while let Ok(batch) = outbound_rx.try_recv() {
+        encoded.extend(batch.iter().filter_map(|(message, times)| encode_outbound(message, Some(times))));
+    }
+    // Held back before the session writes them, as a slow socket would
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let mut next_seq_num = 1;
+    let reports: Vec<String> = encoded.into_iter().map(|report| String::from_utf8_lossy(&stamp_outbound(report, &mut next_seq_num)).into_owned()).collect();
+
+    // Two acceptances and a fill for each side
+    assert_eq!(reports.len(), 4, "{:?}", reports);
+    for report in &reports {
+        let field = |tag: &str| report.trim().split('|').find_map(|pair| pair.split_once('=').filter(|(t, _)| *t == tag)).map(|(_, value)| value.to_string());
+        let time = |tag: &str| {
+            let value = field(tag).unwrap_or_else(|| panic!("no {} in {}", tag, report));
+            NaiveDateTime::parse_from_str(&value, "%Y%m%d-%H:%M:%S%.6f").unwrap_or_else(|_| panic!("{} is not to the microsecond: {}", tag, value))
+        };
+        let (received, matched, sent) = (time("8031"), time("60"), time("52"));
+        assert!(received <= matched && matched < sent, "{}", report);
+        assert!(matched - received < chrono::Duration::seconds(1), "{}", report);
+        assert!(sent - matched >= chrono::Duration::milliseconds(20), "{}", report);
+
+        // Restamped, the message still adds up
+        let (covered, checksum) = report.trim().rsplit_once("10=").unwrap();
+        let sum = covered.bytes().fold(0u8, u8::wrapping_add);
+        assert_eq!(checksum, format!("{:03}|", sum), "{}", report);
+    }
+}
@@ -0,0 +1,81 @@
+use fefix::definitions::fix50::*;
+
+use crate::engine::EngineMessage;
+use crate::framing::RawMessage;
+use crate::fix::{conform, handle_fix_message, serialize_logout, Conformance, Separator};
+use crate::tests::fix_round_trip::encode;
+use crate::types::*;
+
+// As QuickFIX/J sends them with its default session settings: SOH-separated, FIXT.1.1 with
+// DefaultApplVerID on the Logon, and ApplVerID repeated on application messages
+const QUICKFIXJ_LOGON: &str = "8=FIXT.1.1\x019=82\x0135=A\x0134=1\x0149=BANZAI\x0152=20240102-14:30:00.125\x0156=EXCHANGE\x0198=0\x01108=30\x01141=Y\x011137=9\x0110=161\x01";
+const QUICKFIXJ_NEW_ORDER: &str = "8=FIXT.1.1\x019=162\x0135=D\x0134=2\x0149=BANZAI\x0152=20240102-14:30:01.250\x0156=EXCHANGE\x011128=9\x011=BANZAI-1\x0111=1704205801250\x0121=1\x0138=100\x0140=2\x0144=187.25\x0154=1\x0155=AAPL\x0159=0\x0160=20240102-14:30:01.250\x0110=054\x01";
+
+fn parse(message: &str, conformance: Conformance) -> EngineMessage {
+    match conform(message, conformance) {
+        Ok(message) => handle_fix_message(&message),
+        Err(reason) => EngineMessage::InvalidMessage { reason, raw_message: RawMessage::text(message) },
+    }
+}
+
+fn refusal(message: &str) -> String {
+    conform(message, Conformance::Strict).expect_err(message)
+}
+
+#[test]
+fn quickfixj_defaults_are_read_without_leniency() {
+    let logon = parse(QUICKFIXJ_LOGON, Conformance::Strict);
+    assert!(matches!(&logon, EngineMessage::Logon { client_id, cancel_previous_orders: true, .. }
+        if *client_id == ClientID::new("BANZAI".to_string(), None)
+    ), "{:?}", logon);
+
+    let order = parse(QUICKFIXJ_NEW_ORDER, Conformance::Strict);
+    assert!(matches!(&order, EngineMessage::NewOrder { account_id, client_order_id, instrument_id, order_type: OrdType::Limit, side: Side::Buy, quantity: 100, price, time_in_force: Some(TimeInForce::Day), .. }
+        if account_id == "BANZAI-1" && client_order_id.as_deref() == Some("1704205801250") && instrument_id == "AAPL" && *price == Some(Price::from(187.25))
+    ), "{:?}", order);
+}
+
+#[test]
+fn unknown_well_formed_tags_are_ignored() {
+    let order = encode(b"D", &[(9001, "ROUTE-7"), (1, "ACC"), (55, "AAPL"), (54, "2"), (38, "5"), (40, "1"), (5999, "x")]);
+    let parsed = parse(&order, Conformance::Strict);
+    assert!(matches!(parsed, EngineMessage::NewOrder { quantity: 5, order_type: OrdType::Market, .. }), "{:?}", parsed);
+}
+
+#[test]
+fn benign_deviations_are_refused_when_strict_and_looked_past_when_lenient() {
+    let order = encode(b"D", &[(1, "ACC"), (55, "AAPL"), (54, "1"), (53, "10"), (40, "2"), (44, "10.5")]);
+    let checksum_at = order.rfind("|10=").unwrap();
+    let deviants = [
+        (order.replace("8=FIXT.1.1", "8=FIX.4.4"), "BeginString FIX.4.4 is not FIXT.1.1"),
+        (encode(b"D", &[(1128, "6"), (1, "ACC"), (55, "AAPL"), (54, "1"), (53, "10"), (40, "2"), (44, "10.5")]), "ApplVerID 6 is not FIX 5.0"),
+        (format!("{}|10=7|", &order[..checksum_at]), "CheckSum 7 is not three digits"),
+        (format!("{}|10=000|", &order[..checksum_at]), "CheckSum 000 should be"),
+        (order.trim_end_matches('|').to_string(), "No separator after CheckSum"),
+    ];
+    for (deviant, reason) in deviants {
+        assert!(refusal(&deviant).starts_with(reason), "{}: {}", deviant, refusal(&deviant));
+        let parsed = parse(&deviant, Conformance::Lenient);
+        assert!(matches!(parsed, EngineMessage::NewOrder { quantity: 10, .. }), "{}: {:?}", deviant, parsed);
+    }
+}
+
+#[test]
+fn sessions_opened_with_soh_are_answered_in_soh() {
+    assert_eq!(Separator::of(QUICKFIXJ_LOGON), Separator::Soh);
+    assert_eq!(Separator::of(&encode(b"A", &[])), Separator::Pipe);
+
+    let logout = serialize_logout("bye");
+    assert_eq!(Separator::Pipe.apply(logout.clone()), logout);
+    let answered = Separator::Soh.apply([logout.clone(), logout].concat().into());
+    let answered = String::from_utf8_lossy(&answered);
+    // Back to back, as a FIX engine frames them, with no newline between
+    assert!(!answered.contains('\n'), "{:?}", answered);
+    let messages: Vec<String> = answered.split("8=FIXT").skip(1).map(|rest| format!("8=FIXT{}", rest)).collect();
+    assert_eq!(messages.len(), 2, "{:?}", answered);
+    for message in messages {
+        assert!(!message.contains('|') && message.contains("\x0158=bye\x01") && message.ends_with('\x01'), "{:?}", message);
+        // Checksums are redone for SOH, so a strict reader takes the reply as it stands
+        assert!(conform(&message, Conformance::Strict).is_ok(), "{:?}", message);
+    }
+}
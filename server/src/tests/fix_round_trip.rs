@@ -0,0 +1,174 @@
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+
+use crate::engine::EngineMessage;
+use crate::fix::{handle_fix_message, serialize_engine_message};
+
+pub(super) fn encode(msg_type: &[u8], fields: &[(u16, &str)]) -> String {
+    let mut encoder = Encoder::<Config>::default();
+    encoder.config_mut().set_separator(b'|');
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+    msg.set(SENDER_COMP_ID, "FIRM1");
+    msg.set(SENDER_SUB_ID, "ALGO");
+    msg.set(TARGET_COMP_ID, "EXCHANGE");
+    msg.set(SENDING_TIME, Timestamp::parse(b"20240102-14:30:00.125").unwrap());
+    for (tag, value) in fields {
+        msg.set_any(TagU16::new(*tag).unwrap(), *value);
+    }
+    String::from_utf8_lossy(msg.wrap()).into_owned()
+}
+
+// The receive time is stamped on every parse, so it is the one field allowed to differ
+fn comparable(mut message: EngineMessage) -> String {
+    match &mut message {
+        EngineMessage::NewOrder { receiving_time, .. }
+        | EngineMessage::NewOrderMultileg { receiving_time, .. }
+        | EngineMessage::CancelOrder { receiving_time, .. }
+        | EngineMessage::AmendOrder { receiving_time, .. }
+        | EngineMessage::OrderStatusRequest { receiving_time, .. }
+        | EngineMessage::CreateInstrument { receiving_time, .. }
+        | EngineMessage::UpdateInstrumentSpec { receiving_time, .. }
+        | EngineMessage::SetTradingStatus { receiving_time, .. }
+        | EngineMessage::RollSession { receiving_time, .. }
+        | EngineMessage::StartWarmUp { receiving_time, .. }
+        | EngineMessage::SymbolStatusRequest { receiving_time, .. }
+        | EngineMessage::ActivityRequest { receiving_time, .. }
+        | EngineMessage::SubscribeAlerts { receiving_time, .. }
+        | EngineMessage::UnsubscribeAlerts { receiving_time, .. } => {
+            *receiving_time = Timestamp::parse(b"20000101-00:00:00.000").unwrap();
+        }
+        EngineMessage::Ping { received_at, .. } => *received_at = Timestamp::parse(b"20000101-00:00:00.000").unwrap(),
+        _ => {}
+    }
+    format!("{:?}", message)
+}
+
+fn assert_round_trips(canonical: &str) {
+    let parsed = handle_fix_message(canonical);
+    assert!(!matches!(parsed, EngineMessage::InvalidMessage { .. }), "canonical message rejected: {:?}", parsed);
+
+    let serialized = serialize_engine_message(&parsed).expect("message has no FIX encoding");
+    let reparsed = handle_fix_message(serialized.trim());
+    assert_eq!(comparable(reparsed), comparable(parsed), "round trip through {}", serialized.trim());
+}
+
+#[test]
+fn new_order_single_round_trips() {
+    assert_round_trips(&encode(b"D", &[
+        (1, "ACC1"),
+        (11, "CLIENT-7"),
+        (55, "AAPL"),
+        (54, "1"),
+        (53, "100"),
+        (40, "2"),
+        (44, "101.25"),
+        (59, "6"),
+        (126, "20240105-21:00:00.000"),
+    ]));
+    // Market order without the optional fields
+    assert_round_trips(&encode(b"D", &[(1, "ACC1"), (55, "AAPL"), (54, "2"), (53, "5"), (40, "1")]));
+    // For the AAPL of one venue rather than another's
+    assert_round_trips(&encode(b"D", &[(1, "ACC1"), (55, "AAPL"), (207, "XNAS"), (54, "1"), (53, "5"), (40, "1")]));
+    // With a NoStipulations(232) group where FIX puts it, ahead of the quantity, type and price
+    assert_round_trips(&encode(b"D", &[
+        (1, "ACC1"),
+        (55, "AAPL"),
+        (54, "1"),
+        (232, "2"),
+        (233, "MINQTY"),
+        (234, "100"),
+        (233, "LOTVAR"),
+        (234, "5"),
+        (53, "100"),
+        (40, "2"),
+        (44, "101.25"),
+    ]));
+}
+
+#[test]
+fn cancel_round_trips() {
+    assert_round_trips(&encode(b"F", &[(37, "42"), (1, "ACC1")]));
+    // A partial cancel
+    assert_round_trips(&encode(b"F", &[(37, "42"), (1, "ACC1"), (84, "3")]));
+    // By the ClOrdID the order was sent with, with or without its OrderID
+    assert_round_trips(&encode(b"F", &[(41, "CLIENT-7"), (1, "ACC1")]));
+    assert_round_trips(&encode(b"F", &[(37, "42"), (41, "CLIENT-7"), (1, "ACC1")]));
+}
+
+#[test]
+fn amend_round_trips() {
+    assert_round_trips(&encode(b"G", &[(37, "42"), (38, "250"), (44, "99.5"), (59, "1")]));
+    assert_round_trips(&encode(b"G", &[(37, "42"), (38, "250")]));
+}
+
+#[test]
+fn order_status_request_round_trips() {
+    assert_round_trips(&encode(b"H", &[(37, "42")]));
+}
+
+#[test]
+fn create_instrument_round_trips() {
+    assert_round_trips(&encode(b"UCI", &[(55, "AAPL"), (8001, "10"), (8002, "R"), (8008, "500")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "MSFT")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "ES"), (1300, "FUTURES"), (561, "5"), (969, "0.25")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "AAPL"), (8013, "3")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "AUCT"), (8026, "2 4")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "AUCT"), (8029, "A")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "FRZN"), (8037, "F")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "NEAR"), (8038, "0.5"), (8039, "C")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "WIDE"), (8048, "5"), (8049, "0.5"), (8050, "60")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "AAPL-C100"), (311, "AAPL"), (202, "100"), (201, "1"), (8032, "20250117-21:00:00.000"), (1188, "0.25")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "ESZ4-ESH5"), (8027, "ESZ4:1 ESH5:-1")]));
+    assert_round_trips(&encode(b"UCI", &[(55, "ESZ4-ESH5"), (8027, "ESZ4:1 ESH5:-1"), (8028, "ESH5")]));
+    assert_round_trips(&encode(b"UUS", &[(55, "AAPL"), (969, "0.05"), (561, "100")]));
+}
+
+#[test]
+fn segment_admin_round_trips() {
+    assert_round_trips(&encode(b"UTS", &[(1300, "FUTURES"), (326, "2")]));
+    assert_round_trips(&encode(b"UTS", &[(55, "AAPL"), (326, "3")]));
+    assert_round_trips(&encode(b"URS", &[(1300, "FUTURES")]));
+    assert_round_trips(&encode(b"URS", &[]));
+    assert_round_trips(&encode(b"UWU", &[(1300, "EQUITIES"), (342, "20240102-14:30:00.000")]));
+}
+
+#[test]
+fn symbol_status_request_round_trips() {
+    assert_round_trips(&encode(b"USR", &[(55, "AAPL")]));
+    assert_round_trips(&encode(b"USR", &[]));
+}
+
+#[test]
+fn new_order_multileg_round_trips() {
+    assert_round_trips(&encode(b"AB", &[
+        (1, "ACC1"),
+        (11, "SPREAD-1"),
+        (555, "2"),
+        (600, "ESZ4"),
+        (624, "1"),
+        (687, "3"),
+        (566, "101.25"),
+        (600, "ESH5"),
+        (624, "2"),
+        (687, "3"),
+    ]));
+}
+
+#[test]
+fn activity_query_round_trips() {
+    assert_round_trips(&encode(b"UAQ", &[(55, "AAPL")]));
+}
+
+#[test]
+fn ping_round_trips() {
+    assert_round_trips(&encode(b"UPN", &[(8040, "42")]));
+}
+
+#[test]
+fn alert_subscription_round_trips() {
+    assert_round_trips(&encode(b"UAS", &[(263, "1")]));
+    assert_round_trips(&encode(b"UAS", &[(263, "2")]));
+}
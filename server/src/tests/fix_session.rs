@@ -0,0 +1,181 @@
+// A whole FIX session over TCP, as a FIX engine holds one: SOH framing, MsgSeqNum(34) both
+// ways and every message checked the way a strict counterparty checks it. The same session
+// against a real QuickFIX engine only runs on request, with python3 and quickfix installed
+// and QUICKFIX_SPEC naming QuickFIX's dictionaries, as tests/quickfix/counterparty.py says:
+//   cargo test -p exchange-server fix_session -- --ignored --nocapture
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use fefix::definitions::fix50::*;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::config::ServerConfig;
+use crate::credentials::Credentials;
+use crate::tests::logon_credentials::{connect, message, start_server_with};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+// A client that speaks FIX as an engine does, checking each message it reads as it goes
+struct Counterparty {
+    comp_id: &'static str,
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+    buffer: Vec<u8>,
+    sent: u64,
+    received: u64,
+}
+
+impl Counterparty {
+    async fn connect(address: SocketAddr, comp_id: &'static str) -> Self {
+        let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+        Self { comp_id, reader, writer, buffer: Vec::new(), sent: 0, received: 0 }
+    }
+
+    async fn send(&mut self, msg_type: &[u8], fields: &[(u16, &str)]) {
+        self.sent += 1;
+        let mut encoder = Encoder::<Config>::default();
+        let mut buffer = Vec::new();
+        let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+        msg.set(SENDER_COMP_ID, self.comp_id);
+        msg.set(TARGET_COMP_ID, "EXCHANGE");
+        msg.set(MSG_SEQ_NUM, self.sent);
+        msg.set(SENDING_TIME, chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string().as_str());
+        for (tag, value) in fields {
+            msg.set_any(TagU16::new(*tag).unwrap(), *value);
+        }
+        self.writer.write_all(msg.wrap()).await.unwrap();
+    }
+
+    // The next message, framed by its BodyLength as FIX engines frame, once it has passed
+    // every check a strict engine makes of a session's messages
+    async fn receive(&mut self) -> Vec<(String, String)> {
+        let message = loop {
+            if let Some(length) = frame_length(&self.buffer) {
+                if self.buffer.len() >= length {
+                    break self.buffer.drain(..length).collect::<Vec<u8>>();
+                }
+            }
+            let mut chunk = [0; 4096];
+            let read = timeout(WAIT, self.reader.read(&mut chunk)).await.expect("timed out").unwrap();
+            assert!(read > 0, "connection closed mid message: {:?}", String::from_utf8_lossy(&self.buffer));
+            self.buffer.extend_from_slice(&chunk[..read]);
+        };
+        let text = String::from_utf8(message).unwrap();
+        let fields: Vec<(String, String)> = text
+            .strip_suffix('\x01')
+            .unwrap_or_else(|| panic!("no SOH after CheckSum: {:?}", text))
+            .split('\x01')
+            .map(|field| field.split_once('=').map(|(tag, value)| (tag.to_string(), value.to_string())).unwrap_or_else(|| panic!("{:?} in {:?}", field, text)))
+            .collect();
+
+        let tags: Vec<&str> = fields.iter().map(|(tag, _)| tag.as_str()).collect();
+        assert_eq!(tags[..3], ["8", "9", "35"], "{:?}", text);
+        assert_eq!(tags.last(), Some(&"10"), "{:?}", text);
+        let checksum_at = text.rfind("\x0110=").unwrap() + 1;
+        let sum = text.as_bytes()[..checksum_at].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(fields.last().unwrap().1, format!("{:03}", sum), "{:?}", text);
+        // The header's fields all come ahead of the body's
+        let header = ["8", "9", "35", "49", "56", "57", "34", "52"];
+        let header_len = tags.iter().take_while(|tag| header.contains(tag)).count();
+        assert!(tags[header_len..].iter().all(|tag| !header.contains(tag)), "header field in the body of {:?}", text);
+
+        let field = |tag: &str| fields.iter().find(|(t, _)| t == tag).map(|(_, value)| value.as_str());
+        assert_eq!((field("49"), field("56")), (Some("EXCHANGE"), Some(self.comp_id)), "{:?}", text);
+        assert!(field("52").is_some(), "{:?}", text);
+        self.received += 1;
+        // Zero padded, as the session stamps it in place, which FIX's int fields allow
+        assert_eq!(field("34").and_then(|seq_num| seq_num.parse().ok()), Some(self.received), "{:?}", text);
+        assert_ne!(field("35"), Some("3"), "rejected: {:?}", text);
+        fields
+    }
+
+    async fn closed(&mut self) -> bool {
+        self.buffer.is_empty() && timeout(WAIT, self.reader.read(&mut [0; 1])).await.expect("timed out").unwrap() == 0
+    }
+}
+
+// The length of the message the buffer starts with, once its header says so
+fn frame_length(buffer: &[u8]) -> Option<usize> {
+    let begin = b"8=FIXT.1.1\x019=";
+    if buffer.len() < begin.len() {
+        return None;
+    }
+    assert_eq!(&buffer[..begin.len()], begin, "not a message: {:?}", String::from_utf8_lossy(buffer));
+    let digits = buffer[begin.len()..].iter().position(|byte| *byte == b'\x01')?;
+    let body_length: usize = std::str::from_utf8(&buffer[begin.len()..begin.len() + digits]).unwrap().parse().unwrap();
+    // The body runs from after BodyLength's SOH to CheckSum(10), whose "10=nnn\x01" is seven bytes
+    Some(begin.len() + digits + 1 + body_length + 7)
+}
+
+fn value<'a>(fields: &'a [(String, String)], tag: &str) -> Option<&'a str> {
+    fields.iter().find(|(t, _)| t == tag).map(|(_, value)| value.as_str())
+}
+
+// A server with AAPL listed, listed on a side connection that waits for its Ping to come
+// back, so the listing is done before anything else reaches the engine
+async fn listed_server(admin: &str) -> SocketAddr {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(message(b"UCI", admin, &[(55, "AAPL")]).as_bytes()).await.unwrap();
+    writer.write_all(message(b"UPN", admin, &[(8040, "1")]).as_bytes()).await.unwrap();
+    let pong = timeout(WAIT, lines.next_line()).await.unwrap().unwrap().unwrap();
+    assert!(pong.contains("|35=UPO|"), "{}", pong);
+    address
+}
+
+#[tokio::test]
+async fn a_session_runs_from_logon_to_logout_as_a_fix_engine_expects() {
+    let address = listed_server("SESSION_ADMIN").await;
+    let mut session = Counterparty::connect(address, "SESSION_E2E").await;
+
+    session.send(b"A", &[(98, "0"), (108, "30"), (141, "Y"), (1137, "9")]).await;
+    let logon = session.receive().await;
+    assert_eq!(value(&logon, "35"), Some("A"));
+    assert_eq!((value(&logon, "98"), value(&logon, "108"), value(&logon, "141"), value(&logon, "1137")), (Some("0"), Some("30"), Some("Y"), Some("9")));
+
+    session.send(b"D", &[(1, "SESSION_E2E"), (11, "ORDER-1"), (55, "AAPL"), (54, "1"), (38, "5"), (40, "2"), (44, "10"), (60, "20240102-14:30:00.000")]).await;
+    let accepted = session.receive().await;
+    // What FIX 5.0 has every ExecutionReport carry
+    for tag in ["37", "17", "150", "39", "55", "54", "151", "14"] {
+        assert!(value(&accepted, tag).is_some(), "no {} in {:?}", tag, accepted);
+    }
+    assert_eq!((value(&accepted, "35"), value(&accepted, "150"), value(&accepted, "39")), (Some("8"), Some("0"), Some("0")));
+    assert_eq!((value(&accepted, "11"), value(&accepted, "151"), value(&accepted, "14")), (Some("ORDER-1"), Some("5"), Some("0")));
+    let order_id = value(&accepted, "37").unwrap().to_string();
+
+    session.send(b"1", &[(112, "PROBE")]).await;
+    let heartbeat = session.receive().await;
+    assert_eq!((value(&heartbeat, "35"), value(&heartbeat, "112")), (Some("0"), Some("PROBE")));
+    // A Heartbeat only keeps the session alive, and is not answered
+    session.send(b"0", &[]).await;
+
+    session.send(b"F", &[(1, "SESSION_E2E"), (37, &order_id), (41, "ORDER-1"), (11, "CANCEL-1"), (55, "AAPL"), (54, "1"), (60, "20240102-14:30:01.000")]).await;
+    let cancelled = session.receive().await;
+    assert_eq!((value(&cancelled, "35"), value(&cancelled, "150"), value(&cancelled, "39")), (Some("8"), Some("4"), Some("4")));
+    assert_eq!((value(&cancelled, "37"), value(&cancelled, "54"), value(&cancelled, "151"), value(&cancelled, "14")), (Some(order_id.as_str()), Some("1"), Some("0"), Some("0")));
+    assert_ne!(value(&cancelled, "17"), value(&accepted, "17"));
+
+    session.send(b"5", &[]).await;
+    assert_eq!(value(&session.receive().await, "35"), Some("5"));
+    assert!(session.closed().await);
+}
+
+// Drives the session with QuickFIX itself: the script logs on, sends a NewOrderSingle, cancels
+// it once it is accepted and logs out, exiting non-zero on a Reject or anything unexpected
+#[tokio::test]
+#[ignore]
+async fn a_quickfix_engine_holds_a_session_without_rejects() {
+    let address = listed_server("QUICKFIX_ADMIN").await;
+    let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/quickfix/counterparty.py");
+    let run = tokio::process::Command::new("python3").arg(script).arg(address.port().to_string()).output();
+    let output = timeout(Duration::from_secs(60), run).await.expect("counterparty timed out").expect("python3 did not start");
+    let log = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    println!("{}", log);
+    assert!(output.status.success(), "{}", log);
+}
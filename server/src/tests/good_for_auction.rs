@@ -0,0 +1,62 @@
+use fefix::definitions::fix50::Side;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+use crate::instrument::{HaltPolicy, UncrossPolicy};
+use crate::types::{OrderID, Price, Quantity};
+
+fn auction_order(exchange: &mut Exchange, firm: &str, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderSingle::builder().sender(firm).account(firm).symbol("AAPL").side(side).qty(quantity).limit(price).good_for_auction().parse())
+}
+
+fn fills(events: &[EngineMessage]) -> Vec<(OrderID, Quantity, Price)> {
+    events.iter().filter_map(|event| match event {
+        EngineMessage::OrderFilled { order_id, filled_quantity, price, .. } => Some((*order_id, *filled_quantity, *price)),
+        _ => None,
+    }).collect()
+}
+
+// A good-for-auction bid trades at the open and again when a halt lifts, and never against
+// what arrives while the book trades continuously in between
+#[test]
+fn a_good_for_auction_order_trades_only_in_auctions_and_rests_between_them() {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(AdvanceTime::builder().sender("ADMIN").to("20240102-14:00:00.000").parse());
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").halt_policy(HaltPolicy::Freeze).uncross_policy(UncrossPolicy::Auction).parse());
+    exchange.handle_message(WarmUp::builder().sender("ADMIN").symbol("AAPL").open_time("20240102-14:30:00.000").parse());
+
+    let auction_bid = accepted_order_id(&auction_order(&mut exchange, "FIRM1", Side::Buy, 2, 10.0));
+    order(&mut exchange, "FIRM2", Side::Sell, 1, 10.0);
+    // Half of the bid fills in the opening auction
+    let opened = exchange.handle_message(AdvanceTime::builder().sender("ADMIN").to("20240102-14:30:00.000").parse());
+    assert!(fills(&opened).contains(&(auction_bid, 1, Price::from(10.0))), "{:?}", opened);
+
+    // Then it waits out continuous trading: an ask at its price goes to a later bid instead
+    let ask = order(&mut exchange, "FIRM6", Side::Sell, 1, 10.0);
+    assert!(fills(&ask).is_empty(), "{:?}", ask);
+    let continuous = order(&mut exchange, "FIRM3", Side::Buy, 1, 10.0);
+    assert_eq!(fills(&continuous).len(), 2, "{:?}", continuous);
+    assert!(fills(&continuous).iter().all(|(order_id, ..)| *order_id != auction_bid));
+    let below = order(&mut exchange, "FIRM4", Side::Sell, 1, 9.5);
+    assert!(fills(&below).is_empty(), "{:?}", below);
+    let auction_ask = accepted_order_id(&auction_order(&mut exchange, "FIRM5", Side::Sell, 1, 11.0));
+
+    exchange.handle_message(TradingStatus::builder().sender("ADMIN").symbol("AAPL").halt().parse());
+    let resumed = exchange.handle_message(TradingStatus::builder().sender("ADMIN").symbol("AAPL").resume().parse());
+    let resumed = fills(&resumed);
+    assert!(resumed.iter().any(|(order_id, quantity, _)| (*order_id, *quantity) == (auction_bid, 1)), "{:?}", resumed);
+    assert!(resumed.iter().all(|(order_id, ..)| *order_id != auction_ask), "{:?}", resumed);
+
+    // Too dear to trade, the auction ask waits for the next auction, and can be cancelled meanwhile
+    let cancelled = exchange.handle_message(OrderCancelRequest::builder().sender("FIRM5").account("FIRM5").order_id(auction_ask).parse());
+    assert!(matches!(cancelled.as_slice(), [EngineMessage::OrderCancelled { order_id, .. }] if *order_id == auction_ask), "{:?}", cancelled);
+}
+
+#[test]
+fn a_good_for_auction_order_must_be_a_limit_order() {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(CreateInstrument::builder().symbol("AAPL").parse());
+    let market = exchange.handle_message(NewOrderSingle::builder().account("FIRM1").symbol("AAPL").side(Side::Buy).qty(1).market().good_for_auction().parse());
+    assert!(matches!(market.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "GoodForAuction order must be a limit order"), "{:?}", market);
+}
@@ -0,0 +1,48 @@
+use fefix::fix_values::Timestamp;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::{handle_fix_message, serialize_engine_message};
+use crate::tests::fix_round_trip::encode;
+use crate::types::ClientID;
+
+fn send(exchange: &mut Exchange, msg_type: &[u8], fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    exchange.handle_message(handle_fix_message(&encode(msg_type, fields)))
+}
+
+fn limit_order(exchange: &mut Exchange, account: &str, instrument_id: &str, side: &str, quantity: &str, price: &str) -> Vec<EngineMessage> {
+    send(exchange, b"D", &[(1, account), (55, instrument_id), (54, side), (53, quantity), (40, "2"), (44, price)])
+}
+
+#[test]
+fn an_accounts_greeks_come_from_its_options_priced_off_the_underlyings_last_trade() {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(EngineMessage::AdvanceTime {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        timestamp: Timestamp::parse(b"20240102-14:30:00.000").unwrap(),
+    });
+    send(&mut exchange, b"UCI", &[(55, "AAPL")]);
+    let option = [(55, "AAPL-C100"), (311, "AAPL"), (202, "100"), (201, "1"), (8032, "20250102-14:30:00.000"), (1188, "0.2")];
+    send(&mut exchange, b"UCI", &option);
+
+    // Ten at-the-money calls, a year out, and a share of the underlying to hedge them
+    limit_order(&mut exchange, "WRITER", "AAPL-C100", "2", "10", "2");
+    limit_order(&mut exchange, "HOLDER", "AAPL-C100", "1", "10", "2");
+    limit_order(&mut exchange, "WRITER", "AAPL", "2", "1", "100");
+    limit_order(&mut exchange, "HOLDER", "AAPL", "1", "1", "100");
+
+    let report = send(&mut exchange, b"UGQ", &[(1, "HOLDER")]);
+    let [EngineMessage::GreeksReport { account_id, greeks, .. }] = report.as_slice() else { panic!("{:?}", report) };
+    assert_eq!(account_id, "HOLDER");
+    // A call's delta is N(d1), with d1 a little over 0.1 here; the share adds one
+    assert!((greeks.delta - (1.0 + 10.0 * 0.5399)).abs() < 1e-3, "{:?}", greeks);
+    assert!((greeks.gamma - 10.0 * 0.01982).abs() < 1e-4, "{:?}", greeks);
+    assert!((greeks.vega - 10.0 * 39.749).abs() < 0.01, "{:?}", greeks);
+    let wire = serialize_engine_message(&report[0]).unwrap();
+    assert!(wire.contains("|35=UGR|") && wire.contains("|1=HOLDER|") && wire.contains("|8033=6.39"), "{}", wire);
+
+    let unknown = send(&mut exchange, b"UGQ", &[(1, "NOBODY")]);
+    assert!(matches!(unknown.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown account"), "{:?}", unknown);
+}
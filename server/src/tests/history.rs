@@ -0,0 +1,168 @@
+use fefix::definitions::fix50::{OrdStatus, OrdType, Side};
+use fefix::fix_values::Timestamp;
+
+use crate::audit::{HistoryPage, TradeQuery, TradeRecord};
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::{advance_time, client};
+use crate::instrument::SpecOverrides;
+use crate::types::{Price, Quantity};
+
+fn at(time: &str) -> Timestamp {
+    Timestamp::parse(time.as_bytes()).unwrap()
+}
+
+fn limit_order(exchange: &mut Exchange, account: &str, instrument_id: &str, side: Side, quantity: Quantity) {
+    exchange.handle_message(EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(account),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: instrument_id.to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity,
+        price: Some(Price::from(10.0)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    });
+}
+
+// SELLER's order 1 rests 5 AAPL and fills at 14:00, 14:05 and 14:10, as BUYER's orders 2, 3 and
+// 6 take 2, 2 and 1 of it: trades 1, 2 and 4. In between, order 5 buys MSFT from order 4: trade 3.
+fn scripted_session(mut exchange: Exchange) -> Exchange {
+    for instrument_id in ["AAPL", "MSFT"] {
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: instrument_id.to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+    }
+    advance_time(&mut exchange, "20240102-14:00:00.000");
+    limit_order(&mut exchange, "SELLER", "AAPL", Side::Sell, 5);
+    limit_order(&mut exchange, "BUYER", "AAPL", Side::Buy, 2);
+    advance_time(&mut exchange, "20240102-14:05:00.000");
+    limit_order(&mut exchange, "BUYER", "AAPL", Side::Buy, 2);
+    limit_order(&mut exchange, "SELLER", "MSFT", Side::Sell, 1);
+    limit_order(&mut exchange, "OTHER", "MSFT", Side::Buy, 1);
+    advance_time(&mut exchange, "20240102-14:10:00.000");
+    limit_order(&mut exchange, "BUYER", "AAPL", Side::Buy, 1);
+    exchange
+}
+
+fn query(instrument_id: Option<&str>, account_id: Option<&str>, from: &str, to: &str) -> TradeQuery {
+    TradeQuery {
+        instrument_id: instrument_id.map(str::to_string),
+        account_id: account_id.map(str::to_string),
+        from: at(from),
+        to: at(to),
+        after: None,
+        limit: None,
+    }
+}
+
+fn trade_ids(page: &HistoryPage<TradeRecord, u64>) -> Vec<u64> {
+    page.entries.iter().map(|trade| trade.trade_match_id).collect()
+}
+
+#[test]
+fn trades_are_found_by_instrument_account_and_time_a_page_at_a_time() {
+    let exchange = scripted_session(Exchange::new());
+    let (open, close) = ("20240102-14:00:00.000", "20240102-16:00:00.000");
+
+    let aapl = exchange.trade_history(&query(Some("AAPL"), None, open, close));
+    assert_eq!((trade_ids(&aapl), aapl.next), (vec![1, 2, 4], None));
+    let trade = &aapl.entries[1];
+    assert_eq!((trade.price, trade.quantity, trade.timestamp.clone()), (Price::from(10.0), 2, at("20240102-14:05:00.000")));
+    assert_eq!((trade.buyer.order_id, trade.seller.order_id), (3, 1));
+
+    // Either side of the trade counts for an account; the range takes in both of its ends
+    assert_eq!(trade_ids(&exchange.trade_history(&query(None, Some("SELLER"), open, close))), vec![1, 2, 3, 4]);
+    assert_eq!(trade_ids(&exchange.trade_history(&query(None, Some("OTHER"), open, close))), vec![3]);
+    assert_eq!(trade_ids(&exchange.trade_history(&query(Some("MSFT"), Some("BUYER"), open, close))), Vec::<u64>::new());
+    let five_past = "20240102-14:05:00.000";
+    assert_eq!(trade_ids(&exchange.trade_history(&query(None, None, five_past, five_past))), vec![2, 3]);
+
+    // Each page's next goes back as the following page's after, until there are no more
+    let mut paged = query(None, None, open, close);
+    paged.limit = Some(3);
+    let first = exchange.trade_history(&paged);
+    assert_eq!((trade_ids(&first), first.next), (vec![1, 2, 3], Some(3)));
+    paged.after = first.next;
+    let second = exchange.trade_history(&paged);
+    assert_eq!((trade_ids(&second), second.next), (vec![4], None));
+}
+
+#[test]
+fn an_orders_history_is_every_report_it_was_sent_with_its_status_and_cause() {
+    let mut exchange = scripted_session(Exchange::new());
+    exchange.handle_message(EngineMessage::CancelOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("SELLER"),
+        account_id: "SELLER".to_string(),
+        order_id: 1,
+        orig_client_order_id: None,
+        cancel_quantity: None,
+    });
+
+    let history = exchange.order_history(1, None, None).unwrap();
+    assert_eq!(history.next, None);
+    let steps: Vec<_> = history
+        .entries
+        .iter()
+        .map(|event| {
+            let report = match &event.report {
+                EngineMessage::OrderAccepted { .. } => "accepted".to_string(),
+                EngineMessage::OrderFilled { filled_quantity, trade_match_id, .. } => format!("filled {} in trade {}", filled_quantity, trade_match_id),
+                EngineMessage::CancelRejected { .. } => "cancel rejected".to_string(),
+                report => panic!("unexpected report {:?}", report),
+            };
+            (fefix::FixValue::to_string(&event.timestamp), event.status, report, event.cause.as_str(), event.cause_client_id.clone())
+        })
+        .collect();
+    let (seller, buyer) = (Some(client("SELLER")), Some(client("BUYER")));
+    let expected = vec![
+        ("20240102-14:00:00.000".to_string(), OrdStatus::New, "accepted".to_string(), "new_order", seller.clone()),
+        ("20240102-14:00:00.000".to_string(), OrdStatus::PartiallyFilled, "filled 2 in trade 1".to_string(), "new_order", buyer.clone()),
+        ("20240102-14:05:00.000".to_string(), OrdStatus::PartiallyFilled, "filled 2 in trade 2".to_string(), "new_order", buyer.clone()),
+        ("20240102-14:10:00.000".to_string(), OrdStatus::Filled, "filled 1 in trade 4".to_string(), "new_order", buyer),
+        // Too late to cancel, and the history says so without changing the status
+        ("20240102-14:10:00.000".to_string(), OrdStatus::Filled, "cancel rejected".to_string(), "cancel_order", seller),
+    ];
+    assert_eq!(steps, expected);
+
+    let first = exchange.order_history(1, None, Some(2)).unwrap();
+    assert_eq!((first.entries.len(), first.next), (2, Some(1)));
+    let rest = exchange.order_history(1, first.next, Some(2)).unwrap();
+    assert_eq!((rest.entries.len(), rest.next), (2, Some(3)));
+    assert!(matches!(rest.entries[0].report, EngineMessage::OrderFilled { trade_match_id: 2, .. }), "{:?}", rest.entries[0]);
+    let last = exchange.order_history(1, rest.next, Some(2)).unwrap();
+    assert_eq!((last.entries.len(), last.next), (1, None));
+
+    // Asked through the engine, an order it never saw is refused
+    let unknown = exchange.handle_message(EngineMessage::OrderHistoryQuery {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("ADMIN"),
+        order_id: 99,
+        after: None,
+        limit: None,
+    });
+    assert!(matches!(unknown.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown order"), "{:?}", unknown);
+}
+
+#[test]
+fn only_the_most_recent_trades_are_retained() {
+    let exchange = scripted_session(Exchange::new().with_trade_log_retention(2));
+    let page = exchange.trade_history(&query(None, None, "20240102-14:00:00.000", "20240102-16:00:00.000"));
+    assert_eq!((trade_ids(&page), page.next), (vec![3, 4], None));
+}
@@ -0,0 +1,56 @@
+use fefix::definitions::fix50::{OrdType, Side};
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+
+fn rejection(events: &[EngineMessage]) -> &str {
+    match events {
+        [EngineMessage::OrderRejected { reason, .. }] => reason,
+        _ => panic!("not rejected: {:?}", events),
+    }
+}
+
+// AAPL listed on a 0.01 tick in lots of 10, taking only limit orders
+fn listed() -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").tick_size(0.01).lot_size(10).order_types(&[OrdType::Limit]).parse());
+    exchange
+}
+
+#[test]
+fn the_order_after_an_update_is_held_to_the_new_tick_and_lot_sizes() {
+    let mut exchange = listed();
+    accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 10, 10.01));
+
+    let events = exchange.handle_message(InstrumentSpecUpdate::builder().sender("ADMIN").symbol("AAPL").tick_size(0.05).lot_size(5).parse());
+    assert!(matches!(&events[..], [EngineMessage::LogEvent { message, .. }] if message == "Spec for AAPL updated"), "{:?}", events);
+    assert_eq!(rejection(&order(&mut exchange, "FIRM1", Side::Buy, 10, 10.01)), "Price is not a multiple of the tick size");
+    assert_eq!(rejection(&order(&mut exchange, "FIRM1", Side::Buy, 7, 10.05)), "Quantity is not a multiple of the lot size");
+    accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 5, 10.05));
+
+    // The bid resting off the old tick still trades, behind the one on the new
+    let events = order(&mut exchange, "FIRM2", Side::Sell, 10, 10.0);
+    let prices: Vec<f64> = events.iter().filter_map(|event| match event {
+        EngineMessage::OrderFilled { client_id, price, .. } if client_id.to_string() == "FIRM1" => Some(price.into_inner()),
+        _ => None,
+    }).collect();
+    assert_eq!(prices, [10.05, 10.01], "{:?}", events);
+}
+
+#[test]
+fn an_update_leaves_the_settings_it_does_not_give_as_they_were() {
+    let mut exchange = listed();
+    exchange.handle_message(InstrumentSpecUpdate::builder().sender("ADMIN").symbol("AAPL").tick_size(0.05).parse());
+
+    assert_eq!(rejection(&order(&mut exchange, "FIRM1", Side::Buy, 5, 10.05)), "Quantity is not a multiple of the lot size");
+    let market = exchange.handle_message(NewOrderSingle::builder().sender("FIRM1").account("FIRM1").symbol("AAPL").side(Side::Buy).qty(10).market().parse());
+    assert!(matches!(&market[..], [EngineMessage::OrderRejected { .. }]), "{:?}", market);
+}
+
+#[test]
+fn an_update_to_an_unlisted_instrument_is_rejected() {
+    let mut exchange = Exchange::new();
+    let events = exchange.handle_message(InstrumentSpecUpdate::builder().sender("ADMIN").symbol("AAPL").tick_size(0.05).parse());
+    assert_eq!(rejection(&events), "Unknown instrument");
+}
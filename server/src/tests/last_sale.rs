@@ -0,0 +1,90 @@
+use fefix::definitions::fix50::Side;
+
+use crate::engine::{AggressorSide, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::serialize_engine_message;
+use crate::fix::testkit::*;
+use crate::types::*;
+
+// (subscriber, instrument, price, quantity, aggressor) for each tape entry, in order
+fn tape(events: &[EngineMessage]) -> Vec<(String, InstrumentID, f64, Quantity, AggressorSide)> {
+    events.iter().filter_map(|event| match event {
+        EngineMessage::LastSaleTape { client_id, instrument_id, price, quantity, side, .. } => Some((client_id.to_string(), instrument_id.clone(), price.into_inner(), *quantity, *side)),
+        _ => None,
+    }).collect()
+}
+
+fn listed(symbols: &[&str]) -> Exchange {
+    let mut exchange = Exchange::new();
+    for symbol in symbols {
+        exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol(symbol).parse());
+    }
+    exchange
+}
+
+#[test]
+fn each_fill_goes_on_the_tape_with_the_side_that_took_liquidity() {
+    let mut exchange = listed(&["AAPL"]);
+    exchange.handle_message(MarketDataRequest::builder().sender("TAPE").subscribe().symbols(&["AAPL"]).last_sale(true).parse());
+    exchange.handle_message(MarketDataRequest::builder().sender("VIEWER").subscribe().symbols(&["AAPL"]).parse());
+
+    order_in(&mut exchange, "FIRM2", "AAPL", Side::Sell, 2, 10.0);
+    order_in(&mut exchange, "FIRM3", "AAPL", Side::Sell, 1, 11.0);
+    let events = order_in(&mut exchange, "FIRM1", "AAPL", Side::Buy, 3, 11.0);
+    assert_eq!(tape(&events), [
+        ("TAPE".to_string(), "AAPL".to_string(), 10.0, 2, AggressorSide::Buy),
+        ("TAPE".to_string(), "AAPL".to_string(), 11.0, 1, AggressorSide::Buy),
+    ]);
+    let trade_ids: Vec<u64> = events.iter().filter_map(|event| match event {
+        EngineMessage::LastSaleTape { trade_id, .. } => Some(*trade_id),
+        _ => None,
+    }).collect();
+    let match_ids: Vec<u64> = events.iter().filter_map(|event| match event {
+        EngineMessage::OrderFilled { client_id, trade_match_id, .. } if client_id.to_string() == "FIRM1" => Some(*trade_match_id),
+        _ => None,
+    }).collect();
+    assert_eq!(trade_ids, match_ids);
+
+    order_in(&mut exchange, "FIRM4", "AAPL", Side::Buy, 1, 9.0);
+    let events = order_in(&mut exchange, "FIRM5", "AAPL", Side::Sell, 1, 9.0);
+    assert_eq!(tape(&events), [("TAPE".to_string(), "AAPL".to_string(), 9.0, 1, AggressorSide::Sell)]);
+
+    // Resting orders put nothing on the tape, and unsubscribing takes the client off it
+    assert!(tape(&order_in(&mut exchange, "FIRM6", "AAPL", Side::Buy, 1, 8.0)).is_empty());
+    exchange.handle_message(MarketDataRequest::builder().sender("TAPE").unsubscribe().symbols(&["AAPL"]).parse());
+    assert!(tape(&order_in(&mut exchange, "FIRM7", "AAPL", Side::Sell, 1, 8.0)).is_empty());
+}
+
+// A wildcard takes the books listed after it, and subscribing again without the feed stops it
+#[test]
+fn a_wildcard_tape_covers_books_listed_later() {
+    let mut exchange = listed(&["AAPL"]);
+    exchange.handle_message(MarketDataRequest::builder().sender("TAPE").subscribe().symbols(&["*"]).last_sale(true).parse());
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("MSFT").parse());
+
+    order_in(&mut exchange, "FIRM2", "MSFT", Side::Sell, 1, 20.0);
+    let events = order_in(&mut exchange, "FIRM1", "MSFT", Side::Buy, 1, 20.0);
+    assert_eq!(tape(&events), [("TAPE".to_string(), "MSFT".to_string(), 20.0, 1, AggressorSide::Buy)]);
+
+    exchange.handle_message(MarketDataRequest::builder().sender("TAPE").subscribe().symbols(&["*"]).parse());
+    order_in(&mut exchange, "FIRM2", "AAPL", Side::Sell, 1, 10.0);
+    assert!(tape(&order_in(&mut exchange, "FIRM1", "AAPL", Side::Buy, 1, 10.0)).is_empty());
+}
+
+#[test]
+fn the_tape_is_a_new_trade_capture_report_flagging_the_aggressor() {
+    let mut exchange = listed(&["AAPL"]);
+    exchange.handle_message(MarketDataRequest::builder().sender("TAPE").subscribe().symbols(&["AAPL"]).last_sale(true).parse());
+    order_in(&mut exchange, "FIRM1", "AAPL", Side::Buy, 4, 10.5);
+    let events = order_in(&mut exchange, "FIRM2", "AAPL", Side::Sell, 4, 10.5);
+    let entry = events.iter().find(|event| matches!(event, EngineMessage::LastSaleTape { .. })).unwrap();
+
+    let report = serialize_engine_message(entry).unwrap();
+    let fields: Vec<(&str, &str)> = report.trim().split('|').filter_map(|pair| pair.split_once('=')).collect();
+    let field = |tag: &str| fields.iter().find(|(t, _)| *t == tag).map(|(_, value)| *value);
+    assert_eq!((field("35"), field("56"), field("487")), (Some("AE"), Some("TAPE"), Some("0")));
+    assert_eq!((field("55"), field("31"), field("32")), (Some("AAPL"), Some("10.5"), Some("4")));
+    assert_eq!(field("571"), field("880"));
+    let sides: Vec<&str> = fields.iter().filter(|(tag, _)| ["552", "54", "1057"].contains(tag)).map(|(_, value)| *value).collect();
+    assert_eq!(sides, ["2", "1", "N", "2", "Y"]);
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use fefix::definitions::fix50::*;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::audit::MalformedLog;
+use crate::config::{LiveConfig, ServerConfig};
+use crate::credentials::{signed_text, Credential, Credentials, CredentialsConfig};
+use crate::engine::{extract_client_id, OutboundBatch};
+use crate::exchange::Exchange;
+use crate::namespace::Namespaces;
+use crate::fix::{encode_outbound, Conformance};
+use crate::gateway::{accept_connections, ConnectionGate, ConnectionLimits};
+use crate::inbound::inbound_channel;
+use crate::supervisor::EngineHealth;
+
+// Every CompID here is unique to its test, since sessions register in one process-wide map
+pub(super) fn message(msg_type: &[u8], comp_id: &str, fields: &[(u16, &str)]) -> String {
+    sent_at(msg_type, comp_id, &now(), fields)
+}
+
+fn now() -> String {
+    chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+fn sent_at(msg_type: &[u8], comp_id: &str, sending_time: &str, fields: &[(u16, &str)]) -> String {
+    let mut encoder = Encoder::<Config>::default();
+    encoder.config_mut().set_separator(b'|');
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+    msg.set(SENDER_COMP_ID, comp_id);
+    msg.set(TARGET_COMP_ID, "EXCHANGE");
+    msg.set(SENDING_TIME, sending_time);
+    for (tag, value) in fields {
+        msg.set_any(TagU16::new(*tag).unwrap(), *value);
+    }
+    format!("{}\n", String::from_utf8_lossy(msg.wrap()))
+}
+
+async fn start_server(credentials: Arc<Credentials>) -> (SocketAddr, Arc<ConnectionGate>) {
+    start_server_with(credentials, ServerConfig::default()).await
+}
+
+// The whole server but for its listeners, with the gate returned to read its metrics
+pub(super) async fn start_server_with(credentials: Arc<Credentials>, config: ServerConfig) -> (SocketAddr, Arc<ConnectionGate>) {
+    let (tx, rx) = inbound_channel(true);
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<OutboundBatch>();
+    tokio::spawn(crate::consume(Namespaces::new(Exchange::new()), rx, outbound_tx, EngineHealth::new(), std::future::pending()));
+    tokio::spawn(async move {
+        while let Some(batch) = outbound_rx.recv().await {
+            for (message, times) in batch.iter() {
+                let Some(client_id) = extract_client_id(message) else { continue };
+                if let (Some(session), Some(fix_msg)) = (crate::client_senders().get(&client_id), encode_outbound(message, Some(times))) {
+                    let _ = session.send(fix_msg);
+                }
+            }
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let gate = ConnectionGate::new(ConnectionLimits::default());
+    let config = LiveConfig::fixed(config);
+    let malformed_log = MalformedLog::new();
+    tokio::spawn(accept_connections(listener, Arc::clone(&gate), move |stream, permit| {
+        crate::handle_connection(stream, tx.clone(), Arc::clone(&credentials), Namespaces::new(Exchange::new()).book_views(), Conformance::Strict, Arc::clone(&malformed_log), Arc::clone(&config), permit)
+    }));
+    (address, gate)
+}
+
+fn passwords(require_credentials: bool, comp_ids: &[(&str, &str)]) -> Arc<Credentials> {
+    Credentials::from_config(CredentialsConfig {
+        require_credentials,
+        comp_ids: comp_ids
+            .iter()
+            .map(|(comp_id, password)| (comp_id.to_string(), Credential::Password(password.to_string())))
+            .collect::<HashMap<_, _>>(),
+    })
+}
+
+pub(super) async fn connect(address: SocketAddr) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+    let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+    (BufReader::new(reader).lines(), writer)
+}
+
+// Reads the Logon the session answers a logon with, before anything else it sends
+pub(super) async fn logged_on(lines: &mut Lines<BufReader<OwnedReadHalf>>, comp_id: &str) {
+    let logon = lines.next_line().await.unwrap().unwrap();
+    assert!(logon.contains("|35=A|") && logon.contains(&format!("|56={}|", comp_id)), "{}", logon);
+}
+
+// Sends `first` and returns the Logout text the session was refused with, checking it was closed
+async fn refusal(address: SocketAddr, first: &str) -> String {
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(first.as_bytes()).await.unwrap();
+    let logout = lines.next_line().await.unwrap().unwrap();
+    assert!(logout.contains("|35=5|"), "{}", logout);
+    assert_eq!(lines.next_line().await.unwrap(), None);
+    let (_, text) = logout.split('|').find(|field| field.starts_with("58=")).unwrap().split_once('=').unwrap();
+    text.to_string()
+}
+
+#[tokio::test]
+async fn correct_password_opens_the_session() {
+    let (address, gate) = start_server(passwords(true, &[("PASSWORD_OK", "secret")])).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(message(b"A", "PASSWORD_OK", &[(554, "secret")]).as_bytes()).await.unwrap();
+    logged_on(&mut lines, "PASSWORD_OK").await;
+    writer.write_all(message(b"V", "PASSWORD_OK", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+
+    let reply = lines.next_line().await.unwrap().unwrap();
+    assert!(reply.contains("|56=PASSWORD_OK|") && reply.contains("Unknown instrument"), "{}", reply);
+    assert_eq!(gate.metrics().failed_logons, 0);
+}
+
+#[tokio::test]
+async fn wrong_password_gets_a_logout_and_counts_toward_a_ban() {
+    let (address, gate) = start_server(passwords(false, &[("PASSWORD_BAD", "secret")])).await;
+    assert_eq!(refusal(address, &message(b"A", "PASSWORD_BAD", &[(554, "guess")])).await, "Invalid credentials");
+    // Credentials are checked whatever the session opens with
+    assert_eq!(refusal(address, &message(b"V", "PASSWORD_BAD", &[(55, "AAPL")])).await, "Logon required");
+    assert_eq!(gate.metrics().failed_logons, 2);
+}
+
+#[tokio::test]
+async fn missing_credentials_are_refused_when_the_server_requires_them() {
+    let (address, gate) = start_server(passwords(true, &[("LISTED", "secret")])).await;
+    assert_eq!(refusal(address, &message(b"A", "LISTED", &[])).await, "Credentials required");
+    assert_eq!(refusal(address, &message(b"A", "UNLISTED", &[])).await, "Credentials required");
+    assert_eq!(refusal(address, &message(b"A", "UNLISTED", &[(554, "secret")])).await, "Invalid credentials");
+    assert_eq!(refusal(address, &message(b"V", "UNLISTED", &[(55, "AAPL")])).await, "Logon required");
+    assert_eq!(gate.metrics().failed_logons, 4);
+}
+
+#[tokio::test]
+async fn a_logged_on_session_cannot_send_as_another_comp_id() {
+    let (address, _gate) = start_server(passwords(false, &[("PINNED", "secret")])).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(message(b"A", "PINNED", &[(554, "secret")]).as_bytes()).await.unwrap();
+    logged_on(&mut lines, "PINNED").await;
+    writer.write_all(message(b"V", "SPOOFED", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+
+    let reject = lines.next_line().await.unwrap().unwrap();
+    assert!(reject.contains("|35=3|") && reject.contains("SenderCompID does not match the session"), "{}", reject);
+}
+
+#[tokio::test]
+async fn a_logon_signed_with_the_hmac_key_opens_the_session() {
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f";
+    let credentials = Credentials::from_config(CredentialsConfig {
+        require_credentials: true,
+        comp_ids: HashMap::from([("SIGNED".to_string(), Credential::HmacKey(KEY.to_string()))]),
+    });
+    let (address, _gate) = start_server(credentials).await;
+
+    let sending_time = now();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&hex::decode(KEY).unwrap()).unwrap();
+    mac.update(signed_text(&sending_time, 1).as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    let length = signature.len().to_string();
+    let logon = sent_at(b"A", "SIGNED", &sending_time, &[(34, "1"), (95, &length), (96, &signature)]);
+
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(logon.as_bytes()).await.unwrap();
+    logged_on(&mut lines, "SIGNED").await;
+    writer.write_all(message(b"V", "SIGNED", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+    let reply = lines.next_line().await.unwrap().unwrap();
+    assert!(reply.contains("|56=SIGNED|") && reply.contains("Unknown instrument"), "{}", reply);
+
+    // The same signature over a different sequence number does not verify
+    let forged = sent_at(b"A", "SIGNED", &sending_time, &[(34, "2"), (95, &length), (96, &signature)]);
+    assert_eq!(refusal(address, &forged).await, "Invalid credentials");
+}
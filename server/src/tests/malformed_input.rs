@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use fefix::fix_values::Timestamp;
+use tokio::io::AsyncWriteExt;
+
+use crate::audit::MalformedLog;
+use crate::config::{LiveConfig, ServerConfig};
+use crate::credentials::Credentials;
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::Conformance;
+use crate::framing::FrameReader;
+use crate::tests::fix_round_trip::encode;
+use crate::tests::logon_credentials::{connect, logged_on, message, start_server_with};
+use crate::types::ClientID;
+use crate::{read_frame, InvalidMessages};
+
+// A message the decoder cannot read, as any session might send it
+const GARBLED: &str = "8=FIXT.1.1|9=5|=oops|10=000|\n";
+
+fn limit_order(price: &str) -> String {
+    encode(b"D", &[(1, "FIRM1"), (55, "AAPL"), (54, "1"), (53, "1"), (40, "2"), (44, price)]) + "\n"
+}
+
+// A session's stream with binary junk, a message that is not UTF-8 and one the decoder cannot
+// read among its orders: each order is still accepted, and each of the rest is rejected
+// before the engine sees it and lands in the rejection log with its bytes
+#[tokio::test]
+async fn orders_among_junk_are_still_accepted_and_what_could_not_be_read_is_logged_as_it_came() {
+    let junk: &[u8] = &[0x00, 0xff, 0xc3, 0x28, b'\n', 0x80];
+    let order = limit_order("10");
+    let text_at = order.find("|55=AAPL|").unwrap() + "|55=AAPL|".len();
+    let not_utf8 = [&order.as_bytes()[..text_at], b"58=\xff\xfe|", &order.as_bytes()[text_at..]].concat();
+    let stream = [
+        (encode(b"UCI", &[(55, "AAPL")]) + "\n").as_bytes(),
+        junk,
+        limit_order("10").as_bytes(),
+        &not_utf8,
+        b"8=FIXT.1.1|9=5|=oops|10=000|\n",
+        junk,
+        limit_order("11").as_bytes(),
+    ].concat();
+
+    let malformed_log = MalformedLog::new();
+    let mut invalid = InvalidMessages::new(Arc::clone(&malformed_log), LiveConfig::fixed(ServerConfig::default()));
+    let mut exchange = Exchange::new().with_malformed_log(malformed_log);
+    let mut frames = FrameReader::new(&stream[..]);
+    let (mut events, mut rejects) = (Vec::new(), 0);
+    while let Some(frame) = frames.next_frame().await {
+        match invalid.screen(read_frame(frame, Conformance::Lenient).1) {
+            Ok(engine_message) => events.extend(exchange.handle_message(engine_message)),
+            Err(reject) => {
+                assert!(String::from_utf8_lossy(&reject).contains("|35=3|"), "{:?}", reject);
+                rejects += 1;
+            }
+        }
+    }
+    let accepted = events.iter().filter(|event| matches!(event, EngineMessage::OrderAccepted { .. })).count();
+    assert_eq!((accepted, rejects), (2, 4), "{:?}", events);
+
+    let query = exchange.handle_message(EngineMessage::RejectionLogQuery {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        since: Timestamp::parse(b"19700101-00:00:00.000").unwrap(),
+    });
+    let [EngineMessage::RejectionLogReport { malformed, .. }] = query.as_slice() else { panic!("{:?}", query) };
+    let logged: Vec<_> = malformed.iter().map(|entry| (entry.reason.as_str(), entry.raw_message.length, entry.raw_message.failed_at)).collect();
+    let junk_at = (encode(b"UCI", &[(55, "AAPL")]).len() + 1) as u64;
+    assert_eq!(logged, vec![
+        (format!("Skipped 6 bytes with no message at stream offset {}", junk_at).as_str(), 6, Some(0)),
+        ("Message is not valid UTF-8", not_utf8.len() - 1, Some(text_at + "58=".len())),
+        // Where the decoder gave up is the field with no tag
+        ("Invalid FIX message syntax.", 28, Some(15)),
+        (format!("Skipped 6 bytes with no message at stream offset {}", stream.len() - limit_order("11").len() - 6).as_str(), 6, Some(0)),
+    ]);
+    assert_eq!(malformed[0].raw_message.bytes, junk);
+
+    // The admin API hands the bytes out hex encoded
+    let json = serde_json::to_value(&malformed[0]).unwrap();
+    assert_eq!(json["raw_message"]["bytes"], "00ffc3280a80");
+}
+
+#[tokio::test]
+async fn an_unreadable_first_message_is_rejected_and_the_connection_stays_open_for_a_retry() {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(GARBLED.as_bytes()).await.unwrap();
+    let reject = lines.next_line().await.unwrap().unwrap();
+    assert!(reject.contains("|35=3|") && reject.contains("|58=CheckSum 000 should be 139|"), "{}", reject);
+
+    writer.write_all(message(b"V", "GARBLED_FIRST", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+    let reply = lines.next_line().await.unwrap().unwrap();
+    assert!(reply.contains("|56=GARBLED_FIRST|") && reply.contains("Unknown instrument"), "{}", reply);
+}
+
+#[tokio::test]
+async fn an_unreadable_message_mid_session_is_rejected_and_the_session_carries_on() {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(message(b"A", "GARBLED_MID", &[]).as_bytes()).await.unwrap();
+    logged_on(&mut lines, "GARBLED_MID").await;
+    writer.write_all(GARBLED.as_bytes()).await.unwrap();
+    writer.write_all(message(b"V", "GARBLED_MID", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+
+    let reject = lines.next_line().await.unwrap().unwrap();
+    assert!(reject.contains("|35=3|") && reject.contains("|58=CheckSum 000 should be 139|"), "{}", reject);
+    let reply = lines.next_line().await.unwrap().unwrap();
+    assert!(reply.contains("|56=GARBLED_MID|") && reply.contains("Unknown instrument"), "{}", reply);
+}
+
+#[tokio::test]
+async fn too_many_unreadable_messages_in_a_row_log_the_session_out() {
+    let config = ServerConfig { max_invalid_messages: 2, ..ServerConfig::default() };
+    let (address, _gate) = start_server_with(Credentials::open(), config).await;
+    let (mut lines, mut writer) = connect(address).await;
+    writer.write_all(message(b"A", "GARBLED_OFTEN", &[]).as_bytes()).await.unwrap();
+    logged_on(&mut lines, "GARBLED_OFTEN").await;
+
+    // A readable message in between starts the count again
+    writer.write_all(GARBLED.as_bytes()).await.unwrap();
+    assert!(lines.next_line().await.unwrap().unwrap().contains("|35=3|"));
+    writer.write_all(message(b"V", "GARBLED_OFTEN", &[(55, "AAPL")]).as_bytes()).await.unwrap();
+    assert!(lines.next_line().await.unwrap().unwrap().contains("Unknown instrument"));
+
+    writer.write_all(GARBLED.repeat(2).as_bytes()).await.unwrap();
+    assert!(lines.next_line().await.unwrap().unwrap().contains("|35=3|"));
+    assert!(lines.next_line().await.unwrap().unwrap().contains("|35=3|"));
+    let logout = lines.next_line().await.unwrap().unwrap();
+    assert!(logout.contains("|35=5|") && logout.contains("|58=Too many invalid messages|"), "{}", logout);
+    assert_eq!(lines.next_line().await.unwrap(), None);
+}
@@ -0,0 +1,134 @@
+use fefix::definitions::fix50::Side;
+
+use crate::engine::{BookChange, BookGranularity, EngineMessage, ImbalanceDirection, InstrumentPhase, InstrumentStatusEntry, LiquidityScore};
+use crate::fix::{handle_fix_message, serialize_engine_message};
+use crate::types::*;
+
+use super::fix_round_trip::encode;
+
+#[test]
+fn market_data_request_type_picks_snapshot_or_subscription() {
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (264, "5")])),
+        EngineMessage::Snapshot { depth: Some(5), .. }
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (263, "1"), (264, "5")])),
+        EngineMessage::SubscribeOrderBook { depth: 5, ref symbols, subscribe_on_create: false, .. } if *symbols == ["AAPL"]
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (263, "1")])),
+        EngineMessage::SubscribeOrderBook { depth: 0, .. }
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (263, "2")])),
+        EngineMessage::UnsubscribeOrderBook { .. }
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (263, "7")])),
+        EngineMessage::InvalidMessage { .. }
+    ));
+}
+
+#[test]
+fn market_data_book_type_picks_the_snapshot_granularity() {
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (1021, "3")])),
+        EngineMessage::Snapshot { granularity: BookGranularity::ByOrder, depth: None, .. }
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (1021, "2"), (264, "5")])),
+        EngineMessage::Snapshot { granularity: BookGranularity::ByPrice, depth: Some(5), .. }
+    ));
+    assert!(matches!(
+        handle_fix_message(&encode(b"V", &[(55, "AAPL"), (1021, "1")])),
+        EngineMessage::Snapshot { granularity: BookGranularity::ByPrice, depth: Some(1), .. }
+    ));
+}
+
+#[test]
+fn by_order_snapshot_lists_each_order_with_its_id() {
+    let snapshot = serialize_engine_message(&EngineMessage::Snapshot {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        timestamp: fefix::fix_values::Timestamp::utc_now(),
+        instrument_id: "AAPL".to_string(),
+        bids: Vec::new(),
+        asks: Vec::new(),
+        depth: None,
+        granularity: BookGranularity::ByOrder,
+        bid_orders: vec![(7, Price::from(10.0), 2), (9, Price::from(10.0), 3)],
+        ask_orders: vec![(8, Price::from(11.0), 1)],
+    })
+    .unwrap();
+    let entries = "|1021=3|268=3|269=0|37=7|270=10|271=2|269=0|37=9|270=10|271=3|269=1|37=8|270=11|271=1|";
+    assert!(snapshot.contains(entries), "{}", snapshot);
+}
+
+#[test]
+fn book_update_is_an_incremental_refresh() {
+    let update = serialize_engine_message(&EngineMessage::BookUpdate {
+        client_id: ClientID::new("FIRM1".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        changes: vec![
+            BookChange::RemoveLevel { side: Side::Buy, price: Price::from(9.0) },
+            BookChange::AddLevel { side: Side::Sell, price: Price::from(11.0), quantity: 2 },
+            BookChange::UpdateLevel { side: Side::Buy, price: Price::from(10.0), quantity: 3 },
+        ],
+    })
+    .unwrap();
+    assert!(update.contains("|35=X|"), "{}", update);
+    let entries = "|268=3|279=2|269=0|55=AAPL|270=9|279=0|269=1|55=AAPL|270=11|271=2|279=1|269=0|55=AAPL|270=10|271=3|";
+    assert!(update.contains(entries), "{}", update);
+}
+
+#[test]
+fn depth_imbalance_alert_is_news_carrying_the_ratio() {
+    let alert = serialize_engine_message(&EngineMessage::DepthImbalanceAlert {
+        client_id: ClientID::new("SURVEILLANCE".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        ratio: 0.25,
+        direction: ImbalanceDirection::AskHeavy,
+        timestamp: fefix::fix_values::Timestamp::parse(b"20240102-14:30:00.000").unwrap(),
+    })
+    .unwrap();
+    assert!(alert.contains("|35=B|") && alert.contains("|56=SURVEILLANCE|"), "{}", alert);
+    assert!(alert.contains("|148=Depth imbalance on AAPL: ask-heavy, bid/ask 0.25|"), "{}", alert);
+    assert!(alert.contains("|55=AAPL|8014=0.25|"), "{}", alert);
+}
+
+#[test]
+fn liquidity_report_carries_the_score_in_custom_fields() {
+    let report = serialize_engine_message(&EngineMessage::LiquidityReport {
+        client_id: ClientID::new("WATCHER".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        score: LiquidityScore { spread: 2.0, depth_bid: 7, depth_ask: 5, fill_rate: 1.5, impact_cost_estimate: 0.25 },
+    })
+    .unwrap();
+    assert!(report.contains("|35=ULR|") && report.contains("|56=WATCHER|"), "{}", report);
+    assert!(report.contains("|55=AAPL|8016=2|8017=7|8018=5|8019=1.5|8020=0.25|"), "{}", report);
+}
+
+#[test]
+fn symbol_status_report_lists_each_instrument_in_a_group() {
+    let entry = |instrument_id: &str, phase, circuit_breaker, last_price: Option<f64>| InstrumentStatusEntry {
+        instrument_id: instrument_id.to_string(),
+        phase,
+        circuit_breaker,
+        luld_band: None,
+        last_price: last_price.map(Price::from),
+        bid_levels: 3,
+        ask_levels: 1,
+    };
+    let opens_at = fefix::fix_values::Timestamp::parse(b"20240102-14:30:00.000").unwrap();
+    let report = serialize_engine_message(&EngineMessage::SymbolStatusReport {
+        client_id: ClientID::new("OPERATOR".to_string(), None),
+        reports: vec![
+            entry("AAPL", InstrumentPhase::Open, false, Some(10.5)),
+            entry("MSFT", InstrumentPhase::WarmUp { opens_at }, true, None),
+        ],
+    })
+    .unwrap();
+    assert!(report.contains("|35=USS|") && report.contains("|56=OPERATOR|"), "{}", report);
+    assert!(report.contains("|146=2|55=AAPL|326=17|8021=N|31=10.5|8022=3|8023=1|"), "{}", report);
+    assert!(report.contains("|55=MSFT|326=21|342=20240102-14:30:00.000|8021=Y|8022=3|8023=1|"), "{}", report);
+}
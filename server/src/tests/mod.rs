@@ -0,0 +1,41 @@
+#[cfg(target_os = "linux")]
+mod accept_scaling;
+mod activity;
+pub(crate) mod allocations;
+mod amend_order;
+mod cancel_by_client_order_id;
+mod cancel_only;
+mod cancel_ordering;
+mod drop_copy;
+mod execution_limit;
+mod execution_reports;
+mod fix_conformance;
+mod fix_round_trip;
+mod fix_session;
+mod good_for_auction;
+mod greeks;
+mod history;
+mod instrument_updates;
+mod last_sale;
+mod logon_credentials;
+mod malformed_input;
+mod market_data;
+mod multileg;
+mod namespaces;
+mod order_types;
+mod price_bands;
+mod replay;
+mod responses;
+mod outbound_buffers;
+mod price_ladders;
+mod scenarios;
+mod serialization_isolation;
+mod session_identity;
+mod spreads;
+mod statements;
+mod stop_orders;
+mod subscriptions;
+mod summary_fills;
+mod supervision;
+mod surveillance;
+mod volatility_halt;
@@ -0,0 +1,94 @@
+use fefix::definitions::fix50::Side;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+use crate::types::*;
+
+fn multileg(exchange: &mut Exchange, legs: &[(&str, Side, Quantity, Option<f64>)]) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderMultileg::builder().sender("TRADER").account("TRADER").cl_ord_id("SPREAD-1").legs(legs).parse())
+}
+
+// The account's cash and what it holds of each instrument
+fn holdings(exchange: &mut Exchange) -> (AccountBalance, Vec<(InstrumentID, Quantity)>) {
+    let report = exchange.handle_message(PositionQuery::builder().sender("TRADER").account("TRADER").parse());
+    let [EngineMessage::PositionReport { cash, positions, .. }] = report.as_slice() else { panic!("{:?}", report) };
+    (*cash, positions.iter().map(|(instrument_id, quantity, _)| (instrument_id.clone(), *quantity)).collect())
+}
+
+fn filled(events: &[EngineMessage], firm: &str) -> Vec<(InstrumentID, Quantity)> {
+    events.iter().filter_map(|event| match event {
+        EngineMessage::OrderFilled { client_id, instrument_id, filled_quantity, .. } if client_id.to_string() == firm => Some((instrument_id.clone(), *filled_quantity)),
+        _ => None,
+    }).collect()
+}
+
+// ESZ4 offered 2 at 100 and ESH5 bid 4 at 49, with TRADER long 2 ESH5 to sell
+fn calendar() -> Exchange {
+    let mut exchange = Exchange::new();
+    for symbol in ["ESZ4", "ESH5"] {
+        exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol(symbol).parse());
+    }
+    order_in(&mut exchange, "MAKER", "ESH5", Side::Sell, 2, 50.0);
+    order_in(&mut exchange, "TRADER", "ESH5", Side::Buy, 2, 50.0);
+    order_in(&mut exchange, "SELLER", "ESZ4", Side::Sell, 2, 100.0);
+    order_in(&mut exchange, "BIDDER", "ESH5", Side::Buy, 4, 49.0);
+    exchange
+}
+
+#[test]
+fn every_leg_of_a_multileg_order_trades_in_full_as_an_order_of_its_own() {
+    let mut exchange = calendar();
+    let events = multileg(&mut exchange, &[("ESZ4", Side::Buy, 2, Some(101.0)), ("ESH5", Side::Sell, 2, None)]);
+
+    let accepted = events.iter().filter(|event| matches!(event, EngineMessage::OrderAccepted { client_id, .. } if client_id.to_string() == "TRADER")).count();
+    assert_eq!(accepted, 2, "{:?}", events);
+    assert_eq!(filled(&events, "TRADER"), [("ESZ4".to_string(), 2), ("ESH5".to_string(), 2)]);
+    let (_, positions) = holdings(&mut exchange);
+    assert_eq!(positions, [("ESH5".to_string(), 0), ("ESZ4".to_string(), 2)]);
+}
+
+// A leg that cannot fill in full refuses the whole order: the legs before it, which checked out,
+// give back what they reserved and never trade
+#[test]
+fn a_leg_that_fails_rolls_back_the_legs_reserved_before_it() {
+    let mut exchange = calendar();
+    let before = holdings(&mut exchange);
+
+    for (legs, reason) in [
+        (vec![("ESZ4", Side::Buy, 2, Some(101.0)), ("ESH5", Side::Sell, 5, None)], "Leg 2: Insufficient liquidity"),
+        (vec![("ESZ4", Side::Buy, 2, Some(101.0)), ("ESH5", Side::Sell, 2, Some(50.0))], "Leg 2: Insufficient liquidity"),
+        (vec![("ESZ4", Side::Buy, 2, Some(101.0)), ("NQZ4", Side::Sell, 2, None)], "Leg 2: Unknown instrument"),
+        (vec![("ESZ4", Side::Buy, 2, Some(101.0)), ("ESH5", Side::Sell, 3, None)], "Leg 2: Insufficient position"),
+        (vec![("ESZ4", Side::Buy, 2, Some(101.0))], "Multileg order needs at least two legs"),
+        (vec![("ESZ4", Side::Buy, 1, Some(101.0)), ("ESZ4", Side::Buy, 1, Some(101.0))], "Multileg order names an instrument twice"),
+    ] {
+        let events = multileg(&mut exchange, &legs);
+        assert!(matches!(&events[..], [EngineMessage::OrderRejected { reason: refused, .. }] if refused == reason), "{:?} for {:?}", events, legs);
+        assert_eq!(holdings(&mut exchange), before, "after {:?}", legs);
+    }
+
+    // Nothing traded, and no order ID was spent on the refused legs
+    let events = order_in(&mut exchange, "BUYER", "ESZ4", Side::Buy, 2, 100.0);
+    assert_eq!(filled(&events, "BUYER"), [("ESZ4".to_string(), 2)]);
+    assert!(matches!(events[0], EngineMessage::OrderAccepted { order_id: 5, .. }), "{:?}", events);
+}
+
+// Cash is reserved leg by leg, so legs each affordable alone are refused together
+#[test]
+fn the_legs_reserve_cash_against_one_another() {
+    let mut exchange = Exchange::new();
+    for symbol in ["ESZ4", "ESH5"] {
+        exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol(symbol).parse());
+    }
+    order_in(&mut exchange, "SELLER", "ESZ4", Side::Sell, 6, 100.0);
+    order_in(&mut exchange, "MAKER", "ESH5", Side::Sell, 5, 100.0);
+
+    // A new account's 1000, spent 600 on the first leg
+    let events = multileg(&mut exchange, &[("ESZ4", Side::Buy, 6, Some(100.0)), ("ESH5", Side::Buy, 5, Some(100.0))]);
+    assert!(matches!(&events[..], [EngineMessage::OrderRejected { reason, .. }] if reason == "Leg 2: Insufficient funds"), "{:?}", events);
+    assert_eq!(holdings(&mut exchange), (AccountBalance::from(1000.0), Vec::new()));
+
+    let events = multileg(&mut exchange, &[("ESZ4", Side::Buy, 5, Some(100.0)), ("ESH5", Side::Buy, 4, Some(100.0))]);
+    assert_eq!(filled(&events, "TRADER"), [("ESZ4".to_string(), 5), ("ESH5".to_string(), 4)]);
+}
@@ -0,0 +1,139 @@
+use dashmap::DashMap;
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::{handle_fix_message, serialize_engine_message};
+use crate::instrument::SpecOverrides;
+use crate::namespace::Namespaces;
+use crate::supervisor::EngineHealth;
+use crate::types::{ClientID, Price};
+
+// A message from `sender` to the CompID `target`, which names its namespace
+fn addressed(sender: &str, target: &str, msg_type: &[u8], fields: &[(u16, &str)]) -> String {
+    let mut encoder = Encoder::<Config>::default();
+    encoder.config_mut().set_separator(b'|');
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+    msg.set(SENDER_COMP_ID, sender);
+    msg.set(TARGET_COMP_ID, target);
+    msg.set(SENDING_TIME, Timestamp::parse(b"20240102-14:30:00.125").unwrap());
+    for (tag, value) in fields {
+        msg.set_any(TagU16::new(*tag).unwrap(), *value);
+    }
+    String::from_utf8_lossy(msg.wrap()).into_owned()
+}
+
+fn send(namespaces: &mut Namespaces, message: &str) -> Vec<EngineMessage> {
+    namespaces.handle_supervised(handle_fix_message(message), &EngineHealth::default(), &DashMap::new())
+}
+
+fn limit_order(namespaces: &mut Namespaces, sender: &str, target: &str, side: &str, price: &str) -> Vec<EngineMessage> {
+    let order = addressed(sender, target, b"D", &[(1, sender), (55, "AAPL"), (54, side), (53, "5"), (40, "2"), (44, price)]);
+    send(namespaces, &order)
+}
+
+fn positions(namespaces: &mut Namespaces, sender: &str, target: &str) -> Vec<(String, u64)> {
+    let report = send(namespaces, &addressed(sender, target, b"UPQ", &[(1, sender)]));
+    match report.as_slice() {
+        [EngineMessage::PositionReport { positions, .. }] => positions.iter().map(|(instrument_id, quantity, _)| (instrument_id.clone(), *quantity)).collect(),
+        _ => panic!("expected a position report: {:?}", report),
+    }
+}
+
+fn uat_and_dev() -> Namespaces {
+    let mut namespaces = Namespaces::new(Exchange::new())
+        .with_namespace("UAT".to_string(), Exchange::new())
+        .with_namespace("DEV".to_string(), Exchange::new());
+    for target in ["UAT", "DEV"] {
+        send(&mut namespaces, &addressed("ADMIN", target, b"UCI", &[(55, "AAPL")]));
+    }
+    namespaces
+}
+
+#[test]
+fn two_namespaces_trade_the_same_symbol_and_never_see_each_others_books_or_fills() {
+    let mut namespaces = uat_and_dev();
+    let views = namespaces.book_views();
+
+    // The same accounts, on the same symbol, interleaved across both namespaces
+    limit_order(&mut namespaces, "SELLER", "UAT", "2", "10");
+    limit_order(&mut namespaces, "SELLER", "DEV", "2", "20");
+    let dev_bid = limit_order(&mut namespaces, "BUYER", "DEV", "1", "19");
+    let uat_bid = limit_order(&mut namespaces, "BUYER", "UAT", "1", "10");
+
+    // Only UAT's orders crossed, and its fills went to UAT's sessions, answered as UAT
+    assert!(!dev_bid.iter().any(|event| matches!(event, EngineMessage::OrderFilled { .. })), "{:?}", dev_bid);
+    let fills: Vec<_> = uat_bid.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { .. })).collect();
+    assert_eq!(fills.len(), 2, "{:?}", uat_bid);
+    for fill in fills {
+        let EngineMessage::OrderFilled { client_id, price, .. } = fill else { unreachable!() };
+        assert_eq!((client_id.namespace(), *price), (Some("UAT"), Price::from(10.0)));
+        let report = serialize_engine_message(fill).unwrap();
+        assert!(report.contains("|49=UAT|"), "{}", report);
+    }
+
+    // Each namespace publishes its own AAPL book; the default namespace has none at all
+    namespaces.handle_supervised(EngineMessage::PublishBookViews, &EngineHealth::default(), &DashMap::new());
+    let book = |namespace| views.get(namespace).unwrap().latest().books.get("AAPL").cloned();
+    let (uat, dev) = (book(Some("UAT")).unwrap(), book(Some("DEV")).unwrap());
+    assert_eq!((uat.bids, uat.asks), (vec![], vec![]));
+    assert_eq!((dev.bids, dev.asks), (vec![(Price::from(19.0), 5)], vec![(Price::from(20.0), 5)]));
+    assert_eq!(book(None), None);
+    let unknown = limit_order(&mut namespaces, "BUYER", "EXCHANGE", "1", "20");
+    assert!(matches!(unknown.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown instrument"), "{:?}", unknown);
+
+    // The same account holds what it bought in UAT and nothing it didn't in DEV
+    assert_eq!(positions(&mut namespaces, "BUYER", "UAT"), vec![("AAPL".to_string(), 5)]);
+    assert_eq!(positions(&mut namespaces, "BUYER", "DEV"), vec![]);
+
+    // An order ID from one namespace reaches nothing in another
+    let EngineMessage::OrderAccepted { order_id, .. } = &dev_bid[0] else { panic!("{:?}", dev_bid) };
+    let cancel = addressed("BUYER", "UAT", b"F", &[(37, &order_id.to_string()), (1, "BUYER")]);
+    assert!(!send(&mut namespaces, &cancel).iter().any(|event| matches!(event, EngineMessage::OrderCancelled { .. })));
+    let cancel = addressed("BUYER", "DEV", b"F", &[(37, &order_id.to_string()), (1, "BUYER")]);
+    assert!(matches!(send(&mut namespaces, &cancel).as_slice(), [EngineMessage::OrderCancelled { .. }]));
+}
+
+#[test]
+fn an_order_naming_another_namespaces_security_exchange_is_refused_rather_than_matched_there() {
+    let mut namespaces = uat_and_dev();
+    limit_order(&mut namespaces, "SELLER", "DEV", "2", "10");
+    let to_dev = addressed("BUYER", "UAT", b"D", &[(1, "BUYER"), (55, "AAPL"), (207, "DEV"), (54, "1"), (53, "5"), (40, "2"), (44, "10")]);
+    let refused = send(&mut namespaces, &to_dev);
+    assert!(matches!(refused.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "SecurityExchange DEV is not the session's namespace"), "{:?}", refused);
+
+    // Naming its own namespace's venue, the same order trades there
+    let to_dev = addressed("BUYER", "DEV", b"D", &[(1, "BUYER"), (55, "AAPL"), (207, "DEV"), (54, "1"), (53, "5"), (40, "2"), (44, "10")]);
+    let traded = send(&mut namespaces, &to_dev);
+    assert!(traded.iter().any(|event| matches!(event, EngineMessage::OrderFilled { .. })), "{:?}", traded);
+}
+
+#[test]
+fn a_namespace_the_server_does_not_run_is_refused_and_with_none_named_every_target_is_the_exchange() {
+    let mut namespaces = uat_and_dev();
+    let refused = limit_order(&mut namespaces, "BUYER", "PROD", "1", "10");
+    assert!(matches!(refused.as_slice(), [EngineMessage::LogEvent { message, client_id: Some(client_id) }]
+        if message == "Unknown namespace PROD" && client_id.namespace() == Some("PROD")), "{:?}", refused);
+
+    // Without namespaces, whatever a session addresses reaches the one exchange
+    let mut single = Namespaces::new(Exchange::new());
+    single.handle_supervised(
+        EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: ClientID::new("ADMIN".to_string(), None),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        },
+        &EngineHealth::default(),
+        &DashMap::new(),
+    );
+    let accepted = limit_order(&mut single, "BUYER", "PROD", "1", "10");
+    assert!(matches!(accepted.first(), Some(EngineMessage::OrderAccepted { .. })), "{:?}", accepted);
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use fefix::definitions::fix50::{OrdRejReason, OrdType};
+use fefix::fix_values::Timestamp;
+use fefix::FixValue;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::{handle_fix_message, serialize_engine_message};
+use crate::instrument::{OrderType, SpecOverrides};
+use crate::tests::fix_round_trip::encode;
+use crate::types::ClientID;
+
+const UNSUPPORTED: [OrdType; 20] = [
+    OrdType::MarketOnClose,
+    OrdType::WithOrWithout,
+    OrdType::LimitOrBetter,
+    OrdType::LimitWithOrWithout,
+    OrdType::OnBasis,
+    OrdType::OnClose,
+    OrdType::LimitOnClose,
+    OrdType::ForexMarket,
+    OrdType::PreviouslyQuoted,
+    OrdType::PreviouslyIndicated,
+    OrdType::ForexLimit,
+    OrdType::ForexSwap,
+    OrdType::ForexPreviouslyQuoted,
+    OrdType::Funari,
+    OrdType::MarketIfTouched,
+    OrdType::MarketWithLeftOverAsLimit,
+    OrdType::PreviousFundValuationPoint,
+    OrdType::NextFundValuationPoint,
+    OrdType::Pegged,
+    OrdType::CounterOrderSelection,
+];
+
+fn exchange_with(instrument_id: &str, order_types: Option<BTreeSet<OrderType>>) -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        instrument_id: instrument_id.to_string(),
+        segment: None,
+        spec: SpecOverrides { order_types, ..SpecOverrides::default() },
+        spread: None,
+    });
+    exchange
+}
+
+// Sends a priced order of `order_type` in over FIX, as FIRM1 would
+fn order_on_the_wire(exchange: &mut Exchange, instrument_id: &str, order_type: OrdType) -> Vec<EngineMessage> {
+    let ord_type = order_type.to_string();
+    let order = encode(b"D", &[(1, "FIRM1"), (55, instrument_id), (54, "1"), (53, "1"), (40, &ord_type), (44, "10")]);
+    let parsed = handle_fix_message(&order);
+    assert!(matches!(parsed, EngineMessage::NewOrder { .. }), "{:?}: {:?}", order_type, parsed);
+    exchange.handle_message(parsed)
+}
+
+fn is_unsupported_reject(events: &[EngineMessage]) -> bool {
+    matches!(events, [EngineMessage::OrderRejected { reason, code: Some(OrdRejReason::UnsupportedOrderCharacteristic), .. }]
+        if reason == "Order type not supported on this instrument")
+}
+
+#[test]
+fn every_order_type_the_engine_cannot_match_is_refused_with_a_reject_code() {
+    let mut exchange = exchange_with("AAPL", None);
+    for order_type in UNSUPPORTED {
+        assert_eq!(OrderType::of(order_type), None, "{:?}", order_type);
+        let events = order_on_the_wire(&mut exchange, "AAPL", order_type);
+        assert!(is_unsupported_reject(&events), "{:?}: {:?}", order_type, events);
+        let report = serialize_engine_message(&events[0]).unwrap();
+        assert!(report.contains("|39=8|") && report.contains("|103=11|"), "{:?}: {}", order_type, report);
+    }
+    // Nothing refused reached the book or the account
+    assert!(order_on_the_wire(&mut exchange, "AAPL", OrdType::Limit).iter().any(|event| matches!(event, EngineMessage::OrderAccepted { order_id: 1, .. })));
+}
+
+#[test]
+fn an_instrument_takes_only_the_order_types_it_lists() {
+    let auction_only = BTreeSet::from([OrderType::Limit]);
+    let mut exchange = exchange_with("AUCT", Some(auction_only));
+    for order_type in [OrdType::Market, OrdType::Stop, OrdType::StopLimit] {
+        let events = order_on_the_wire(&mut exchange, "AUCT", order_type);
+        assert!(is_unsupported_reject(&events), "{:?}: {:?}", order_type, events);
+    }
+    let events = order_on_the_wire(&mut exchange, "AUCT", OrdType::Limit);
+    assert!(matches!(events.first(), Some(EngineMessage::OrderAccepted { .. })), "{:?}", events);
+}
@@ -0,0 +1,100 @@
+// Outbound encoding, once its buffers are warm, allocates nothing per message. The benchmark
+// compares it with a fresh buffer per message, and only runs on request:
+//   cargo test --release -p exchange-server outbound -- --ignored --nocapture
+
+use std::time::Instant;
+
+use bytes::BytesMut;
+use fefix::definitions::fix50::Side;
+
+use crate::engine::EngineMessage;
+use crate::fix::{encode_engine_message, serialize_engine_message, Separator};
+use crate::tests::allocations::allocations;
+use crate::types::{ClientID, Price};
+use crate::wire::{stamp_outbound, BufferPool};
+
+const MESSAGES: usize = 200_000;
+const RUNS: usize = 5;
+
+fn fill(order_id: u64) -> EngineMessage {
+    EngineMessage::OrderFilled {
+        client_id: ClientID::new("FIRM1".to_string(), Some("DESK".to_string())),
+        order_id,
+        client_order_id: Some("ORDER-1".to_string()),
+        filled_quantity: 100,
+        remaining_quantity: 50,
+        cumulative_quantity: 100,
+        price: Price::from(101.25),
+        instrument_id: "AAPL".to_string(),
+        side: Side::Buy,
+        arrival_bid: Some(Price::from(101.2)),
+        arrival_ask: Some(Price::from(101.3)),
+        trade_match_id: order_id,
+        average_price: Price::from(101.25),
+        summary: false,
+    }
+}
+
+// What a session's writer does with one report: encode it, stamp and rewrite it for a SOH
+// client, write it and give its buffer back
+fn send(pool: &BufferPool, message: &EngineMessage) -> usize {
+    let encoded = pool.encode(|buffer| encode_engine_message(message, None, buffer)).unwrap();
+    let written = Separator::Soh.apply(stamp_outbound(encoded, &mut 1));
+    let length = written.len();
+    pool.restore(written);
+    length
+}
+
+fn without_times(message: &str) -> Vec<&str> {
+    message.split('|').filter(|field| !["52=", "60=", "17=", "9=", "10="].iter().any(|tag| field.starts_with(tag))).collect()
+}
+
+#[test]
+fn an_execution_report_is_encoded_without_allocating_once_buffers_are_warm() {
+    let pool = BufferPool::new();
+    let message = fill(123_456);
+    for _ in 0..10 {
+        send(&pool, &message);
+    }
+
+    let before = allocations();
+    for _ in 0..1_000 {
+        send(&pool, &message);
+    }
+    assert_eq!(allocations() - before, 0);
+
+    // Pooled or not, the report is the same but for when it was matched and sent
+    let pooled = pool.encode(|buffer| encode_engine_message(&message, None, buffer)).unwrap();
+    let soh = Separator::Soh.apply(pooled.clone());
+    assert_eq!(without_times(&String::from_utf8_lossy(&pooled)), without_times(&serialize_engine_message(&message).unwrap()));
+    assert!(soh.ends_with(b"\x01") && !soh.contains(&b'|') && !soh.contains(&b'\n'), "{:?}", soh);
+}
+
+#[test]
+#[ignore]
+fn pooled_buffers_encode_faster_than_fresh_ones() {
+    let pool = BufferPool::new();
+    let messages: Vec<EngineMessage> = (0..64).map(fill).collect();
+    let mut best = (f64::MAX, f64::MAX);
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        let mut bytes = 0;
+        for index in 0..MESSAGES {
+            bytes += send(&pool, &messages[index % messages.len()]);
+        }
+        best.0 = best.0.min(started.elapsed().as_secs_f64());
+
+        let started = Instant::now();
+        for index in 0..MESSAGES {
+            let mut buffer = BytesMut::new();
+            encode_engine_message(&messages[index % messages.len()], None, &mut buffer);
+            bytes -= Separator::Soh.apply(buffer.freeze()).len();
+        }
+        best.1 = best.1.min(started.elapsed().as_secs_f64());
+        assert_eq!(bytes, 0);
+    }
+    let rate = |seconds: f64| MESSAGES as f64 / seconds / 1e6;
+    println!("pooled: {:.2}M messages/s, fresh buffers: {:.2}M messages/s", rate(best.0), rate(best.1));
+    assert!(best.0 <= best.1 * 1.1, "pooled {:.3}s, fresh {:.3}s", best.0, best.1);
+}
+
@@ -0,0 +1,60 @@
+use fefix::definitions::fix50::Side;
+
+use crate::config::LiveConfig;
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+
+fn buy(exchange: &mut Exchange, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(NewOrderSingle::builder().account("FIRM1").symbol("AAPL").side(Side::Buy).qty(1).limit(price).parse())
+}
+
+// A band reloaded while the exchange runs holds the very next order, with no restart
+#[test]
+fn a_price_band_changed_at_runtime_holds_the_next_order() {
+    let path = std::env::temp_dir().join(format!("price-bands-{}.toml", std::process::id()));
+    std::fs::write(&path, "price_band_percent = 10.0\n").unwrap();
+    let config = LiveConfig::load(&path).unwrap();
+    let mut exchange = Exchange::new().with_config(config.clone());
+    exchange.handle_message(CreateInstrument::builder().symbol("AAPL").parse());
+    exchange.handle_message(NewOrderSingle::builder().sender("FIRM2").account("FIRM2").symbol("AAPL").side(Side::Sell).qty(1).limit(10.0).parse());
+    let trade = buy(&mut exchange, 10.0);
+    assert!(trade.iter().any(|event| matches!(event, EngineMessage::OrderFilled { .. })), "{:?}", trade);
+
+    let within = buy(&mut exchange, 10.8);
+    assert!(matches!(within.as_slice(), [EngineMessage::OrderAccepted { .. }, ..]), "{:?}", within);
+
+    std::fs::write(&path, "price_band_percent = 5.0\n").unwrap();
+    assert_eq!(config.reload().unwrap().applied, vec!["price_band_percent"]);
+    let outside = buy(&mut exchange, 10.8);
+    assert!(matches!(outside.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Price outside band"), "{:?}", outside);
+    assert!(matches!(buy(&mut exchange, 10.4).as_slice(), [EngineMessage::OrderAccepted { .. }, ..]));
+
+    // An instrument's own band stands in for the default
+    std::fs::write(&path, "price_band_percent = 5.0\n[price_bands]\nAAPL = 20.0\n").unwrap();
+    config.reload().unwrap();
+    assert!(matches!(buy(&mut exchange, 10.8).as_slice(), [EngineMessage::OrderAccepted { .. }, ..]));
+    std::fs::remove_file(&path).unwrap();
+}
+
+// After a split the band is measured from the last trade as the shares now trade, so an order at
+// the split price is in band and one at the old price is not
+#[test]
+fn a_split_moves_the_band_with_the_price() {
+    let path = std::env::temp_dir().join(format!("price-bands-split-{}.toml", std::process::id()));
+    std::fs::write(&path, "price_band_percent = 10.0\n").unwrap();
+    let mut exchange = Exchange::new().with_config(LiveConfig::load(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    exchange.handle_message(CreateInstrument::builder().symbol("AAPL").parse());
+    advance_time(&mut exchange, "20240101-09:30:00.000");
+    exchange.handle_message(NewOrderSingle::builder().sender("FIRM2").account("FIRM2").symbol("AAPL").side(Side::Sell).qty(1).limit(10.0).parse());
+    buy(&mut exchange, 10.0);
+
+    let split = exchange.handle_message(CorporateActionRequest::builder().sender("ADMIN").symbol("AAPL").effective_time("20240101-09:30:00.000").split(2, 1).parse());
+    assert!(split.iter().any(|event| matches!(event, EngineMessage::CorporateActionApplied { .. })), "{:?}", split);
+
+    let at_split_price = buy(&mut exchange, 5.0);
+    assert!(matches!(at_split_price.as_slice(), [EngineMessage::OrderAccepted { .. }, ..]), "{:?}", at_split_price);
+    let at_old_price = buy(&mut exchange, 10.0);
+    assert!(matches!(at_old_price.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Price outside band"), "{:?}", at_old_price);
+}
@@ -0,0 +1,107 @@
+// The same inserts, matches and cancels on a side kept in a tree and on one kept on a ladder.
+// The benchmark only runs on request:
+//   cargo test --release -p exchange-server price_ladders -- --ignored --nocapture
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::price_levels::{Levels, PriceLadder, PriceLevels};
+use crate::types::Price;
+
+const TICK: f64 = 0.01;
+const TICKS: u64 = 2_000; // 90.00 to 110.00
+const ORDERS: u64 = 100_000;
+const RUNS: usize = 5;
+
+fn tree() -> Levels<u64> {
+    Levels::default()
+}
+
+fn ladder() -> Levels<u64> {
+    Levels::default().onto(PriceLadder::new(Price::from(90.0), Price::from(110.0), Price::from(TICK)).unwrap())
+}
+
+// Order `id`'s price, spread over the band so levels fill unevenly
+fn price_of(id: u64) -> Price {
+    Price::from(90.0 + (id * 7_919 % TICKS) as f64 * TICK)
+}
+
+fn insert(levels: &mut impl PriceLevels<u64>, orders: u64) {
+    for id in 0..orders {
+        levels.level_mut(price_of(id)).push_back(id);
+    }
+}
+
+// Cancels every third order, dropping the levels it empties
+fn cancel(levels: &mut impl PriceLevels<u64>, orders: u64) {
+    for id in (0..orders).step_by(3) {
+        let price = price_of(id);
+        let queue = levels.get_mut(&price).unwrap();
+        queue.retain(|&order| order != id);
+        if queue.is_empty() {
+            levels.remove(&price);
+        }
+    }
+}
+
+// Fills resting orders from the lowest price up, as a buy sweeping offers would, returning them in fill order
+fn sweep(levels: &mut impl PriceLevels<u64>, quantity: usize) -> Vec<u64> {
+    let mut filled = Vec::with_capacity(quantity);
+    while filled.len() < quantity {
+        let Some(&best) = levels.keys().next() else { break };
+        let queue = levels.get_mut(&best).unwrap();
+        filled.extend(queue.pop_front());
+        if queue.is_empty() {
+            levels.remove(&best);
+        }
+    }
+    filled
+}
+
+fn state(levels: &impl PriceLevels<u64>) -> Vec<(Price, VecDeque<u64>)> {
+    levels.iter().map(|(price, queue)| (*price, queue.clone())).collect()
+}
+
+#[test]
+fn both_backends_come_out_of_the_same_workload_the_same() {
+    let (mut tree, mut ladder) = (tree(), ladder());
+    for levels in [&mut tree, &mut ladder] {
+        insert(levels, 5_000);
+        cancel(levels, 5_000);
+    }
+    assert_eq!(state(&ladder), state(&tree));
+    assert_eq!(sweep(&mut ladder, 1_234), sweep(&mut tree, 1_234));
+    assert_eq!(state(&ladder), state(&tree));
+    assert_eq!(ladder.keys().next_back(), tree.keys().next_back());
+    assert!(ladder.is_ladder());
+}
+
+#[test]
+#[ignore]
+fn a_ladder_inserts_matches_and_cancels_faster_than_a_tree() {
+    // (insert, cancel, match) seconds, the best of the runs, for the tree then the ladder
+    let mut best = [[f64::MAX; 3]; 2];
+    for _ in 0..RUNS {
+        for (backend, levels) in best.iter_mut().zip([tree(), ladder()]) {
+            let mut levels = levels;
+            let started = Instant::now();
+            insert(&mut levels, ORDERS);
+            backend[0] = backend[0].min(started.elapsed().as_secs_f64());
+
+            let started = Instant::now();
+            cancel(&mut levels, ORDERS);
+            backend[1] = backend[1].min(started.elapsed().as_secs_f64());
+
+            let started = Instant::now();
+            let filled = sweep(&mut levels, ORDERS as usize);
+            backend[2] = backend[2].min(started.elapsed().as_secs_f64());
+            assert_eq!(filled.len() as u64, ORDERS - ORDERS.div_ceil(3));
+        }
+    }
+    let rate = |seconds: f64| ORDERS as f64 / seconds / 1e6;
+    for (operation, index) in [("insert", 0), ("cancel", 1), ("match", 2)] {
+        let (tree, ladder) = (best[0][index], best[1][index]);
+        println!("{}: tree {:.2}M orders/s, ladder {:.2}M orders/s", operation, rate(tree), rate(ladder));
+        assert!(ladder <= tree * 1.1, "{}: tree {:.4}s, ladder {:.4}s", operation, tree, ladder);
+    }
+}
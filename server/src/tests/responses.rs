@@ -0,0 +1,106 @@
+use fefix::definitions::fix50::{OrdStatus, Side};
+
+use crate::engine::{CancelReason, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::handle_fix_message;
+use crate::tests::fix_round_trip::encode;
+use crate::types::*;
+
+fn send(exchange: &mut Exchange, msg_type: &[u8], fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    exchange.handle_message(handle_fix_message(&encode(msg_type, fields)))
+}
+
+fn firm() -> ClientID {
+    ClientID::new("FIRM1".to_string(), Some("ALGO".to_string()))
+}
+
+// Responses compare whole, so a test can spell out everything it expects of one
+#[test]
+fn each_response_to_an_orders_life_compares_equal_to_the_message_spelled_out() {
+    let mut exchange = Exchange::new();
+    send(&mut exchange, b"UCI", &[(55, "AAPL")]);
+    let offer = send(&mut exchange, b"D", &[(1, "SELLER"), (11, "S1"), (55, "AAPL"), (54, "2"), (53, "5"), (40, "2"), (44, "10")]);
+    let accepted = |order_id, client_order_id: &str, side, quantity| EngineMessage::OrderAccepted {
+        client_id: firm(),
+        order_id,
+        client_order_id: Some(client_order_id.to_string()),
+        instrument_id: "AAPL".to_string(),
+        side,
+        quantity,
+    };
+    assert_eq!(offer, vec![accepted(1, "S1", Side::Sell, 5)]);
+
+    let fill = |order_id, client_order_id: &str, side, remaining_quantity, arrival_ask| EngineMessage::OrderFilled {
+        client_id: firm(),
+        order_id,
+        client_order_id: Some(client_order_id.to_string()),
+        filled_quantity: 3,
+        remaining_quantity,
+        cumulative_quantity: 3,
+        price: Price::from(10.0),
+        instrument_id: "AAPL".to_string(),
+        side,
+        arrival_bid: None,
+        arrival_ask,
+        trade_match_id: 1,
+        average_price: Price::from(10.0),
+        summary: false,
+    };
+    let bid = send(&mut exchange, b"D", &[(1, "BUYER"), (11, "B1"), (55, "AAPL"), (54, "1"), (53, "3"), (40, "2"), (44, "10")]);
+    assert_eq!(bid, vec![
+        accepted(2, "B1", Side::Buy, 3),
+        fill(2, "B1", Side::Buy, 0, Some(Price::from(10.0))),
+        fill(1, "S1", Side::Sell, 2, None),
+    ]);
+
+    let amend = send(&mut exchange, b"G", &[(37, "1"), (1, "SELLER"), (55, "AAPL"), (54, "2"), (38, "4"), (40, "2"), (44, "10")]);
+    assert_eq!(amend, vec![EngineMessage::OrderAmended {
+        client_id: firm(),
+        order_id: 1,
+        new_quantity: Some(4),
+        new_price: Some(Price::from(10.0)),
+        status: OrdStatus::PartiallyFilled,
+        cumulative_quantity: 3,
+        leaves_quantity: 1,
+    }]);
+
+    let cancel = send(&mut exchange, b"F", &[(37, "1"), (1, "SELLER")]);
+    assert_eq!(cancel, vec![EngineMessage::OrderCancelled {
+        client_id: firm(),
+        order_id: 1,
+        reason: CancelReason::ClientRequested,
+        instrument_id: "AAPL".to_string(),
+        cancelled_price: Some(Price::from(10.0)),
+        cancelled_quantity: 1,
+        side: Side::Sell,
+        cumulative_quantity: 3,
+        average_price: Price::from(10.0),
+    }]);
+    let too_late = send(&mut exchange, b"F", &[(37, "1"), (1, "SELLER")]);
+    assert_eq!(too_late, vec![EngineMessage::CancelRejected {
+        client_id: firm(),
+        order_id: 1,
+        reason: "Too late to cancel".to_string(),
+        status: OrdStatus::Canceled,
+        cumulative_quantity: 3,
+        average_price: Price::from(10.0),
+    }]);
+
+    let unknown = send(&mut exchange, b"D", &[(1, "BUYER"), (55, "MSFT"), (54, "1"), (53, "3"), (40, "2"), (44, "10")]);
+    assert_eq!(unknown, vec![EngineMessage::OrderRejected { client_id: firm(), reason: "Unknown instrument".to_string(), code: None }]);
+
+    let positions = send(&mut exchange, b"UPQ", &[(1, "BUYER")]);
+    assert_eq!(positions, vec![EngineMessage::PositionReport {
+        client_id: firm(),
+        account_id: "BUYER".to_string(),
+        cash: AccountBalance::from(970.0),
+        positions: vec![("AAPL".to_string(), 3, Price::from(10.0))],
+    }]);
+    // And unequal where any field differs
+    assert_ne!(positions[0], EngineMessage::PositionReport {
+        client_id: firm(),
+        account_id: "BUYER".to_string(),
+        cash: AccountBalance::from(970.0),
+        positions: vec![("AAPL".to_string(), 3, Price::from(10.5))],
+    });
+}
@@ -0,0 +1,518 @@
+// Declarative matching scenarios. Each file under tests/scenarios is a sequence of
+// steps fed to a fresh Exchange as FIX messages; every step lists the exact events it must
+// produce.
+// See tests/scenarios/README.md for the fixture format.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use serde::Deserialize;
+
+use crate::engine::{CancelReason, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::handle_fix_message;
+use crate::fix::testkit::*;
+use crate::instrument::PriceLevelPolicy;
+use crate::types::*;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    description: String, // what the scenario shows, heading its failures
+    #[serde(rename = "step")]
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+struct Step {
+    #[serde(flatten)]
+    input: Input,
+    #[serde(default)]
+    expect: Vec<Expected>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Input {
+    CreateInstrument {
+        instrument: String,
+        #[serde(default)]
+        max_price_levels: usize,
+        #[serde(default = "default_price_level_policy")]
+        price_level_policy: String,
+        #[serde(default)]
+        max_resting_orders: usize,
+    },
+    NewOrder {
+        alias: Option<String>,
+        account: String,
+        client: Option<String>, // defaults to the account
+        instrument: Option<String>, // defaults to the last instrument created
+        client_order_id: Option<String>,
+        side: String,
+        #[serde(rename = "type", default = "default_order_type")]
+        order_type: String,
+        quantity: Quantity,
+        price: Option<f64>,
+        time_in_force: Option<String>,
+        expire_time: Option<String>,
+    },
+    Cancel {
+        order: String,
+        account: Option<String>, // defaults to the account that placed the order
+        client: Option<String>,
+        quantity: Option<Quantity>, // how much to cancel, all of it if left out
+    },
+    Amend {
+        order: String,
+        client: Option<String>, // defaults to the account that placed the order
+        quantity: Option<Quantity>, // the new total, filled quantity included
+        price: Option<f64>,
+        time_in_force: Option<String>,
+    },
+    AdvanceTime {
+        time: String,
+    },
+    Snapshot {
+        instrument: Option<String>,
+        depth: Option<u32>,
+    },
+}
+
+fn default_price_level_policy() -> String {
+    "evict_worst".to_string()
+}
+
+fn default_order_type() -> String {
+    "limit".to_string()
+}
+
+// An expected event; any field left out is not checked
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Expected {
+    event: String,
+    order: Option<String>,
+    quantity: Option<Quantity>,
+    remaining: Option<Quantity>,
+    price: Option<f64>,
+    status: Option<String>,
+    reason: Option<String>,
+    bids: Option<Vec<(f64, Quantity)>>,
+    asks: Option<Vec<(f64, Quantity)>>,
+}
+
+// An event the exchange produced, in the same vocabulary as Expected
+#[derive(Default)]
+struct Observed {
+    event: String,
+    order: Option<String>,
+    quantity: Option<Quantity>,
+    remaining: Option<Quantity>,
+    price: Option<f64>,
+    status: Option<String>,
+    reason: Option<String>,
+    bids: Option<Vec<(f64, Quantity)>>,
+    asks: Option<Vec<(f64, Quantity)>>,
+}
+
+impl Display for Observed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.event)?;
+        if let Some(order) = &self.order { write!(f, " order={}", order)?; }
+        if let Some(quantity) = self.quantity { write!(f, " quantity={}", quantity)?; }
+        if let Some(remaining) = self.remaining { write!(f, " remaining={}", remaining)?; }
+        if let Some(price) = self.price { write!(f, " price={}", price)?; }
+        if let Some(status) = &self.status { write!(f, " status={}", status)?; }
+        if let Some(reason) = &self.reason { write!(f, " reason={:?}", reason)?; }
+        if let Some(bids) = &self.bids { write!(f, " bids={:?}", bids)?; }
+        if let Some(asks) = &self.asks { write!(f, " asks={:?}", asks)?; }
+        Ok(())
+    }
+}
+
+impl Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_observed = Observed {
+            event: self.event.clone(),
+            order: self.order.clone(),
+            quantity: self.quantity,
+            remaining: self.remaining,
+            price: self.price,
+            status: self.status.clone(),
+            reason: self.reason.clone(),
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+        };
+        as_observed.fmt(f)
+    }
+}
+
+// The fields of `expected` that `observed` disagrees with
+fn mismatches(expected: &Expected, observed: &Observed) -> Vec<String> {
+    fn check<T: PartialEq + fmt::Debug>(name: &str, expected: &Option<T>, observed: &Option<T>, out: &mut Vec<String>) {
+        if let Some(value) = expected {
+            match observed {
+                Some(observed) if observed == value => {}
+                Some(observed) => out.push(format!("{}: expected {:?}, got {:?}", name, value, observed)),
+                None => out.push(format!("{}: expected {:?}, got none", name, value)),
+            }
+        }
+    }
+    let mut out = Vec::new();
+    if expected.event != observed.event {
+        out.push(format!("event: expected {:?}, got {:?}", expected.event, observed.event));
+        return out;
+    }
+    check("order", &expected.order, &observed.order, &mut out);
+    check("quantity", &expected.quantity, &observed.quantity, &mut out);
+    check("remaining", &expected.remaining, &observed.remaining, &mut out);
+    check("price", &expected.price, &observed.price, &mut out);
+    check("status", &expected.status, &observed.status, &mut out);
+    check("reason", &expected.reason, &observed.reason, &mut out);
+    check("bids", &expected.bids, &observed.bids, &mut out);
+    check("asks", &expected.asks, &observed.asks, &mut out);
+    out
+}
+
+fn parse_side(side: &str) -> Result<Side, String> {
+    match side {
+        "buy" => Ok(Side::Buy),
+        "sell" => Ok(Side::Sell),
+        _ => Err(format!("unknown side {:?}", side)),
+    }
+}
+
+fn parse_order_type(order_type: &str) -> Result<OrdType, String> {
+    match order_type {
+        "limit" => Ok(OrdType::Limit),
+        "market" => Ok(OrdType::Market),
+        "stop" => Ok(OrdType::Stop),
+        "stop_limit" => Ok(OrdType::StopLimit),
+        _ => Err(format!("unknown order type {:?}", order_type)),
+    }
+}
+
+fn parse_time_in_force(time_in_force: &str) -> Result<TimeInForce, String> {
+    match time_in_force {
+        "day" => Ok(TimeInForce::Day),
+        "gtc" => Ok(TimeInForce::GoodTillCancel),
+        "ioc" => Ok(TimeInForce::ImmediateOrCancel),
+        "fok" => Ok(TimeInForce::FillOrKill),
+        "gtd" => Ok(TimeInForce::GoodTillDate),
+        _ => Err(format!("unknown time in force {:?}", time_in_force)),
+    }
+}
+
+fn parse_price_level_policy(policy: &str) -> Result<PriceLevelPolicy, String> {
+    match policy {
+        "evict_worst" => Ok(PriceLevelPolicy::EvictWorst),
+        "reject" => Ok(PriceLevelPolicy::Reject),
+        _ => Err(format!("unknown price level policy {:?}", policy)),
+    }
+}
+
+fn parse_time(time: &str) -> Result<Timestamp, String> {
+    Timestamp::parse(time.as_bytes()).ok_or_else(|| format!("bad timestamp {:?}, expected YYYYMMDD-HH:MM:SS.sss", time))
+}
+
+// The engine message a step's FIX message parses to; a fixture whose steps do not parse is broken
+fn parsed(message: &str) -> Result<EngineMessage, String> {
+    match handle_fix_message(message) {
+        EngineMessage::InvalidMessage { reason, .. } => Err(format!("{}: {}", reason, message)),
+        parsed => Ok(parsed),
+    }
+}
+
+fn status_name(status: OrdStatus) -> String {
+    match status {
+        OrdStatus::New => "new",
+        OrdStatus::PartiallyFilled => "partially_filled",
+        OrdStatus::Filled => "filled",
+        OrdStatus::Canceled => "canceled",
+        OrdStatus::Expired => "expired",
+        OrdStatus::Rejected => "rejected",
+        other => return format!("{:?}", other),
+    }
+    .to_string()
+}
+
+fn levels(levels: &[(Price, Quantity)]) -> Vec<(f64, Quantity)> {
+    levels.iter().map(|(price, quantity)| (price.into_inner(), *quantity)).collect()
+}
+
+// Runs one scenario against a fresh exchange, resolving aliases as orders are accepted
+struct Runner {
+    exchange: Exchange,
+    aliases: HashMap<String, OrderID>,
+    names: HashMap<OrderID, String>, // alias of each aliased order id
+    order_accounts: HashMap<OrderID, AccountID>,
+    instrument: Option<InstrumentID>,
+}
+
+impl Runner {
+    fn new() -> Self {
+        Self {
+            exchange: Exchange::new(),
+            aliases: HashMap::new(),
+            names: HashMap::new(),
+            order_accounts: HashMap::new(),
+            instrument: None,
+        }
+    }
+
+    fn order_id(&self, alias: &str) -> Result<OrderID, String> {
+        self.aliases.get(alias).copied().ok_or_else(|| format!("unknown order alias {:?}", alias))
+    }
+
+    fn instrument(&self, instrument: Option<String>) -> Result<InstrumentID, String> {
+        instrument.or_else(|| self.instrument.clone()).ok_or_else(|| "no instrument created yet".to_string())
+    }
+
+    // Each step goes in as the FIX message a client would send for it
+    fn run(&mut self, input: Input) -> Result<Vec<Observed>, String> {
+        let message = match input {
+            Input::CreateInstrument { instrument, max_price_levels, price_level_policy, max_resting_orders } => {
+                self.instrument = Some(instrument.clone());
+                CreateInstrument::builder()
+                    .sender("ADMIN")
+                    .symbol(&instrument)
+                    .max_price_levels(max_price_levels)
+                    .price_level_policy(parse_price_level_policy(&price_level_policy)?)
+                    .max_resting_orders(max_resting_orders)
+                    .build()
+            }
+            Input::NewOrder {
+                alias,
+                account,
+                client: client_name,
+                instrument,
+                client_order_id,
+                side,
+                order_type,
+                quantity,
+                price,
+                time_in_force,
+                expire_time,
+            } => {
+                let mut order = NewOrderSingle::builder()
+                    .sender(client_name.as_deref().unwrap_or(&account))
+                    .account(&account)
+                    .symbol(&self.instrument(instrument)?)
+                    .side(parse_side(&side)?)
+                    .order_type(parse_order_type(&order_type)?)
+                    .qty(quantity);
+                if let Some(client_order_id) = client_order_id {
+                    order = order.cl_ord_id(&client_order_id);
+                }
+                if let Some(price) = price {
+                    order = order.price(price);
+                }
+                if let Some(time_in_force) = time_in_force {
+                    order = order.time_in_force(parse_time_in_force(&time_in_force)?);
+                }
+                if let Some(expire_time) = expire_time {
+                    order = order.expire_time(&expire_time);
+                }
+                let events = self.exchange.handle_message(parsed(&order.build())?);
+                if let Some(EngineMessage::OrderAccepted { order_id, .. }) = events.first() {
+                    self.order_accounts.insert(*order_id, account);
+                    if let Some(alias) = alias {
+                        if self.aliases.insert(alias.clone(), *order_id).is_some() {
+                            return Err(format!("order alias {:?} used twice", alias));
+                        }
+                        self.names.insert(*order_id, alias);
+                    }
+                }
+                return Ok(events.iter().map(|event| self.observe(event)).collect());
+            }
+            Input::Cancel { order, account, client: client_name, quantity } => {
+                let order_id = self.order_id(&order)?;
+                let account = account.unwrap_or_else(|| self.order_accounts[&order_id].clone());
+                let cancel = OrderCancelRequest::builder().sender(client_name.as_deref().unwrap_or(&account)).order_id(order_id).account(&account);
+                match quantity {
+                    Some(quantity) => cancel.cxl_qty(quantity).build(),
+                    None => cancel.build(),
+                }
+            }
+            Input::Amend { order, client: client_name, quantity, price, time_in_force } => {
+                let order_id = self.order_id(&order)?;
+                let client_name = client_name.unwrap_or_else(|| self.order_accounts[&order_id].clone());
+                let mut amend = OrderCancelReplaceRequest::builder().sender(&client_name).order_id(order_id);
+                if let Some(quantity) = quantity {
+                    amend = amend.qty(quantity);
+                }
+                if let Some(price) = price {
+                    amend = amend.price(price);
+                }
+                if let Some(time_in_force) = time_in_force {
+                    amend = amend.time_in_force(parse_time_in_force(&time_in_force)?);
+                }
+                amend.build()
+            }
+            Input::AdvanceTime { time } => {
+                parse_time(&time)?;
+                AdvanceTime::builder().sender("ADMIN").to(&time).build()
+            }
+            Input::Snapshot { instrument, depth } => {
+                let snapshot = MarketDataRequest::builder().sender("ADMIN").symbol(&self.instrument(instrument)?).snapshot();
+                match depth {
+                    Some(depth) => snapshot.depth(depth).build(),
+                    None => snapshot.build(),
+                }
+            }
+        };
+        let events = self.exchange.handle_message(parsed(&message)?);
+        Ok(events.iter().map(|event| self.observe(event)).collect())
+    }
+
+    fn order_name(&self, order_id: OrderID) -> String {
+        self.names.get(&order_id).cloned().unwrap_or_else(|| format!("#{}", order_id))
+    }
+
+    fn observe(&self, event: &EngineMessage) -> Observed {
+        match event {
+            EngineMessage::OrderAccepted { order_id, .. } => Observed {
+                event: "accepted".to_string(),
+                order: Some(self.order_name(*order_id)),
+                status: Some("new".to_string()),
+                ..Observed::default()
+            },
+            EngineMessage::OrderRejected { reason, .. } => Observed {
+                event: "rejected".to_string(),
+                status: Some("rejected".to_string()),
+                reason: Some(reason.clone()),
+                ..Observed::default()
+            },
+            EngineMessage::OrderFilled { order_id, filled_quantity, remaining_quantity, price, .. } => Observed {
+                event: "filled".to_string(),
+                order: Some(self.order_name(*order_id)),
+                quantity: Some(*filled_quantity),
+                remaining: Some(*remaining_quantity),
+                price: Some(price.into_inner()),
+                status: Some(if *remaining_quantity == 0 { "filled" } else { "partially_filled" }.to_string()),
+                ..Observed::default()
+            },
+            EngineMessage::OrderCancelled { order_id, reason, cancelled_price, cancelled_quantity, .. } => Observed {
+                event: "cancelled".to_string(),
+                order: Some(self.order_name(*order_id)),
+                quantity: Some(*cancelled_quantity),
+                price: cancelled_price.map(|price| price.into_inner()),
+                status: Some(if *reason == CancelReason::Expired { "expired" } else { "canceled" }.to_string()),
+                reason: Some(reason.to_string()),
+                ..Observed::default()
+            },
+            EngineMessage::CancelRejected { order_id, reason, status, cumulative_quantity, average_price, .. } => Observed {
+                event: "cancel_rejected".to_string(),
+                order: Some(self.order_name(*order_id)),
+                quantity: Some(*cumulative_quantity),
+                price: Some(average_price.into_inner()),
+                status: Some(status_name(*status)),
+                reason: Some(reason.clone()),
+                ..Observed::default()
+            },
+            EngineMessage::OrderAmended { order_id, new_quantity, new_price, status, leaves_quantity, .. } => Observed {
+                event: "amended".to_string(),
+                order: Some(self.order_name(*order_id)),
+                quantity: *new_quantity,
+                remaining: Some(*leaves_quantity),
+                price: new_price.map(|price| price.into_inner()),
+                status: Some(status_name(*status)),
+                ..Observed::default()
+            },
+            EngineMessage::AmendRejected { order_id, reason, status, .. } => Observed {
+                event: "amend_rejected".to_string(),
+                order: Some(self.order_name(*order_id)),
+                status: Some(status_name(*status)),
+                reason: Some(reason.clone()),
+                ..Observed::default()
+            },
+            EngineMessage::OrderExpired { order_id, .. } => Observed {
+                event: "expired".to_string(),
+                order: Some(self.order_name(*order_id)),
+                status: Some("expired".to_string()),
+                ..Observed::default()
+            },
+            EngineMessage::Snapshot { bids, asks, .. } => Observed {
+                event: "snapshot".to_string(),
+                bids: Some(levels(bids)),
+                asks: Some(levels(asks)),
+                ..Observed::default()
+            },
+            EngineMessage::AccountStatements { statements, .. } => Observed {
+                event: "statements".to_string(),
+                reason: Some(statements.iter().map(|statement| statement.account_id.as_str()).collect::<Vec<_>>().join(",")),
+                ..Observed::default()
+            },
+            EngineMessage::LogEvent { message, .. } => Observed {
+                event: "log".to_string(),
+                reason: Some(message.clone()),
+                ..Observed::default()
+            },
+            other => Observed {
+                event: "unhandled".to_string(),
+                reason: Some(format!("{:?}", other)),
+                ..Observed::default()
+            },
+        }
+    }
+}
+
+// Describes every way a step's events differ from what the fixture expects
+fn diff_events(expected: &[Expected], observed: &[Observed]) -> Vec<String> {
+    let mut report = Vec::new();
+    for index in 0..expected.len().max(observed.len()) {
+        match (expected.get(index), observed.get(index)) {
+            (Some(expected), Some(observed)) => {
+                let fields = mismatches(expected, observed);
+                if !fields.is_empty() {
+                    report.push(format!("  event {}: {}\n    expected: {}\n    got:      {}", index + 1, fields.join("; "), expected, observed));
+                }
+            }
+            (Some(expected), None) => report.push(format!("  event {}: missing {}", index + 1, expected)),
+            (None, Some(observed)) => report.push(format!("  event {}: unexpected {}", index + 1, observed)),
+            (None, None) => unreachable!(),
+        }
+    }
+    report
+}
+
+fn run_scenario(path: &Path) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let Scenario { description, steps } = toml::from_str(&text).map_err(|e| e.to_string())?;
+    let mut runner = Runner::new();
+    for (index, step) in steps.into_iter().enumerate() {
+        let described = format!("{:?}", step.input);
+        let observed = runner.run(step.input).map_err(|e| format!("{}\nstep {}: {}", description, index + 1, e))?;
+        let report = diff_events(&step.expect, &observed);
+        if !report.is_empty() {
+            let got: Vec<String> = observed.iter().map(|event| format!("    {}", event)).collect();
+            return Err(format!("{}\nstep {} {}\n{}\n  all events:\n{}", description, index + 1, described, report.join("\n"), got.join("\n")));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn scenarios_produce_their_expected_events() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(&directory)
+        .expect("scenario directory missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no scenarios in {}", directory.display());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| {
+            // A panicking scenario is reported with the others rather than hiding them
+            let outcome = std::panic::catch_unwind(|| run_scenario(path)).unwrap_or_else(|_| Err("panicked".to_string()));
+            outcome.err().map(|e| format!("{}: {}", path.file_name().unwrap().to_string_lossy(), e))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{} of {} scenarios failed\n\n{}", failures.len(), paths.len(), failures.join("\n\n"));
+}
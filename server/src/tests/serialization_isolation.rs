@@ -0,0 +1,110 @@
+// Benchmark: the consumer's CPU time per order must not grow when the outbound stage
+// serializes every event to FIX. Timing-sensitive, so it only runs on request:
+//   cargo test --release -p exchange-server serialization -- --ignored --nocapture
+// CPU time is read per thread, so the outbound thread competing for the same cores
+// does not count against the consumer.
+
+use std::time::Duration;
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use tokio::sync::mpsc;
+
+use crate::engine::{EngineMessage, OutboundBatch};
+use crate::exchange::Exchange;
+use crate::namespace::Namespaces;
+use crate::fix::serialize_engine_message;
+use crate::inbound::inbound_channel;
+use crate::instrument::SpecOverrides;
+use crate::supervisor::EngineHealth;
+use crate::types::*;
+
+const ORDERS: usize = 40_000;
+// Enough accounts that none runs out of its starting cash over the run
+const ACCOUNTS: usize = 400;
+const RUNS: usize = 5;
+
+fn order(index: usize) -> EngineMessage {
+    let account = format!("T{}", index % ACCOUNTS);
+    EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new(account.clone(), None),
+        account_id: account,
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        // Every sell takes the bid just before it, so each pair is two acks and two fills
+        side: if index.is_multiple_of(2) { Side::Buy } else { Side::Sell },
+        quantity: 1,
+        price: Some(Price::from(1.0)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    }
+}
+
+// CPU time the calling thread has spent running, from the scheduler's own accounting
+fn thread_cpu_time() -> Duration {
+    let schedstat = std::fs::read_to_string("/proc/thread-self/schedstat").unwrap();
+    let nanos = schedstat.split_whitespace().next().unwrap().parse().unwrap();
+    Duration::from_nanos(nanos)
+}
+
+// Consumer CPU time per order, with the outbound stage serializing or discarding what it gets
+fn consumer_time_per_order(serialize: bool) -> Duration {
+    let (tx, rx) = inbound_channel(true);
+    tx.send(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    }).unwrap();
+    for index in 0..ORDERS {
+        tx.send(order(index)).unwrap();
+    }
+    drop(tx);
+
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<OutboundBatch>();
+    let outbound = std::thread::spawn(move || {
+        let mut serialized = 0;
+        while let Some(batch) = outbound_rx.blocking_recv() {
+            if serialize {
+                serialized += batch.events.iter().filter_map(serialize_engine_message).count();
+            }
+        }
+        serialized
+    });
+
+    let consumer = std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let start = thread_cpu_time();
+        rt.block_on(crate::consume(Namespaces::new(Exchange::new()), rx, outbound_tx, EngineHealth::new(), std::future::pending()));
+        thread_cpu_time() - start
+    });
+    let elapsed = consumer.join().unwrap();
+    let serialized = outbound.join().unwrap();
+    assert_eq!(serialized > 0, serialize);
+    elapsed / ORDERS as u32
+}
+
+#[test]
+#[ignore]
+#[cfg(target_os = "linux")]
+fn serialization_does_not_slow_the_matching_thread() {
+    // Alternate the two so drift on the machine hits both alike, and keep each one's best run
+    let mut without = Duration::MAX;
+    let mut with = Duration::MAX;
+    for _ in 0..RUNS {
+        without = without.min(consumer_time_per_order(false));
+        with = with.min(consumer_time_per_order(true));
+    }
+    println!("matching thread per order: {:?} without serialization, {:?} with", without, with);
+    // Allow for cache noise from sharing cores with the outbound thread
+    assert!(with <= without * 5 / 4, "serialization added {:?} per order", with.saturating_sub(without));
+}
@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::config::ServerConfig;
+use crate::credentials::Credentials;
+use crate::tests::logon_credentials::{connect, logged_on, message, start_server_with};
+
+// Every CompID here is unique to its test, since sessions register in one process-wide map
+
+// A session logged on as `comp_id`, with SenderSubID `sub_id` if given
+async fn logon(address: SocketAddr, comp_id: &str, sub_id: Option<&str>) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+    let (mut lines, mut writer) = connect(address).await;
+    let fields: Vec<_> = sub_id.map(|sub_id| (50, sub_id)).into_iter().collect();
+    writer.write_all(message(b"A", comp_id, &fields).as_bytes()).await.unwrap();
+    logged_on(&mut lines, comp_id).await;
+    (lines, writer)
+}
+
+// Asks for a book that is not there, so the next line is the engine's answer or the session's reject
+async fn request_book(lines: &mut Lines<BufReader<OwnedReadHalf>>, writer: &mut OwnedWriteHalf, comp_id: &str, sub_id: Option<&str>) -> String {
+    let mut fields = vec![(55, "AAPL")];
+    fields.extend(sub_id.map(|sub_id| (50, sub_id)));
+    writer.write_all(message(b"V", comp_id, &fields).as_bytes()).await.unwrap();
+    lines.next_line().await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn a_message_that_leaves_out_the_sub_id_logged_on_with_is_answered_on_the_session() {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = logon(address, "SUB_ID_DROPPED", Some("DESK")).await;
+
+    let reply = request_book(&mut lines, &mut writer, "SUB_ID_DROPPED", None).await;
+    assert!(reply.contains("|56=SUB_ID_DROPPED|57=DESK|") && reply.contains("Unknown instrument"), "{}", reply);
+}
+
+#[tokio::test]
+async fn a_sub_id_on_a_session_that_logged_on_without_one_is_rejected() {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = logon(address, "SUB_ID_ADDED", None).await;
+
+    let reject = request_book(&mut lines, &mut writer, "SUB_ID_ADDED", Some("DESK")).await;
+    assert!(reject.contains("|35=3|") && reject.contains("SenderSubID does not match the session"), "{}", reject);
+    // The session carries on as it logged on
+    let reply = request_book(&mut lines, &mut writer, "SUB_ID_ADDED", None).await;
+    assert!(reply.contains("|56=SUB_ID_ADDED|") && !reply.contains("|57=") && reply.contains("Unknown instrument"), "{}", reply);
+}
+
+#[tokio::test]
+async fn a_changed_sub_id_is_rejected_rather_than_routed_elsewhere() {
+    let (address, _gate) = start_server_with(Credentials::open(), ServerConfig::default()).await;
+    let (mut lines, mut writer) = logon(address, "SUB_ID_CHANGED", Some("DESK")).await;
+
+    let reject = request_book(&mut lines, &mut writer, "SUB_ID_CHANGED", Some("ALGO")).await;
+    assert!(reject.contains("|35=3|") && reject.contains("SenderSubID does not match the session"), "{}", reject);
+    let reply = request_book(&mut lines, &mut writer, "SUB_ID_CHANGED", Some("DESK")).await;
+    assert!(reply.contains("|57=DESK|") && reply.contains("Unknown instrument"), "{}", reply);
+}
@@ -0,0 +1,131 @@
+use fefix::definitions::fix50::{OrdType, Side};
+use fefix::fix_values::Timestamp;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::client;
+use crate::fix::handle_fix_message;
+use crate::instrument::SpecOverrides;
+use crate::tests::fix_round_trip::encode;
+use crate::types::{Price, Quantity};
+
+// Defines an instrument over FIX, as an admin would, and returns what the exchange said
+fn define(exchange: &mut Exchange, fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    let definition = handle_fix_message(&encode(b"UCI", fields));
+    assert!(matches!(definition, EngineMessage::CreateInstrument { .. }), "{:?}", definition);
+    exchange.handle_message(definition)
+}
+
+fn order(exchange: &mut Exchange, account: &str, instrument_id: &str, side: Side, quantity: Quantity, price: Option<f64>) -> Vec<EngineMessage> {
+    exchange.handle_message(EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(account),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: instrument_id.to_string(),
+        order_type: if price.is_some() { OrdType::Limit } else { OrdType::Market },
+        side,
+        quantity,
+        price: price.map(Price::from),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    })
+}
+
+// Trades `quantity` of an outright between two accounts, so it has a last trade and the buyer holds it
+fn trade(exchange: &mut Exchange, instrument_id: &str, buyer: &str, quantity: Quantity, price: f64) {
+    order(exchange, "MAKER", instrument_id, Side::Sell, quantity, Some(price));
+    order(exchange, buyer, instrument_id, Side::Buy, quantity, None);
+}
+
+// Cash and each held instrument's quantity and average cost
+fn position(exchange: &mut Exchange, account: &str) -> (Price, Vec<(String, Quantity, Price)>) {
+    let reply = exchange.handle_message(EngineMessage::PositionQuery {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(account),
+        account_id: account.to_string(),
+    });
+    match reply.as_slice() {
+        [EngineMessage::PositionReport { cash, positions, .. }] => (*cash, positions.iter().filter(|(_, quantity, _)| *quantity > 0).cloned().collect()),
+        _ => panic!("expected a position report: {:?}", reply),
+    }
+}
+
+fn rejection(events: &[EngineMessage]) -> &str {
+    match events {
+        [EngineMessage::OrderRejected { reason, .. }] => reason,
+        _ => panic!("expected a rejection: {:?}", events),
+    }
+}
+
+fn calendar_spread() -> Exchange {
+    let mut exchange = Exchange::new();
+    for instrument_id in ["ESZ4", "ESH5"] {
+        exchange.handle_message(EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: client("ADMIN"),
+            instrument_id: instrument_id.to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        });
+    }
+    assert!(define(&mut exchange, &[(55, "ESZ4-ESH5"), (8027, "ESZ4:1 ESH5:-1")]).is_empty());
+    exchange
+}
+
+#[test]
+fn a_spread_fill_is_booked_into_its_legs_at_prices_off_the_front_leg() {
+    let mut exchange = calendar_spread();
+    // Nothing to price the legs off until the front month has traded
+    assert_eq!(rejection(&order(&mut exchange, "LONG", "ESZ4-ESH5", Side::Buy, 1, Some(2.0))), "Spread anchor leg has not traded");
+    trade(&mut exchange, "ESH5", "LONG", 5, 99.0);
+    trade(&mut exchange, "ESZ4", "OTHER", 1, 102.0);
+
+    // The spread trades on its own book, like any instrument
+    order(&mut exchange, "SHORT", "ESZ4-ESH5", Side::Sell, 2, Some(2.5));
+    let (cash_before, _) = position(&mut exchange, "LONG");
+    let events = order(&mut exchange, "LONG", "ESZ4-ESH5", Side::Buy, 2, None);
+    assert_eq!(events.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { price, .. } if *price == Price::from(2.5))).count(), 2, "{:?}", events);
+
+    // Buying the spread bought the front month at its last trade and sold the back month at
+    // what makes the two add up to 2.5; only the spread's price changed hands
+    let (cash, positions) = position(&mut exchange, "LONG");
+    assert_eq!(cash, cash_before - Price::from(5.0));
+    assert_eq!(positions, vec![
+        ("ESH5".to_string(), 3, Price::from(99.0)),
+        ("ESZ4".to_string(), 2, Price::from(102.0)),
+    ]);
+    // The seller is the other way round, and neither holds the spread itself
+    let (_, positions) = position(&mut exchange, "SHORT");
+    assert_eq!(positions, vec![("ESH5".to_string(), 2, Price::from(99.5))]);
+}
+
+#[test]
+fn a_spread_can_be_anchored_on_another_leg_but_only_on_listed_outrights() {
+    let mut exchange = calendar_spread();
+    assert!(define(&mut exchange, &[(55, "BACK"), (8027, "ESZ4:1 ESH5:-1"), (8028, "ESH5")]).is_empty());
+    trade(&mut exchange, "ESH5", "OTHER", 1, 100.0);
+    order(&mut exchange, "SHORT", "BACK", Side::Sell, 1, Some(3.0));
+    order(&mut exchange, "LONG", "BACK", Side::Buy, 1, None);
+    let (_, positions) = position(&mut exchange, "LONG");
+    assert_eq!(positions, vec![("ESZ4".to_string(), 1, Price::from(103.0))]);
+
+    for (legs, reason) in [
+        ("ESZ4:1", "A spread takes two legs"),
+        ("ESZ4:1 ESZ4:-1", "Spread legs must be different instruments"),
+        ("ESZ4:1 ESH5:0", "Spread leg ratio must not be zero"),
+        ("ESZ4:1 ESM5:-1", "Unknown spread leg"),
+        ("ESZ4:1 ESZ4-ESH5:-1", "Unknown spread leg"),
+    ] {
+        assert_eq!(rejection(&define(&mut exchange, &[(55, "BAD"), (8027, legs)])), reason, "{}", legs);
+    }
+    assert_eq!(rejection(&define(&mut exchange, &[(55, "BAD"), (8027, "ESZ4:1 ESH5:-1"), (8028, "ESM5")])), "Spread anchor is not one of its legs");
+    assert!(matches!(handle_fix_message(&encode(b"UCI", &[(55, "BAD"), (8027, "ESZ4 ESH5")])), EngineMessage::InvalidMessage { reason, .. } if reason == "Invalid SpreadLegs"));
+}
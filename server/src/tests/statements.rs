@@ -0,0 +1,99 @@
+use fefix::definitions::fix50::Side;
+use fefix::fix_values::Timestamp;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::*;
+use crate::statements::AccountStatement;
+use crate::types::ClientID;
+
+// The statement has to tie out with the account as the exchange holds it: every row moves the
+// balance by its amount from where the statement opened to where it closed, which is the
+// account's cash, and the closing positions are the account's own
+fn assert_reconciles(statement: &AccountStatement, exchange: &mut Exchange) {
+    let mut balance = statement.opening_cash;
+    for entry in &statement.entries {
+        balance += entry.amount;
+        assert_eq!(entry.balance, balance, "{:?}", entry);
+    }
+    assert_eq!(balance, statement.closing_cash);
+
+    let firm = &statement.account_id;
+    let report = exchange.handle_message(PositionQuery::builder().sender(firm).account(firm).parse());
+    let [EngineMessage::PositionReport { cash, positions, .. }] = report.as_slice() else { panic!("{:?}", report) };
+    assert_eq!(*cash, statement.closing_cash);
+    let held: Vec<_> = positions.iter().map(|(instrument_id, quantity, _)| (instrument_id.clone(), *quantity)).collect();
+    let closing: Vec<_> = statement.closing_positions.iter().map(|position| (position.instrument_id.clone(), position.quantity)).collect();
+    assert_eq!(held, closing);
+}
+
+// A day of resting, filling and cancelling, closed by the next day's start: each account's
+// statement lists every hold, release and fill with the balance it left, and marks what is
+// still held at the day's last trade
+#[test]
+fn the_day_roll_closes_a_statement_per_account_that_ties_out_with_its_cash_and_positions() {
+    let mut exchange = Exchange::new();
+    advance_time(&mut exchange, "20240102-09:30:00.000");
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").parse());
+
+    let ask = accepted_order_id(&order(&mut exchange, "FIRM2", Side::Sell, 4, 10.0));
+    let bid = accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 4, 10.0));
+    let cancelled = accepted_order_id(&order(&mut exchange, "FIRM1", Side::Buy, 2, 9.0));
+    exchange.handle_message(OrderCancelRequest::builder().sender("FIRM1").account("FIRM1").order_id(cancelled).parse());
+    let sale = accepted_order_id(&order(&mut exchange, "FIRM1", Side::Sell, 1, 11.0));
+    let lift = accepted_order_id(&order(&mut exchange, "FIRM2", Side::Buy, 1, 11.0));
+
+    let rolled = advance_time(&mut exchange, "20240103-09:30:00.000");
+    let statements: Vec<&AccountStatement> = rolled.iter().filter_map(|event| match event {
+        EngineMessage::AccountStatements { client_id: None, statements } => Some(statements),
+        _ => None,
+    }).flatten().collect();
+    let [firm1, firm2] = statements.as_slice() else { panic!("{:?}", rolled) };
+    assert_eq!((firm1.account_id.as_str(), firm1.session.as_str()), ("FIRM1", "20240102"));
+
+    assert_eq!(firm1.to_csv(), [
+        "row,order_id,instrument,quantity,price,amount,balance".to_string(),
+        "opening_cash,,,,,,1000".to_string(),
+        format!("hold,{},AAPL,4,10,-40,960", bid),
+        format!("release,{},AAPL,4,10,40,1000", bid),
+        format!("buy,{},AAPL,4,10,-40,960", bid),
+        format!("hold,{},AAPL,2,9,-18,942", cancelled),
+        format!("release,{},AAPL,2,9,18,960", cancelled),
+        format!("sell,{},AAPL,1,11,11,971", sale),
+        "closing_position,,AAPL,3,11,33,".to_string(),
+        "realized_pnl,,,,,1,".to_string(),
+        "closing_cash,,,,,,971".to_string(),
+    ].map(|row| row + "\n").concat());
+    assert_eq!(firm2.to_csv(), [
+        "row,order_id,instrument,quantity,price,amount,balance".to_string(),
+        "opening_cash,,,,,,1000".to_string(),
+        format!("sell,{},AAPL,4,10,40,1040", ask),
+        format!("hold,{},AAPL,1,11,-11,1029", lift),
+        format!("release,{},AAPL,1,11,11,1040", lift),
+        format!("buy,{},AAPL,1,11,-11,1029", lift),
+        "closing_position,,AAPL,1,11,11,".to_string(),
+        "realized_pnl,,,,,0,".to_string(),
+        "closing_cash,,,,,,1029".to_string(),
+    ].map(|row| row + "\n").concat());
+    assert_reconciles(firm1, &mut exchange);
+    assert_reconciles(firm2, &mut exchange);
+
+    let dir = std::env::temp_dir().join(format!("statements-{}", std::process::id()));
+    let path = firm1.write_csv(&dir).unwrap();
+    assert_eq!(path, dir.join("FIRM1-20240102.csv"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), firm1.to_csv());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Asked for on the next day, a statement opens where the last one closed
+    let asked = exchange.handle_message(EngineMessage::StatementRequest {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new("ADMIN".to_string(), None),
+        account_id: Some("FIRM1".to_string()),
+    });
+    let [EngineMessage::AccountStatements { client_id: Some(_), statements }] = asked.as_slice() else { panic!("{:?}", asked) };
+    let [today] = statements.as_slice() else { panic!("{:?}", statements) };
+    assert_eq!((today.session.as_str(), today.opening_cash, today.opening_positions.clone()), ("20240103", firm1.closing_cash, vec![("AAPL".to_string(), 3)]));
+    assert!(today.entries.is_empty());
+    assert_reconciles(today, &mut exchange);
+}
@@ -0,0 +1,335 @@
+use fefix::definitions::fix50::{OrdStatus, OrdType, Side, TimeInForce};
+use fefix::fix_values::Timestamp;
+
+use crate::engine::{CancelReason, EngineMessage, SmpAction};
+use crate::exchange::Exchange;
+use crate::fix::serialize_engine_message;
+use crate::fix::testkit::{advance_time, client};
+use crate::instrument::SpecOverrides;
+use crate::types::{OrderID, Price, Quantity};
+
+fn exchange() -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("ADMIN"),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    });
+    advance_time(&mut exchange, "20240102-14:30:00.000");
+    exchange
+}
+
+fn order(exchange: &mut Exchange, account: &str, order_type: OrdType, side: Side, quantity: Quantity, price: f64) -> Vec<EngineMessage> {
+    exchange.handle_message(EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(account),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type,
+        side,
+        quantity,
+        price: Some(Price::from(price)),
+        time_in_force: Some(TimeInForce::Day),
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    })
+}
+
+// Places a stop the touch hasn't reached, so all it gets back is its acceptance
+fn parked_stop(exchange: &mut Exchange, order_type: OrdType, side: Side, price: f64) -> OrderID {
+    let events = order(exchange, "STOPPER", order_type, side, 1, price);
+    match events.as_slice() {
+        [EngineMessage::OrderAccepted { order_id, .. }] => *order_id,
+        _ => panic!("expected the stop to be parked: {:?}", events),
+    }
+}
+
+fn status(exchange: &mut Exchange, sender: &str, order_id: OrderID) -> Vec<EngineMessage> {
+    exchange.handle_message(EngineMessage::OrderStatusRequest {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(sender),
+        order_id,
+    })
+}
+
+fn cash(exchange: &mut Exchange, account: &str) -> Price {
+    let report = exchange.handle_message(EngineMessage::PositionQuery {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client(account),
+        account_id: account.to_string(),
+    });
+    match report.as_slice() {
+        [EngineMessage::PositionReport { cash, .. }] => *cash,
+        _ => panic!("{:?}", report),
+    }
+}
+
+// The prices the order filled at among `events`
+fn fills_of(events: &[EngineMessage], order_id: OrderID) -> Vec<Price> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            EngineMessage::OrderFilled { order_id: filled, price, .. } if *filled == order_id => Some(*price),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn a_buy_stop_triggers_on_a_trade_at_or_above_its_stop_price() {
+    let mut exchange = exchange();
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 2, 11.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+
+    // The sweep trades at 10 and then at the stop price, which triggers it into what is left
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 2, 11.0);
+    assert_eq!(fills_of(&events, stop), vec![Price::from(11.0)], "{:?}", events);
+    // It traded as a market order, so only the fill was paid for
+    let position = exchange.handle_message(EngineMessage::PositionQuery {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        account_id: "STOPPER".to_string(),
+    });
+    assert!(matches!(position.as_slice(), [EngineMessage::PositionReport { cash, .. }] if *cash == Price::from(989.0)), "{:?}", position);
+}
+
+#[test]
+fn a_buy_stop_waits_through_trades_below_its_stop_price() {
+    let mut exchange = exchange();
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 2, 12.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+
+    // A trade at 10 leaves it parked, and so does the touch moving past it with nothing trading
+    advance_time(&mut exchange, "20240102-14:31:00.000");
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 1, 10.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+
+    // Still there for the first trade that reaches it
+    advance_time(&mut exchange, "20240102-14:32:00.000");
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 1, 12.0);
+    assert_eq!(fills_of(&events, stop), vec![Price::from(12.0)], "{:?}", events);
+}
+
+#[test]
+fn a_triggered_buy_stop_limit_rests_at_its_limit_price() {
+    let mut exchange = exchange();
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 11.0);
+    let stop = parked_stop(&mut exchange, OrdType::StopLimit, Side::Buy, 11.0);
+
+    // Triggered by the trade at 11, with nothing left offered at or under its limit
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 2, 11.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+    assert!(!events.iter().any(|event| matches!(event, EngineMessage::OrderCancelled { order_id, .. } if *order_id == stop)), "{:?}", events);
+
+    // It is on the book now, a bid at 11 a seller can take
+    let events = order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 11.0);
+    assert_eq!(fills_of(&events, stop), vec![Price::from(11.0)], "{:?}", events);
+}
+
+#[test]
+fn a_sell_stop_limit_the_market_gaps_past_is_cancelled() {
+    let mut exchange = exchange();
+    order(&mut exchange, "BUYER", OrdType::Limit, Side::Buy, 1, 10.0);
+    order(&mut exchange, "BUYER", OrdType::Limit, Side::Buy, 1, 8.0);
+    let stop = parked_stop(&mut exchange, OrdType::StopLimit, Side::Sell, 9.0);
+
+    // The sweep trades at 10 and then at 8, already under the limit of 9 when it triggers
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Sell, 2, 8.0);
+    let cancelled = events.iter().find(|event| matches!(event, EngineMessage::OrderCancelled { order_id, .. } if *order_id == stop));
+    assert!(matches!(cancelled, Some(EngineMessage::OrderCancelled { reason: CancelReason::Other(reason), cancelled_quantity: 1, .. })
+        if reason == "Market moved past stop limit price"), "{:?}", events);
+    assert!(fills_of(&events, stop).is_empty());
+    // Nor was it left offered at 9
+    let events = order(&mut exchange, "BUYER", OrdType::Limit, Side::Buy, 1, 9.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+}
+
+#[test]
+fn a_sell_stop_triggers_on_a_trade_at_or_below_its_stop_price() {
+    let mut exchange = exchange();
+    for price in [10.0, 9.0, 8.0] {
+        order(&mut exchange, "BUYER", OrdType::Limit, Side::Buy, 1, price);
+    }
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Sell, 9.0);
+
+    // Trading at 10 leaves it parked; the trade at 9 triggers it into the bid at 8
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Sell, 1, 10.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+    advance_time(&mut exchange, "20240102-14:31:00.000");
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Sell, 1, 9.0);
+    assert_eq!(fills_of(&events, stop), vec![Price::from(8.0)], "{:?}", events);
+}
+
+// A parked stop is as open as a resting order: its owner, and only its owner, sees it as New
+// and untriggered, and moving its stop price moves what trade it waits for
+#[test]
+fn a_parked_stop_is_an_open_order_its_owner_can_see_and_amend() {
+    let mut exchange = exchange();
+    for (quantity, price) in [(1, 10.0), (1, 11.0), (2, 12.0)] {
+        order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, quantity, price);
+    }
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+    let events = status(&mut exchange, "STOPPER", stop);
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderStatusReport { status: OrdStatus::New, instrument_id: Some(instrument_id), leaves_quantity: 1, untriggered: true, .. }]
+        if instrument_id == "AAPL"), "{:?}", events);
+    let events = status(&mut exchange, "OTHER", stop);
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Order not found"), "{:?}", events);
+
+    let events = exchange.handle_message(EngineMessage::AmendOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        order_id: stop,
+        new_quantity: None,
+        new_price: Some(Price::from(12.0)),
+        time_in_force: None,
+    });
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderAmended { new_price: Some(price), .. }] if *price == Price::from(12.0)), "{:?}", events);
+
+    // Trading at 10 and 11 no longer reaches it
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 2, 11.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+    assert!(matches!(status(&mut exchange, "STOPPER", stop).as_slice(), [EngineMessage::OrderStatusReport { untriggered: true, .. }]));
+
+    advance_time(&mut exchange, "20240102-14:31:00.000");
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 1, 12.0);
+    assert_eq!(fills_of(&events, stop), vec![Price::from(12.0)], "{:?}", events);
+    let events = status(&mut exchange, "STOPPER", stop);
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderStatusReport { status: OrdStatus::Filled, instrument_id: None, cumulative_quantity: 1, leaves_quantity: 0, untriggered: false, average_price, .. }]
+        if *average_price == Price::from(12.0)), "{:?}", events);
+}
+
+// Amended to where it triggers at once, a stop of an account that rejects its own aggressors
+// is refused if what it triggers into would trade with that account, and stays parked as it
+// was, even though as a limit at its stop price it would not have crossed
+#[test]
+fn a_stop_amended_into_crossing_its_own_account_is_refused_and_left_parked() {
+    let mut exchange = exchange();
+    exchange.handle_message(EngineMessage::SetSmpAction {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        account_id: "STOPPER".to_string(),
+        smp_action: SmpAction::RejectAggressor,
+    });
+    order(&mut exchange, "STOPPER", OrdType::Limit, Side::Buy, 1, 10.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Sell, 8.0);
+    let cash_before = cash(&mut exchange, "STOPPER");
+
+    let events = exchange.handle_message(EngineMessage::AmendOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        order_id: stop,
+        new_quantity: None,
+        new_price: Some(Price::from(11.0)),
+        time_in_force: None,
+    });
+    assert!(matches!(events.as_slice(), [EngineMessage::AmendRejected { order_id, reason, status: OrdStatus::New, .. }]
+        if *order_id == stop && reason == "Would cross own resting order"), "{:?}", events);
+    assert_eq!(cash(&mut exchange, "STOPPER"), cash_before);
+    assert!(matches!(status(&mut exchange, "STOPPER", stop).as_slice(), [EngineMessage::OrderStatusReport { untriggered: true, leaves_quantity: 1, .. }]));
+}
+
+// Once a trade triggers it, a stop of an account that rejects its own aggressors is cancelled,
+// not traded, if it would trade with that account, and says so on its ExecutionReport
+#[test]
+fn a_triggered_stop_that_would_cross_its_own_account_is_cancelled_as_self_trade_prevention() {
+    let mut exchange = exchange();
+    exchange.handle_message(EngineMessage::SetSmpAction {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        account_id: "STOPPER".to_string(),
+        smp_action: SmpAction::RejectAggressor,
+    });
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 11.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+    order(&mut exchange, "STOPPER", OrdType::Limit, Side::Sell, 1, 12.0);
+    let cash_before = cash(&mut exchange, "STOPPER");
+
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 2, 11.0);
+    let cancelled = events.iter().find(|event| matches!(event, EngineMessage::OrderCancelled { order_id, .. } if *order_id == stop));
+    let Some(cancelled @ EngineMessage::OrderCancelled { reason: CancelReason::SelfTradePrevention, .. }) = cancelled else {
+        panic!("expected the stop cancelled as self-trade prevention: {:?}", events);
+    };
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+    // The stop's hold comes back
+    assert_eq!(cash(&mut exchange, "STOPPER"), cash_before + Price::from(11.0));
+
+    let report = serialize_engine_message(cancelled).unwrap();
+    let field = |tag: &str| report.trim().split('|').find_map(|pair| pair.split_once('=').filter(|(t, _)| *t == tag)).map(|(_, value)| value.to_string());
+    assert_eq!((field("150"), field("378"), field("58")), (Some("4".to_string()), Some("8".to_string()), Some("Cancelled to prevent a self-trade".to_string())));
+}
+
+#[test]
+fn a_cancelled_parked_stop_gives_back_its_hold_and_never_triggers() {
+    let mut exchange = exchange();
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 11.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+    assert_eq!(cash(&mut exchange, "STOPPER"), Price::from(989.0));
+
+    let events = exchange.handle_message(EngineMessage::CancelOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        account_id: "STOPPER".to_string(),
+        order_id: stop,
+        orig_client_order_id: None,
+        cancel_quantity: None,
+    });
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderCancelled { order_id, reason: CancelReason::ClientRequested, .. }] if *order_id == stop), "{:?}", events);
+    assert_eq!(cash(&mut exchange, "STOPPER"), Price::from(1000.0));
+
+    let events = order(&mut exchange, "TAKER", OrdType::Limit, Side::Buy, 2, 11.0);
+    assert!(fills_of(&events, stop).is_empty(), "{:?}", events);
+}
+
+// Parked stops take a place on the book like any resting order, and a clean logon pulls them
+// with the rest of the client's orders
+#[test]
+fn parked_stops_count_against_the_resting_limit_and_go_with_a_clean_logon() {
+    let mut exchange = exchange();
+    exchange.handle_message(EngineMessage::SetRestingOrderLimit {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("ADMIN"),
+        instrument_id: Some("AAPL".to_string()),
+        max_resting_orders: 2,
+    });
+    order(&mut exchange, "SELLER", OrdType::Limit, Side::Sell, 1, 10.0);
+    let stop = parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 11.0);
+    let events = order(&mut exchange, "STOPPER", OrdType::Stop, Side::Buy, 1, 12.0);
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Book full"), "{:?}", events);
+
+    let events = exchange.handle_message(EngineMessage::Logon {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("STOPPER"),
+        cancel_previous_orders: true,
+        heart_bt_int: 30,
+        default_appl_ver_id: None,
+        summary_fills: false,
+    });
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderCancelled { order_id, reason: CancelReason::Disconnect, .. }, EngineMessage::PositionReport { .. }]
+        if *order_id == stop), "{:?}", events);
+    assert_eq!(cash(&mut exchange, "STOPPER"), Price::from(1000.0));
+    // Its place on the book is free again
+    parked_stop(&mut exchange, OrdType::Stop, Side::Buy, 12.0);
+}
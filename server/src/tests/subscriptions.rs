@@ -0,0 +1,103 @@
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use fefix::tagvalue::{Config, Encoder};
+use fefix::TagU16;
+
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::handle_fix_message;
+use crate::types::ClientID;
+
+fn from(sender: &str, msg_type: &[u8], fields: &[(u16, &str)]) -> String {
+    let mut encoder = Encoder::<Config>::default();
+    encoder.config_mut().set_separator(b'|');
+    let mut buffer = Vec::new();
+    let mut msg = encoder.start_message(b"FIXT.1.1", &mut buffer, msg_type);
+    msg.set(SENDER_COMP_ID, sender);
+    msg.set(TARGET_COMP_ID, "EXCHANGE");
+    msg.set(SENDING_TIME, Timestamp::parse(b"20240102-14:30:00.125").unwrap());
+    for (tag, value) in fields {
+        msg.set_any(TagU16::new(*tag).unwrap(), *value);
+    }
+    String::from_utf8_lossy(msg.wrap()).into_owned()
+}
+
+fn send(exchange: &mut Exchange, sender: &str, msg_type: &[u8], fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    exchange.handle_message(handle_fix_message(&from(sender, msg_type, fields)))
+}
+
+fn subscribe(exchange: &mut Exchange, sender: &str, fields: &[(u16, &str)]) -> Vec<EngineMessage> {
+    send(exchange, sender, b"V", &[&[(263, "1")], fields].concat())
+}
+
+fn create(exchange: &mut Exchange, instrument_id: &str) -> Vec<EngineMessage> {
+    send(exchange, "ADMIN", b"UCI", &[(55, instrument_id)])
+}
+
+fn offer(exchange: &mut Exchange, instrument_id: &str) -> Vec<EngineMessage> {
+    send(exchange, "TRADER", b"D", &[(1, "TRADER"), (55, instrument_id), (54, "2"), (53, "1"), (40, "2"), (44, "10")])
+}
+
+// (subscriber, book) for each book update among the events
+fn updated(events: &[EngineMessage]) -> Vec<(String, String)> {
+    let mut updated: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            EngineMessage::BookUpdate { client_id, instrument_id, .. } => Some((client_id.comp_id().to_string(), instrument_id.clone())),
+            _ => None,
+        })
+        .collect();
+    updated.sort();
+    updated
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected.iter().map(|(client, instrument_id)| (client.to_string(), instrument_id.to_string())).collect()
+}
+
+#[test]
+fn each_client_is_sent_the_deltas_of_exactly_the_books_its_symbols_and_wildcards_cover() {
+    let mut exchange = Exchange::new();
+    for instrument_id in ["AAPL", "FUT-ESZ4", "FUT-NQZ4"] {
+        create(&mut exchange, instrument_id);
+    }
+    assert_eq!(updated(&subscribe(&mut exchange, "ALL", &[(55, "*")])), pairs(&[("ALL", "AAPL"), ("ALL", "FUT-ESZ4"), ("ALL", "FUT-NQZ4")]));
+    assert_eq!(updated(&subscribe(&mut exchange, "FUTURES", &[(55, "FUT-*")])), pairs(&[("FUTURES", "FUT-ESZ4"), ("FUTURES", "FUT-NQZ4")]));
+    let listed = subscribe(&mut exchange, "PICKER", &[(146, "2"), (55, "AAPL"), (55, "FUT-ESZ4")]);
+    assert_eq!(updated(&listed), pairs(&[("PICKER", "AAPL"), ("PICKER", "FUT-ESZ4")]));
+
+    assert_eq!(updated(&offer(&mut exchange, "AAPL")), pairs(&[("ALL", "AAPL"), ("PICKER", "AAPL")]));
+    assert_eq!(updated(&offer(&mut exchange, "FUT-ESZ4")), pairs(&[("ALL", "FUT-ESZ4"), ("FUTURES", "FUT-ESZ4"), ("PICKER", "FUT-ESZ4")]));
+    assert_eq!(updated(&offer(&mut exchange, "FUT-NQZ4")), pairs(&[("ALL", "FUT-NQZ4"), ("FUTURES", "FUT-NQZ4")]));
+
+    // A book listed later joins the wildcards covering it
+    assert_eq!(updated(&create(&mut exchange, "FUT-CLZ4")), pairs(&[("ALL", "FUT-CLZ4"), ("FUTURES", "FUT-CLZ4")]));
+    assert_eq!(updated(&offer(&mut exchange, "FUT-CLZ4")), pairs(&[("ALL", "FUT-CLZ4"), ("FUTURES", "FUT-CLZ4")]));
+
+    // Unsubscribing the wildcard ends every book it brought in, and only those
+    assert!(send(&mut exchange, "FUTURES", b"V", &[(263, "2"), (55, "FUT-*")]).is_empty());
+    assert_eq!(updated(&offer(&mut exchange, "FUT-ESZ4")), pairs(&[("ALL", "FUT-ESZ4"), ("PICKER", "FUT-ESZ4")]));
+
+    // Once its session closes, a client's subscriptions all go
+    exchange.handle_message(EngineMessage::SessionClosed { client_id: ClientID::new("ALL".to_string(), None) });
+    assert_eq!(updated(&offer(&mut exchange, "AAPL")), pairs(&[("PICKER", "AAPL")]));
+    assert_eq!(updated(&create(&mut exchange, "FUT-6EZ4")), vec![]);
+}
+
+#[test]
+fn a_symbol_not_yet_listed_is_refused_unless_asked_for_once_it_is() {
+    let mut exchange = Exchange::new();
+    let refused = subscribe(&mut exchange, "EARLY", &[(55, "FUT-RTYZ4")]);
+    assert!(matches!(refused.as_slice(), [EngineMessage::OrderRejected { reason, .. }] if reason == "Unknown instrument"), "{:?}", refused);
+    assert_eq!(updated(&create(&mut exchange, "FUT-RTYZ4")), vec![]);
+
+    assert!(subscribe(&mut exchange, "EARLY", &[(55, "FUT-YMZ4"), (8036, "Y")]).is_empty());
+    assert_eq!(updated(&create(&mut exchange, "FUT-YMZ4")), pairs(&[("EARLY", "FUT-YMZ4")]));
+    assert_eq!(updated(&offer(&mut exchange, "FUT-YMZ4")), pairs(&[("EARLY", "FUT-YMZ4")]));
+
+    // Snapshots are of one book named outright
+    for fields in [&[(55, "FUT-*")][..], &[(146, "2"), (55, "AAPL"), (55, "FUT-YMZ4")][..]] {
+        let snapshot = handle_fix_message(&from("EARLY", b"V", fields));
+        assert!(matches!(&snapshot, EngineMessage::InvalidMessage { reason, .. } if reason == "A snapshot takes a single Symbol"), "{:?}", snapshot);
+    }
+}
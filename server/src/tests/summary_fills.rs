@@ -0,0 +1,95 @@
+use fefix::definitions::fix50::{Side, EXEC_TYPE, ORD_STATUS};
+
+use crate::engine::{extract_client_id, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::serialize_engine_message;
+use crate::fix::testkit::*;
+
+// AAPL listed, with BUYER logged on for summaries or for every fill
+fn listed(summary: bool) -> Exchange {
+    let mut exchange = Exchange::new();
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").parse());
+    exchange.handle_message(Logon::builder().sender("BUYER").summary_fills(summary).parse());
+    exchange
+}
+
+// The ExecutionReports BUYER's session is sent for `events`
+fn sent_to_buyer(events: &[EngineMessage]) -> Vec<Outbound> {
+    events.iter()
+        .filter(|event| extract_client_id(event).is_some_and(|client_id| client_id.to_string() == "BUYER"))
+        .filter_map(serialize_engine_message)
+        .map(|report| Outbound::parse(&report))
+        .filter(|report| report.msg_type() == "8")
+        .collect()
+}
+
+fn statuses(reports: &[Outbound]) -> Vec<(&str, &str)> {
+    reports.iter().map(|report| (report.get(EXEC_TYPE).unwrap(), report.get(ORD_STATUS).unwrap())).collect()
+}
+
+// Offers of 2 at 10, 1 at 11 and 1 at 12 that a bid for 4 at 12 takes all of, at an average
+// of 10.75 over the 4 (their plain mean would be 11)
+fn three_fills(exchange: &mut Exchange) -> Vec<EngineMessage> {
+    order(exchange, "SELLER1", Side::Sell, 2, 10.0);
+    order(exchange, "SELLER2", Side::Sell, 1, 11.0);
+    order(exchange, "SELLER3", Side::Sell, 1, 12.0);
+    order(exchange, "BUYER", Side::Buy, 4, 12.0)
+}
+
+#[test]
+fn every_fill_is_reported_as_it_happens_by_default() {
+    let mut exchange = listed(false);
+    let reports = sent_to_buyer(&three_fills(&mut exchange));
+    assert_eq!(statuses(&reports), [("0", "0"), ("F", "1"), ("F", "1"), ("F", "2")]);
+    let last = reports.into_iter().last().unwrap();
+    last.last_qty(1).last_px(12.0).cum_qty(4).avg_px(10.75);
+}
+
+#[test]
+fn a_summary_session_is_sent_one_report_as_the_order_fills() {
+    let mut exchange = listed(true);
+    let events = three_fills(&mut exchange);
+    let reports = sent_to_buyer(&events);
+    assert_eq!(statuses(&reports), [("0", "0"), ("F", "2")]);
+    // The one report accounts for the whole order
+    let filled = reports.into_iter().last().unwrap();
+    filled.last_qty(4).last_px(10.75).cum_qty(4).leaves_qty(0).avg_px(10.75);
+
+    // The sellers, never having asked for summaries, each still hear of their fill
+    let fills = events.iter().filter(|event| matches!(event, EngineMessage::OrderFilled { .. }) && serialize_engine_message(event).is_some()).count();
+    assert_eq!(fills, 3 + 1, "{:?}", events);
+}
+
+// A bid for 5 at 10 filling 1 and then 2 in separate matches before its owner cancels the rest
+fn partly_filled_then_cancelled(summary: bool) -> Vec<Outbound> {
+    let mut exchange = listed(summary);
+    let mut events = order(&mut exchange, "BUYER", Side::Buy, 5, 10.0);
+    let bid = accepted_order_id(&events);
+    events.extend(order(&mut exchange, "SELLER1", Side::Sell, 1, 10.0));
+    events.extend(order(&mut exchange, "SELLER2", Side::Sell, 2, 10.0));
+    events.extend(exchange.handle_message(OrderCancelRequest::builder().sender("BUYER").account("BUYER").order_id(bid).parse()));
+    sent_to_buyer(&events)
+}
+
+#[test]
+fn an_order_cancelled_after_partial_fills_still_reports_what_filled_in_either_mode() {
+    let reports = partly_filled_then_cancelled(false);
+    assert_eq!(statuses(&reports), [("0", "0"), ("F", "1"), ("F", "1"), ("4", "4")]);
+
+    let reports = partly_filled_then_cancelled(true);
+    assert_eq!(statuses(&reports), [("0", "0"), ("4", "4")]);
+    let cancelled = reports.into_iter().last().unwrap();
+    cancelled.cum_qty(3).leaves_qty(0).avg_px(10.0);
+}
+
+// Between the first report and the last, a summary session learns how its order stands by asking
+#[test]
+fn a_summary_session_asks_for_interim_reports() {
+    let mut exchange = listed(true);
+    let bid = accepted_order_id(&order(&mut exchange, "BUYER", Side::Buy, 5, 10.0));
+    assert!(sent_to_buyer(&order(&mut exchange, "SELLER1", Side::Sell, 2, 10.0)).is_empty());
+
+    let status = sent_to_buyer(&exchange.handle_message(OrderStatusRequest::builder().sender("BUYER").order_id(bid).parse()));
+    assert_eq!(statuses(&status), [("I", "1")]);
+    status.into_iter().next().unwrap().cum_qty(2).leaves_qty(3).avg_px(10.0);
+}
@@ -0,0 +1,126 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::audit::MalformedLog;
+use crate::config::{LiveConfig, ServerConfig};
+use crate::credentials::Credentials;
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::namespace::Namespaces;
+use crate::fix::Conformance;
+use crate::gateway::{ConnectionGate, ConnectionLimits};
+use crate::inbound::inbound_channel;
+use crate::instrument::{CorporateAction, SpecOverrides};
+use crate::supervisor::{EngineHealth, EngineStatus};
+use crate::tests::fix_round_trip::encode;
+use crate::types::*;
+
+fn admin() -> ClientID {
+    ClientID::new("ADMIN".to_string(), None)
+}
+
+fn order(account: &str, side: Side, quantity: Quantity) -> EngineMessage {
+    EngineMessage::NewOrder {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: ClientID::new(account.to_string(), None),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity,
+        price: Some(Price::from(10.0)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    }
+}
+
+// A split big enough to overflow a resting order's quantity, which only panics with overflow checks on
+#[test]
+#[cfg(debug_assertions)]
+fn a_message_that_panics_the_engine_is_skipped_and_later_orders_still_trade() {
+    let now = || Timestamp::parse(b"20240102-14:30:00.000").unwrap();
+    let (tx, rx) = inbound_channel(true);
+    for message in [
+        EngineMessage::CreateInstrument {
+            sending_time: Timestamp::utc_now(),
+            receiving_time: Timestamp::utc_now(),
+            client_id: admin(),
+            instrument_id: "AAPL".to_string(),
+            segment: None,
+            spec: SpecOverrides::default(),
+            spread: None,
+        },
+        EngineMessage::AdvanceTime { sending_time: now(), receiving_time: now(), client_id: admin(), timestamp: now() },
+        order("SELLER", Side::Sell, 2),
+        EngineMessage::CorporateAction {
+            sending_time: now(),
+            receiving_time: now(),
+            client_id: admin(),
+            instrument_id: "AAPL".to_string(),
+            effective_time: now(),
+            action: CorporateAction::Split { numerator: 1 << 63, denominator: 1 },
+        },
+        order("SELLER", Side::Sell, 1),
+        order("BUYER", Side::Buy, 1),
+    ] {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    // A session with nothing to do with the message still hears of the panic
+    let (bystander_tx, _bystander_rx) = mpsc::unbounded_channel();
+    crate::client_senders().insert(ClientID::new("BYSTANDER".to_string(), None), bystander_tx);
+
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+    let health = EngineHealth::new();
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    rt.block_on(crate::consume(Namespaces::new(Exchange::new()), rx, outbound_tx, Arc::clone(&health), std::future::pending()));
+    crate::client_senders().remove(&ClientID::new("BYSTANDER".to_string(), None));
+
+    let mut events = Vec::new();
+    while let Ok(batch) = outbound_rx.try_recv() {
+        events.extend(batch.events);
+    }
+    let panic_notice = |to: ClientID| events.iter().position(|event| matches!(event,
+        EngineMessage::LogEvent { client_id: Some(client_id), message } if *client_id == to && message.starts_with("Engine panic: ")
+    ));
+    let skipped = panic_notice(admin());
+    assert!(panic_notice(ClientID::new("BYSTANDER".to_string(), None)).is_some(), "{:#?}", events);
+    let filled = events.iter().position(|event| matches!(event,
+        EngineMessage::OrderFilled { client_id, remaining_quantity: 0, .. } if client_id.comp_id() == "BUYER"
+    ));
+    assert!(skipped.is_some() && filled > skipped, "{:#?}", events);
+    assert_eq!(health.status(), EngineStatus { running: false, recovered_panics: 1 });
+}
+
+#[tokio::test]
+async fn sessions_are_logged_out_once_the_engine_has_stopped() {
+    let (tx, rx) = inbound_channel(true);
+    drop(rx);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let gate = ConnectionGate::new(ConnectionLimits::default());
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let permit = gate.admit(IpAddr::V4(Ipv4Addr::LOCALHOST), Instant::now()).unwrap();
+        crate::handle_connection(stream, tx, Credentials::open(), Namespaces::new(Exchange::new()).book_views(), Conformance::Strict, MalformedLog::new(), LiveConfig::fixed(ServerConfig::default()), permit).await;
+    });
+
+    let (reader, mut writer) = TcpStream::connect(address).await.unwrap().into_split();
+    writer.write_all(format!("{}\n", encode(b"V", &[(55, "AAPL")])).as_bytes()).await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    let logout = lines.next_line().await.unwrap().unwrap();
+    assert!(logout.contains("|35=5|") && logout.contains("|58=exchange unavailable|"), "{}", logout);
+    assert_eq!(lines.next_line().await.unwrap(), None);
+}
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use fefix::definitions::fix50::*;
+use fefix::fix_values::Timestamp;
+use tokio::sync::mpsc;
+
+use crate::audit::TradeParty;
+use crate::engine::EngineMessage;
+use crate::exchange::Exchange;
+use crate::fix::testkit::{advance_time, client};
+use crate::instrument::SpecOverrides;
+use crate::surveillance::{run, SurveillanceConfig, SurveillanceFlag, SurveillanceReport};
+use crate::types::*;
+
+fn at(time: &str) -> Timestamp {
+    Timestamp::parse(format!("20240102-{}", time).as_bytes()).unwrap()
+}
+
+// A limit order the exchange received at `received`
+fn order(exchange: &mut Exchange, account: &str, side: Side, price: f64, received: &str) -> OrderID {
+    let events = exchange.handle_message(EngineMessage::NewOrder {
+        sending_time: at(received),
+        receiving_time: at(received),
+        client_id: client(account),
+        account_id: account.to_string(),
+        client_order_id: None,
+        instrument_id: "AAPL".to_string(),
+        order_type: OrdType::Limit,
+        side,
+        quantity: 1,
+        price: Some(Price::from(price)),
+        time_in_force: None,
+        expire_time: None,
+        exchange_code: None,
+        good_for_auction: false,
+        stipulations: Vec::new(),
+    });
+    match events.first() {
+        Some(EngineMessage::OrderAccepted { order_id, .. }) => *order_id,
+        _ => panic!("order not accepted: {:?}", events),
+    }
+}
+
+fn cancel(exchange: &mut Exchange, account: &str, order_id: OrderID, received: &str) {
+    let events = exchange.handle_message(EngineMessage::CancelOrder {
+        sending_time: at(received),
+        receiving_time: at(received),
+        client_id: client(account),
+        account_id: account.to_string(),
+        order_id,
+        orig_client_order_id: None,
+        cancel_quantity: None,
+    });
+    assert!(matches!(events.as_slice(), [EngineMessage::OrderCancelled { .. }]), "{:?}", events);
+}
+
+fn party(order_id: OrderID, account: &str) -> TradeParty {
+    TradeParty { order_id, account_id: account.to_string(), client_id: client(account) }
+}
+
+#[tokio::test]
+async fn a_scripted_session_raises_exactly_its_wash_trades_and_quick_cancels() {
+    let report_path = std::env::temp_dir().join(format!("surveillance-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&report_path);
+    let config = SurveillanceConfig {
+        quick_cancel_ms: 100,
+        min_orders: 4,
+        report_path: Some(report_path.clone()),
+        ..SurveillanceConfig::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("surveillance.toml")).unwrap()
+    };
+    let (tx, rx) = mpsc::unbounded_channel();
+    let report = SurveillanceReport::new();
+    let surveillance = tokio::spawn(run(rx, config, report.clone()));
+
+    let mut exchange = Exchange::new().with_surveillance(tx);
+    exchange.handle_message(EngineMessage::CreateInstrument {
+        sending_time: Timestamp::utc_now(),
+        receiving_time: Timestamp::utc_now(),
+        client_id: client("ADMIN"),
+        instrument_id: "AAPL".to_string(),
+        segment: None,
+        spec: SpecOverrides::default(),
+        spread: None,
+    });
+    advance_time(&mut exchange, "20240102-14:00:00.000");
+
+    // WASH trades with itself
+    let wash_buy = order(&mut exchange, "WASH", Side::Buy, 10.0, "14:00:00.000");
+    let wash_sell = order(&mut exchange, "WASH", Side::Sell, 10.0, "14:00:00.000");
+
+    // FLIP buys and sells back at the same price 400ms apart; SLOW takes two seconds
+    order(&mut exchange, "MAKER1", Side::Sell, 10.0, "14:00:00.000");
+    let flip_buy = order(&mut exchange, "FLIP", Side::Buy, 10.0, "14:00:00.000");
+    order(&mut exchange, "MAKER2", Side::Sell, 10.0, "14:00:00.000");
+    order(&mut exchange, "SLOW", Side::Buy, 10.0, "14:00:00.000");
+    advance_time(&mut exchange, "20240102-14:00:00.400");
+    order(&mut exchange, "MAKER3", Side::Buy, 10.0, "14:00:00.400");
+    let flip_sell = order(&mut exchange, "FLIP", Side::Sell, 10.0, "14:00:00.400");
+    advance_time(&mut exchange, "20240102-14:00:02.000");
+    order(&mut exchange, "MAKER4", Side::Buy, 10.0, "14:00:02.000");
+    order(&mut exchange, "SLOW", Side::Sell, 10.0, "14:00:02.000");
+
+    // SPOOF pulls three of four bids within 50ms; PATIENT pulls all of its four, but only after 200ms
+    let spoofs: Vec<OrderID> = (0..4).map(|_| order(&mut exchange, "SPOOF", Side::Buy, 9.0, "14:00:03.000")).collect();
+    let patient: Vec<OrderID> = (0..4).map(|_| order(&mut exchange, "PATIENT", Side::Buy, 9.0, "14:00:03.000")).collect();
+    for &order_id in &spoofs[..3] {
+        cancel(&mut exchange, "SPOOF", order_id, "14:00:03.050");
+    }
+    for &order_id in &patient {
+        cancel(&mut exchange, "PATIENT", order_id, "14:00:03.200");
+    }
+
+    drop(exchange);
+    surveillance.await.unwrap();
+    let expected = vec![
+        SurveillanceFlag::SelfTrade {
+            instrument_id: "AAPL".to_string(),
+            price: Price::from(10.0),
+            quantity: 1,
+            timestamp: at("14:00:00.000"),
+            buyer: party(wash_buy, "WASH"),
+            seller: party(wash_sell, "WASH"),
+        },
+        SurveillanceFlag::RoundTrip {
+            account_id: "FLIP".to_string(),
+            instrument_id: "AAPL".to_string(),
+            price: Price::from(10.0),
+            opening_order_id: flip_buy,
+            closing_order_id: flip_sell,
+            elapsed_ms: 400,
+        },
+        SurveillanceFlag::QuickCancels { account_id: "SPOOF".to_string(), orders: 4, quick_cancels: 3 },
+    ];
+    assert_eq!(report.flags(), expected);
+
+    let written = std::fs::read_to_string(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    let lines: Vec<serde_json::Value> = written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 3, "{}", written);
+    assert_eq!(lines[0]["kind"], "self_trade");
+    assert_eq!(lines[0]["timestamp"], "20240102-14:00:00.000");
+    assert_eq!(lines[1]["kind"], "round_trip");
+    assert_eq!(lines[2]["kind"], "quick_cancels");
+}
@@ -0,0 +1,88 @@
+use fefix::definitions::fix50::Side;
+use fefix::fix_values::Timestamp;
+
+use crate::engine::{CancelReason, EngineMessage};
+use crate::exchange::Exchange;
+use crate::fix::serialize_engine_message;
+use crate::fix::testkit::*;
+use crate::instrument::HaltPolicy;
+use crate::types::*;
+
+// (halted, resumes_at) of each TradingHalt sent to VIEWER
+fn halts(events: &[EngineMessage]) -> Vec<(bool, Option<String>)> {
+    events.iter().filter_map(|event| match event {
+        EngineMessage::TradingHalt { client_id, halted, resumes_at, .. } if client_id.to_string() == "VIEWER" => Some((*halted, resumes_at.as_ref().map(format_timestamp))),
+        _ => None,
+    }).collect()
+}
+
+// AAPL halting for a minute once its spread passes 5 times its reference, with VIEWER watching
+// its book and the clock at 14:30
+fn watched(policy: HaltPolicy, reference_spread: f64) -> Exchange {
+    let mut exchange = Exchange::new();
+    advance_time(&mut exchange, "20240102-14:30:00.000");
+    exchange.handle_message(CreateInstrument::builder().sender("ADMIN").symbol("AAPL").spread_halt(5.0, reference_spread, 60).halt_policy(policy).parse());
+    exchange.handle_message(MarketDataRequest::builder().sender("VIEWER").subscribe().symbols(&["AAPL"]).parse());
+    exchange
+}
+
+#[test]
+fn a_spread_that_blows_out_halts_the_book_until_the_halt_runs_its_course() {
+    let mut exchange = watched(HaltPolicy::CancelAll, 0.5);
+    order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+    order(&mut exchange, "SELLER", Side::Sell, 1, 10.5);
+    assert!(halts(&order(&mut exchange, "SELLER2", Side::Sell, 1, 13.0)).is_empty());
+
+    // Taking the offer at 10.5 leaves 3.0 between the bid and the next offer
+    let events = order(&mut exchange, "TAKER", Side::Buy, 1, 10.5);
+    assert!(events.iter().any(|event| matches!(event, EngineMessage::OrderFilled { .. })), "{:?}", events);
+    assert_eq!(halts(&events), [(true, Some("20240102-14:31:00.000".to_string()))]);
+    let cancelled = events.iter().filter(|event| matches!(event, EngineMessage::OrderCancelled { reason: CancelReason::TradingHalt, .. })).count();
+    assert_eq!(cancelled, 2, "{:?}", events);
+    assert!(matches!(&order(&mut exchange, "BUYER", Side::Buy, 1, 10.0)[..], [EngineMessage::OrderRejected { reason, .. }] if reason == "Instrument halted"));
+
+    assert!(halts(&advance_time(&mut exchange, "20240102-14:30:59.999")).is_empty());
+    assert_eq!(halts(&advance_time(&mut exchange, "20240102-14:31:00.000")), [(false, None)]);
+    assert!(matches!(order(&mut exchange, "BUYER", Side::Buy, 1, 10.0).first(), Some(EngineMessage::OrderAccepted { .. })));
+}
+
+// With no reference given, the book's first spread is its reference, and a frozen book keeps
+// its orders through the halt; an admin's resumption ends the halt early
+#[test]
+fn the_first_spread_is_the_reference_and_an_admin_may_end_the_halt_early() {
+    let mut exchange = watched(HaltPolicy::Freeze, 0.0);
+    order(&mut exchange, "BUYER", Side::Buy, 1, 10.0);
+    order(&mut exchange, "SELLER", Side::Sell, 1, 11.0);
+    order(&mut exchange, "SELLER", Side::Buy, 1, 9.0);
+    // The bid at 10 leaving widens the spread from 1 to 2, well within the multiplier
+    let events = exchange.handle_message(OrderCancelRequest::builder().sender("BUYER").account("BUYER").order_id(1).parse());
+    assert!(halts(&events).is_empty(), "{:?}", events);
+
+    let events = order(&mut exchange, "SELLER", Side::Buy, 1, 2.0);
+    assert!(halts(&events).is_empty());
+    let events = exchange.handle_message(OrderCancelRequest::builder().sender("SELLER").account("SELLER").order_id(3).parse());
+    assert_eq!(halts(&events), [(true, Some("20240102-14:31:00.000".to_string()))]);
+    assert_eq!(events.iter().filter(|event| matches!(event, EngineMessage::OrderCancelled { .. })).count(), 1, "{:?}", events);
+
+    let events = exchange.handle_message(TradingStatus::builder().sender("ADMIN").symbol("AAPL").resume().parse());
+    assert!(halts(&events).is_empty(), "{:?}", events);
+    assert!(matches!(order(&mut exchange, "BUYER", Side::Buy, 1, 10.0).first(), Some(EngineMessage::OrderAccepted { .. })));
+    // The admin's resumption stands; the halt's own end comes and goes
+    assert!(halts(&advance_time(&mut exchange, "20240102-14:31:00.000")).is_empty());
+}
+
+#[test]
+fn a_trading_halt_is_a_security_status() {
+    let at = |time: &str| Timestamp::parse(time.as_bytes()).unwrap();
+    let halt = serialize_engine_message(&EngineMessage::TradingHalt {
+        client_id: ClientID::new("VIEWER".to_string(), None),
+        instrument_id: "AAPL".to_string(),
+        halted: true,
+        reason: "Volatility halt".to_string(),
+        resumes_at: Some(at("20240102-14:31:00.000")),
+        timestamp: at("20240102-14:30:00.000"),
+    })
+    .unwrap();
+    assert!(halt.contains("|35=f|") && halt.contains("|56=VIEWER|"), "{}", halt);
+    assert!(halt.contains("|55=AAPL|326=2|60=20240102-14:30:00.000|8051=20240102-14:31:00.000|58=Volatility halt|"), "{}", halt);
+}
@@ -1,19 +1,31 @@
 use std::fmt::Display;
 
+use chrono::{Datelike, Timelike};
+use fefix::fix_values::{Date, Time, Timestamp};
+use fefix::FixValue;
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::ExchangeTime;
 
 pub(crate) type OrderID = u64;
 
 pub(crate) type ClOrdID = String;
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub(crate) struct ClientID {
     comp_id: String,
-    sub_id: Option<String>
+    sub_id: Option<String>,
+    // The environment the session trades in, when it addressed one rather than the exchange itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<Namespace>,
 }
 
 impl Display for ClientID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(namespace) = &self.namespace {
+            write!(f, "{}/", namespace)?;
+        }
         if let Some(sub_id) = &self.sub_id {
             Ok(write!(f, "{}::{}", self.comp_id, sub_id)?)
         }
@@ -25,13 +37,240 @@ impl Display for ClientID {
 
 impl ClientID {
     pub(crate) fn new(comp_id: String, sub_id: Option<String>) -> Self {
-        Self { comp_id, sub_id }
+        Self { comp_id, sub_id, namespace: None }
+    }
+
+    pub(crate) fn in_namespace(self, namespace: Option<Namespace>) -> Self {
+        Self { namespace, ..self }
+    }
+
+    pub(crate) fn comp_id(&self) -> &str {
+        &self.comp_id
+    }
+
+    pub(crate) fn sub_id(&self) -> Option<&str> {
+        self.sub_id.as_deref()
+    }
+
+    pub(crate) fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    // Who a message sent as this ClientID on the session registered as `session` is from. The
+    // session's identity is fixed at logon: a message from its CompID that leaves SenderSubID
+    // out is the session's own, and one naming another SenderSubID, or any on a session that
+    // logged on without one, is None rather than routed to a client it cannot reach. Messages
+    // sent as other CompIDs are left as they are.
+    pub(crate) fn routed_within(self, session: &ClientID) -> Option<Self> {
+        if self.comp_id != session.comp_id {
+            return Some(self);
+        }
+        match (&self.sub_id, &session.sub_id) {
+            (None, _) => Some(Self { sub_id: session.sub_id.clone(), ..self }),
+            (Some(sub_id), Some(session_sub_id)) if sub_id == session_sub_id => Some(self),
+            (Some(_), _) => None,
+        }
     }
 }
 
+pub(crate) type Namespace = String;
 pub(crate) type InstrumentID = String;
+pub(crate) type SegmentName = String;
 pub(crate) type Quantity = u64;
 pub(crate) type Price = OrderedFloat<f64>;
 pub(crate) type AccountBalance = OrderedFloat<f64>;
 
 pub(crate) type AccountID = String;
+
+// Timestamp has no ordering of its own, so compare on (date, time)
+pub(crate) fn timestamp_key(timestamp: &Timestamp) -> (Date, Time) {
+    (timestamp.date(), timestamp.time())
+}
+
+// FIX UTCTimestamp text, e.g. 20240102-14:30:00.125
+pub(crate) fn format_timestamp(timestamp: &Timestamp) -> String {
+    String::from_utf8_lossy(&timestamp.to_bytes()).into_owned()
+}
+
+// Milliseconds since the epoch, for measuring time between timestamps
+pub(crate) fn timestamp_millis(timestamp: &Timestamp) -> i64 {
+    chrono::NaiveDateTime::parse_from_str(&format_timestamp(timestamp), "%Y%m%d-%H:%M:%S%.f")
+        .map_or(0, |time| time.and_utc().timestamp_millis())
+}
+
+// `seconds` after `timestamp`, to the millisecond
+pub(crate) fn timestamp_after(timestamp: &Timestamp, seconds: u64) -> Timestamp {
+    match timestamp.to_chrono_utc() {
+        Some(time) => to_timestamp(&(time + chrono::Duration::seconds(seconds as i64))),
+        None => timestamp.clone(),
+    }
+}
+
+// An ExchangeTime as a Timestamp, dropping what's finer than a millisecond
+pub(crate) fn to_timestamp(time: &ExchangeTime) -> Timestamp {
+    Timestamp::new(
+        Date::new(time.year() as u32, time.month(), time.day()).unwrap(),
+        Time::from_hmsm(time.hour(), time.minute(), time.second(), time.nanosecond() / 1_000_000).unwrap(),
+    )
+}
+
+// JSON form of fefix values (enums, timestamps) that have no serde support of their own:
+// the same text they carry on the FIX wire, e.g. "1" for Side::Buy. For use with #[serde(with)].
+pub(crate) mod fix_value_serde {
+    use fefix::FixValue;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: for<'a> FixValue<'a>,
+        S: Serializer,
+    {
+        serializer.serialize_str(&String::from_utf8_lossy(&value.to_bytes()))
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: for<'a> FixValue<'a>,
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        T::deserialize(text.as_bytes()).map_err(|_| D::Error::custom(format!("invalid FIX value {:?}", text)))
+    }
+}
+
+// fix_value_serde for optional fields, with null for None
+pub(crate) mod optional_fix_value_serde {
+    use fefix::FixValue;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(transparent)]
+    struct Wrapped<T: for<'a> FixValue<'a>>(#[serde(with = "super::fix_value_serde")] T);
+
+    pub(crate) fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: for<'a> FixValue<'a>,
+        S: Serializer,
+    {
+        match value {
+            Some(value) => super::fix_value_serde::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: for<'a> FixValue<'a>,
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<Wrapped<T>>::deserialize(deserializer)?.map(|Wrapped(value)| value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    use super::*;
+
+    #[test]
+    fn displays_comp_id_alone() {
+        assert_eq!(ClientID::new("FIRM1".to_string(), None).to_string(), "FIRM1");
+    }
+
+    #[test]
+    fn displays_comp_id_and_sub_id() {
+        assert_eq!(ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())).to_string(), "FIRM1::ALGO");
+    }
+
+    #[test]
+    fn sub_id_distinguishes_clients() {
+        let algo = ClientID::new("FIRM1".to_string(), Some("ALGO".to_string()));
+        let desk = ClientID::new("FIRM1".to_string(), Some("DESK".to_string()));
+        let bare = ClientID::new("FIRM1".to_string(), None);
+        assert_ne!(algo, desk);
+        assert_ne!(algo, bare);
+        assert_eq!(algo, ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())));
+    }
+
+    #[test]
+    fn namespace_distinguishes_clients_and_leads_their_display() {
+        let uat = ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())).in_namespace(Some("UAT".to_string()));
+        assert_ne!(uat, ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())));
+        assert_eq!(uat.to_string(), "UAT/FIRM1::ALGO");
+    }
+
+    #[test]
+    fn a_session_sub_id_fills_in_for_one_left_out_and_refuses_any_other() {
+        let client = |comp_id: &str, sub_id: Option<&str>| ClientID::new(comp_id.to_string(), sub_id.map(str::to_string));
+        let desk = client("FIRM1", Some("DESK"));
+        assert_eq!(client("FIRM1", None).routed_within(&desk), Some(desk.clone()));
+        assert_eq!(client("FIRM1", Some("DESK")).routed_within(&desk), Some(desk.clone()));
+        assert_eq!(client("FIRM1", Some("ALGO")).routed_within(&desk), None);
+        assert_eq!(client("FIRM1", Some("DESK")).routed_within(&client("FIRM1", None)), None);
+        assert_eq!(client("FIRM1", None).routed_within(&client("FIRM1", None)), Some(client("FIRM1", None)));
+        // Another CompID is not this session's to fill in
+        assert_eq!(client("ADMIN", None).routed_within(&desk), Some(client("ADMIN", None)));
+    }
+
+    #[test]
+    fn works_as_hash_map_key() {
+        let mut senders = HashMap::new();
+        senders.insert(ClientID::new("FIRM1".to_string(), Some("ALGO".to_string())), 1);
+        senders.insert(ClientID::new("FIRM1".to_string(), None), 2);
+
+        assert_eq!(senders.len(), 2);
+        assert_eq!(senders.get(&ClientID::new("FIRM1".to_string(), Some("ALGO".to_string()))), Some(&1));
+        assert_eq!(senders.get(&ClientID::new("FIRM1".to_string(), None)), Some(&2));
+        assert_eq!(senders.get(&ClientID::new("FIRM1".to_string(), Some("DESK".to_string()))), None);
+    }
+
+    fn hash_of(price: Price) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        price.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // The book keys its levels and the engine its lookups by Price, so these are the
+    // properties matching relies on
+
+    #[test]
+    fn equal_prices_compare_and_hash_equal() {
+        assert_eq!(Price::from(1.0), Price::from(1.0));
+        assert_eq!(hash_of(Price::from(1.0)), hash_of(Price::from(1.0)));
+        // Zero is one level whatever its sign
+        assert_eq!(Price::from(0.0), Price::from(-0.0));
+        assert_eq!(hash_of(Price::from(0.0)), hash_of(Price::from(-0.0)));
+    }
+
+    #[test]
+    fn nan_is_one_price_above_all_others() {
+        // Unlike a bare f64, OrderedFloat makes NaN equal to itself (every NaN alike) and
+        // greater than any number, so a NaN price would land on a single level at the top
+        // of the book rather than vanish from lookups. Prices must be validated upstream.
+        let nan = Price::from(f64::NAN);
+        assert_eq!(nan, Price::from(f64::NAN));
+        assert_eq!(hash_of(nan), hash_of(Price::from(-f64::NAN)));
+        assert_ne!(nan, Price::from(0.0));
+        assert!(nan > Price::from(f64::INFINITY));
+    }
+
+    #[test]
+    fn prices_round_trip_through_a_hash_map() {
+        let mut levels: HashMap<Price, u64> = HashMap::new();
+        for (price, quantity) in [(9.9, 5), (10.0, 3), (10.01, 7)] {
+            levels.insert(Price::from(price), quantity);
+        }
+        *levels.get_mut(&Price::from(10.0)).unwrap() += 2;
+
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[&Price::from(9.9)], 5);
+        assert_eq!(levels[&Price::from(10.0)], 5);
+        assert_eq!(levels[&Price::from(10.01)], 7);
+        // Keys match bit for bit, so a price reached by arithmetic may miss its level
+        assert_eq!(levels.get(&Price::from(3.0 * 3.3)), None);
+    }
+}
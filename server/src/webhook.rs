@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use fefix::fix_values::Timestamp;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use url::Url;
+
+use crate::audit::TradeRecord;
+use crate::config::{logs, LiveConfig, LogLevel};
+use crate::engine::EngineMessage;
+use crate::types::*;
+
+// Tries at a trade before it is given up on, waiting twice as long after each failure
+pub const WEBHOOK_ATTEMPTS: u32 = 3;
+const FIRST_RETRY: Duration = Duration::from_millis(200);
+// So an endpoint that never answers cannot hold a trade's task open for good
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What the webhook is sent for each trade, e.g.
+//   {"trade_id":7,"instrument":"AAPL","price":10.0,"quantity":4,"buyer":"FIRM1","seller":"FIRM2","timestamp":"20240102-09:30:00.000"}
+// The buyer and seller are the accounts the trade was booked to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TradeNotification {
+    pub trade_id: u64,
+    pub instrument: InstrumentID,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub buyer: AccountID,
+    pub seller: AccountID,
+    #[serde(with = "fix_value_serde")]
+    pub timestamp: Timestamp,
+}
+
+impl From<&TradeRecord> for TradeNotification {
+    fn from(trade: &TradeRecord) -> Self {
+        Self {
+            trade_id: trade.trade_match_id,
+            instrument: trade.instrument_id.clone(),
+            price: trade.price,
+            quantity: trade.quantity,
+            buyer: trade.buyer.account_id.clone(),
+            seller: trade.seller.account_id.clone(),
+            timestamp: trade.timestamp.clone(),
+        }
+    }
+}
+
+// POSTs each trade the engine reports to the configured URL, off the outbound stage so a
+// slow endpoint never holds up sessions' reports
+#[derive(Debug, Clone)]
+pub struct TradeWebhook {
+    client: reqwest::Client,
+    runtime: Handle, // the outbound stage is not always on a runtime of its own
+}
+
+impl TradeWebhook {
+    pub fn new(runtime: Handle) -> Self {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().expect("Failed to build the webhook client");
+        Self { client, runtime }
+    }
+
+    // Sends every trade in a batch, each on a task of its own, to the URL configured now
+    pub fn deliver(&self, events: &[EngineMessage], config: &LiveConfig) {
+        let mut trades = events.iter().filter_map(|event| match event {
+            EngineMessage::TradeExecuted { trade } => Some(trade),
+            _ => None,
+        }).peekable();
+        if trades.peek().is_none() {
+            return;
+        }
+        let Some(url) = config.snapshot().webhook_url.clone() else { return };
+        for trade in trades {
+            let (client, url, notification) = (self.client.clone(), url.clone(), TradeNotification::from(trade));
+            self.runtime.spawn(async move {
+                if let Err(e) = notify(&client, &url, &notification).await {
+                    if logs(LogLevel::Warn) {
+                        eprintln!("Trade {} not sent to the webhook: {}", notification.trade_id, e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+// One trade, retried with exponential backoff until the endpoint takes it with a 2xx or
+// WEBHOOK_ATTEMPTS have failed
+pub async fn notify(client: &reqwest::Client, url: &Url, notification: &TradeNotification) -> Result<(), String> {
+    let mut wait = FIRST_RETRY;
+    let mut attempt = 1;
+    loop {
+        let failure = match client.post(url.clone()).json(notification).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => format!("{} answered {}", url, response.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt == WEBHOOK_ATTEMPTS {
+            return Err(format!("{} after {} attempts", failure, attempt));
+        }
+        tokio::time::sleep(wait).await;
+        wait *= 2;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use fefix::definitions::fix50::Side;
+
+    use crate::audit::TradeParty;
+    use crate::config::ServerConfig;
+    use crate::exchange::Exchange;
+    use crate::fix::testkit::*;
+    use crate::types::ClientID;
+
+    fn trade() -> TradeRecord {
+        let party = |order_id, account: &str| TradeParty { order_id, account_id: account.to_string(), client_id: ClientID::new(account.to_string(), None) };
+        TradeRecord {
+            trade_match_id: 7,
+            instrument_id: "AAPL".to_string(),
+            price: 10.5.into(),
+            quantity: 4,
+            timestamp: Timestamp::parse(b"20240102-09:30:00.000").unwrap(),
+            buyer: party(1, "FIRM1"),
+            seller: party(2, "FIRM2"),
+        }
+    }
+
+    // Answers each connection with the next status, handing back the body of each request
+    async fn endpoint(statuses: Vec<&'static str>) -> (Url, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/trades", listener.local_addr().unwrap())).unwrap();
+        let served = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // The request ends with the body its Content-Length promised
+                let body = loop {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else { continue };
+                    let length = head.lines().find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ").map(|n| n.parse::<usize>().unwrap())).unwrap();
+                    if body.len() >= length {
+                        break body.to_string();
+                    }
+                };
+                bodies.push(body);
+                stream.write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+        (url, served)
+    }
+
+    #[tokio::test]
+    async fn a_trade_is_posted_as_json_and_retried_until_the_endpoint_takes_it() {
+        let (url, served) = endpoint(vec!["503 Service Unavailable", "500 Internal Server Error", "200 OK"]).await;
+        let client = reqwest::Client::new();
+        notify(&client, &url, &TradeNotification::from(&trade())).await.unwrap();
+
+        let bodies = served.await.unwrap();
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+        let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "trade_id": 7,
+            "instrument": "AAPL",
+            "price": 10.5,
+            "quantity": 4,
+            "buyer": "FIRM1",
+            "seller": "FIRM2",
+            "timestamp": "20240102-09:30:00.000",
+        }));
+    }
+
+    #[tokio::test]
+    async fn a_trade_is_given_up_on_after_three_failed_attempts() {
+        let (url, served) = endpoint(vec!["500 Internal Server Error"; WEBHOOK_ATTEMPTS as usize]).await;
+        let client = reqwest::Client::new();
+        let error = notify(&client, &url, &TradeNotification::from(&trade())).await.unwrap_err();
+        assert!(error.ends_with("answered 500 Internal Server Error after 3 attempts"), "{}", error);
+        assert_eq!(served.await.unwrap().len(), 3);
+    }
+
+    // The engine reports its trades only while a webhook wants them, and the outbound stage
+    // passes each on with the accounts on both sides
+    #[tokio::test]
+    async fn each_trade_the_engine_reports_is_posted_to_the_configured_webhook() {
+        let (url, served) = endpoint(vec!["200 OK"]).await;
+        let unset = LiveConfig::fixed(ServerConfig::default());
+        let set = LiveConfig::fixed(ServerConfig { webhook_url: Some(url), ..ServerConfig::default() });
+        let mut trades = Vec::new();
+        for config in [&unset, &set] {
+            let mut exchange = Exchange::new().with_config(config.clone());
+            exchange.handle_message(CreateInstrument::builder().symbol("AAPL").parse());
+            exchange.handle_message(NewOrderSingle::builder().sender("FIRM2").account("FIRM2").symbol("AAPL").side(Side::Sell).qty(4).limit(10.0).parse());
+            let events = exchange.handle_message(NewOrderSingle::builder().sender("FIRM1").account("FIRM1").symbol("AAPL").side(Side::Buy).qty(4).limit(10.0).parse());
+            trades.push(events.iter().filter(|event| matches!(event, EngineMessage::TradeExecuted { .. })).count());
+            TradeWebhook::new(Handle::current()).deliver(&events, config);
+        }
+        assert_eq!(trades, [0, 1]);
+
+        let bodies = served.await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!((&body["instrument"], &body["quantity"], &body["buyer"], &body["seller"]), (&"AAPL".into(), &4.into(), &"FIRM1".into(), &"FIRM2".into()));
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use bytes::{Bytes, BytesMut};
+use fefix::definitions::fix50::*;
+use fefix::dict::IsFieldDefinition;
+use fefix::fix_values::Timestamp;
+use fefix::FixValue;
+use parking_lot::Mutex;
+
+use crate::clock::{exchange_now, ExchangeTime};
+use crate::custom_fields::dictionary;
+
+// Room a fresh buffer starts with; an execution report takes about 150 bytes
+const BUFFER_CAPACITY: usize = 512;
+// Buffers kept for reuse, and the largest kept, so one big snapshot doesn't pin its memory
+const POOLED_BUFFERS: usize = 4_096;
+const MAX_POOLED_CAPACITY: usize = 16 * 1024;
+// BodyLength(9) is written as six zero-padded digits once the body is known, as fefix does
+const BODY_LENGTH_PLACEHOLDER: &[u8] = b"9=000000|";
+// An ExchangeTime on the wire, YYYYMMDD-HH:MM:SS.ffffff
+const EXCHANGE_TIME_WIDTH: usize = 24;
+// MsgSeqNum(34) is only known to the session writing the message, so it is encoded as nine
+// zero-padded digits for the session to fill in
+const MSG_SEQ_NUM_PLACEHOLDER: &[u8] = b"|34=000000000|";
+
+// Buffers outbound messages are encoded into, handed to a session's writer as Bytes and
+// given back once written. A buffer another session still holds, like a status broadcast
+// to every session, comes back from the last of them.
+pub struct BufferPool {
+    free: Mutex<Vec<BytesMut>>,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self { free: Mutex::new(Vec::new()) }
+    }
+
+    pub fn checkout(&self) -> BytesMut {
+        self.free.lock().pop().unwrap_or_else(fresh_buffer)
+    }
+
+    // Takes a written message back, if nothing else holds it
+    pub fn restore(&self, message: Bytes) {
+        if let Ok(buffer) = message.try_into_mut() {
+            self.put_back(buffer);
+        }
+    }
+
+    fn put_back(&self, mut buffer: BytesMut) {
+        if buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        let mut free = self.free.lock();
+        if free.len() < POOLED_BUFFERS {
+            free.push(buffer);
+        }
+    }
+
+    // Encodes into a pooled buffer with `encode`, which says whether it wrote anything
+    pub fn encode(&self, encode: impl FnOnce(&mut BytesMut) -> bool) -> Option<Bytes> {
+        let mut buffer = self.checkout();
+        if encode(&mut buffer) {
+            Some(buffer.freeze())
+        } else {
+            self.put_back(buffer);
+            None
+        }
+    }
+}
+
+// A buffer already shared, as a split leaves it. A plain one boxes up its bookkeeping each time
+// it is frozen and drops it again when thawed; a shared one keeps it across round trips.
+fn fresh_buffer() -> BytesMut {
+    let mut buffer = BytesMut::with_capacity(BUFFER_CAPACITY);
+    drop(buffer.split());
+    buffer
+}
+
+// The pool the outbound thread and every session's writer share
+pub fn buffer_pool() -> &'static BufferPool {
+    static POOL: OnceLock<BufferPool> = OnceLock::new();
+    POOL.get_or_init(BufferPool::new)
+}
+
+// A field value as it goes on the wire. fefix formats tags and numbers through a String
+// each; these write straight into the message, so encoding allocates nothing once the
+// buffer has room.
+pub(crate) trait WireValue {
+    fn write_to(&self, buffer: &mut BytesMut);
+}
+
+impl WireValue for &str {
+    fn write_to(&self, buffer: &mut BytesMut) {
+        buffer.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl WireValue for &[u8] {
+    fn write_to(&self, buffer: &mut BytesMut) {
+        buffer.extend_from_slice(self);
+    }
+}
+
+macro_rules! wire_value_as_displayed {
+    ($($type:ty),*) => {$(
+        impl WireValue for $type {
+            fn write_to(&self, buffer: &mut BytesMut) {
+                // Writing to a BytesMut only fails if it cannot grow, which would already have aborted
+                let _ = write!(buffer, "{}", self);
+            }
+        }
+    )*};
+}
+
+wire_value_as_displayed!(u32, u64, usize, f64);
+
+// Types whose fefix encoding is already written without allocating
+macro_rules! wire_value_as_fix_value {
+    ($($type:ty),*) => {$(
+        impl WireValue for $type {
+            fn write_to(&self, buffer: &mut BytesMut) {
+                self.serialize(buffer);
+            }
+        }
+    )*};
+}
+
+impl WireValue for ExchangeTime {
+    fn write_to(&self, buffer: &mut BytesMut) {
+        let start = buffer.len();
+        buffer.resize(start + EXCHANGE_TIME_WIDTH, b'0');
+        write_exchange_time(self, &mut buffer[start..]);
+    }
+}
+
+// Writes `time` over the EXCHANGE_TIME_WIDTH bytes of `out`, without formatting through a String
+fn write_exchange_time(time: &ExchangeTime, out: &mut [u8]) {
+    use chrono::{Datelike, Timelike};
+    let digits = |out: &mut [u8], mut value: u32| {
+        for digit in out.iter_mut().rev() {
+            *digit = b'0' + (value % 10) as u8;
+            value /= 10;
+        }
+    };
+    digits(&mut out[0..4], time.year() as u32);
+    digits(&mut out[4..6], time.month());
+    digits(&mut out[6..8], time.day());
+    out[8] = b'-';
+    digits(&mut out[9..11], time.hour());
+    out[11] = b':';
+    digits(&mut out[12..14], time.minute());
+    out[14] = b':';
+    digits(&mut out[15..17], time.second());
+    out[17] = b'.';
+    // A leap second carries on past a billion nanoseconds, and keeps its six digits
+    digits(&mut out[18..24], time.nanosecond() / 1_000);
+}
+
+// Stamps each outgoing message with the session's next MsgSeqNum(34), counted on in
+// `next_seq_num`, and its SendingTime(52) with the moment it is written to the socket,
+// recomputing its CheckSum to match. Only fields the exchange encoded, at its own widths, are
+// replaced; messages no other session holds are stamped where they lie.
+pub fn stamp_outbound(messages: Bytes, next_seq_num: &mut u64) -> Bytes {
+    let mut messages = messages.try_into_mut().unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+    let now = exchange_now();
+    for line in messages.split_mut(|byte| *byte == b'\n') {
+        let mut stamped = false;
+        if let Some(at) = line.windows(MSG_SEQ_NUM_PLACEHOLDER.len()).position(|field| field == MSG_SEQ_NUM_PLACEHOLDER) {
+            let mut remaining = *next_seq_num;
+            for digit in line[at + 4..at + MSG_SEQ_NUM_PLACEHOLDER.len() - 1].iter_mut().rev() {
+                *digit = b'0' + (remaining % 10) as u8;
+                remaining /= 10;
+            }
+            *next_seq_num += 1;
+            stamped = true;
+        }
+        if let Some(at) = line.windows(4).position(|field| field == b"|52=") {
+            let value = at + 4..at + 4 + EXCHANGE_TIME_WIDTH;
+            if line.get(value.end) == Some(&b'|') {
+                write_exchange_time(&now, &mut line[value]);
+                stamped = true;
+            }
+        }
+        if stamped {
+            restamp_checksum(line, b'|');
+        }
+    }
+    messages.freeze()
+}
+
+// Rewrites a message's three-digit CheckSum in place after its text has changed
+pub(crate) fn restamp_checksum(message: &mut [u8], separator: u8) {
+    let Some(at) = message.windows(4).rposition(|field| field[0] == separator && &field[1..] == b"10=") else { return };
+    if message.len() != at + 8 {
+        return;
+    }
+    let checksum = message[..=at].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    message[at + 4..at + 7].copy_from_slice(&[b'0' + checksum / 100, b'0' + checksum / 10 % 10, b'0' + checksum % 10]);
+}
+
+wire_value_as_fix_value!(
+    bool, Timestamp, Side, OrdType, TimeInForce, ExecType, OrdStatus, OrdRejReason, ExecRestatementReason, CxlRejReason,
+    CxlRejResponseTo, MdEntryType, MdUpdateAction, MdBookType, SubscriptionRequestType, PutOrCall,
+    TradeReportTransType
+);
+
+// One message being encoded, '|'-separated, after whatever `buffer` already holds
+pub(crate) struct FixWriter<'a> {
+    buffer: &'a mut BytesMut,
+    start: usize,
+    body_start: usize,
+}
+
+impl<'a> FixWriter<'a> {
+    pub(crate) fn start(buffer: &'a mut BytesMut, begin_string: &[u8], msg_type: &[u8]) -> Self {
+        let start = buffer.len();
+        let mut writer = Self { buffer, start, body_start: 0 };
+        writer.set_fv(&8, begin_string);
+        writer.buffer.extend_from_slice(BODY_LENGTH_PLACEHOLDER);
+        writer.body_start = writer.buffer.len();
+        writer.set_fv(&35, msg_type);
+        writer
+    }
+
+    // A field as the dictionary defines it; one it doesn't, or knows by another name, is a
+    // tag used out of step with the codec reading it back
+    pub(crate) fn set<F: IsFieldDefinition>(&mut self, field: &F, value: impl WireValue) {
+        let tag = field.tag().get() as u32;
+        debug_assert!(dictionary().field_by_tag(tag).is_some_and(|defined| defined.name() == field.name()), "{}({}) is not in the dictionary", field.name(), tag);
+        self.set_fv(&tag, value);
+    }
+
+    // MsgSeqNum(34) for the session to fill in as it writes the message; see stamp_outbound
+    pub(crate) fn reserve_msg_seq_num(&mut self) {
+        self.buffer.extend_from_slice(&MSG_SEQ_NUM_PLACEHOLDER[1..]);
+    }
+
+    pub(crate) fn set_fv(&mut self, tag: &u32, value: impl WireValue) {
+        tag.write_to(self.buffer);
+        self.buffer.extend_from_slice(b"=");
+        value.write_to(self.buffer);
+        self.buffer.extend_from_slice(b"|");
+    }
+
+    // Fills in BodyLength(9) and ends the message with its CheckSum(10)
+    pub(crate) fn wrap(self) {
+        let body_length = self.buffer.len() - self.body_start;
+        let digits = &mut self.buffer[self.body_start - 7..self.body_start - 1];
+        let mut remaining = body_length;
+        for digit in digits.iter_mut().rev() {
+            *digit = b'0' + (remaining % 10) as u8;
+            remaining /= 10;
+        }
+        let checksum = self.buffer[self.start..].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        self.buffer.extend_from_slice(b"10=");
+        self.buffer.extend_from_slice(&[b'0' + checksum / 100, b'0' + checksum / 10 % 10, b'0' + checksum % 10, b'|']);
+    }
+}
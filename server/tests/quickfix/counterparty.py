@@ -0,0 +1,147 @@
+#!/usr/bin/env python3
+# A QuickFIX initiator that holds one session against the exchange listening on the port given:
+# Logon, a NewOrderSingle for AAPL, an OrderCancelRequest once it is accepted, then Logout.
+# QuickFIX checks every message it reads against the FIXT.1.1 and FIX 5.0 dictionaries, so any
+# Reject, sent or received, fails the run. Needs `pip install quickfix`, and QUICKFIX_SPEC set
+# to the directory holding QuickFIX's FIXT11.xml and FIX50.xml (its source tree's spec/).
+#
+#   python3 counterparty.py <port>
+
+import os
+import sys
+import tempfile
+import threading
+
+import quickfix as fix
+
+WAIT = 10
+SENDER = "QUICKFIX"
+
+SETTINGS = """[DEFAULT]
+ConnectionType=initiator
+BeginString=FIXT.1.1
+DefaultApplVerID=FIX.5.0
+SenderCompID={sender}
+TargetCompID=EXCHANGE
+SocketConnectHost=127.0.0.1
+SocketConnectPort={port}
+HeartBtInt=30
+ReconnectInterval=60
+ResetOnLogon=Y
+StartTime=00:00:00
+EndTime=00:00:00
+UseDataDictionary=Y
+TransportDataDictionary={spec}/FIXT11.xml
+AppDataDictionary={spec}/FIX50.xml
+ValidateUserDefinedFields=N
+
+[SESSION]
+"""
+
+
+class Counterparty(fix.Application):
+    def __init__(self):
+        super().__init__()
+        self.session_id = None
+        self.logged_on = threading.Event()
+        self.logged_out = threading.Event()
+        self.reports = {}  # ExecType(150) to the first ExecutionReport of it
+        self.reported = threading.Condition()
+        self.failures = []
+
+    def onCreate(self, session_id):
+        self.session_id = session_id
+
+    def onLogon(self, session_id):
+        self.logged_on.set()
+
+    def onLogout(self, session_id):
+        self.logged_out.set()
+
+    def toAdmin(self, message, session_id):
+        # A Reject QuickFIX sends is one of ours it could not take
+        if msg_type(message) == "3":
+            self.failures.append("sent Reject: " + printable(message))
+
+    def fromAdmin(self, message, session_id):
+        if msg_type(message) == "3":
+            self.failures.append("received Reject: " + printable(message))
+
+    def toApp(self, message, session_id):
+        pass
+
+    def fromApp(self, message, session_id):
+        if msg_type(message) in ("3", "j"):
+            self.failures.append("received reject: " + printable(message))
+        elif msg_type(message) == "8":
+            with self.reported:
+                self.reports.setdefault(message.getField(150), message)
+                self.reported.notify_all()
+        else:
+            self.failures.append("unexpected message: " + printable(message))
+
+    def report(self, exec_type):
+        with self.reported:
+            self.reported.wait_for(lambda: exec_type in self.reports or self.failures, WAIT)
+            return self.reports.get(exec_type)
+
+
+def msg_type(message):
+    return message.getHeader().getField(35)
+
+
+def printable(message):
+    return message.toString().replace("\x01", "|")
+
+
+def application_message(msg_type, fields):
+    message = fix.Message()
+    message.getHeader().setField(fix.MsgType(msg_type))
+    for tag, value in fields:
+        message.setField(tag, value)
+    message.setField(fix.TransactTime())
+    return message
+
+
+def run(port, spec):
+    with tempfile.NamedTemporaryFile("w", suffix=".cfg", delete=False) as file:
+        file.write(SETTINGS.format(sender=SENDER, port=port, spec=spec))
+    settings = fix.SessionSettings(file.name)
+    app = Counterparty()
+    initiator = fix.SocketInitiator(app, fix.MemoryStoreFactory(), settings, fix.ScreenLogFactory(settings))
+    initiator.start()
+    try:
+        if not app.logged_on.wait(WAIT):
+            return ["no Logon"]
+        fix.Session.sendToTarget(application_message("D", [
+            (1, SENDER), (11, "QF-1"), (55, "AAPL"), (54, "1"), (38, "5"), (40, "2"), (44, "10"),
+        ]), app.session_id)
+        accepted = app.report("0")
+        if accepted is None:
+            return app.failures + ["no ExecutionReport for the order"]
+        fix.Session.sendToTarget(application_message("F", [
+            (1, SENDER), (11, "QF-2"), (41, "QF-1"), (37, accepted.getField(37)), (55, "AAPL"), (54, "1"),
+        ]), app.session_id)
+        if app.report("4") is None:
+            return app.failures + ["no ExecutionReport for the cancel"]
+        fix.Session.lookupSession(app.session_id).logout()
+        if not app.logged_out.wait(WAIT):
+            return app.failures + ["no Logout"]
+        return app.failures
+    finally:
+        initiator.stop()
+        os.unlink(file.name)
+
+
+def main():
+    spec = os.environ.get("QUICKFIX_SPEC")
+    if len(sys.argv) != 2 or not spec:
+        sys.exit("usage: QUICKFIX_SPEC=<quickfix spec dir> counterparty.py <port>")
+    failures = run(int(sys.argv[1]), spec)
+    for failure in failures:
+        print(failure, file=sys.stderr)
+    sys.exit(1 if failures else 0)
+
+
+if __name__ == "__main__":
+    main()
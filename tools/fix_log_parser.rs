@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use exchange_server::{fix_parse_error, Conformance};
+use serde::Serialize;
+
+// What a FIX log held, message by message: how many of each MsgType the exchange would take,
+// how many it would refuse, and how long reading each took it
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+struct LogReport {
+    msg_counts: BTreeMap<String, u64>,
+    parse_errors: u64,
+    latency_p50_us: u64,
+    latency_p99_us: u64,
+}
+
+// A message's MsgType (35), whichever separator it was logged with
+fn msg_type(line: &str) -> Option<&str> {
+    line.split(['|', '\u{1}']).find_map(|field| field.strip_prefix("35="))
+}
+
+// The duration `percent` of the readings took at most, in microseconds
+fn percentile(sorted: &[Duration], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1].as_micros() as u64
+}
+
+// Reads each non-blank line as the exchange would read it from a session, timing each read
+fn report(lines: impl Iterator<Item = String>, conformance: Conformance) -> LogReport {
+    let mut report = LogReport::default();
+    let mut latencies = Vec::new();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let started = Instant::now();
+        let error = fix_parse_error(&line, conformance);
+        latencies.push(started.elapsed());
+        match (error, msg_type(&line)) {
+            (None, Some(msg_type)) => *report.msg_counts.entry(msg_type.to_string()).or_default() += 1,
+            _ => report.parse_errors += 1,
+        }
+    }
+    latencies.sort();
+    report.latency_p50_us = percentile(&latencies, 50);
+    report.latency_p99_us = percentile(&latencies, 99);
+    report
+}
+
+// fix_log_parser <log file> [--lenient-fix], printing the log's report as JSON
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = args.iter().find(|arg| !arg.starts_with("--")).ok_or("usage: fix_log_parser <log file> [--lenient-fix]")?;
+    let conformance = if args.iter().any(|arg| arg == "--lenient-fix") { Conformance::Lenient } else { Conformance::Strict };
+    let lines = BufReader::new(File::open(path)?).lines().map_while(Result::ok);
+    println!("{}", serde_json::to_string_pretty(&report(lines, conformance))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEW_ORDER: &str = "8=FIXT.1.1|9=100|35=D|49=FIRM1|56=EXCHANGE|34=1|52=20240102-14:30:00.000|11=C-1|1=FIRM1|55=AAPL|54=1|38=1|40=2|44=10|10=000|";
+    const CANCEL: &str = "8=FIXT.1.1|9=91|35=F|49=FIRM1|56=EXCHANGE|34=2|52=20240102-14:30:00.100|11=C-2|41=C-1|1=FIRM1|55=AAPL|54=1|10=119|";
+
+    fn lines<'a>(lines: &'a [&str]) -> impl Iterator<Item = String> + 'a {
+        lines.iter().map(|line| line.to_string())
+    }
+
+    #[test]
+    fn counts_each_msg_type_the_exchange_takes_and_every_line_it_would_refuse() {
+        let soh = NEW_ORDER.replace('|', "\u{1}").replace("10=000", "10=070");
+        let report = report(lines(&[NEW_ORDER, "", CANCEL, &soh, "8=FIXT.1.1|9=5|=oops|10=000|", "not fix at all"]), Conformance::Strict);
+        assert_eq!(report.msg_counts, BTreeMap::from([("D".to_string(), 2), ("F".to_string(), 1)]));
+        assert_eq!(report.parse_errors, 2);
+        assert!(report.latency_p50_us <= report.latency_p99_us);
+    }
+
+    #[test]
+    fn a_strict_read_refuses_what_a_lenient_one_looks_past() {
+        // A CheckSum a hand-edited log no longer matches
+        let edited = NEW_ORDER.replace("44=10|", "44=11|");
+        assert_eq!(report(lines(&[&edited]), Conformance::Strict).parse_errors, 1);
+        assert_eq!(report(lines(&[&edited]), Conformance::Lenient).msg_counts["D"], 1);
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let sorted: Vec<_> = (1..=200).map(Duration::from_micros).collect();
+        assert_eq!((percentile(&sorted, 50), percentile(&sorted, 99)), (100, 198));
+        assert_eq!(percentile(&sorted[..1], 99), 1);
+        assert_eq!(percentile(&[], 50), 0);
+    }
+}